@@ -0,0 +1,568 @@
+use std::io::{self, ErrorKind};
+use std::marker;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use std::time::Duration;
+
+use rmqtt::futures::future::LocalBoxFuture;
+use rmqtt::futures::FutureExt;
+use rmqtt::ntex::codec::{AsyncRead, AsyncWrite, ReadBuf};
+use rmqtt::ntex::rt::net::TcpStream;
+use rmqtt::ntex::util::Ready;
+use rmqtt::ntex::{Service, ServiceFactory};
+use rmqtt::ntex_mqtt;
+use rmqtt::tls::PeerCert;
+use rmqtt::tokio::io::AsyncReadExt;
+use rmqtt::{log, tokio, MqttError, Runtime};
+
+const V2_SIGNATURE: &[u8; 12] = b"\r\n\r\n\0\r\nQUIT\n";
+const V1_PREFIX: &[u8; 6] = b"PROXY ";
+const V1_MAX_LEN: usize = 107;
+
+const V2_CMD_LOCAL: u8 = 0x00;
+const V2_CMD_PROXY: u8 = 0x01;
+const V2_AF_INET: u8 = 0x10;
+const V2_AF_INET6: u8 = 0x20;
+
+const PP2_TYPE_SSL: u8 = 0x20;
+const PP2_SUBTYPE_SSL_CN: u8 = 0x22;
+const PP2_CLIENT_SSL: u8 = 0x01;
+const PP2_CLIENT_CERT_CONN: u8 = 0x02;
+const PP2_CLIENT_CERT_SESS: u8 = 0x04;
+
+///Reads the PROXY protocol v1/v2 header sent by a load balancer before the client data.
+///If disabled, the connection is passed through unchanged.
+pub struct ProxyServer<T> {
+    enabled: bool,
+    timeout: Duration,
+    io: marker::PhantomData<T>,
+}
+
+impl<T: AsyncRead + AsyncWrite> ProxyServer<T> {
+    pub fn new(enabled: bool, timeout: Duration) -> Self {
+        ProxyServer { enabled, timeout, io: marker::PhantomData }
+    }
+}
+
+impl<T> Clone for ProxyServer<T> {
+    fn clone(&self) -> Self {
+        Self { enabled: self.enabled, timeout: self.timeout, io: marker::PhantomData }
+    }
+}
+
+impl<T: AsyncRead + AsyncWrite + Unpin + 'static> ServiceFactory for ProxyServer<T> {
+    type Request = T;
+    type Response = ProxyStream<T>;
+    type Error = ntex_mqtt::MqttError<MqttError>;
+    type Config = ();
+
+    type Service = ProxyService<T>;
+    type InitError = ();
+    type Future = Ready<Self::Service, Self::InitError>;
+
+    fn new_service(&self, _: ()) -> Self::Future {
+        Ready::Ok(ProxyService { enabled: self.enabled, timeout: self.timeout, io: marker::PhantomData })
+    }
+}
+
+pub struct ProxyService<T> {
+    enabled: bool,
+    timeout: Duration,
+    io: marker::PhantomData<T>,
+}
+
+impl<T: AsyncRead + AsyncWrite + Unpin + 'static> Service for ProxyService<T> {
+    type Request = T;
+    type Response = ProxyStream<T>;
+    type Error = ntex_mqtt::MqttError<MqttError>;
+    type Future = LocalBoxFuture<'static, Result<ProxyStream<T>, Self::Error>>;
+
+    #[inline]
+    fn poll_ready(&self, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    #[inline]
+    fn call(&self, mut io: Self::Request) -> Self::Future {
+        let enabled = self.enabled;
+        let timeout = self.timeout;
+        async move {
+            if !enabled {
+                return Ok(ProxyStream::new(io, None));
+            }
+            let header = if timeout.is_zero() {
+                read_header(&mut io).await
+            } else {
+                tokio::time::timeout(timeout, read_header(&mut io))
+                    .await
+                    .map_err(|_| ntex_mqtt::MqttError::HandshakeTimeout)?
+            };
+            match header {
+                Ok(header) => {
+                    if let Some(h) = header.as_ref() {
+                        log::debug!("PROXY protocol, src: {}, dst: {}, ssl: {:?}", h.src, h.dst, h.ssl);
+                    }
+                    Ok(ProxyStream::new(io, header))
+                }
+                Err(e) => {
+                    log::warn!("Connection Refused, bad PROXY protocol header, {:?}", e);
+                    Err(ntex_mqtt::MqttError::Service(MqttError::from(e)))
+                }
+            }
+        }
+        .boxed_local()
+    }
+}
+
+///Connection information carried in the PROXY protocol header
+#[derive(Debug, Clone)]
+pub struct ProxyHeader {
+    //Address of the client
+    pub src: SocketAddr,
+    //Address the client connected to on the load balancer
+    pub dst: SocketAddr,
+    //TLS connection information, if the load balancer terminated TLS
+    pub ssl: Option<ProxySsl>,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct ProxySsl {
+    //The client connected over TLS
+    pub client_ssl: bool,
+    //The client presented a certificate on this connection or in the resumed TLS session
+    pub client_cert: bool,
+    //The certificate was successfully verified, also set if the client did not present one
+    pub verified: bool,
+    //Common name of the client certificate
+    pub cn: Option<String>,
+}
+
+///Connection stream of the listeners, the bytes read and written are counted
+///in the bytes.received and bytes.sent metrics
+pub struct ProxyStream<S> {
+    s: S,
+    header: Option<ProxyHeader>,
+}
+
+impl<S> ProxyStream<S> {
+    pub fn new(s: S, header: Option<ProxyHeader>) -> Self {
+        Self { s, header }
+    }
+
+    #[inline]
+    pub fn get_ref(&self) -> &S {
+        &self.s
+    }
+
+    ///Client certificate verified by the load balancer that terminated TLS
+    #[inline]
+    pub fn peer_cert(&self) -> Option<PeerCert> {
+        self.header
+            .as_ref()
+            .and_then(|h| h.ssl.as_ref())
+            .filter(|ssl| ssl.client_ssl && ssl.client_cert && ssl.verified)
+            .and_then(|ssl| ssl.cn.clone())
+            .map(|cn| PeerCert { cn: Some(cn), ..Default::default() })
+    }
+}
+
+impl ProxyStream<TcpStream> {
+    ///Address of the client, taken from the PROXY protocol header if present
+    #[inline]
+    pub fn peer_addr(&self) -> io::Result<SocketAddr> {
+        if let Some(h) = self.header.as_ref() {
+            Ok(h.src)
+        } else {
+            self.s.peer_addr()
+        }
+    }
+
+    ///Local address of the socket, the listener is looked up by this address
+    #[inline]
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.s.local_addr()
+    }
+}
+
+impl<S: AsyncRead + Unpin> AsyncRead for ProxyStream<S> {
+    #[inline]
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let filled = buf.filled().len();
+        let res = Pin::new(&mut self.s).poll_read(cx, buf);
+        if let Poll::Ready(Ok(())) = res {
+            Runtime::instance().metrics.bytes_received_add(buf.filled().len() - filled);
+        }
+        res
+    }
+}
+
+impl<S: AsyncWrite + Unpin> AsyncWrite for ProxyStream<S> {
+    #[inline]
+    fn poll_write(mut self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<io::Result<usize>> {
+        let res = Pin::new(&mut self.s).poll_write(cx, buf);
+        if let Poll::Ready(Ok(n)) = res {
+            Runtime::instance().metrics.bytes_sent_add(n);
+        }
+        res
+    }
+
+    #[inline]
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.s).poll_flush(cx)
+    }
+
+    #[inline]
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.s).poll_shutdown(cx)
+    }
+}
+
+//Read the header exactly, so that no client data is consumed. Returns None if the header
+//does not carry the client address (v1 UNKNOWN, v2 LOCAL or non-IP address family).
+async fn read_header<T: AsyncRead + Unpin>(io: &mut T) -> io::Result<Option<ProxyHeader>> {
+    let mut buf = vec![0u8; V2_SIGNATURE.len()];
+    io.read_exact(&mut buf).await?;
+    if buf.as_slice() == V2_SIGNATURE {
+        read_header_v2(io).await
+    } else if buf.starts_with(V1_PREFIX) {
+        read_header_v1(io, buf).await
+    } else {
+        Err(invalid_data("PROXY protocol header is not found"))
+    }
+}
+
+async fn read_header_v1<T: AsyncRead + Unpin>(
+    io: &mut T,
+    mut buf: Vec<u8>,
+) -> io::Result<Option<ProxyHeader>> {
+    while !buf.ends_with(b"\r\n") {
+        if buf.len() >= V1_MAX_LEN {
+            return Err(invalid_data("PROXY protocol v1 header is too long"));
+        }
+        buf.push(io.read_u8().await?);
+    }
+    let line = std::str::from_utf8(&buf[..buf.len() - 2])
+        .map_err(|_| invalid_data("PROXY protocol v1 header is not valid UTF-8"))?;
+    let parts = line.split(' ').collect::<Vec<_>>();
+    match parts.as_slice() {
+        ["PROXY", "UNKNOWN", ..] => Ok(None),
+        ["PROXY", proto @ ("TCP4" | "TCP6"), src, dst, src_port, dst_port] => {
+            let src_ip = src.parse::<IpAddr>().map_err(|_| invalid_data("bad source address"))?;
+            let dst_ip = dst.parse::<IpAddr>().map_err(|_| invalid_data("bad destination address"))?;
+            if (*proto == "TCP4") != (src_ip.is_ipv4() && dst_ip.is_ipv4()) {
+                return Err(invalid_data("address family mismatch"));
+            }
+            let src_port = src_port.parse::<u16>().map_err(|_| invalid_data("bad source port"))?;
+            let dst_port = dst_port.parse::<u16>().map_err(|_| invalid_data("bad destination port"))?;
+            Ok(Some(ProxyHeader {
+                src: SocketAddr::new(src_ip, src_port),
+                dst: SocketAddr::new(dst_ip, dst_port),
+                ssl: None,
+            }))
+        }
+        _ => Err(invalid_data("bad PROXY protocol v1 header")),
+    }
+}
+
+async fn read_header_v2<T: AsyncRead + Unpin>(io: &mut T) -> io::Result<Option<ProxyHeader>> {
+    let ver_cmd = io.read_u8().await?;
+    let fam = io.read_u8().await?;
+    let len = io.read_u16().await? as usize;
+    let mut data = vec![0u8; len];
+    io.read_exact(&mut data).await?;
+
+    if ver_cmd >> 4 != 0x02 {
+        return Err(invalid_data("unsupported PROXY protocol version"));
+    }
+    match ver_cmd & 0x0F {
+        V2_CMD_LOCAL => return Ok(None),
+        V2_CMD_PROXY => {}
+        _ => return Err(invalid_data("unsupported PROXY protocol command")),
+    }
+
+    let (src, dst, tlvs) = match fam & 0xF0 {
+        V2_AF_INET if data.len() >= 12 => {
+            let src_ip = Ipv4Addr::new(data[0], data[1], data[2], data[3]);
+            let dst_ip = Ipv4Addr::new(data[4], data[5], data[6], data[7]);
+            let src_port = u16::from_be_bytes([data[8], data[9]]);
+            let dst_port = u16::from_be_bytes([data[10], data[11]]);
+            (SocketAddr::new(src_ip.into(), src_port), SocketAddr::new(dst_ip.into(), dst_port), &data[12..])
+        }
+        V2_AF_INET6 if data.len() >= 36 => {
+            let src_ip =
+                Ipv6Addr::from(<[u8; 16]>::try_from(&data[0..16]).map_err(|_| invalid_data("bad address"))?);
+            let dst_ip =
+                Ipv6Addr::from(<[u8; 16]>::try_from(&data[16..32]).map_err(|_| invalid_data("bad address"))?);
+            let src_port = u16::from_be_bytes([data[32], data[33]]);
+            let dst_port = u16::from_be_bytes([data[34], data[35]]);
+            (SocketAddr::new(src_ip.into(), src_port), SocketAddr::new(dst_ip.into(), dst_port), &data[36..])
+        }
+        V2_AF_INET | V2_AF_INET6 => return Err(invalid_data("PROXY protocol v2 address is truncated")),
+        _ => return Ok(None),
+    };
+
+    let ssl = parse_tlvs(tlvs)?
+        .into_iter()
+        .find(|(typ, _)| *typ == PP2_TYPE_SSL)
+        .map(|(_, v)| parse_ssl(v))
+        .transpose()?;
+
+    Ok(Some(ProxyHeader { src, dst, ssl }))
+}
+
+//PP2_TYPE_SSL: client(u8), verify(u32, 0 means the client certificate was verified), sub-TLVs
+fn parse_ssl(value: &[u8]) -> io::Result<ProxySsl> {
+    if value.len() < 5 {
+        return Err(invalid_data("PROXY protocol v2 SSL TLV is truncated"));
+    }
+    let client = value[0];
+    let verify = u32::from_be_bytes([value[1], value[2], value[3], value[4]]);
+    let mut ssl = ProxySsl {
+        client_ssl: client & PP2_CLIENT_SSL != 0,
+        client_cert: client & (PP2_CLIENT_CERT_CONN | PP2_CLIENT_CERT_SESS) != 0,
+        verified: verify == 0,
+        ..Default::default()
+    };
+    for (typ, v) in parse_tlvs(&value[5..])? {
+        if typ == PP2_SUBTYPE_SSL_CN {
+            ssl.cn = Some(String::from_utf8_lossy(v).into_owned());
+        }
+    }
+    Ok(ssl)
+}
+
+fn parse_tlvs(mut data: &[u8]) -> io::Result<Vec<(u8, &[u8])>> {
+    let mut tlvs = Vec::new();
+    while !data.is_empty() {
+        if data.len() < 3 {
+            return Err(invalid_data("PROXY protocol v2 TLV is truncated"));
+        }
+        let typ = data[0];
+        let len = u16::from_be_bytes([data[1], data[2]]) as usize;
+        if data.len() < 3 + len {
+            return Err(invalid_data("PROXY protocol v2 TLV is truncated"));
+        }
+        tlvs.push((typ, &data[3..3 + len]));
+        data = &data[3 + len..];
+    }
+    Ok(tlvs)
+}
+
+#[inline]
+fn invalid_data(msg: &str) -> io::Error {
+    io::Error::new(ErrorKind::InvalidData, msg)
+}
+
+#[cfg(test)]
+mod tests {
+    use rmqtt::tokio::io::AsyncWriteExt;
+
+    use super::*;
+
+    async fn parse(data: &[u8]) -> io::Result<Option<ProxyHeader>> {
+        let mut io = data;
+        read_header(&mut io).await
+    }
+
+    fn v2_header(ver_cmd: u8, fam: u8, payload: &[u8]) -> Vec<u8> {
+        let mut data = V2_SIGNATURE.to_vec();
+        data.extend_from_slice(&[ver_cmd, fam]);
+        data.extend_from_slice(&(payload.len() as u16).to_be_bytes());
+        data.extend_from_slice(payload);
+        data
+    }
+
+    #[tokio::test(crate = "rmqtt::tokio")]
+    async fn v1_tcp4() {
+        let mut io: &[u8] = b"PROXY TCP4 192.168.0.1 192.168.0.11 56324 1883\r\n\x10\x0c";
+        let h = read_header(&mut io).await.unwrap().unwrap();
+        assert_eq!(h.src, "192.168.0.1:56324".parse::<SocketAddr>().unwrap());
+        assert_eq!(h.dst, "192.168.0.11:1883".parse::<SocketAddr>().unwrap());
+        assert!(h.ssl.is_none());
+        //The client data is not consumed
+        assert_eq!(io, b"\x10\x0c");
+    }
+
+    #[tokio::test(crate = "rmqtt::tokio")]
+    async fn v1_tcp6() {
+        let h = parse(b"PROXY TCP6 2001:db8::1 2001:db8::2 56324 1883\r\n").await.unwrap().unwrap();
+        assert_eq!(h.src, "[2001:db8::1]:56324".parse::<SocketAddr>().unwrap());
+        assert_eq!(h.dst, "[2001:db8::2]:1883".parse::<SocketAddr>().unwrap());
+    }
+
+    #[tokio::test(crate = "rmqtt::tokio")]
+    async fn v1_unknown() {
+        assert!(parse(b"PROXY UNKNOWN\r\n").await.unwrap().is_none());
+        assert!(parse(b"PROXY UNKNOWN ffff::1 ffff::2 1 2\r\n").await.unwrap().is_none());
+    }
+
+    #[tokio::test(crate = "rmqtt::tokio")]
+    async fn v1_invalid() {
+        //Address family mismatch
+        assert!(parse(b"PROXY TCP4 2001:db8::1 2001:db8::2 56324 1883\r\n").await.is_err());
+        //Bad port
+        assert!(parse(b"PROXY TCP4 192.168.0.1 192.168.0.11 65536 1883\r\n").await.is_err());
+        //Missing fields
+        assert!(parse(b"PROXY TCP4 192.168.0.1 192.168.0.11\r\n").await.is_err());
+        //No PROXY protocol header
+        assert!(parse(b"\x10\x0c\x00\x04MQTT\x04\x02\x00\x3c").await.is_err());
+    }
+
+    #[tokio::test(crate = "rmqtt::tokio")]
+    async fn v1_too_long() {
+        let mut data = b"PROXY TCP4 192.168.0.1 192.168.0.11 56324 1883".to_vec();
+        data.resize(V1_MAX_LEN + 10, b' ');
+        data.extend_from_slice(b"\r\n");
+        let e = parse(&data).await.unwrap_err();
+        assert_eq!(e.kind(), ErrorKind::InvalidData);
+    }
+
+    #[tokio::test(crate = "rmqtt::tokio")]
+    async fn v1_truncated() {
+        let e = parse(b"PROXY TCP4 192.168.0.1 192.168").await.unwrap_err();
+        assert_eq!(e.kind(), ErrorKind::UnexpectedEof);
+        let e = parse(b"PROXY").await.unwrap_err();
+        assert_eq!(e.kind(), ErrorKind::UnexpectedEof);
+    }
+
+    #[tokio::test(crate = "rmqtt::tokio")]
+    async fn v2_proxy_inet() {
+        let mut payload = vec![192, 168, 0, 1, 192, 168, 0, 11];
+        payload.extend_from_slice(&56324u16.to_be_bytes());
+        payload.extend_from_slice(&1883u16.to_be_bytes());
+        let mut data = v2_header(0x21, 0x11, &payload);
+        data.extend_from_slice(b"\x10\x0c");
+        let mut io = data.as_slice();
+        let h = read_header(&mut io).await.unwrap().unwrap();
+        assert_eq!(h.src, "192.168.0.1:56324".parse::<SocketAddr>().unwrap());
+        assert_eq!(h.dst, "192.168.0.11:1883".parse::<SocketAddr>().unwrap());
+        assert!(h.ssl.is_none());
+        assert_eq!(io, b"\x10\x0c");
+    }
+
+    #[tokio::test(crate = "rmqtt::tokio")]
+    async fn v2_proxy_ssl() {
+        let mut payload = vec![192, 168, 0, 1, 192, 168, 0, 11];
+        payload.extend_from_slice(&56324u16.to_be_bytes());
+        payload.extend_from_slice(&1883u16.to_be_bytes());
+        let ssl_header = |client: u8, verify: u32| {
+            let mut payload = payload.clone();
+            let mut ssl = vec![client];
+            ssl.extend_from_slice(&verify.to_be_bytes());
+            ssl.push(PP2_SUBTYPE_SSL_CN);
+            ssl.extend_from_slice(&7u16.to_be_bytes());
+            ssl.extend_from_slice(b"client1");
+            payload.push(PP2_TYPE_SSL);
+            payload.extend_from_slice(&(ssl.len() as u16).to_be_bytes());
+            payload.extend_from_slice(&ssl);
+            v2_header(0x21, 0x11, &payload)
+        };
+
+        let h = parse(&ssl_header(PP2_CLIENT_SSL | PP2_CLIENT_CERT_CONN, 0)).await.unwrap().unwrap();
+        let ssl = h.ssl.clone().unwrap();
+        assert!(ssl.client_ssl);
+        assert!(ssl.client_cert);
+        assert!(ssl.verified);
+        assert_eq!(ssl.cn.as_deref(), Some("client1"));
+        let peer_cert = ProxyStream::new((), Some(h)).peer_cert().unwrap();
+        assert_eq!(peer_cert.cn.as_deref(), Some("client1"));
+
+        //certificate of the resumed TLS session
+        let h = parse(&ssl_header(PP2_CLIENT_SSL | PP2_CLIENT_CERT_SESS, 0)).await.unwrap().unwrap();
+        assert!(ProxyStream::new((), Some(h)).peer_cert().is_some());
+
+        //verify is 0 when the client did not present a certificate
+        let h = parse(&ssl_header(PP2_CLIENT_SSL, 0)).await.unwrap().unwrap();
+        assert!(!h.ssl.as_ref().unwrap().client_cert);
+        assert!(ProxyStream::new((), Some(h)).peer_cert().is_none());
+
+        //the certificate was not verified
+        let h = parse(&ssl_header(PP2_CLIENT_SSL | PP2_CLIENT_CERT_CONN, 1)).await.unwrap().unwrap();
+        assert!(ProxyStream::new((), Some(h)).peer_cert().is_none());
+    }
+
+    #[tokio::test(crate = "rmqtt::tokio")]
+    async fn v2_local() {
+        //The address block of a LOCAL header is skipped
+        let mut data = v2_header(0x20, 0x11, &[0u8; 12]);
+        data.extend_from_slice(b"\x10\x0c");
+        let mut io = data.as_slice();
+        assert!(read_header(&mut io).await.unwrap().is_none());
+        assert_eq!(io, b"\x10\x0c");
+        //Unspecified address family
+        assert!(parse(&v2_header(0x21, 0x00, &[])).await.unwrap().is_none());
+    }
+
+    #[tokio::test(crate = "rmqtt::tokio")]
+    async fn v2_invalid() {
+        //Unsupported version
+        assert!(parse(&v2_header(0x11, 0x11, &[0u8; 12])).await.is_err());
+        //Unsupported command
+        assert!(parse(&v2_header(0x22, 0x11, &[0u8; 12])).await.is_err());
+        //Bad signature
+        let mut data = v2_header(0x21, 0x11, &[0u8; 12]);
+        data[11] = b'\r';
+        assert!(parse(&data).await.is_err());
+    }
+
+    #[tokio::test(crate = "rmqtt::tokio")]
+    async fn v2_truncated() {
+        //The address block is shorter than the address family requires
+        let e = parse(&v2_header(0x21, 0x11, &[0u8; 8])).await.unwrap_err();
+        assert_eq!(e.kind(), ErrorKind::InvalidData);
+        let e = parse(&v2_header(0x21, 0x21, &[0u8; 12])).await.unwrap_err();
+        assert_eq!(e.kind(), ErrorKind::InvalidData);
+        //The header ends before the length in the header
+        let mut data = v2_header(0x21, 0x11, &[0u8; 12]);
+        data.truncate(data.len() - 4);
+        let e = parse(&data).await.unwrap_err();
+        assert_eq!(e.kind(), ErrorKind::UnexpectedEof);
+        let e = parse(&V2_SIGNATURE[..8]).await.unwrap_err();
+        assert_eq!(e.kind(), ErrorKind::UnexpectedEof);
+    }
+
+    #[tokio::test(crate = "rmqtt::tokio")]
+    async fn v2_oversized_length() {
+        //The length in the header exceeds the data that is sent
+        let mut data = V2_SIGNATURE.to_vec();
+        data.extend_from_slice(&[0x21, 0x11]);
+        data.extend_from_slice(&u16::MAX.to_be_bytes());
+        data.extend_from_slice(&[0u8; 12]);
+        let e = parse(&data).await.unwrap_err();
+        assert_eq!(e.kind(), ErrorKind::UnexpectedEof);
+
+        //The length of a TLV exceeds the header
+        let mut payload = vec![0u8; 12];
+        payload.push(PP2_TYPE_SSL);
+        payload.extend_from_slice(&100u16.to_be_bytes());
+        payload.extend_from_slice(&[PP2_CLIENT_SSL, 0, 0, 0, 0]);
+        let e = parse(&v2_header(0x21, 0x11, &payload)).await.unwrap_err();
+        assert_eq!(e.kind(), ErrorKind::InvalidData);
+
+        //The length of a SSL sub-TLV exceeds the SSL TLV
+        let mut ssl = vec![PP2_CLIENT_SSL, 0, 0, 0, 0, PP2_SUBTYPE_SSL_CN];
+        ssl.extend_from_slice(&100u16.to_be_bytes());
+        ssl.extend_from_slice(b"client1");
+        let mut payload = vec![0u8; 12];
+        payload.push(PP2_TYPE_SSL);
+        payload.extend_from_slice(&(ssl.len() as u16).to_be_bytes());
+        payload.extend_from_slice(&ssl);
+        let e = parse(&v2_header(0x21, 0x11, &payload)).await.unwrap_err();
+        assert_eq!(e.kind(), ErrorKind::InvalidData);
+    }
+
+    #[tokio::test(crate = "rmqtt::tokio")]
+    async fn timeout() {
+        //The load balancer sends part of the header and stalls
+        let (client, mut server) = tokio::io::duplex(1024);
+        server.write_all(b"PROXY TCP4 192.168.0.1").await.unwrap();
+        let svc = ProxyService { enabled: true, timeout: Duration::from_millis(50), io: marker::PhantomData };
+        let res = svc.call(client).await;
+        assert!(matches!(res, Err(ntex_mqtt::MqttError::HandshakeTimeout)));
+    }
+}
@@ -1,21 +1,20 @@
 #![deny(unsafe_code)]
 
-use std::{process, sync::Arc, time::Duration};
+use std::{cell::RefCell, collections::HashMap, process, time::Duration};
 
 #[cfg(not(target_os = "windows"))]
 use rustls::crypto::aws_lc_rs as provider;
 #[cfg(target_os = "windows")]
 use rustls::crypto::ring as provider;
-use rustls::pki_types::pem::PemObject;
-use rustls::server::WebPkiClientVerifier;
-use rustls::{RootCertStore, ServerConfig};
 
-use rmqtt::anyhow::anyhow;
 use rmqtt::broker::{
     v3::control_message as control_message_v3, v3::handshake as handshake_v3, v3::publish as publish_v3,
     v5::control_message as control_message_v5, v5::handshake as handshake_v5, v5::publish as publish_v5,
 };
-use rmqtt::futures::future::ok;
+use rmqtt::futures::{
+    self,
+    future::{join_all, ok},
+};
 use rmqtt::ntex::{
     self,
     rt::net::TcpStream,
@@ -29,16 +28,31 @@ use rmqtt::ntex_mqtt::{
     v5::Handshake as HandshakeV5,
     {v3, v5, MqttServer},
 };
-use rmqtt::settings::{listener::Listener, Options, Settings};
+use rmqtt::settings::{
+    listener::{Listener, ListenerType},
+    Options, Settings,
+};
+use rmqtt::tls::PeerCert;
+use rmqtt::tokio::sync::broadcast;
 use rmqtt::{log, rustls, structopt::StructOpt, tokio};
 use rmqtt::{logger::logger_init, runtime, MqttError, Result, Runtime, SessionState};
 
+mod proxy;
 mod ws;
 
 #[cfg(target_os = "linux")]
 #[global_allocator]
 static GLOBAL: tikv_jemallocator::Jemalloc = tikv_jemallocator::Jemalloc;
 
+//'max_connections' is checked in the handshake, so it can be changed by reloading the listeners. The
+//connection limit of ntex is fixed when the listener is bound, it is high enough to never apply first.
+const MAXCONN: usize = 1_000_000;
+
+thread_local! {
+    //Listener servers, stopped when the listener is removed or when shutting down
+    static SERVERS: RefCell<HashMap<(ListenerType, u16), ntex::server::Server>> = RefCell::new(HashMap::new());
+}
+
 #[allow(dead_code)]
 mod plugin {
     include!(concat!(env!("OUT_DIR"), "/plugin.rs"));
@@ -78,52 +92,108 @@ async fn main() {
     //hook, before startup
     Runtime::instance().extends.hook_mgr().await.before_startup().await;
 
-    //tcp
-    for (_, listen_cfg) in Runtime::instance().settings.listeners.tcps.iter() {
-        let name = format!("{}/{:?}", &listen_cfg.name, &listen_cfg.addr);
-        ntex::rt::spawn(async {
-            if let Err(err) = listen(name, listen_cfg).await {
-                log::error!("listen mqtt failed: {}", err);
-                process::exit(1);
-            }
-        });
+    //tcp, tls, websocket and tls-websocket listeners
+    for (typ, listen_cfg) in Runtime::instance().settings.listeners.all() {
+        start_listener(typ, listen_cfg, true);
     }
 
-    //tls
-    for (_, listen_cfg) in Runtime::instance().settings.listeners.tlss.iter() {
-        let name = format!("{}/{:?}", &listen_cfg.name, &listen_cfg.addr);
-        ntex::rt::spawn(async {
-            if let Err(err) = listen_tls(name, listen_cfg).await {
-                log::error!("listen mqtt tls failed: {}", err);
-                process::exit(1);
-            }
-        });
+    //start and stop listeners when the listener configuration is reloaded
+    ntex::rt::spawn(listener_changes_process());
+
+    //reload the listener configuration on SIGHUP
+    #[cfg(unix)]
+    ntex::rt::spawn(reload_signal());
+
+    //waiting for SIGTERM or Ctrl-C
+    shutdown_signal().await;
+    log::info!("shutting down ...");
+
+    //stop accepting new connections, disconnect clients and wait for their sessions to be saved
+    let servers = SERVERS.with(|servers| servers.take());
+    let stop_listeners = join_all(servers.values().map(|server| server.stop(true)));
+    let (_, remaining) = futures::join!(stop_listeners, Runtime::instance().node.shutdown());
+    if remaining > 0 {
+        log::warn!("shutting down, {} sessions may not have been saved", remaining);
     }
 
-    //websocket
-    for (_, listen_cfg) in Runtime::instance().settings.listeners.wss.iter() {
-        let name = format!("{}/{:?}", &listen_cfg.name, &listen_cfg.addr);
-        ntex::rt::spawn(async {
-            if let Err(err) = listen_ws(name, listen_cfg).await {
-                log::error!("listen websocket failed: {}", err);
-                process::exit(1);
-            }
-        });
+    //flush and stop plugins
+    Runtime::instance().plugins.shutdown().await;
+    log::info!("shutdown completed");
+}
+
+#[cfg(unix)]
+async fn shutdown_signal() {
+    use tokio::signal::unix::{signal, SignalKind};
+    let mut sigterm = signal(SignalKind::terminate()).expect("signal terminate");
+    tokio::select! {
+        _ = tokio::signal::ctrl_c() => log::info!("Ctrl-C received"),
+        _ = sigterm.recv() => log::info!("SIGTERM received"),
     }
+}
+
+#[cfg(not(unix))]
+async fn shutdown_signal() {
+    tokio::signal::ctrl_c().await.expect("signal ctrl c");
+    log::info!("Ctrl-C received");
+}
+
+#[cfg(unix)]
+async fn reload_signal() {
+    use tokio::signal::unix::{signal, SignalKind};
+    let mut sighup = signal(SignalKind::hangup()).expect("signal hangup");
+    while sighup.recv().await.is_some() {
+        log::info!("SIGHUP received, reloading listeners ...");
+        if let Err(e) = Runtime::instance().node.reload_listeners() {
+            log::error!("reload listeners failed, {:?}", e);
+        }
+    }
+}
 
-    //tls-websocket
-    for (_, listen_cfg) in Runtime::instance().settings.listeners.wsss.iter() {
+fn start_listener(typ: ListenerType, listen_cfg: Listener, exit_on_failure: bool) {
+    ntex::rt::spawn(async move {
         let name = format!("{}/{:?}", &listen_cfg.name, &listen_cfg.addr);
-        ntex::rt::spawn(async {
-            if let Err(err) = listen_wss(name, listen_cfg).await {
-                log::error!("listen websocket tls failed: {}", err);
+        let res = match typ {
+            ListenerType::Tcp => listen(name, &listen_cfg).await,
+            ListenerType::Tls => listen_tls(name, &listen_cfg).await,
+            ListenerType::Ws => listen_ws(name, &listen_cfg).await,
+            ListenerType::Wss => listen_wss(name, &listen_cfg).await,
+        };
+        if let Err(err) = res {
+            log::error!("listen {} failed: {}", typ, err);
+            if exit_on_failure {
                 process::exit(1);
             }
-        });
-    }
+        }
+    });
+}
 
-    ntex::rt::signal::ctrl_c().await.expect("signal ctrl c");
-    tokio::time::sleep(Duration::from_secs(1)).await;
+async fn listener_changes_process() {
+    let mut changes_rx = Runtime::instance().node.listener_changes();
+    loop {
+        let changes = match changes_rx.recv().await {
+            Ok(changes) => changes,
+            Err(broadcast::error::RecvError::Lagged(n)) => {
+                log::warn!("listener changes lagged, skipped {} changes", n);
+                continue;
+            }
+            Err(broadcast::error::RecvError::Closed) => break,
+        };
+
+        for l in changes.removed {
+            let server = SERVERS.with(|servers| servers.borrow_mut().remove(&(l.typ, l.addr.port())));
+            if let Some(server) = server {
+                log::info!("stop {} listener {} on {}", l.typ, l.name, l.addr);
+                ntex::rt::spawn(async move { server.stop(true).await });
+            }
+        }
+
+        for l in changes.added {
+            if let Some(listen_cfg) = Runtime::instance().settings.listeners.find(l.typ, l.addr.port()) {
+                log::info!("start {} listener {} on {}", l.typ, l.name, l.addr);
+                start_listener(l.typ, listen_cfg, false);
+            }
+        }
+    }
 }
 
 async fn listen(name: String, listen_cfg: &Listener) -> Result<()> {
@@ -131,77 +201,94 @@ async fn listen(name: String, listen_cfg: &Listener) -> Result<()> {
         let max_inflight = listen_cfg.max_inflight.get() as usize;
         let handshake_timeout = listen_cfg.handshake_timeout();
         let max_size = listen_cfg.max_packet_size.as_u32();
-        ntex::server::Server::build()
+        let proxy_protocol = listen_cfg.proxy_protocol;
+        let proxy_protocol_timeout = listen_cfg.proxy_protocol_timeout;
+        let server = ntex::server::Server::build()
             .backlog(listen_cfg.backlog)
             .reuseaddr(listen_cfg.reuseaddr)
             .reuseport(listen_cfg.reuseport)
             .bind(name, listen_cfg.addr, move || {
-                MqttServer::new()
-                    .v3(v3::MqttServer::new(move |mut handshake: HandshakeV3<TcpStream>| async {
-                        let remote_addr = handshake.io().peer_addr()?;
-                        let local_addr = handshake.io().local_addr()?;
-                        let listen_cfg =
-                            Runtime::instance().settings.listeners.tcp(local_addr.port()).ok_or_else(
-                                || {
-                                    log::error!(
-                                        "tcp listener config is not found, local addr is {:?}",
-                                        local_addr
-                                    );
-                                    MqttError::ListenerConfigError
-                                },
-                            )?;
-                        handshake_v3(listen_cfg, handshake, remote_addr, local_addr).await
-                    })
-                    // .v3(v3::MqttServer::new(handshake_v3)
-                    .inflight(max_inflight)
-                    .handshake_timeout(handshake_timeout)
-                    .max_size(max_size)
-                    .publish(fn_factory_with_config(|session: v3::Session<SessionState>| {
-                        ok::<_, MqttError>(fn_service(move |req| publish_v3(session.clone(), req)))
-                    }))
-                    .control(fn_factory_with_config(
-                        |session: v3::Session<SessionState>| {
-                            ok::<_, MqttError>(fn_service(move |req| {
-                                control_message_v3(session.clone(), req)
-                            }))
-                        },
-                    )))
-                    .v5(v5::MqttServer::new(move |mut handshake: HandshakeV5<TcpStream>| async {
-                        let peer_addr = handshake.io().peer_addr()?;
-                        let local_addr = handshake.io().local_addr()?;
-                        let listen_cfg =
-                            Runtime::instance().settings.listeners.tcp(local_addr.port()).ok_or_else(
-                                || {
-                                    log::error!(
-                                        "tcp listener config is not found, local addr is {:?}",
-                                        local_addr
-                                    );
-                                    MqttError::ListenerConfigError
-                                },
-                            )?;
-                        handshake_v5(listen_cfg, handshake, peer_addr, local_addr).await
-                    })
-                    //v5::MqttServer::new(handshake_v5)
-                    .receive_max(max_inflight as u16)
-                    .handshake_timeout(handshake_timeout)
-                    .max_size(max_size)
-                    // .max_qos(max_qos)
-                    //.max_topic_alias(max_topic_alias),
-                    .publish(fn_factory_with_config(|session: v5::Session<SessionState>| {
-                        ok::<_, MqttError>(fn_service(move |req| publish_v5(session.clone(), req)))
-                    }))
-                    .control(fn_factory_with_config(
-                        |session: v5::Session<SessionState>| {
-                            ok::<_, MqttError>(fn_service(move |req| {
-                                control_message_v5(session.clone(), req)
-                            }))
-                        },
-                    )))
+                pipeline_factory(proxy::ProxyServer::new(proxy_protocol, proxy_protocol_timeout)).and_then(
+                    MqttServer::new()
+                        .v3(v3::MqttServer::new(
+                            move |mut handshake: HandshakeV3<proxy::ProxyStream<TcpStream>>| async {
+                                let peer_cert = handshake.io().peer_cert();
+                                let remote_addr = handshake.io().peer_addr()?;
+                                let local_addr = handshake.io().local_addr()?;
+                                let listen_cfg = Runtime::instance()
+                                    .settings
+                                    .listeners
+                                    .tcp(local_addr.port())
+                                    .ok_or_else(|| {
+                                        log::error!(
+                                            "tcp listener config is not found, local addr is {:?}",
+                                            local_addr
+                                        );
+                                        MqttError::ListenerConfigError
+                                    })?;
+                                handshake_v3(listen_cfg, handshake, remote_addr, local_addr, peer_cert).await
+                            },
+                        )
+                        // .v3(v3::MqttServer::new(handshake_v3)
+                        .inflight(max_inflight)
+                        .handshake_timeout(handshake_timeout)
+                        .max_size(max_size)
+                        .publish(fn_factory_with_config(|session: v3::Session<SessionState>| {
+                            ok::<_, MqttError>(fn_service(move |req| publish_v3(session.clone(), req)))
+                        }))
+                        .control(fn_factory_with_config(
+                            |session: v3::Session<SessionState>| {
+                                ok::<_, MqttError>(fn_service(move |req| {
+                                    control_message_v3(session.clone(), req)
+                                }))
+                            },
+                        )))
+                        .v5(v5::MqttServer::new(
+                            move |mut handshake: HandshakeV5<proxy::ProxyStream<TcpStream>>| async {
+                                let peer_cert = handshake.io().peer_cert();
+                                let peer_addr = handshake.io().peer_addr()?;
+                                let local_addr = handshake.io().local_addr()?;
+                                let listen_cfg = Runtime::instance()
+                                    .settings
+                                    .listeners
+                                    .tcp(local_addr.port())
+                                    .ok_or_else(|| {
+                                        log::error!(
+                                            "tcp listener config is not found, local addr is {:?}",
+                                            local_addr
+                                        );
+                                        MqttError::ListenerConfigError
+                                    })?;
+                                handshake_v5(listen_cfg, handshake, peer_addr, local_addr, peer_cert).await
+                            },
+                        )
+                        //v5::MqttServer::new(handshake_v5)
+                        .receive_max(max_inflight as u16)
+                        .handshake_timeout(handshake_timeout)
+                        .max_size(max_size)
+                        // .max_qos(max_qos)
+                        //.max_topic_alias(max_topic_alias),
+                        .publish(fn_factory_with_config(|session: v5::Session<SessionState>| {
+                            ok::<_, MqttError>(fn_service(move |req| publish_v5(session.clone(), req)))
+                        }))
+                        .control(fn_factory_with_config(
+                            |session: v5::Session<SessionState>| {
+                                ok::<_, MqttError>(fn_service(move |req| {
+                                    control_message_v5(session.clone(), req)
+                                }))
+                            },
+                        ))),
+                )
             })?
             .workers(listen_cfg.workers)
-            .maxconn(listen_cfg.max_connections / listen_cfg.workers)
-            .run()
-            .await?;
+            .maxconn(MAXCONN)
+            .shutdown_timeout(Runtime::instance().settings.node.shutdown.drain_timeout.as_secs())
+            .disable_signals()
+            .run();
+        SERVERS.with(|servers| {
+            servers.borrow_mut().insert((ListenerType::Tcp, listen_cfg.addr.port()), server.clone())
+        });
+        server.await?;
         Ok(())
     }
 
@@ -213,53 +300,32 @@ async fn listen(name: String, listen_cfg: &Listener) -> Result<()> {
 
 async fn listen_tls(name: String, listen_cfg: &Listener) -> Result<()> {
     async fn _listen_tls(name: &str, listen_cfg: &Listener) -> Result<()> {
-        let cert_file = listen_cfg.cert.as_ref().ok_or::<MqttError>("cert is None".into())?;
-        let key_file = listen_cfg.key.as_ref().ok_or::<MqttError>("key is None".into())?;
-
-        let cert_chain = rustls::pki_types::CertificateDer::pem_file_iter(cert_file)
-            .map_err(|e| anyhow!(e))?
-            .collect::<Result<Vec<_>, _>>()
-            .map_err(|e| anyhow!(e))?;
-        let key = rustls::pki_types::PrivateKeyDer::from_pem_file(key_file).map_err(|e| anyhow!(e))?;
-
-        let provider = Arc::new(provider::default_provider());
-        let client_auth = if listen_cfg.cross_certificate {
-            let root_chain = cert_chain.clone();
-            let mut client_auth_roots = RootCertStore::empty();
-            for root in root_chain {
-                client_auth_roots.add(root).map_err(|e| anyhow!(e))?;
-            }
-            WebPkiClientVerifier::builder_with_provider(client_auth_roots.into(), provider.clone())
-                .build()
-                .map_err(|e| anyhow!(e))?
-        } else {
-            WebPkiClientVerifier::no_client_auth()
-        };
-
-        let tls_config = ServerConfig::builder_with_provider(provider)
-            .with_safe_default_protocol_versions()
-            .map_err(|e| anyhow!(e))?
-            .with_client_cert_verifier(client_auth)
-            .with_single_cert(cert_chain, key)
-            .map_err(|e| anyhow!(format!("bad certs/private key, {}", e)))?;
-
+        let tls_config = rmqtt::tls::server_config(listen_cfg)?;
         let tls_acceptor = Acceptor::new(tls_config);
 
         let max_inflight = listen_cfg.max_inflight.get() as usize;
         let handshake_timeout = listen_cfg.handshake_timeout();
         let max_size = listen_cfg.max_packet_size.as_u32();
-        ntex::server::Server::build()
+        let proxy_protocol = listen_cfg.proxy_protocol;
+        let proxy_protocol_timeout = listen_cfg.proxy_protocol_timeout;
+        let server = ntex::server::Server::build()
             .backlog(listen_cfg.backlog)
             .reuseaddr(listen_cfg.reuseaddr)
             .reuseport(listen_cfg.reuseport)
             .bind(name, listen_cfg.addr, move || {
-                pipeline_factory(tls_acceptor.clone())
-                    .map_err(|e| ntex_mqtt::MqttError::Service(MqttError::from(e)))
+                pipeline_factory(proxy::ProxyServer::new(proxy_protocol, proxy_protocol_timeout))
+                    .and_then(
+                        pipeline_factory(tls_acceptor.clone())
+                            .map_err(|e| ntex_mqtt::MqttError::Service(MqttError::from(e))),
+                    )
                     .and_then(
                         MqttServer::new()
                             .v3(v3::MqttServer::new(
-                                move |mut handshake: HandshakeV3<TlsStream<TcpStream>>| async {
-                                    let (io, _) = handshake.io().get_ref();
+                                move |mut handshake: HandshakeV3<
+                                    TlsStream<proxy::ProxyStream<TcpStream>>,
+                                >| async {
+                                    let (io, conn) = handshake.io().get_ref();
+                                    let peer_cert = PeerCert::from_conn(conn);
                                     let peer_addr = io.peer_addr()?;
                                     let local_addr = io.local_addr()?;
                                     let listen_cfg = Runtime::instance()
@@ -274,7 +340,8 @@ async fn listen_tls(name: String, listen_cfg: &Listener) -> Result<()> {
                                             MqttError::ListenerConfigError
                                         })?;
 
-                                    handshake_v3(listen_cfg, handshake, peer_addr, local_addr).await
+                                    handshake_v3(listen_cfg, handshake, peer_addr, local_addr, peer_cert)
+                                        .await
                                 },
                             )
                             //.v3(v3::MqttServer::new(handshake_v3)
@@ -294,8 +361,11 @@ async fn listen_tls(name: String, listen_cfg: &Listener) -> Result<()> {
                             .v5(
                                 //v5::MqttServer::new(handshake_v5)
                                 v5::MqttServer::new(
-                                    move |mut handshake: HandshakeV5<TlsStream<TcpStream>>| async {
-                                        let (io, _) = handshake.io().get_ref();
+                                    move |mut handshake: HandshakeV5<
+                                        TlsStream<proxy::ProxyStream<TcpStream>>,
+                                    >| async {
+                                        let (io, conn) = handshake.io().get_ref();
+                                        let peer_cert = PeerCert::from_conn(conn);
                                         let peer_addr = io.peer_addr()?;
                                         let local_addr = io.local_addr()?;
                                         let listen_cfg = Runtime::instance()
@@ -309,7 +379,8 @@ async fn listen_tls(name: String, listen_cfg: &Listener) -> Result<()> {
                                                 );
                                                 MqttError::ListenerConfigError
                                             })?;
-                                        handshake_v5(listen_cfg, handshake, peer_addr, local_addr).await
+                                        handshake_v5(listen_cfg, handshake, peer_addr, local_addr, peer_cert)
+                                            .await
                                     },
                                 )
                                 .receive_max(max_inflight as u16)
@@ -333,9 +404,14 @@ async fn listen_tls(name: String, listen_cfg: &Listener) -> Result<()> {
                     )
             })?
             .workers(listen_cfg.workers)
-            .maxconn(listen_cfg.max_connections / listen_cfg.workers)
-            .run()
-            .await?;
+            .maxconn(MAXCONN)
+            .shutdown_timeout(Runtime::instance().settings.node.shutdown.drain_timeout.as_secs())
+            .disable_signals()
+            .run();
+        SERVERS.with(|servers| {
+            servers.borrow_mut().insert((ListenerType::Tls, listen_cfg.addr.port()), server.clone())
+        });
+        server.await?;
         Ok(())
     }
 
@@ -357,83 +433,102 @@ async fn listen_ws(name: String, listen_cfg: &Listener) -> Result<()> {
         let max_inflight = listen_cfg.max_inflight.get() as usize;
         let handshake_timeout = listen_cfg.handshake_timeout();
         let max_size = listen_cfg.max_packet_size.as_u32();
-        ntex::server::Server::build()
+        let proxy_protocol = listen_cfg.proxy_protocol;
+        let proxy_protocol_timeout = listen_cfg.proxy_protocol_timeout;
+        let server = ntex::server::Server::build()
             .backlog(listen_cfg.backlog)
             .reuseaddr(listen_cfg.reuseaddr)
             .reuseport(listen_cfg.reuseport)
             .bind(name, listen_cfg.addr, move || {
-                pipeline_factory(ws::WSServer::new(Duration::from_secs(handshake_timeout as u64))).and_then(
-                    MqttServer::new()
-                        .v3(v3::MqttServer::new(
-                            move |mut handshake: HandshakeV3<ws::WsStream<TcpStream>>| async {
-                                let io = handshake.io().get_ref();
-                                let remote_addr = io.peer_addr()?;
-                                let local_addr = io.local_addr()?;
-                                let listen_cfg =
-                                    Runtime::instance().settings.listeners.ws(local_addr.port()).ok_or_else(
-                                        || {
+                pipeline_factory(proxy::ProxyServer::new(proxy_protocol, proxy_protocol_timeout))
+                    .and_then(ws::WSServer::new(Duration::from_secs(handshake_timeout as u64)))
+                    .and_then(
+                        MqttServer::new()
+                            .v3(v3::MqttServer::new(
+                                move |mut handshake: HandshakeV3<
+                                    ws::WsStream<proxy::ProxyStream<TcpStream>>,
+                                >| async {
+                                    let io = handshake.io().get_ref();
+                                    let peer_cert = io.peer_cert();
+                                    let remote_addr = io.peer_addr()?;
+                                    let local_addr = io.local_addr()?;
+                                    let listen_cfg = Runtime::instance()
+                                        .settings
+                                        .listeners
+                                        .ws(local_addr.port())
+                                        .ok_or_else(|| {
                                             log::error!(
                                                 "ws listener config is not found, local addr is {:?}",
                                                 local_addr
                                             );
                                             MqttError::ListenerConfigError
-                                        },
-                                    )?;
-                                handshake_v3(listen_cfg, handshake, remote_addr, local_addr).await
-                            },
-                        )
-                        .inflight(max_inflight)
-                        .handshake_timeout(handshake_timeout)
-                        .max_size(max_size)
-                        .publish(fn_factory_with_config(|session: v3::Session<SessionState>| {
-                            ok::<_, MqttError>(fn_service(move |req| publish_v3(session.clone(), req)))
-                        }))
-                        .control(fn_factory_with_config(
-                            |session: v3::Session<SessionState>| {
-                                ok::<_, MqttError>(fn_service(move |req| {
-                                    control_message_v3(session.clone(), req)
-                                }))
-                            },
-                        )))
-                        .v5(v5::MqttServer::new(
-                            move |mut handshake: HandshakeV5<ws::WsStream<TcpStream>>| async {
-                                let io = handshake.io().get_ref();
-                                let remote_addr = io.peer_addr()?;
-                                let local_addr = io.local_addr()?;
-                                let listen_cfg =
-                                    Runtime::instance().settings.listeners.ws(local_addr.port()).ok_or_else(
-                                        || {
+                                        })?;
+                                    handshake_v3(listen_cfg, handshake, remote_addr, local_addr, peer_cert)
+                                        .await
+                                },
+                            )
+                            .inflight(max_inflight)
+                            .handshake_timeout(handshake_timeout)
+                            .max_size(max_size)
+                            .publish(fn_factory_with_config(|session: v3::Session<SessionState>| {
+                                ok::<_, MqttError>(fn_service(move |req| publish_v3(session.clone(), req)))
+                            }))
+                            .control(fn_factory_with_config(
+                                |session: v3::Session<SessionState>| {
+                                    ok::<_, MqttError>(fn_service(move |req| {
+                                        control_message_v3(session.clone(), req)
+                                    }))
+                                },
+                            )))
+                            .v5(v5::MqttServer::new(
+                                move |mut handshake: HandshakeV5<
+                                    ws::WsStream<proxy::ProxyStream<TcpStream>>,
+                                >| async {
+                                    let io = handshake.io().get_ref();
+                                    let peer_cert = io.peer_cert();
+                                    let remote_addr = io.peer_addr()?;
+                                    let local_addr = io.local_addr()?;
+                                    let listen_cfg = Runtime::instance()
+                                        .settings
+                                        .listeners
+                                        .ws(local_addr.port())
+                                        .ok_or_else(|| {
                                             log::error!(
                                                 "ws listener config is not found, local addr is {:?}",
                                                 local_addr
                                             );
                                             MqttError::ListenerConfigError
-                                        },
-                                    )?;
-                                handshake_v5(listen_cfg, handshake, remote_addr, local_addr).await
-                            },
-                        )
-                        .receive_max(max_inflight as u16)
-                        .handshake_timeout(handshake_timeout)
-                        .max_size(max_size)
-                        // .max_qos(max_qos)
-                        //.max_topic_alias(max_topic_alias),
-                        .publish(fn_factory_with_config(|session: v5::Session<SessionState>| {
-                            ok::<_, MqttError>(fn_service(move |req| publish_v5(session.clone(), req)))
-                        }))
-                        .control(fn_factory_with_config(
-                            |session: v5::Session<SessionState>| {
-                                ok::<_, MqttError>(fn_service(move |req| {
-                                    control_message_v5(session.clone(), req)
-                                }))
-                            },
-                        ))),
-                )
+                                        })?;
+                                    handshake_v5(listen_cfg, handshake, remote_addr, local_addr, peer_cert)
+                                        .await
+                                },
+                            )
+                            .receive_max(max_inflight as u16)
+                            .handshake_timeout(handshake_timeout)
+                            .max_size(max_size)
+                            // .max_qos(max_qos)
+                            //.max_topic_alias(max_topic_alias),
+                            .publish(fn_factory_with_config(|session: v5::Session<SessionState>| {
+                                ok::<_, MqttError>(fn_service(move |req| publish_v5(session.clone(), req)))
+                            }))
+                            .control(fn_factory_with_config(
+                                |session: v5::Session<SessionState>| {
+                                    ok::<_, MqttError>(fn_service(move |req| {
+                                        control_message_v5(session.clone(), req)
+                                    }))
+                                },
+                            ))),
+                    )
             })?
             .workers(listen_cfg.workers)
-            .maxconn(listen_cfg.max_connections / listen_cfg.workers)
-            .run()
-            .await?;
+            .maxconn(MAXCONN)
+            .shutdown_timeout(Runtime::instance().settings.node.shutdown.drain_timeout.as_secs())
+            .disable_signals()
+            .run();
+        SERVERS.with(|servers| {
+            servers.borrow_mut().insert((ListenerType::Ws, listen_cfg.addr.port()), server.clone())
+        });
+        server.await?;
         Ok(())
     }
 
@@ -445,54 +540,33 @@ async fn listen_ws(name: String, listen_cfg: &Listener) -> Result<()> {
 
 async fn listen_wss(name: String, listen_cfg: &Listener) -> Result<()> {
     async fn _listen_wss(name: &str, listen_cfg: &Listener) -> Result<()> {
-        let cert_file = listen_cfg.cert.as_ref().ok_or::<MqttError>("cert is None".into())?;
-        let key_file = listen_cfg.key.as_ref().ok_or::<MqttError>("key is None".into())?;
-
-        let cert_chain = rustls::pki_types::CertificateDer::pem_file_iter(cert_file)
-            .map_err(|e| anyhow!(e))?
-            .collect::<Result<Vec<_>, _>>()
-            .map_err(|e| anyhow!(e))?;
-        let key = rustls::pki_types::PrivateKeyDer::from_pem_file(key_file).map_err(|e| anyhow!(e))?;
-
-        let provider = Arc::new(provider::default_provider());
-        let client_auth = if listen_cfg.cross_certificate {
-            let root_chain = cert_chain.clone();
-            let mut client_auth_roots = RootCertStore::empty();
-            for root in root_chain {
-                client_auth_roots.add(root).map_err(|e| anyhow!(e))?;
-            }
-            WebPkiClientVerifier::builder_with_provider(client_auth_roots.into(), provider.clone())
-                .build()
-                .map_err(|e| anyhow!(e))?
-        } else {
-            WebPkiClientVerifier::no_client_auth()
-        };
-
-        let tls_config = ServerConfig::builder_with_provider(provider)
-            .with_safe_default_protocol_versions()
-            .map_err(|e| anyhow!(e))?
-            .with_client_cert_verifier(client_auth)
-            .with_single_cert(cert_chain, key)
-            .map_err(|e| anyhow!(format!("bad certs/private key, {}", e)))?;
-
+        let tls_config = rmqtt::tls::server_config(listen_cfg)?;
         let tls_acceptor = Acceptor::new(tls_config);
 
         let max_inflight = listen_cfg.max_inflight.get() as usize;
         let handshake_timeout = listen_cfg.handshake_timeout();
         let max_size = listen_cfg.max_packet_size.as_u32();
-        ntex::server::Server::build()
+        let proxy_protocol = listen_cfg.proxy_protocol;
+        let proxy_protocol_timeout = listen_cfg.proxy_protocol_timeout;
+        let server = ntex::server::Server::build()
             .backlog(listen_cfg.backlog)
             .reuseaddr(listen_cfg.reuseaddr)
             .reuseport(listen_cfg.reuseport)
             .bind(name, listen_cfg.addr, move || {
-                pipeline_factory(tls_acceptor.clone())
-                    .map_err(|e| ntex_mqtt::MqttError::Service(MqttError::from(e)))
+                pipeline_factory(proxy::ProxyServer::new(proxy_protocol, proxy_protocol_timeout))
+                    .and_then(
+                        pipeline_factory(tls_acceptor.clone())
+                            .map_err(|e| ntex_mqtt::MqttError::Service(MqttError::from(e))),
+                    )
                     .and_then(ws::WSServer::new(Duration::from_secs(handshake_timeout as u64)))
                     .and_then(
                         MqttServer::new()
                             .v3(v3::MqttServer::new(
-                                move |mut handshake: HandshakeV3<ws::WsStream<TlsStream<TcpStream>>>| async {
-                                    let (io, _) = handshake.io().get_ref().get_ref();
+                                move |mut handshake: HandshakeV3<
+                                    ws::WsStream<TlsStream<proxy::ProxyStream<TcpStream>>>,
+                                >| async {
+                                    let (io, conn) = handshake.io().get_ref().get_ref();
+                                    let peer_cert = PeerCert::from_conn(conn);
                                     let peer_addr = io.peer_addr()?;
                                     let local_addr = io.local_addr()?;
                                     let listen_cfg = Runtime::instance()
@@ -507,7 +581,8 @@ async fn listen_wss(name: String, listen_cfg: &Listener) -> Result<()> {
                                             MqttError::ListenerConfigError
                                         })?;
 
-                                    handshake_v3(listen_cfg, handshake, peer_addr, local_addr).await
+                                    handshake_v3(listen_cfg, handshake, peer_addr, local_addr, peer_cert)
+                                        .await
                                 },
                             )
                             .inflight(max_inflight)
@@ -524,8 +599,11 @@ async fn listen_wss(name: String, listen_cfg: &Listener) -> Result<()> {
                                 },
                             )))
                             .v5(v5::MqttServer::new(
-                                move |mut handshake: HandshakeV5<ws::WsStream<TlsStream<TcpStream>>>| async {
-                                    let (io, _) = handshake.io().get_ref().get_ref();
+                                move |mut handshake: HandshakeV5<
+                                    ws::WsStream<TlsStream<proxy::ProxyStream<TcpStream>>>,
+                                >| async {
+                                    let (io, conn) = handshake.io().get_ref().get_ref();
+                                    let peer_cert = PeerCert::from_conn(conn);
                                     let peer_addr = io.peer_addr()?;
                                     let local_addr = io.local_addr()?;
                                     let listen_cfg = Runtime::instance()
@@ -539,7 +617,8 @@ async fn listen_wss(name: String, listen_cfg: &Listener) -> Result<()> {
                                             );
                                             MqttError::ListenerConfigError
                                         })?;
-                                    handshake_v5(listen_cfg, handshake, peer_addr, local_addr).await
+                                    handshake_v5(listen_cfg, handshake, peer_addr, local_addr, peer_cert)
+                                        .await
                                 },
                             )
                             .receive_max(max_inflight as u16)
@@ -560,9 +639,14 @@ async fn listen_wss(name: String, listen_cfg: &Listener) -> Result<()> {
                     )
             })?
             .workers(listen_cfg.workers)
-            .maxconn(listen_cfg.max_connections / listen_cfg.workers)
-            .run()
-            .await?;
+            .maxconn(MAXCONN)
+            .shutdown_timeout(Runtime::instance().settings.node.shutdown.drain_timeout.as_secs())
+            .disable_signals()
+            .run();
+        SERVERS.with(|servers| {
+            servers.borrow_mut().insert((ListenerType::Wss, listen_cfg.addr.port()), server.clone())
+        });
+        server.await?;
         Ok(())
     }
 
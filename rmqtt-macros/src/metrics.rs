@@ -1,13 +1,17 @@
 use quote::quote;
-use syn::{parse_macro_input, Data, DeriveInput, Fields, FieldsNamed, Ident};
+use syn::{parse_macro_input, Data, DeriveInput, Field, Fields, FieldsNamed, Ident, Type};
 
 pub(crate) fn build(input: proc_macro::TokenStream) -> proc_macro::TokenStream {
     let input = parse_macro_input!(input as DeriveInput);
 
     let name = input.ident;
 
-    let clone_items = get_fields_named(&input.data)
-        .named
+    let fields = &get_fields_named(&input.data).named;
+    //AtomicUsize fields are counters, the other fields, such as histograms, provide
+    //clone(), Default, add(&other) and to_json()
+    let (counters, others): (Vec<&Field>, Vec<&Field>) = fields.iter().partition(|f| is_counter(&f.ty));
+
+    let clone_items = counters
         .iter()
         .map(|f| {
             let name = &f.ident;
@@ -15,10 +19,15 @@ pub(crate) fn build(input: proc_macro::TokenStream) -> proc_macro::TokenStream {
                 #name: AtomicUsize::new(self.#name.load(Ordering::SeqCst)),
             )
         })
+        .chain(others.iter().map(|f| {
+            let name = &f.ident;
+            quote!(
+                #name: self.#name.clone(),
+            )
+        }))
         .collect::<Vec<_>>();
 
-    let init_items = get_fields_named(&input.data)
-        .named
+    let init_items = counters
         .iter()
         .map(|f| {
             let name = &f.ident;
@@ -26,25 +35,35 @@ pub(crate) fn build(input: proc_macro::TokenStream) -> proc_macro::TokenStream {
                 #name: AtomicUsize::new(0),
             )
         })
+        .chain(others.iter().map(|f| {
+            let name = &f.ident;
+            quote!(
+                #name: Default::default(),
+            )
+        }))
         .collect::<Vec<_>>();
 
-    let inc_items = get_fields_named(&input.data)
-        .named
+    let inc_items = counters
         .iter()
         .map(|f| {
             let name = &f.ident;
             let fn_name = name.as_ref().map(|ref i| Ident::new(&format!("{}_inc", i), i.span()));
+            let add_fn_name = name.as_ref().map(|ref i| Ident::new(&format!("{}_add", i), i.span()));
             quote! {
                 #[inline]
                 pub fn #fn_name(&self) {
                     self.#name.fetch_add(1, Ordering::SeqCst);
                 }
+
+                #[inline]
+                pub fn #add_fn_name(&self, n: usize) {
+                    self.#name.fetch_add(n, Ordering::SeqCst);
+                }
             }
         })
         .collect::<Vec<_>>();
 
-    let get_items = get_fields_named(&input.data)
-        .named
+    let get_items = counters
         .iter()
         .map(|f| {
             let name = &f.ident;
@@ -56,22 +75,37 @@ pub(crate) fn build(input: proc_macro::TokenStream) -> proc_macro::TokenStream {
                 }
             }
         })
+        .chain(others.iter().map(|f| {
+            let name = &f.ident;
+            let ty = &f.ty;
+            quote! {
+                #[inline]
+                pub fn #name(&self) -> &#ty {
+                    &self.#name
+                }
+            }
+        }))
         .collect::<Vec<_>>();
 
-    let json_items = get_fields_named(&input.data)
-        .named
+    let json_items = counters
         .iter()
         .map(|f| {
             let name = &f.ident;
             let attr_name = f.ident.as_ref().map(|i| i.to_string().replace('_', "."));
             quote!(
-                #attr_name : self.#name.load(Ordering::SeqCst),
+                map.insert(#attr_name.into(), serde_json::Value::from(self.#name.load(Ordering::SeqCst)));
             )
         })
+        .chain(others.iter().map(|f| {
+            let name = &f.ident;
+            let attr_name = f.ident.as_ref().map(|i| i.to_string().replace('_', "."));
+            quote!(
+                map.insert(#attr_name.into(), self.#name.to_json());
+            )
+        }))
         .collect::<Vec<_>>();
 
-    let add_items = get_fields_named(&input.data)
-        .named
+    let add_items = counters
         .iter()
         .map(|f| {
             let name = &f.ident;
@@ -79,18 +113,29 @@ pub(crate) fn build(input: proc_macro::TokenStream) -> proc_macro::TokenStream {
                 self.#name.fetch_add(other.#name.load(Ordering::SeqCst), Ordering::SeqCst);
             )
         })
+        .chain(others.iter().map(|f| {
+            let name = &f.ident;
+            quote!(
+                self.#name.add(&other.#name);
+            )
+        }))
         .collect::<Vec<_>>();
 
-    let prometheus_items = get_fields_named(&input.data)
-        .named
+    let prometheus_items = counters
         .iter()
         .map(|f| {
             let name = &f.ident;
             let attr_name = f.ident.as_ref().map(|i| i.to_string().replace('_', "."));
             quote!(
-                metrics_gauge_vec
-                    .with_label_values(&[&label, #attr_name])
-                    .set(self.#name.load(Ordering::SeqCst) as i64);
+                let counter = metrics_counter_vec.with_label_values(&[&label, #attr_name]);
+                let value = self.#name.load(Ordering::SeqCst) as u64;
+                let current = counter.get();
+                if value >= current {
+                    counter.inc_by(value - current);
+                } else {
+                    counter.reset();
+                    counter.inc_by(value);
+                }
             )
         })
         .collect::<Vec<_>>();
@@ -119,9 +164,9 @@ pub(crate) fn build(input: proc_macro::TokenStream) -> proc_macro::TokenStream {
 
             #[inline]
             pub fn to_json(&self) -> serde_json::Value {
-                serde_json::json!({
-                    #(#json_items)*
-                })
+                let mut map = serde_json::Map::new();
+                #(#json_items)*
+                serde_json::Value::Object(map)
             }
 
             #[inline]
@@ -129,8 +174,10 @@ pub(crate) fn build(input: proc_macro::TokenStream) -> proc_macro::TokenStream {
                 #(#add_items)*
             }
 
+            ///Sets the counters to the values of the metrics, they are not reset before, so that
+            ///scrapes in the meantime do not see them drop to zero
             #[inline]
-            pub fn build_prometheus_metrics(&self, label: &str, metrics_gauge_vec: &prometheus::IntGaugeVec) {
+            pub fn build_prometheus_metrics(&self, label: &str, metrics_counter_vec: &prometheus::IntCounterVec) {
                 #(#prometheus_items)*
             }
 
@@ -153,3 +200,10 @@ fn get_fields_named(data: &Data) -> &FieldsNamed {
         Data::Enum(_) | Data::Union(_) => unreachable!(),
     }
 }
+
+fn is_counter(ty: &Type) -> bool {
+    match ty {
+        Type::Path(p) => p.path.segments.last().map(|s| s.ident == "AtomicUsize").unwrap_or_default(),
+        _ => false,
+    }
+}
@@ -6,6 +6,7 @@ use serde::ser::{self, Serialize};
 
 use rmqtt::broker::hook::Priority;
 use rmqtt::broker::topic::TopicTree;
+use rmqtt::grpc::MessageType;
 use rmqtt::{
     ahash, dashmap, log,
     serde_json::{self, Value},
@@ -14,10 +15,39 @@ use rmqtt::{
 };
 use rmqtt::{ClientId, MqttError, Password, Result, Superuser, Topic, UserName};
 
+use rmqtt_storage::Config;
+
 type DashSet<V> = dashmap::DashSet<V, ahash::RandomState>;
 
 pub const PH_C: &str = "%c";
 pub const PH_U: &str = "%u";
+//Common name of the client certificate
+pub const PH_CN: &str = "%C";
+//Subject distinguished name of the client certificate
+pub const PH_DN: &str = "%d";
+
+#[inline]
+pub(crate) fn has_placeholder(s: &str) -> bool {
+    s.contains(PH_U) || s.contains(PH_C) || s.contains(PH_CN) || s.contains(PH_DN)
+}
+
+//Wildcards must take a whole level and '#' must be the last level. The placeholders are checked as
+//a normal level, they are replaced when the client connects.
+fn check_topic_filter(topic_filter: &str) -> Result<()> {
+    let tf = topic_filter.replace(PH_U, "u").replace(PH_C, "c").replace(PH_CN, "cn").replace(PH_DN, "dn");
+    let levels = tf.split('/').collect::<Vec<_>>();
+    let valid = !tf.is_empty()
+        && levels.iter().enumerate().all(|(i, level)| match *level {
+            "#" => i == levels.len() - 1,
+            "+" => true,
+            level => !level.contains(['#', '+']),
+        });
+    if valid {
+        Ok(())
+    } else {
+        Err(MqttError::from(format!("invalid topic filter '{}'", topic_filter)))
+    }
+}
 
 #[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct PluginConfig {
@@ -29,6 +59,14 @@ pub struct PluginConfig {
     #[serde(default = "PluginConfig::priority_default")]
     pub priority: Priority,
 
+    ///gRPC message type, used to synchronise the rules edited at runtime across the cluster
+    #[serde(default = "PluginConfig::message_type_default")]
+    pub message_type: MessageType,
+
+    ///Storage of the rules edited at runtime, they are not persisted if not configured
+    #[serde(default)]
+    pub storage: Option<Config>,
+
     #[serde(
         default,
         serialize_with = "PluginConfig::serialize_rules",
@@ -46,12 +84,35 @@ impl PluginConfig {
         10
     }
 
+    fn message_type_default() -> MessageType {
+        96
+    }
+
     #[inline]
     pub fn rules(&self) -> &Vec<Rule> {
         let (_rules, _) = &self.rules;
         _rules
     }
 
+    ///Rules in the configuration format
+    #[inline]
+    pub fn rules_json(&self) -> Vec<serde_json::Value> {
+        let (_, json_rules) = &self.rules;
+        json_rules.as_array().cloned().unwrap_or_default()
+    }
+
+    ///Replaces all rules, nothing is changed if any rule is invalid
+    #[inline]
+    pub fn set_rules(&mut self, json_rules: Vec<serde_json::Value>) -> Result<()> {
+        let rules = json_rules
+            .iter()
+            .enumerate()
+            .map(|(i, rule)| Rule::try_from(rule).map_err(|e| MqttError::from(format!("rule {}, {}", i, e))))
+            .collect::<Result<Vec<_>>>()?;
+        self.rules = (rules, serde_json::Value::Array(json_rules));
+        Ok(())
+    }
+
     #[inline]
     fn serialize_rules<S>(
         rules: &(Vec<Rule>, serde_json::Value),
@@ -143,7 +204,9 @@ impl std::convert::TryFrom<&serde_json::Value> for Rule {
             let access = Access::try_from(access_cfg)?;
             let users = users_try_from(user_cfg, access)?;
             let control = Control::try_from(control_cfg)?;
-            let topics = Topics::try_from(topics_cfg)?;
+            let topics = Topics::try_from(topics_cfg).map_err(|e| {
+                MqttError::from(format!("ACL Rule config error, {}, rule config is {:?}", e, rule_cfg))
+            })?;
             if topics_cfg.is_some() && matches!(control, Control::Connect) {
                 log::warn!("ACL Rule config, the third column of a quadruple is Connect, but the fourth column is not empty! topics config is {:?}", topics_cfg);
             }
@@ -351,7 +414,8 @@ impl std::convert::TryFrom<Option<&serde_json::Value>> for Topics {
                 for topic in topics.iter() {
                     match topic {
                         Value::String(topic) => {
-                            if topic.contains(PH_U) || topic.contains(PH_C) {
+                            check_topic_filter(topic)?;
+                            if has_placeholder(topic) {
                                 placeholders.push(topic.clone());
                             } else {
                                 tree.insert(&Topic::from_str(topic.as_str())?, ());
@@ -359,7 +423,7 @@ impl std::convert::TryFrom<Option<&serde_json::Value>> for Topics {
                         }
                         Value::Object(eq_map) => match eq_map.get("eq") {
                             Some(Value::String(eq)) => {
-                                if eq.contains(PH_U) || eq.contains(PH_C) {
+                                if has_placeholder(eq) {
                                     eq_placeholders.push(eq.clone());
                                 } else {
                                     eqs.insert(eq.clone());
@@ -382,3 +446,38 @@ impl std::convert::TryFrom<Option<&serde_json::Value>> for Topics {
         })
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn topic_filter() {
+        for tf in ["#", "+", "a/b", "a/+/b", "a/#", "/a/#", "+/+/#", "a//b", "sensor/%c/#", "user/%u/+/ctrl"]
+        {
+            assert!(check_topic_filter(tf).is_ok(), "{}", tf);
+        }
+        for tf in ["", "a/#/b", "#/a", "a#", "a/b+", "a/+b/c", "sensor/%c#", "%u+/x"] {
+            assert!(check_topic_filter(tf).is_err(), "{}", tf);
+        }
+    }
+
+    #[test]
+    fn rule_with_invalid_topic_filter() {
+        assert!(Rule::try_from(&json!(["allow", "all", "pubsub", ["a/+/b", {"eq": "a/#"}]])).is_ok());
+        let e = Rule::try_from(&json!(["allow", "all", "pubsub", ["a/#/b"]])).unwrap_err();
+        assert!(e.to_string().contains("a/#/b"));
+        assert!(Rule::try_from(&json!(["allow", "all", "pubsub", ["sensor/%c+"]])).is_err());
+
+        let mut cfg = serde_json::from_value::<PluginConfig>(json!({})).unwrap();
+        let e = cfg
+            .set_rules(vec![
+                json!(["allow", "all", "pubsub", ["a/b"]]),
+                json!(["deny", "all", "publish", ["x/#/y"]]),
+            ])
+            .unwrap_err();
+        assert!(e.to_string().starts_with("rule 1,"));
+        assert!(cfg.rules().is_empty());
+    }
+}
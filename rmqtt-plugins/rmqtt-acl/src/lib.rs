@@ -5,41 +5,323 @@ extern crate serde;
 #[macro_use]
 extern crate rmqtt_macros;
 
+use std::net::{IpAddr, SocketAddr};
 use std::str::FromStr;
 use std::sync::Arc;
+use std::time::Duration;
 
-use config::{Access, Control, PluginConfig, PH_C, PH_U};
+use config::{Access, Control, PluginConfig, Rule, PH_C, PH_CN, PH_DN, PH_U};
 use rmqtt::{
     async_trait::async_trait,
-    log, serde_json,
-    tokio::{self, sync::RwLock},
+    log,
+    serde_json::{self, json},
+    tokio::{
+        self,
+        sync::{Mutex, RwLock},
+    },
 };
 use rmqtt::{
-    broker::hook::{Handler, HookResult, Parameter, Register, ReturnType, Type},
+    broker::hook::{dry_run_note, is_dry_run, Handler, HookResult, Parameter, Register, ReturnType, Type},
+    broker::topic::TopicTree,
     broker::types::{AuthResult, PublishAclResult, SubscribeAckReason, SubscribeAclResult, Topic},
+    grpc::{
+        broadcast_data, collect_data, Message as GrpcMessage, MessageReply as GrpcMessageReply, MessageType,
+    },
     plugin::{PackageInfo, Plugin},
-    register, Result, Runtime,
+    register, timestamp_millis, ClientId, Id, MqttError, NodeId, Password, Result, Runtime, TimestampMillis,
+    UserName,
 };
 
+use store::RuleStore;
+
 mod config;
+mod store;
 
 register!(AclPlugin::new);
 
+///Rule management, the commands of the HTTP API /api/v1/acl/rules
+#[derive(Deserialize, Serialize, Debug)]
+#[serde(tag = "cmd", rename_all = "snake_case")]
+enum Command {
+    List,
+    Insert {
+        //Defaults to the end of the rules
+        #[serde(default)]
+        position: Option<usize>,
+        rule: serde_json::Value,
+    },
+    Update {
+        index: usize,
+        rule: serde_json::Value,
+    },
+    Delete {
+        index: usize,
+    },
+    //Drops the rules edited at runtime and uses the rules of the configuration file again
+    Reset,
+    //Checks the rule and tests it against a sample connection
+    Validate {
+        rule: serde_json::Value,
+        connect_info: SampleConnectInfo,
+        //connect, publish, subscribe, pubsub or all
+        #[serde(default)]
+        action: Option<String>,
+        #[serde(default)]
+        topic: Option<String>,
+    },
+}
+
+#[derive(Deserialize, Serialize, Debug)]
+struct SampleConnectInfo {
+    clientid: String,
+    #[serde(default)]
+    username: Option<String>,
+    #[serde(default)]
+    password: Option<String>,
+    #[serde(default)]
+    ipaddr: Option<IpAddr>,
+    //MQTT Protocol Ver, 3=MQTT 3.1, 4=MQTT 3.11, 5=MQTT 5.0
+    #[serde(default)]
+    protocol: Option<u8>,
+}
+
+impl SampleConnectInfo {
+    #[inline]
+    fn id(&self, node_id: NodeId) -> Id {
+        Id::new(
+            node_id,
+            None,
+            self.ipaddr.map(|ip| SocketAddr::new(ip, 0)),
+            ClientId::from(self.clientid.as_str()),
+            self.username.as_deref().map(UserName::from),
+        )
+    }
+}
+
+///Messages used to synchronise the rules across the cluster
+#[derive(Deserialize, Serialize, Debug)]
+enum Message {
+    SetRules { version: TimestampMillis, rules: Vec<serde_json::Value> },
+    //The rules of the configuration file are used from this version on
+    Reset { version: TimestampMillis },
+    //Answered with the version and the rules, the rules are None if the last change was a reset
+    GetRules,
+}
+
+impl Message {
+    //JSON is used, the rules can not be encoded with bincode
+    #[inline]
+    fn encode(&self) -> Result<Vec<u8>> {
+        Ok(serde_json::to_vec(self)?)
+    }
+    #[inline]
+    fn decode(data: &[u8]) -> Result<Message> {
+        Ok(serde_json::from_slice::<Message>(data)?)
+    }
+}
+
 #[derive(Plugin)]
 struct AclPlugin {
     runtime: &'static Runtime,
     register: Box<dyn Register>,
     cfg: Arc<RwLock<PluginConfig>>,
+    store: Arc<RuleStore>,
+    //Serializes the rule edits of this node
+    editing: Mutex<()>,
 }
 
 impl AclPlugin {
     #[inline]
     async fn new<N: Into<String>>(runtime: &'static Runtime, name: N) -> Result<Self> {
         let name = name.into();
-        let cfg = Arc::new(RwLock::new(runtime.settings.plugins.load_config::<PluginConfig>(&name)?));
+        let mut cfg = runtime.settings.plugins.load_config::<PluginConfig>(&name)?;
+        let store = Arc::new(RuleStore::new(runtime.node.id(), cfg.storage.as_ref()).await?);
+        match store.load().await {
+            Ok(Some((version, Some(rules)))) => {
+                log::info!("{} load runtime rules, version: {}, count: {}", name, version, rules.len());
+                cfg.set_rules(rules)?;
+            }
+            Ok(Some((version, None))) => log::info!("{} rules were reset, version: {}", name, version),
+            Ok(None) => {}
+            Err(e) => log::warn!("{} load runtime rules error, {:?}", name, e),
+        }
+        let cfg = Arc::new(RwLock::new(cfg));
         log::debug!("{} AclPlugin cfg: {:?}", name, cfg.read().await);
         let register = runtime.extends.hook_mgr().await.register();
-        Ok(Self { runtime, register, cfg })
+        Ok(Self { runtime, register, cfg, store, editing: Mutex::new(()) })
+    }
+
+    //Edits a copy of the rules, applies it and synchronises it to the other nodes
+    async fn edit<F>(&self, f: F) -> Result<serde_json::Value>
+    where
+        F: FnOnce(&mut Vec<serde_json::Value>) -> Result<()>,
+    {
+        let _editing = self.editing.lock().await;
+        let mut rules = self.cfg.read().await.rules_json();
+        f(&mut rules)?;
+        let version = self.next_version();
+        apply_rules(&self.cfg, &self.store, version, rules.clone()).await?;
+        log::info!("{} rules changed, version: {}, count: {}", self.name(), version, rules.len());
+        self.broadcast(Message::SetRules { version, rules }).await?;
+        self.list().await
+    }
+
+    async fn reset(&self) -> Result<serde_json::Value> {
+        let _editing = self.editing.lock().await;
+        let version = self.next_version();
+        reset_rules(self.name(), &self.cfg, &self.store, version).await?;
+        self.broadcast(Message::Reset { version }).await?;
+        self.list().await
+    }
+
+    #[inline]
+    fn next_version(&self) -> TimestampMillis {
+        timestamp_millis().max(self.store.version() + 1)
+    }
+
+    async fn list(&self) -> Result<serde_json::Value> {
+        Ok(json!({
+            "version": self.store.version(),
+            "rules": self.cfg.read().await.rules_json(),
+        }))
+    }
+
+    async fn validate(
+        &self,
+        rule: serde_json::Value,
+        connect_info: SampleConnectInfo,
+        action: Option<String>,
+        topic: Option<String>,
+    ) -> Result<serde_json::Value> {
+        let rule = Rule::try_from(&rule)?;
+        let action = Control::try_from(action.map(serde_json::Value::String).as_ref())?;
+        let id = connect_info.id(self.runtime.node.id());
+        let password = connect_info.password.map(Password::from);
+        build_placeholders(&rule, &id).await;
+
+        let allow = matches!(rule.access, Access::Allow);
+        let (hit, superuser) = rule.hit(&id, password.as_ref(), connect_info.protocol, allow);
+        let control_match = match action {
+            Control::All => true,
+            Control::Connect => matches!(rule.control, Control::Connect | Control::All),
+            Control::Publish => matches!(rule.control, Control::Publish | Control::Pubsub | Control::All),
+            Control::Subscribe => matches!(rule.control, Control::Subscribe | Control::Pubsub | Control::All),
+            Control::Pubsub => matches!(rule.control, Control::Pubsub | Control::All),
+        };
+        let topic_match = match (action, topic) {
+            (Control::Connect, _) | (_, None) => None,
+            (_, Some(t)) => {
+                let topic = Topic::from_str(&t).unwrap_or_else(|_| Topic::from(Vec::new()));
+                Some(rule.topics.is_match(&topic, &t).await)
+            }
+        };
+        Ok(json!({
+            "matched": hit && control_match && topic_match.unwrap_or(true),
+            "access": if allow { "allow" } else { "deny" },
+            "hit": hit,
+            "superuser": superuser,
+            "control_match": control_match,
+            "topic_match": topic_match,
+        }))
+    }
+
+    async fn broadcast(&self, msg: Message) -> Result<()> {
+        let message_type = self.cfg.read().await.message_type;
+        broadcast_data(message_type, msg.encode()?, Duration::from_secs(10)).await;
+        Ok(())
+    }
+
+    //Takes the newest rules of the other nodes
+    async fn sync_from_cluster(&self) -> Result<()> {
+        let message_type = self.cfg.read().await.message_type;
+        let newest = collect_data(message_type, Message::GetRules.encode()?, Duration::from_secs(5))
+            .await
+            .into_iter()
+            .filter_map(|(id, data)| {
+                match serde_json::from_slice::<(TimestampMillis, Option<Vec<serde_json::Value>>)>(&data) {
+                    Ok(rules) => Some(rules),
+                    Err(e) => {
+                        log::warn!("get acl rules from node({}) error, {:?}", id, e);
+                        None
+                    }
+                }
+            })
+            .max_by_key(|(version, _)| *version);
+        match newest {
+            Some((version, Some(rules))) if version > self.store.version() => {
+                log::info!("{} take rules from cluster, version: {}", self.name(), version);
+                apply_rules(&self.cfg, &self.store, version, rules).await?;
+            }
+            Some((version, None)) if version > self.store.version() => {
+                log::info!("{} take reset from cluster, version: {}", self.name(), version);
+                reset_rules(self.name(), &self.cfg, &self.store, version).await?;
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+}
+
+//Replaces the rules in use and persists them
+async fn apply_rules(
+    cfg: &RwLock<PluginConfig>,
+    store: &RuleStore,
+    version: TimestampMillis,
+    rules: Vec<serde_json::Value>,
+) -> Result<()> {
+    cfg.write().await.set_rules(rules.clone())?;
+    build_sessions_placeholders(cfg).await;
+    store.save(version, &rules).await
+}
+
+//Restores the rules of the configuration file
+async fn reset_rules(
+    name: &str,
+    cfg: &RwLock<PluginConfig>,
+    store: &RuleStore,
+    version: TimestampMillis,
+) -> Result<()> {
+    let file_cfg = Runtime::instance().settings.plugins.load_config::<PluginConfig>(name)?;
+    cfg.write().await.set_rules(file_cfg.rules_json())?;
+    build_sessions_placeholders(cfg).await;
+    store.reset(version).await
+}
+
+//Rules that are replaced have no placeholders built for the sessions already connected to this node
+async fn build_sessions_placeholders(cfg: &RwLock<PluginConfig>) {
+    let cfg = cfg.read().await;
+    let has_placeholders = cfg
+        .rules()
+        .iter()
+        .any(|rule| !rule.topics.placeholders.is_empty() || !rule.topics.eq_placeholders.is_empty());
+    if !has_placeholders {
+        return;
+    }
+    let ids = Runtime::instance()
+        .extends
+        .shared()
+        .await
+        .iter()
+        .filter_map(|entry| entry.session().map(|s| s.id.clone()))
+        .collect::<Vec<_>>();
+    for id in ids {
+        for rule in cfg.rules() {
+            build_placeholders(rule, &id).await;
+        }
+    }
+}
+
+#[inline]
+async fn build_placeholders(rule: &Rule, id: &Id) {
+    for ph_tf in &rule.topics.placeholders {
+        let tf = replace_placeholders(ph_tf, id);
+        if let Err(e) = rule.add_topic_filter(&tf).await {
+            log::error!("acl config error, build_placeholders, add topic filter error, {:?}", e);
+        }
+    }
+
+    for eq_ph_t in &rule.topics.eq_placeholders {
+        rule.add_topic_to_eqs(replace_placeholders(eq_ph_t, id));
     }
 }
 
@@ -49,7 +331,10 @@ impl Plugin for AclPlugin {
     async fn init(&mut self) -> Result<()> {
         log::info!("{} init", self.name());
         let cfg = &self.cfg;
-        let priority = cfg.read().await.priority;
+        let (priority, message_type) = {
+            let cfg = cfg.read().await;
+            (cfg.priority, cfg.message_type)
+        };
         self.register.add_priority(Type::ClientConnected, priority, Box::new(AclHandler::new(cfg))).await;
         self.register.add_priority(Type::ClientAuthenticate, priority, Box::new(AclHandler::new(cfg))).await;
         self.register
@@ -58,6 +343,17 @@ impl Plugin for AclPlugin {
         self.register
             .add_priority(Type::MessagePublishCheckAcl, priority, Box::new(AclHandler::new(cfg)))
             .await;
+        self.register
+            .add(
+                Type::GrpcMessageReceived,
+                Box::new(RuleSyncHandler {
+                    name: self.name().to_owned(),
+                    cfg: cfg.clone(),
+                    store: self.store.clone(),
+                    message_type,
+                }),
+            )
+            .await;
         Ok(())
     }
 
@@ -68,8 +364,13 @@ impl Plugin for AclPlugin {
 
     #[inline]
     async fn load_config(&mut self) -> Result<()> {
-        let new_cfg = self.runtime.settings.plugins.load_config::<PluginConfig>(self.name())?;
+        let mut new_cfg = self.runtime.settings.plugins.load_config::<PluginConfig>(self.name())?;
+        //The rules edited at runtime take precedence over the configuration file
+        if self.store.edited() {
+            new_cfg.set_rules(self.cfg.read().await.rules_json())?;
+        }
         *self.cfg.write().await = new_cfg;
+        build_sessions_placeholders(&self.cfg).await;
         log::debug!("load_config ok,  {:?}", self.cfg);
         Ok(())
     }
@@ -78,6 +379,9 @@ impl Plugin for AclPlugin {
     async fn start(&mut self) -> Result<()> {
         log::info!("{} start", self.name());
         self.register.start().await;
+        if let Err(e) = self.sync_from_cluster().await {
+            log::warn!("{} sync rules from cluster error, {:?}", self.name(), e);
+        }
         Ok(())
     }
 
@@ -87,6 +391,117 @@ impl Plugin for AclPlugin {
         //self.register.stop().await;
         Ok(false)
     }
+
+    #[inline]
+    async fn attrs(&self) -> serde_json::Value {
+        json!({
+            "version": self.store.version(),
+            "rules": self.cfg.read().await.rules().len(),
+            "storage_info": self.store.info().await,
+        })
+    }
+
+    ///Rule management, see Command
+    #[inline]
+    async fn send(&self, msg: serde_json::Value) -> Result<serde_json::Value> {
+        match serde_json::from_value::<Command>(msg)? {
+            Command::List => self.list().await,
+            Command::Insert { position, rule } => {
+                Rule::try_from(&rule)?;
+                self.edit(|rules| {
+                    let position = position.unwrap_or(rules.len());
+                    if position > rules.len() {
+                        return Err(MqttError::from(format!(
+                            "position {} is out of range, rules: {}",
+                            position,
+                            rules.len()
+                        )));
+                    }
+                    rules.insert(position, rule);
+                    Ok(())
+                })
+                .await
+            }
+            Command::Update { index, rule } => {
+                Rule::try_from(&rule)?;
+                self.edit(|rules| {
+                    let len = rules.len();
+                    let r = rules.get_mut(index).ok_or_else(|| {
+                        MqttError::from(format!("index {} is out of range, rules: {}", index, len))
+                    })?;
+                    *r = rule;
+                    Ok(())
+                })
+                .await
+            }
+            Command::Delete { index } => {
+                self.edit(|rules| {
+                    if index >= rules.len() {
+                        return Err(MqttError::from(format!(
+                            "index {} is out of range, rules: {}",
+                            index,
+                            rules.len()
+                        )));
+                    }
+                    rules.remove(index);
+                    Ok(())
+                })
+                .await
+            }
+            Command::Reset => self.reset().await,
+            Command::Validate { rule, connect_info, action, topic } => {
+                self.validate(rule, connect_info, action, topic).await
+            }
+        }
+    }
+}
+
+struct RuleSyncHandler {
+    name: String,
+    cfg: Arc<RwLock<PluginConfig>>,
+    store: Arc<RuleStore>,
+    message_type: MessageType,
+}
+
+impl RuleSyncHandler {
+    async fn handle(&self, data: &[u8]) -> Result<GrpcMessageReply> {
+        match Message::decode(data)? {
+            Message::SetRules { version, rules } => {
+                //Stale changes are ignored
+                if version > self.store.version() {
+                    log::info!("{} rules changed by other node, version: {}", self.name, version);
+                    apply_rules(&self.cfg, &self.store, version, rules).await?;
+                }
+                Ok(GrpcMessageReply::Success)
+            }
+            Message::Reset { version } => {
+                //Stale resets are ignored as well
+                if version > self.store.version() {
+                    log::info!("{} rules reset by other node, version: {}", self.name, version);
+                    reset_rules(&self.name, &self.cfg, &self.store, version).await?;
+                }
+                Ok(GrpcMessageReply::Success)
+            }
+            Message::GetRules => {
+                let rules = if self.store.edited() { Some(self.cfg.read().await.rules_json()) } else { None };
+                Ok(GrpcMessageReply::Data(serde_json::to_vec(&(self.store.version(), rules))?))
+            }
+        }
+    }
+}
+
+#[async_trait]
+impl Handler for RuleSyncHandler {
+    async fn hook(&self, param: &Parameter, acc: Option<HookResult>) -> ReturnType {
+        if let Parameter::GrpcMessageReceived(typ, GrpcMessage::Data(data)) = param {
+            if self.message_type == *typ {
+                let reply =
+                    self.handle(data).await.unwrap_or_else(|e| GrpcMessageReply::Error(e.to_string()));
+                return (false, Some(HookResult::GrpcMessageReply(Ok(reply))));
+            }
+        }
+        (true, acc)
+    }
 }
 
 struct AclHandler {
@@ -105,34 +520,10 @@ impl Handler for AclHandler {
         match param {
             Parameter::ClientConnected(session) => {
                 let cfg = self.cfg.clone();
-                let client_id = session.id.client_id.clone();
-                let username = session.id.username.clone();
-                let build_placeholders = async move {
+                let id = session.id.clone();
+                let build = async move {
                     for rule in cfg.read().await.rules() {
-                        for ph_tf in &rule.topics.placeholders {
-                            let mut tf = ph_tf.replace(PH_C, &client_id);
-                            if let Some(un) = &username {
-                                tf = tf.replace(PH_U, un);
-                            } else {
-                                tf = tf.replace(PH_U, "");
-                            }
-                            if let Err(e) = rule.add_topic_filter(&tf).await {
-                                log::error!(
-                                    "acl config error, build_placeholders, add topic filter error, {:?}",
-                                    e
-                                );
-                            }
-                        }
-
-                        for eq_ph_t in &rule.topics.eq_placeholders {
-                            let mut t = eq_ph_t.replace(PH_C, &client_id);
-                            if let Some(un) = &username {
-                                t = t.replace(PH_U, un);
-                            } else {
-                                t = t.replace(PH_U, "");
-                            }
-                            rule.add_topic_to_eqs(t);
-                        }
+                        build_placeholders(rule, &id).await;
 
                         log::debug!("rule.access: {:?}", rule.access);
                         log::debug!("rule.users: {:?}", rule.users);
@@ -141,7 +532,7 @@ impl Handler for AclHandler {
                         log::debug!("rule.topics.tree: {:?}", rule.topics.tree.read().await.list(100));
                     }
                 };
-                tokio::spawn(build_placeholders);
+                tokio::spawn(build);
             }
 
             Parameter::ClientAuthenticate(connect_info) => {
@@ -187,7 +578,8 @@ impl Handler for AclHandler {
                 let topic =
                     Topic::from_str(&subscribe.topic_filter).unwrap_or_else(|_| Topic::from(Vec::new()));
                 let topic_filter = &subscribe.topic_filter;
-                for (idx, rule) in self.cfg.read().await.rules().iter().enumerate() {
+                let cfg = self.cfg.read().await;
+                for (idx, rule) in cfg.rules().iter().enumerate() {
                     if !matches!(rule.control, Control::Subscribe | Control::Pubsub | Control::All) {
                         continue;
                     }
@@ -198,7 +590,9 @@ impl Handler for AclHandler {
                     if !hit {
                         continue;
                     }
-                    if !rule.topics.is_match(&topic, topic_filter).await {
+                    if !rule.topics.is_match(&topic, topic_filter).await
+                        && !(is_dry_run() && placeholders_match(rule, &session.id, &topic, topic_filter))
+                    {
                         continue;
                     }
                     log::debug!(
@@ -207,6 +601,7 @@ impl Handler for AclHandler {
                         idx,
                         topic_filter
                     );
+                    dry_run_note(|| format!("rule {} matched, {:?}", idx, cfg.rules_json().get(idx)));
                     return if allow {
                         (
                             false,
@@ -224,6 +619,7 @@ impl Handler for AclHandler {
                         )
                     };
                 }
+                dry_run_note(|| "no rule matched".into());
                 return (
                     false,
                     Some(HookResult::SubscribeAclResult(SubscribeAclResult::new_failure(
@@ -238,8 +634,9 @@ impl Handler for AclHandler {
                 }
                 let topic_str = publish.topic();
                 let topic = Topic::from_str(topic_str).unwrap_or_else(|_| Topic::from(Vec::new()));
-                let disconnect_if_pub_rejected = self.cfg.read().await.disconnect_if_pub_rejected;
-                for (idx, rule) in self.cfg.read().await.rules().iter().enumerate() {
+                let cfg = self.cfg.read().await;
+                let disconnect_if_pub_rejected = cfg.disconnect_if_pub_rejected;
+                for (idx, rule) in cfg.rules().iter().enumerate() {
                     if !matches!(rule.control, Control::Publish | Control::Pubsub | Control::All) {
                         continue;
                     }
@@ -250,7 +647,9 @@ impl Handler for AclHandler {
                     if !hit {
                         continue;
                     }
-                    if !rule.topics.is_match(&topic, topic_str).await {
+                    if !rule.topics.is_match(&topic, topic_str).await
+                        && !(is_dry_run() && placeholders_match(rule, &session.id, &topic, topic_str))
+                    {
                         continue;
                     }
                     log::debug!(
@@ -259,6 +658,7 @@ impl Handler for AclHandler {
                         idx,
                         topic_str
                     );
+                    dry_run_note(|| format!("rule {} matched, {:?}", idx, cfg.rules_json().get(idx)));
                     return if allow {
                         (false, Some(HookResult::PublishAclResult(PublishAclResult::Allow)))
                     } else {
@@ -270,6 +670,7 @@ impl Handler for AclHandler {
                         )
                     };
                 }
+                dry_run_note(|| "no rule matched".into());
                 return (
                     false,
                     Some(HookResult::PublishAclResult(PublishAclResult::Rejected(
@@ -284,3 +685,78 @@ impl Handler for AclHandler {
         (true, acc)
     }
 }
+
+//The topic filters with placeholders are built into the rules when the client connects. The
+//ClientConnected hook is not executed for the sample connection of a dry run, so they are
+//matched here without changing the rules.
+fn placeholders_match(rule: &Rule, id: &Id, topic: &Topic, topic_str: &str) -> bool {
+    if rule.topics.eq_placeholders.iter().any(|eq_ph_t| replace_placeholders(eq_ph_t, id) == topic_str) {
+        return true;
+    }
+    rule.topics.placeholders.iter().any(|ph_tf| match Topic::from_str(&replace_placeholders(ph_tf, id)) {
+        Ok(tf) => {
+            let mut tree = TopicTree::default();
+            tree.insert(&tf, ());
+            tree.is_match(topic)
+        }
+        Err(_) => false,
+    })
+}
+
+#[inline]
+fn replace_placeholders(s: &str, id: &Id) -> String {
+    let peer_cert = id.peer_cert.as_ref();
+    s.replace(PH_C, &id.client_id)
+        .replace(PH_U, id.username.as_ref().map(|un| un.as_ref()).unwrap_or_default())
+        .replace(PH_CN, peer_cert.and_then(|c| c.cn.as_deref()).unwrap_or_default())
+        .replace(PH_DN, peer_cert.and_then(|c| c.dn.as_deref()).unwrap_or_default())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rmqtt::tls::PeerCert;
+
+    fn id(client_id: &str, username: Option<&str>) -> Id {
+        Id::new(1, None, None, ClientId::from(client_id.to_owned()), username.map(UserName::from))
+    }
+
+    fn is_match(rule: &Rule, id: &Id, topic: &str) -> bool {
+        placeholders_match(rule, id, &Topic::from_str(topic).unwrap(), topic)
+    }
+
+    #[test]
+    fn placeholders() {
+        let rule = Rule::try_from(&json!(["allow", "all", "pubsub", ["sensor/%u/+/ctrl", {"eq": "cmd/%c"}]]))
+            .unwrap();
+        let user1 = id("light", Some("user1"));
+        assert!(is_match(&rule, &user1, "sensor/user1/light/ctrl"));
+        assert!(is_match(&rule, &user1, "cmd/light"));
+        assert!(!is_match(&rule, &user1, "sensor/user2/light/ctrl"));
+        assert!(!is_match(&rule, &user1, "cmd/+"));
+        assert!(!is_match(&rule, &id("light", Some("user2")), "sensor/user1/light/ctrl"));
+
+        //The rule is not changed
+        assert!(rule.topics.tree.try_read().unwrap().list(10).is_empty());
+        assert!(rule.topics.eqs.is_empty());
+    }
+
+    #[test]
+    fn peer_cert_placeholders() {
+        let rule =
+            Rule::try_from(&json!(["allow", "all", "pubsub", ["devices/%C/#", {"eq": "certs/%d"}]])).unwrap();
+        let peer_cert =
+            PeerCert { cn: Some("dev-1".into()), dn: Some("CN=dev-1, O=rmqtt".into()), san: None };
+        let dev1 = Id::with_peer_cert(1, None, None, ClientId::from_static("c1"), None, Some(peer_cert));
+        assert!(is_match(&rule, &dev1, "devices/dev-1/temp"));
+        assert!(is_match(&rule, &dev1, "certs/CN=dev-1, O=rmqtt"));
+        assert!(!is_match(&rule, &dev1, "devices/dev-2/temp"));
+        assert!(!is_match(&rule, &dev1, "certs/CN=dev-2, O=rmqtt"));
+
+        //without a client certificate the placeholders are empty
+        let anonymous = id("c1", None);
+        assert!(!is_match(&rule, &anonymous, "devices/dev-1/temp"));
+        assert!(!is_match(&rule, &anonymous, "certs/CN=dev-1, O=rmqtt"));
+        assert!(is_match(&rule, &anonymous, "certs/"));
+    }
+}
@@ -0,0 +1,148 @@
+use std::sync::atomic::{AtomicBool, AtomicI64, Ordering};
+
+use rmqtt::{log, serde_json};
+use rmqtt::{NodeId, Result, TimestampMillis};
+use rmqtt_storage::{init_db, Config, DefaultStorageDB, StorageType};
+
+const RULES_KEY: &[u8] = b"rules";
+
+#[derive(Deserialize, Serialize, Debug)]
+struct StoredRules {
+    version: TimestampMillis,
+    //Rules in JSON format, None after a reset
+    rules: Option<String>,
+}
+
+///Rules edited at runtime, persisted in the storage db if configured.
+///The version is the time of the last change or reset, 0 means the rules were never changed.
+pub(crate) struct RuleStore {
+    storage_db: Option<DefaultStorageDB>,
+    version: AtomicI64,
+    //The rules edited at runtime are used instead of the rules of the configuration file
+    edited: AtomicBool,
+}
+
+impl RuleStore {
+    pub(crate) async fn new(node_id: NodeId, cfg: Option<&Config>) -> Result<Self> {
+        let storage_db = if let Some(cfg) = cfg {
+            let mut cfg = cfg.clone();
+            match cfg.typ {
+                StorageType::Sled => {
+                    cfg.sled.path = cfg.sled.path.replace("{node}", &format!("{}", node_id));
+                }
+                StorageType::Redis => {
+                    cfg.redis.prefix = cfg.redis.prefix.replace("{node}", &format!("{}", node_id));
+                }
+                StorageType::RedisCluster => {
+                    cfg.redis_cluster.prefix =
+                        cfg.redis_cluster.prefix.replace("{node}", &format!("{}", node_id));
+                }
+            }
+            Some(init_db(&cfg).await?)
+        } else {
+            None
+        };
+        Ok(Self { storage_db, version: AtomicI64::new(0), edited: AtomicBool::new(false) })
+    }
+
+    #[inline]
+    pub(crate) fn version(&self) -> TimestampMillis {
+        self.version.load(Ordering::SeqCst)
+    }
+
+    #[inline]
+    pub(crate) fn edited(&self) -> bool {
+        self.edited.load(Ordering::SeqCst)
+    }
+
+    ///Loads the persisted rules, if any. The rules are None if the last change was a reset.
+    pub(crate) async fn load(&self) -> Result<Option<(TimestampMillis, Option<Vec<serde_json::Value>>)>> {
+        let storage_db = if let Some(storage_db) = self.storage_db.as_ref() {
+            storage_db
+        } else {
+            return Ok(None);
+        };
+        if let Some(stored) = storage_db.get::<_, StoredRules>(RULES_KEY).await? {
+            let rules = stored
+                .rules
+                .map(|rules| serde_json::from_str::<Vec<serde_json::Value>>(&rules))
+                .transpose()?;
+            self.version.store(stored.version, Ordering::SeqCst);
+            self.edited.store(rules.is_some(), Ordering::SeqCst);
+            Ok(Some((stored.version, rules)))
+        } else {
+            Ok(None)
+        }
+    }
+
+    pub(crate) async fn save(&self, version: TimestampMillis, rules: &[serde_json::Value]) -> Result<()> {
+        self.store(version, Some(serde_json::to_string(rules)?)).await
+    }
+
+    ///Drops the rules edited at runtime, the rules of the configuration file are used again.
+    ///The version of the reset is kept, so that older changes of the other nodes do not win over it.
+    pub(crate) async fn reset(&self, version: TimestampMillis) -> Result<()> {
+        self.store(version, None).await?;
+        log::info!("runtime acl rules are reset, version: {}", version);
+        Ok(())
+    }
+
+    async fn store(&self, version: TimestampMillis, rules: Option<String>) -> Result<()> {
+        self.version.store(version, Ordering::SeqCst);
+        self.edited.store(rules.is_some(), Ordering::SeqCst);
+        if let Some(storage_db) = self.storage_db.as_ref() {
+            storage_db.insert(RULES_KEY, &StoredRules { version, rules }).await?;
+        }
+        Ok(())
+    }
+
+    pub(crate) async fn info(&self) -> Option<serde_json::Value> {
+        if let Some(storage_db) = self.storage_db.as_ref() {
+            storage_db.info().await.ok()
+        } else {
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rmqtt::tokio;
+
+    async fn rule_store(name: &str) -> RuleStore {
+        let dir = std::env::temp_dir().join(format!("rmqtt-acl-test-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        let cfg: Config =
+            serde_json::from_value(serde_json::json!({"type": "sled", "sled": {"path": dir}})).unwrap();
+        RuleStore::new(1, Some(&cfg)).await.unwrap()
+    }
+
+    #[tokio::test(crate = "rmqtt::tokio")]
+    async fn reset_keeps_version() {
+        let store = rule_store("reset").await;
+        assert!(store.load().await.unwrap().is_none());
+        assert_eq!(store.version(), 0);
+
+        let rules = vec![serde_json::json!(["allow", "all"])];
+        store.save(100, &rules).await.unwrap();
+        assert!(store.edited());
+        store.reset(200).await.unwrap();
+        assert!(!store.edited());
+        assert_eq!(store.version(), 200);
+
+        //The reset is restored with its version
+        let restored = RuleStore {
+            storage_db: store.storage_db.clone(),
+            version: AtomicI64::new(0),
+            edited: AtomicBool::new(false),
+        };
+        assert_eq!(restored.load().await.unwrap(), Some((200, None)));
+        assert_eq!(restored.version(), 200);
+        assert!(!restored.edited());
+
+        store.save(300, &rules).await.unwrap();
+        assert_eq!(restored.load().await.unwrap(), Some((300, Some(rules))));
+        assert!(restored.edited());
+    }
+}
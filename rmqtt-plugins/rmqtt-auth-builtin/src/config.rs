@@ -0,0 +1,99 @@
+use std::time::Duration;
+
+use rmqtt::serde_json;
+use rmqtt::{broker::hook::Priority, grpc::MessageType, settings::deserialize_duration};
+
+use rmqtt_storage::Config;
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct PluginConfig {
+    ///Hook priority
+    #[serde(default = "PluginConfig::priority_default")]
+    pub priority: Priority,
+
+    #[serde(default = "PluginConfig::message_type_default")]
+    pub message_type: MessageType,
+
+    #[serde(default = "PluginConfig::disconnect_if_pub_rejected_default")]
+    pub disconnect_if_pub_rejected: bool,
+
+    ///Hash of new passwords, stored hashes are verified with the algorithm they were created with
+    #[serde(default)]
+    pub password_hash: PasswordHashConfig,
+
+    ///How long deleted users are remembered, a node that was down for longer saves them again
+    #[serde(default = "PluginConfig::deleted_retention_default", deserialize_with = "deserialize_duration")]
+    pub deleted_retention: Duration,
+
+    #[serde(default)]
+    pub storage: Config,
+}
+
+impl PluginConfig {
+    #[inline]
+    pub fn to_json(&self) -> serde_json::Value {
+        serde_json::json!(self)
+    }
+
+    fn priority_default() -> Priority {
+        80
+    }
+
+    fn message_type_default() -> MessageType {
+        95
+    }
+
+    fn disconnect_if_pub_rejected_default() -> bool {
+        true
+    }
+
+    fn deleted_retention_default() -> Duration {
+        Duration::from_secs(60 * 60 * 24 * 7)
+    }
+}
+
+#[derive(Debug, Clone, Copy, Deserialize, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum HashAlgorithm {
+    Pbkdf2,
+    Bcrypt,
+    Argon2,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct PasswordHashConfig {
+    #[serde(default = "PasswordHashConfig::algorithm_default")]
+    pub algorithm: HashAlgorithm,
+
+    //PBKDF2-HMAC-SHA256 iterations
+    #[serde(default = "PasswordHashConfig::pbkdf2_rounds_default")]
+    pub pbkdf2_rounds: u32,
+
+    //bcrypt cost, 4 to 31
+    #[serde(default = "PasswordHashConfig::bcrypt_cost_default")]
+    pub bcrypt_cost: u32,
+}
+
+impl Default for PasswordHashConfig {
+    fn default() -> Self {
+        Self {
+            algorithm: Self::algorithm_default(),
+            pbkdf2_rounds: Self::pbkdf2_rounds_default(),
+            bcrypt_cost: Self::bcrypt_cost_default(),
+        }
+    }
+}
+
+impl PasswordHashConfig {
+    fn algorithm_default() -> HashAlgorithm {
+        HashAlgorithm::Pbkdf2
+    }
+
+    fn pbkdf2_rounds_default() -> u32 {
+        600_000
+    }
+
+    fn bcrypt_cost_default() -> u32 {
+        10
+    }
+}
@@ -0,0 +1,132 @@
+use std::collections::HashSet;
+
+use rmqtt::serde_json;
+use rmqtt::{MqttError, Result};
+
+#[derive(Deserialize, Serialize, Debug, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum Format {
+    Csv,
+    Json,
+}
+
+///User fields accepted by the add, update and import commands
+#[derive(Deserialize, Serialize, Debug, Clone, Default)]
+pub(crate) struct UserParams {
+    pub username: String,
+    #[serde(default)]
+    pub password: Option<String>,
+    //Hash created by another system, used instead of the password, see password::check()
+    #[serde(default)]
+    pub password_hash: Option<String>,
+    #[serde(default)]
+    pub superuser: Option<bool>,
+    #[serde(default)]
+    pub acl: Option<Vec<serde_json::Value>>,
+}
+
+//The acl column holds the rules as a JSON array
+#[derive(Deserialize, Debug)]
+struct CsvRecord {
+    username: String,
+    #[serde(default)]
+    password: Option<String>,
+    #[serde(default)]
+    password_hash: Option<String>,
+    #[serde(default)]
+    superuser: Option<bool>,
+    #[serde(default)]
+    acl: Option<String>,
+}
+
+///Parses the users to import, CSV data must start with a header line such as "username,password,superuser".
+///A username must not be given more than once.
+pub(crate) fn parse(format: Format, data: &str) -> Result<Vec<UserParams>> {
+    let users = parse_users(format, data)?;
+    let mut usernames = HashSet::new();
+    for (i, user) in users.iter().enumerate() {
+        if !usernames.insert(user.username.as_str()) {
+            return Err(MqttError::from(format!("record {} error, duplicate user {}", i + 1, user.username)));
+        }
+    }
+    Ok(users)
+}
+
+fn parse_users(format: Format, data: &str) -> Result<Vec<UserParams>> {
+    match format {
+        Format::Json => Ok(serde_json::from_str::<Vec<UserParams>>(data)?),
+        Format::Csv => {
+            let mut rdr = csv::ReaderBuilder::new().trim(csv::Trim::All).from_reader(data.as_bytes());
+            let mut users = Vec::new();
+            for (line, record) in rdr.deserialize::<CsvRecord>().enumerate() {
+                let record =
+                    record.map_err(|e| MqttError::from(format!("csv record {} error, {}", line + 1, e)))?;
+                let acl = record
+                    .acl
+                    .filter(|acl| !acl.is_empty())
+                    .map(|acl| serde_json::from_str::<Vec<serde_json::Value>>(&acl))
+                    .transpose()
+                    .map_err(|e| MqttError::from(format!("csv record {} acl error, {}", line + 1, e)))?;
+                users.push(UserParams {
+                    username: record.username,
+                    password: record.password,
+                    password_hash: record.password_hash,
+                    superuser: record.superuser,
+                    acl,
+                });
+            }
+            Ok(users)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn csv() {
+        let data = "username,password,superuser,acl\n\
+                    admin, secret ,true,\n\
+                    user1,pass1,,\"[{\"\"action\"\":\"\"all\"\",\"\"permission\"\":\"\"allow\"\",\"\"topic\"\":\"\"foo/#\"\"}]\"\n";
+        let users = parse(Format::Csv, data).unwrap();
+        assert_eq!(users.len(), 2);
+        assert_eq!(users[0].username, "admin");
+        assert_eq!(users[0].password.as_deref(), Some("secret"));
+        assert_eq!(users[0].superuser, Some(true));
+        assert!(users[0].acl.is_none());
+        assert_eq!(users[1].superuser, None);
+        assert_eq!(users[1].acl.as_ref().map(|acl| acl.len()), Some(1));
+
+        let data = "username,password_hash\nuser1,$2b$10$abcdefghijklmnopqrstuv\n";
+        let users = parse(Format::Csv, data).unwrap();
+        assert_eq!(users[0].password_hash.as_deref(), Some("$2b$10$abcdefghijklmnopqrstuv"));
+        assert!(users[0].password.is_none());
+    }
+
+    #[test]
+    fn csv_malformed() {
+        //Wrong number of fields
+        let e = parse(Format::Csv, "username,password\nuser1,pass1\nuser2,pass2,true\n").unwrap_err();
+        assert!(e.to_string().contains("csv record 2"), "{}", e);
+        //Not a boolean
+        let e = parse(Format::Csv, "username,password,superuser\nuser1,pass1,yes\n").unwrap_err();
+        assert!(e.to_string().contains("csv record 1"), "{}", e);
+        //Invalid acl
+        let e = parse(Format::Csv, "username,password,acl\nuser1,pass1,[allow\n").unwrap_err();
+        assert!(e.to_string().contains("csv record 1 acl"), "{}", e);
+        //No username column
+        assert!(parse(Format::Csv, "password\npass1\n").is_err());
+    }
+
+    #[test]
+    fn duplicate_users() {
+        let e = parse(Format::Csv, "username,password\nuser1,pass1\nuser2,pass2\nuser1,pass3\n").unwrap_err();
+        assert!(e.to_string().contains("record 3 error, duplicate user user1"), "{}", e);
+
+        let data = r#"[{"username":"user1","password":"pass1"},{"username":"user1","password":"pass2"}]"#;
+        assert!(parse(Format::Json, data).is_err());
+        let data = r#"[{"username":"user1","password":"pass1"},{"username":"user2","password":"pass2"}]"#;
+        assert_eq!(parse(Format::Json, data).unwrap().len(), 2);
+    }
+}
@@ -0,0 +1,507 @@
+#![deny(unsafe_code)]
+#[macro_use]
+extern crate serde;
+
+#[macro_use]
+extern crate rmqtt_macros;
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use rmqtt::{
+    anyhow::anyhow,
+    async_trait::async_trait,
+    log,
+    once_cell::sync::OnceCell,
+    serde_json::{self, json},
+    tokio,
+};
+use rmqtt::{
+    broker::hook::{dry_run_note, Handler, HookResult, Parameter, Register, ReturnType, Type},
+    broker::types::{AuthResult, PublishAclResult, MQTT_LEVEL_311},
+    grpc::{
+        broadcast_data, collect_data, decode_data, encode_data, Message as GrpcMessage,
+        MessageReply as GrpcMessageReply,
+    },
+    plugin::{PackageInfo, Plugin},
+    register,
+    settings::acl::{AuthInfo, Rule},
+    timestamp_secs, ClientId, ConnectInfo, Id, MqttError, Result, Runtime, Timestamp, UserName,
+};
+
+use rmqtt_storage::{init_db, StorageType};
+
+use config::PluginConfig;
+use import::{Format, UserParams};
+use users::{User, UserStore, UserSync};
+
+mod config;
+mod import;
+mod password;
+mod users;
+
+static USER_STORE: OnceCell<UserStore> = OnceCell::new();
+
+//Interval for forgetting the deletions older than deleted_retention
+const CLEANUP_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+register!(AuthBuiltinPlugin::new);
+
+///User management, the commands of the HTTP API /api/v1/auth/users
+#[derive(Deserialize, Serialize, Debug)]
+#[serde(tag = "cmd", rename_all = "snake_case")]
+enum Command {
+    List,
+    Get {
+        username: String,
+    },
+    Add(UserParams),
+    Update(UserParams),
+    Delete {
+        username: String,
+    },
+    Import {
+        format: Format,
+        data: String,
+        //Replace the users that already exist, they are skipped otherwise
+        #[serde(default)]
+        overwrite: bool,
+    },
+}
+
+///Changes replicated to the other nodes of the cluster, passwords are already hashed
+#[derive(Deserialize, Serialize, Debug)]
+enum Message {
+    Put(Vec<User>),
+    Delete { username: String, deleted_at: Timestamp },
+    //Asks for all users of the node, answered with an encoded UserSync
+    List,
+}
+
+#[derive(Plugin)]
+struct AuthBuiltinPlugin {
+    register: Box<dyn Register>,
+    cfg: Arc<PluginConfig>,
+    users: &'static UserStore,
+    cleaner: Option<tokio::task::JoinHandle<()>>,
+}
+
+impl AuthBuiltinPlugin {
+    #[inline]
+    async fn new<S: Into<String>>(runtime: &'static Runtime, name: S) -> Result<Self> {
+        let name = name.into();
+        let mut cfg = runtime.settings.plugins.load_config_default::<PluginConfig>(&name)?;
+        match cfg.storage.typ {
+            StorageType::Sled => {
+                cfg.storage.sled.path =
+                    cfg.storage.sled.path.replace("{node}", &format!("{}", runtime.node.id()));
+            }
+            StorageType::Redis => {
+                cfg.storage.redis.prefix =
+                    cfg.storage.redis.prefix.replace("{node}", &format!("{}", runtime.node.id()));
+            }
+            StorageType::RedisCluster => {
+                cfg.storage.redis_cluster.prefix =
+                    cfg.storage.redis_cluster.prefix.replace("{node}", &format!("{}", runtime.node.id()));
+            }
+        }
+        log::info!("{} AuthBuiltinPlugin cfg: {:?}", name, cfg);
+
+        let users = if let Some(users) = USER_STORE.get() {
+            users
+        } else {
+            let storage_db = init_db(&cfg.storage).await?;
+            USER_STORE.set(UserStore::new(storage_db)).map_err(|_| anyhow!("init error!"))?;
+            USER_STORE.get().ok_or_else(|| anyhow!("init error!"))?
+        };
+        let register = runtime.extends.hook_mgr().await.register();
+        Ok(Self { register, cfg: Arc::new(cfg), users, cleaner: None })
+    }
+
+    //Builds the stored user from the params, the password is hashed here
+    async fn build_user(&self, params: UserParams, old: Option<&User>) -> Result<User> {
+        if params.username.is_empty() {
+            return Err(MqttError::from("username is empty"));
+        }
+        let password_hash = match (params.password, params.password_hash) {
+            (Some(password), _) => {
+                let cfg = self.cfg.password_hash.clone();
+                tokio::task::spawn_blocking(move || password::hash(&cfg, password.as_bytes()))
+                    .await
+                    .map_err(|e| anyhow!(e))??
+            }
+            (None, Some(password_hash)) => {
+                password::check(&password_hash)?;
+                password_hash
+            }
+            (None, None) => match old {
+                Some(old) => old.password_hash.clone(),
+                None => return Err(MqttError::from("password or password_hash is required")),
+            },
+        };
+        let acl = match params.acl {
+            Some(acl) => {
+                validate_acl(&params.username, &acl)?;
+                serde_json::to_string(&acl)?
+            }
+            None => old.map(|old| old.acl.clone()).unwrap_or_default(),
+        };
+        let now = timestamp_secs();
+        Ok(User {
+            username: params.username,
+            password_hash,
+            superuser: params.superuser.or_else(|| old.map(|old| old.superuser)).unwrap_or_default(),
+            acl,
+            created_at: old.map(|old| old.created_at).unwrap_or(now),
+            updated_at: now,
+        })
+    }
+
+    async fn add(&self, params: UserParams) -> Result<serde_json::Value> {
+        if self.users.get(&params.username).await?.is_some() {
+            return Err(MqttError::from(format!("user {} already exists", params.username)));
+        }
+        let user = self.build_user(params, None).await?;
+        let reply = user.to_json();
+        self.apply(Message::Put(vec![user])).await?;
+        Ok(reply)
+    }
+
+    async fn update(&self, params: UserParams) -> Result<serde_json::Value> {
+        let old = match self.users.get(&params.username).await? {
+            Some(old) => old,
+            None => return Ok(serde_json::Value::Null),
+        };
+        let user = self.build_user(params, Some(&old)).await?;
+        let reply = user.to_json();
+        self.apply(Message::Put(vec![user])).await?;
+        Ok(reply)
+    }
+
+    async fn delete(&self, username: String) -> Result<serde_json::Value> {
+        if self.users.get(&username).await?.is_none() {
+            return Ok(serde_json::Value::Null);
+        }
+        self.apply(Message::Delete { username, deleted_at: timestamp_secs() }).await?;
+        Ok(json!({ "removed": true }))
+    }
+
+    async fn import(&self, format: Format, data: &str, overwrite: bool) -> Result<serde_json::Value> {
+        let mut users = Vec::new();
+        let mut skipped = Vec::new();
+        let mut errors = Vec::new();
+        for params in import::parse(format, data)? {
+            let username = params.username.clone();
+            let old = match self.users.get(&username).await? {
+                Some(_) if !overwrite => {
+                    skipped.push(username);
+                    continue;
+                }
+                old => old,
+            };
+            match self.build_user(params, old.as_ref()).await {
+                Ok(user) => users.push(user),
+                Err(e) => errors.push(json!({ "username": username, "error": e.to_string() })),
+            }
+        }
+        let imported = users.len();
+        if !users.is_empty() {
+            self.apply(Message::Put(users)).await?;
+        }
+        log::info!(
+            "import users, imported: {}, skipped: {}, errors: {}",
+            imported,
+            skipped.len(),
+            errors.len()
+        );
+        Ok(json!({
+            "imported": imported,
+            "skipped": skipped,
+            "errors": errors,
+        }))
+    }
+
+    //Applies the change locally and replicates it to the other nodes
+    async fn apply(&self, msg: Message) -> Result<()> {
+        apply(self.users, &msg).await?;
+        broadcast_data(self.cfg.message_type, encode_data(&msg)?, Duration::from_secs(10)).await;
+        Ok(())
+    }
+
+    async fn sync_from_cluster(&self) -> Result<()> {
+        let data = encode_data(&Message::List)?;
+        for (id, data) in collect_data(self.cfg.message_type, data, Duration::from_secs(5)).await {
+            match decode_data::<UserSync>(&data) {
+                Ok(sync) => {
+                    let (saved, deleted) = self.users.merge(sync).await?;
+                    log::info!(
+                        "{} take {} users and {} deletions from node({})",
+                        self.name(),
+                        saved,
+                        deleted,
+                        id
+                    );
+                }
+                Err(e) => log::warn!("get users from node({}) error, {:?}", id, e),
+            }
+        }
+        Ok(())
+    }
+}
+
+async fn handle(users: &UserStore, data: &[u8]) -> Result<GrpcMessageReply> {
+    match decode_data::<Message>(data)? {
+        Message::List => Ok(GrpcMessageReply::Data(encode_data(&users.sync().await?)?)),
+        msg => {
+            apply(users, &msg).await?;
+            Ok(GrpcMessageReply::Success)
+        }
+    }
+}
+
+async fn apply(users: &UserStore, msg: &Message) -> Result<()> {
+    match msg {
+        Message::Put(puts) => {
+            for user in puts {
+                users.put(user).await?;
+                log::info!("user {} saved, superuser: {}", user.username, user.superuser);
+            }
+        }
+        Message::Delete { username, deleted_at } => {
+            let removed = users.remove(username, *deleted_at).await?;
+            log::info!("user {} removed: {}", username, removed);
+        }
+        Message::List => {}
+    }
+    Ok(())
+}
+
+//The rules are checked with a sample connection of the user, placeholders are replaced on authentication
+#[inline]
+fn validate_acl(username: &str, acl: &[serde_json::Value]) -> Result<()> {
+    let connect_info = ConnectInfo::with_credentials(
+        Id::from(Runtime::instance().node.id(), ClientId::from_static("validate")),
+        Some(UserName::from(username)),
+        None,
+        MQTT_LEVEL_311,
+    );
+    for rule in acl {
+        Rule::try_from((rule, &connect_info))?;
+    }
+    Ok(())
+}
+
+#[async_trait]
+impl Plugin for AuthBuiltinPlugin {
+    #[inline]
+    async fn init(&mut self) -> Result<()> {
+        log::info!("{} init", self.name());
+        let priority = self.cfg.priority;
+        self.register
+            .add_priority(
+                Type::ClientAuthenticate,
+                priority,
+                Box::new(AuthHandler::new(&self.cfg, self.users)),
+            )
+            .await;
+        self.register
+            .add_priority(
+                Type::ClientSubscribeCheckAcl,
+                priority,
+                Box::new(AuthHandler::new(&self.cfg, self.users)),
+            )
+            .await;
+        self.register
+            .add_priority(
+                Type::MessagePublishCheckAcl,
+                priority,
+                Box::new(AuthHandler::new(&self.cfg, self.users)),
+            )
+            .await;
+        self.register.add(Type::GrpcMessageReceived, Box::new(AuthHandler::new(&self.cfg, self.users))).await;
+        Ok(())
+    }
+
+    #[inline]
+    async fn get_config(&self) -> Result<serde_json::Value> {
+        Ok(self.cfg.to_json())
+    }
+
+    #[inline]
+    async fn start(&mut self) -> Result<()> {
+        log::info!("{} start", self.name());
+        self.register.start().await;
+        if let Err(e) = self.sync_from_cluster().await {
+            log::warn!("{} sync users from cluster error, {:?}", self.name(), e);
+        }
+        let users = self.users;
+        let deleted_retention = self.cfg.deleted_retention;
+        self.cleaner = Some(tokio::spawn(async move {
+            loop {
+                if let Err(e) = users.cleanup(deleted_retention).await {
+                    log::warn!("cleanup deleted users error, {:?}", e);
+                }
+                tokio::time::sleep(CLEANUP_INTERVAL).await;
+            }
+        }));
+        Ok(())
+    }
+
+    #[inline]
+    async fn stop(&mut self) -> Result<bool> {
+        log::info!("{} stop", self.name());
+        self.register.stop().await;
+        if let Some(cleaner) = self.cleaner.take() {
+            cleaner.abort();
+        }
+        Ok(true)
+    }
+
+    #[inline]
+    async fn attrs(&self) -> serde_json::Value {
+        let storage_info = self.users.storage_db.info().await.unwrap_or_default();
+        json!({
+            "storage_info": storage_info,
+        })
+    }
+
+    ///User management, see Command. Null is returned if the user does not exist
+    #[inline]
+    async fn send(&self, msg: serde_json::Value) -> Result<serde_json::Value> {
+        match serde_json::from_value::<Command>(msg)? {
+            Command::List => {
+                Ok(serde_json::Value::Array(self.users.list().await?.iter().map(|u| u.to_json()).collect()))
+            }
+            Command::Get { username } => {
+                Ok(self.users.get(&username).await?.map(|u| u.to_json()).unwrap_or_default())
+            }
+            Command::Add(params) => self.add(params).await,
+            Command::Update(params) => self.update(params).await,
+            Command::Delete { username } => self.delete(username).await,
+            Command::Import { format, data, overwrite } => self.import(format, &data, overwrite).await,
+        }
+    }
+}
+
+struct AuthHandler {
+    cfg: Arc<PluginConfig>,
+    users: &'static UserStore,
+}
+
+impl AuthHandler {
+    fn new(cfg: &Arc<PluginConfig>, users: &'static UserStore) -> Self {
+        Self { cfg: cfg.clone(), users }
+    }
+}
+
+#[async_trait]
+impl Handler for AuthHandler {
+    async fn hook(&self, param: &Parameter, acc: Option<HookResult>) -> ReturnType {
+        match param {
+            Parameter::ClientAuthenticate(connect_info) => {
+                log::debug!("ClientAuthenticate auth-builtin");
+                if matches!(
+                    acc,
+                    Some(HookResult::AuthResult(AuthResult::BadUsernameOrPassword))
+                        | Some(HookResult::AuthResult(AuthResult::NotAuthorized))
+                ) {
+                    return (false, acc);
+                }
+
+                //Clients without a username or unknown users are left to the subsequent authentication chain
+                let username = match connect_info.username() {
+                    Some(username) => username,
+                    None => return (true, acc),
+                };
+                let user = match self.users.get(username).await {
+                    Ok(Some(user)) => user,
+                    Ok(None) => {
+                        dry_run_note(|| format!("user {} not found", username));
+                        return (true, acc);
+                    }
+                    Err(e) => {
+                        log::warn!("{} load user error, {:?}", connect_info.id(), e);
+                        return (true, acc);
+                    }
+                };
+
+                let password = connect_info.password().cloned().unwrap_or_default();
+                let password_hash = user.password_hash.clone();
+                let verified =
+                    tokio::task::spawn_blocking(move || password::verify(&password, &password_hash))
+                        .await
+                        .unwrap_or_default();
+                if !verified {
+                    log::debug!("{} password verification failed", connect_info.id());
+                    return (false, Some(HookResult::AuthResult(AuthResult::BadUsernameOrPassword)));
+                }
+
+                let rules = match user.acl().and_then(|acl| {
+                    acl.iter().map(|acl| Rule::try_from((acl, *connect_info))).collect::<Result<Vec<Rule>>>()
+                }) {
+                    Ok(rules) => rules,
+                    Err(e) => {
+                        log::warn!("{} {}", connect_info.id(), e);
+                        return (false, Some(HookResult::AuthResult(AuthResult::NotAuthorized)));
+                    }
+                };
+                log::debug!("rules: {:?}", rules);
+                let auth_info =
+                    AuthInfo { superuser: user.superuser, expire_at: None, rules, publish_limit: None };
+                return (
+                    false,
+                    Some(HookResult::AuthResult(AuthResult::Allow(user.superuser, Some(auth_info)))),
+                );
+            }
+
+            Parameter::ClientSubscribeCheckAcl(session, subscribe) => {
+                log::debug!("ClientSubscribeCheckAcl auth-builtin");
+                if let Some(HookResult::SubscribeAclResult(acl_result)) = &acc {
+                    if acl_result.failure() {
+                        return (false, acc);
+                    }
+                }
+
+                if let Some(auth_info) = session.auth_info() {
+                    if let Some(acl_res) = auth_info.subscribe_acl(subscribe).await {
+                        return acl_res;
+                    }
+                }
+                //If none of the rules match, continue executing the subsequent authentication chain.
+            }
+
+            Parameter::MessagePublishCheckAcl(session, publish) => {
+                log::debug!("MessagePublishCheckAcl auth-builtin");
+                if let Some(HookResult::PublishAclResult(PublishAclResult::Rejected(_))) = &acc {
+                    return (false, acc);
+                }
+
+                if let Some(auth_info) = session.auth_info() {
+                    if let Some(acl_res) =
+                        auth_info.publish_acl(publish, self.cfg.disconnect_if_pub_rejected).await
+                    {
+                        return acl_res;
+                    }
+                }
+                //If none of the rules match, continue executing the subsequent authentication chain.
+            }
+
+            Parameter::GrpcMessageReceived(typ, msg) => {
+                if self.cfg.message_type != *typ {
+                    return (true, acc);
+                }
+                if let GrpcMessage::Data(data) = msg {
+                    let reply = handle(self.users, data)
+                        .await
+                        .unwrap_or_else(|e| GrpcMessageReply::Error(e.to_string()));
+                    return (false, Some(HookResult::GrpcMessageReply(Ok(reply))));
+                }
+            }
+
+            _ => {
+                log::error!("unimplemented, {:?}", param)
+            }
+        }
+        (true, acc)
+    }
+}
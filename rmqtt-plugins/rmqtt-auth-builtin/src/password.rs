@@ -0,0 +1,121 @@
+use argon2::password_hash::{rand_core::OsRng, PasswordHash, PasswordHasher, PasswordVerifier, SaltString};
+use argon2::Argon2;
+use pbkdf2::{Algorithm, Params, Pbkdf2};
+
+use rmqtt::{MqttError, Result};
+
+use crate::config::{HashAlgorithm, PasswordHashConfig};
+
+///Hashes the password with a random salt, the result is a PHC string, or a bcrypt string such as "$2b$10$..."
+pub(crate) fn hash(cfg: &PasswordHashConfig, password: &[u8]) -> Result<String> {
+    match cfg.algorithm {
+        HashAlgorithm::Pbkdf2 => {
+            let salt = SaltString::generate(&mut OsRng);
+            let params = Params { rounds: cfg.pbkdf2_rounds, ..Default::default() };
+            Pbkdf2
+                .hash_password_customized(
+                    password,
+                    Some(Algorithm::Pbkdf2Sha256.ident()),
+                    None,
+                    params,
+                    &salt,
+                )
+                .map(|h| h.to_string())
+                .map_err(|e| MqttError::from(format!("pbkdf2 hash error, {}", e)))
+        }
+        HashAlgorithm::Bcrypt => bcrypt::hash(password, cfg.bcrypt_cost)
+            .map_err(|e| MqttError::from(format!("bcrypt hash error, {}", e))),
+        HashAlgorithm::Argon2 => {
+            let salt = SaltString::generate(&mut OsRng);
+            Argon2::default()
+                .hash_password(password, &salt)
+                .map(|h| h.to_string())
+                .map_err(|e| MqttError::from(format!("argon2 hash error, {}", e)))
+        }
+    }
+}
+
+///Verifies the password against a hash created by any of the supported algorithms
+pub(crate) fn verify(password: &[u8], hash: &str) -> bool {
+    if is_bcrypt(hash) {
+        return bcrypt::verify(password, hash).unwrap_or_default();
+    }
+    match PasswordHash::new(hash) {
+        Ok(parsed) => match parsed.algorithm.as_str() {
+            alg if alg.starts_with("pbkdf2") => Pbkdf2.verify_password(password, &parsed).is_ok(),
+            alg if alg.starts_with("argon2") => Argon2::default().verify_password(password, &parsed).is_ok(),
+            _ => false,
+        },
+        Err(_) => false,
+    }
+}
+
+///Checks a hash given on import, plain passwords must not be stored as hashes
+pub(crate) fn check(hash: &str) -> Result<()> {
+    if is_bcrypt(hash) {
+        return Ok(());
+    }
+    let parsed =
+        PasswordHash::new(hash).map_err(|e| MqttError::from(format!("invalid password hash, {}", e)))?;
+    let alg = parsed.algorithm.as_str();
+    if alg.starts_with("pbkdf2") || alg.starts_with("argon2") {
+        Ok(())
+    } else {
+        Err(MqttError::from(format!("unsupported password hash algorithm, {}", alg)))
+    }
+}
+
+#[inline]
+fn is_bcrypt(hash: &str) -> bool {
+    hash.starts_with("$2a$") || hash.starts_with("$2b$") || hash.starts_with("$2y$")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cfg(algorithm: HashAlgorithm) -> PasswordHashConfig {
+        //Low costs, the tests are not about the strength of the hashes
+        PasswordHashConfig { algorithm, pbkdf2_rounds: 1_000, bcrypt_cost: 4 }
+    }
+
+    fn round_trip(algorithm: HashAlgorithm, prefix: &str) {
+        let hash = hash(&cfg(algorithm), b"secret").unwrap();
+        assert!(hash.starts_with(prefix), "{}", hash);
+        assert!(verify(b"secret", &hash));
+        assert!(!verify(b"Secret", &hash));
+        assert!(!verify(b"", &hash));
+        assert!(check(&hash).is_ok());
+        //A random salt is used for each hash
+        assert_ne!(hash, super::hash(&cfg(algorithm), b"secret").unwrap());
+    }
+
+    #[test]
+    fn pbkdf2() {
+        round_trip(HashAlgorithm::Pbkdf2, "$pbkdf2-sha256$i=1000,");
+    }
+
+    #[test]
+    fn bcrypt() {
+        round_trip(HashAlgorithm::Bcrypt, "$2b$04$");
+    }
+
+    #[test]
+    fn argon2() {
+        round_trip(HashAlgorithm::Argon2, "$argon2id$");
+    }
+
+    #[test]
+    fn invalid_hash() {
+        assert!(!verify(b"secret", "secret"));
+        assert!(!verify(b"secret", ""));
+        assert!(!verify(b"secret", "$2b$04$invalid"));
+        assert!(check("secret").is_err());
+        assert!(check("$scrypt$ln=4,r=8,p=1$c2FsdHNhbHQ$aGFzaGhhc2g").is_err());
+    }
+
+    #[test]
+    fn default_rounds() {
+        assert!(PasswordHashConfig::default().pbkdf2_rounds >= 600_000);
+    }
+}
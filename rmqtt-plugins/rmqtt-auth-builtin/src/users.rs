@@ -0,0 +1,282 @@
+use std::time::Duration;
+
+use serde::de::DeserializeOwned;
+
+use rmqtt::{futures::StreamExt, log, serde_json, timestamp_secs};
+use rmqtt::{Result, Timestamp};
+use rmqtt_storage::DefaultStorageDB;
+
+const USER_PREFIX: &[u8] = b"u|";
+const DELETED_PREFIX: &[u8] = b"d|";
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub(crate) struct User {
+    pub username: String,
+    //PHC string of PBKDF2 or Argon2, or a bcrypt hash
+    pub password_hash: String,
+    pub superuser: bool,
+    //ACL rules in JSON format, same as the "acl" of rmqtt-auth-http and rmqtt-auth-jwt
+    pub acl: String,
+    //Unix timestamp in seconds
+    pub created_at: Timestamp,
+    pub updated_at: Timestamp,
+}
+
+impl User {
+    #[inline]
+    pub(crate) fn acl(&self) -> Result<Vec<serde_json::Value>> {
+        if self.acl.is_empty() {
+            Ok(Vec::new())
+        } else {
+            Ok(serde_json::from_str(&self.acl)?)
+        }
+    }
+
+    ///The password hash is never returned
+    #[inline]
+    pub(crate) fn to_json(&self) -> serde_json::Value {
+        serde_json::json!({
+            "username": self.username,
+            "superuser": self.superuser,
+            "acl": self.acl().unwrap_or_default(),
+            "created_at": self.created_at,
+            "updated_at": self.updated_at,
+        })
+    }
+}
+
+///Kept after a user is deleted, so that the user is not saved again from the older copy of another node
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq, Eq)]
+pub(crate) struct DeletedUser {
+    pub username: String,
+    //Unix timestamp in seconds
+    pub deleted_at: Timestamp,
+}
+
+///Answer to the List message, the users and the deleted users of the node
+#[derive(Deserialize, Serialize, Debug, Default)]
+pub(crate) struct UserSync {
+    pub users: Vec<User>,
+    pub deleteds: Vec<DeletedUser>,
+}
+
+///Users persisted in the storage db
+pub(crate) struct UserStore {
+    pub(crate) storage_db: DefaultStorageDB,
+}
+
+impl UserStore {
+    #[inline]
+    pub(crate) fn new(storage_db: DefaultStorageDB) -> Self {
+        Self { storage_db }
+    }
+
+    #[inline]
+    pub(crate) async fn get(&self, username: &str) -> Result<Option<User>> {
+        Ok(self.storage_db.get::<_, User>(make_stored_key(USER_PREFIX, username).as_slice()).await?)
+    }
+
+    #[inline]
+    async fn deleted(&self, username: &str) -> Result<Option<DeletedUser>> {
+        Ok(self
+            .storage_db
+            .get::<_, DeletedUser>(make_stored_key(DELETED_PREFIX, username).as_slice())
+            .await?)
+    }
+
+    ///Saves the user, a user deleted earlier is no longer remembered as deleted
+    #[inline]
+    pub(crate) async fn put(&self, user: &User) -> Result<()> {
+        self.storage_db.insert(make_stored_key(USER_PREFIX, &user.username).as_slice(), user).await?;
+        self.storage_db.remove(make_stored_key(DELETED_PREFIX, &user.username).as_slice()).await?;
+        Ok(())
+    }
+
+    ///Deletes the user and remembers the deletion, a user updated after 'deleted_at' is kept.
+    ///Returns true if the user was deleted.
+    pub(crate) async fn remove(&self, username: &str, deleted_at: Timestamp) -> Result<bool> {
+        let user = self.get(username).await?;
+        if user.as_ref().map(|u| u.updated_at > deleted_at).unwrap_or_default() {
+            return Ok(false);
+        }
+        let deleted = DeletedUser { username: username.to_owned(), deleted_at };
+        self.storage_db.insert(make_stored_key(DELETED_PREFIX, username).as_slice(), &deleted).await?;
+        self.storage_db.remove(make_stored_key(USER_PREFIX, username).as_slice()).await?;
+        Ok(user.is_some())
+    }
+
+    ///All users, sorted by username
+    pub(crate) async fn list(&self) -> Result<Vec<User>> {
+        let mut users = self.scan::<User>(USER_PREFIX).await?;
+        users.sort_by(|a, b| a.username.cmp(&b.username));
+        Ok(users)
+    }
+
+    pub(crate) async fn sync(&self) -> Result<UserSync> {
+        Ok(UserSync { users: self.list().await?, deleteds: self.scan::<DeletedUser>(DELETED_PREFIX).await? })
+    }
+
+    ///Takes the changes of another node: users deleted there later than they were updated here are
+    ///deleted, users missing or older here are saved unless they were deleted here later.
+    ///Returns the number of saved and deleted users.
+    pub(crate) async fn merge(&self, sync: UserSync) -> Result<(usize, usize)> {
+        let mut deleteds = 0;
+        for d in sync.deleteds {
+            if self.deleted(&d.username).await?.map(|old| old.deleted_at >= d.deleted_at).unwrap_or_default()
+            {
+                continue;
+            }
+            if self.remove(&d.username, d.deleted_at).await? {
+                deleteds += 1;
+            }
+        }
+
+        let mut saveds = 0;
+        for user in sync.users {
+            if self
+                .get(&user.username)
+                .await?
+                .map(|old| old.updated_at >= user.updated_at)
+                .unwrap_or_default()
+                || self
+                    .deleted(&user.username)
+                    .await?
+                    .map(|d| d.deleted_at >= user.updated_at)
+                    .unwrap_or_default()
+            {
+                continue;
+            }
+            self.put(&user).await?;
+            saveds += 1;
+        }
+        Ok((saveds, deleteds))
+    }
+
+    ///Forgets the deletions older than the retention period
+    pub(crate) async fn cleanup(&self, deleted_retention: Duration) -> Result<()> {
+        let since = timestamp_secs() - deleted_retention.as_secs() as Timestamp;
+        for d in self.scan::<DeletedUser>(DELETED_PREFIX).await? {
+            if d.deleted_at <= since {
+                self.storage_db.remove(make_stored_key(DELETED_PREFIX, &d.username).as_slice()).await?;
+            }
+        }
+        Ok(())
+    }
+
+    async fn scan<T: DeserializeOwned>(&self, prefix: &[u8]) -> Result<Vec<T>> {
+        let mut storage_db = self.storage_db.clone();
+        let mut keys = Vec::new();
+        {
+            let mut iter = storage_db.scan([prefix, b"*"].concat()).await?;
+            while let Some(key) = iter.next().await {
+                match key {
+                    Ok(key) => keys.push(key),
+                    Err(e) => log::warn!("scan users error, {:?}", e),
+                }
+            }
+        }
+        let mut items = Vec::with_capacity(keys.len());
+        for key in keys {
+            match storage_db.get::<_, T>(key.as_slice()).await {
+                Ok(Some(item)) => items.push(item),
+                Ok(None) => {}
+                Err(e) => {
+                    log::warn!("load user error, {:?}, key: {:?}", e, String::from_utf8_lossy(&key))
+                }
+            }
+        }
+        Ok(items)
+    }
+}
+
+#[inline]
+fn make_stored_key(prefix: &[u8], username: &str) -> Vec<u8> {
+    [prefix, username.as_bytes()].concat()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rmqtt::tokio;
+    use rmqtt_storage::{init_db, Config};
+
+    async fn user_store(name: &str) -> UserStore {
+        let dir =
+            std::env::temp_dir().join(format!("rmqtt-auth-builtin-test-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        let cfg: Config =
+            serde_json::from_value(serde_json::json!({"type": "sled", "sled": {"path": dir}})).unwrap();
+        UserStore::new(init_db(&cfg).await.unwrap())
+    }
+
+    fn user(username: &str, updated_at: Timestamp) -> User {
+        User {
+            username: username.into(),
+            password_hash: String::new(),
+            superuser: false,
+            acl: String::new(),
+            created_at: 100,
+            updated_at,
+        }
+    }
+
+    async fn usernames(users: &UserStore) -> Vec<String> {
+        users.list().await.unwrap().into_iter().map(|u| u.username).collect()
+    }
+
+    #[tokio::test(crate = "rmqtt::tokio")]
+    async fn delete_while_node_down() {
+        let node1 = user_store("delete-node1").await;
+        let node2 = user_store("delete-node2").await;
+        for users in [&node1, &node2] {
+            users.put(&user("user1", 100)).await.unwrap();
+            users.put(&user("user2", 100)).await.unwrap();
+        }
+
+        //node2 is down while user1 is deleted and user3 is added on node1
+        assert!(node1.remove("user1", 200).await.unwrap());
+        node1.put(&user("user3", 150)).await.unwrap();
+
+        //node2 takes the deletion on start
+        assert_eq!(node2.merge(node1.sync().await.unwrap()).await.unwrap(), (1, 1));
+        assert_eq!(usernames(&node2).await, vec!["user2", "user3"]);
+
+        //node1 does not take the deleted user back from the older copy of node2
+        let stale = UserSync { users: vec![user("user1", 100)], deleteds: Vec::new() };
+        assert_eq!(node1.merge(stale).await.unwrap(), (0, 0));
+        assert_eq!(usernames(&node1).await, vec!["user2", "user3"]);
+    }
+
+    #[tokio::test(crate = "rmqtt::tokio")]
+    async fn update_after_delete() {
+        let node1 = user_store("update-node1").await;
+        let node2 = user_store("update-node2").await;
+        node1.put(&user("user1", 100)).await.unwrap();
+        node1.remove("user1", 200).await.unwrap();
+
+        //The user added again later wins over the deletion
+        node2.put(&user("user1", 300)).await.unwrap();
+        assert_eq!(node2.merge(node1.sync().await.unwrap()).await.unwrap(), (0, 0));
+        assert_eq!(usernames(&node2).await, vec!["user1"]);
+        assert!(!node2.remove("user1", 250).await.unwrap());
+
+        assert_eq!(node1.merge(node2.sync().await.unwrap()).await.unwrap(), (1, 0));
+        assert_eq!(usernames(&node1).await, vec!["user1"]);
+        assert!(node1.sync().await.unwrap().deleteds.is_empty());
+    }
+
+    #[tokio::test(crate = "rmqtt::tokio")]
+    async fn deleted_retention() {
+        let users = user_store("retention").await;
+        let now = timestamp_secs();
+        users.remove("user1", now - 10).await.unwrap();
+        users.remove("user2", now - 100).await.unwrap();
+
+        users.cleanup(Duration::from_secs(60)).await.unwrap();
+        let deleteds = users.sync().await.unwrap().deleteds;
+        assert_eq!(deleteds, vec![DeletedUser { username: "user1".into(), deleted_at: now - 10 }]);
+
+        users.cleanup(Duration::from_secs(5)).await.unwrap();
+        assert!(users.sync().await.unwrap().deleteds.is_empty());
+    }
+}
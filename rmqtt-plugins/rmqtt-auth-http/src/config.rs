@@ -41,8 +41,16 @@ pub struct PluginConfig {
     #[serde(default)]
     pub http_retry: Retry,
 
+    ///Local cache of the auth and ACL results
+    #[serde(default)]
+    pub cache: CacheConfig,
+    #[serde(default)]
+    pub circuit_breaker: CircuitBreakerConfig,
+
     pub http_auth_req: Option<Req>,
     pub http_acl_req: Option<Req>,
+    ///MQTT 5.0 enhanced authentication, SCRAM-SHA-256 credentials request
+    pub http_scram_req: Option<Req>,
 }
 
 impl PluginConfig {
@@ -134,6 +142,70 @@ impl Retry {
     }
 }
 
+#[derive(Debug, Clone, Copy, Deserialize, Serialize)]
+pub struct CacheConfig {
+    //Maximum number of cached results, 0 disables the cache
+    #[serde(default)]
+    pub capacity: usize,
+    //Lifetime of 'allow' results
+    #[serde(default = "CacheConfig::allow_ttl_default", deserialize_with = "deserialize_duration")]
+    pub allow_ttl: Duration,
+    //Lifetime of 'deny' results
+    #[serde(default = "CacheConfig::deny_ttl_default", deserialize_with = "deserialize_duration")]
+    pub deny_ttl: Duration,
+    //Expired results are kept for this long and used while the circuit breaker is open
+    #[serde(default = "CacheConfig::stale_ttl_default", deserialize_with = "deserialize_duration")]
+    pub stale_ttl: Duration,
+}
+
+impl Default for CacheConfig {
+    fn default() -> Self {
+        Self {
+            capacity: 0,
+            allow_ttl: Self::allow_ttl_default(),
+            deny_ttl: Self::deny_ttl_default(),
+            stale_ttl: Self::stale_ttl_default(),
+        }
+    }
+}
+
+impl CacheConfig {
+    fn allow_ttl_default() -> Duration {
+        Duration::from_secs(60)
+    }
+    fn deny_ttl_default() -> Duration {
+        Duration::from_secs(10)
+    }
+    fn stale_ttl_default() -> Duration {
+        Duration::from_secs(600)
+    }
+}
+
+#[derive(Debug, Clone, Copy, Deserialize, Serialize)]
+pub struct CircuitBreakerConfig {
+    //Consecutive failed requests that open the circuit, 0 disables the circuit breaker
+    #[serde(default = "CircuitBreakerConfig::failure_threshold_default")]
+    pub failure_threshold: usize,
+    //Time the circuit stays open, then a single request is let through to probe the server
+    #[serde(default = "CircuitBreakerConfig::open_time_default", deserialize_with = "deserialize_duration")]
+    pub open_time: Duration,
+}
+
+impl Default for CircuitBreakerConfig {
+    fn default() -> Self {
+        Self { failure_threshold: Self::failure_threshold_default(), open_time: Self::open_time_default() }
+    }
+}
+
+impl CircuitBreakerConfig {
+    fn failure_threshold_default() -> usize {
+        5
+    }
+    fn open_time_default() -> Duration {
+        Duration::from_secs(30)
+    }
+}
+
 #[derive(Debug, Clone)]
 pub enum ContentType {
     Json,
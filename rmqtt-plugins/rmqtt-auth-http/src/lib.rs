@@ -19,26 +19,31 @@ use rmqtt::{
     reqwest::Response, serde_json, tokio,
 };
 use rmqtt::{
-    broker::hook::{Handler, HookResult, Parameter, Register, ReturnType, Type},
+    broker::hook::{dry_run_note, is_dry_run, Handler, HookResult, Parameter, Register, ReturnType, Type},
+    broker::scram::{ClientFirst, ScramCredentials, ScramServer, SCRAM_SHA_256},
     broker::types::{
-        AuthResult, ConnectInfo, Message, Password, PublishAclResult, Reason, SubscribeAckReason,
-        SubscribeAclResult, Superuser,
+        AuthData, AuthResult, ConnectAckReasonV5, ConnectInfo, EnhancedAuth, EnhancedAuthResult, Message,
+        Password, PublishAclResult, Reason, SubscribeAckReason, SubscribeAclResult, Superuser,
     },
     plugin::{PackageInfo, Plugin},
-    register, timestamp_millis, Id, MqttError, Result, Runtime, TopicName,
+    register, timestamp_millis, Id, MqttError, Result, Runtime, TopicName, UserName,
 };
 
 use config::PluginConfig;
 use rmqtt::reqwest::header::CONTENT_TYPE;
 use rmqtt::settings::acl::{AuthInfo, Rule};
+use rmqtt::settings::listener::PublishLimit;
+use upstream::Upstream;
 
 mod config;
+mod upstream;
 
 type HashMap<K, V> = std::collections::HashMap<K, V, ahash::RandomState>;
 
 const CACHEABLE: &str = "X-Cache";
 const SUPERUSER: &str = "X-Superuser";
 const CACHE_KEY: &str = "ACL-CACHE-MAP";
+const SCRAM_KEY: &str = "SCRAM-SERVER";
 
 #[derive(Clone, Debug)]
 struct ResponseResult {
@@ -47,15 +52,33 @@ struct ResponseResult {
     cacheable: Cacheable,
     expire_at: Option<Duration>,
     acl_data: Option<serde_json::Value>,
+    scram_data: Option<serde_json::Value>,
+    publish_limit: Option<PublishLimit>,
 }
 
 impl ResponseResult {
     #[inline]
     fn new(permission: Permission, superuser: Superuser, cacheable: Cacheable) -> ResponseResult {
-        ResponseResult { permission, superuser, cacheable, expire_at: None, acl_data: None }
+        ResponseResult {
+            permission,
+            superuser,
+            cacheable,
+            expire_at: None,
+            acl_data: None,
+            scram_data: None,
+            publish_limit: None,
+        }
     }
 }
 
+///SCRAM exchange state, kept between the server-first and client-final messages
+#[derive(Clone)]
+struct ScramState {
+    server: ScramServer,
+    superuser: Superuser,
+    auth_info: Option<AuthInfo>,
+}
+
 #[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
 enum Permission {
     Allow(Superuser),
@@ -115,16 +138,19 @@ struct AuthHttpPlugin {
     runtime: &'static Runtime,
     register: Box<dyn Register>,
     cfg: Arc<RwLock<PluginConfig>>,
+    upstream: Arc<Upstream>,
 }
 
 impl AuthHttpPlugin {
     #[inline]
     async fn new<S: Into<String>>(runtime: &'static Runtime, name: S) -> Result<Self> {
         let name = name.into();
-        let cfg = Arc::new(RwLock::new(runtime.settings.plugins.load_config::<PluginConfig>(&name)?));
+        let cfg = runtime.settings.plugins.load_config::<PluginConfig>(&name)?;
+        let upstream = Arc::new(Upstream::new(&cfg.cache));
+        let cfg = Arc::new(RwLock::new(cfg));
         log::debug!("{} AuthHttpPlugin cfg: {:?}", name, cfg.read().await);
         let register = runtime.extends.hook_mgr().await.register();
-        Ok(Self { runtime, register, cfg })
+        Ok(Self { runtime, register, cfg, upstream })
     }
 }
 
@@ -134,16 +160,26 @@ impl Plugin for AuthHttpPlugin {
     async fn init(&mut self) -> Result<()> {
         log::info!("{} init", self.name());
         let cfg = &self.cfg;
+        let upstream = &self.upstream;
 
         let priority = cfg.read().await.priority;
-        self.register.add_priority(Type::ClientAuthenticate, priority, Box::new(AuthHandler::new(cfg))).await;
         self.register
-            .add_priority(Type::ClientSubscribeCheckAcl, priority, Box::new(AuthHandler::new(cfg)))
+            .add_priority(Type::ClientAuthenticate, priority, Box::new(AuthHandler::new(cfg, upstream)))
+            .await;
+        self.register
+            .add_priority(Type::ClientSubscribeCheckAcl, priority, Box::new(AuthHandler::new(cfg, upstream)))
+            .await;
+        self.register
+            .add_priority(Type::MessagePublishCheckAcl, priority, Box::new(AuthHandler::new(cfg, upstream)))
             .await;
+        self.register.add(Type::ClientKeepalive, Box::new(AuthHandler::new(cfg, upstream))).await;
         self.register
-            .add_priority(Type::MessagePublishCheckAcl, priority, Box::new(AuthHandler::new(cfg)))
+            .add_priority(
+                Type::ClientEnhancedAuthenticate,
+                priority,
+                Box::new(AuthHandler::new(cfg, upstream)),
+            )
             .await;
-        self.register.add(Type::ClientKeepalive, Box::new(AuthHandler::new(cfg))).await;
         Ok(())
     }
 
@@ -155,6 +191,7 @@ impl Plugin for AuthHttpPlugin {
     #[inline]
     async fn load_config(&mut self) -> Result<()> {
         let new_cfg = self.runtime.settings.plugins.load_config::<PluginConfig>(self.name())?;
+        self.upstream.reset(&new_cfg.cache);
         *self.cfg.write().await = new_cfg;
         log::debug!("load_config ok,  {:?}", self.cfg);
         Ok(())
@@ -176,17 +213,18 @@ impl Plugin for AuthHttpPlugin {
 
     #[inline]
     async fn attrs(&self) -> serde_json::Value {
-        serde_json::json!({})
+        self.upstream.to_json()
     }
 }
 
 struct AuthHandler {
     cfg: Arc<RwLock<PluginConfig>>,
+    upstream: Arc<Upstream>,
 }
 
 impl AuthHandler {
-    fn new(cfg: &Arc<RwLock<PluginConfig>>) -> Self {
-        Self { cfg: cfg.clone() }
+    fn new(cfg: &Arc<RwLock<PluginConfig>>, upstream: &Arc<Upstream>) -> Self {
+        Self { cfg: cfg.clone(), upstream: upstream.clone() }
     }
 
     async fn response_result(resp: Response) -> Result<ResponseResult> {
@@ -225,8 +263,18 @@ impl AuthHandler {
                         obj.get("expire_at").and_then(|res| res.as_u64().map(Duration::from_secs));
                     let permission = Permission::try_from((result, superuser))?;
                     let acl_data = obj.remove("acl");
-
-                    ResponseResult { permission, superuser, cacheable: cache_timeout, expire_at, acl_data }
+                    let scram_data = obj.remove("scram");
+                    let publish_limit = PublishLimit::from_fields(|k| obj.get(k))?;
+
+                    ResponseResult {
+                        permission,
+                        superuser,
+                        cacheable: cache_timeout,
+                        expire_at,
+                        acl_data,
+                        scram_data,
+                        publish_limit,
+                    }
                 } else if let Some(body) = body.as_str() {
                     log::debug!("body: {:?}", body);
                     ResponseResult::new(Permission::try_from((body, superuser))?, superuser, cache_timeout)
@@ -333,10 +381,15 @@ impl AuthHandler {
         let client_id = id.client_id.as_ref();
         let username = id.username.as_ref().map(|n| n.as_ref()).unwrap_or("");
         let remote_addr = id.remote_addr.map(|addr| addr.ip().to_string()).unwrap_or_default();
+        let peer_cert = id.peer_cert.as_ref();
+        let cert_cn = peer_cert.and_then(|c| c.cn.as_deref()).unwrap_or_default();
+        let cert_dn = peer_cert.and_then(|c| c.dn.as_deref()).unwrap_or_default();
         for v in params.values_mut() {
             *v = v.replace("%u", username);
             *v = v.replace("%c", client_id);
             *v = v.replace("%a", &remote_addr);
+            *v = v.replace("%C", cert_cn);
+            *v = v.replace("%d", cert_dn);
             *v = v.replace("%P", &password);
             if let Some(protocol) = protocol {
                 let mut buffer = rmqtt::itoa::Buffer::new();
@@ -360,9 +413,10 @@ impl AuthHandler {
         password: Option<&Password>,
         protocol: Option<u8>,
         sub_or_pub: Option<(ACLType, &TopicName)>,
+        cacheable: bool,
     ) -> Result<ResponseResult> {
         log::debug!("{:?} req_cfg.url.path(): {:?}", id, req_cfg.url.path());
-        let (headers, timeout) = {
+        let (headers, timeout, cache_cfg, breaker_cfg) = {
             let cfg = self.cfg.read().await;
             let headers = match (cfg.headers(), req_cfg.headers()) {
                 (Some(def_headers), Some(req_headers)) => {
@@ -374,23 +428,28 @@ impl AuthHandler {
                 (None, Some(req_headers)) => req_headers.clone(),
                 (None, None) => HeaderMap::new(),
             };
-            (headers, cfg.http_timeout)
+            (headers, cfg.http_timeout, cfg.cache, cfg.circuit_breaker)
         };
 
-        let auth_result = if req_cfg.is_get() {
-            let body = &mut req_cfg.params;
-            Self::replaces(body, id, password, protocol, sub_or_pub)?;
-            Self::http_get_request(req_cfg.url, body, headers, timeout).await?
-        } else if req_cfg.json_body() {
-            let body = &mut req_cfg.params;
-            Self::replaces(body, id, password, protocol, sub_or_pub)?;
-            Self::http_json_request(req_cfg.url, req_cfg.method, body, headers, timeout).await?
-        } else {
-            //form body
-            let body = &mut req_cfg.params;
-            Self::replaces(body, id, password, protocol, sub_or_pub)?;
-            Self::http_form_request(req_cfg.url, req_cfg.method, body, headers, timeout).await?
+        Self::replaces(&mut req_cfg.params, id, password, protocol, sub_or_pub)?;
+        let key = {
+            let mut params = req_cfg.params.iter().collect::<Vec<_>>();
+            params.sort();
+            upstream::cache_key(req_cfg.method.as_str(), req_cfg.url.as_str(), &params)
+        };
+        let (is_get, json_body) = (req_cfg.is_get(), req_cfg.json_body());
+        let config::Req { url, method, params: body, .. } = req_cfg;
+        let req = async move {
+            if is_get {
+                Self::http_get_request(url, &body, headers, timeout).await
+            } else if json_body {
+                Self::http_json_request(url, method, &body, headers, timeout).await
+            } else {
+                //form body
+                Self::http_form_request(url, method, &body, headers, timeout).await
+            }
         };
+        let auth_result = self.upstream.call(key, cacheable, &cache_cfg, &breaker_cfg, req).await?;
         log::debug!("auth_result: {:?}", auth_result);
         Ok(auth_result)
     }
@@ -405,40 +464,13 @@ impl AuthHandler {
                     connect_info.password(),
                     Some(connect_info.proto_ver()),
                     None,
+                    true,
                 )
                 .await
             {
                 Ok(auth_res) => {
                     log::debug!("auth result: {:?}", auth_res);
-                    let auth_info = if matches!(auth_res.permission, Permission::Allow(_)) {
-                        if let Some(acl_data) =
-                            auth_res.acl_data.as_ref().and_then(|acl_data| acl_data.as_array())
-                        {
-                            match acl_data
-                                .iter()
-                                .map(|acl| Rule::try_from((acl, connect_info)))
-                                .collect::<Result<Vec<Rule>>>()
-                            {
-                                Ok(rules) => {
-                                    let auth_info = AuthInfo {
-                                        superuser: auth_res.superuser,
-                                        expire_at: auth_res.expire_at,
-                                        rules,
-                                    };
-                                    log::debug!("auth_info: {:?}", auth_info);
-                                    Some(auth_info)
-                                }
-                                Err(e) => {
-                                    log::warn!("{} {}", connect_info.id(), e);
-                                    None
-                                }
-                            }
-                        } else {
-                            None
-                        }
-                    } else {
-                        None
-                    };
+                    let auth_info = Self::auth_info(&auth_res, connect_info);
                     (auth_res.permission, auth_info)
                 }
                 Err(e) => {
@@ -455,6 +487,126 @@ impl AuthHandler {
         }
     }
 
+    #[inline]
+    fn auth_info(auth_res: &ResponseResult, connect_info: &ConnectInfo) -> Option<AuthInfo> {
+        if !matches!(auth_res.permission, Permission::Allow(_)) {
+            return None;
+        }
+        let acl_data = auth_res.acl_data.as_ref().and_then(|acl_data| acl_data.as_array());
+        if acl_data.is_none() && auth_res.publish_limit.is_none() {
+            return None;
+        }
+        match acl_data
+            .into_iter()
+            .flatten()
+            .map(|acl| Rule::try_from((acl, connect_info)))
+            .collect::<Result<Vec<Rule>>>()
+        {
+            Ok(rules) => {
+                let auth_info = AuthInfo {
+                    superuser: auth_res.superuser,
+                    expire_at: auth_res.expire_at,
+                    rules,
+                    publish_limit: auth_res.publish_limit,
+                };
+                log::debug!("auth_info: {:?}", auth_info);
+                Some(auth_info)
+            }
+            Err(e) => {
+                log::warn!("{} {}", connect_info.id(), e);
+                None
+            }
+        }
+    }
+
+    ///SCRAM-SHA-256, the credentials of the user are obtained through `http_scram_req`
+    #[inline]
+    async fn scram_auth(
+        &self,
+        req: config::Req,
+        connect_info: &ConnectInfo,
+        auth: &EnhancedAuth,
+        data: Option<&AuthData>,
+    ) -> Option<EnhancedAuthResult> {
+        let data = data.map(|d| d.as_ref()).unwrap_or_default();
+        let state = auth.attrs.write().remove::<ScramState>(SCRAM_KEY);
+        if let Some(state) = state {
+            //client-final-message
+            return Some(match state.server.client_final(data) {
+                Ok(server_final) => {
+                    if state.auth_info.as_ref().map(|ai| ai.is_expired()).unwrap_or_default() {
+                        log::warn!("{} authentication information has expired.", connect_info.id());
+                        EnhancedAuthResult::Failure(ConnectAckReasonV5::NotAuthorized)
+                    } else {
+                        let username = UserName::from(state.server.username());
+                        EnhancedAuthResult::Success(
+                            state.superuser,
+                            state.auth_info,
+                            Some(AuthData::from(server_final)),
+                            Some(username),
+                        )
+                    }
+                }
+                Err(e) => {
+                    log::warn!("{:?} {}", connect_info.id(), e);
+                    EnhancedAuthResult::Failure(ConnectAckReasonV5::NotAuthorized)
+                }
+            });
+        }
+
+        //client-first-message
+        let client_first = match ClientFirst::parse(data) {
+            Ok(client_first) => client_first,
+            Err(e) => {
+                log::warn!("{:?} {}", connect_info.id(), e);
+                return Some(EnhancedAuthResult::Failure(ConnectAckReasonV5::MalformedPacket));
+            }
+        };
+        let id = connect_info.id();
+        let scram_id = Id::new(
+            id.node_id,
+            id.local_addr,
+            id.remote_addr,
+            id.client_id.clone(),
+            Some(client_first.username.as_str().into()),
+        );
+        let auth_res =
+            match self.request(&scram_id, req, None, Some(connect_info.proto_ver()), None, false).await {
+                Ok(auth_res) => auth_res,
+                Err(e) => {
+                    log::warn!("{:?} scram auth error, {:?}", id, e);
+                    return if self.cfg.read().await.deny_if_error {
+                        Some(EnhancedAuthResult::Failure(ConnectAckReasonV5::NotAuthorized))
+                    } else {
+                        None
+                    };
+                }
+            };
+        log::debug!("scram auth result: {:?}", auth_res);
+        let superuser = match auth_res.permission {
+            Permission::Allow(superuser) => superuser,
+            Permission::Deny => return Some(EnhancedAuthResult::Failure(ConnectAckReasonV5::NotAuthorized)),
+            Permission::Ignore => return None,
+        };
+        let server = match auth_res
+            .scram_data
+            .as_ref()
+            .ok_or_else(|| MqttError::from("SCRAM credentials does not exist"))
+            .and_then(ScramCredentials::from_json)
+            .and_then(|credentials| ScramServer::new(client_first, credentials))
+        {
+            Ok(server) => server,
+            Err(e) => {
+                log::warn!("{:?} {}", id, e);
+                return Some(EnhancedAuthResult::Failure(ConnectAckReasonV5::NotAuthorized));
+            }
+        };
+        let server_first = AuthData::copy_from_slice(server.server_first());
+        let auth_info = Self::auth_info(&auth_res, connect_info);
+        auth.attrs.write().insert(SCRAM_KEY.into(), ScramState { server, superuser, auth_info });
+        Some(EnhancedAuthResult::Continue(server_first))
+    }
+
     #[inline]
     async fn acl(
         &self,
@@ -463,7 +615,7 @@ impl AuthHandler {
         sub_or_pub: Option<(ACLType, &TopicName)>,
     ) -> (Permission, Cacheable) {
         if let Some(req) = { self.cfg.read().await.http_acl_req.clone() } {
-            match self.request(id, req, None, protocol, sub_or_pub).await {
+            match self.request(id, req, None, protocol, sub_or_pub, true).await {
                 Ok(acl_res) => {
                     log::debug!("acl result: {:?}", acl_res);
                     (acl_res.permission, acl_res.cacheable)
@@ -513,6 +665,18 @@ impl Handler for AuthHandler {
                 };
             }
 
+            Parameter::ClientEnhancedAuthenticate(connect_info, auth, data) => {
+                log::debug!("ClientEnhancedAuthenticate auth-http, {:?}", auth);
+                if acc.is_some() || auth.method != SCRAM_SHA_256 {
+                    return (true, acc);
+                }
+                if let Some(req) = { self.cfg.read().await.http_scram_req.clone() } {
+                    if let Some(res) = self.scram_auth(req, connect_info, auth, *data).await {
+                        return (false, Some(HookResult::EnhancedAuthResult(res)));
+                    }
+                }
+            }
+
             Parameter::ClientSubscribeCheckAcl(session, subscribe) => {
                 if let Some(HookResult::SubscribeAclResult(acl_result)) = &acc {
                     if acl_result.failure() {
@@ -520,7 +684,7 @@ impl Handler for AuthHandler {
                     }
                 }
 
-                if let Some(auth_info) = &session.auth_info {
+                if let Some(auth_info) = session.auth_info() {
                     if let Some(acl_res) = auth_info.subscribe_acl(subscribe).await {
                         return acl_res;
                     }
@@ -558,7 +722,7 @@ impl Handler for AuthHandler {
                     return (false, acc);
                 }
 
-                if let Some(auth_info) = &session.auth_info {
+                if let Some(auth_info) = session.auth_info() {
                     if let Some(acl_res) =
                         auth_info.publish_acl(publish, self.cfg.read().await.disconnect_if_pub_rejected).await
                     {
@@ -574,6 +738,7 @@ impl Handler for AuthHandler {
                     .and_then(|cache_map| cache_map.get(publish.topic()))
                 {
                     if *expire < 0 || timestamp_millis() < *expire {
+                        dry_run_note(|| format!("cache hit, {:?}", acl_res));
                         Some(*acl_res)
                     } else {
                         None
@@ -593,7 +758,8 @@ impl Handler for AuthHandler {
                             Some((ACLType::Pub, publish.topic())),
                         )
                         .await;
-                    if let Some(tm) = cacheable {
+                    dry_run_note(|| format!("http acl request, {:?}", acl_res));
+                    if let Some(tm) = cacheable.filter(|_| !is_dry_run()) {
                         let expire = if tm < 0 { tm } else { timestamp_millis() + tm };
                         if let Some(cache_map) = session
                             .extra_attrs
@@ -622,7 +788,7 @@ impl Handler for AuthHandler {
             }
 
             Parameter::ClientKeepalive(s, _) => {
-                if let Some(auth) = &s.auth_info {
+                if let Some(auth) = s.auth_info() {
                     log::debug!("Keepalive auth-http, is_expired: {:?}", auth.is_expired());
                     if auth.is_expired() && self.cfg.read().await.disconnect_if_expiry {
                         if let Some(tx) =
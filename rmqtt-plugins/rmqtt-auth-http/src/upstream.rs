@@ -0,0 +1,458 @@
+use std::future::Future;
+use std::num::NonZeroUsize;
+use std::sync::atomic::{AtomicBool, AtomicI64, AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use lru::LruCache;
+use sha2::{Digest, Sha256};
+
+use rmqtt::{
+    broker::hook::{dry_run_note, is_dry_run},
+    timestamp_millis, DashMap, MqttError, Result, TimestampMillis,
+};
+use rmqtt::{log, serde_json, tokio::sync::OnceCell};
+
+use crate::config::{CacheConfig, CircuitBreakerConfig};
+use crate::{Permission, ResponseResult};
+
+//Upper bounds of the upstream latency buckets, in milliseconds
+const LATENCY_BUCKETS: [u64; 10] = [5, 10, 25, 50, 100, 250, 500, 1000, 2500, 5000];
+
+///SHA-256 of the request after the variables are replaced, the password is not kept in memory
+pub(crate) type CacheKey = [u8; 32];
+
+#[inline]
+pub(crate) fn cache_key(method: &str, url: &str, params: &[(&String, &String)]) -> CacheKey {
+    let mut hasher = Sha256::new();
+    hasher.update(method.as_bytes());
+    hasher.update([0]);
+    hasher.update(url.as_bytes());
+    for (k, v) in params {
+        hasher.update([0]);
+        hasher.update(k.as_bytes());
+        hasher.update([1]);
+        hasher.update(v.as_bytes());
+    }
+    hasher.finalize().into()
+}
+
+struct CacheEntry {
+    result: ResponseResult,
+    expire_at: TimestampMillis,
+    //The entry is dropped after this time, it is only used while the circuit is open before
+    stale_at: TimestampMillis,
+}
+
+type Inflight = Arc<OnceCell<std::result::Result<ResponseResult, String>>>;
+
+///Requests to the HTTP server: local result cache, coalescing of identical concurrent requests and
+///circuit breaker
+pub(crate) struct Upstream {
+    cache: Mutex<Option<LruCache<CacheKey, CacheEntry>>>,
+    inflights: DashMap<CacheKey, Inflight>,
+    breaker: CircuitBreaker,
+    metrics: Metrics,
+}
+
+impl Upstream {
+    pub(crate) fn new(cache_cfg: &CacheConfig) -> Self {
+        Self {
+            cache: Mutex::new(NonZeroUsize::new(cache_cfg.capacity).map(LruCache::new)),
+            inflights: DashMap::default(),
+            breaker: CircuitBreaker::default(),
+            metrics: Metrics::default(),
+        }
+    }
+
+    ///Applies a reloaded configuration, cached results are dropped
+    pub(crate) fn reset(&self, cache_cfg: &CacheConfig) {
+        if let Ok(mut cache) = self.cache.lock() {
+            *cache = NonZeroUsize::new(cache_cfg.capacity).map(LruCache::new);
+        }
+    }
+
+    ///Sends the request unless a cached result is available. Results are cached if 'cacheable' is true,
+    ///in dry-run mode the cache, the circuit breaker and the metrics are not updated.
+    pub(crate) async fn call<F>(
+        &self,
+        key: CacheKey,
+        cacheable: bool,
+        cache_cfg: &CacheConfig,
+        breaker_cfg: &CircuitBreakerConfig,
+        req: F,
+    ) -> Result<ResponseResult>
+    where
+        F: Future<Output = Result<ResponseResult>>,
+    {
+        let dry_run = is_dry_run();
+        let now = timestamp_millis();
+        if cacheable {
+            if let Some(res) = self.cache_get(&key, now, false) {
+                dry_run_note(|| format!("local cache hit, {:?}", res.permission));
+                if !dry_run {
+                    self.metrics.cache_hits.fetch_add(1, Ordering::Relaxed);
+                }
+                return Ok(res);
+            }
+            if !dry_run {
+                self.metrics.cache_misses.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+
+        //Held until the result is reported, a dropped or coalesced probe lets the next request probe
+        let permit = match self.breaker.allow(breaker_cfg, now, dry_run) {
+            Some(permit) => permit,
+            None => {
+                if !dry_run {
+                    self.metrics.circuit_rejects.fetch_add(1, Ordering::Relaxed);
+                }
+                if cacheable {
+                    if let Some(res) = self.cache_get(&key, now, true) {
+                        dry_run_note(|| {
+                            format!("circuit breaker open, stale cache hit, {:?}", res.permission)
+                        });
+                        if !dry_run {
+                            self.metrics.stale_hits.fetch_add(1, Ordering::Relaxed);
+                        }
+                        return Ok(res);
+                    }
+                }
+                dry_run_note(|| "circuit breaker open".into());
+                return Err(MqttError::from("circuit breaker is open"));
+            }
+        };
+
+        let inflight = self.inflights.entry(key).or_insert_with(|| Arc::new(OnceCell::new())).value().clone();
+        let mut leader = false;
+        let start = Instant::now();
+        let res = inflight
+            .get_or_init(|| {
+                leader = true;
+                async move { req.await.map_err(|e| e.to_string()) }
+            })
+            .await
+            .clone();
+
+        if !leader {
+            dry_run_note(|| "coalesced with an identical request".into());
+            if !dry_run {
+                self.metrics.coalesced.fetch_add(1, Ordering::Relaxed);
+            }
+            return res.map_err(MqttError::from);
+        }
+
+        self.inflights.remove_if(&key, |_, v| Arc::ptr_eq(v, &inflight));
+        if !dry_run {
+            self.metrics.observe(start.elapsed(), res.is_ok());
+            match &res {
+                Ok(res) => {
+                    self.breaker.on_success();
+                    if cacheable {
+                        self.cache_put(key, res, cache_cfg);
+                    }
+                }
+                Err(_) => self.breaker.on_failure(breaker_cfg, timestamp_millis(), permit.probe),
+            }
+        }
+        res.map_err(MqttError::from)
+    }
+
+    #[inline]
+    fn cache_get(&self, key: &CacheKey, now: TimestampMillis, stale: bool) -> Option<ResponseResult> {
+        let mut cache = self.cache.lock().ok()?;
+        let cache = cache.as_mut()?;
+        let entry = cache.get(key)?;
+        if now < entry.expire_at || (stale && now < entry.stale_at) {
+            Some(entry.result.clone())
+        } else {
+            if now >= entry.stale_at {
+                cache.pop(key);
+            }
+            None
+        }
+    }
+
+    //Only decisions are cached, 'ignore' leaves the decision to the other plugins
+    #[inline]
+    fn cache_put(&self, key: CacheKey, res: &ResponseResult, cache_cfg: &CacheConfig) {
+        let ttl = match res.permission {
+            Permission::Allow(_) => cache_cfg.allow_ttl,
+            Permission::Deny => cache_cfg.deny_ttl,
+            Permission::Ignore => return,
+        };
+        if ttl.is_zero() {
+            return;
+        }
+        if let Ok(mut cache) = self.cache.lock() {
+            if let Some(cache) = cache.as_mut() {
+                let expire_at = timestamp_millis() + ttl.as_millis() as TimestampMillis;
+                let stale_at = expire_at + cache_cfg.stale_ttl.as_millis() as TimestampMillis;
+                cache.put(key, CacheEntry { result: res.clone(), expire_at, stale_at });
+            }
+        }
+    }
+
+    pub(crate) fn to_json(&self) -> serde_json::Value {
+        let (capacity, len) = self
+            .cache
+            .lock()
+            .ok()
+            .and_then(|cache| cache.as_ref().map(|c| (c.cap().get(), c.len())))
+            .unwrap_or_default();
+        let mut json = self.metrics.to_json();
+        if let Some(obj) = json.as_object_mut() {
+            obj.insert("cache_capacity".into(), capacity.into());
+            obj.insert("cache_len".into(), len.into());
+            obj.insert("circuit_breaker".into(), self.breaker.state(timestamp_millis()).into());
+        }
+        json
+    }
+}
+
+#[derive(Default)]
+struct CircuitBreaker {
+    failures: AtomicUsize,
+    //0 if the circuit is closed
+    open_until: AtomicI64,
+    //A probe request is in flight after the open time, cleared when its Permit is dropped
+    probing: AtomicBool,
+}
+
+///Request let through by the circuit breaker
+struct Permit<'a> {
+    breaker: &'a CircuitBreaker,
+    //The request probes the server after the open time
+    probe: bool,
+}
+
+impl Drop for Permit<'_> {
+    #[inline]
+    fn drop(&mut self) {
+        if self.probe {
+            self.breaker.probing.store(false, Ordering::SeqCst);
+        }
+    }
+}
+
+impl CircuitBreaker {
+    #[inline]
+    fn allow(&self, cfg: &CircuitBreakerConfig, now: TimestampMillis, dry_run: bool) -> Option<Permit<'_>> {
+        let permit = |probe| Some(Permit { breaker: self, probe });
+        if cfg.failure_threshold == 0 {
+            return permit(false);
+        }
+        let open_until = self.open_until.load(Ordering::SeqCst);
+        if open_until == 0 {
+            return permit(false);
+        }
+        if now < open_until {
+            return None;
+        }
+        //Dry-run requests do not report their result, they must not take the place of the probe
+        if dry_run {
+            return permit(false);
+        }
+        if self.probing.swap(true, Ordering::SeqCst) {
+            None
+        } else {
+            permit(true)
+        }
+    }
+
+    #[inline]
+    fn on_success(&self) {
+        self.failures.store(0, Ordering::SeqCst);
+        if self.open_until.swap(0, Ordering::SeqCst) != 0 {
+            log::info!("auth-http circuit breaker closed");
+        }
+    }
+
+    #[inline]
+    fn on_failure(&self, cfg: &CircuitBreakerConfig, now: TimestampMillis, probe: bool) {
+        let failures = self.failures.fetch_add(1, Ordering::SeqCst) + 1;
+        if cfg.failure_threshold == 0 {
+            return;
+        }
+        if failures >= cfg.failure_threshold || probe {
+            self.open_until.store(now + cfg.open_time.as_millis() as TimestampMillis, Ordering::SeqCst);
+            log::warn!(
+                "auth-http circuit breaker opened for {:?}, consecutive failures: {}",
+                cfg.open_time,
+                failures
+            );
+        }
+    }
+
+    #[inline]
+    fn state(&self, now: TimestampMillis) -> &'static str {
+        match self.open_until.load(Ordering::SeqCst) {
+            0 => "closed",
+            open_until if now < open_until => "open",
+            _ => "half_open",
+        }
+    }
+}
+
+#[derive(Default)]
+struct Metrics {
+    cache_hits: AtomicU64,
+    cache_misses: AtomicU64,
+    stale_hits: AtomicU64,
+    coalesced: AtomicU64,
+    circuit_rejects: AtomicU64,
+    requests: AtomicU64,
+    request_errors: AtomicU64,
+    latency_sum_ms: AtomicU64,
+    latency_max_ms: AtomicU64,
+    //The last bucket counts the requests slower than the last bound
+    latency_buckets: [AtomicU64; LATENCY_BUCKETS.len() + 1],
+}
+
+impl Metrics {
+    #[inline]
+    fn observe(&self, elapsed: Duration, ok: bool) {
+        let ms = elapsed.as_millis() as u64;
+        self.requests.fetch_add(1, Ordering::Relaxed);
+        if !ok {
+            self.request_errors.fetch_add(1, Ordering::Relaxed);
+        }
+        self.latency_sum_ms.fetch_add(ms, Ordering::Relaxed);
+        self.latency_max_ms.fetch_max(ms, Ordering::Relaxed);
+        let idx = LATENCY_BUCKETS.iter().position(|b| ms <= *b).unwrap_or(LATENCY_BUCKETS.len());
+        self.latency_buckets[idx].fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn to_json(&self) -> serde_json::Value {
+        let hits = self.cache_hits.load(Ordering::Relaxed);
+        let misses = self.cache_misses.load(Ordering::Relaxed);
+        let requests = self.requests.load(Ordering::Relaxed);
+        let latency_sum_ms = self.latency_sum_ms.load(Ordering::Relaxed);
+        //Cumulative counts, as in a Prometheus histogram
+        let mut count = 0;
+        let mut buckets = serde_json::Map::new();
+        for (i, bucket) in self.latency_buckets.iter().enumerate() {
+            count += bucket.load(Ordering::Relaxed);
+            let le = LATENCY_BUCKETS.get(i).map(|b| b.to_string()).unwrap_or_else(|| "+Inf".into());
+            buckets.insert(le, count.into());
+        }
+        serde_json::json!({
+            "cache_hits": hits,
+            "cache_misses": misses,
+            "cache_hit_rate": if hits + misses > 0 { hits as f64 / (hits + misses) as f64 } else { 0.0 },
+            "cache_stale_hits": self.stale_hits.load(Ordering::Relaxed),
+            "coalesced_requests": self.coalesced.load(Ordering::Relaxed),
+            "circuit_breaker_rejects": self.circuit_rejects.load(Ordering::Relaxed),
+            "upstream_requests": requests,
+            "upstream_errors": self.request_errors.load(Ordering::Relaxed),
+            "upstream_latency_ms": {
+                "avg": if requests > 0 { latency_sum_ms as f64 / requests as f64 } else { 0.0 },
+                "max": self.latency_max_ms.load(Ordering::Relaxed),
+                "buckets": buckets,
+            },
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::future::pending;
+
+    use rmqtt::tokio;
+
+    use super::*;
+
+    fn breaker_cfg() -> CircuitBreakerConfig {
+        CircuitBreakerConfig { failure_threshold: 2, open_time: Duration::from_secs(10) }
+    }
+
+    fn open(upstream: &Upstream, cfg: &CircuitBreakerConfig) {
+        for _ in 0..cfg.failure_threshold {
+            upstream.breaker.on_failure(cfg, 0, false);
+        }
+    }
+
+    #[test]
+    fn circuit_breaker() {
+        let cfg = breaker_cfg();
+        let breaker = CircuitBreaker::default();
+        assert!(breaker.allow(&cfg, 0, false).is_some());
+        breaker.on_failure(&cfg, 0, false);
+        assert_eq!(breaker.state(0), "closed");
+        breaker.on_failure(&cfg, 0, false);
+        assert_eq!(breaker.state(0), "open");
+        assert!(breaker.allow(&cfg, 5_000, false).is_none());
+
+        //A single probe after the open time
+        let probe = breaker.allow(&cfg, 10_000, false).unwrap();
+        assert!(probe.probe);
+        assert!(breaker.allow(&cfg, 10_000, false).is_none());
+        assert!(breaker.allow(&cfg, 10_000, true).is_some());
+        //A failed probe opens the circuit again
+        breaker.on_failure(&cfg, 10_000, probe.probe);
+        drop(probe);
+        assert_eq!(breaker.state(15_000), "open");
+
+        let probe = breaker.allow(&cfg, 20_000, false).unwrap();
+        breaker.on_success();
+        drop(probe);
+        assert_eq!(breaker.state(20_000), "closed");
+        assert!(!breaker.probing.load(Ordering::SeqCst));
+    }
+
+    #[tokio::test(crate = "rmqtt::tokio")]
+    async fn dropped_probe() {
+        let upstream = Upstream::new(&CacheConfig::default());
+        let cfg = breaker_cfg();
+        open(&upstream, &cfg);
+        upstream.breaker.open_until.store(1, Ordering::SeqCst);
+
+        //The probe is cancelled while the request is in flight, e.g. the client disconnected
+        let probe = upstream.call([0; 32], false, &CacheConfig::default(), &cfg, pending());
+        assert!(tokio::time::timeout(Duration::from_millis(10), probe).await.is_err());
+        assert!(!upstream.breaker.probing.load(Ordering::SeqCst));
+        assert_eq!(upstream.breaker.state(timestamp_millis()), "half_open");
+
+        //The next request probes the server and closes the circuit
+        let res = upstream
+            .call([1; 32], false, &CacheConfig::default(), &cfg, async {
+                Ok(ResponseResult::new(Permission::Allow(false), false, None))
+            })
+            .await;
+        assert!(res.is_ok());
+        assert_eq!(upstream.breaker.state(timestamp_millis()), "closed");
+    }
+
+    #[tokio::test(crate = "rmqtt::tokio")]
+    async fn coalesced_probe() {
+        let upstream = Upstream::new(&CacheConfig::default());
+        let cfg = breaker_cfg();
+        let (tx, rx) = tokio::sync::oneshot::channel::<()>();
+        //A request sent before the circuit opened is still in flight
+        let leader = upstream.call([0; 32], false, &CacheConfig::default(), &cfg, async move {
+            let _ = rx.await;
+            Err(MqttError::from("timeout"))
+        });
+        //The probe is coalesced with it and does not report the result
+        let probe = async {
+            open(&upstream, &cfg);
+            upstream.breaker.open_until.store(1, Ordering::SeqCst);
+            upstream.call([0; 32], false, &CacheConfig::default(), &cfg, pending()).await
+        };
+        let reply = async {
+            tokio::task::yield_now().await;
+            let _ = tx.send(());
+        };
+        let (leader, probe, _) = tokio::join!(leader, probe, reply);
+        assert!(leader.is_err());
+        assert!(probe.is_err());
+        assert_eq!(upstream.metrics.coalesced.load(Ordering::Relaxed), 1);
+        assert!(!upstream.breaker.probing.load(Ordering::SeqCst));
+
+        //The failure of the first request opened the circuit again, it is probed after the open time
+        let now = timestamp_millis();
+        assert!(upstream.breaker.allow(&cfg, now, false).is_none());
+        let probe = upstream.breaker.allow(&cfg, now + cfg.open_time.as_millis() as TimestampMillis, false);
+        assert!(probe.map(|p| p.probe).unwrap_or_default());
+    }
+}
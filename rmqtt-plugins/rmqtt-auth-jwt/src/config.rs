@@ -1,4 +1,6 @@
 use std::fmt;
+use std::sync::Arc;
+use std::time::Duration;
 
 use jsonwebtoken::DecodingKey;
 use serde::de::{self, Deserialize, Deserializer};
@@ -7,10 +9,14 @@ use serde::ser::{self, Serialize, Serializer};
 use rmqtt::{ahash, anyhow, itertools::Itertools, serde_json};
 use rmqtt::{
     broker::hook::Priority,
+    grpc::MessageType,
     settings::acl::{PLACEHOLDER_CLIENTID, PLACEHOLDER_IPADDR, PLACEHOLDER_PROTOCOL, PLACEHOLDER_USERNAME},
+    settings::deserialize_duration,
     Result,
 };
 
+use crate::jwks::Jwks;
+
 type HashMap<K, V> = std::collections::HashMap<K, V, ahash::RandomState>;
 
 #[derive(Clone, Deserialize, Serialize)]
@@ -22,6 +28,14 @@ pub struct PluginConfig {
     #[serde(default = "PluginConfig::disconnect_if_expiry_default")]
     pub disconnect_if_expiry: bool,
 
+    ///Disconnect the sessions of a token when it is revoked
+    #[serde(default = "PluginConfig::disconnect_if_revoked_default")]
+    pub disconnect_if_revoked: bool,
+
+    ///gRPC message type, used to get the revocation list from the other nodes on start
+    #[serde(default = "PluginConfig::message_type_default")]
+    pub message_type: MessageType,
+
     ///Hook priority
     #[serde(default = "PluginConfig::priority_default")]
     pub priority: Priority,
@@ -45,6 +59,8 @@ pub struct PluginConfig {
     pub hmac_base64: bool,
     #[serde(default)]
     pub public_key: String,
+    #[serde(default)]
+    pub jwks: JwksConfig,
 
     #[serde(
         default,
@@ -55,6 +71,8 @@ pub struct PluginConfig {
 
     #[serde(skip, default = "PluginConfig::decoded_key_default")]
     pub decoded_key: DecodingKey,
+    #[serde(skip)]
+    pub jwks_keys: Option<Arc<Jwks>>,
 }
 
 impl fmt::Debug for PluginConfig {
@@ -88,6 +106,9 @@ impl PluginConfig {
                     DecodingKey::from_ed_pem(&std::fs::read(&self.public_key)?).map_err(anyhow::Error::new)?
                 };
             }
+            JWTEncrypt::Jwks => {
+                self.jwks_keys = Some(Arc::new(Jwks::new(&self.jwks)?));
+            }
         }
         Ok(())
     }
@@ -116,6 +137,14 @@ impl PluginConfig {
         false
     }
 
+    fn disconnect_if_revoked_default() -> bool {
+        true
+    }
+
+    fn message_type_default() -> MessageType {
+        94
+    }
+
     #[inline]
     fn serialize_encrypt<S>(enc: &JWTEncrypt, s: S) -> std::result::Result<S::Ok, S::Error>
     where
@@ -124,6 +153,7 @@ impl PluginConfig {
         let enc = match enc {
             JWTEncrypt::HmacBased => "hmac-based",
             JWTEncrypt::PublicKey => "public-key",
+            JWTEncrypt::Jwks => "jwks",
         };
         enc.serialize(s)
     }
@@ -134,15 +164,15 @@ impl PluginConfig {
         D: Deserializer<'de>,
     {
         let enc: String = String::deserialize(deserializer)?;
-        let enc = match enc.as_str() {
-            "hmac-based" => JWTEncrypt::HmacBased,
-            "public-key" => JWTEncrypt::PublicKey,
-            _ => {
-                return Err(de::Error::custom(
-                    "Invalid encryption method, only 'hmac-based' and 'public-key' are supported.",
-                ))
-            }
-        };
+        let enc =
+            match enc.as_str() {
+                "hmac-based" => JWTEncrypt::HmacBased,
+                "public-key" => JWTEncrypt::PublicKey,
+                "jwks" => JWTEncrypt::Jwks,
+                _ => return Err(de::Error::custom(
+                    "Invalid encryption method, only 'hmac-based', 'public-key' and 'jwks' are supported.",
+                )),
+            };
         Ok(enc)
     }
 
@@ -258,6 +288,62 @@ pub(crate) enum JWTFrom {
 pub(crate) enum JWTEncrypt {
     HmacBased,
     PublicKey,
+    Jwks,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct JwksConfig {
+    ///Key sets of the trusted issuers
+    #[serde(default)]
+    pub sources: Vec<JwksSource>,
+    ///Interval for reloading all key sets
+    #[serde(default = "JwksConfig::refresh_interval_default", deserialize_with = "deserialize_duration")]
+    pub refresh_interval: Duration,
+    ///Minimum interval between reloads of a key set triggered by an unknown 'kid'
+    #[serde(default = "JwksConfig::min_refresh_interval_default", deserialize_with = "deserialize_duration")]
+    pub min_refresh_interval: Duration,
+    ///Timeout for fetching a key set from a URL
+    #[serde(default = "JwksConfig::timeout_default", deserialize_with = "deserialize_duration")]
+    pub timeout: Duration,
+}
+
+impl Default for JwksConfig {
+    fn default() -> Self {
+        Self {
+            sources: Vec::new(),
+            refresh_interval: Self::refresh_interval_default(),
+            min_refresh_interval: Self::min_refresh_interval_default(),
+            timeout: Self::timeout_default(),
+        }
+    }
+}
+
+impl JwksConfig {
+    fn refresh_interval_default() -> Duration {
+        Duration::from_secs(300)
+    }
+
+    fn min_refresh_interval_default() -> Duration {
+        Duration::from_secs(30)
+    }
+
+    fn timeout_default() -> Duration {
+        Duration::from_secs(5)
+    }
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct JwksSource {
+    ///Tokens are only verified with this key set if their 'iss' claim matches,
+    ///if not set, the key set is used for tokens of any issuer
+    #[serde(default)]
+    pub issuer: Option<String>,
+    ///JWKS document URL, e.g. https://idp.example.com/.well-known/jwks.json
+    #[serde(default)]
+    pub url: Option<String>,
+    ///Local JWKS document file, used if url is not set
+    #[serde(default)]
+    pub file: Option<String>,
 }
 
 type HasPlaceholderUsername = bool;
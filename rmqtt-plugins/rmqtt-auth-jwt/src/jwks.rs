@@ -0,0 +1,304 @@
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use jsonwebtoken::jwk::JwkSet;
+use jsonwebtoken::DecodingKey;
+use tokio::sync::{Mutex, RwLock};
+
+use rmqtt::base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use rmqtt::{ahash, anyhow::anyhow, log, reqwest, serde_json, tokio};
+use rmqtt::{MqttError, Result};
+
+use crate::config::{JwksConfig, JwksSource};
+
+type HashMap<K, V> = std::collections::HashMap<K, V, ahash::RandomState>;
+
+///JSON Web Key Sets of the trusted issuers, the decoding key of a token is selected
+///by its 'iss' claim and 'kid' header.
+pub(crate) struct Jwks {
+    sources: Vec<KeySet>,
+    min_refresh_interval: Duration,
+    client: reqwest::Client,
+}
+
+impl Jwks {
+    pub(crate) fn new(cfg: &JwksConfig) -> Result<Self> {
+        if cfg.sources.is_empty() {
+            return Err(MqttError::from("jwks.sources is empty"));
+        }
+        let sources = cfg
+            .sources
+            .iter()
+            .map(|source| {
+                if source.url.is_none() && source.file.is_none() {
+                    Err(MqttError::from("jwks source requires a 'url' or 'file'"))
+                } else {
+                    Ok(KeySet::new(source.clone()))
+                }
+            })
+            .collect::<Result<Vec<_>>>()?;
+        let client = reqwest::Client::builder()
+            .connect_timeout(cfg.timeout)
+            .timeout(cfg.timeout)
+            .build()
+            .map_err(|e| MqttError::from(anyhow!(e)))?;
+        Ok(Self { sources, min_refresh_interval: cfg.min_refresh_interval, client })
+    }
+
+    ///Reload all key sets, keys of a source that fails to load are kept
+    pub(crate) async fn refresh(&self) {
+        for source in &self.sources {
+            if let Err(e) = source.refresh(&self.client).await {
+                log::warn!("refresh jwks failed, {:?}, {}", source.source, e);
+            }
+        }
+    }
+
+    ///Decoding key for the token and the issuer of the key set it was found in.
+    ///If the 'kid' is unknown, the key set is reloaded, at most once per min_refresh_interval.
+    pub(crate) async fn key(
+        &self,
+        token: &str,
+        kid: Option<&str>,
+    ) -> Result<(Arc<DecodingKey>, Option<String>)> {
+        let iss = issuer(token);
+        let sources = self
+            .sources
+            .iter()
+            .filter(|s| s.source.issuer.is_none() || s.source.issuer.as_deref() == iss.as_deref())
+            .collect::<Vec<_>>();
+        if sources.is_empty() {
+            return Err(MqttError::from(format!("untrusted issuer: {:?}", iss)));
+        }
+
+        for source in sources.iter() {
+            if let Some(key) = source.get(kid).await {
+                return Ok((key, source.source.issuer.clone()));
+            }
+        }
+
+        for source in sources {
+            if source.try_refresh(&self.client, self.min_refresh_interval).await {
+                if let Some(key) = source.get(kid).await {
+                    return Ok((key, source.source.issuer.clone()));
+                }
+            }
+        }
+        Err(MqttError::from(format!("no matching key in jwks, kid: {:?}, issuer: {:?}", kid, iss)))
+    }
+}
+
+struct KeySet {
+    source: JwksSource,
+    keys: RwLock<Keys>,
+    //The last time a reload was attempted, None means never loaded
+    last_refresh: Mutex<Option<Instant>>,
+}
+
+#[derive(Default)]
+struct Keys {
+    by_kid: HashMap<String, Arc<DecodingKey>>,
+    //Keys without 'kid', only used when the token has no 'kid' header
+    anonymous: Vec<Arc<DecodingKey>>,
+}
+
+impl KeySet {
+    fn new(source: JwksSource) -> Self {
+        Self { source, keys: RwLock::new(Keys::default()), last_refresh: Mutex::new(None) }
+    }
+
+    async fn get(&self, kid: Option<&str>) -> Option<Arc<DecodingKey>> {
+        let keys = self.keys.read().await;
+        match kid {
+            Some(kid) => keys.by_kid.get(kid).cloned(),
+            None if keys.by_kid.len() + keys.anonymous.len() == 1 => {
+                keys.anonymous.first().or_else(|| keys.by_kid.values().next()).cloned()
+            }
+            None => None,
+        }
+    }
+
+    async fn try_refresh(&self, client: &reqwest::Client, min_refresh_interval: Duration) -> bool {
+        let mut last_refresh = self.last_refresh.lock().await;
+        if last_refresh.map(|t| t.elapsed() < min_refresh_interval).unwrap_or_default() {
+            return false;
+        }
+        *last_refresh = Some(Instant::now());
+        match self.load(client).await {
+            Ok(keys) => {
+                *self.keys.write().await = keys;
+                true
+            }
+            Err(e) => {
+                log::warn!("refresh jwks failed, {:?}, {}", self.source, e);
+                false
+            }
+        }
+    }
+
+    async fn refresh(&self, client: &reqwest::Client) -> Result<()> {
+        *self.last_refresh.lock().await = Some(Instant::now());
+        let keys = self.load(client).await?;
+        *self.keys.write().await = keys;
+        Ok(())
+    }
+
+    async fn load(&self, client: &reqwest::Client) -> Result<Keys> {
+        let data = if let Some(url) = self.source.url.as_ref() {
+            client
+                .get(url)
+                .send()
+                .await
+                .and_then(|resp| resp.error_for_status())
+                .map_err(|e| MqttError::from(anyhow!(e)))?
+                .bytes()
+                .await
+                .map_err(|e| MqttError::from(anyhow!(e)))?
+                .to_vec()
+        } else if let Some(file) = self.source.file.as_ref() {
+            tokio::fs::read(file).await?
+        } else {
+            return Err(MqttError::from("jwks source requires a 'url' or 'file'"));
+        };
+        let jwks: JwkSet = serde_json::from_slice(&data)?;
+
+        let mut keys = Keys::default();
+        for jwk in jwks.keys.iter() {
+            let key = match DecodingKey::from_jwk(jwk) {
+                Ok(key) => Arc::new(key),
+                Err(e) => {
+                    log::warn!("unsupported jwk, kid: {:?}, {}", jwk.common.key_id, e);
+                    continue;
+                }
+            };
+            match jwk.common.key_id.as_ref() {
+                Some(kid) => {
+                    keys.by_kid.insert(kid.clone(), key);
+                }
+                None => keys.anonymous.push(key),
+            }
+        }
+        log::debug!("jwks loaded, {:?}, kids: {:?}", self.source, keys.by_kid.keys().collect::<Vec<_>>());
+        Ok(keys)
+    }
+}
+
+//The 'iss' claim of the token, read before the signature is verified so that the key set can be selected
+fn issuer(token: &str) -> Option<String> {
+    let payload = token.split('.').nth(1)?;
+    let payload = URL_SAFE_NO_PAD.decode(payload).ok()?;
+    let claims: serde_json::Value = serde_json::from_slice(&payload).ok()?;
+    claims.get("iss").and_then(|iss| iss.as_str()).map(String::from)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use jsonwebtoken::{encode, Algorithm, EncodingKey, Header};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+
+    //Local HTTP stand-in for the identity provider, serves the key sets in order, one per request
+    async fn serve(jwks: Vec<String>) -> (String, Arc<AtomicUsize>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/jwks.json", listener.local_addr().unwrap());
+        let requests = Arc::new(AtomicUsize::new(0));
+        let counter = requests.clone();
+        tokio::spawn(async move {
+            while let Ok((mut stream, _)) = listener.accept().await {
+                let mut buf = [0u8; 1024];
+                let _ = stream.read(&mut buf).await;
+                let n = counter.fetch_add(1, Ordering::SeqCst);
+                let body = &jwks[n.min(jwks.len() - 1)];
+                let resp = format!(
+                    "HTTP/1.1 200 OK\r\ncontent-type: application/json\r\ncontent-length: {}\r\nconnection: close\r\n\r\n{}",
+                    body.len(),
+                    body
+                );
+                let _ = stream.write_all(resp.as_bytes()).await;
+            }
+        });
+        (url, requests)
+    }
+
+    fn jwk_set(keys: &[(&str, &[u8])]) -> String {
+        let keys = keys
+            .iter()
+            .map(|(kid, secret)| {
+                serde_json::json!({"kty": "oct", "kid": kid, "alg": "HS256", "k": URL_SAFE_NO_PAD.encode(secret)})
+            })
+            .collect::<Vec<_>>();
+        serde_json::json!({ "keys": keys }).to_string()
+    }
+
+    fn token(kid: &str, secret: &[u8], iss: &str) -> String {
+        let mut header = Header::new(Algorithm::HS256);
+        header.kid = Some(kid.into());
+        encode(&header, &serde_json::json!({"iss": iss, "sub": "user1"}), &EncodingKey::from_secret(secret))
+            .unwrap()
+    }
+
+    fn config(sources: Vec<JwksSource>) -> JwksConfig {
+        JwksConfig { sources, min_refresh_interval: Duration::ZERO, ..Default::default() }
+    }
+
+    #[test]
+    fn jwks_key_rotation() {
+        let rt = tokio::runtime::Builder::new_current_thread().enable_all().build().unwrap();
+        rt.block_on(async {
+            let (url, requests) = serve(vec![
+                jwk_set(&[("k1", b"secret1")]),
+                jwk_set(&[("k1", b"secret1"), ("k2", b"secret2")]),
+            ])
+            .await;
+            let jwks = Jwks::new(&config(vec![JwksSource {
+                issuer: Some("https://idp1".into()),
+                url: Some(url),
+                file: None,
+            }]))
+            .unwrap();
+
+            let t1 = token("k1", b"secret1", "https://idp1");
+            assert!(jwks.key(&t1, Some("k1")).await.is_ok());
+            assert_eq!(requests.load(Ordering::SeqCst), 1);
+
+            //Unknown kid triggers a reload
+            let t2 = token("k2", b"secret2", "https://idp1");
+            let (key, iss) = jwks.key(&t2, Some("k2")).await.unwrap();
+            assert_eq!(iss.as_deref(), Some("https://idp1"));
+            assert_eq!(requests.load(Ordering::SeqCst), 2);
+            let mut validation = jsonwebtoken::Validation::new(Algorithm::HS256);
+            validation.required_spec_claims.clear();
+            validation.validate_exp = false;
+            assert!(jsonwebtoken::decode::<serde_json::Value>(&t2, &key, &validation).is_ok());
+
+            //Untrusted issuer
+            let t3 = token("k1", b"secret1", "https://idp2");
+            assert!(jwks.key(&t3, Some("k1")).await.is_err());
+        });
+    }
+
+    #[test]
+    fn jwks_multiple_issuers() {
+        let rt = tokio::runtime::Builder::new_current_thread().enable_all().build().unwrap();
+        rt.block_on(async {
+            let (url1, _) = serve(vec![jwk_set(&[("a", b"secret-a")])]).await;
+            let (url2, _) = serve(vec![jwk_set(&[("a", b"secret-b")])]).await;
+            let jwks = Jwks::new(&config(vec![
+                JwksSource { issuer: Some("https://idp1".into()), url: Some(url1), file: None },
+                JwksSource { issuer: Some("https://idp2".into()), url: Some(url2), file: None },
+            ]))
+            .unwrap();
+            jwks.refresh().await;
+
+            let t = token("a", b"secret-b", "https://idp2");
+            let (key, iss) = jwks.key(&t, Some("a")).await.unwrap();
+            assert_eq!(iss.as_deref(), Some("https://idp2"));
+            let mut validation = jsonwebtoken::Validation::new(Algorithm::HS256);
+            validation.required_spec_claims.clear();
+            validation.validate_exp = false;
+            assert!(jsonwebtoken::decode::<serde_json::Value>(&t, &key, &validation).is_ok());
+        });
+    }
+}
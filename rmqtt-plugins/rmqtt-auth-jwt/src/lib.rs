@@ -11,24 +11,32 @@ use std::sync::Arc;
 use std::time::Duration;
 
 use async_trait::async_trait;
-use jsonwebtoken::{decode, TokenData, Validation};
+use jsonwebtoken::{decode, DecodingKey, TokenData, Validation};
 use tokio::sync::RwLock;
 
 use rmqtt::{ahash, anyhow::anyhow, async_trait, itoa::Buffer, log, serde_json, tokio};
 use rmqtt::{
     broker::hook::{Handler, HookResult, Parameter, Register, ReturnType, Type},
-    broker::types::{AuthResult, PublishAclResult},
+    broker::types::{AuthResult, PublishAclResult, SubscribeAckReason, SubscribeAclResult},
+    grpc::{
+        collect_data, decode_data, encode_data, Message as GrpcMessage, MessageReply as GrpcMessageReply,
+        MessageType,
+    },
     plugin::{PackageInfo, Plugin},
     register,
     settings::acl::{
         AuthInfo, Rule, PLACEHOLDER_CLIENTID, PLACEHOLDER_IPADDR, PLACEHOLDER_PROTOCOL, PLACEHOLDER_USERNAME,
     },
+    settings::listener::PublishLimit,
     ConnectInfo, Message, MqttError, Reason, Result, Runtime,
 };
 
 use crate::config::{JWTFrom, PluginConfig, ValidateClaims};
+use crate::revocation::{Command, Message as RevocationMessage, Revocation, Revocations};
 
 mod config;
+mod jwks;
+mod revocation;
 
 type HashMap<K, V> = std::collections::HashMap<K, V, ahash::RandomState>;
 
@@ -39,6 +47,9 @@ struct AuthJwtPlugin {
     runtime: &'static Runtime,
     register: Box<dyn Register>,
     cfg: Arc<RwLock<PluginConfig>>,
+    revocations: Arc<Revocations>,
+    jwks_refresher: Option<tokio::task::JoinHandle<()>>,
+    revocations_cleaner: Option<tokio::task::JoinHandle<()>>,
 }
 
 impl AuthJwtPlugin {
@@ -50,7 +61,24 @@ impl AuthJwtPlugin {
         let cfg = Arc::new(RwLock::new(cfg));
         log::info!("{} AuthJwtPlugin cfg: {:?}", name, cfg.read().await);
         let register = runtime.extends.hook_mgr().await.register();
-        Ok(Self { runtime, register, cfg })
+        let revocations = Arc::new(Revocations::default());
+        Ok(Self { runtime, register, cfg, revocations, jwks_refresher: None, revocations_cleaner: None })
+    }
+
+    //Takes the revocations of the other nodes, this node may have been restarted with an empty list
+    async fn sync_from_cluster(&self) -> Result<()> {
+        let message_type = self.cfg.read().await.message_type;
+        let data = encode_data(&RevocationMessage::GetRevocations)?;
+        for (id, data) in collect_data(message_type, data, Duration::from_secs(5)).await {
+            match decode_data::<Vec<Revocation>>(&data) {
+                Ok(revocations) => {
+                    let added = self.revocations.merge(revocations);
+                    log::info!("{} take {} revocations from node({})", self.name(), added, id);
+                }
+                Err(e) => log::warn!("get revocations from node({}) error, {:?}", id, e),
+            }
+        }
+        Ok(())
     }
 }
 
@@ -60,16 +88,28 @@ impl Plugin for AuthJwtPlugin {
     async fn init(&mut self) -> Result<()> {
         log::info!("{} init", self.name());
         let cfg = &self.cfg;
+        let revs = &self.revocations;
 
         let priority = cfg.read().await.priority;
-        self.register.add_priority(Type::ClientAuthenticate, priority, Box::new(AuthHandler::new(cfg))).await;
         self.register
-            .add_priority(Type::ClientSubscribeCheckAcl, priority, Box::new(AuthHandler::new(cfg)))
+            .add_priority(Type::ClientAuthenticate, priority, Box::new(AuthHandler::new(cfg, revs)))
+            .await;
+        self.register
+            .add_priority(Type::ClientSubscribeCheckAcl, priority, Box::new(AuthHandler::new(cfg, revs)))
             .await;
         self.register
-            .add_priority(Type::MessagePublishCheckAcl, priority, Box::new(AuthHandler::new(cfg)))
+            .add_priority(Type::MessagePublishCheckAcl, priority, Box::new(AuthHandler::new(cfg, revs)))
+            .await;
+        self.register.add(Type::ClientConnected, Box::new(AuthHandler::new(cfg, revs))).await;
+        self.register.add(Type::ClientKeepalive, Box::new(AuthHandler::new(cfg, revs))).await;
+        self.register.add(Type::SessionTerminated, Box::new(AuthHandler::new(cfg, revs))).await;
+        let message_type = cfg.read().await.message_type;
+        self.register
+            .add(
+                Type::GrpcMessageReceived,
+                Box::new(RevocationSyncHandler { revocations: revs.clone(), message_type }),
+            )
             .await;
-        self.register.add(Type::ClientKeepalive, Box::new(AuthHandler::new(cfg))).await;
         Ok(())
     }
 
@@ -80,7 +120,8 @@ impl Plugin for AuthJwtPlugin {
 
     #[inline]
     async fn load_config(&mut self) -> Result<()> {
-        let new_cfg = self.runtime.settings.plugins.load_config::<PluginConfig>(self.name())?;
+        let mut new_cfg = self.runtime.settings.plugins.load_config::<PluginConfig>(self.name())?;
+        new_cfg.init_decoding_key()?;
         *self.cfg.write().await = new_cfg;
         log::debug!("load_config ok,  {:?}", self.cfg);
         Ok(())
@@ -90,6 +131,32 @@ impl Plugin for AuthJwtPlugin {
     async fn start(&mut self) -> Result<()> {
         log::info!("{} start", self.name());
         self.register.start().await;
+        if let Err(e) = self.sync_from_cluster().await {
+            log::warn!("{} sync revocations from cluster error, {:?}", self.name(), e);
+        }
+        let cfg = self.cfg.clone();
+        self.jwks_refresher = Some(tokio::spawn(async move {
+            loop {
+                let (jwks_keys, refresh_interval) = {
+                    let cfg = cfg.read().await;
+                    (cfg.jwks_keys.clone(), cfg.jwks.refresh_interval)
+                };
+                if let Some(jwks_keys) = jwks_keys {
+                    jwks_keys.refresh().await;
+                }
+                tokio::time::sleep(refresh_interval.max(Duration::from_secs(1))).await;
+            }
+        }));
+        let revocations = self.revocations.clone();
+        self.revocations_cleaner = Some(tokio::spawn(async move {
+            loop {
+                tokio::time::sleep(Duration::from_secs(60)).await;
+                let removeds = revocations.remove_expireds();
+                if removeds > 0 {
+                    log::debug!("remove expired revocations: {}", removeds);
+                }
+            }
+        }));
         Ok(())
     }
 
@@ -97,6 +164,12 @@ impl Plugin for AuthJwtPlugin {
     async fn stop(&mut self) -> Result<bool> {
         log::info!("{} stop", self.name());
         self.register.stop().await;
+        if let Some(jwks_refresher) = self.jwks_refresher.take() {
+            jwks_refresher.abort();
+        }
+        if let Some(revocations_cleaner) = self.revocations_cleaner.take() {
+            revocations_cleaner.abort();
+        }
         Ok(true)
     }
 
@@ -104,15 +177,52 @@ impl Plugin for AuthJwtPlugin {
     async fn attrs(&self) -> serde_json::Value {
         serde_json::json!({})
     }
+
+    ///Revocation list management, see revocation::Command
+    #[inline]
+    async fn send(&self, msg: serde_json::Value) -> Result<serde_json::Value> {
+        let cmd: Command = serde_json::from_value(msg)?;
+        let disconnect = self.cfg.read().await.disconnect_if_revoked;
+        self.revocations.handle(cmd, disconnect).await
+    }
+}
+
+struct RevocationSyncHandler {
+    revocations: Arc<Revocations>,
+    message_type: MessageType,
+}
+
+impl RevocationSyncHandler {
+    fn handle(&self, data: &[u8]) -> Result<GrpcMessageReply> {
+        match decode_data::<RevocationMessage>(data)? {
+            RevocationMessage::GetRevocations => {
+                Ok(GrpcMessageReply::Data(encode_data(&self.revocations.list())?))
+            }
+        }
+    }
+}
+
+#[async_trait]
+impl Handler for RevocationSyncHandler {
+    async fn hook(&self, param: &Parameter, acc: Option<HookResult>) -> ReturnType {
+        if let Parameter::GrpcMessageReceived(typ, GrpcMessage::Data(data)) = param {
+            if self.message_type == *typ {
+                let reply = self.handle(data).unwrap_or_else(|e| GrpcMessageReply::Error(e.to_string()));
+                return (false, Some(HookResult::GrpcMessageReply(Ok(reply))));
+            }
+        }
+        (true, acc)
+    }
 }
 
 struct AuthHandler {
     cfg: Arc<RwLock<PluginConfig>>,
+    revocations: Arc<Revocations>,
 }
 
 impl AuthHandler {
-    fn new(cfg: &Arc<RwLock<PluginConfig>>) -> Self {
-        Self { cfg: cfg.clone() }
+    fn new(cfg: &Arc<RwLock<PluginConfig>>, revocations: &Arc<Revocations>) -> Self {
+        Self { cfg: cfg.clone(), revocations: revocations.clone() }
     }
 
     #[inline]
@@ -211,6 +321,20 @@ impl AuthHandler {
 
         let header = jsonwebtoken::decode_header(token).map_err(|e| anyhow!(e))?;
         log::debug!("header: {:?}", header);
+
+        //With JWKS, the key is selected by the token's 'kid' header and 'iss' claim
+        let jwks_keys = self.cfg.read().await.jwks_keys.clone();
+        let jwk_key = if let Some(jwks_keys) = jwks_keys {
+            let (key, issuer) = jwks_keys.key(token, header.kid.as_deref()).await?;
+            if let (None, Some(issuer)) = (iss.as_ref(), issuer) {
+                iss = Some(HashSet::from([issuer]));
+                required_spec_claims.insert("iss".into());
+            }
+            Some(key)
+        } else {
+            None
+        };
+
         let mut validation = Validation::new(header.alg);
         validation.validate_exp = validate_exp;
         validation.validate_nbf = validate_nbf;
@@ -222,16 +346,41 @@ impl AuthHandler {
 
         log::debug!("validation: {:?}", validation);
 
-        let token_data = decode::<HashMap<String, serde_json::Value>>(
-            token,
-            &self.cfg.read().await.decoded_key,
-            &validation,
-        )
+        let token_data = if let Some(key) = jwk_key {
+            decode::<HashMap<String, serde_json::Value>>(token, &key, &validation)
+        } else {
+            decode::<HashMap<String, serde_json::Value>>(
+                token,
+                &self.cfg.read().await.decoded_key,
+                &validation,
+            )
+        }
         .map_err(|e| anyhow!(e))?;
 
         Ok(token_data)
     }
 
+    //The claims of a token that has already been verified by standard_auth
+    #[inline]
+    fn claims(token: &str) -> Result<TokenData<HashMap<String, serde_json::Value>>> {
+        let header = jsonwebtoken::decode_header(token).map_err(|e| anyhow!(e))?;
+        let mut validation = Validation::new(header.alg);
+        validation.insecure_disable_signature_validation();
+        validation.validate_exp = false;
+        validation.validate_aud = false;
+        validation.required_spec_claims.clear();
+        Ok(decode::<HashMap<String, serde_json::Value>>(token, &DecodingKey::from_secret(&[]), &validation)
+            .map_err(|e| anyhow!(e))?)
+    }
+
+    #[inline]
+    fn jti_sub(
+        token_data: &TokenData<HashMap<String, serde_json::Value>>,
+    ) -> (Option<String>, Option<String>) {
+        let claim = |name: &str| token_data.claims.get(name).and_then(|v| v.as_str()).map(String::from);
+        (claim("jti"), claim("sub"))
+    }
+
     #[inline]
     fn extended_auth(
         &self,
@@ -312,6 +461,12 @@ impl Handler for AuthHandler {
                 log::debug!("token_data header: {:?}", token_data.header);
                 log::debug!("token_data claims: {:?}", token_data.claims);
 
+                let (jti, sub) = Self::jti_sub(&token_data);
+                if self.revocations.is_revoked(jti.as_deref(), sub.as_deref()) {
+                    log::warn!("{} JWT revoked, jti: {:?}, sub: {:?}", connect_info.id(), jti, sub);
+                    return (false, Some(HookResult::AuthResult(AuthResult::NotAuthorized)));
+                }
+
                 let superuser =
                     token_data.claims.get("superuser").and_then(|v| v.as_bool()).unwrap_or_default();
 
@@ -333,7 +488,14 @@ impl Handler for AuthHandler {
                 log::debug!("rules: {:?}", rules);
                 let expire_at =
                     token_data.claims.get("exp").and_then(|exp| exp.as_u64().map(Duration::from_secs));
-                let auth_info = AuthInfo { superuser, expire_at, rules };
+                let publish_limit = match PublishLimit::from_fields(|k| token_data.claims.get(k)) {
+                    Ok(publish_limit) => publish_limit,
+                    Err(e) => {
+                        log::warn!("{} {}", connect_info.id(), e);
+                        return (false, Some(HookResult::AuthResult(AuthResult::NotAuthorized)));
+                    }
+                };
+                let auth_info = AuthInfo { superuser, expire_at, rules, publish_limit };
                 return (false, Some(HookResult::AuthResult(AuthResult::Allow(superuser, Some(auth_info)))));
             }
 
@@ -345,7 +507,17 @@ impl Handler for AuthHandler {
                     }
                 }
 
-                if let Some(auth_info) = &session.auth_info {
+                if self.revocations.is_session_revoked(session.id()) {
+                    log::warn!("{} JWT revoked, subscribe rejected", session.id());
+                    return (
+                        false,
+                        Some(HookResult::SubscribeAclResult(SubscribeAclResult::new_failure(
+                            SubscribeAckReason::NotAuthorized,
+                        ))),
+                    );
+                }
+
+                if let Some(auth_info) = session.auth_info() {
                     if let Some(acl_res) = auth_info.subscribe_acl(subscribe).await {
                         return acl_res;
                     }
@@ -359,7 +531,16 @@ impl Handler for AuthHandler {
                     return (false, acc);
                 }
 
-                if let Some(auth_info) = &session.auth_info {
+                if self.revocations.is_session_revoked(session.id()) {
+                    log::warn!("{} JWT revoked, publish rejected", session.id());
+                    let disconnect = self.cfg.read().await.disconnect_if_pub_rejected;
+                    return (
+                        false,
+                        Some(HookResult::PublishAclResult(PublishAclResult::Rejected(disconnect))),
+                    );
+                }
+
+                if let Some(auth_info) = session.auth_info() {
                     if let Some(acl_res) =
                         auth_info.publish_acl(publish, self.cfg.read().await.disconnect_if_pub_rejected).await
                     {
@@ -369,8 +550,28 @@ impl Handler for AuthHandler {
                 //If none of the rules match, continue executing the subsequent authentication chain.
             }
 
+            //The token is kept from here on, the handshake may still fail after the authentication
+            Parameter::ClientConnected(session) => {
+                let connect_info = match session.connect_info().await {
+                    Ok(connect_info) => connect_info,
+                    Err(e) => {
+                        log::debug!("{} {:?}", session.id(), e);
+                        return (true, acc);
+                    }
+                };
+                if let Some(token) = self.token(&connect_info).await {
+                    match Self::claims(token.as_ref()) {
+                        Ok(token_data) => {
+                            let (jti, sub) = Self::jti_sub(&token_data);
+                            self.revocations.add_token(session.id(), jti, sub);
+                        }
+                        Err(e) => log::debug!("{} {:?}", session.id(), e),
+                    }
+                }
+            }
+
             Parameter::ClientKeepalive(s, _) => {
-                if let Some(auth) = &s.auth_info {
+                if let Some(auth) = s.auth_info() {
                     log::debug!("Keepalive auth-jwt, is_expired: {:?}", auth.is_expired());
                     if auth.is_expired() && self.cfg.read().await.disconnect_if_expiry {
                         if let Some(tx) =
@@ -386,6 +587,10 @@ impl Handler for AuthHandler {
                 }
             }
 
+            Parameter::SessionTerminated(s, _) => {
+                self.revocations.remove_token(s.id());
+            }
+
             _ => {
                 log::error!("unimplemented, {:?}", param)
             }
@@ -0,0 +1,260 @@
+use rmqtt::{log, serde_json, timestamp_secs};
+use rmqtt::{ClientId, DashMap, Id, Message, Reason, Result, Runtime, Timestamp};
+
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum RevocationKind {
+    Jti,
+    Sub,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub(crate) struct Revocation {
+    pub kind: RevocationKind,
+    pub value: String,
+    //Unix timestamp in seconds, usually the 'exp' of the revoked token, the entry is dropped after it
+    #[serde(default)]
+    pub expire_at: Option<Timestamp>,
+}
+
+///Revocation management, the commands of the HTTP API /api/v1/jwt/revocations
+#[derive(Deserialize, Serialize, Debug)]
+#[serde(tag = "cmd", rename_all = "snake_case")]
+pub(crate) enum Command {
+    Revoke(Revocation),
+    Unrevoke { kind: RevocationKind, value: String },
+    List,
+}
+
+//Claims of the token a local session was authenticated with
+struct TokenClaims {
+    id: Id,
+    jti: Option<String>,
+    sub: Option<String>,
+}
+
+impl TokenClaims {
+    #[inline]
+    fn matches(&self, kind: RevocationKind, value: &str) -> bool {
+        match kind {
+            RevocationKind::Jti => self.jti.as_deref() == Some(value),
+            RevocationKind::Sub => self.sub.as_deref() == Some(value),
+        }
+    }
+}
+
+///Messages exchanged with the other nodes of the cluster
+#[derive(Deserialize, Serialize, Debug)]
+pub(crate) enum Message {
+    GetRevocations,
+}
+
+///Revoked tokens, keyed by 'jti' or 'sub', and the tokens of the sessions connected to this node
+#[derive(Default)]
+pub(crate) struct Revocations {
+    revoked: DashMap<(RevocationKind, String), Option<Timestamp>>,
+    tokens: DashMap<ClientId, TokenClaims>,
+}
+
+impl Revocations {
+    #[inline]
+    pub(crate) fn is_revoked(&self, jti: Option<&str>, sub: Option<&str>) -> bool {
+        if self.revoked.is_empty() {
+            return false;
+        }
+        let now = timestamp_secs();
+        let hit = |kind: RevocationKind, value: Option<&str>| {
+            value
+                .and_then(|v| self.revoked.get(&(kind, v.to_owned())).map(|exp| exp.map(|exp| exp > now)))
+                .map(|active| active.unwrap_or(true))
+                .unwrap_or_default()
+        };
+        hit(RevocationKind::Jti, jti) || hit(RevocationKind::Sub, sub)
+    }
+
+    ///Whether the token the session was authenticated with has been revoked
+    #[inline]
+    pub(crate) fn is_session_revoked(&self, id: &Id) -> bool {
+        if self.revoked.is_empty() {
+            return false;
+        }
+        self.tokens
+            .get(&id.client_id)
+            .map(|t| t.id == *id && self.is_revoked(t.jti.as_deref(), t.sub.as_deref()))
+            .unwrap_or_default()
+    }
+
+    #[inline]
+    pub(crate) fn add_token(&self, id: &Id, jti: Option<String>, sub: Option<String>) {
+        self.tokens.insert(id.client_id.clone(), TokenClaims { id: id.clone(), jti, sub });
+    }
+
+    #[inline]
+    pub(crate) fn remove_token(&self, id: &Id) {
+        self.tokens.remove_if(&id.client_id, |_, t| t.id == *id);
+    }
+
+    pub(crate) async fn handle(&self, cmd: Command, disconnect: bool) -> Result<serde_json::Value> {
+        match cmd {
+            Command::Revoke(r) => {
+                let kicks = self.revoke(r, disconnect).await;
+                Ok(serde_json::json!({ "kicks": kicks }))
+            }
+            Command::Unrevoke { kind, value } => {
+                let removed = self.revoked.remove(&(kind, value)).is_some();
+                Ok(serde_json::json!({ "removed": removed }))
+            }
+            Command::List => Ok(serde_json::to_value(self.list())?),
+        }
+    }
+
+    //Adds the revocation and disconnects the matching sessions of this node, returns the number of kicked sessions
+    async fn revoke(&self, r: Revocation, disconnect: bool) -> usize {
+        if !disconnect {
+            self.revoked.insert((r.kind, r.value), r.expire_at);
+            return 0;
+        }
+        let ids = self
+            .tokens
+            .iter()
+            .filter(|t| t.matches(r.kind, &r.value))
+            .map(|t| t.id.clone())
+            .collect::<Vec<_>>();
+        self.revoked.insert((r.kind, r.value), r.expire_at);
+
+        let mut kicks = 0;
+        for id in ids {
+            if let Some(tx) = Runtime::instance().extends.shared().await.entry(id.clone()).tx() {
+                if let Err(e) =
+                    tx.unbounded_send(Message::Closed(Reason::ConnectDisconnect(Some("JWT revoked".into()))))
+                {
+                    log::warn!("{} {}", id, e);
+                } else {
+                    kicks += 1;
+                }
+            }
+        }
+        kicks
+    }
+
+    ///Adds the revocations taken from the other nodes, the entries of this node are kept
+    pub(crate) fn merge(&self, revocations: Vec<Revocation>) -> usize {
+        let now = timestamp_secs();
+        let mut added = 0;
+        for r in revocations {
+            if r.expire_at.map(|exp| exp <= now).unwrap_or_default() {
+                continue;
+            }
+            self.revoked.entry((r.kind, r.value)).or_insert_with(|| {
+                added += 1;
+                r.expire_at
+            });
+        }
+        added
+    }
+
+    pub(crate) fn list(&self) -> Vec<Revocation> {
+        let now = timestamp_secs();
+        self.revoked
+            .iter()
+            .filter(|e| e.value().map(|exp| exp > now).unwrap_or(true))
+            .map(|e| Revocation { kind: e.key().0, value: e.key().1.clone(), expire_at: *e.value() })
+            .collect()
+    }
+
+    ///Drops the revocations whose token has expired, returns the number of dropped entries
+    pub(crate) fn remove_expireds(&self) -> usize {
+        let now = timestamp_secs();
+        let len = self.revoked.len();
+        self.revoked.retain(|_, exp| exp.map(|exp| exp > now).unwrap_or(true));
+        len - self.revoked.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rmqtt::tokio;
+
+    fn id(client_id: &str, remote_port: u16) -> Id {
+        Id::new(
+            1,
+            None,
+            Some(([127, 0, 0, 1], remote_port).into()),
+            ClientId::from(client_id.to_owned()),
+            None,
+        )
+    }
+
+    fn revocation(kind: RevocationKind, value: &str, expire_at: Option<Timestamp>) -> Revocation {
+        Revocation { kind, value: value.into(), expire_at }
+    }
+
+    #[tokio::test(crate = "rmqtt::tokio")]
+    async fn is_revoked() {
+        let revs = Revocations::default();
+        assert!(!revs.is_revoked(Some("t1"), Some("user1")));
+
+        let now = timestamp_secs();
+        revs.revoke(revocation(RevocationKind::Jti, "t1", None), false).await;
+        revs.revoke(revocation(RevocationKind::Sub, "user2", Some(now + 60)), false).await;
+        revs.revoke(revocation(RevocationKind::Sub, "user3", Some(now - 1)), false).await;
+        assert!(revs.is_revoked(Some("t1"), None));
+        assert!(revs.is_revoked(Some("t1"), Some("user1")));
+        assert!(revs.is_revoked(None, Some("user2")));
+        //the kinds are not mixed
+        assert!(!revs.is_revoked(Some("user2"), None));
+        assert!(!revs.is_revoked(None, Some("t1")));
+        //the token has expired
+        assert!(!revs.is_revoked(None, Some("user3")));
+        assert!(!revs.is_revoked(None, None));
+
+        assert_eq!(revs.remove_expireds(), 1);
+        assert_eq!(revs.list().len(), 2);
+        revs.handle(Command::Unrevoke { kind: RevocationKind::Jti, value: "t1".into() }, false)
+            .await
+            .unwrap();
+        assert!(!revs.is_revoked(Some("t1"), None));
+    }
+
+    #[tokio::test(crate = "rmqtt::tokio")]
+    async fn is_session_revoked() {
+        let revs = Revocations::default();
+        let s1 = id("c1", 1000);
+        revs.add_token(&s1, Some("t1".into()), Some("user1".into()));
+        revs.add_token(&id("c2", 1001), None, Some("user2".into()));
+        assert!(!revs.is_session_revoked(&s1));
+
+        revs.revoke(revocation(RevocationKind::Sub, "user1", None), false).await;
+        assert!(revs.is_session_revoked(&s1));
+        assert!(!revs.is_session_revoked(&id("c2", 1001)));
+        //a newer connection with the same client id, its token is not known yet
+        assert!(!revs.is_session_revoked(&id("c1", 1002)));
+
+        //the token of the previous connection is not removed by a newer one
+        revs.remove_token(&id("c1", 1002));
+        assert!(revs.is_session_revoked(&s1));
+        revs.remove_token(&s1);
+        assert!(!revs.is_session_revoked(&s1));
+    }
+
+    #[test]
+    fn merge() {
+        let revs = Revocations::default();
+        let now = timestamp_secs();
+        revs.merge(vec![revocation(RevocationKind::Jti, "t1", Some(now + 60))]);
+        let added = revs.merge(vec![
+            //the entry of this node is kept
+            revocation(RevocationKind::Jti, "t1", Some(now + 120)),
+            revocation(RevocationKind::Sub, "user1", None),
+            //expired entries are not taken
+            revocation(RevocationKind::Jti, "t2", Some(now)),
+        ]);
+        assert_eq!(added, 1);
+        assert!(revs.is_revoked(None, Some("user1")));
+        assert!(!revs.is_revoked(Some("t2"), None));
+        let mut list = revs.list().into_iter().map(|r| (r.value, r.expire_at)).collect::<Vec<_>>();
+        list.sort();
+        assert_eq!(list, [("t1".into(), Some(now + 60)), ("user1".into(), None)]);
+    }
+}
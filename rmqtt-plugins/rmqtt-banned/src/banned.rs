@@ -0,0 +1,609 @@
+use std::fmt;
+use std::net::IpAddr;
+use std::sync::RwLock;
+use std::time::Duration;
+
+use ipnet::IpNet;
+
+use rmqtt::{futures::StreamExt, log, timestamp_secs};
+use rmqtt::{DashMap, Id, Message, MqttError, Reason, Result, Runtime, Timestamp};
+use rmqtt_storage::DefaultStorageDB;
+
+const BANNED_PREFIX: &[u8] = b"b|";
+const UNBANNED_PREFIX: &[u8] = b"u|";
+
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum Kind {
+    ClientId,
+    Username,
+    IpAddr,
+}
+
+impl Kind {
+    #[inline]
+    fn as_str(&self) -> &'static str {
+        match self {
+            Kind::ClientId => "clientid",
+            Kind::Username => "username",
+            Kind::IpAddr => "ipaddr",
+        }
+    }
+}
+
+impl fmt::Display for Kind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub(crate) struct Banned {
+    pub kind: Kind,
+    //Client ID, username, IP address or CIDR range, such as 192.168.1.0/24
+    pub value: String,
+    #[serde(default)]
+    pub by: Option<String>,
+    #[serde(default)]
+    pub reason: Option<String>,
+    //Unix timestamp in seconds
+    #[serde(default = "timestamp_secs")]
+    pub at: Timestamp,
+    //Unix timestamp in seconds, None means the ban never expires
+    #[serde(default)]
+    pub until: Option<Timestamp>,
+}
+
+impl Banned {
+    #[inline]
+    pub(crate) fn is_expired(&self, now: Timestamp) -> bool {
+        self.until.map(|until| until <= now).unwrap_or_default()
+    }
+
+    #[inline]
+    fn matches(&self, id: &Id, net: Option<&IpNet>) -> bool {
+        match self.kind {
+            Kind::ClientId => &*id.client_id == self.value.as_str(),
+            Kind::Username => id.username.as_deref() == Some(self.value.as_str()),
+            Kind::IpAddr => match (net, id.remote_addr) {
+                (Some(net), Some(addr)) => net.contains(&addr.ip()),
+                _ => false,
+            },
+        }
+    }
+}
+
+///Tombstone of a removed entry, the entry is not taken back from a node that was down when it was removed
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Unbanned {
+    pub kind: Kind,
+    pub value: String,
+    //Unix timestamp in seconds
+    pub at: Timestamp,
+}
+
+///Entries and tombstones of a node, taken by the other nodes on start
+#[derive(Deserialize, Serialize, Debug, Default)]
+pub(crate) struct BannedSync {
+    pub banneds: Vec<Banned>,
+    pub unbanneds: Vec<Unbanned>,
+}
+
+//IP addresses are stored as single host networks
+#[inline]
+fn parse_net(value: &str) -> Result<IpNet> {
+    if let Ok(net) = value.parse::<IpNet>() {
+        Ok(net)
+    } else {
+        value
+            .parse::<IpAddr>()
+            .map(IpNet::from)
+            .map_err(|e| MqttError::from(format!("invalid ipaddr '{}', {}", value, e)))
+    }
+}
+
+///Banned clients, kept in memory and persisted in the storage db
+pub(crate) struct BannedList {
+    pub(crate) storage_db: DefaultStorageDB,
+    index: BannedIndex,
+    //Time of removal of the unbanned entries
+    unbanneds: DashMap<(Kind, String), Timestamp>,
+}
+
+impl BannedList {
+    #[inline]
+    pub(crate) async fn new(storage_db: DefaultStorageDB) -> Result<Self> {
+        let this = Self { storage_db, index: BannedIndex::default(), unbanneds: DashMap::default() };
+        this.restore().await?;
+        this.restore_unbanneds().await?;
+        log::info!("restore banned list, count: {}, unbanneds: {}", this.index.len(), this.unbanneds.len());
+        Ok(this)
+    }
+
+    async fn restore(&self) -> Result<()> {
+        let mut storage_db = self.storage_db.clone();
+        let mut keys = Vec::new();
+        {
+            let mut iter = storage_db.scan([BANNED_PREFIX, b"*"].concat()).await?;
+            while let Some(key) = iter.next().await {
+                match key {
+                    Ok(key) => keys.push(key),
+                    Err(e) => log::warn!("scan banned error, {:?}", e),
+                }
+            }
+        }
+        let now = timestamp_secs();
+        for key in keys {
+            match storage_db.get::<_, Banned>(key.as_slice()).await {
+                Ok(Some(b)) if !b.is_expired(now) => {
+                    if let Err(e) = self.index.insert(b) {
+                        log::warn!("restore banned error, {:?}", e);
+                    }
+                }
+                Ok(Some(_)) => {
+                    if let Err(e) = storage_db.remove(key.as_slice()).await {
+                        log::warn!("remove banned error, {:?}", e);
+                    }
+                }
+                Ok(None) => {}
+                Err(e) => {
+                    log::warn!("load banned error, {:?}, key: {:?}", e, String::from_utf8_lossy(&key));
+                    if let Err(e) = storage_db.remove(key.as_slice()).await {
+                        log::warn!("remove banned error, {:?}", e);
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    async fn restore_unbanneds(&self) -> Result<()> {
+        let mut storage_db = self.storage_db.clone();
+        let mut keys = Vec::new();
+        {
+            let mut iter = storage_db.scan([UNBANNED_PREFIX, b"*"].concat()).await?;
+            while let Some(key) = iter.next().await {
+                match key {
+                    Ok(key) => keys.push(key),
+                    Err(e) => log::warn!("scan unbanned error, {:?}", e),
+                }
+            }
+        }
+        for key in keys {
+            match storage_db.get::<_, Unbanned>(key.as_slice()).await {
+                Ok(Some(u)) => {
+                    self.unbanneds.insert((u.kind, u.value), u.at);
+                }
+                Ok(None) => {}
+                Err(e) => {
+                    log::warn!("load unbanned error, {:?}, key: {:?}", e, String::from_utf8_lossy(&key));
+                    if let Err(e) = storage_db.remove(key.as_slice()).await {
+                        log::warn!("remove unbanned error, {:?}", e);
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    ///The entry that bans the client, if any
+    #[inline]
+    pub(crate) fn check(&self, id: &Id) -> Option<Banned> {
+        self.index.check(id, timestamp_secs())
+    }
+
+    ///Adds or replaces the entry and disconnects the matching sessions of this node,
+    ///returns the number of disconnected sessions
+    pub(crate) async fn add(&self, b: Banned) -> Result<usize> {
+        self.store(b.clone()).await?;
+        Ok(self.kick(&b).await)
+    }
+
+    //The tombstone of an earlier removal is dropped
+    async fn store(&self, b: Banned) -> Result<()> {
+        if b.kind == Kind::IpAddr {
+            parse_net(&b.value)?;
+        }
+        self.storage_db.insert(make_stored_key(BANNED_PREFIX, b.kind, &b.value).as_slice(), &b).await?;
+        if self.unbanneds.remove(&(b.kind, b.value.clone())).is_some() {
+            self.storage_db.remove(make_stored_key(UNBANNED_PREFIX, b.kind, &b.value).as_slice()).await?;
+        }
+        self.index.insert(b)
+    }
+
+    //Disconnects the matching sessions of this node, returns the number of disconnected sessions
+    async fn kick(&self, b: &Banned) -> usize {
+        let net = if b.kind == Kind::IpAddr { parse_net(&b.value).ok() } else { None };
+        let txs = {
+            let shared = Runtime::instance().extends.shared().await;
+            shared
+                .iter()
+                .filter_map(|entry| {
+                    entry
+                        .session()
+                        .and_then(|s| if b.matches(&s.id, net.as_ref()) { entry.tx() } else { None })
+                })
+                .collect::<Vec<_>>()
+        };
+        let mut kicks = 0;
+        for tx in txs {
+            if let Err(e) =
+                tx.unbounded_send(Message::Closed(Reason::ConnectDisconnect(Some("Banned".into()))))
+            {
+                log::warn!("{}:{} {}", b.kind, b.value, e);
+            } else {
+                kicks += 1;
+            }
+        }
+        kicks
+    }
+
+    ///Takes the entries and the tombstones of another node, an entry is added if it is missing or
+    ///older on this node and it was not removed later. Returns the number of added and removed entries.
+    pub(crate) async fn merge(&self, sync: BannedSync) -> (usize, usize) {
+        let (addeds, removeds) = self._merge(sync).await;
+        for b in addeds.iter() {
+            self.kick(b).await;
+        }
+        (addeds.len(), removeds)
+    }
+
+    async fn _merge(&self, sync: BannedSync) -> (Vec<Banned>, usize) {
+        let mut removeds = 0;
+        for u in sync.unbanneds {
+            if self.unbanneds.get(&(u.kind, u.value.clone())).map(|at| *at >= u.at).unwrap_or_default() {
+                continue;
+            }
+            match self.unban(u.kind, &u.value, u.at).await {
+                Ok(Some(_)) => removeds += 1,
+                Ok(None) => {}
+                Err(e) => log::warn!("merge unbanned error, {:?}", e),
+            }
+        }
+
+        let now = timestamp_secs();
+        let mut addeds = Vec::new();
+        for b in sync.banneds {
+            let key = (b.kind, b.value.clone());
+            if b.is_expired(now)
+                || self.index.get(b.kind, &b.value).map(|old| old.at >= b.at).unwrap_or_default()
+                || self.unbanneds.get(&key).map(|at| *at >= b.at).unwrap_or_default()
+            {
+                continue;
+            }
+            match self.store(b.clone()).await {
+                Ok(()) => addeds.push(b),
+                Err(e) => log::warn!("merge banned error, {:?}", e),
+            }
+        }
+        (addeds, removeds)
+    }
+
+    ///Removes the entry and keeps a tombstone, an entry banned after 'at' is kept
+    pub(crate) async fn unban(&self, kind: Kind, value: &str, at: Timestamp) -> Result<Option<Banned>> {
+        if self.index.get(kind, value).map(|b| b.at > at).unwrap_or_default() {
+            return Ok(None);
+        }
+        let u = Unbanned { kind, value: value.to_owned(), at };
+        self.storage_db.insert(make_stored_key(UNBANNED_PREFIX, kind, value).as_slice(), &u).await?;
+        self.unbanneds.insert((kind, value.to_owned()), at);
+        self.remove(kind, value).await
+    }
+
+    async fn remove(&self, kind: Kind, value: &str) -> Result<Option<Banned>> {
+        self.storage_db.remove(make_stored_key(BANNED_PREFIX, kind, value).as_slice()).await?;
+        Ok(self.index.take(kind, value))
+    }
+
+    #[inline]
+    pub(crate) fn list(&self) -> Vec<Banned> {
+        self.index.list(timestamp_secs())
+    }
+
+    #[inline]
+    pub(crate) fn sync(&self) -> BannedSync {
+        let unbanneds = self
+            .unbanneds
+            .iter()
+            .map(|e| Unbanned { kind: e.key().0, value: e.key().1.clone(), at: *e.value() })
+            .collect();
+        BannedSync { banneds: self.list(), unbanneds }
+    }
+
+    #[inline]
+    pub(crate) fn len(&self) -> usize {
+        self.index.len()
+    }
+
+    ///Removes the expired entries and the tombstones older than the retention period
+    pub(crate) async fn cleanup(&self, unbanned_retention: Duration) {
+        let now = timestamp_secs();
+        for (kind, value) in self.index.expireds(now) {
+            log::debug!("banned expired, {}:{}", kind, value);
+            if let Err(e) = self.remove(kind, &value).await {
+                log::warn!("remove banned error, {:?}", e);
+            }
+        }
+
+        let retention = unbanned_retention.as_secs() as Timestamp;
+        let expireds = self
+            .unbanneds
+            .iter()
+            .filter(|e| *e.value() + retention <= now)
+            .map(|e| e.key().clone())
+            .collect::<Vec<_>>();
+        for (kind, value) in expireds {
+            self.unbanneds.remove(&(kind, value.clone()));
+            if let Err(e) =
+                self.storage_db.remove(make_stored_key(UNBANNED_PREFIX, kind, &value).as_slice()).await
+            {
+                log::warn!("remove unbanned error, {:?}", e);
+            }
+        }
+    }
+}
+
+//In-memory index of the banned list
+#[derive(Default)]
+struct BannedIndex {
+    entries: DashMap<(Kind, String), Banned>,
+    //Banned IP addresses and CIDR ranges
+    nets: RwLock<Vec<(IpNet, String)>>,
+}
+
+impl BannedIndex {
+    #[inline]
+    fn insert(&self, b: Banned) -> Result<()> {
+        if b.kind == Kind::IpAddr {
+            let net = parse_net(&b.value)?;
+            let mut nets = self.nets.write().map_err(|e| MqttError::from(e.to_string()))?;
+            nets.retain(|(_, v)| *v != b.value);
+            nets.push((net, b.value.clone()));
+        }
+        self.entries.insert((b.kind, b.value.clone()), b);
+        Ok(())
+    }
+
+    #[inline]
+    fn take(&self, kind: Kind, value: &str) -> Option<Banned> {
+        if kind == Kind::IpAddr {
+            if let Ok(mut nets) = self.nets.write() {
+                nets.retain(|(_, v)| v != value);
+            }
+        }
+        self.entries.remove(&(kind, value.to_owned())).map(|(_, b)| b)
+    }
+
+    #[inline]
+    fn get(&self, kind: Kind, value: &str) -> Option<Banned> {
+        self.entries.get(&(kind, value.to_owned())).map(|b| b.value().clone())
+    }
+
+    //The entry that bans the client, the expired entries are skipped
+    fn check(&self, id: &Id, now: Timestamp) -> Option<Banned> {
+        if self.entries.is_empty() {
+            return None;
+        }
+        let get = |kind: Kind, value: &str| self.get(kind, value).filter(|b| !b.is_expired(now));
+        if let Some(b) = get(Kind::ClientId, &id.client_id) {
+            return Some(b);
+        }
+        if let Some(b) = id.username.as_ref().and_then(|u| get(Kind::Username, u)) {
+            return Some(b);
+        }
+        let ip = id.remote_addr.map(|addr| addr.ip())?;
+        //The ranges may overlap, an expired range must not hide an active one
+        let values = self
+            .nets
+            .read()
+            .ok()?
+            .iter()
+            .filter(|(net, _)| net.contains(&ip))
+            .map(|(_, value)| value.clone())
+            .collect::<Vec<_>>();
+        values.into_iter().find_map(|value| get(Kind::IpAddr, &value))
+    }
+
+    #[inline]
+    fn list(&self, now: Timestamp) -> Vec<Banned> {
+        self.entries.iter().map(|b| b.value().clone()).filter(|b| !b.is_expired(now)).collect()
+    }
+
+    #[inline]
+    fn expireds(&self, now: Timestamp) -> Vec<(Kind, String)> {
+        self.entries.iter().filter(|b| b.value().is_expired(now)).map(|b| b.key().clone()).collect()
+    }
+
+    #[inline]
+    fn len(&self) -> usize {
+        self.entries.len()
+    }
+}
+
+#[inline]
+fn make_stored_key(prefix: &[u8], kind: Kind, value: &str) -> Vec<u8> {
+    [prefix, kind.as_str().as_bytes(), b"|", value.as_bytes()].concat()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rmqtt::{serde_json, tokio, ClientId};
+    use rmqtt_storage::{init_db, Config};
+
+    fn banned(kind: Kind, value: &str, until: Option<Timestamp>) -> Banned {
+        Banned { until, ..banned_at(kind, value, 100) }
+    }
+
+    fn banned_at(kind: Kind, value: &str, at: Timestamp) -> Banned {
+        Banned { kind, value: value.into(), by: None, reason: None, at, until: None }
+    }
+
+    async fn banned_list(name: &str) -> BannedList {
+        let dir = std::env::temp_dir().join(format!("rmqtt-banned-test-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        let cfg: Config =
+            serde_json::from_value(serde_json::json!({"type": "sled", "sled": {"path": dir}})).unwrap();
+        BannedList::new(init_db(&cfg).await.unwrap()).await.unwrap()
+    }
+
+    fn values(list: &BannedList) -> Vec<String> {
+        let mut values = list.list().into_iter().map(|b| b.value).collect::<Vec<_>>();
+        values.sort();
+        values
+    }
+
+    fn id(client_id: &str, username: Option<&str>, addr: &str) -> Id {
+        Id::new(
+            1,
+            None,
+            Some(addr.parse().unwrap()),
+            ClientId::from(client_id.to_owned()),
+            username.map(|u| u.to_owned().into()),
+        )
+    }
+
+    #[test]
+    fn check_clientid_username() {
+        let index = BannedIndex::default();
+        index.insert(banned(Kind::ClientId, "c1", None)).unwrap();
+        index.insert(banned(Kind::Username, "u1", Some(200))).unwrap();
+
+        assert_eq!(index.check(&id("c1", None, "10.0.0.1:1000"), 150).unwrap().value, "c1");
+        assert_eq!(index.check(&id("c2", Some("u1"), "10.0.0.1:1000"), 150).unwrap().value, "u1");
+        assert!(index.check(&id("c2", Some("u1"), "10.0.0.1:1000"), 200).is_none());
+        assert!(index.check(&id("c2", Some("u2"), "10.0.0.1:1000"), 150).is_none());
+    }
+
+    #[test]
+    fn check_ipaddr() {
+        let index = BannedIndex::default();
+        index.insert(banned(Kind::IpAddr, "10.0.0.1", None)).unwrap();
+        index.insert(banned(Kind::IpAddr, "2001:db8::/32", None)).unwrap();
+
+        assert_eq!(index.check(&id("c1", None, "10.0.0.1:1000"), 150).unwrap().value, "10.0.0.1");
+        assert!(index.check(&id("c1", None, "10.0.0.2:1000"), 150).is_none());
+        assert_eq!(index.check(&id("c1", None, "[2001:db8::1]:1000"), 150).unwrap().value, "2001:db8::/32");
+        assert!(index.check(&id("c1", None, "[2001:db9::1]:1000"), 150).is_none());
+        assert!(index.insert(banned(Kind::IpAddr, "10.0.0.300", None)).is_err());
+    }
+
+    #[test]
+    fn check_overlapping_cidrs() {
+        let index = BannedIndex::default();
+        //The broad range expires first and is checked first
+        index.insert(banned(Kind::IpAddr, "10.0.0.0/8", Some(200))).unwrap();
+        index.insert(banned(Kind::IpAddr, "10.1.0.0/16", Some(300))).unwrap();
+        index.insert(banned(Kind::IpAddr, "10.1.2.3", None)).unwrap();
+
+        let client = id("c1", None, "10.1.0.1:1000");
+        assert_eq!(index.check(&client, 150).unwrap().value, "10.0.0.0/8");
+        assert_eq!(index.check(&client, 250).unwrap().value, "10.1.0.0/16");
+        assert!(index.check(&client, 300).is_none());
+        assert_eq!(index.check(&id("c1", None, "10.1.2.3:1000"), 400).unwrap().value, "10.1.2.3");
+        assert!(index.check(&id("c1", None, "10.2.0.1:1000"), 250).is_none());
+
+        //Removing a range keeps the others
+        index.take(Kind::IpAddr, "10.0.0.0/8");
+        assert_eq!(index.check(&client, 150).unwrap().value, "10.1.0.0/16");
+    }
+
+    #[test]
+    fn expiry() {
+        let index = BannedIndex::default();
+        index.insert(banned(Kind::ClientId, "c1", Some(200))).unwrap();
+        index.insert(banned(Kind::ClientId, "c2", None)).unwrap();
+        index.insert(banned(Kind::IpAddr, "10.0.0.0/8", Some(200))).unwrap();
+
+        assert_eq!(index.list(150).len(), 3);
+        assert_eq!(index.list(200).len(), 1);
+        let mut expireds = index.expireds(200);
+        expireds.sort_by(|a, b| a.1.cmp(&b.1));
+        assert_eq!(
+            expireds,
+            vec![(Kind::IpAddr, "10.0.0.0/8".to_owned()), (Kind::ClientId, "c1".to_owned())]
+        );
+        for (kind, value) in expireds {
+            index.take(kind, &value);
+        }
+        assert_eq!(index.len(), 1);
+        assert!(index.check(&id("c1", None, "10.0.0.1:1000"), 150).is_none());
+    }
+
+    #[tokio::test(crate = "rmqtt::tokio")]
+    async fn unban_while_node_down() {
+        let node1 = banned_list("unban-node1").await;
+        let node2 = banned_list("unban-node2").await;
+        for list in [&node1, &node2] {
+            list.store(banned_at(Kind::ClientId, "c1", 100)).await.unwrap();
+            list.store(banned_at(Kind::ClientId, "c2", 100)).await.unwrap();
+        }
+
+        //node2 is down while c1 is unbanned and c3 is banned on node1
+        node1.unban(Kind::ClientId, "c1", 200).await.unwrap().unwrap();
+        node1.store(banned_at(Kind::ClientId, "c3", 150)).await.unwrap();
+
+        //node2 takes the removal on start
+        let (addeds, removeds) = node2._merge(node1.sync()).await;
+        assert_eq!(addeds.len(), 1);
+        assert_eq!(removeds, 1);
+        assert_eq!(values(&node2), vec!["c2", "c3"]);
+
+        //An older entry of a third node is not taken back
+        let stale = BannedSync { banneds: vec![banned_at(Kind::ClientId, "c1", 100)], unbanneds: vec![] };
+        let (addeds, _) = node2._merge(stale).await;
+        assert!(addeds.is_empty());
+        assert_eq!(values(&node2), vec!["c2", "c3"]);
+
+        //Tombstones are restored from the storage
+        let restored = BannedList::new(node2.storage_db.clone()).await.unwrap();
+        assert_eq!(
+            restored.sync().unbanneds,
+            vec![Unbanned { kind: Kind::ClientId, value: "c1".into(), at: 200 }]
+        );
+        assert_eq!(values(&restored), vec!["c2", "c3"]);
+    }
+
+    #[tokio::test(crate = "rmqtt::tokio")]
+    async fn ban_after_unban() {
+        let node1 = banned_list("reban-node1").await;
+        let node2 = banned_list("reban-node2").await;
+        node1.store(banned_at(Kind::ClientId, "c1", 100)).await.unwrap();
+        node1.unban(Kind::ClientId, "c1", 200).await.unwrap();
+
+        //A later ban drops the tombstone and wins over the older removal
+        node2.store(banned_at(Kind::ClientId, "c1", 300)).await.unwrap();
+        let (addeds, removeds) = node2._merge(node1.sync()).await;
+        assert!(addeds.is_empty());
+        assert_eq!(removeds, 0);
+        assert_eq!(values(&node2), vec!["c1"]);
+
+        let (addeds, removeds) = node1._merge(node2.sync()).await;
+        assert_eq!(addeds.len(), 1);
+        assert_eq!(removeds, 0);
+        assert_eq!(values(&node1), vec!["c1"]);
+        assert!(node1.sync().unbanneds.is_empty());
+
+        //An unban older than the entry keeps it
+        assert!(node1.unban(Kind::ClientId, "c1", 250).await.unwrap().is_none());
+        assert_eq!(values(&node1), vec!["c1"]);
+    }
+
+    #[tokio::test(crate = "rmqtt::tokio")]
+    async fn unbanned_retention() {
+        let list = banned_list("retention").await;
+        let now = timestamp_secs();
+        list.unban(Kind::Username, "u1", now - 10).await.unwrap();
+        list.unban(Kind::Username, "u2", now - 100).await.unwrap();
+
+        list.cleanup(Duration::from_secs(60)).await;
+        let unbanneds = list.sync().unbanneds;
+        assert_eq!(unbanneds.len(), 1);
+        assert_eq!(unbanneds[0].value, "u1");
+
+        list.cleanup(Duration::from_secs(5)).await;
+        assert!(list.sync().unbanneds.is_empty());
+        let restored = BannedList::new(list.storage_db.clone()).await.unwrap();
+        assert!(restored.sync().unbanneds.is_empty());
+    }
+}
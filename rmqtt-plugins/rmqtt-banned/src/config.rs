@@ -0,0 +1,50 @@
+use std::time::Duration;
+
+use rmqtt::serde_json;
+use rmqtt::{broker::hook::Priority, grpc::MessageType, settings::deserialize_duration};
+
+use rmqtt_storage::Config;
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct PluginConfig {
+    ///Hook priority
+    #[serde(default = "PluginConfig::priority_default")]
+    pub priority: Priority,
+
+    #[serde(default = "PluginConfig::message_type_default")]
+    pub message_type: MessageType,
+
+    #[serde(default = "PluginConfig::cleanup_interval_default", deserialize_with = "deserialize_duration")]
+    pub cleanup_interval: Duration,
+
+    ///How long the removed entries are remembered, an entry removed earlier may be taken back
+    ///from a node that was down for longer
+    #[serde(default = "PluginConfig::unbanned_retention_default", deserialize_with = "deserialize_duration")]
+    pub unbanned_retention: Duration,
+
+    #[serde(default)]
+    pub storage: Config,
+}
+
+impl PluginConfig {
+    #[inline]
+    pub fn to_json(&self) -> serde_json::Value {
+        serde_json::json!(self)
+    }
+
+    fn priority_default() -> Priority {
+        1000
+    }
+
+    fn message_type_default() -> MessageType {
+        97
+    }
+
+    fn cleanup_interval_default() -> Duration {
+        Duration::from_secs(60)
+    }
+
+    fn unbanned_retention_default() -> Duration {
+        Duration::from_secs(60 * 60 * 24 * 7)
+    }
+}
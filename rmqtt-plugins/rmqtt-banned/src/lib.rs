@@ -0,0 +1,292 @@
+#![deny(unsafe_code)]
+#[macro_use]
+extern crate serde;
+
+#[macro_use]
+extern crate rmqtt_macros;
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use rmqtt::{
+    anyhow::anyhow,
+    async_trait::async_trait,
+    log,
+    once_cell::sync::OnceCell,
+    serde_json::{self, json},
+    tokio,
+};
+use rmqtt::{
+    broker::hook::{Handler, HookResult, Parameter, Register, ReturnType, Type},
+    broker::types::{ConnectAckReason, ConnectAckReasonV3, ConnectAckReasonV5, MQTT_LEVEL_5},
+    grpc::{
+        broadcast_data, collect_data, decode_data, encode_data, Message as GrpcMessage,
+        MessageReply as GrpcMessageReply, MessageType,
+    },
+    plugin::{PackageInfo, Plugin},
+    register, timestamp_secs, MqttError, Result, Runtime, Timestamp,
+};
+
+use rmqtt_storage::{init_db, StorageType};
+
+use banned::{Banned, BannedList, BannedSync, Kind};
+use config::PluginConfig;
+
+mod banned;
+mod config;
+
+static BANNED_LIST: OnceCell<BannedList> = OnceCell::new();
+
+register!(BannedPlugin::new);
+
+///Banned list management, the commands of the HTTP API /api/v1/banned
+#[derive(Deserialize, Serialize, Debug)]
+#[serde(tag = "cmd", rename_all = "snake_case")]
+enum Command {
+    Ban(Banned),
+    Unban { kind: Kind, value: String },
+    List,
+}
+
+///Changes replicated to the other nodes of the cluster
+#[derive(Deserialize, Serialize, Debug)]
+enum Message {
+    Ban(Banned),
+    //'at' is the time of removal on the originating node, kept as a tombstone
+    Unban { kind: Kind, value: String, at: Timestamp },
+    //Gets the entries and the tombstones of the node
+    List,
+}
+
+#[derive(Plugin)]
+struct BannedPlugin {
+    register: Box<dyn Register>,
+    cfg: Arc<PluginConfig>,
+    banned_list: &'static BannedList,
+    cleaner: Option<tokio::task::JoinHandle<()>>,
+}
+
+impl BannedPlugin {
+    #[inline]
+    async fn new<S: Into<String>>(runtime: &'static Runtime, name: S) -> Result<Self> {
+        let name = name.into();
+        let mut cfg = runtime.settings.plugins.load_config_default::<PluginConfig>(&name)?;
+        match cfg.storage.typ {
+            StorageType::Sled => {
+                cfg.storage.sled.path =
+                    cfg.storage.sled.path.replace("{node}", &format!("{}", runtime.node.id()));
+            }
+            StorageType::Redis => {
+                cfg.storage.redis.prefix =
+                    cfg.storage.redis.prefix.replace("{node}", &format!("{}", runtime.node.id()));
+            }
+            StorageType::RedisCluster => {
+                cfg.storage.redis_cluster.prefix =
+                    cfg.storage.redis_cluster.prefix.replace("{node}", &format!("{}", runtime.node.id()));
+            }
+        }
+        log::info!("{} BannedPlugin cfg: {:?}", name, cfg);
+
+        let banned_list = if let Some(banned_list) = BANNED_LIST.get() {
+            banned_list
+        } else {
+            let storage_db = init_db(&cfg.storage).await?;
+            let banned_list = BannedList::new(storage_db).await?;
+            BANNED_LIST.set(banned_list).map_err(|_| anyhow!("init error!"))?;
+            BANNED_LIST.get().ok_or_else(|| anyhow!("init error!"))?
+        };
+        let register = runtime.extends.hook_mgr().await.register();
+        Ok(Self { register, cfg: Arc::new(cfg), banned_list, cleaner: None })
+    }
+
+    //Applies the change locally and replicates it to the other nodes
+    async fn apply(&self, msg: Message) -> Result<serde_json::Value> {
+        let reply = apply(self.banned_list, &msg).await?;
+        broadcast_data(self.cfg.message_type, encode_data(&msg)?, Duration::from_secs(10)).await;
+        Ok(reply)
+    }
+
+    async fn sync_from_cluster(&self) -> Result<()> {
+        let data = encode_data(&Message::List)?;
+        for (id, data) in collect_data(self.cfg.message_type, data, Duration::from_secs(5)).await {
+            match decode_data::<BannedSync>(&data) {
+                Ok(sync) => {
+                    let (added, removed) = self.banned_list.merge(sync).await;
+                    log::info!(
+                        "{} take {} banned entries and {} removals from node({})",
+                        self.name(),
+                        added,
+                        removed,
+                        id
+                    );
+                }
+                Err(e) => log::warn!("get banned list from node({}) error, {:?}", id, e),
+            }
+        }
+        Ok(())
+    }
+}
+
+async fn apply(banned_list: &BannedList, msg: &Message) -> Result<serde_json::Value> {
+    match msg {
+        Message::Ban(b) => {
+            let kicks = banned_list.add(b.clone()).await?;
+            log::info!(
+                "banned {}:{}, reason: {:?}, until: {:?}, kicks: {}",
+                b.kind,
+                b.value,
+                b.reason,
+                b.until,
+                kicks
+            );
+            Ok(json!({ "kicks": kicks }))
+        }
+        Message::Unban { kind, value, at } => {
+            let removed = banned_list.unban(*kind, value, *at).await?.is_some();
+            log::info!("unbanned {}:{}, removed: {}", kind, value, removed);
+            Ok(json!({ "removed": removed }))
+        }
+        Message::List => Ok(serde_json::to_value(banned_list.list())?),
+    }
+}
+
+#[async_trait]
+impl Plugin for BannedPlugin {
+    #[inline]
+    async fn init(&mut self) -> Result<()> {
+        log::info!("{} init", self.name());
+        self.register
+            .add_priority(
+                Type::ClientConnect,
+                self.cfg.priority,
+                Box::new(BannedHandler::new(self.banned_list, self.cfg.message_type)),
+            )
+            .await;
+        self.register
+            .add(
+                Type::GrpcMessageReceived,
+                Box::new(BannedHandler::new(self.banned_list, self.cfg.message_type)),
+            )
+            .await;
+        Ok(())
+    }
+
+    #[inline]
+    async fn get_config(&self) -> Result<serde_json::Value> {
+        Ok(self.cfg.to_json())
+    }
+
+    #[inline]
+    async fn start(&mut self) -> Result<()> {
+        log::info!("{} start", self.name());
+        self.register.start().await;
+        if let Err(e) = self.sync_from_cluster().await {
+            log::warn!("{} sync banned list from cluster error, {:?}", self.name(), e);
+        }
+        let banned_list = self.banned_list;
+        let cleanup_interval = self.cfg.cleanup_interval.max(Duration::from_secs(1));
+        let unbanned_retention = self.cfg.unbanned_retention;
+        self.cleaner = Some(tokio::spawn(async move {
+            loop {
+                tokio::time::sleep(cleanup_interval).await;
+                banned_list.cleanup(unbanned_retention).await;
+            }
+        }));
+        Ok(())
+    }
+
+    #[inline]
+    async fn stop(&mut self) -> Result<bool> {
+        log::info!("{} stop", self.name());
+        self.register.stop().await;
+        if let Some(cleaner) = self.cleaner.take() {
+            cleaner.abort();
+        }
+        Ok(true)
+    }
+
+    #[inline]
+    async fn attrs(&self) -> serde_json::Value {
+        let storage_info = self.banned_list.storage_db.info().await.unwrap_or_default();
+        json!({
+            "banneds": self.banned_list.len(),
+            "storage_info": storage_info,
+        })
+    }
+
+    ///Banned list management, see Command
+    #[inline]
+    async fn send(&self, msg: serde_json::Value) -> Result<serde_json::Value> {
+        match serde_json::from_value::<Command>(msg)? {
+            Command::Ban(b) => {
+                if b.value.is_empty() {
+                    return Err(MqttError::from("value is empty"));
+                }
+                self.apply(Message::Ban(b)).await
+            }
+            Command::Unban { kind, value } => {
+                self.apply(Message::Unban { kind, value, at: timestamp_secs() }).await
+            }
+            Command::List => Ok(serde_json::to_value(self.banned_list.list())?),
+        }
+    }
+}
+
+struct BannedHandler {
+    banned_list: &'static BannedList,
+    message_type: MessageType,
+}
+
+impl BannedHandler {
+    fn new(banned_list: &'static BannedList, message_type: MessageType) -> Self {
+        Self { banned_list, message_type }
+    }
+}
+
+#[async_trait]
+impl Handler for BannedHandler {
+    async fn hook(&self, param: &Parameter, acc: Option<HookResult>) -> ReturnType {
+        match param {
+            Parameter::ClientConnect(connect_info) => {
+                if let Some(b) = self.banned_list.check(connect_info.id()) {
+                    log::info!(
+                        "{} Connection refused, banned by {}:{}, reason: {:?}",
+                        connect_info.id(),
+                        b.kind,
+                        b.value,
+                        b.reason
+                    );
+                    let reason = if connect_info.proto_ver() == MQTT_LEVEL_5 {
+                        ConnectAckReason::V5(ConnectAckReasonV5::Banned)
+                    } else {
+                        ConnectAckReason::V3(ConnectAckReasonV3::NotAuthorized)
+                    };
+                    return (false, Some(HookResult::ConnectAckReason(reason)));
+                }
+            }
+            Parameter::GrpcMessageReceived(typ, msg) => {
+                if self.message_type != *typ {
+                    return (true, acc);
+                }
+                if let GrpcMessage::Data(data) = msg {
+                    let reply = match decode_data::<Message>(data) {
+                        Ok(Message::List) => match encode_data(&self.banned_list.sync()) {
+                            Ok(data) => GrpcMessageReply::Data(data),
+                            Err(e) => GrpcMessageReply::Error(e.to_string()),
+                        },
+                        Ok(msg) => match apply(self.banned_list, &msg).await {
+                            Ok(_) => GrpcMessageReply::Success,
+                            Err(e) => GrpcMessageReply::Error(e.to_string()),
+                        },
+                        Err(e) => GrpcMessageReply::Error(e.to_string()),
+                    };
+                    return (false, Some(HookResult::GrpcMessageReply(Ok(reply))));
+                }
+            }
+            _ => {
+                log::error!("unimplemented, {:?}", param)
+            }
+        }
+        (true, acc)
+    }
+}
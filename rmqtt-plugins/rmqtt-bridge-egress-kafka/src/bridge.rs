@@ -11,6 +11,7 @@ use rmqtt::bytestring::ByteString;
 use rmqtt::rust_box::task_exec_queue::SpawnExt;
 use rmqtt::{
     broker::topic::{TopicTree, VecToTopic},
+    telemetry::{start_publish_span, SpanKind, TraceContext, TRACEPARENT},
     timestamp_millis, From, MqttError, NodeId, Publish, QoSEx, Result, Topic,
 };
 use rmqtt::{
@@ -97,6 +98,16 @@ impl Producer {
             .insert(Header { key: "time", value: Some(itoa::Buffer::new().format(timestamp_millis())) });
         headers = headers.insert(Header { key: "topic", value: Some(p.topic().as_str()) });
 
+        let mut span = start_publish_span("bridge.kafka.send", SpanKind::Producer, p);
+        let trace_ctx = span.as_ref().map(|s| s.context()).or_else(|| TraceContext::from_publish(p));
+        if let Some(trace_ctx) = trace_ctx {
+            headers =
+                headers.insert(Header { key: TRACEPARENT, value: Some(trace_ctx.to_traceparent().as_str()) });
+        }
+        if let Some(span) = span.as_mut() {
+            span.set_attribute("rmqtt.bridge.name", self.cfg.name.as_str());
+        }
+
         let topic = self.cfg_entry.remote.make_topic(&p.topic);
         let payload = p.payload().clone();
         let queue_timeout = self.cfg_entry.remote.queue_timeout;
@@ -119,6 +130,9 @@ impl Producer {
                 }
                 Err((e, msg)) => {
                     log::error!("{} delivery error: {:?}, message: {:?}", name, e, msg);
+                    if let Some(span) = span.as_mut() {
+                        span.set_error(e);
+                    }
                 }
             };
         }
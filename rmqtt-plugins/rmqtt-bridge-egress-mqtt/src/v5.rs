@@ -18,7 +18,10 @@ use rmqtt::{
 };
 use rmqtt::{ClientId, MqttError, NodeId, Result};
 
-use crate::bridge::{build_tls_connector, BridgePublish, Command, CommandMailbox};
+use crate::bridge::{
+    activate_disconnected_alarm, build_tls_connector, deactivate_disconnected_alarm, BridgePublish, Command,
+    CommandMailbox,
+};
 use crate::config::Bridge;
 
 enum MqttConnector {
@@ -157,12 +160,20 @@ impl Client {
             match builder.connect().await {
                 Ok(c) => {
                     log::info!("{} Successfully connected to {:?}", client.client_id, client.cfg.server);
+                    deactivate_disconnected_alarm(&client.client_id);
 
                     let sink = c.sink();
                     client.sink.replace(Some(sink.clone()));
 
                     //client event loop
                     client.clone().ev_loop(c).await;
+                    if !client.is_closed() {
+                        activate_disconnected_alarm(
+                            &client.cfg,
+                            &client.client_id,
+                            "connection closed".into(),
+                        );
+                    }
                 }
                 Err(e) => {
                     log::warn!(
@@ -171,9 +182,11 @@ impl Client {
                         client.cfg.server,
                         e.to_string()
                     );
+                    activate_disconnected_alarm(&client.cfg, &client.client_id, e.to_string());
                 }
             }
             if client.is_closed() {
+                deactivate_disconnected_alarm(&client.client_id);
                 break;
             } else {
                 ntex::time::sleep(sleep_interval).await;
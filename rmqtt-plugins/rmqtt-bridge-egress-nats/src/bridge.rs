@@ -11,6 +11,7 @@ use rmqtt::{
 
 use rmqtt::{
     broker::topic::{TopicTree, VecToTopic},
+    telemetry::{start_publish_span, SpanKind, TraceContext, TRACEPARENT},
     From, MqttError, NodeId, Publish, QoSEx, Result, Topic,
 };
 
@@ -175,9 +176,19 @@ impl Producer {
                     //Must forward
                     properties.insert("topic", p.topic().as_ref());
 
+                    let mut span = start_publish_span("bridge.nats.publish", SpanKind::Producer, &p);
+                    if let Some(trace_ctx) =
+                        span.as_ref().map(|s| s.context()).or_else(|| TraceContext::from_publish(&p))
+                    {
+                        properties.insert(TRACEPARENT, trace_ctx.to_traceparent());
+                    }
+
                     if let Err(e) = jetstream.publish_with_headers(topic.clone(), properties, p.payload).await
                     {
                         log::warn!("{}", e);
+                        if let Some(span) = span.as_mut() {
+                            span.set_error(e);
+                        }
                     }
                 }
             }
@@ -23,6 +23,10 @@ use rmqtt::{
 };
 
 use rmqtt::ntex_mqtt::types::{MQTT_LEVEL_31, MQTT_LEVEL_311, MQTT_LEVEL_5};
+use rmqtt::{
+    broker::alarm::{Alarms, ALARM_BRIDGE_DISCONNECTED},
+    serde_json::json,
+};
 
 use crate::config::{Bridge, PluginConfig};
 use crate::v4::Client as ClientV4;
@@ -300,6 +304,26 @@ fn to_properties(props: ntex_mqtt::v5::codec::PublishProperties) -> PublishPrope
     }
 }
 
+///The alarm is activated when the client is disconnected from the remote server
+#[inline]
+pub(crate) fn activate_disconnected_alarm(cfg: &Bridge, client_id: &ClientId, reason: String) {
+    Alarms::instance().activate(
+        format!("{}/{}", ALARM_BRIDGE_DISCONNECTED, client_id),
+        format!("Bridge {} is disconnected from {}", cfg.name, cfg.server.addr),
+        json!({
+            "bridge": cfg.name.to_string(),
+            "client_id": client_id.to_string(),
+            "server": cfg.server.addr,
+            "reason": reason,
+        }),
+    );
+}
+
+#[inline]
+pub(crate) fn deactivate_disconnected_alarm(client_id: &ClientId) {
+    Alarms::instance().deactivate(&format!("{}/{}", ALARM_BRIDGE_DISCONNECTED, client_id));
+}
+
 pub(crate) fn build_tls_connector(cfg: &super::config::Bridge) -> Result<TlsConnector<String>> {
     let mut root_store = RootCertStore { roots: webpki_roots::TLS_SERVER_ROOTS.into() };
 
@@ -24,7 +24,8 @@ use rmqtt::log;
 use rmqtt::{ClientId, MqttError, NodeId, Result, UserName};
 
 use crate::bridge::{
-    build_tls_connector, BridgeClient, BridgePublish, Command, CommandMailbox, OnMessageEvent,
+    activate_disconnected_alarm, build_tls_connector, deactivate_disconnected_alarm, BridgeClient,
+    BridgePublish, Command, CommandMailbox, OnMessageEvent,
 };
 use crate::config::Bridge;
 
@@ -160,6 +161,7 @@ impl Client {
             match builder.connect().await {
                 Ok(c) => {
                     log::info!("{} Successfully connected to {:?}", client.client_id, client.cfg.server);
+                    deactivate_disconnected_alarm(&client.client_id);
 
                     let sink = c.sink();
                     client.sink.replace(Some(sink.clone()));
@@ -178,6 +180,13 @@ impl Client {
 
                     //client event loop
                     client.clone().ev_loop(c).await;
+                    if !client.is_closed() {
+                        activate_disconnected_alarm(
+                            &client.cfg,
+                            &client.client_id,
+                            "connection closed".into(),
+                        );
+                    }
                 }
                 Err(e) => {
                     log::warn!(
@@ -186,9 +195,11 @@ impl Client {
                         client.cfg.server,
                         e.to_string()
                     );
+                    activate_disconnected_alarm(&client.cfg, &client.client_id, e.to_string());
                 }
             }
             if client.is_closed() {
+                deactivate_disconnected_alarm(&client.client_id);
                 break;
             } else {
                 ntex::time::sleep(sleep_interval).await;
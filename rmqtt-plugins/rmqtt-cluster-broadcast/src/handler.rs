@@ -6,6 +6,7 @@ use rmqtt::{
         types::{From, Publish, SubRelationsMap, SubscriptionClientIds},
     },
     grpc::{Message, MessageReply},
+    telemetry::start_forwarded_span,
     Id, Runtime,
 };
 
@@ -33,14 +34,16 @@ impl Handler for HookHandler {
                 }
                 match msg {
                     Message::Forwards(from, publish) => {
-                        let (shared_subs, subs_size) = forwards(from.clone(), publish.clone()).await;
+                        let (publish, _span) = start_forwarded_span("mqtt.forwards", from, publish);
+                        let (shared_subs, subs_size) = forwards(from.clone(), publish.into_owned()).await;
                         let new_acc =
                             HookResult::GrpcMessageReply(Ok(MessageReply::Forwards(shared_subs, subs_size)));
                         return (false, Some(new_acc));
                     }
                     Message::ForwardsTo(from, publish, sub_rels) => {
+                        let (publish, _span) = start_forwarded_span("mqtt.forwards_to", from, publish);
                         if let Err(droppeds) =
-                            self.shared.inner().forwards_to(from.clone(), publish, sub_rels.clone()).await
+                            self.shared.inner().forwards_to(from.clone(), &publish, sub_rels.clone()).await
                         {
                             hook_message_dropped(droppeds).await;
                         }
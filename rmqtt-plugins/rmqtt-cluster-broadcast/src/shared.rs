@@ -10,10 +10,10 @@ use rmqtt::{
         default::DefaultShared,
         session::Session,
         types::{
-            ClientId, From, Id, IsAdmin, IsOnline, NodeId, OfflineSession, Publish, Reason, SessionStatus,
-            SharedGroup, SharedGroupType, SubRelations, SubRelationsMap, SubsSearchParams, SubsSearchResult,
-            Subscribe, SubscribeReturn, SubscriptionClientIds, SubscriptionIdentifier, SubscriptionOptions,
-            To, TopicFilter, Tx, Unsubscribe,
+            ClientId, From, Id, IsAdmin, NodeId, OfflineSession, Publish, Reason, SessionStatus, SharedGroup,
+            SharedGroupType, SharedSubChoice, SubRelations, SubRelationsMap, SubsSearchParams,
+            SubsSearchResult, Subscribe, SubscribeReturn, SubscriptionClientIds, SubscriptionIdentifier,
+            SubscriptionOptions, To, TopicFilter, Tx, Unsubscribe,
         },
         Entry, Shared,
     },
@@ -282,16 +282,7 @@ impl Shared for &'static ClusterShared {
 
             type SharedSubGroups = HashMap<
                 TopicFilter, //key is TopicFilter
-                HashMap<
-                    SharedGroup,
-                    Vec<(
-                        NodeId,
-                        ClientId,
-                        SubscriptionOptions,
-                        Option<Vec<SubscriptionIdentifier>>,
-                        Option<IsOnline>,
-                    )>,
-                >,
+                HashMap<SharedGroup, Vec<SharedSubChoice>>,
             >;
             type SharedRelation = (
                 TopicFilter,
@@ -375,9 +366,13 @@ impl Shared for &'static ClusterShared {
             //shared subscription choice
             let mut node_shared_subs: HashMap<NodeId, SubRelations> = HashMap::default();
             for (topic_filter, sub_groups) in shared_sub_groups.iter_mut() {
-                for (_group, subs) in sub_groups.iter_mut() {
-                    if let Some((idx, _is_online)) =
-                        Runtime::instance().extends.shared_subscription().await.choice(subs).await
+                for (group, subs) in sub_groups.iter_mut() {
+                    if let Some((idx, _is_online)) = Runtime::instance()
+                        .extends
+                        .shared_subscription()
+                        .await
+                        .choice(&from.id, publish.topic(), topic_filter, group, subs)
+                        .await
                     {
                         let (node_id, client_id, opts, sub_ids, _is_online) = subs.remove(idx);
                         node_shared_subs.entry(node_id).or_default().push((
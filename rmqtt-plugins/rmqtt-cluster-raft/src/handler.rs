@@ -6,6 +6,7 @@ use rmqtt::{async_trait::async_trait, log, tokio, MqttError};
 use rmqtt::{
     broker::hook::{Handler, HookResult, Parameter, ReturnType},
     grpc::{Message as GrpcMessage, MessageReply},
+    telemetry::start_forwarded_span,
     Id, Runtime,
 };
 
@@ -111,8 +112,9 @@ impl Handler for HookHandler {
                 }
                 match msg {
                     GrpcMessage::ForwardsTo(from, publish, sub_rels) => {
+                        let (publish, _span) = start_forwarded_span("mqtt.forwards_to", from, publish);
                         if let Err(droppeds) =
-                            self.shared.forwards_to(from.clone(), publish, sub_rels.clone()).await
+                            self.shared.forwards_to(from.clone(), &publish, sub_rels.clone()).await
                         {
                             hook_message_dropped(droppeds).await;
                         }
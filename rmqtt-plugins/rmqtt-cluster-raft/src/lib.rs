@@ -24,6 +24,7 @@ use rmqtt::{
 };
 use rmqtt::{
     broker::{
+        alarm::{Alarms, ALARM_CLUSTER_RAFT_UNAVAILABLE},
         error::MqttError,
         hook::{Register, Type},
         types::{From, Publish, Reason, To},
@@ -243,14 +244,28 @@ impl ClusterPlugin {
                 match raft_mailbox.status().await {
                     Err(e) => {
                         log::error!("Error retrieving cluster status, {}", e);
+                        Alarms::instance().activate(
+                            ALARM_CLUSTER_RAFT_UNAVAILABLE,
+                            "Error retrieving raft cluster status",
+                            json!({ "error": e.to_string() }),
+                        );
                     }
                     Ok(s) => {
                         if s.available() {
                             if continuous_unavailable_count > 0 {
                                 continuous_unavailable_count = 0;
                             }
+                            Alarms::instance().deactivate(ALARM_CLUSTER_RAFT_UNAVAILABLE);
                         } else {
                             continuous_unavailable_count += 1;
+                            Alarms::instance().activate(
+                                ALARM_CLUSTER_RAFT_UNAVAILABLE,
+                                "Raft cluster is unavailable, the leader does not exist or the quorum is lost",
+                                json!({
+                                    "leader_id": s.leader_id,
+                                    "continuous_unavailable_count": continuous_unavailable_count,
+                                }),
+                            );
                             log::error!(
                                 "cluster node unavailable({}), node status: {:?}",
                                 continuous_unavailable_count,
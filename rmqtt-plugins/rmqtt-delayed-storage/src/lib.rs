@@ -0,0 +1,111 @@
+#![deny(unsafe_code)]
+#[macro_use]
+extern crate serde;
+
+#[macro_use]
+extern crate rmqtt_macros;
+
+use std::sync::Arc;
+
+use rmqtt::{
+    async_trait::async_trait,
+    log,
+    serde_json::{self, json},
+};
+
+use rmqtt::{
+    broker::DelayedSender,
+    plugin::{PackageInfo, Plugin},
+    register, Result, Runtime,
+};
+
+use rmqtt_storage::{init_db, StorageType};
+
+use config::PluginConfig;
+use storage::StorageDelayedSender;
+
+mod config;
+mod storage;
+
+register!(DelayedStoragePlugin::new);
+
+#[derive(Plugin)]
+struct DelayedStoragePlugin {
+    runtime: &'static Runtime,
+    cfg: Arc<PluginConfig>,
+    delayed_sender: &'static StorageDelayedSender,
+}
+
+impl DelayedStoragePlugin {
+    #[inline]
+    async fn new<S: Into<String>>(runtime: &'static Runtime, name: S) -> Result<Self> {
+        let name = name.into();
+        let mut cfg = runtime.settings.plugins.load_config_default::<PluginConfig>(&name)?;
+        match cfg.storage.typ {
+            StorageType::Sled => {
+                cfg.storage.sled.path =
+                    cfg.storage.sled.path.replace("{node}", &format!("{}", runtime.node.id()));
+            }
+            StorageType::Redis => {
+                cfg.storage.redis.prefix =
+                    cfg.storage.redis.prefix.replace("{node}", &format!("{}", runtime.node.id()));
+            }
+            StorageType::RedisCluster => {
+                cfg.storage.redis_cluster.prefix =
+                    cfg.storage.redis_cluster.prefix.replace("{node}", &format!("{}", runtime.node.id()));
+            }
+        }
+
+        log::info!("{} DelayedStoragePlugin cfg: {:?}", name, cfg);
+
+        let storage_db = init_db(&cfg.storage).await?;
+        let delayed_sender = storage::get_or_init(storage_db).await?;
+        Ok(Self { runtime, cfg: Arc::new(cfg), delayed_sender })
+    }
+}
+
+#[async_trait]
+impl Plugin for DelayedStoragePlugin {
+    #[inline]
+    async fn init(&mut self) -> Result<()> {
+        log::info!("{} init", self.name());
+        Ok(())
+    }
+
+    #[inline]
+    async fn get_config(&self) -> Result<serde_json::Value> {
+        Ok(self.cfg.to_json())
+    }
+
+    #[inline]
+    async fn start(&mut self) -> Result<()> {
+        log::info!("{} start", self.name());
+        let prev = std::mem::replace(
+            &mut *self.runtime.extends.delayed_sender_mut().await,
+            Box::new(self.delayed_sender),
+        );
+        //The pending delayed messages of the previous sender are moved to the storage, so they are
+        //not lost when the plugin is started at runtime
+        let migrateds = self.delayed_sender.migrate(prev.as_ref()).await?;
+        if migrateds > 0 {
+            log::info!("{} migrated delayed publishs: {}", self.name(), migrateds);
+            self.runtime.stats.delayed_publishs.max_max(self.delayed_sender.len().await as isize);
+        }
+        Ok(())
+    }
+
+    #[inline]
+    async fn stop(&mut self) -> Result<bool> {
+        log::warn!("{} stop, if the delayed-storage plugin is started, it cannot be stopped", self.name());
+        Ok(false)
+    }
+
+    #[inline]
+    async fn attrs(&self) -> serde_json::Value {
+        let storage_info = self.delayed_sender.storage_db.info().await.unwrap_or_default();
+        json!({
+            "delayed_publishs": self.delayed_sender.len().await,
+            "storage_info": storage_info,
+        })
+    }
+}
@@ -0,0 +1,388 @@
+use std::collections::BTreeSet;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::time::Duration;
+
+use rmqtt::{
+    anyhow::anyhow, async_trait::async_trait, futures::StreamExt, log, once_cell::sync::OnceCell, tokio,
+    tokio::sync::RwLock,
+};
+
+use rmqtt::{
+    broker::default::DefaultDelayedSender, broker::DelayedSender, timestamp_millis, DelayedPublish,
+    DelayedPublishId, From, Publish, Result, Runtime, SessionState, TimestampMillis,
+};
+use rmqtt_storage::DefaultStorageDB;
+
+const DELAYED_PUBLISH_PREFIX: &[u8] = b"d|";
+
+static INSTANCE: OnceCell<StorageDelayedSender> = OnceCell::new();
+
+#[inline]
+pub(crate) async fn get_or_init(storage_db: DefaultStorageDB) -> Result<&'static StorageDelayedSender> {
+    if let Some(sender) = INSTANCE.get() {
+        return Ok(sender);
+    }
+    let sender = StorageDelayedSender::new(storage_db).await?;
+    INSTANCE.set(sender).map_err(|_| anyhow!("init error!"))?;
+    if let Some(sender) = INSTANCE.get() {
+        tokio::spawn(sender.start());
+        Ok(sender)
+    } else {
+        unreachable!()
+    }
+}
+
+pub(crate) struct StorageDelayedSender {
+    pub(crate) storage_db: DefaultStorageDB,
+    //(expired_time, id), ordered by expiration time
+    index: RwLock<BTreeSet<(TimestampMillis, DelayedPublishId)>>,
+    //slots taken by the messages being written to the database, counted in the capacity
+    reserveds: AtomicUsize,
+    id_gen: AtomicU64,
+}
+
+impl StorageDelayedSender {
+    #[inline]
+    async fn new(storage_db: DefaultStorageDB) -> Result<Self> {
+        let (index, max_id) = Self::restore(storage_db.clone()).await?;
+        log::info!("restore delayed publishs, count: {}, max id: {}", index.len(), max_id);
+        Ok(Self {
+            storage_db,
+            index: RwLock::new(index),
+            reserveds: AtomicUsize::new(0),
+            id_gen: AtomicU64::new(max_id + 1),
+        })
+    }
+
+    //Load the index of pending delayed messages from the database
+    async fn restore(
+        mut storage_db: DefaultStorageDB,
+    ) -> Result<(BTreeSet<(TimestampMillis, DelayedPublishId)>, DelayedPublishId)> {
+        let mut keys = Vec::new();
+        {
+            let mut iter = storage_db.scan([DELAYED_PUBLISH_PREFIX, b"*"].concat()).await?;
+            while let Some(key) = iter.next().await {
+                match key {
+                    Ok(key) => keys.push(key),
+                    Err(e) => log::warn!("scan delayed publish error, {:?}", e),
+                }
+            }
+        }
+
+        let mut index = BTreeSet::new();
+        let mut max_id = 0;
+        for key in keys {
+            match storage_db.get::<_, DelayedPublish>(key.as_slice()).await {
+                Ok(Some(dp)) => {
+                    max_id = max_id.max(dp.id);
+                    index.insert((dp.expired_time, dp.id));
+                }
+                Ok(None) => {}
+                Err(e) => {
+                    log::warn!(
+                        "load delayed publish error, {:?}, key: {:?}",
+                        e,
+                        String::from_utf8_lossy(&key)
+                    );
+                    if let Err(e) = storage_db.remove(key.as_slice()).await {
+                        log::warn!("remove delayed publish error, {:?}", e);
+                    }
+                }
+            }
+        }
+        Ok((index, max_id))
+    }
+
+    async fn start(&'static self) {
+        loop {
+            tokio::time::sleep(Duration::from_millis(500)).await;
+            let now = timestamp_millis();
+            loop {
+                let expired = {
+                    let mut index = self.index.write().await;
+                    match index.first() {
+                        Some((expired_time, _)) if *expired_time < now => index.pop_first(),
+                        _ => None,
+                    }
+                };
+                if let Some((_, id)) = expired {
+                    self.send(id).await;
+                } else {
+                    break;
+                }
+            }
+        }
+    }
+
+    ///Store a delayed message with a new id, the message is returned if `max` messages are pending.
+    ///The slot is reserved under the index lock, the database is written without holding it.
+    #[inline]
+    async fn store(&self, mut dp: DelayedPublish, max: usize) -> Result<Option<DelayedPublish>> {
+        {
+            let index = self.index.write().await;
+            if index.len() + self.reserveds.load(Ordering::SeqCst) >= max {
+                return Ok(Some(dp));
+            }
+            self.reserveds.fetch_add(1, Ordering::SeqCst);
+        }
+        dp.id = self.id_gen.fetch_add(1, Ordering::SeqCst);
+        let res = self.storage_db.insert(make_stored_key(dp.id).as_slice(), &dp).await;
+        let mut index = self.index.write().await;
+        self.reserveds.fetch_sub(1, Ordering::SeqCst);
+        res?;
+        index.insert((dp.expired_time, dp.id));
+        Ok(None)
+    }
+
+    ///Move the pending delayed messages of another DelayedSender, such as the in-memory one, to the
+    ///storage, their expiration times are kept. Returns the number of moved messages.
+    #[inline]
+    pub(crate) async fn migrate(&self, prev: &dyn DelayedSender) -> Result<usize> {
+        let mut migrateds = 0;
+        let mut offset = 0;
+        loop {
+            let dps = prev.list(offset, 1000).await?;
+            if dps.is_empty() {
+                break;
+            }
+            for dp in dps {
+                match prev.cancel(dp.id).await? {
+                    Some(dp) => {
+                        self.store(dp, usize::MAX).await?;
+                        migrateds += 1;
+                    }
+                    //Already popped and being sent
+                    None => offset += 1,
+                }
+            }
+        }
+        Ok(migrateds)
+    }
+
+    #[inline]
+    async fn send(&self, id: DelayedPublishId) {
+        let key = make_stored_key(id);
+        let dp = match self.storage_db.get::<_, DelayedPublish>(key.as_slice()).await {
+            Ok(Some(dp)) => dp,
+            Ok(None) => return,
+            Err(e) => {
+                log::warn!("load delayed publish error, {:?}, id: {}", e, id);
+                return;
+            }
+        };
+        log::debug!("pop {:?} {:?}", dp.expired_time, dp.publish.topic);
+        if let Err(e) = SessionState::forwards(
+            dp.from,
+            dp.publish,
+            dp.message_storage_available,
+            dp.message_expiry_interval,
+        )
+        .await
+        {
+            log::warn!("delayed forwards error, {:?}", e);
+        }
+        if let Err(e) = self.storage_db.remove(key.as_slice()).await {
+            log::warn!("remove delayed publish error, {:?}, id: {}", e, id);
+        }
+    }
+}
+
+#[async_trait]
+impl DelayedSender for &'static StorageDelayedSender {
+    #[inline]
+    fn parse(&self, publish: Publish) -> Result<Publish> {
+        DefaultDelayedSender::instance().parse(publish)
+    }
+
+    #[inline]
+    async fn delay_publish(
+        &self,
+        from: From,
+        publish: Publish,
+        message_storage_available: bool,
+        message_expiry_interval: Option<Duration>,
+    ) -> Result<Option<(From, Publish)>> {
+        let dp = DelayedPublish::new(0, from, publish, message_storage_available, message_expiry_interval);
+        let max = Runtime::instance().settings.mqtt.delayed_publish_max;
+        if let Some(dp) = self.store(dp, max).await? {
+            return Ok(Some((dp.from, dp.publish)));
+        }
+        Runtime::instance().stats.delayed_publishs.max_max(self.len().await as isize);
+        Ok(None)
+    }
+
+    #[inline]
+    async fn len(&self) -> usize {
+        self.index.read().await.len()
+    }
+
+    #[inline]
+    async fn list(&self, offset: usize, limit: usize) -> Result<Vec<DelayedPublish>> {
+        let ids =
+            self.index.read().await.iter().skip(offset).take(limit).map(|(_, id)| *id).collect::<Vec<_>>();
+        let mut dps = Vec::new();
+        for id in ids {
+            if let Some(dp) = self.storage_db.get::<_, DelayedPublish>(make_stored_key(id).as_slice()).await?
+            {
+                dps.push(dp);
+            }
+        }
+        Ok(dps)
+    }
+
+    #[inline]
+    async fn get(&self, id: DelayedPublishId) -> Result<Option<DelayedPublish>> {
+        Ok(self.storage_db.get::<_, DelayedPublish>(make_stored_key(id).as_slice()).await?)
+    }
+
+    #[inline]
+    async fn cancel(&self, id: DelayedPublishId) -> Result<Option<DelayedPublish>> {
+        let key = make_stored_key(id);
+        let mut index = self.index.write().await;
+        let dp = self.storage_db.get::<_, DelayedPublish>(key.as_slice()).await?;
+        if let Some(dp) = &dp {
+            if !index.remove(&(dp.expired_time, id)) {
+                //Already popped and being sent
+                return Ok(None);
+            }
+            self.storage_db.remove(key.as_slice()).await?;
+        }
+        Ok(dp)
+    }
+}
+
+#[inline]
+fn make_stored_key(id: DelayedPublishId) -> Vec<u8> {
+    [DELAYED_PUBLISH_PREFIX, id.to_string().as_bytes()].concat()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rmqtt::{bytes::Bytes, serde_json, ClientId, Id, PublishProperties, QoS};
+    use rmqtt_storage::{init_db, Config};
+
+    async fn sender(name: &str) -> &'static StorageDelayedSender {
+        let dir = std::env::temp_dir().join(format!("rmqtt-delayed-test-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        let cfg: Config =
+            serde_json::from_value(serde_json::json!({"type": "sled", "sled": {"path": dir}})).unwrap();
+        let storage_db = init_db(&cfg).await.unwrap();
+        Box::leak(Box::new(StorageDelayedSender::new(storage_db).await.unwrap()))
+    }
+
+    fn delayed_publish(topic: &str, delay_interval: u32) -> DelayedPublish {
+        let publish = Publish {
+            dup: false,
+            retain: false,
+            qos: QoS::AtLeastOnce,
+            topic: topic.into(),
+            packet_id: None,
+            payload: Bytes::from_static(b"data"),
+            properties: PublishProperties::default(),
+            delay_interval: Some(delay_interval),
+            create_time: timestamp_millis(),
+        };
+        let from = From::from_custom(Id::new(1, None, None, ClientId::from_static("dev-1"), None));
+        DelayedPublish::new(0, from, publish, false, None)
+    }
+
+    //The pending messages of the in-memory sender
+    struct MemorySender(tokio::sync::Mutex<Vec<DelayedPublish>>);
+
+    #[async_trait]
+    impl DelayedSender for MemorySender {
+        fn parse(&self, publish: Publish) -> Result<Publish> {
+            Ok(publish)
+        }
+
+        async fn delay_publish(
+            &self,
+            from: From,
+            publish: Publish,
+            _message_storage_available: bool,
+            _message_expiry_interval: Option<Duration>,
+        ) -> Result<Option<(From, Publish)>> {
+            Ok(Some((from, publish)))
+        }
+
+        async fn len(&self) -> usize {
+            self.0.lock().await.len()
+        }
+
+        async fn list(&self, offset: usize, limit: usize) -> Result<Vec<DelayedPublish>> {
+            Ok(self.0.lock().await.iter().skip(offset).take(limit).cloned().collect())
+        }
+
+        async fn get(&self, id: DelayedPublishId) -> Result<Option<DelayedPublish>> {
+            Ok(self.0.lock().await.iter().find(|dp| dp.id == id).cloned())
+        }
+
+        async fn cancel(&self, id: DelayedPublishId) -> Result<Option<DelayedPublish>> {
+            let mut dps = self.0.lock().await;
+            Ok(dps.iter().position(|dp| dp.id == id).map(|idx| dps.remove(idx)))
+        }
+    }
+
+    #[tokio::test(crate = "rmqtt::tokio")]
+    async fn store_round_trip() {
+        let sender = sender("round-trip").await;
+        assert!(sender.store(delayed_publish("t/2", 20), 10).await.unwrap().is_none());
+        assert!(sender.store(delayed_publish("t/1", 10), 10).await.unwrap().is_none());
+        assert_eq!(sender.len().await, 2);
+
+        //ordered by expiration time
+        let dps = sender.list(0, 10).await.unwrap();
+        assert_eq!(dps.iter().map(|dp| dp.publish.topic.as_ref()).collect::<Vec<_>>(), ["t/1", "t/2"]);
+        let dp = sender.get(dps[0].id).await.unwrap().unwrap();
+        assert_eq!(dp.publish.payload, Bytes::from_static(b"data"));
+        assert_eq!(dp.expired_time, dps[0].expired_time);
+
+        //the index and the next id are restored from the database
+        let (index, max_id) = StorageDelayedSender::restore(sender.storage_db.clone()).await.unwrap();
+        assert_eq!(index, *sender.index.read().await);
+        assert_eq!(max_id, dps.iter().map(|dp| dp.id).max().unwrap());
+    }
+
+    #[tokio::test(crate = "rmqtt::tokio")]
+    async fn store_capacity_limit() {
+        let sender = sender("capacity").await;
+        assert!(sender.store(delayed_publish("t/1", 10), 2).await.unwrap().is_none());
+        assert!(sender.store(delayed_publish("t/2", 10), 2).await.unwrap().is_none());
+        let rejected = sender.store(delayed_publish("t/3", 10), 2).await.unwrap();
+        assert_eq!(rejected.map(|dp| dp.publish.topic), Some("t/3".into()));
+        assert_eq!(sender.len().await, 2);
+        assert_eq!(sender.reserveds.load(Ordering::SeqCst), 0);
+    }
+
+    #[tokio::test(crate = "rmqtt::tokio")]
+    async fn cancel() {
+        let sender = sender("cancel").await;
+        assert!(sender.store(delayed_publish("t/1", 10), 10).await.unwrap().is_none());
+        let id = sender.list(0, 1).await.unwrap()[0].id;
+        let dp = sender.cancel(id).await.unwrap();
+        assert_eq!(dp.map(|dp| dp.id), Some(id));
+        assert_eq!(sender.len().await, 0);
+        assert!(sender.get(id).await.unwrap().is_none());
+        assert!(sender.cancel(id).await.unwrap().is_none());
+    }
+
+    #[tokio::test(crate = "rmqtt::tokio")]
+    async fn migrate() {
+        let sender = sender("migrate").await;
+        let mut dps = Vec::new();
+        for (id, topic) in [(1, "t/1"), (2, "t/2"), (3, "t/3")] {
+            let mut dp = delayed_publish(topic, 10 * id as u32);
+            dp.id = id;
+            dps.push(dp);
+        }
+        let expired_times = dps.iter().map(|dp| dp.expired_time).collect::<Vec<_>>();
+        let prev = MemorySender(tokio::sync::Mutex::new(dps));
+
+        assert_eq!(sender.migrate(&prev).await.unwrap(), 3);
+        assert!(prev.is_empty().await);
+        let dps = sender.list(0, 10).await.unwrap();
+        assert_eq!(dps.iter().map(|dp| dp.publish.topic.as_ref()).collect::<Vec<_>>(), ["t/1", "t/2", "t/3"]);
+        assert_eq!(dps.iter().map(|dp| dp.expired_time).collect::<Vec<_>>(), expired_times);
+    }
+}
@@ -0,0 +1,71 @@
+use std::time::Duration;
+
+use serde::de::{self, Deserialize, Deserializer};
+
+use rmqtt::serde_json;
+use rmqtt::{broker::types::QoS, settings::deserialize_duration, Result};
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct PluginConfig {
+    #[serde(default = "PluginConfig::window_time_default", deserialize_with = "deserialize_duration")]
+    pub window_time: Duration,
+
+    #[serde(default = "PluginConfig::max_count_default")]
+    pub max_count: usize,
+
+    #[serde(default = "PluginConfig::ban_time_default", deserialize_with = "deserialize_duration")]
+    pub ban_time: Duration,
+
+    #[serde(
+        default = "PluginConfig::publish_qos_default",
+        deserialize_with = "PluginConfig::deserialize_publish_qos"
+    )]
+    pub publish_qos: QoS,
+
+    #[serde(
+        default = "PluginConfig::message_expiry_interval_default",
+        deserialize_with = "deserialize_duration"
+    )]
+    pub message_expiry_interval: Duration,
+}
+
+impl PluginConfig {
+    fn window_time_default() -> Duration {
+        Duration::from_secs(60)
+    }
+
+    fn max_count_default() -> usize {
+        15
+    }
+
+    fn ban_time_default() -> Duration {
+        Duration::from_secs(300)
+    }
+
+    fn publish_qos_default() -> QoS {
+        QoS::AtLeastOnce
+    }
+
+    fn message_expiry_interval_default() -> Duration {
+        Duration::from_secs(300)
+    }
+
+    #[inline]
+    pub fn to_json(&self) -> Result<serde_json::Value> {
+        Ok(serde_json::to_value(self)?)
+    }
+
+    #[inline]
+    fn deserialize_publish_qos<'de, D>(deserializer: D) -> Result<QoS, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let qos = match u8::deserialize(deserializer)? {
+            0 => QoS::AtMostOnce,
+            1 => QoS::AtLeastOnce,
+            2 => QoS::ExactlyOnce,
+            _ => return Err(de::Error::custom("QoS configuration error, only values (0,1,2) are supported")),
+        };
+        Ok(qos)
+    }
+}
@@ -0,0 +1,297 @@
+#![deny(unsafe_code)]
+#[macro_use]
+extern crate serde;
+
+#[macro_use]
+extern crate rmqtt_macros;
+
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use config::PluginConfig;
+use rmqtt::{
+    async_trait::async_trait,
+    chrono, log,
+    serde_json::{self, json},
+    tokio,
+};
+use rmqtt::{
+    broker::hook::{Handler, HookResult, Parameter, Register, ReturnType, Type},
+    broker::types::Id,
+    plugin::{PackageInfo, Plugin},
+    register, timestamp_millis, timestamp_secs, ClientId, DashMap, NodeId, Result, Runtime, SessionState,
+    TimestampMillis,
+};
+
+mod config;
+
+const BANNED_PLUGIN: &str = "rmqtt-banned";
+
+//The missing rmqtt-banned plugin is logged once, until it is started
+static BANNED_PLUGIN_MISSING: AtomicBool = AtomicBool::new(false);
+
+register!(FlappingDetectPlugin::new);
+
+#[derive(Plugin)]
+struct FlappingDetectPlugin {
+    runtime: &'static Runtime,
+    register: Box<dyn Register>,
+    cfg: Arc<PluginConfig>,
+    //Connect times of each client within the window
+    connects: Arc<DashMap<ClientId, VecDeque<TimestampMillis>>>,
+    cleaner: Option<tokio::task::JoinHandle<()>>,
+}
+
+impl FlappingDetectPlugin {
+    #[inline]
+    async fn new<S: Into<String>>(runtime: &'static Runtime, name: S) -> Result<Self> {
+        let name = name.into();
+        let cfg = runtime.settings.plugins.load_config_default::<PluginConfig>(&name)?;
+        log::info!("{} FlappingDetectPlugin cfg: {:?}", name, cfg);
+        let register = runtime.extends.hook_mgr().await.register();
+        Ok(Self {
+            runtime,
+            register,
+            cfg: Arc::new(cfg),
+            connects: Arc::new(DashMap::default()),
+            cleaner: None,
+        })
+    }
+}
+
+#[async_trait]
+impl Plugin for FlappingDetectPlugin {
+    #[inline]
+    async fn init(&mut self) -> Result<()> {
+        log::info!("{} init", self.name());
+        self.register
+            .add(
+                Type::ClientConnected,
+                Box::new(FlappingHandler::new(
+                    self.runtime.node.id(),
+                    self.cfg.clone(),
+                    self.connects.clone(),
+                )),
+            )
+            .await;
+        Ok(())
+    }
+
+    #[inline]
+    async fn get_config(&self) -> Result<serde_json::Value> {
+        self.cfg.to_json()
+    }
+
+    #[inline]
+    async fn start(&mut self) -> Result<()> {
+        log::info!("{} start", self.name());
+        self.register.start().await;
+        let connects = self.connects.clone();
+        let window_time = self.cfg.window_time;
+        self.cleaner = Some(tokio::spawn(async move {
+            loop {
+                tokio::time::sleep(window_time.max(Duration::from_secs(1))).await;
+                let now = timestamp_millis();
+                connects.retain(|_, times| {
+                    evict(times, now, window_time);
+                    !times.is_empty()
+                });
+            }
+        }));
+        Ok(())
+    }
+
+    #[inline]
+    async fn stop(&mut self) -> Result<bool> {
+        log::info!("{} stop", self.name());
+        self.register.stop().await;
+        if let Some(cleaner) = self.cleaner.take() {
+            cleaner.abort();
+        }
+        self.connects.clear();
+        Ok(true)
+    }
+
+    #[inline]
+    async fn attrs(&self) -> serde_json::Value {
+        json!({
+            "tracked_clients": self.connects.len(),
+        })
+    }
+}
+
+//Drops the connect times that have left the window
+#[inline]
+fn evict(times: &mut VecDeque<TimestampMillis>, now: TimestampMillis, window_time: Duration) {
+    let since = now - window_time.as_millis() as TimestampMillis;
+    while times.front().map(|t| *t <= since).unwrap_or_default() {
+        times.pop_front();
+    }
+}
+
+struct FlappingHandler {
+    nodeid: NodeId,
+    cfg: Arc<PluginConfig>,
+    connects: Arc<DashMap<ClientId, VecDeque<TimestampMillis>>>,
+}
+
+impl FlappingHandler {
+    fn new(
+        nodeid: NodeId,
+        cfg: Arc<PluginConfig>,
+        connects: Arc<DashMap<ClientId, VecDeque<TimestampMillis>>>,
+    ) -> Self {
+        Self { nodeid, cfg, connects }
+    }
+
+    //Records the connect, returns the number of connects within the window if the threshold is reached
+    #[inline]
+    fn record(&self, client_id: &ClientId, now: TimestampMillis) -> Option<usize> {
+        let mut times = self.connects.entry(client_id.clone()).or_default();
+        times.push_back(now);
+        evict(&mut times, now, self.cfg.window_time);
+        let count = times.len();
+        if count >= self.cfg.max_count {
+            times.clear();
+            Some(count)
+        } else {
+            None
+        }
+    }
+}
+
+#[async_trait]
+impl Handler for FlappingHandler {
+    async fn hook(&self, param: &Parameter, acc: Option<HookResult>) -> ReturnType {
+        match param {
+            Parameter::ClientConnected(session) => {
+                if self.cfg.max_count == 0 {
+                    return (true, acc);
+                }
+                if let Some(count) = self.record(&session.id.client_id, timestamp_millis()) {
+                    let id = session.id.clone();
+                    let nodeid = self.nodeid;
+                    let cfg = self.cfg.clone();
+                    tokio::spawn(async move {
+                        flapping(nodeid, id, count, cfg).await;
+                    });
+                }
+            }
+            _ => {
+                log::error!("unimplemented, {:?}", param)
+            }
+        }
+        (true, acc)
+    }
+}
+
+//Bans the client through the rmqtt-banned plugin and publishes the $SYS alert
+async fn flapping(nodeid: NodeId, id: Id, count: usize, cfg: Arc<PluginConfig>) {
+    let reason = format!("flapping, {} connects in {:?}", count, cfg.window_time);
+    log::warn!("{} {}, banned for {:?}", id, reason, cfg.ban_time);
+
+    if Runtime::instance().plugins.is_active(BANNED_PLUGIN) {
+        BANNED_PLUGIN_MISSING.store(false, Ordering::SeqCst);
+        ban(&id, &reason, &cfg).await;
+    } else if !BANNED_PLUGIN_MISSING.swap(true, Ordering::SeqCst) {
+        log::error!("{} is not started, flapping clients are not banned", BANNED_PLUGIN);
+    }
+
+    let now = chrono::Local::now();
+    let body = json!({
+        "node": id.node(),
+        "ipaddress": id.remote_addr,
+        "clientid": id.client_id,
+        "username": id.username_ref(),
+        "count": count,
+        "window_time": cfg.window_time.as_secs(),
+        "ban_time": cfg.ban_time.as_secs(),
+        "reason": reason,
+        "time": now.format("%Y-%m-%d %H:%M:%S%.3f").to_string()
+    });
+    let topic = format!("$SYS/brokers/{}/clients/{}/flapping", nodeid, id.client_id);
+    SessionState::sys_publish(nodeid, topic, cfg.publish_qos, body, cfg.message_expiry_interval).await;
+}
+
+//Adds the client to the banned list of the rmqtt-banned plugin, it is replicated to the other nodes
+async fn ban(id: &Id, reason: &str, cfg: &PluginConfig) {
+    let msg = json!({
+        "cmd": "ban",
+        "kind": "clientid",
+        "value": id.client_id,
+        "by": "rmqtt-flapping-detect",
+        "reason": reason,
+        "until": timestamp_secs() + cfg.ban_time.as_secs() as i64,
+    });
+    if let Err(e) = Runtime::instance().plugins.send(BANNED_PLUGIN, msg).await {
+        log::warn!("{} ban error, {:?}", id, e);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn handler(window_time: &str, max_count: usize) -> FlappingHandler {
+        let cfg: PluginConfig =
+            serde_json::from_value(json!({"window_time": window_time, "max_count": max_count})).unwrap();
+        FlappingHandler::new(1, Arc::new(cfg), Arc::new(DashMap::default()))
+    }
+
+    #[test]
+    fn evict_window_edge() {
+        let mut times = VecDeque::from([1000, 2000, 3000]);
+        evict(&mut times, 3500, Duration::from_secs(10));
+        assert_eq!(times, vec![1000, 2000, 3000]);
+
+        //A connect exactly window_time ago has left the window
+        evict(&mut times, 4000, Duration::from_secs(2));
+        assert_eq!(times, vec![3000]);
+
+        evict(&mut times, 5001, Duration::from_secs(2));
+        assert!(times.is_empty());
+        evict(&mut times, 6000, Duration::from_secs(2));
+        assert!(times.is_empty());
+    }
+
+    #[test]
+    fn record_threshold() {
+        let h = handler("10s", 3);
+        let c1 = ClientId::from_static("c1");
+        assert_eq!(h.record(&c1, 0), None);
+        assert_eq!(h.record(&c1, 1000), None);
+        assert_eq!(h.record(&c1, 2000), Some(3));
+
+        //The count starts again after the threshold is reached
+        assert_eq!(h.record(&c1, 3000), None);
+        assert_eq!(h.connects.get(&c1).unwrap().len(), 1);
+
+        //Clients are counted separately
+        let c2 = ClientId::from_static("c2");
+        assert_eq!(h.record(&c2, 3000), None);
+        assert_eq!(h.record(&c1, 3500), None);
+        assert_eq!(h.record(&c2, 4000), None);
+        assert_eq!(h.record(&c1, 4000), Some(3));
+    }
+
+    #[test]
+    fn record_window_edge() {
+        let h = handler("10s", 3);
+        let c1 = ClientId::from_static("c1");
+        assert_eq!(h.record(&c1, 0), None);
+        assert_eq!(h.record(&c1, 5000), None);
+        //The first connect leaves the window at 10000
+        assert_eq!(h.record(&c1, 10000), None);
+        assert_eq!(h.record(&c1, 10001), Some(3));
+    }
+
+    #[test]
+    fn record_max_count_one() {
+        let h = handler("10s", 1);
+        let c1 = ClientId::from_static("c1");
+        assert_eq!(h.record(&c1, 0), Some(1));
+        assert_eq!(h.record(&c1, 1), Some(1));
+    }
+}
@@ -1,16 +1,20 @@
+use std::collections::BTreeMap;
 use std::convert::From as _;
 use std::io::ErrorKind;
 use std::net::SocketAddr;
 use std::time::Duration;
 
 use salvo::conn::tcp::TcpAcceptor;
-use salvo::http::header::{HeaderValue, CONTENT_TYPE};
+use salvo::http::header::{HeaderValue, CONTENT_DISPOSITION, CONTENT_TYPE};
 use salvo::http::mime;
 use salvo::prelude::*;
 
+use rmqtt::broker::alarm::{AlarmInfo, Alarms};
+use rmqtt::broker::auth_chain::{AuthChainStats, AuthenticatorStats};
 use rmqtt::metrics::Metrics;
 use rmqtt::node::NodeInfo;
 use rmqtt::stats::Stats;
+use rmqtt::topic_metrics::{merge_topic_metrics, TopicMetrics, TopicMetricsInfo};
 
 use rmqtt::{
     anyhow::{self, anyhow},
@@ -28,16 +32,19 @@ use rmqtt::{
         MessageSender, MessageType,
     },
     node::NodeStatus,
-    timestamp_millis, ClientId, From, Id, MqttError, Publish, PublishProperties, QoS, Result, Runtime,
-    SessionState, SubsSearchParams, TopicFilter, TopicName, UserName,
+    timestamp_millis, ClientId, DelayedPublish, DelayedPublishId, From, Id, MqttError, Publish,
+    PublishProperties, QoS, Result, Runtime, SessionState, SubsSearchParams, TopicFilter, TopicName,
+    UserName,
 };
 
 use super::prome;
+use super::trace::{TraceDef, TraceInfo, TraceLog, TraceManager, TRACE_LOG_READ_MAX, TRACE_STREAM_INTERVAL};
 use super::types::{
-    ClientSearchParams, ClientSearchResult, Message, MessageReply, PrometheusDataType, PublishParams,
-    SubscribeParams, UnsubscribeParams,
+    AclExplainParams, AclExplainResult, ClientSearchParams, ClientSearchResult, JwtRevokeParams, Message,
+    MessageReply, PrometheusDataType, PublishParams, SubscribeParams, TopicMetricsParams, TraceParams,
+    UnsubscribeParams,
 };
-use super::{clients, plugin, subs, PluginConfigType};
+use super::{clients, explain, plugin, subs, PluginConfigType};
 
 struct BearerValidator {
     token: String,
@@ -87,6 +94,54 @@ fn route(cfg: PluginConfigType, token: Option<String>) -> Router {
                 .push(Router::with_path("{clientid}").get(get_client_subscriptions)),
         )
         .push(Router::with_path("routes").get(get_routes).push(Router::with_path("{topic}").get(get_route)))
+        .push(
+            Router::with_path("delayed")
+                .get(get_delayeds)
+                .push(Router::with_path("{node}/{id}").get(get_delayed).delete(cancel_delayed)),
+        )
+        .push(
+            Router::with_path("listeners")
+                .push(Router::with_path("reload").put(reload_listeners))
+                .push(Router::with_path("{node}/reload").put(reload_listeners)),
+        )
+        .push(
+            Router::with_path("banned")
+                .get(get_banneds)
+                .post(ban)
+                .push(Router::with_path("{kind}/{**value}").delete(unban)),
+        )
+        .push(
+            Router::with_path("acl/rules")
+                .get(get_acl_rules)
+                .post(insert_acl_rule)
+                .push(Router::with_path("reset").post(reset_acl_rules))
+                .push(Router::with_path("validate").post(validate_acl_rule))
+                .push(Router::with_path("{index}").put(update_acl_rule).delete(delete_acl_rule)),
+        )
+        .push(Router::with_path("acl/explain").post(acl_explain))
+        .push(
+            Router::with_path("jwt/revocations")
+                .get(get_jwt_revocations)
+                .post(jwt_revoke)
+                .push(Router::with_path("{kind}/{value}").delete(jwt_unrevoke)),
+        )
+        .push(
+            Router::with_path("auth/chain")
+                .get(get_auth_chain)
+                .push(Router::with_path("{id}").get(get_auth_chain)),
+        )
+        .push(
+            Router::with_path("auth/users")
+                .get(get_auth_users)
+                .post(add_auth_user)
+                .push(Router::with_path("import").post(import_auth_users))
+                .push(
+                    Router::with_path("{username}")
+                        .get(get_auth_user)
+                        .put(update_auth_user)
+                        .delete(delete_auth_user),
+                ),
+        )
         .push(
             Router::with_path("mqtt")
                 .push(Router::with_path("publish").post(publish))
@@ -121,6 +176,28 @@ fn route(cfg: PluginConfigType, token: Option<String>) -> Router {
                 .push(Router::with_path("sum").get(get_metrics_sum))
                 .push(Router::with_path("{id}").get(get_metrics)),
         )
+        .push(
+            Router::with_path("topic_metrics")
+                .get(get_topic_metrics)
+                .post(register_topic_metrics)
+                .push(Router::with_path("{**topic}").get(get_topic_metrics).delete(unregister_topic_metrics)),
+        )
+        .push(
+            Router::with_path("trace").get(get_traces).post(start_trace).push(
+                Router::with_path("{name}")
+                    .delete(delete_trace)
+                    .push(Router::with_path("stop").put(stop_trace))
+                    .push(Router::with_path("log").get(get_trace_log))
+                    .push(Router::with_path("download").get(download_trace))
+                    .push(Router::with_path("stream").get(stream_trace)),
+            ),
+        )
+        .push(
+            Router::with_path("alarms")
+                .get(get_alarms)
+                .delete(clear_deactivated_alarms)
+                .push(Router::with_path("{id}").get(get_alarms)),
+        )
 }
 
 pub(crate) async fn listen_and_serve(
@@ -232,6 +309,161 @@ async fn list_apis(res: &mut Response) {
             "descr": "Get routing information from the cluster"
         },
 
+        {
+            "name": "get_delayeds",
+            "method": "GET",
+            "path": "/delayed",
+            "descr": "Return pending delayed messages from the cluster"
+        },
+        {
+            "name": "get_delayed",
+            "method": "GET",
+            "path": "/delayed/{node}/{id}",
+            "descr": "Get a pending delayed message"
+        },
+        {
+            "name": "cancel_delayed",
+            "method": "DELETE",
+            "path": "/delayed/{node}/{id}",
+            "descr": "Cancel a pending delayed message"
+        },
+
+        {
+            "name": "reload_listeners",
+            "method": "PUT",
+            "path": "/listeners/reload",
+            "descr": "Reload the listener configuration of all nodes in the cluster"
+        },
+        {
+            "name": "node_reload_listeners",
+            "method": "PUT",
+            "path": "/listeners/{node}/reload",
+            "descr": "Reload the listener configuration of the specified node"
+        },
+
+        {
+            "name": "get_banneds",
+            "method": "GET",
+            "path": "/banned",
+            "descr": "Get the banned list"
+        },
+        {
+            "name": "ban",
+            "method": "POST",
+            "path": "/banned",
+            "descr": "Ban a client id, username or IP address/CIDR range on all nodes in the cluster"
+        },
+        {
+            "name": "unban",
+            "method": "DELETE",
+            "path": "/banned/{kind}/{value}",
+            "descr": "Remove an entry from the banned list on all nodes in the cluster"
+        },
+
+        {
+            "name": "get_acl_rules",
+            "method": "GET",
+            "path": "/acl/rules",
+            "descr": "Get the rules of the rmqtt-acl plugin"
+        },
+        {
+            "name": "insert_acl_rule",
+            "method": "POST",
+            "path": "/acl/rules",
+            "descr": "Insert an ACL rule at a position on all nodes in the cluster"
+        },
+        {
+            "name": "update_acl_rule",
+            "method": "PUT",
+            "path": "/acl/rules/{index}",
+            "descr": "Replace an ACL rule on all nodes in the cluster"
+        },
+        {
+            "name": "delete_acl_rule",
+            "method": "DELETE",
+            "path": "/acl/rules/{index}",
+            "descr": "Delete an ACL rule on all nodes in the cluster"
+        },
+        {
+            "name": "reset_acl_rules",
+            "method": "POST",
+            "path": "/acl/rules/reset",
+            "descr": "Restore the ACL rules of the configuration file on all nodes in the cluster"
+        },
+        {
+            "name": "validate_acl_rule",
+            "method": "POST",
+            "path": "/acl/rules/validate",
+            "descr": "Check an ACL rule and test it against a sample connection"
+        },
+        {
+            "name": "acl_explain",
+            "method": "POST",
+            "path": "/acl/explain",
+            "descr": "Run the ACL check of a client or a sample connection in dry-run mode and explain the decision"
+        },
+
+        {
+            "name": "get_jwt_revocations",
+            "method": "GET",
+            "path": "/jwt/revocations",
+            "descr": "Get the JWT revocation list"
+        },
+        {
+            "name": "jwt_revoke",
+            "method": "POST",
+            "path": "/jwt/revocations",
+            "descr": "Revoke JWT tokens by 'jti' or 'sub' on all nodes in the cluster"
+        },
+        {
+            "name": "jwt_unrevoke",
+            "method": "DELETE",
+            "path": "/jwt/revocations/{kind}/{value}",
+            "descr": "Remove an entry from the JWT revocation list on all nodes in the cluster"
+        },
+        {
+            "name": "get_auth_chain",
+            "method": "GET",
+            "path": "/auth/chain/{node}",
+            "descr": "Get the authentication chain of the nodes and the results of each authenticator"
+        },
+        {
+            "name": "get_auth_users",
+            "method": "GET",
+            "path": "/auth/users",
+            "descr": "Get the users of the rmqtt-auth-builtin plugin"
+        },
+        {
+            "name": "add_auth_user",
+            "method": "POST",
+            "path": "/auth/users",
+            "descr": "Add a user on all nodes in the cluster"
+        },
+        {
+            "name": "import_auth_users",
+            "method": "POST",
+            "path": "/auth/users/import",
+            "descr": "Import users from CSV or JSON on all nodes in the cluster"
+        },
+        {
+            "name": "get_auth_user",
+            "method": "GET",
+            "path": "/auth/users/{username}",
+            "descr": "Get a user"
+        },
+        {
+            "name": "update_auth_user",
+            "method": "PUT",
+            "path": "/auth/users/{username}",
+            "descr": "Update the password, superuser flag or ACL rules of a user on all nodes in the cluster"
+        },
+        {
+            "name": "delete_auth_user",
+            "method": "DELETE",
+            "path": "/auth/users/{username}",
+            "descr": "Delete a user on all nodes in the cluster"
+        },
+
         {
             "name": "publish",
             "method": "POST",
@@ -327,6 +559,80 @@ async fn list_apis(res: &mut Response) {
           "descr": "Get prometheus metrics from the cluster"
         },
 
+        {
+            "name": "get_topic_metrics",
+            "method": "GET",
+            "path": "/topic_metrics/{topic}",
+            "descr": "Get the message metrics of the monitored topic filters, summarized from the cluster"
+        },
+        {
+            "name": "register_topic_metrics",
+            "method": "POST",
+            "path": "/topic_metrics",
+            "descr": "Start monitoring a topic filter on all nodes in the cluster"
+        },
+        {
+            "name": "unregister_topic_metrics",
+            "method": "DELETE",
+            "path": "/topic_metrics/{topic}",
+            "descr": "Stop monitoring a topic filter on all nodes in the cluster"
+        },
+
+        {
+            "name": "get_traces",
+            "method": "GET",
+            "path": "/trace",
+            "descr": "Returns the traces of the cluster"
+        },
+        {
+            "name": "start_trace",
+            "method": "POST",
+            "path": "/trace",
+            "descr": "Start a trace of a client ID, username, IP address or topic filter on all nodes in the cluster"
+        },
+        {
+            "name": "stop_trace",
+            "method": "PUT",
+            "path": "/trace/{name}/stop",
+            "descr": "Stop the specified trace on all nodes in the cluster, the logs are kept"
+        },
+        {
+            "name": "delete_trace",
+            "method": "DELETE",
+            "path": "/trace/{name}",
+            "descr": "Delete the specified trace and its logs on all nodes in the cluster"
+        },
+        {
+            "name": "get_trace_log",
+            "method": "GET",
+            "path": "/trace/{name}/log",
+            "descr": "Read the log of the specified trace on a node, from a position"
+        },
+        {
+            "name": "download_trace",
+            "method": "GET",
+            "path": "/trace/{name}/download",
+            "descr": "Download the logs of the specified trace of all nodes in the cluster"
+        },
+        {
+            "name": "stream_trace",
+            "method": "GET",
+            "path": "/trace/{name}/stream",
+            "descr": "Stream the new logs of the specified trace of all nodes in the cluster until it stops"
+        },
+        {
+            "name": "get_alarms",
+            "method": "GET",
+            "path": "/alarms/{node}",
+            "descr": "Returns the activated and deactivated alarms of all nodes or the specified node in the cluster"
+        },
+        {
+            "name": "clear_deactivated_alarms",
+            "method": "DELETE",
+            "path": "/alarms",
+            "descr": "Clear the deactivated alarms of all nodes in the cluster"
+        },
+
 
     ]);
     res.render(Json(data));
@@ -957,245 +1263,563 @@ async fn get_route(req: &mut Request, res: &mut Response) {
 }
 
 #[handler]
-async fn publish(req: &mut Request, depot: &mut Depot, res: &mut Response) -> Result<(), salvo::Error> {
+async fn get_delayeds(req: &mut Request, depot: &mut Depot, res: &mut Response) -> Result<(), salvo::Error> {
     let cfg = get_cfg(depot)?;
-    let (http_laddr, expiry_interval) = {
-        let cfg_rl = cfg.read().await;
-        (cfg_rl.http_laddr, cfg_rl.message_expiry_interval)
-    };
-
-    let addr = req.remote_addr();
-    let remote_addr = if let Some(ipv4) = addr.as_ipv4() {
-        Some(SocketAddr::V4(*ipv4))
+    let message_type = cfg.read().await.message_type;
+    let max_row_limit = cfg.read().await.max_row_limit;
+    let limit = req.query::<usize>("_limit");
+    let limit = if let Some(limit) = limit {
+        if limit > max_row_limit {
+            max_row_limit
+        } else {
+            limit
+        }
     } else {
-        addr.as_ipv6().map(|ipv6| SocketAddr::V6(*ipv6))
+        max_row_limit
     };
+    match _get_delayeds(message_type, limit).await {
+        Ok(replys) => res.render(Json(replys)),
+        Err(e) => res.render(StatusError::service_unavailable().detail(e.to_string())),
+    }
+    Ok(())
+}
 
-    let params = match req.parse_json::<PublishParams>().await {
-        Ok(p) => p,
-        Err(e) => {
-            res.render(StatusError::bad_request().detail(e.to_string()));
-            return Ok(());
+async fn _get_delayeds(message_type: MessageType, limit: usize) -> Result<Vec<serde_json::Value>> {
+    let node_id = Runtime::instance().node.id();
+    let mut replys = Runtime::instance()
+        .extends
+        .delayed_sender()
+        .await
+        .list(0, limit)
+        .await?
+        .iter()
+        .map(|dp| delayed_to_json(node_id, dp))
+        .collect::<Vec<_>>();
+    let grpc_clients = Runtime::instance().extends.shared().await.get_grpc_clients();
+    for (id, (_addr, c)) in grpc_clients.iter() {
+        if replys.len() >= limit {
+            break;
         }
+        let q = Message::DelayedList { limit: limit - replys.len() }.encode()?;
+        let reply =
+            MessageSender::new(c.clone(), message_type, GrpcMessage::Data(q), Some(Duration::from_secs(10)))
+                .send()
+                .await;
+        match reply {
+            Ok(GrpcMessageReply::Data(res)) => match MessageReply::decode(&res)? {
+                MessageReply::DelayedList(dps) => {
+                    replys.extend(dps.iter().map(|dp| delayed_to_json(*id, dp)));
+                }
+                _ => unreachable!(),
+            },
+            Err(e) => {
+                log::warn!("Get GrpcMessage::DelayedList, error: {:?}", e);
+            }
+            Ok(reply) => {
+                log::warn!("Get GrpcMessage::DelayedList from other node({}), reply: {:?}", id, reply);
+            }
+        };
+    }
+    Ok(replys)
+}
+
+#[handler]
+async fn get_delayed(req: &mut Request, depot: &mut Depot, res: &mut Response) -> Result<(), salvo::Error> {
+    let cfg = get_cfg(depot)?;
+    let message_type = cfg.read().await.message_type;
+    let (node_id, id) = if let (Some(node_id), Some(id)) =
+        (req.param::<NodeId>("node"), req.param::<DelayedPublishId>("id"))
+    {
+        (node_id, id)
+    } else {
+        res.status_code(StatusCode::NOT_FOUND);
+        return Ok(());
     };
-    match _publish(params, remote_addr, http_laddr, expiry_interval).await {
-        Ok(()) => res.render(Text::Plain("ok")),
+    match _get_delayed(node_id, id, message_type).await {
+        Ok(Some(reply)) => res.render(Json(reply)),
+        Ok(None) => {
+            res.status_code(StatusCode::NOT_FOUND);
+        }
         Err(e) => res.render(StatusError::service_unavailable().detail(e.to_string())),
     }
     Ok(())
 }
 
-async fn _publish(
-    params: PublishParams,
-    remote_addr: Option<SocketAddr>,
-    http_laddr: SocketAddr,
-    expiry_interval: Duration,
-) -> Result<()> {
-    let mut topics = if let Some(topics) = params.topics {
-        topics.split(',').collect::<Vec<_>>().iter().map(|t| TopicName::from(t.trim())).collect()
+async fn _get_delayed(
+    node_id: NodeId,
+    id: DelayedPublishId,
+    message_type: MessageType,
+) -> Result<Option<serde_json::Value>> {
+    let dp = if node_id == Runtime::instance().node.id() {
+        Runtime::instance().extends.delayed_sender().await.get(id).await?
     } else {
-        Vec::new()
+        let c = get_grpc_client(node_id).await?;
+        let msg = Message::DelayedGet { id }.encode()?;
+        let reply =
+            MessageSender::new(c, message_type, GrpcMessage::Data(msg), Some(Duration::from_secs(10)))
+                .send()
+                .await?;
+        match reply {
+            GrpcMessageReply::Data(msg) => match MessageReply::decode(&msg)? {
+                MessageReply::DelayedGet(dp) => dp,
+                _ => unreachable!(),
+            },
+            reply => {
+                log::info!("Get GrpcMessage::DelayedGet from other node({}), reply: {:?}", node_id, reply);
+                return Err(MqttError::from("Invalid Result"));
+            }
+        }
     };
-    if let Some(topic) = params.topic {
-        topics.push(topic);
-    }
-    if topics.is_empty() {
-        return Err(MqttError::Msg("topics or topic is empty".into()));
-    }
-    let qos = QoS::try_from(params.qos).map_err(|e| anyhow::Error::msg(e.to_string()))?;
-    let encoding = params.encoding.to_ascii_lowercase();
-    let payload = if encoding == "plain" {
-        bytes::Bytes::from(params.payload)
-    } else if encoding == "base64" {
-        bytes::Bytes::from(BASE64_STANDARD.decode(params.payload).map_err(anyhow::Error::new)?)
+    Ok(dp.map(|dp| delayed_to_json(node_id, &dp)))
+}
+
+#[handler]
+async fn cancel_delayed(
+    req: &mut Request,
+    depot: &mut Depot,
+    res: &mut Response,
+) -> Result<(), salvo::Error> {
+    let cfg = get_cfg(depot)?;
+    let message_type = cfg.read().await.message_type;
+    let (node_id, id) = if let (Some(node_id), Some(id)) =
+        (req.param::<NodeId>("node"), req.param::<DelayedPublishId>("id"))
+    {
+        (node_id, id)
     } else {
-        return Err(MqttError::Msg("encoding error, currently only plain and base64 are supported".into()));
+        res.status_code(StatusCode::NOT_FOUND);
+        return Ok(());
     };
-
-    let from = From::from_admin(Id::new(
-        Runtime::instance().node.id(),
-        Some(http_laddr),
-        remote_addr,
-        params.clientid,
-        Some(UserName::from("admin")),
-    ));
-    let p = Publish {
-        dup: false,
-        retain: params.retain,
-        qos,
-        topic: "".into(),
-        packet_id: None,
-        payload,
-        properties: PublishProperties::default(),
-        delay_interval: None,
-        create_time: timestamp_millis(),
+    match _cancel_delayed(node_id, id, message_type).await {
+        Ok(Some(reply)) => res.render(Json(reply)),
+        Ok(None) => {
+            res.status_code(StatusCode::NOT_FOUND);
+        }
+        Err(e) => res.render(StatusError::service_unavailable().detail(e.to_string())),
+    }
+    Ok(())
+}
+
+async fn _cancel_delayed(
+    node_id: NodeId,
+    id: DelayedPublishId,
+    message_type: MessageType,
+) -> Result<Option<serde_json::Value>> {
+    let dp = if node_id == Runtime::instance().node.id() {
+        Runtime::instance().extends.delayed_sender().await.cancel(id).await?
+    } else {
+        let c = get_grpc_client(node_id).await?;
+        let msg = Message::DelayedCancel { id }.encode()?;
+        let reply =
+            MessageSender::new(c, message_type, GrpcMessage::Data(msg), Some(Duration::from_secs(10)))
+                .send()
+                .await?;
+        match reply {
+            GrpcMessageReply::Data(msg) => match MessageReply::decode(&msg)? {
+                MessageReply::DelayedCancel(dp) => dp,
+                _ => unreachable!(),
+            },
+            reply => {
+                log::info!(
+                    "Cancel GrpcMessage::DelayedCancel from other node({}), reply: {:?}",
+                    node_id,
+                    reply
+                );
+                return Err(MqttError::from("Invalid Result"));
+            }
+        }
     };
+    Ok(dp.map(|dp| delayed_to_json(node_id, &dp)))
+}
 
-    let message_expiry_interval = params
-        .properties
-        .as_ref()
-        .and_then(|props| {
-            props.message_expiry_interval.map(|interval| Duration::from_secs(interval.get() as u64))
-        })
-        .unwrap_or(expiry_interval);
-    log::debug!("message_expiry_interval: {:?}", message_expiry_interval);
+#[inline]
+fn delayed_to_json(node_id: NodeId, dp: &DelayedPublish) -> serde_json::Value {
+    let mut json = dp.to_json();
+    if let Some(obj) = json.as_object_mut() {
+        obj.insert("node".into(), json!(node_id));
+    }
+    json
+}
 
-    let storage_available = Runtime::instance().extends.message_mgr().await.enable();
+#[handler]
+async fn reload_listeners(
+    req: &mut Request,
+    depot: &mut Depot,
+    res: &mut Response,
+) -> Result<(), salvo::Error> {
+    let cfg = get_cfg(depot)?;
+    let message_type = cfg.read().await.message_type;
 
-    let mut futs = Vec::new();
-    for topic in topics {
-        let from = from.clone();
-        let mut p1 = p.clone();
-        p1.topic = topic;
+    if let Some(node_id) = req.param::<NodeId>("node") {
+        match _reload_listeners(node_id, message_type).await {
+            Ok(reply) => res.render(Json(reply)),
+            Err(e) => res.render(StatusError::service_unavailable().detail(e.to_string())),
+        }
+    } else {
+        match _reload_all_listeners(message_type).await {
+            Ok(replys) => res.render(Json(replys)),
+            Err(e) => res.render(StatusError::service_unavailable().detail(e.to_string())),
+        }
+    }
+    Ok(())
+}
 
-        let fut = async move {
-            //hook, message_publish
-            let p1 = Runtime::instance()
-                .extends
-                .hook_mgr()
-                .await
-                .message_publish(None, from.clone(), &p1)
-                .await
-                .unwrap_or(p1);
+async fn _reload_listeners(node_id: NodeId, message_type: MessageType) -> Result<serde_json::Value> {
+    let changes = if node_id == Runtime::instance().node.id() {
+        Runtime::instance().node.reload_listeners()?
+    } else {
+        let c = get_grpc_client(node_id).await?;
+        let msg = Message::ReloadListeners.encode()?;
+        let reply =
+            MessageSender::new(c, message_type, GrpcMessage::Data(msg), Some(Duration::from_secs(15)))
+                .send()
+                .await?;
+        match reply {
+            GrpcMessageReply::Data(msg) => match MessageReply::decode(&msg)? {
+                MessageReply::ReloadListeners(changes) => changes,
+                _ => unreachable!(),
+            },
+            GrpcMessageReply::Error(e) => return Err(MqttError::from(e)),
+            reply => {
+                log::info!(
+                    "Reload GrpcMessage::ReloadListeners from other node({}), reply: {:?}",
+                    node_id,
+                    reply
+                );
+                return Err(MqttError::from("Invalid Result"));
+            }
+        }
+    };
+    Ok(listener_changes_to_json(node_id, changes.to_json()))
+}
 
-            if let Err(e) =
-                SessionState::forwards(from, p1, storage_available, Some(message_expiry_interval)).await
-            {
-                log::warn!("{:?}", e);
+async fn _reload_all_listeners(message_type: MessageType) -> Result<Vec<serde_json::Value>> {
+    let node_id = Runtime::instance().node.id();
+    let mut replys = match Runtime::instance().node.reload_listeners() {
+        Ok(changes) => vec![listener_changes_to_json(node_id, changes.to_json())],
+        Err(e) => vec![listener_changes_to_json(node_id, json!({ "error": e.to_string() }))],
+    };
+    let grpc_clients = Runtime::instance().extends.shared().await.get_grpc_clients();
+    if !grpc_clients.is_empty() {
+        let msg = Message::ReloadListeners.encode()?;
+        let others = MessageBroadcaster::new(
+            grpc_clients,
+            message_type,
+            GrpcMessage::Data(msg),
+            Some(Duration::from_secs(15)),
+        )
+        .join_all()
+        .await
+        .drain(..)
+        .map(|reply| match reply {
+            (id, Ok(GrpcMessageReply::Data(msg))) => match MessageReply::decode(&msg) {
+                Ok(MessageReply::ReloadListeners(changes)) => {
+                    Ok(listener_changes_to_json(id, changes.to_json()))
+                }
+                Err(e) => Err(e),
+                _ => unreachable!(),
+            },
+            (id, Ok(GrpcMessageReply::Error(e))) => Ok(listener_changes_to_json(id, json!({ "error": e }))),
+            (id, Ok(reply)) => {
+                log::info!("Reload GrpcMessage::ReloadListeners from other node({}), reply: {:?}", id, reply);
+                Ok(listener_changes_to_json(id, json!({ "error": "Invalid Result" })))
             }
-        };
-        futs.push(fut);
+            (id, Err(e)) => {
+                log::warn!("Reload GrpcMessage::ReloadListeners from other node({}), error: {:?}", id, e);
+                Ok(listener_changes_to_json(id, json!({ "error": e.to_string() })))
+            }
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+        replys.extend(others);
     }
-    let _ = futures::future::join_all(futs).await;
-    Ok(())
+    Ok(replys)
+}
+
+#[inline]
+fn listener_changes_to_json(node_id: NodeId, mut json: serde_json::Value) -> serde_json::Value {
+    if let Some(obj) = json.as_object_mut() {
+        obj.insert("node".into(), json!(node_id));
+    }
+    json
 }
 
+const BANNED_PLUGIN: &str = "rmqtt-banned";
+
 #[handler]
-async fn subscribe(req: &mut Request, depot: &mut Depot, res: &mut Response) -> Result<(), salvo::Error> {
-    let params = match req.parse_json::<SubscribeParams>().await {
+async fn get_banneds(res: &mut Response) {
+    match Runtime::instance().plugins.send(BANNED_PLUGIN, json!({ "cmd": "list" })).await {
+        Ok(reply) => res.render(Json(reply)),
+        Err(e) => res.render(StatusError::service_unavailable().detail(e.to_string())),
+    }
+}
+
+//The banned plugin replicates the change to the other nodes
+#[handler]
+async fn ban(req: &mut Request, res: &mut Response) {
+    let mut params = match req.parse_json::<serde_json::Map<String, serde_json::Value>>().await {
         Ok(p) => p,
         Err(e) => {
             res.render(StatusError::bad_request().detail(e.to_string()));
-            return Ok(());
+            return;
         }
     };
+    params.insert("cmd".into(), json!("ban"));
+    match Runtime::instance().plugins.send(BANNED_PLUGIN, serde_json::Value::Object(params)).await {
+        Ok(reply) => res.render(Json(reply)),
+        Err(e) => res.render(StatusError::service_unavailable().detail(e.to_string())),
+    }
+}
 
-    let node_id = if let Some(status) =
-        Runtime::instance().extends.shared().await.session_status(&params.clientid).await
-    {
-        if status.online {
-            status.id.node_id
-        } else {
-            res.render(StatusError::service_unavailable().detail("the session is offline"));
-            return Ok(());
+#[handler]
+async fn unban(req: &mut Request, res: &mut Response) {
+    let (kind, value) = match (req.param::<String>("kind"), req.param::<String>("value")) {
+        (Some(kind), Some(value)) => (kind, value),
+        _ => {
+            res.render(StatusError::bad_request().detail("kind or value is empty"));
+            return;
         }
-    } else {
-        res.render(StatusError::not_found().detail("session does not exist"));
-        return Ok(());
     };
+    let msg = json!({ "cmd": "unban", "kind": kind, "value": value });
+    match Runtime::instance().plugins.send(BANNED_PLUGIN, msg).await {
+        Ok(reply) => res.render(Json(reply)),
+        Err(e) => res.render(StatusError::service_unavailable().detail(e.to_string())),
+    }
+}
 
-    if node_id == Runtime::instance().node.id() {
-        #[allow(clippy::mutable_key_type)]
-        match subs::subscribe(params).await {
-            Ok(replys) => {
-                let replys = replys
-                    .into_iter()
-                    .map(|(t, r)| {
-                        let r = match r {
-                            Ok(b) => serde_json::Value::Bool(b),
-                            Err(e) => serde_json::Value::String(e.to_string()),
-                        };
-                        (t, r)
-                    })
-                    .collect::<HashMap<_, _>>();
-                res.render(Json(replys))
-            }
-            Err(e) => res.render(StatusError::service_unavailable().detail(e.to_string())),
+const ACL_PLUGIN: &str = "rmqtt-acl";
+
+#[handler]
+async fn get_acl_rules(res: &mut Response) {
+    acl_send(res, json!({ "cmd": "list" })).await
+}
+
+//The acl plugin synchronises the change to the other nodes
+#[handler]
+async fn insert_acl_rule(req: &mut Request, res: &mut Response) {
+    match req.parse_json::<serde_json::Map<String, serde_json::Value>>().await {
+        Ok(mut params) => {
+            params.insert("cmd".into(), json!("insert"));
+            acl_send(res, serde_json::Value::Object(params)).await
         }
-    } else {
-        let cfg = get_cfg(depot)?;
-        let message_type = cfg.read().await.message_type;
-        //The session is on another node
-        #[allow(clippy::mutable_key_type)]
-        match _subscribe_on_other_node(message_type, node_id, params).await {
-            Ok(replys) => {
-                let replys = replys
-                    .into_iter()
-                    .map(|(t, r)| {
-                        let r = match r {
-                            (b, None) => serde_json::Value::Bool(b),
-                            (true, _) => serde_json::Value::Bool(true),
-                            (false, Some(reason)) => serde_json::Value::String(reason),
-                        };
-                        (t, r)
-                    })
-                    .collect::<HashMap<_, _>>();
-                res.render(Json(replys))
-            }
-            Err(e) => res.render(StatusError::service_unavailable().detail(e.to_string())),
+        Err(e) => res.render(StatusError::bad_request().detail(e.to_string())),
+    }
+}
+
+#[handler]
+async fn update_acl_rule(req: &mut Request, res: &mut Response) {
+    let index = match req.param::<usize>("index") {
+        Some(index) => index,
+        None => {
+            res.render(StatusError::bad_request().detail("index is invalid"));
+            return;
         }
+    };
+    match req.parse_json::<serde_json::Map<String, serde_json::Value>>().await {
+        Ok(mut params) => {
+            params.insert("cmd".into(), json!("update"));
+            params.insert("index".into(), json!(index));
+            acl_send(res, serde_json::Value::Object(params)).await
+        }
+        Err(e) => res.render(StatusError::bad_request().detail(e.to_string())),
     }
-    Ok(())
 }
 
-#[inline]
-async fn _subscribe_on_other_node(
-    message_type: MessageType,
-    node_id: NodeId,
-    params: SubscribeParams,
-) -> Result<HashMap<TopicFilter, (bool, Option<String>)>> {
-    let c = get_grpc_client(node_id).await?;
-    let q = Message::Subscribe(params).encode()?;
-    let reply = MessageSender::new(c, message_type, GrpcMessage::Data(q), Some(Duration::from_secs(15)))
-        .send()
-        .await?;
-    match reply {
-        GrpcMessageReply::Data(res) => match MessageReply::decode(&res)? {
-            MessageReply::Subscribe(ress) => Ok(ress),
-            _ => unreachable!(),
-        },
-        reply => {
-            log::info!("Subscribe GrpcMessage::Subscribe from other node({}), reply: {:?}", node_id, reply);
-            Err(MqttError::Msg("Invalid Operation".into()))
+#[handler]
+async fn delete_acl_rule(req: &mut Request, res: &mut Response) {
+    match req.param::<usize>("index") {
+        Some(index) => acl_send(res, json!({ "cmd": "delete", "index": index })).await,
+        None => res.render(StatusError::bad_request().detail("index is invalid")),
+    }
+}
+
+#[handler]
+async fn reset_acl_rules(res: &mut Response) {
+    acl_send(res, json!({ "cmd": "reset" })).await
+}
+
+#[handler]
+async fn validate_acl_rule(req: &mut Request, res: &mut Response) {
+    match req.parse_json::<serde_json::Map<String, serde_json::Value>>().await {
+        Ok(mut params) => {
+            params.insert("cmd".into(), json!("validate"));
+            acl_send(res, serde_json::Value::Object(params)).await
         }
+        Err(e) => res.render(StatusError::bad_request().detail(e.to_string())),
+    }
+}
+
+#[inline]
+async fn acl_send(res: &mut Response, msg: serde_json::Value) {
+    match Runtime::instance().plugins.send(ACL_PLUGIN, msg).await {
+        Ok(reply) => res.render(Json(reply)),
+        Err(e) => res.render(StatusError::bad_request().detail(e.to_string())),
     }
 }
 
 #[handler]
-async fn unsubscribe(req: &mut Request, depot: &mut Depot, res: &mut Response) -> Result<(), salvo::Error> {
-    let params = match req.parse_json::<UnsubscribeParams>().await {
+async fn acl_explain(req: &mut Request, depot: &mut Depot, res: &mut Response) -> Result<(), salvo::Error> {
+    let cfg = get_cfg(depot)?;
+    let message_type = cfg.read().await.message_type;
+
+    let params = match req.parse_json::<AclExplainParams>().await {
         Ok(p) => p,
         Err(e) => {
             res.render(StatusError::bad_request().detail(e.to_string()));
             return Ok(());
         }
     };
+    if params.clientid.is_none() && params.connect_info.is_none() {
+        res.render(StatusError::bad_request().detail("clientid or connect_info is required"));
+        return Ok(());
+    }
 
-    let node_id = if let Some(status) =
-        Runtime::instance().extends.shared().await.session_status(&params.clientid).await
-    {
-        if status.online {
-            status.id.node_id
-        } else {
-            res.render(StatusError::service_unavailable().detail("the session is offline"));
-            return Ok(());
+    match _acl_explain(message_type, params).await {
+        Ok(Some(reply)) => res.render(Json(reply)),
+        Ok(None) | Err(MqttError::None) => {
+            res.status_code(StatusCode::NOT_FOUND);
+        }
+        Err(e) => res.render(StatusError::service_unavailable().detail(e.to_string())),
+    }
+    Ok(())
+}
+
+//Sample connections are explained on this node, connected clients on the node that holds the session
+async fn _acl_explain(
+    message_type: MessageType,
+    params: AclExplainParams,
+) -> Result<Option<AclExplainResult>> {
+    if let Some(reply) = explain::explain(&params).await? {
+        return Ok(Some(reply));
+    }
+
+    let check_result = |reply: GrpcMessageReply| match reply {
+        GrpcMessageReply::Data(res) => match MessageReply::decode(&res) {
+            Ok(MessageReply::AclExplain(ress)) => match ress {
+                Some(res) => Ok(res),
+                None => Err(MqttError::None),
+            },
+            Err(e) => Err(e),
+            _ => unreachable!(),
+        },
+        GrpcMessageReply::Error(e) => Err(MqttError::Msg(e)),
+        reply => {
+            log::info!("GrpcMessage::AclExplain from other node, reply: {:?}", reply);
+            Err(MqttError::Msg("Invalid Result".into()))
         }
-    } else {
-        res.render(StatusError::not_found().detail("session does not exist"));
-        return Ok(());
     };
 
-    if node_id == Runtime::instance().node.id() {
-        match subs::unsubscribe(params).await {
-            Ok(()) => res.render(Json(true)),
+    let grpc_clients = Runtime::instance().extends.shared().await.get_grpc_clients();
+    if !grpc_clients.is_empty() {
+        let q = Message::AclExplain(params).encode()?;
+        let reply = MessageBroadcaster::new(
+            grpc_clients,
+            message_type,
+            GrpcMessage::Data(q),
+            Some(Duration::from_secs(10)),
+        )
+        .select_ok(check_result)
+        .await?;
+        return Ok(Some(reply));
+    }
+
+    Ok(None)
+}
+
+const AUTH_BUILTIN_PLUGIN: &str = "rmqtt-auth-builtin";
+
+#[handler]
+async fn get_auth_users(res: &mut Response) {
+    auth_builtin_send(res, json!({ "cmd": "list" })).await
+}
+
+#[handler]
+async fn get_auth_user(req: &mut Request, res: &mut Response) {
+    match req.param::<String>("username") {
+        Some(username) => auth_builtin_send(res, json!({ "cmd": "get", "username": username })).await,
+        None => res.render(StatusError::bad_request().detail("username is empty")),
+    }
+}
+
+//The auth-builtin plugin replicates the change to the other nodes
+#[handler]
+async fn add_auth_user(req: &mut Request, res: &mut Response) {
+    match req.parse_json::<serde_json::Map<String, serde_json::Value>>().await {
+        Ok(mut params) => {
+            params.insert("cmd".into(), json!("add"));
+            auth_builtin_send(res, serde_json::Value::Object(params)).await
+        }
+        Err(e) => res.render(StatusError::bad_request().detail(e.to_string())),
+    }
+}
+
+#[handler]
+async fn update_auth_user(req: &mut Request, res: &mut Response) {
+    let username = match req.param::<String>("username") {
+        Some(username) => username,
+        None => {
+            res.render(StatusError::bad_request().detail("username is empty"));
+            return;
+        }
+    };
+    match req.parse_json::<serde_json::Map<String, serde_json::Value>>().await {
+        Ok(mut params) => {
+            params.insert("cmd".into(), json!("update"));
+            params.insert("username".into(), json!(username));
+            auth_builtin_send(res, serde_json::Value::Object(params)).await
+        }
+        Err(e) => res.render(StatusError::bad_request().detail(e.to_string())),
+    }
+}
+
+#[handler]
+async fn delete_auth_user(req: &mut Request, res: &mut Response) {
+    match req.param::<String>("username") {
+        Some(username) => auth_builtin_send(res, json!({ "cmd": "delete", "username": username })).await,
+        None => res.render(StatusError::bad_request().detail("username is empty")),
+    }
+}
+
+//The body is the CSV or JSON data, the format is taken from the "format" query or the content type
+#[handler]
+async fn import_auth_users(req: &mut Request, res: &mut Response) {
+    let format = req.query::<String>("format").unwrap_or_else(|| {
+        match req.content_type() {
+            Some(m) if m.subtype() == mime::CSV => "csv",
+            _ => "json",
+        }
+        .into()
+    });
+    let overwrite = req.query::<bool>("overwrite").unwrap_or_default();
+    let data = match req.payload().await {
+        Ok(body) => String::from_utf8_lossy(body).to_string(),
+        Err(e) => {
+            res.render(StatusError::bad_request().detail(e.to_string()));
+            return;
+        }
+    };
+    let msg = json!({ "cmd": "import", "format": format, "data": data, "overwrite": overwrite });
+    auth_builtin_send(res, msg).await
+}
+
+#[handler]
+async fn get_auth_chain(
+    req: &mut Request,
+    depot: &mut Depot,
+    res: &mut Response,
+) -> Result<(), salvo::Error> {
+    let cfg = get_cfg(depot)?;
+    let message_type = cfg.read().await.message_type;
+
+    if let Some(id) = req.param::<NodeId>("id") {
+        match _get_auth_chain_one(message_type, id).await {
+            Ok(Some(chain)) => res.render(Json(json!({ "node": id, "chain": chain }))),
+            Ok(None) | Err(MqttError::None) => {
+                res.status_code(StatusCode::NOT_FOUND);
+            }
             Err(e) => res.render(StatusError::service_unavailable().detail(e.to_string())),
         }
     } else {
-        let cfg = get_cfg(depot)?;
-        let message_type = cfg.read().await.message_type;
-        //The session is on another node
-        match _unsubscribe_on_other_node(message_type, node_id, params).await {
-            Ok(()) => res.render(Text::Plain("ok")),
+        match _get_auth_chain_all(message_type).await {
+            Ok(chains) => res.render(Json(chains)),
             Err(e) => res.render(StatusError::service_unavailable().detail(e.to_string())),
         }
     }
@@ -1203,59 +1827,1019 @@ async fn unsubscribe(req: &mut Request, depot: &mut Depot, res: &mut Response) -
 }
 
 #[inline]
-async fn _unsubscribe_on_other_node(
+fn auth_chain_stats() -> Vec<AuthenticatorStats> {
+    AuthChainStats::instance().stats(&Runtime::instance().settings.auth.chain)
+}
+
+async fn _get_auth_chain_one(
     message_type: MessageType,
-    node_id: NodeId,
-    params: UnsubscribeParams,
-) -> Result<()> {
-    let c = get_grpc_client(node_id).await?;
-    let q = Message::Unsubscribe(params).encode()?;
-    let reply = MessageSender::new(c, message_type, GrpcMessage::Data(q), Some(Duration::from_secs(15)))
+    id: NodeId,
+) -> Result<Option<Vec<AuthenticatorStats>>> {
+    if id == Runtime::instance().node.id() {
+        return Ok(Some(auth_chain_stats()));
+    }
+    let grpc_clients = Runtime::instance().extends.shared().await.get_grpc_clients();
+    let c = if let Some(c) = grpc_clients.get(&id).map(|(_, c)| c.clone()) {
+        c
+    } else {
+        return Ok(None);
+    };
+    let msg = Message::AuthChain.encode()?;
+    let reply = MessageSender::new(c, message_type, GrpcMessage::Data(msg), Some(Duration::from_secs(10)))
         .send()
         .await?;
     match reply {
-        GrpcMessageReply::Data(res) => match MessageReply::decode(&res)? {
-            MessageReply::Unsubscribe => Ok(()),
+        GrpcMessageReply::Data(msg) => match MessageReply::decode(&msg)? {
+            MessageReply::AuthChain(chain) => Ok(Some(chain)),
             _ => unreachable!(),
         },
         reply => {
-            log::info!(
-                "Unsubscribe GrpcMessage::Unsubscribe from other node({}), reply: {:?}",
-                node_id,
-                reply
-            );
-            Err(MqttError::Msg("Invalid Operation".into()))
+            log::info!("Get GrpcMessage::AuthChain from other node({}), reply: {:?}", id, reply);
+            Err(MqttError::from("Invalid Result"))
+        }
+    }
+}
+
+async fn _get_auth_chain_all(message_type: MessageType) -> Result<Vec<serde_json::Value>> {
+    let mut chains = vec![json!({
+        "node": Runtime::instance().node.id(),
+        "chain": auth_chain_stats(),
+    })];
+    let grpc_clients = Runtime::instance().extends.shared().await.get_grpc_clients();
+    if !grpc_clients.is_empty() {
+        let msg = Message::AuthChain.encode()?;
+        let replys = MessageBroadcaster::new(
+            grpc_clients,
+            message_type,
+            GrpcMessage::Data(msg),
+            Some(Duration::from_secs(10)),
+        )
+        .join_all()
+        .await
+        .drain(..)
+        .map(|(node_id, reply)| {
+            let chain = match reply {
+                Ok(GrpcMessageReply::Data(reply_msg)) => match MessageReply::decode(&reply_msg) {
+                    Ok(MessageReply::AuthChain(chain)) => json!(chain),
+                    Err(e) => serde_json::Value::String(e.to_string()),
+                    _ => unreachable!(),
+                },
+                Ok(_) => serde_json::Value::String("Invalid Result".into()),
+                Err(e) => serde_json::Value::String(e.to_string()),
+            };
+            json!({
+                "node": node_id,
+                "chain": chain,
+            })
+        })
+        .collect::<Vec<_>>();
+        chains.extend(replys);
+    }
+    Ok(chains)
+}
+
+//Null is returned by the plugin if the user does not exist
+#[inline]
+async fn auth_builtin_send(res: &mut Response, msg: serde_json::Value) {
+    match Runtime::instance().plugins.send(AUTH_BUILTIN_PLUGIN, msg).await {
+        Ok(serde_json::Value::Null) => {
+            res.status_code(StatusCode::NOT_FOUND);
         }
+        Ok(reply) => res.render(Json(reply)),
+        Err(e) => res.render(StatusError::bad_request().detail(e.to_string())),
+    }
+}
+
+const AUTH_JWT_PLUGIN: &str = "rmqtt-auth-jwt";
+
+#[handler]
+async fn get_jwt_revocations(res: &mut Response) {
+    match Runtime::instance().plugins.send(AUTH_JWT_PLUGIN, json!({ "cmd": "list" })).await {
+        Ok(reply) => res.render(Json(reply)),
+        Err(e) => res.render(StatusError::service_unavailable().detail(e.to_string())),
     }
 }
 
 #[handler]
-async fn all_plugins(depot: &mut Depot, res: &mut Response) -> Result<(), salvo::Error> {
+async fn jwt_revoke(req: &mut Request, depot: &mut Depot, res: &mut Response) -> Result<(), salvo::Error> {
     let cfg = get_cfg(depot)?;
     let message_type = cfg.read().await.message_type;
 
-    match _all_plugins(message_type).await {
-        Ok(pluginss) => res.render(Json(pluginss)),
+    let params = match req.parse_json::<JwtRevokeParams>().await {
+        Ok(p) => p,
+        Err(e) => {
+            res.render(StatusError::bad_request().detail(e.to_string()));
+            return Ok(());
+        }
+    };
+    let msg = json!({
+        "cmd": "revoke",
+        "kind": params.kind,
+        "value": params.value,
+        "expire_at": params.expire_at,
+    });
+    match _plugin_send_all(AUTH_JWT_PLUGIN, msg, message_type).await {
+        Ok(replys) => res.render(Json(replys)),
+        Err(e) => res.render(StatusError::service_unavailable().detail(e.to_string())),
+    }
+    Ok(())
+}
+
+#[handler]
+async fn jwt_unrevoke(req: &mut Request, depot: &mut Depot, res: &mut Response) -> Result<(), salvo::Error> {
+    let cfg = get_cfg(depot)?;
+    let message_type = cfg.read().await.message_type;
+
+    let (kind, value) = match (req.param::<String>("kind"), req.param::<String>("value")) {
+        (Some(kind), Some(value)) => (kind, value),
+        _ => {
+            res.render(StatusError::bad_request().detail("kind or value is empty"));
+            return Ok(());
+        }
+    };
+    let msg = json!({ "cmd": "unrevoke", "kind": kind, "value": value });
+    match _plugin_send_all(AUTH_JWT_PLUGIN, msg, message_type).await {
+        Ok(replys) => res.render(Json(replys)),
+        Err(e) => res.render(StatusError::service_unavailable().detail(e.to_string())),
+    }
+    Ok(())
+}
+
+//Sends the message to the plugin on all nodes in the cluster, the replies are tagged with the node id
+async fn _plugin_send_all(
+    name: &str,
+    msg: serde_json::Value,
+    message_type: MessageType,
+) -> Result<Vec<serde_json::Value>> {
+    let node_id = Runtime::instance().node.id();
+    let mut replys = match Runtime::instance().plugins.send(name, msg.clone()).await {
+        Ok(reply) => vec![plugin_reply_to_json(node_id, reply)],
+        Err(e) => vec![plugin_reply_to_json(node_id, json!({ "error": e.to_string() }))],
+    };
+    let grpc_clients = Runtime::instance().extends.shared().await.get_grpc_clients();
+    if !grpc_clients.is_empty() {
+        let msg = Message::PluginSend { name, msg: msg.to_string() }.encode()?;
+        let others = MessageBroadcaster::new(
+            grpc_clients,
+            message_type,
+            GrpcMessage::Data(msg),
+            Some(Duration::from_secs(15)),
+        )
+        .join_all()
+        .await
+        .drain(..)
+        .map(|reply| match reply {
+            (id, Ok(GrpcMessageReply::Data(msg))) => match MessageReply::decode(&msg) {
+                Ok(MessageReply::PluginSend(reply)) => serde_json::from_str(&reply)
+                    .map(|reply| plugin_reply_to_json(id, reply))
+                    .map_err(MqttError::from),
+                Err(e) => Err(e),
+                _ => unreachable!(),
+            },
+            (id, Ok(GrpcMessageReply::Error(e))) => Ok(plugin_reply_to_json(id, json!({ "error": e }))),
+            (id, Ok(reply)) => {
+                log::info!("GrpcMessage::PluginSend from other node({}), reply: {:?}", id, reply);
+                Ok(plugin_reply_to_json(id, json!({ "error": "Invalid Result" })))
+            }
+            (id, Err(e)) => {
+                log::warn!("GrpcMessage::PluginSend from other node({}), error: {:?}", id, e);
+                Ok(plugin_reply_to_json(id, json!({ "error": e.to_string() })))
+            }
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+        replys.extend(others);
+    }
+    Ok(replys)
+}
+
+#[inline]
+fn plugin_reply_to_json(node_id: NodeId, reply: serde_json::Value) -> serde_json::Value {
+    let mut json = if reply.is_object() { reply } else { json!({ "result": reply }) };
+    if let Some(obj) = json.as_object_mut() {
+        obj.insert("node".into(), json!(node_id));
+    }
+    json
+}
+
+#[handler]
+async fn publish(req: &mut Request, depot: &mut Depot, res: &mut Response) -> Result<(), salvo::Error> {
+    let cfg = get_cfg(depot)?;
+    let (http_laddr, expiry_interval) = {
+        let cfg_rl = cfg.read().await;
+        (cfg_rl.http_laddr, cfg_rl.message_expiry_interval)
+    };
+
+    let addr = req.remote_addr();
+    let remote_addr = if let Some(ipv4) = addr.as_ipv4() {
+        Some(SocketAddr::V4(*ipv4))
+    } else {
+        addr.as_ipv6().map(|ipv6| SocketAddr::V6(*ipv6))
+    };
+
+    let params = match req.parse_json::<PublishParams>().await {
+        Ok(p) => p,
+        Err(e) => {
+            res.render(StatusError::bad_request().detail(e.to_string()));
+            return Ok(());
+        }
+    };
+    match _publish(params, remote_addr, http_laddr, expiry_interval).await {
+        Ok(()) => res.render(Text::Plain("ok")),
         Err(e) => res.render(StatusError::service_unavailable().detail(e.to_string())),
     }
-    Ok(())
+    Ok(())
+}
+
+async fn _publish(
+    params: PublishParams,
+    remote_addr: Option<SocketAddr>,
+    http_laddr: SocketAddr,
+    expiry_interval: Duration,
+) -> Result<()> {
+    let mut topics = if let Some(topics) = params.topics {
+        topics.split(',').collect::<Vec<_>>().iter().map(|t| TopicName::from(t.trim())).collect()
+    } else {
+        Vec::new()
+    };
+    if let Some(topic) = params.topic {
+        topics.push(topic);
+    }
+    if topics.is_empty() {
+        return Err(MqttError::Msg("topics or topic is empty".into()));
+    }
+    let qos = QoS::try_from(params.qos).map_err(|e| anyhow::Error::msg(e.to_string()))?;
+    let encoding = params.encoding.to_ascii_lowercase();
+    let payload = if encoding == "plain" {
+        bytes::Bytes::from(params.payload)
+    } else if encoding == "base64" {
+        bytes::Bytes::from(BASE64_STANDARD.decode(params.payload).map_err(anyhow::Error::new)?)
+    } else {
+        return Err(MqttError::Msg("encoding error, currently only plain and base64 are supported".into()));
+    };
+
+    let from = From::from_admin(Id::new(
+        Runtime::instance().node.id(),
+        Some(http_laddr),
+        remote_addr,
+        params.clientid,
+        Some(UserName::from("admin")),
+    ));
+    let p = Publish {
+        dup: false,
+        retain: params.retain,
+        qos,
+        topic: "".into(),
+        packet_id: None,
+        payload,
+        properties: PublishProperties::default(),
+        delay_interval: None,
+        create_time: timestamp_millis(),
+    };
+
+    let message_expiry_interval = params
+        .properties
+        .as_ref()
+        .and_then(|props| {
+            props.message_expiry_interval.map(|interval| Duration::from_secs(interval.get() as u64))
+        })
+        .unwrap_or(expiry_interval);
+    log::debug!("message_expiry_interval: {:?}", message_expiry_interval);
+
+    let storage_available = Runtime::instance().extends.message_mgr().await.enable();
+
+    let mut futs = Vec::new();
+    for topic in topics {
+        let from = from.clone();
+        let mut p1 = p.clone();
+        p1.topic = topic;
+
+        let fut = async move {
+            //hook, message_publish
+            let p1 = Runtime::instance()
+                .extends
+                .hook_mgr()
+                .await
+                .message_publish(None, from.clone(), &p1)
+                .await
+                .unwrap_or(p1);
+
+            if let Err(e) =
+                SessionState::forwards(from, p1, storage_available, Some(message_expiry_interval)).await
+            {
+                log::warn!("{:?}", e);
+            }
+        };
+        futs.push(fut);
+    }
+    let _ = futures::future::join_all(futs).await;
+    Ok(())
+}
+
+#[handler]
+async fn subscribe(req: &mut Request, depot: &mut Depot, res: &mut Response) -> Result<(), salvo::Error> {
+    let params = match req.parse_json::<SubscribeParams>().await {
+        Ok(p) => p,
+        Err(e) => {
+            res.render(StatusError::bad_request().detail(e.to_string()));
+            return Ok(());
+        }
+    };
+
+    let node_id = if let Some(status) =
+        Runtime::instance().extends.shared().await.session_status(&params.clientid).await
+    {
+        if status.online {
+            status.id.node_id
+        } else {
+            res.render(StatusError::service_unavailable().detail("the session is offline"));
+            return Ok(());
+        }
+    } else {
+        res.render(StatusError::not_found().detail("session does not exist"));
+        return Ok(());
+    };
+
+    if node_id == Runtime::instance().node.id() {
+        #[allow(clippy::mutable_key_type)]
+        match subs::subscribe(params).await {
+            Ok(replys) => {
+                let replys = replys
+                    .into_iter()
+                    .map(|(t, r)| {
+                        let r = match r {
+                            Ok(b) => serde_json::Value::Bool(b),
+                            Err(e) => serde_json::Value::String(e.to_string()),
+                        };
+                        (t, r)
+                    })
+                    .collect::<HashMap<_, _>>();
+                res.render(Json(replys))
+            }
+            Err(e) => res.render(StatusError::service_unavailable().detail(e.to_string())),
+        }
+    } else {
+        let cfg = get_cfg(depot)?;
+        let message_type = cfg.read().await.message_type;
+        //The session is on another node
+        #[allow(clippy::mutable_key_type)]
+        match _subscribe_on_other_node(message_type, node_id, params).await {
+            Ok(replys) => {
+                let replys = replys
+                    .into_iter()
+                    .map(|(t, r)| {
+                        let r = match r {
+                            (b, None) => serde_json::Value::Bool(b),
+                            (true, _) => serde_json::Value::Bool(true),
+                            (false, Some(reason)) => serde_json::Value::String(reason),
+                        };
+                        (t, r)
+                    })
+                    .collect::<HashMap<_, _>>();
+                res.render(Json(replys))
+            }
+            Err(e) => res.render(StatusError::service_unavailable().detail(e.to_string())),
+        }
+    }
+    Ok(())
+}
+
+#[inline]
+async fn _subscribe_on_other_node(
+    message_type: MessageType,
+    node_id: NodeId,
+    params: SubscribeParams,
+) -> Result<HashMap<TopicFilter, (bool, Option<String>)>> {
+    let c = get_grpc_client(node_id).await?;
+    let q = Message::Subscribe(params).encode()?;
+    let reply = MessageSender::new(c, message_type, GrpcMessage::Data(q), Some(Duration::from_secs(15)))
+        .send()
+        .await?;
+    match reply {
+        GrpcMessageReply::Data(res) => match MessageReply::decode(&res)? {
+            MessageReply::Subscribe(ress) => Ok(ress),
+            _ => unreachable!(),
+        },
+        reply => {
+            log::info!("Subscribe GrpcMessage::Subscribe from other node({}), reply: {:?}", node_id, reply);
+            Err(MqttError::Msg("Invalid Operation".into()))
+        }
+    }
+}
+
+#[handler]
+async fn unsubscribe(req: &mut Request, depot: &mut Depot, res: &mut Response) -> Result<(), salvo::Error> {
+    let params = match req.parse_json::<UnsubscribeParams>().await {
+        Ok(p) => p,
+        Err(e) => {
+            res.render(StatusError::bad_request().detail(e.to_string()));
+            return Ok(());
+        }
+    };
+
+    let node_id = if let Some(status) =
+        Runtime::instance().extends.shared().await.session_status(&params.clientid).await
+    {
+        if status.online {
+            status.id.node_id
+        } else {
+            res.render(StatusError::service_unavailable().detail("the session is offline"));
+            return Ok(());
+        }
+    } else {
+        res.render(StatusError::not_found().detail("session does not exist"));
+        return Ok(());
+    };
+
+    if node_id == Runtime::instance().node.id() {
+        match subs::unsubscribe(params).await {
+            Ok(()) => res.render(Json(true)),
+            Err(e) => res.render(StatusError::service_unavailable().detail(e.to_string())),
+        }
+    } else {
+        let cfg = get_cfg(depot)?;
+        let message_type = cfg.read().await.message_type;
+        //The session is on another node
+        match _unsubscribe_on_other_node(message_type, node_id, params).await {
+            Ok(()) => res.render(Text::Plain("ok")),
+            Err(e) => res.render(StatusError::service_unavailable().detail(e.to_string())),
+        }
+    }
+    Ok(())
+}
+
+#[inline]
+async fn _unsubscribe_on_other_node(
+    message_type: MessageType,
+    node_id: NodeId,
+    params: UnsubscribeParams,
+) -> Result<()> {
+    let c = get_grpc_client(node_id).await?;
+    let q = Message::Unsubscribe(params).encode()?;
+    let reply = MessageSender::new(c, message_type, GrpcMessage::Data(q), Some(Duration::from_secs(15)))
+        .send()
+        .await?;
+    match reply {
+        GrpcMessageReply::Data(res) => match MessageReply::decode(&res)? {
+            MessageReply::Unsubscribe => Ok(()),
+            _ => unreachable!(),
+        },
+        reply => {
+            log::info!(
+                "Unsubscribe GrpcMessage::Unsubscribe from other node({}), reply: {:?}",
+                node_id,
+                reply
+            );
+            Err(MqttError::Msg("Invalid Operation".into()))
+        }
+    }
+}
+
+#[handler]
+async fn all_plugins(depot: &mut Depot, res: &mut Response) -> Result<(), salvo::Error> {
+    let cfg = get_cfg(depot)?;
+    let message_type = cfg.read().await.message_type;
+
+    match _all_plugins(message_type).await {
+        Ok(pluginss) => res.render(Json(pluginss)),
+        Err(e) => res.render(StatusError::service_unavailable().detail(e.to_string())),
+    }
+    Ok(())
+}
+
+#[inline]
+async fn _all_plugins(message_type: MessageType) -> Result<Vec<serde_json::Value>> {
+    let mut pluginss = Vec::new();
+    let node_id = Runtime::instance().node.id();
+    let plugins = plugin::get_plugins().await?;
+    let plugins = plugins.into_iter().map(|p| p.to_json()).collect::<Result<Vec<_>>>()?;
+    pluginss.push(json!({
+        "node": node_id,
+        "plugins": plugins,
+    }));
+
+    let grpc_clients = Runtime::instance().extends.shared().await.get_grpc_clients();
+    if !grpc_clients.is_empty() {
+        let msg = Message::GetPlugins.encode()?;
+        let replys = MessageBroadcaster::new(
+            grpc_clients,
+            message_type,
+            GrpcMessage::Data(msg),
+            Some(Duration::from_secs(10)),
+        )
+        .join_all()
+        .await
+        .drain(..)
+        .map(|(node_id, reply)| {
+            let plugins = match reply {
+                Ok(GrpcMessageReply::Data(reply_msg)) => match MessageReply::decode(&reply_msg) {
+                    Ok(MessageReply::GetPlugins(plugins)) => {
+                        match plugins.into_iter().map(|p| p.to_json()).collect::<Result<Vec<_>>>() {
+                            Ok(plugins) => serde_json::Value::Array(plugins),
+                            Err(e) => serde_json::Value::String(e.to_string()),
+                        }
+                    }
+                    Err(e) => serde_json::Value::String(e.to_string()),
+                    _ => unreachable!(),
+                },
+                Ok(_) => serde_json::Value::String("Invalid Result".into()),
+                Err(e) => serde_json::Value::String(e.to_string()),
+            };
+            json!({
+                "node": node_id,
+                "plugins": plugins,
+            })
+        })
+        .collect::<Vec<_>>();
+        pluginss.extend(replys);
+    }
+    Ok(pluginss)
+}
+
+#[handler]
+async fn node_plugins(req: &mut Request, depot: &mut Depot, res: &mut Response) -> Result<(), salvo::Error> {
+    let cfg = get_cfg(depot)?;
+    let message_type = cfg.read().await.message_type;
+    let node_id = if let Some(node_id) = req.param::<NodeId>("node") {
+        node_id
+    } else {
+        res.status_code(StatusCode::NOT_FOUND);
+        return Ok(());
+    };
+    match _node_plugins(node_id, message_type).await {
+        Ok(plugins) => res.render(Json(plugins)),
+        Err(e) => res.render(StatusError::service_unavailable().detail(e.to_string())),
+    }
+    Ok(())
+}
+
+async fn _node_plugins(node_id: NodeId, message_type: MessageType) -> Result<Vec<serde_json::Value>> {
+    let plugins = if node_id == Runtime::instance().node.id() {
+        plugin::get_plugins().await?
+    } else {
+        let c = get_grpc_client(node_id).await?;
+        let msg = Message::GetPlugins.encode()?;
+        let reply =
+            MessageSender::new(c, message_type, GrpcMessage::Data(msg), Some(Duration::from_secs(10)))
+                .send()
+                .await?;
+        match reply {
+            GrpcMessageReply::Data(msg) => match MessageReply::decode(&msg)? {
+                MessageReply::GetPlugins(plugins) => plugins,
+                _ => unreachable!(),
+            },
+            reply => {
+                log::info!("Get GrpcMessage::GetPlugins from other node({}), reply: {:?}", node_id, reply);
+                return Err(MqttError::from("Invalid Result"));
+            }
+        }
+    };
+    plugins.into_iter().map(|p| p.to_json()).collect::<Result<Vec<_>>>()
+}
+
+#[handler]
+async fn node_plugin_info(
+    req: &mut Request,
+    depot: &mut Depot,
+    res: &mut Response,
+) -> Result<(), salvo::Error> {
+    let cfg = get_cfg(depot)?;
+    let message_type = cfg.read().await.message_type;
+    let node_id = if let Some(node_id) = req.param::<NodeId>("node") {
+        node_id
+    } else {
+        res.status_code(StatusCode::NOT_FOUND);
+        return Ok(());
+    };
+    let name = if let Some(name) = req.param::<String>("plugin") {
+        name
+    } else {
+        res.status_code(StatusCode::NOT_FOUND);
+        return Ok(());
+    };
+
+    match _node_plugin_info(node_id, &name, message_type).await {
+        Ok(plugin) => res.render(Json(plugin)),
+        Err(e) => res.render(StatusError::service_unavailable().detail(e.to_string())),
+    }
+
+    Ok(())
+}
+
+async fn _node_plugin_info(
+    node_id: NodeId,
+    name: &str,
+    message_type: MessageType,
+) -> Result<Option<serde_json::Value>> {
+    let plugin = if node_id == Runtime::instance().node.id() {
+        plugin::get_plugin(name).await?
+    } else {
+        let c = get_grpc_client(node_id).await?;
+        let msg = Message::GetPlugin { name }.encode()?;
+        let reply =
+            MessageSender::new(c, message_type, GrpcMessage::Data(msg), Some(Duration::from_secs(10)))
+                .send()
+                .await?;
+        match reply {
+            GrpcMessageReply::Data(msg) => match MessageReply::decode(&msg)? {
+                MessageReply::GetPlugin(plugin) => plugin,
+                _ => unreachable!(),
+            },
+            reply => {
+                log::info!("Get GrpcMessage::GetPlugin from other node({}), reply: {:?}", node_id, reply);
+                return Err(MqttError::from("Invalid Result"));
+            }
+        }
+    };
+    if let Some(plugin) = plugin {
+        Ok(Some(plugin.to_json()?))
+    } else {
+        Ok(None)
+    }
+}
+
+#[handler]
+async fn node_plugin_config(
+    req: &mut Request,
+    depot: &mut Depot,
+    res: &mut Response,
+) -> Result<(), salvo::Error> {
+    let cfg = get_cfg(depot)?;
+    let message_type = cfg.read().await.message_type;
+    let node_id = if let Some(node_id) = req.param::<NodeId>("node") {
+        node_id
+    } else {
+        res.status_code(StatusCode::NOT_FOUND);
+        return Ok(());
+    };
+    let name = if let Some(name) = req.param::<String>("plugin") {
+        name
+    } else {
+        res.status_code(StatusCode::NOT_FOUND);
+        return Ok(());
+    };
+
+    match _node_plugin_config(node_id, &name, message_type).await {
+        Ok(cfg) => {
+            res.headers_mut()
+                .insert(CONTENT_TYPE, HeaderValue::from_static("application/json; charset=utf-8"));
+            res.write_body(cfg).ok();
+        }
+        Err(e) => res.render(StatusError::service_unavailable().detail(e.to_string())),
+    }
+    Ok(())
+}
+
+async fn _node_plugin_config(node_id: NodeId, name: &str, message_type: MessageType) -> Result<Vec<u8>> {
+    let plugin_cfg = if node_id == Runtime::instance().node.id() {
+        plugin::get_plugin_config(name).await?
+    } else {
+        let c = get_grpc_client(node_id).await?;
+        let msg = Message::GetPluginConfig { name }.encode()?;
+        let reply =
+            MessageSender::new(c, message_type, GrpcMessage::Data(msg), Some(Duration::from_secs(10)))
+                .send()
+                .await?;
+        match reply {
+            GrpcMessageReply::Data(msg) => match MessageReply::decode(&msg)? {
+                MessageReply::GetPluginConfig(cfg) => cfg,
+                _ => unreachable!(),
+            },
+            reply => {
+                log::info!(
+                    "Get GrpcMessage::GetPluginConfig from other node({}), reply: {:?}",
+                    node_id,
+                    reply
+                );
+                return Err(MqttError::from("Invalid Result"));
+            }
+        }
+    };
+    Ok(plugin_cfg)
+}
+
+#[handler]
+async fn node_plugin_config_reload(
+    req: &mut Request,
+    depot: &mut Depot,
+    res: &mut Response,
+) -> Result<(), salvo::Error> {
+    let cfg = get_cfg(depot)?;
+    let message_type = cfg.read().await.message_type;
+    let node_id = if let Some(node_id) = req.param::<NodeId>("node") {
+        node_id
+    } else {
+        res.status_code(StatusCode::NOT_FOUND);
+        return Ok(());
+    };
+    let name = if let Some(name) = req.param::<String>("plugin") {
+        name
+    } else {
+        res.status_code(StatusCode::NOT_FOUND);
+        return Ok(());
+    };
+
+    match _node_plugin_config_reload(node_id, &name, message_type).await {
+        Ok(r) => res.render(Json(r)),
+        Err(e) => res.render(StatusError::service_unavailable().detail(e.to_string())),
+    }
+    Ok(())
+}
+
+async fn _node_plugin_config_reload(node_id: NodeId, name: &str, message_type: MessageType) -> Result<bool> {
+    if node_id == Runtime::instance().node.id() {
+        Runtime::instance().plugins.load_config(name).await?;
+        Ok(true)
+    } else {
+        let c = get_grpc_client(node_id).await?;
+        let msg = Message::ReloadPluginConfig { name }.encode()?;
+        let reply =
+            MessageSender::new(c, message_type, GrpcMessage::Data(msg), Some(Duration::from_secs(15)))
+                .send()
+                .await?;
+        match reply {
+            GrpcMessageReply::Data(msg) => match MessageReply::decode(&msg)? {
+                MessageReply::ReloadPluginConfig => Ok(true),
+                _ => unreachable!(),
+            },
+            reply => {
+                log::info!(
+                    "ConfigReload GrpcMessage::ReloadPluginConfig from other node({}), reply: {:?}",
+                    node_id,
+                    reply
+                );
+                Ok(false)
+            }
+        }
+    }
+}
+
+#[handler]
+async fn node_plugin_load(
+    req: &mut Request,
+    depot: &mut Depot,
+    res: &mut Response,
+) -> Result<(), salvo::Error> {
+    let cfg = get_cfg(depot)?;
+    let message_type = cfg.read().await.message_type;
+    let node_id = if let Some(node_id) = req.param::<NodeId>("node") {
+        node_id
+    } else {
+        res.status_code(StatusCode::NOT_FOUND);
+        return Ok(());
+    };
+    let name = if let Some(name) = req.param::<String>("plugin") {
+        name
+    } else {
+        res.status_code(StatusCode::NOT_FOUND);
+        return Ok(());
+    };
+
+    match _node_plugin_load(node_id, &name, message_type).await {
+        Ok(r) => res.render(Json(r)),
+        Err(e) => res.render(StatusError::service_unavailable().detail(e.to_string())),
+    }
+    Ok(())
+}
+
+async fn _node_plugin_load(node_id: NodeId, name: &str, message_type: MessageType) -> Result<bool> {
+    if node_id == Runtime::instance().node.id() {
+        Runtime::instance().plugins.start(name).await?;
+        Ok(true)
+    } else {
+        let c = get_grpc_client(node_id).await?;
+        let msg = Message::LoadPlugin { name }.encode()?;
+        let reply =
+            MessageSender::new(c, message_type, GrpcMessage::Data(msg), Some(Duration::from_secs(10)))
+                .send()
+                .await?;
+        match reply {
+            GrpcMessageReply::Data(msg) => match MessageReply::decode(&msg)? {
+                MessageReply::LoadPlugin => Ok(true),
+                _ => unreachable!(),
+            },
+            reply => {
+                log::info!("Load GrpcMessage::LoadPlugin from other node({}), reply: {:?}", node_id, reply);
+                Ok(false)
+            }
+        }
+    }
+}
+
+#[handler]
+async fn node_plugin_unload(
+    req: &mut Request,
+    depot: &mut Depot,
+    res: &mut Response,
+) -> Result<(), salvo::Error> {
+    let cfg = get_cfg(depot)?;
+    let message_type = cfg.read().await.message_type;
+    let node_id = if let Some(node_id) = req.param::<NodeId>("node") {
+        node_id
+    } else {
+        res.status_code(StatusCode::NOT_FOUND);
+        return Ok(());
+    };
+    let name = if let Some(name) = req.param::<String>("plugin") {
+        name
+    } else {
+        res.status_code(StatusCode::NOT_FOUND);
+        return Ok(());
+    };
+
+    match _node_plugin_unload(node_id, &name, message_type).await {
+        Ok(r) => res.render(Json(r)),
+        Err(e) => res.render(StatusError::service_unavailable().detail(e.to_string())),
+    }
+    Ok(())
+}
+
+async fn _node_plugin_unload(node_id: NodeId, name: &str, message_type: MessageType) -> Result<bool> {
+    if node_id == Runtime::instance().node.id() {
+        Runtime::instance().plugins.stop(name).await
+    } else {
+        let c = get_grpc_client(node_id).await?;
+        let msg = Message::UnloadPlugin { name }.encode()?;
+        let reply =
+            MessageSender::new(c, message_type, GrpcMessage::Data(msg), Some(Duration::from_secs(10)))
+                .send()
+                .await?;
+        match reply {
+            GrpcMessageReply::Data(msg) => match MessageReply::decode(&msg)? {
+                MessageReply::UnloadPlugin(ok) => Ok(ok),
+                _ => unreachable!(),
+            },
+            reply => {
+                log::info!(
+                    "Unload GrpcMessage::UnloadPlugin from other node({}), reply: {:?}",
+                    node_id,
+                    reply
+                );
+                Ok(false)
+            }
+        }
+    }
+}
+
+#[handler]
+async fn get_stats_sum(depot: &mut Depot, res: &mut Response) -> Result<(), salvo::Error> {
+    let cfg = get_cfg(depot)?;
+    let message_type = cfg.read().await.message_type;
+
+    match _get_stats_sum(message_type).await {
+        Ok(stats_sum) => res.render(Json(stats_sum)),
+        Err(e) => res.render(StatusError::service_unavailable().detail(e.to_string())),
+    }
+    Ok(())
+}
+
+async fn _get_stats_sum(message_type: MessageType) -> Result<serde_json::Value> {
+    let this_id = Runtime::instance().node.id();
+    let mut nodes = HashMap::default();
+    nodes.insert(
+        this_id,
+        json!({
+            "name": Runtime::instance().node.name(this_id).await,
+            "running": Runtime::instance().node.status().await.is_running(),
+        }),
+    );
+
+    let mut stats_sum = Runtime::instance().stats.clone().await;
+    let grpc_clients = Runtime::instance().extends.shared().await.get_grpc_clients();
+    if !grpc_clients.is_empty() {
+        let msg = Message::StatsInfo.encode()?;
+        for reply in MessageBroadcaster::new(
+            grpc_clients,
+            message_type,
+            GrpcMessage::Data(msg),
+            Some(Duration::from_secs(10)),
+        )
+        .join_all()
+        .await
+        {
+            match reply {
+                (id, Ok(GrpcMessageReply::Data(msg))) => match MessageReply::decode(&msg)? {
+                    MessageReply::StatsInfo(node_status, stats) => {
+                        nodes.insert(
+                            id,
+                            json!({
+                                "name": Runtime::instance().node.name(id).await,
+                                "running": node_status.is_running(),
+                            }),
+                        );
+                        stats_sum.add(*stats);
+                    }
+                    _ => unreachable!(),
+                },
+                (id, Ok(reply)) => {
+                    log::info!("Get GrpcMessage::StateInfo from other node({}), reply: {:?}", id, reply);
+                    continue;
+                }
+                (id, Err(e)) => {
+                    log::warn!("Get GrpcMessage::StateInfo from other node({}), error: {:?}", id, e);
+                    nodes.insert(id, serde_json::Value::String(e.to_string()));
+                }
+            };
+        }
+    }
+
+    let stats_sum = json!({
+        "nodes": nodes,
+        "stats": stats_sum.to_json().await
+    });
+
+    Ok(stats_sum)
+}
+
+#[handler]
+async fn get_stats(req: &mut Request, depot: &mut Depot, res: &mut Response) -> Result<(), salvo::Error> {
+    let cfg = get_cfg(depot)?;
+    let message_type = cfg.read().await.message_type;
+
+    let id = req.param::<NodeId>("id");
+    if let Some(id) = id {
+        match get_stats_one(message_type, id).await {
+            Ok(Some((node_status, stats))) => {
+                let stat_info = _build_stats(id, node_status, stats.to_json().await).await;
+                res.render(Json(stat_info))
+            }
+            Ok(None) | Err(MqttError::None) => {
+                res.status_code(StatusCode::NOT_FOUND);
+            }
+            Err(e) => res.render(StatusError::service_unavailable().detail(e.to_string())),
+        }
+    } else {
+        match get_stats_all(message_type).await {
+            Ok(stats) => {
+                let mut stat_infos = Vec::new();
+                for item in stats {
+                    match item {
+                        Ok((id, node_status, state)) => {
+                            stat_infos.push(_build_stats(id, node_status, state.to_json().await).await);
+                        }
+                        Err(e) => {
+                            stat_infos.push(serde_json::Value::String(e.to_string()));
+                        }
+                    }
+                }
+                res.render(Json(stat_infos))
+            }
+            Err(e) => res.render(StatusError::service_unavailable().detail(e.to_string())),
+        }
+    }
+    Ok(())
+}
+
+#[inline]
+pub(crate) async fn get_stats_one(
+    message_type: MessageType,
+    id: NodeId,
+) -> Result<Option<(NodeStatus, Box<Stats>)>> {
+    if id == Runtime::instance().node.id() {
+        let node_status = Runtime::instance().node.status().await;
+        let stats = Runtime::instance().stats.clone().await;
+        Ok(Some((node_status, Box::new(stats))))
+    } else {
+        let grpc_clients = Runtime::instance().extends.shared().await.get_grpc_clients();
+        if let Some(c) = grpc_clients.get(&id).map(|(_, c)| c.clone()) {
+            let msg = Message::StatsInfo.encode()?;
+            let reply =
+                MessageSender::new(c, message_type, GrpcMessage::Data(msg), Some(Duration::from_secs(10)))
+                    .send()
+                    .await;
+            match reply {
+                Ok(GrpcMessageReply::Data(msg)) => match MessageReply::decode(&msg)? {
+                    MessageReply::StatsInfo(node_status, stats) => Ok(Some((node_status, stats))),
+                    _ => unreachable!(),
+                },
+                Ok(reply) => {
+                    log::info!("Get GrpcMessage::StateInfo from other node, reply: {:?}", reply);
+                    Err(MqttError::from("Invalid Result"))
+                }
+                Err(e) => {
+                    log::warn!("Get GrpcMessage::StateInfo from other node, error: {:?}", e);
+                    Err(e)
+                }
+            }
+        } else {
+            Ok(None)
+        }
+    }
 }
 
 #[inline]
-async fn _all_plugins(message_type: MessageType) -> Result<Vec<serde_json::Value>> {
-    let mut pluginss = Vec::new();
-    let node_id = Runtime::instance().node.id();
-    let plugins = plugin::get_plugins().await?;
-    let plugins = plugins.into_iter().map(|p| p.to_json()).collect::<Result<Vec<_>>>()?;
-    pluginss.push(json!({
-        "node": node_id,
-        "plugins": plugins,
-    }));
+pub(crate) async fn get_stats_all(
+    message_type: MessageType,
+) -> Result<Vec<Result<(NodeId, NodeStatus, Box<Stats>)>>> {
+    let id = Runtime::instance().node.id();
+    let node_status = Runtime::instance().node.status().await;
+    let state = Runtime::instance().stats.clone().await;
+    //let mut stats = vec![_build_stats(id, node_status, state).await];
+    let mut stats = vec![Ok((id, node_status, Box::new(state)))];
 
     let grpc_clients = Runtime::instance().extends.shared().await.get_grpc_clients();
     if !grpc_clients.is_empty() {
-        let msg = Message::GetPlugins.encode()?;
-        let replys = MessageBroadcaster::new(
+        let msg = Message::StatsInfo.encode()?;
+        for reply in MessageBroadcaster::new(
             grpc_clients,
             message_type,
             GrpcMessage::Data(msg),
@@ -1263,558 +2847,591 @@ async fn _all_plugins(message_type: MessageType) -> Result<Vec<serde_json::Value
         )
         .join_all()
         .await
-        .drain(..)
-        .map(|(node_id, reply)| {
-            let plugins = match reply {
-                Ok(GrpcMessageReply::Data(reply_msg)) => match MessageReply::decode(&reply_msg) {
-                    Ok(MessageReply::GetPlugins(plugins)) => {
-                        match plugins.into_iter().map(|p| p.to_json()).collect::<Result<Vec<_>>>() {
-                            Ok(plugins) => serde_json::Value::Array(plugins),
-                            Err(e) => serde_json::Value::String(e.to_string()),
-                        }
-                    }
-                    Err(e) => serde_json::Value::String(e.to_string()),
+        {
+            let data = match reply {
+                (id, Ok(GrpcMessageReply::Data(msg))) => match MessageReply::decode(&msg)? {
+                    MessageReply::StatsInfo(node_status, stats) => Ok((id, node_status, stats)),
                     _ => unreachable!(),
                 },
-                Ok(_) => serde_json::Value::String("Invalid Result".into()),
-                Err(e) => serde_json::Value::String(e.to_string()),
+                (id, Ok(reply)) => {
+                    log::info!("Get GrpcMessage::StateInfo from other node({}), reply: {:?}", id, reply);
+                    continue;
+                }
+                (id, Err(e)) => {
+                    log::warn!("Get GrpcMessage::StateInfo from other node({}), error: {:?}", id, e);
+                    Err(e)
+                }
             };
-            json!({
-                "node": node_id,
-                "plugins": plugins,
-            })
-        })
-        .collect::<Vec<_>>();
-        pluginss.extend(replys);
-    }
-    Ok(pluginss)
-}
-
-#[handler]
-async fn node_plugins(req: &mut Request, depot: &mut Depot, res: &mut Response) -> Result<(), salvo::Error> {
-    let cfg = get_cfg(depot)?;
-    let message_type = cfg.read().await.message_type;
-    let node_id = if let Some(node_id) = req.param::<NodeId>("node") {
-        node_id
-    } else {
-        res.status_code(StatusCode::NOT_FOUND);
-        return Ok(());
-    };
-    match _node_plugins(node_id, message_type).await {
-        Ok(plugins) => res.render(Json(plugins)),
-        Err(e) => res.render(StatusError::service_unavailable().detail(e.to_string())),
+            stats.push(data);
+        }
     }
-    Ok(())
+    Ok(stats)
 }
 
-async fn _node_plugins(node_id: NodeId, message_type: MessageType) -> Result<Vec<serde_json::Value>> {
-    let plugins = if node_id == Runtime::instance().node.id() {
-        plugin::get_plugins().await?
-    } else {
-        let c = get_grpc_client(node_id).await?;
-        let msg = Message::GetPlugins.encode()?;
-        let reply =
-            MessageSender::new(c, message_type, GrpcMessage::Data(msg), Some(Duration::from_secs(10)))
-                .send()
-                .await?;
-        match reply {
-            GrpcMessageReply::Data(msg) => match MessageReply::decode(&msg)? {
-                MessageReply::GetPlugins(plugins) => plugins,
-                _ => unreachable!(),
-            },
-            reply => {
-                log::info!("Get GrpcMessage::GetPlugins from other node({}), reply: {:?}", node_id, reply);
-                return Err(MqttError::from("Invalid Result"));
-            }
-        }
-    };
-    plugins.into_iter().map(|p| p.to_json()).collect::<Result<Vec<_>>>()
+#[inline]
+async fn _build_stats(id: NodeId, node_status: NodeStatus, stats: serde_json::Value) -> serde_json::Value {
+    let node_name = Runtime::instance().node.name(id).await;
+    let data = json!({
+        "node": {
+            "id": id,
+            "name": node_name,
+            "running": node_status.is_running(),
+        },
+        "stats": stats
+    });
+    data
 }
 
 #[handler]
-async fn node_plugin_info(
+async fn get_topic_metrics(
     req: &mut Request,
     depot: &mut Depot,
     res: &mut Response,
 ) -> Result<(), salvo::Error> {
     let cfg = get_cfg(depot)?;
     let message_type = cfg.read().await.message_type;
-    let node_id = if let Some(node_id) = req.param::<NodeId>("node") {
-        node_id
-    } else {
-        res.status_code(StatusCode::NOT_FOUND);
-        return Ok(());
-    };
-    let name = if let Some(name) = req.param::<String>("plugin") {
-        name
-    } else {
-        res.status_code(StatusCode::NOT_FOUND);
-        return Ok(());
-    };
+    let topic = req.param::<String>("topic");
 
-    match _node_plugin_info(node_id, &name, message_type).await {
-        Ok(plugin) => res.render(Json(plugin)),
+    match get_topic_metrics_all(message_type).await {
+        Ok(items) => {
+            let metrics = merge_topic_metrics(items.into_iter().filter_map(|item| match item {
+                Ok((_, infos)) => Some(infos),
+                Err(_) => None,
+            }));
+            if let Some(topic) = topic {
+                if let Some(info) = metrics.iter().find(|info| info.topic == topic) {
+                    res.render(Json(info.to_json()))
+                } else {
+                    res.status_code(StatusCode::NOT_FOUND);
+                }
+            } else {
+                res.render(Json(metrics.iter().map(|info| info.to_json()).collect::<Vec<_>>()))
+            }
+        }
         Err(e) => res.render(StatusError::service_unavailable().detail(e.to_string())),
     }
-
     Ok(())
 }
 
-async fn _node_plugin_info(
-    node_id: NodeId,
-    name: &str,
+#[inline]
+pub(crate) async fn get_topic_metrics_one(
     message_type: MessageType,
-) -> Result<Option<serde_json::Value>> {
-    let plugin = if node_id == Runtime::instance().node.id() {
-        plugin::get_plugin(name).await?
+    id: NodeId,
+) -> Result<Option<Vec<TopicMetricsInfo>>> {
+    if id == Runtime::instance().node.id() {
+        return Ok(Some(TopicMetrics::instance().list()));
+    }
+    let grpc_clients = Runtime::instance().extends.shared().await.get_grpc_clients();
+    let c = if let Some(c) = grpc_clients.get(&id).map(|(_, c)| c.clone()) {
+        c
     } else {
-        let c = get_grpc_client(node_id).await?;
-        let msg = Message::GetPlugin { name }.encode()?;
-        let reply =
-            MessageSender::new(c, message_type, GrpcMessage::Data(msg), Some(Duration::from_secs(10)))
-                .send()
-                .await?;
-        match reply {
-            GrpcMessageReply::Data(msg) => match MessageReply::decode(&msg)? {
-                MessageReply::GetPlugin(plugin) => plugin,
-                _ => unreachable!(),
-            },
-            reply => {
-                log::info!("Get GrpcMessage::GetPlugin from other node({}), reply: {:?}", node_id, reply);
-                return Err(MqttError::from("Invalid Result"));
-            }
-        }
+        return Ok(None);
     };
-    if let Some(plugin) = plugin {
-        Ok(Some(plugin.to_json()?))
-    } else {
-        Ok(None)
+    let msg = Message::TopicMetricsList.encode()?;
+    let reply = MessageSender::new(c, message_type, GrpcMessage::Data(msg), Some(Duration::from_secs(10)))
+        .send()
+        .await?;
+    match reply {
+        GrpcMessageReply::Data(msg) => match MessageReply::decode(&msg)? {
+            MessageReply::TopicMetricsList(infos) => Ok(Some(infos)),
+            _ => unreachable!(),
+        },
+        reply => {
+            log::info!("Get GrpcMessage::TopicMetricsList from other node({}), reply: {:?}", id, reply);
+            Err(MqttError::from("Invalid Result"))
+        }
     }
 }
 
-#[handler]
-async fn node_plugin_config(
-    req: &mut Request,
-    depot: &mut Depot,
-    res: &mut Response,
-) -> Result<(), salvo::Error> {
-    let cfg = get_cfg(depot)?;
-    let message_type = cfg.read().await.message_type;
-    let node_id = if let Some(node_id) = req.param::<NodeId>("node") {
-        node_id
-    } else {
-        res.status_code(StatusCode::NOT_FOUND);
-        return Ok(());
-    };
-    let name = if let Some(name) = req.param::<String>("plugin") {
-        name
-    } else {
-        res.status_code(StatusCode::NOT_FOUND);
-        return Ok(());
-    };
+#[inline]
+pub(crate) async fn get_topic_metrics_all(
+    message_type: MessageType,
+) -> Result<Vec<Result<(NodeId, Vec<TopicMetricsInfo>)>>> {
+    let id = Runtime::instance().node.id();
+    let mut metricses = vec![Ok((id, TopicMetrics::instance().list()))];
 
-    match _node_plugin_config(node_id, &name, message_type).await {
-        Ok(cfg) => {
-            res.headers_mut()
-                .insert(CONTENT_TYPE, HeaderValue::from_static("application/json; charset=utf-8"));
-            res.write_body(cfg).ok();
+    let grpc_clients = Runtime::instance().extends.shared().await.get_grpc_clients();
+    if !grpc_clients.is_empty() {
+        let msg = Message::TopicMetricsList.encode()?;
+        let replys = MessageBroadcaster::new(
+            grpc_clients,
+            message_type,
+            GrpcMessage::Data(msg),
+            Some(Duration::from_secs(10)),
+        )
+        .join_all()
+        .await;
+        for reply in replys {
+            let data = match reply {
+                (id, Ok(GrpcMessageReply::Data(msg))) => match MessageReply::decode(&msg)? {
+                    MessageReply::TopicMetricsList(infos) => Ok((id, infos)),
+                    _ => unreachable!(),
+                },
+                (id, Ok(reply)) => {
+                    log::info!(
+                        "Get GrpcMessage::TopicMetricsList from other node({}), reply: {:?}",
+                        id,
+                        reply
+                    );
+                    continue;
+                }
+                (id, Err(e)) => {
+                    log::warn!("Get GrpcMessage::TopicMetricsList from other node({}), error: {:?}", id, e);
+                    Err(e)
+                }
+            };
+            metricses.push(data);
         }
-        Err(e) => res.render(StatusError::service_unavailable().detail(e.to_string())),
     }
-    Ok(())
+    Ok(metricses)
 }
 
-async fn _node_plugin_config(node_id: NodeId, name: &str, message_type: MessageType) -> Result<Vec<u8>> {
-    let plugin_cfg = if node_id == Runtime::instance().node.id() {
-        plugin::get_plugin_config(name).await?
-    } else {
-        let c = get_grpc_client(node_id).await?;
-        let msg = Message::GetPluginConfig { name }.encode()?;
-        let reply =
-            MessageSender::new(c, message_type, GrpcMessage::Data(msg), Some(Duration::from_secs(10)))
-                .send()
-                .await?;
-        match reply {
-            GrpcMessageReply::Data(msg) => match MessageReply::decode(&msg)? {
-                MessageReply::GetPluginConfig(cfg) => cfg,
-                _ => unreachable!(),
-            },
-            reply => {
-                log::info!(
-                    "Get GrpcMessage::GetPluginConfig from other node({}), reply: {:?}",
-                    node_id,
-                    reply
-                );
-                return Err(MqttError::from("Invalid Result"));
-            }
+//The topic filter is registered on all nodes, true is returned if it was not monitored before
+#[handler]
+async fn register_topic_metrics(
+    req: &mut Request,
+    depot: &mut Depot,
+    res: &mut Response,
+) -> Result<(), salvo::Error> {
+    let cfg = get_cfg(depot)?;
+    let message_type = cfg.read().await.message_type;
+
+    let params = match req.parse_json::<TopicMetricsParams>().await {
+        Ok(p) => p,
+        Err(e) => {
+            res.render(StatusError::bad_request().detail(e.to_string()));
+            return Ok(());
         }
     };
-    Ok(plugin_cfg)
+    let registered = match TopicMetrics::instance().register(&params.topic) {
+        Ok(registered) => registered,
+        Err(e) => {
+            res.render(StatusError::bad_request().detail(e.to_string()));
+            return Ok(());
+        }
+    };
+    let msg = Message::TopicMetricsRegister { topic: &params.topic };
+    match _topic_metrics_broadcast(message_type, msg).await {
+        Ok(others) => res.render(Json(registered || others)),
+        Err(e) => res.render(StatusError::service_unavailable().detail(e.to_string())),
+    }
+    Ok(())
 }
 
 #[handler]
-async fn node_plugin_config_reload(
+async fn unregister_topic_metrics(
     req: &mut Request,
     depot: &mut Depot,
     res: &mut Response,
 ) -> Result<(), salvo::Error> {
     let cfg = get_cfg(depot)?;
     let message_type = cfg.read().await.message_type;
-    let node_id = if let Some(node_id) = req.param::<NodeId>("node") {
-        node_id
-    } else {
-        res.status_code(StatusCode::NOT_FOUND);
-        return Ok(());
-    };
-    let name = if let Some(name) = req.param::<String>("plugin") {
-        name
-    } else {
-        res.status_code(StatusCode::NOT_FOUND);
-        return Ok(());
-    };
 
-    match _node_plugin_config_reload(node_id, &name, message_type).await {
-        Ok(r) => res.render(Json(r)),
+    let topic = match req.param::<String>("topic") {
+        Some(topic) => topic,
+        None => {
+            res.render(StatusError::bad_request().detail("topic is empty"));
+            return Ok(());
+        }
+    };
+    let unregistered = TopicMetrics::instance().unregister(&topic);
+    match _topic_metrics_broadcast(message_type, Message::TopicMetricsUnregister { topic: &topic }).await {
+        Ok(others) if unregistered || others => res.render(Json(true)),
+        Ok(_) => {
+            res.status_code(StatusCode::NOT_FOUND);
+        }
         Err(e) => res.render(StatusError::service_unavailable().detail(e.to_string())),
     }
     Ok(())
 }
 
-async fn _node_plugin_config_reload(node_id: NodeId, name: &str, message_type: MessageType) -> Result<bool> {
-    if node_id == Runtime::instance().node.id() {
-        Runtime::instance().plugins.load_config(name).await?;
-        Ok(true)
-    } else {
-        let c = get_grpc_client(node_id).await?;
-        let msg = Message::ReloadPluginConfig { name }.encode()?;
-        let reply =
-            MessageSender::new(c, message_type, GrpcMessage::Data(msg), Some(Duration::from_secs(15)))
-                .send()
-                .await?;
+//Sends the register or unregister message to the other nodes, true is returned if any of them changed
+async fn _topic_metrics_broadcast(message_type: MessageType, msg: Message<'_>) -> Result<bool> {
+    let grpc_clients = Runtime::instance().extends.shared().await.get_grpc_clients();
+    if grpc_clients.is_empty() {
+        return Ok(false);
+    }
+    let msg = msg.encode()?;
+    let replys = MessageBroadcaster::new(
+        grpc_clients,
+        message_type,
+        GrpcMessage::Data(msg),
+        Some(Duration::from_secs(10)),
+    )
+    .join_all()
+    .await;
+    let mut changed = false;
+    for (id, reply) in replys {
         match reply {
-            GrpcMessageReply::Data(msg) => match MessageReply::decode(&msg)? {
-                MessageReply::ReloadPluginConfig => Ok(true),
+            Ok(GrpcMessageReply::Data(msg)) => match MessageReply::decode(&msg)? {
+                MessageReply::TopicMetricsRegister(c) | MessageReply::TopicMetricsUnregister(c) => {
+                    changed |= c
+                }
                 _ => unreachable!(),
             },
-            reply => {
-                log::info!(
-                    "ConfigReload GrpcMessage::ReloadPluginConfig from other node({}), reply: {:?}",
-                    node_id,
-                    reply
-                );
-                Ok(false)
+            Ok(reply) => {
+                log::warn!("Send topic metrics message to other node({}), reply: {:?}", id, reply);
+            }
+            Err(e) => {
+                log::warn!("Send topic metrics message to other node({}), error: {:?}", id, e);
             }
         }
     }
+    Ok(changed)
 }
 
 #[handler]
-async fn node_plugin_load(
-    req: &mut Request,
-    depot: &mut Depot,
-    res: &mut Response,
-) -> Result<(), salvo::Error> {
+async fn get_traces(depot: &mut Depot, res: &mut Response) -> Result<(), salvo::Error> {
     let cfg = get_cfg(depot)?;
     let message_type = cfg.read().await.message_type;
-    let node_id = if let Some(node_id) = req.param::<NodeId>("node") {
-        node_id
-    } else {
-        res.status_code(StatusCode::NOT_FOUND);
-        return Ok(());
-    };
-    let name = if let Some(name) = req.param::<String>("plugin") {
-        name
-    } else {
-        res.status_code(StatusCode::NOT_FOUND);
-        return Ok(());
-    };
-
-    match _node_plugin_load(node_id, &name, message_type).await {
-        Ok(r) => res.render(Json(r)),
+    match get_traces_all(message_type).await {
+        Ok(traces) => res.render(Json(_merge_traces(traces))),
         Err(e) => res.render(StatusError::service_unavailable().detail(e.to_string())),
     }
     Ok(())
 }
 
-async fn _node_plugin_load(node_id: NodeId, name: &str, message_type: MessageType) -> Result<bool> {
-    if node_id == Runtime::instance().node.id() {
-        Runtime::instance().plugins.start(name).await?;
-        Ok(true)
-    } else {
-        let c = get_grpc_client(node_id).await?;
-        let msg = Message::LoadPlugin { name }.encode()?;
-        let reply =
-            MessageSender::new(c, message_type, GrpcMessage::Data(msg), Some(Duration::from_secs(10)))
-                .send()
-                .await?;
-        match reply {
-            GrpcMessageReply::Data(msg) => match MessageReply::decode(&msg)? {
-                MessageReply::LoadPlugin => Ok(true),
-                _ => unreachable!(),
-            },
-            reply => {
-                log::info!("Load GrpcMessage::LoadPlugin from other node({}), reply: {:?}", node_id, reply);
-                Ok(false)
+#[inline]
+async fn get_traces_all(message_type: MessageType) -> Result<Vec<(NodeId, Vec<TraceInfo>)>> {
+    let mut traces = vec![(Runtime::instance().node.id(), TraceManager::instance().list())];
+
+    let grpc_clients = Runtime::instance().extends.shared().await.get_grpc_clients();
+    if !grpc_clients.is_empty() {
+        let msg = Message::TraceList.encode()?;
+        let replys = MessageBroadcaster::new(
+            grpc_clients,
+            message_type,
+            GrpcMessage::Data(msg),
+            Some(Duration::from_secs(10)),
+        )
+        .join_all()
+        .await;
+        for reply in replys {
+            match reply {
+                (id, Ok(GrpcMessageReply::Data(msg))) => match MessageReply::decode(&msg)? {
+                    MessageReply::TraceList(infos) => traces.push((id, infos)),
+                    _ => unreachable!(),
+                },
+                (id, Ok(reply)) => {
+                    log::info!("Get GrpcMessage::TraceList from other node({}), reply: {:?}", id, reply);
+                }
+                (id, Err(e)) => {
+                    log::warn!("Get GrpcMessage::TraceList from other node({}), error: {:?}", id, e);
+                }
+            }
+        }
+    }
+    Ok(traces)
+}
+
+//A trace is running if it is running on any node, the log sizes are listed by node
+fn _merge_traces(traces: Vec<(NodeId, Vec<TraceInfo>)>) -> Vec<serde_json::Value> {
+    type LogSizes = serde_json::Map<String, serde_json::Value>;
+    let mut merged: BTreeMap<String, (TraceDef, bool, LogSizes)> = BTreeMap::new();
+    for (node_id, infos) in traces {
+        for info in infos {
+            let (_, running, log_size) = merged
+                .entry(info.def.name.clone())
+                .or_insert_with(|| (info.def.clone(), false, LogSizes::new()));
+            *running |= info.running;
+            log_size.insert(node_id.to_string(), json!(info.log_size));
+        }
+    }
+    merged
+        .into_values()
+        .map(|(def, running, log_size)| {
+            let mut trace = def.to_json();
+            if let Some(obj) = trace.as_object_mut() {
+                obj.insert("status".into(), json!(if running { "running" } else { "stopped" }));
+                obj.insert("log_size".into(), serde_json::Value::Object(log_size));
             }
+            trace
+        })
+        .collect()
+}
+
+#[handler]
+async fn start_trace(req: &mut Request, depot: &mut Depot, res: &mut Response) -> Result<(), salvo::Error> {
+    let cfg = get_cfg(depot)?;
+    let message_type = cfg.read().await.message_type;
+
+    let params = match req.parse_json::<TraceParams>().await {
+        Ok(p) => p,
+        Err(e) => {
+            res.render(StatusError::bad_request().detail(e.to_string()));
+            return Ok(());
+        }
+    };
+    let def = match TraceDef::from_params(&params, &TraceManager::instance().config()) {
+        Ok(def) => def,
+        Err(e) => {
+            res.render(StatusError::bad_request().detail(e.to_string()));
+            return Ok(());
         }
+    };
+    if let Err(e) = TraceManager::instance().start(def.clone()) {
+        res.render(StatusError::bad_request().detail(e.to_string()));
+        return Ok(());
     }
+    match _trace_broadcast(message_type, Message::TraceStart(def.clone())).await {
+        Ok(_) => res.render(Json(def.to_json())),
+        Err(e) => res.render(StatusError::service_unavailable().detail(e.to_string())),
+    }
+    Ok(())
 }
 
 #[handler]
-async fn node_plugin_unload(
-    req: &mut Request,
-    depot: &mut Depot,
-    res: &mut Response,
-) -> Result<(), salvo::Error> {
+async fn stop_trace(req: &mut Request, depot: &mut Depot, res: &mut Response) -> Result<(), salvo::Error> {
     let cfg = get_cfg(depot)?;
     let message_type = cfg.read().await.message_type;
-    let node_id = if let Some(node_id) = req.param::<NodeId>("node") {
-        node_id
+    let name = if let Some(name) = req.param::<String>("name") {
+        name
     } else {
         res.status_code(StatusCode::NOT_FOUND);
         return Ok(());
     };
-    let name = if let Some(name) = req.param::<String>("plugin") {
+    let stopped = TraceManager::instance().stop(&name).await;
+    match _trace_broadcast(message_type, Message::TraceStop { name: &name }).await {
+        Ok(others) if stopped || others => res.render(Json(true)),
+        Ok(_) => {
+            res.status_code(StatusCode::NOT_FOUND);
+        }
+        Err(e) => res.render(StatusError::service_unavailable().detail(e.to_string())),
+    }
+    Ok(())
+}
+
+#[handler]
+async fn delete_trace(req: &mut Request, depot: &mut Depot, res: &mut Response) -> Result<(), salvo::Error> {
+    let cfg = get_cfg(depot)?;
+    let message_type = cfg.read().await.message_type;
+    let name = if let Some(name) = req.param::<String>("name") {
         name
     } else {
         res.status_code(StatusCode::NOT_FOUND);
         return Ok(());
     };
-
-    match _node_plugin_unload(node_id, &name, message_type).await {
-        Ok(r) => res.render(Json(r)),
+    let deleted = TraceManager::instance().delete(&name).await;
+    match _trace_broadcast(message_type, Message::TraceDelete { name: &name }).await {
+        Ok(others) if deleted || others => res.render(Json(true)),
+        Ok(_) => {
+            res.status_code(StatusCode::NOT_FOUND);
+        }
         Err(e) => res.render(StatusError::service_unavailable().detail(e.to_string())),
     }
     Ok(())
 }
 
-async fn _node_plugin_unload(node_id: NodeId, name: &str, message_type: MessageType) -> Result<bool> {
-    if node_id == Runtime::instance().node.id() {
-        Runtime::instance().plugins.stop(name).await
-    } else {
-        let c = get_grpc_client(node_id).await?;
-        let msg = Message::UnloadPlugin { name }.encode()?;
-        let reply =
-            MessageSender::new(c, message_type, GrpcMessage::Data(msg), Some(Duration::from_secs(10)))
-                .send()
-                .await?;
+//Sends the trace message to the other nodes, true is returned if any of them changed
+async fn _trace_broadcast(message_type: MessageType, msg: Message<'_>) -> Result<bool> {
+    let grpc_clients = Runtime::instance().extends.shared().await.get_grpc_clients();
+    if grpc_clients.is_empty() {
+        return Ok(false);
+    }
+    let msg = msg.encode()?;
+    let replys = MessageBroadcaster::new(
+        grpc_clients,
+        message_type,
+        GrpcMessage::Data(msg),
+        Some(Duration::from_secs(10)),
+    )
+    .join_all()
+    .await;
+    let mut changed = false;
+    for (id, reply) in replys {
         match reply {
-            GrpcMessageReply::Data(msg) => match MessageReply::decode(&msg)? {
-                MessageReply::UnloadPlugin(ok) => Ok(ok),
+            Ok(GrpcMessageReply::Data(msg)) => match MessageReply::decode(&msg)? {
+                MessageReply::TraceStart => changed = true,
+                MessageReply::TraceStop(c) | MessageReply::TraceDelete(c) => changed |= c,
                 _ => unreachable!(),
             },
-            reply => {
-                log::info!(
-                    "Unload GrpcMessage::UnloadPlugin from other node({}), reply: {:?}",
-                    node_id,
-                    reply
-                );
-                Ok(false)
+            Ok(reply) => {
+                log::warn!("Send trace message to other node({}), reply: {:?}", id, reply);
+            }
+            Err(e) => {
+                log::warn!("Send trace message to other node({}), error: {:?}", id, e);
             }
         }
     }
+    Ok(changed)
 }
 
 #[handler]
-async fn get_stats_sum(depot: &mut Depot, res: &mut Response) -> Result<(), salvo::Error> {
+async fn get_trace_log(req: &mut Request, depot: &mut Depot, res: &mut Response) -> Result<(), salvo::Error> {
     let cfg = get_cfg(depot)?;
     let message_type = cfg.read().await.message_type;
-
-    match _get_stats_sum(message_type).await {
-        Ok(stats_sum) => res.render(Json(stats_sum)),
+    let name = if let Some(name) = req.param::<String>("name") {
+        name
+    } else {
+        res.status_code(StatusCode::NOT_FOUND);
+        return Ok(());
+    };
+    let node_id = req.query::<NodeId>("node").unwrap_or_else(|| Runtime::instance().node.id());
+    let position = req.query::<u64>("position").unwrap_or_default();
+    let bytes = req.query::<usize>("bytes").unwrap_or(1024 * 1024);
+
+    match _get_trace_log(message_type, node_id, &name, position, bytes).await {
+        Ok(Some(log)) => res.render(Json(json!({
+            "node": node_id,
+            "data": log.data,
+            "position": log.position,
+            "size": log.size,
+            "status": if log.running { "running" } else { "stopped" },
+        }))),
+        Ok(None) => {
+            res.status_code(StatusCode::NOT_FOUND);
+        }
         Err(e) => res.render(StatusError::service_unavailable().detail(e.to_string())),
     }
     Ok(())
 }
 
-async fn _get_stats_sum(message_type: MessageType) -> Result<serde_json::Value> {
-    let this_id = Runtime::instance().node.id();
-    let mut nodes = HashMap::default();
-    nodes.insert(
-        this_id,
-        json!({
-            "name": Runtime::instance().node.name(this_id).await,
-            "running": Runtime::instance().node.status().await.is_running(),
-        }),
-    );
-
-    let mut stats_sum = Runtime::instance().stats.clone().await;
-    let grpc_clients = Runtime::instance().extends.shared().await.get_grpc_clients();
-    if !grpc_clients.is_empty() {
-        let msg = Message::StatsInfo.encode()?;
-        for reply in MessageBroadcaster::new(
-            grpc_clients,
-            message_type,
-            GrpcMessage::Data(msg),
-            Some(Duration::from_secs(10)),
-        )
-        .join_all()
-        .await
-        {
-            match reply {
-                (id, Ok(GrpcMessageReply::Data(msg))) => match MessageReply::decode(&msg)? {
-                    MessageReply::StatsInfo(node_status, stats) => {
-                        nodes.insert(
-                            id,
-                            json!({
-                                "name": Runtime::instance().node.name(id).await,
-                                "running": node_status.is_running(),
-                            }),
-                        );
-                        stats_sum.add(*stats);
+//The logs of all nodes, one node after another
+#[handler]
+async fn download_trace(
+    req: &mut Request,
+    depot: &mut Depot,
+    res: &mut Response,
+) -> Result<(), salvo::Error> {
+    let cfg = get_cfg(depot)?;
+    let message_type = cfg.read().await.message_type;
+    let name = if let Some(name) = req.param::<String>("name") {
+        name
+    } else {
+        res.status_code(StatusCode::NOT_FOUND);
+        return Ok(());
+    };
+    let node_ids =
+        if let Some(node_id) = req.query::<NodeId>("node") { vec![node_id] } else { _trace_nodes().await };
+
+    let mut found = false;
+    let mut data = String::new();
+    for node_id in node_ids {
+        let mut position = 0;
+        loop {
+            match _get_trace_log(message_type, node_id, &name, position, TRACE_LOG_READ_MAX).await {
+                Ok(Some(log)) => {
+                    found = true;
+                    data.push_str(&log.data);
+                    if log.data.is_empty() || log.position >= log.size {
+                        break;
                     }
-                    _ => unreachable!(),
-                },
-                (id, Ok(reply)) => {
-                    log::info!("Get GrpcMessage::StateInfo from other node({}), reply: {:?}", id, reply);
-                    continue;
+                    position = log.position;
                 }
-                (id, Err(e)) => {
-                    log::warn!("Get GrpcMessage::StateInfo from other node({}), error: {:?}", id, e);
-                    nodes.insert(id, serde_json::Value::String(e.to_string()));
+                Ok(None) => break,
+                Err(e) => {
+                    log::warn!("Get trace log from node({}), error: {:?}", node_id, e);
+                    break;
                 }
-            };
+            }
         }
     }
 
-    let stats_sum = json!({
-        "nodes": nodes,
-        "stats": stats_sum.to_json().await
-    });
-
-    Ok(stats_sum)
+    if !found {
+        res.status_code(StatusCode::NOT_FOUND);
+        return Ok(());
+    }
+    res.headers_mut().insert(CONTENT_TYPE, HeaderValue::from_static("text/plain; charset=utf-8"));
+    if let Ok(v) = HeaderValue::from_str(&format!("attachment; filename=\"{}.log\"", name)) {
+        res.headers_mut().insert(CONTENT_DISPOSITION, v);
+    }
+    res.write_body(data).ok();
+    Ok(())
 }
 
+//Streams the new logs of all nodes, the stream ends when the trace is stopped on all nodes
 #[handler]
-async fn get_stats(req: &mut Request, depot: &mut Depot, res: &mut Response) -> Result<(), salvo::Error> {
+async fn stream_trace(req: &mut Request, depot: &mut Depot, res: &mut Response) -> Result<(), salvo::Error> {
     let cfg = get_cfg(depot)?;
     let message_type = cfg.read().await.message_type;
+    let name = if let Some(name) = req.param::<String>("name") {
+        name
+    } else {
+        res.status_code(StatusCode::NOT_FOUND);
+        return Ok(());
+    };
 
-    let id = req.param::<NodeId>("id");
-    if let Some(id) = id {
-        match get_stats_one(message_type, id).await {
-            Ok(Some((node_status, stats))) => {
-                let stat_info = _build_stats(id, node_status, stats.to_json().await).await;
-                res.render(Json(stat_info))
-            }
-            Ok(None) | Err(MqttError::None) => {
-                res.status_code(StatusCode::NOT_FOUND);
-            }
-            Err(e) => res.render(StatusError::service_unavailable().detail(e.to_string())),
+    //Start at the end of the logs
+    let mut positions = Vec::new();
+    for node_id in _trace_nodes().await {
+        match _get_trace_log(message_type, node_id, &name, u64::MAX, 0).await {
+            Ok(Some(log)) => positions.push((node_id, log.position)),
+            Ok(None) => {}
+            Err(e) => log::warn!("Get trace log from node({}), error: {:?}", node_id, e),
         }
-    } else {
-        match get_stats_all(message_type).await {
-            Ok(stats) => {
-                let mut stat_infos = Vec::new();
-                for item in stats {
-                    match item {
-                        Ok((id, node_status, state)) => {
-                            stat_infos.push(_build_stats(id, node_status, state.to_json().await).await);
-                        }
-                        Err(e) => {
-                            stat_infos.push(serde_json::Value::String(e.to_string()));
+    }
+    if positions.is_empty() {
+        res.status_code(StatusCode::NOT_FOUND);
+        return Ok(());
+    }
+
+    let stream = futures::stream::unfold(positions, move |mut positions| {
+        let name = name.clone();
+        async move {
+            loop {
+                let mut data = String::new();
+                let mut running = false;
+                for (node_id, position) in positions.iter_mut() {
+                    match _get_trace_log(message_type, *node_id, &name, *position, TRACE_LOG_READ_MAX).await {
+                        Ok(Some(log)) => {
+                            data.push_str(&log.data);
+                            *position = log.position;
+                            running |= log.running;
                         }
+                        Ok(None) => {}
+                        Err(e) => log::warn!("Get trace log from node({}), error: {:?}", node_id, e),
                     }
                 }
-                res.render(Json(stat_infos))
-            }
-            Err(e) => res.render(StatusError::service_unavailable().detail(e.to_string())),
-        }
-    }
-    Ok(())
-}
-
-#[inline]
-pub(crate) async fn get_stats_one(
-    message_type: MessageType,
-    id: NodeId,
-) -> Result<Option<(NodeStatus, Box<Stats>)>> {
-    if id == Runtime::instance().node.id() {
-        let node_status = Runtime::instance().node.status().await;
-        let stats = Runtime::instance().stats.clone().await;
-        Ok(Some((node_status, Box::new(stats))))
-    } else {
-        let grpc_clients = Runtime::instance().extends.shared().await.get_grpc_clients();
-        if let Some(c) = grpc_clients.get(&id).map(|(_, c)| c.clone()) {
-            let msg = Message::StatsInfo.encode()?;
-            let reply =
-                MessageSender::new(c, message_type, GrpcMessage::Data(msg), Some(Duration::from_secs(10)))
-                    .send()
-                    .await;
-            match reply {
-                Ok(GrpcMessageReply::Data(msg)) => match MessageReply::decode(&msg)? {
-                    MessageReply::StatsInfo(node_status, stats) => Ok(Some((node_status, stats))),
-                    _ => unreachable!(),
-                },
-                Ok(reply) => {
-                    log::info!("Get GrpcMessage::StateInfo from other node, reply: {:?}", reply);
-                    Err(MqttError::from("Invalid Result"))
+                if !data.is_empty() {
+                    return Some((Ok::<_, MqttError>(bytes::Bytes::from(data)), positions));
                 }
-                Err(e) => {
-                    log::warn!("Get GrpcMessage::StateInfo from other node, error: {:?}", e);
-                    Err(e)
+                if !running {
+                    return None;
                 }
+                tokio::time::sleep(TRACE_STREAM_INTERVAL).await;
             }
-        } else {
-            Ok(None)
         }
-    }
+    });
+    res.headers_mut().insert(CONTENT_TYPE, HeaderValue::from_static("text/plain; charset=utf-8"));
+    res.stream(stream);
+    Ok(())
 }
 
 #[inline]
-pub(crate) async fn get_stats_all(
+async fn _get_trace_log(
     message_type: MessageType,
-) -> Result<Vec<Result<(NodeId, NodeStatus, Box<Stats>)>>> {
-    let id = Runtime::instance().node.id();
-    let node_status = Runtime::instance().node.status().await;
-    let state = Runtime::instance().stats.clone().await;
-    //let mut stats = vec![_build_stats(id, node_status, state).await];
-    let mut stats = vec![Ok((id, node_status, Box::new(state)))];
-
-    let grpc_clients = Runtime::instance().extends.shared().await.get_grpc_clients();
-    if !grpc_clients.is_empty() {
-        let msg = Message::StatsInfo.encode()?;
-        for reply in MessageBroadcaster::new(
-            grpc_clients,
-            message_type,
-            GrpcMessage::Data(msg),
-            Some(Duration::from_secs(10)),
-        )
-        .join_all()
-        .await
-        {
-            let data = match reply {
-                (id, Ok(GrpcMessageReply::Data(msg))) => match MessageReply::decode(&msg)? {
-                    MessageReply::StatsInfo(node_status, stats) => Ok((id, node_status, stats)),
-                    _ => unreachable!(),
-                },
-                (id, Ok(reply)) => {
-                    log::info!("Get GrpcMessage::StateInfo from other node({}), reply: {:?}", id, reply);
-                    continue;
-                }
-                (id, Err(e)) => {
-                    log::warn!("Get GrpcMessage::StateInfo from other node({}), error: {:?}", id, e);
-                    Err(e)
-                }
-            };
-            stats.push(data);
+    node_id: NodeId,
+    name: &str,
+    position: u64,
+    bytes: usize,
+) -> Result<Option<TraceLog>> {
+    if node_id == Runtime::instance().node.id() {
+        return TraceManager::instance().read_log(name, position, bytes);
+    }
+    let c = get_grpc_client(node_id).await?;
+    let msg = Message::TraceLog { name, position, bytes }.encode()?;
+    let reply = MessageSender::new(c, message_type, GrpcMessage::Data(msg), Some(Duration::from_secs(10)))
+        .send()
+        .await?;
+    match reply {
+        GrpcMessageReply::Data(msg) => match MessageReply::decode(&msg)? {
+            MessageReply::TraceLog(log) => Ok(log),
+            _ => unreachable!(),
+        },
+        GrpcMessageReply::Error(e) => Err(MqttError::from(e)),
+        reply => {
+            log::info!("Get GrpcMessage::TraceLog from other node({}), reply: {:?}", node_id, reply);
+            Err(MqttError::from("Invalid Result"))
         }
     }
-    Ok(stats)
 }
 
 #[inline]
-async fn _build_stats(id: NodeId, node_status: NodeStatus, stats: serde_json::Value) -> serde_json::Value {
-    let node_name = Runtime::instance().node.name(id).await;
-    let data = json!({
-        "node": {
-            "id": id,
-            "name": node_name,
-            "running": node_status.is_running(),
-        },
-        "stats": stats
-    });
-    data
+async fn _trace_nodes() -> Vec<NodeId> {
+    let mut node_ids = vec![Runtime::instance().node.id()];
+    node_ids.extend(Runtime::instance().extends.shared().await.get_grpc_clients().keys().copied());
+    node_ids.sort();
+    node_ids
 }
 
 #[handler]
@@ -2045,3 +3662,173 @@ async fn get_grpc_client(node_id: NodeId) -> Result<NodeGrpcClient> {
         .map(|(_, c)| c.clone())
         .ok_or_else(|| MqttError::from("node grpc client is not exist!"))
 }
+
+//The activated alarms are filtered by 'activated=true', the deactivated alarms by 'activated=false'
+#[handler]
+async fn get_alarms(req: &mut Request, depot: &mut Depot, res: &mut Response) -> Result<(), salvo::Error> {
+    let cfg = get_cfg(depot)?;
+    let message_type = cfg.read().await.message_type;
+    let activated = req.query::<bool>("activated");
+
+    if let Some(id) = req.param::<NodeId>("id") {
+        match get_alarms_one(message_type, id).await {
+            Ok(Some((actives, inactives))) => {
+                res.render(Json(_alarms_to_json(actives, inactives, activated)))
+            }
+            Ok(None) => {
+                res.status_code(StatusCode::NOT_FOUND);
+            }
+            Err(e) => res.render(StatusError::service_unavailable().detail(e.to_string())),
+        }
+    } else {
+        match get_alarms_all(message_type).await {
+            Ok(alarms) => {
+                let (actives, inactives): (Vec<_>, Vec<_>) = alarms.into_iter().unzip();
+                res.render(Json(_alarms_to_json(
+                    actives.into_iter().flatten().collect(),
+                    inactives.into_iter().flatten().collect(),
+                    activated,
+                )))
+            }
+            Err(e) => res.render(StatusError::service_unavailable().detail(e.to_string())),
+        }
+    }
+    Ok(())
+}
+
+#[inline]
+fn _alarms_to_json(
+    mut actives: Vec<AlarmInfo>,
+    mut inactives: Vec<AlarmInfo>,
+    activated: Option<bool>,
+) -> Vec<serde_json::Value> {
+    actives.sort_by_key(|a| a.activated_at);
+    inactives.sort_by(|a, b| b.deactivated_at.cmp(&a.deactivated_at));
+    let actives = if activated != Some(false) { actives } else { Vec::new() };
+    let inactives = if activated != Some(true) { inactives } else { Vec::new() };
+    actives.iter().chain(inactives.iter()).map(|a| a.to_json()).collect()
+}
+
+#[inline]
+async fn get_alarms_one(
+    message_type: MessageType,
+    id: NodeId,
+) -> Result<Option<(Vec<AlarmInfo>, Vec<AlarmInfo>)>> {
+    if id == Runtime::instance().node.id() {
+        let alarms = Alarms::instance();
+        return Ok(Some((alarms.activated(), alarms.deactivated())));
+    }
+    let grpc_clients = Runtime::instance().extends.shared().await.get_grpc_clients();
+    let c = if let Some(c) = grpc_clients.get(&id).map(|(_, c)| c.clone()) {
+        c
+    } else {
+        return Ok(None);
+    };
+    let msg = Message::AlarmList.encode()?;
+    let reply = MessageSender::new(c, message_type, GrpcMessage::Data(msg), Some(Duration::from_secs(10)))
+        .send()
+        .await?;
+    match reply {
+        GrpcMessageReply::Data(msg) => match MessageReply::decode(&msg)? {
+            MessageReply::AlarmList(actives, inactives) => Ok(Some((actives, inactives))),
+            _ => unreachable!(),
+        },
+        reply => {
+            log::info!("Get GrpcMessage::AlarmList from other node({}), reply: {:?}", id, reply);
+            Err(MqttError::from("Invalid Result"))
+        }
+    }
+}
+
+//The alarms of the nodes that fail to reply are not included
+#[inline]
+async fn get_alarms_all(message_type: MessageType) -> Result<Vec<(Vec<AlarmInfo>, Vec<AlarmInfo>)>> {
+    let alarms = Alarms::instance();
+    let mut alarmses = vec![(alarms.activated(), alarms.deactivated())];
+
+    let grpc_clients = Runtime::instance().extends.shared().await.get_grpc_clients();
+    if !grpc_clients.is_empty() {
+        let msg = Message::AlarmList.encode()?;
+        let replys = MessageBroadcaster::new(
+            grpc_clients,
+            message_type,
+            GrpcMessage::Data(msg),
+            Some(Duration::from_secs(10)),
+        )
+        .join_all()
+        .await;
+        for (id, reply) in replys {
+            match reply {
+                Ok(GrpcMessageReply::Data(msg)) => match MessageReply::decode(&msg)? {
+                    MessageReply::AlarmList(actives, inactives) => alarmses.push((actives, inactives)),
+                    _ => unreachable!(),
+                },
+                Ok(reply) => {
+                    log::info!("Get GrpcMessage::AlarmList from other node({}), reply: {:?}", id, reply);
+                }
+                Err(e) => {
+                    log::warn!("Get GrpcMessage::AlarmList from other node({}), error: {:?}", id, e);
+                }
+            }
+        }
+    }
+    Ok(alarmses)
+}
+
+//Returns the number of cleared alarms
+#[handler]
+async fn clear_deactivated_alarms(depot: &mut Depot, res: &mut Response) -> Result<(), salvo::Error> {
+    let cfg = get_cfg(depot)?;
+    let message_type = cfg.read().await.message_type;
+
+    let mut cleared = Alarms::instance().clear_deactivated();
+    let grpc_clients = Runtime::instance().extends.shared().await.get_grpc_clients();
+    if !grpc_clients.is_empty() {
+        let msg = match Message::AlarmClearDeactivated.encode() {
+            Ok(msg) => msg,
+            Err(e) => {
+                res.render(StatusError::service_unavailable().detail(e.to_string()));
+                return Ok(());
+            }
+        };
+        let replys = MessageBroadcaster::new(
+            grpc_clients,
+            message_type,
+            GrpcMessage::Data(msg),
+            Some(Duration::from_secs(10)),
+        )
+        .join_all()
+        .await;
+        for reply in replys {
+            match reply {
+                (id, Ok(GrpcMessageReply::Data(msg))) => match MessageReply::decode(&msg) {
+                    Ok(MessageReply::AlarmClearDeactivated(c)) => cleared += c,
+                    Err(e) => {
+                        log::warn!(
+                            "Send GrpcMessage::AlarmClearDeactivated to other node({}), error: {:?}",
+                            id,
+                            e
+                        );
+                    }
+                    _ => unreachable!(),
+                },
+                (id, Ok(reply)) => {
+                    log::info!(
+                        "Send GrpcMessage::AlarmClearDeactivated to other node({}), reply: {:?}",
+                        id,
+                        reply
+                    );
+                }
+                (id, Err(e)) => {
+                    log::warn!(
+                        "Send GrpcMessage::AlarmClearDeactivated to other node({}), error: {:?}",
+                        id,
+                        e
+                    );
+                }
+            }
+        }
+    }
+    res.render(Json(cleared));
+    Ok(())
+}
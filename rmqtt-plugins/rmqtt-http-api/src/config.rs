@@ -4,7 +4,7 @@ use std::time::Duration;
 use rmqtt::serde_json;
 use rmqtt::{
     grpc::MessageType,
-    settings::{deserialize_addr, deserialize_duration},
+    settings::{deserialize_addr, deserialize_duration, Bytesize},
     Result,
 };
 
@@ -50,6 +50,9 @@ pub struct PluginConfig {
         deserialize_with = "deserialize_duration"
     )]
     pub prometheus_metrics_cache_interval: Duration,
+
+    #[serde(default)]
+    pub trace: TraceConfig,
 }
 
 impl PluginConfig {
@@ -115,6 +118,7 @@ impl PluginConfig {
             || self.http_laddr != other.http_laddr
             || self.metrics_sample_interval != other.metrics_sample_interval
             || self.http_request_log != other.http_request_log
+            || self.trace != other.trace
     }
 
     #[inline]
@@ -122,3 +126,71 @@ impl PluginConfig {
         self.workers != other.workers || self.http_laddr != other.http_laddr
     }
 }
+
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct TraceConfig {
+    //Directory of the trace log files
+    #[serde(default = "TraceConfig::dir_default")]
+    pub dir: String,
+    //Maximum number of traces, including the stopped traces that are not deleted
+    #[serde(default = "TraceConfig::max_default")]
+    pub max: usize,
+    //Maximum duration of a trace
+    #[serde(default = "TraceConfig::duration_max_default", deserialize_with = "deserialize_duration")]
+    pub duration_max: Duration,
+    //The log file is rotated when it reaches this size
+    #[serde(default = "TraceConfig::file_max_size_default")]
+    pub file_max_size: Bytesize,
+    //Number of rotated log files that are kept
+    #[serde(default = "TraceConfig::file_rotations_default")]
+    pub file_rotations: usize,
+    //Maximum number of payload bytes written to the log
+    #[serde(default = "TraceConfig::payload_max_size_default")]
+    pub payload_max_size: Bytesize,
+}
+
+impl Default for TraceConfig {
+    #[inline]
+    fn default() -> Self {
+        Self {
+            dir: Self::dir_default(),
+            max: Self::max_default(),
+            duration_max: Self::duration_max_default(),
+            file_max_size: Self::file_max_size_default(),
+            file_rotations: Self::file_rotations_default(),
+            payload_max_size: Self::payload_max_size_default(),
+        }
+    }
+}
+
+impl TraceConfig {
+    #[inline]
+    fn dir_default() -> String {
+        "/var/log/rmqtt/trace".into()
+    }
+
+    #[inline]
+    fn max_default() -> usize {
+        30
+    }
+
+    #[inline]
+    fn duration_max_default() -> Duration {
+        Duration::from_secs(60 * 60 * 24)
+    }
+
+    #[inline]
+    fn file_max_size_default() -> Bytesize {
+        Bytesize::from(10 * 1024 * 1024)
+    }
+
+    #[inline]
+    fn file_rotations_default() -> usize {
+        3
+    }
+
+    #[inline]
+    fn payload_max_size_default() -> Bytesize {
+        Bytesize::from(1024)
+    }
+}
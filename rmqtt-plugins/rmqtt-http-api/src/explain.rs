@@ -0,0 +1,168 @@
+use std::net::SocketAddr;
+use std::sync::Arc;
+
+use rmqtt::bytes::Bytes;
+use rmqtt::{
+    broker::default::{DefaultHook, DefaultHookManager},
+    broker::hook::{dry_run, Hook, HookTrace},
+    broker::types::MQTT_LEVEL_311,
+    settings::listener::{Listener, ListenerType},
+};
+use rmqtt::{
+    timestamp_millis, ClientId, ConnectAckReason, ConnectInfo, Id, MqttError, Password, Publish,
+    PublishAclResult, PublishProperties, QoS, Result, Runtime, Session, Subscribe, TopicFilter, TopicName,
+    UserName,
+};
+
+use super::types::{AclAction, AclExplainParams, AclExplainResult, SampleConnectInfo};
+
+///Runs the ACL hooks for the client in dry-run mode, returns None if the client is not connected to this node
+pub(crate) async fn explain(params: &AclExplainParams) -> Result<Option<AclExplainResult>> {
+    let node_id = Runtime::instance().node.id();
+    let qos = match params.qos {
+        0 => QoS::AtMostOnce,
+        1 => QoS::AtLeastOnce,
+        2 => QoS::ExactlyOnce,
+        _ => return Err(MqttError::from("qos must be 0, 1 or 2")),
+    };
+
+    let (session, authenticate, authenticate_trace) = if let Some(clientid) = params.clientid.as_ref() {
+        let shared = Runtime::instance().extends.shared().await;
+        if !shared.exist(clientid) {
+            return Ok(None);
+        }
+        match shared.entry(Id::from(node_id, ClientId::from(clientid.as_str()))).session() {
+            Some(s) => (s, None, Vec::new()),
+            None => return Ok(None),
+        }
+    } else if let Some(connect_info) = params.connect_info.as_ref() {
+        let (reason, session, trace) = synthetic_session(connect_info).await?;
+        let authenticate = Some(format!("{:?}", reason));
+        match session {
+            Some(s) => (s, authenticate, trace),
+            None => {
+                return Ok(Some(AclExplainResult {
+                    node: node_id,
+                    clientid: connect_info.clientid.clone(),
+                    synthetic: true,
+                    authenticate,
+                    authenticate_trace: trace,
+                    superuser: false,
+                    allow: false,
+                    result: "Connection refused".into(),
+                    decided_by: None,
+                    trace: Vec::new(),
+                }));
+            }
+        }
+    } else {
+        return Err(MqttError::from("clientid or connect_info is required"));
+    };
+
+    //Hook objects of the hook manager can not be held across awaits in a Send future
+    let hook = DefaultHook::new(DefaultHookManager::instance(), &session);
+    let superuser = session.superuser().await.unwrap_or_default();
+    let (allow, result, trace) = match params.action {
+        AclAction::Subscribe => {
+            let listen_cfg = session.listen_cfg();
+            let shared_subscription =
+                Runtime::instance().extends.shared_subscription().await.is_supported(listen_cfg);
+            let sub = Subscribe::from_v3(
+                &TopicFilter::from(params.topic.as_str()),
+                qos,
+                shared_subscription,
+                listen_cfg.limit_subscription,
+            )?;
+            let (res, trace) = dry_run(hook.client_subscribe_check_acl(&sub)).await;
+            //No result means that no handler decided, the subscription is allowed
+            let allow = res.as_ref().map(|r| r.success().is_some()).unwrap_or(true);
+            (allow, format!("{:?}", res), trace)
+        }
+        AclAction::Publish => {
+            let publish = Publish {
+                dup: false,
+                retain: params.retain,
+                qos,
+                topic: TopicName::from(params.topic.as_str()),
+                packet_id: None,
+                payload: Bytes::new(),
+                properties: PublishProperties::default(),
+                delay_interval: None,
+                create_time: timestamp_millis(),
+            };
+            let (res, trace) = dry_run(hook.message_publish_check_acl(&publish)).await;
+            (matches!(res, PublishAclResult::Allow), format!("{:?}", res), trace)
+        }
+    };
+
+    Ok(Some(AclExplainResult {
+        node: node_id,
+        clientid: session.id.client_id.to_string(),
+        synthetic: params.clientid.is_none(),
+        authenticate,
+        authenticate_trace,
+        superuser,
+        allow,
+        result,
+        //The ACL hooks are not executed for superusers
+        decided_by: if superuser { Some("superuser".into()) } else { decided_by(&trace) },
+        trace,
+    }))
+}
+
+//The handler that stopped the hook chain, or the last one that returned a result
+#[inline]
+fn decided_by(trace: &[HookTrace]) -> Option<String> {
+    trace
+        .iter()
+        .rev()
+        .find(|t| !t.proceed)
+        .or_else(|| trace.iter().rev().find(|t| t.result.is_some()))
+        .map(|t| t.plugin.clone())
+}
+
+//Authenticates the sample connection in dry-run mode, the session is None if the connection is refused
+async fn synthetic_session(
+    connect_info: &SampleConnectInfo,
+) -> Result<(ConnectAckReason, Option<Session>, Vec<HookTrace>)> {
+    let listen_cfg = listener(connect_info.port)?;
+    let id = Id::new(
+        Runtime::instance().node.id(),
+        Some(listen_cfg.addr),
+        connect_info.ipaddr.map(|ip| SocketAddr::new(ip, 0)),
+        ClientId::from(connect_info.clientid.as_str()),
+        connect_info.username.as_deref().map(UserName::from),
+    );
+    let conn_info = Arc::new(ConnectInfo::with_credentials(
+        id.clone(),
+        connect_info.username.as_deref().map(UserName::from),
+        connect_info.password.clone().map(Password::from),
+        connect_info.protocol.unwrap_or(MQTT_LEVEL_311),
+    ));
+    let allow_anonymous = listen_cfg.allow_anonymous;
+    let ((reason, superuser, auth_info), trace) = dry_run(async {
+        Runtime::instance().extends.hook_mgr().await.client_authenticate(&conn_info, allow_anonymous).await
+    })
+    .await;
+    if !reason.success() {
+        return Ok((reason, None, trace));
+    }
+    let session = Session::detached(id, listen_cfg, conn_info, auth_info, superuser).await?;
+    Ok((reason, Some(session), trace))
+}
+
+#[inline]
+fn listener(port: Option<u16>) -> Result<Listener> {
+    let listeners = &Runtime::instance().settings.listeners;
+    if let Some(port) = port {
+        listeners.get(port).ok_or_else(|| MqttError::from(format!("listener {} does not exist", port)))
+    } else {
+        listeners
+            .all()
+            .into_iter()
+            .filter(|(typ, _)| matches!(typ, ListenerType::Tcp | ListenerType::Tls))
+            .map(|(_, l)| l)
+            .min_by_key(|l| l.addr.port())
+            .ok_or_else(|| MqttError::from("no listener"))
+    }
+}
@@ -1,13 +1,18 @@
 use rmqtt::{async_trait::async_trait, log};
 use rmqtt::{
+    broker::alarm::Alarms,
+    broker::auth_chain::AuthChainStats,
     broker::hook::{Handler, HookResult, Parameter, ReturnType},
     grpc::{Message as GrpcMessage, MessageReply as GrpcMessageReply, MessageType},
+    topic_metrics::TopicMetrics,
     Runtime,
 };
 
 use super::clients;
+use super::explain;
 use super::plugin;
 use super::subs;
+use super::trace::TraceManager;
 use super::types::{Message, MessageReply};
 
 pub(crate) struct HookHandler {
@@ -226,6 +231,230 @@ impl Handler for HookHandler {
                                     ))),
                                 }
                             }
+                            Ok(Message::DelayedList { limit }) => {
+                                match Runtime::instance().extends.delayed_sender().await.list(0, limit).await
+                                {
+                                    Ok(dps) => match MessageReply::DelayedList(dps).encode() {
+                                        Ok(ress) => {
+                                            HookResult::GrpcMessageReply(Ok(GrpcMessageReply::Data(ress)))
+                                        }
+                                        Err(e) => HookResult::GrpcMessageReply(Ok(GrpcMessageReply::Error(
+                                            e.to_string(),
+                                        ))),
+                                    },
+                                    Err(e) => HookResult::GrpcMessageReply(Ok(GrpcMessageReply::Error(
+                                        e.to_string(),
+                                    ))),
+                                }
+                            }
+                            Ok(Message::DelayedGet { id }) => {
+                                match Runtime::instance().extends.delayed_sender().await.get(id).await {
+                                    Ok(dp) => match MessageReply::DelayedGet(dp).encode() {
+                                        Ok(ress) => {
+                                            HookResult::GrpcMessageReply(Ok(GrpcMessageReply::Data(ress)))
+                                        }
+                                        Err(e) => HookResult::GrpcMessageReply(Ok(GrpcMessageReply::Error(
+                                            e.to_string(),
+                                        ))),
+                                    },
+                                    Err(e) => HookResult::GrpcMessageReply(Ok(GrpcMessageReply::Error(
+                                        e.to_string(),
+                                    ))),
+                                }
+                            }
+                            Ok(Message::DelayedCancel { id }) => {
+                                match Runtime::instance().extends.delayed_sender().await.cancel(id).await {
+                                    Ok(dp) => match MessageReply::DelayedCancel(dp).encode() {
+                                        Ok(ress) => {
+                                            HookResult::GrpcMessageReply(Ok(GrpcMessageReply::Data(ress)))
+                                        }
+                                        Err(e) => HookResult::GrpcMessageReply(Ok(GrpcMessageReply::Error(
+                                            e.to_string(),
+                                        ))),
+                                    },
+                                    Err(e) => HookResult::GrpcMessageReply(Ok(GrpcMessageReply::Error(
+                                        e.to_string(),
+                                    ))),
+                                }
+                            }
+                            Ok(Message::ReloadListeners) => match Runtime::instance().node.reload_listeners()
+                            {
+                                Ok(changes) => match MessageReply::ReloadListeners(changes).encode() {
+                                    Ok(ress) => {
+                                        HookResult::GrpcMessageReply(Ok(GrpcMessageReply::Data(ress)))
+                                    }
+                                    Err(e) => HookResult::GrpcMessageReply(Ok(GrpcMessageReply::Error(
+                                        e.to_string(),
+                                    ))),
+                                },
+                                Err(e) => {
+                                    HookResult::GrpcMessageReply(Ok(GrpcMessageReply::Error(e.to_string())))
+                                }
+                            },
+                            Ok(Message::PluginSend { name, msg }) => {
+                                match plugin::plugin_send(name, &msg).await {
+                                    Ok(reply) => match MessageReply::PluginSend(reply).encode() {
+                                        Ok(ress) => {
+                                            HookResult::GrpcMessageReply(Ok(GrpcMessageReply::Data(ress)))
+                                        }
+                                        Err(e) => HookResult::GrpcMessageReply(Ok(GrpcMessageReply::Error(
+                                            e.to_string(),
+                                        ))),
+                                    },
+                                    Err(e) => HookResult::GrpcMessageReply(Ok(GrpcMessageReply::Error(
+                                        e.to_string(),
+                                    ))),
+                                }
+                            }
+                            Ok(Message::AclExplain(params)) => match explain::explain(&params).await {
+                                Ok(reply) => match MessageReply::AclExplain(reply).encode() {
+                                    Ok(ress) => {
+                                        HookResult::GrpcMessageReply(Ok(GrpcMessageReply::Data(ress)))
+                                    }
+                                    Err(e) => HookResult::GrpcMessageReply(Ok(GrpcMessageReply::Error(
+                                        e.to_string(),
+                                    ))),
+                                },
+                                Err(e) => {
+                                    HookResult::GrpcMessageReply(Ok(GrpcMessageReply::Error(e.to_string())))
+                                }
+                            },
+                            Ok(Message::AuthChain) => {
+                                let chain = AuthChainStats::instance()
+                                    .stats(&Runtime::instance().settings.auth.chain);
+                                match MessageReply::AuthChain(chain).encode() {
+                                    Ok(ress) => {
+                                        HookResult::GrpcMessageReply(Ok(GrpcMessageReply::Data(ress)))
+                                    }
+                                    Err(e) => HookResult::GrpcMessageReply(Ok(GrpcMessageReply::Error(
+                                        e.to_string(),
+                                    ))),
+                                }
+                            }
+                            Ok(Message::TopicMetricsList) => {
+                                let infos = TopicMetrics::instance().list();
+                                match MessageReply::TopicMetricsList(infos).encode() {
+                                    Ok(ress) => {
+                                        HookResult::GrpcMessageReply(Ok(GrpcMessageReply::Data(ress)))
+                                    }
+                                    Err(e) => HookResult::GrpcMessageReply(Ok(GrpcMessageReply::Error(
+                                        e.to_string(),
+                                    ))),
+                                }
+                            }
+                            Ok(Message::TopicMetricsRegister { topic }) => {
+                                match TopicMetrics::instance().register(topic) {
+                                    Ok(registered) => {
+                                        match MessageReply::TopicMetricsRegister(registered).encode() {
+                                            Ok(ress) => {
+                                                HookResult::GrpcMessageReply(Ok(GrpcMessageReply::Data(ress)))
+                                            }
+                                            Err(e) => HookResult::GrpcMessageReply(Ok(
+                                                GrpcMessageReply::Error(e.to_string()),
+                                            )),
+                                        }
+                                    }
+                                    Err(e) => HookResult::GrpcMessageReply(Ok(GrpcMessageReply::Error(
+                                        e.to_string(),
+                                    ))),
+                                }
+                            }
+                            Ok(Message::TopicMetricsUnregister { topic }) => {
+                                let unregistered = TopicMetrics::instance().unregister(topic);
+                                match MessageReply::TopicMetricsUnregister(unregistered).encode() {
+                                    Ok(ress) => {
+                                        HookResult::GrpcMessageReply(Ok(GrpcMessageReply::Data(ress)))
+                                    }
+                                    Err(e) => HookResult::GrpcMessageReply(Ok(GrpcMessageReply::Error(
+                                        e.to_string(),
+                                    ))),
+                                }
+                            }
+                            Ok(Message::TraceStart(def)) => match TraceManager::instance().start(def) {
+                                Ok(()) => match MessageReply::TraceStart.encode() {
+                                    Ok(ress) => {
+                                        HookResult::GrpcMessageReply(Ok(GrpcMessageReply::Data(ress)))
+                                    }
+                                    Err(e) => HookResult::GrpcMessageReply(Ok(GrpcMessageReply::Error(
+                                        e.to_string(),
+                                    ))),
+                                },
+                                Err(e) => {
+                                    HookResult::GrpcMessageReply(Ok(GrpcMessageReply::Error(e.to_string())))
+                                }
+                            },
+                            Ok(Message::TraceStop { name }) => {
+                                let stopped = TraceManager::instance().stop(name).await;
+                                match MessageReply::TraceStop(stopped).encode() {
+                                    Ok(ress) => {
+                                        HookResult::GrpcMessageReply(Ok(GrpcMessageReply::Data(ress)))
+                                    }
+                                    Err(e) => HookResult::GrpcMessageReply(Ok(GrpcMessageReply::Error(
+                                        e.to_string(),
+                                    ))),
+                                }
+                            }
+                            Ok(Message::TraceDelete { name }) => {
+                                let deleted = TraceManager::instance().delete(name).await;
+                                match MessageReply::TraceDelete(deleted).encode() {
+                                    Ok(ress) => {
+                                        HookResult::GrpcMessageReply(Ok(GrpcMessageReply::Data(ress)))
+                                    }
+                                    Err(e) => HookResult::GrpcMessageReply(Ok(GrpcMessageReply::Error(
+                                        e.to_string(),
+                                    ))),
+                                }
+                            }
+                            Ok(Message::TraceList) => {
+                                let traces = TraceManager::instance().list();
+                                match MessageReply::TraceList(traces).encode() {
+                                    Ok(ress) => {
+                                        HookResult::GrpcMessageReply(Ok(GrpcMessageReply::Data(ress)))
+                                    }
+                                    Err(e) => HookResult::GrpcMessageReply(Ok(GrpcMessageReply::Error(
+                                        e.to_string(),
+                                    ))),
+                                }
+                            }
+                            Ok(Message::TraceLog { name, position, bytes }) => {
+                                match TraceManager::instance().read_log(name, position, bytes) {
+                                    Ok(log) => match MessageReply::TraceLog(log).encode() {
+                                        Ok(ress) => {
+                                            HookResult::GrpcMessageReply(Ok(GrpcMessageReply::Data(ress)))
+                                        }
+                                        Err(e) => HookResult::GrpcMessageReply(Ok(GrpcMessageReply::Error(
+                                            e.to_string(),
+                                        ))),
+                                    },
+                                    Err(e) => HookResult::GrpcMessageReply(Ok(GrpcMessageReply::Error(
+                                        e.to_string(),
+                                    ))),
+                                }
+                            }
+                            Ok(Message::AlarmList) => {
+                                let alarms = Alarms::instance();
+                                match MessageReply::AlarmList(alarms.activated(), alarms.deactivated())
+                                    .encode()
+                                {
+                                    Ok(ress) => {
+                                        HookResult::GrpcMessageReply(Ok(GrpcMessageReply::Data(ress)))
+                                    }
+                                    Err(e) => HookResult::GrpcMessageReply(Ok(GrpcMessageReply::Error(
+                                        e.to_string(),
+                                    ))),
+                                }
+                            }
+                            Ok(Message::AlarmClearDeactivated) => {
+                                let cleared = Alarms::instance().clear_deactivated();
+                                match MessageReply::AlarmClearDeactivated(cleared).encode() {
+                                    Ok(ress) => {
+                                        HookResult::GrpcMessageReply(Ok(GrpcMessageReply::Data(ress)))
+                                    }
+                                    Err(e) => HookResult::GrpcMessageReply(Ok(GrpcMessageReply::Error(
+                                        e.to_string(),
+                                    ))),
+                                }
+                            }
                         };
                         return (false, Some(new_acc));
                     }
@@ -14,18 +14,21 @@ use rmqtt::{
     tokio::{self, sync::oneshot, sync::RwLock},
 };
 use rmqtt::{
-    broker::hook::{Register, Type},
+    broker::hook::{Priority, Register, Type},
     plugin::{PackageInfo, Plugin},
     register, Result, Runtime,
 };
+use trace::{TraceHandler, TraceManager};
 
 mod api;
 mod clients;
 mod config;
+mod explain;
 mod handler;
 mod plugin;
 mod prome;
 mod subs;
+mod trace;
 mod types;
 
 type ShutdownTX = oneshot::Sender<()>;
@@ -86,6 +89,30 @@ impl Plugin for HttpApiPlugin {
         log::info!("{} init", self.name());
         let mgs_type = self.cfg.read().await.message_type;
         self.register.add(Type::GrpcMessageReceived, Box::new(handler::HookHandler::new(mgs_type))).await;
+
+        TraceManager::instance().set_config(self.cfg.read().await.trace.clone());
+        for typ in [
+            Type::ClientConnect,
+            Type::ClientConnack,
+            Type::ClientConnected,
+            Type::ClientDisconnected,
+            Type::ClientKeepalive,
+            Type::SessionCreated,
+            Type::SessionTerminated,
+            Type::ClientSubscribe,
+            Type::ClientSubscribeAclChecked,
+            Type::SessionSubscribed,
+            Type::ClientUnsubscribe,
+            Type::SessionUnsubscribed,
+            Type::MessagePublishAclChecked,
+            Type::MessagePublish,
+            Type::MessageDelivered,
+            Type::MessageAcked,
+            Type::MessageDropped,
+            Type::OfflineMessage,
+        ] {
+            self.register.add_priority(typ, Priority::MAX, Box::new(TraceHandler)).await;
+        }
         Ok(())
     }
 
@@ -113,6 +140,7 @@ impl Plugin for HttpApiPlugin {
         } else {
             *self.cfg.write().await = new_cfg;
         }
+        TraceManager::instance().set_config(self.cfg.read().await.trace.clone());
 
         log::debug!("load_config ok,  {:?}", self.cfg);
         Ok(())
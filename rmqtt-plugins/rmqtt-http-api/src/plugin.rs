@@ -29,3 +29,11 @@ pub(crate) async fn get_plugin_config(name: &str) -> Result<Vec<u8>> {
     let data = Runtime::instance().plugins.get_config(name).await.map(|cfg| serde_json::to_vec(&cfg))??;
     Ok(data)
 }
+
+///Sends a JSON message to the plugin, the message and reply are JSON text so they can be carried by bincode
+#[inline]
+pub(crate) async fn plugin_send(name: &str, msg: &str) -> Result<String> {
+    let msg = serde_json::from_str(msg)?;
+    let reply = Runtime::instance().plugins.send(name, msg).await?;
+    Ok(serde_json::to_string(&reply)?)
+}
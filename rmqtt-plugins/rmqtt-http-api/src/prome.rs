@@ -1,17 +1,25 @@
+use std::collections::{HashMap, HashSet};
 use std::sync::atomic::{AtomicI64, Ordering};
-use std::sync::Arc;
+use std::sync::{Arc, RwLock};
 use std::time::Duration;
 
+use rmqtt::prometheus::core::{Collector, Desc, MetricVec, MetricVecBuilder};
+use rmqtt::prometheus::proto::{self, Bucket, LabelPair, Metric, MetricFamily, MetricType};
 use rmqtt::prometheus::{
-    register_gauge_vec_with_registry, register_int_gauge_vec_with_registry, Encoder, GaugeVec, IntGaugeVec,
+    register_gauge_vec_with_registry, register_int_counter_vec_with_registry,
+    register_int_gauge_vec_with_registry, Encoder, GaugeVec, IntCounter, IntCounterVec, IntGaugeVec,
     Registry, Result as PromeResult, TextEncoder,
 };
 
-use rmqtt::metrics::Metrics;
-use rmqtt::{anyhow::anyhow, log, once_cell::sync::OnceCell, DashMap, MqttError, NodeId};
+use rmqtt::metrics::{Histogram, Metrics};
+use rmqtt::topic_metrics::{merge_topic_metrics, TopicMetricsInfo};
+use rmqtt::{anyhow::anyhow, log, once_cell::sync::OnceCell, parking_lot::Mutex, DashMap, MqttError, NodeId};
 use rmqtt::{grpc::MessageType, node::NodeInfo, stats::Stats, timestamp_secs, Result, Runtime};
 
-use crate::api::{get_metrics_all, get_metrics_one, get_node, get_nodes_all, get_stats_all, get_stats_one};
+use crate::api::{
+    get_metrics_all, get_metrics_one, get_node, get_nodes_all, get_stats_all, get_stats_one,
+    get_topic_metrics_all, get_topic_metrics_one,
+};
 use crate::types::PrometheusDataType;
 
 #[inline]
@@ -35,7 +43,18 @@ struct MonitorData {
     //Node Status Data
     stats_gauge_vec: IntGaugeVec,
     //Node Metric Data
-    metrics_gauge_vec: IntGaugeVec,
+    metrics_counter_vec: IntCounterVec,
+    //Node latency histograms
+    histograms: Histograms,
+    //Message metrics of the monitored topic filters
+    topic_metrics_counter_vec: IntCounterVec,
+    //Label values of the topic counters set in the last refresh
+    topic_metrics_labels: Arc<Mutex<HashSet<[String; 3]>>>,
+    topic_rates_gauge_vec: GaugeVec,
+    //TLS certificate expiration time
+    tls_cert_gauge_vec: IntGaugeVec,
+    //Label values of the certificates set in the last refresh
+    tls_cert_labels: Arc<Mutex<HashSet<[String; 3]>>>,
 }
 
 impl MonitorData {
@@ -49,11 +68,50 @@ impl MonitorData {
             register_int_gauge_vec_with_registry!("rmqtt_stats", "status data", &["node", "item"], reg)
                 .ok()?;
 
-        let metrics_gauge_vec =
-            register_int_gauge_vec_with_registry!("rmqtt_metrics", "metrics data", &["node", "item"], reg)
+        let metrics_counter_vec =
+            register_int_counter_vec_with_registry!("rmqtt_metrics", "metrics data", &["node", "item"], reg)
                 .ok()?;
 
-        Some(Self { typ, reg, nodes_gauge_vec, stats_gauge_vec, metrics_gauge_vec })
+        let histograms = Histograms::new().ok()?;
+        reg.register(Box::new(histograms.clone())).ok()?;
+
+        let topic_metrics_counter_vec = register_int_counter_vec_with_registry!(
+            "rmqtt_topic_metrics",
+            "message metrics of the monitored topic filters",
+            &["node", "topic", "item"],
+            reg
+        )
+        .ok()?;
+
+        let topic_rates_gauge_vec = register_gauge_vec_with_registry!(
+            "rmqtt_topic_rates",
+            "message rates of the monitored topic filters, per second",
+            &["node", "topic", "item"],
+            reg
+        )
+        .ok()?;
+
+        let tls_cert_gauge_vec = register_int_gauge_vec_with_registry!(
+            "rmqtt_tls_cert_expiry",
+            "expiration time of the listener certificates, unix timestamp in seconds",
+            &["node", "listener", "addr"],
+            reg
+        )
+        .ok()?;
+
+        Some(Self {
+            typ,
+            reg,
+            nodes_gauge_vec,
+            stats_gauge_vec,
+            metrics_counter_vec,
+            histograms,
+            topic_metrics_counter_vec,
+            topic_metrics_labels: Arc::new(Mutex::new(HashSet::default())),
+            topic_rates_gauge_vec,
+            tls_cert_gauge_vec,
+            tls_cert_labels: Arc::new(Mutex::new(HashSet::default())),
+        })
     }
 
     #[inline]
@@ -67,19 +125,27 @@ impl MonitorData {
 
     #[inline]
     async fn refresh_data(&self, message_type: MessageType) -> Result<()> {
-        match self.typ {
-            PrometheusDataType::All => {
-                self.refresh_data_all(message_type, false).await?;
-            }
-            PrometheusDataType::Sum => {
-                self.refresh_data_all(message_type, true).await?;
-            }
-            PrometheusDataType::Node(node_id) => {
-                self.refresh_data_one(message_type, node_id).await?;
-            }
-        }
+        let prev_tls_cert_labels = std::mem::take(&mut *self.tls_cert_labels.lock());
+        let prev_topic_metrics_labels = std::mem::take(&mut *self.topic_metrics_labels.lock());
+        let res = match self.typ {
+            PrometheusDataType::All => self.refresh_data_all(message_type, false).await,
+            PrometheusDataType::Sum => self.refresh_data_all(message_type, true).await,
+            PrometheusDataType::Node(node_id) => self.refresh_data_one(message_type, node_id).await,
+        };
+        self.tls_cert_labels_refreshed(prev_tls_cert_labels, res.is_ok());
+        labels_refreshed(
+            &self.topic_metrics_counter_vec,
+            &self.topic_metrics_labels,
+            prev_topic_metrics_labels,
+            res.is_ok(),
+        );
+        res
+    }
 
-        Ok(())
+    //Remove the certificates of the listeners that were removed or have changed
+    #[inline]
+    fn tls_cert_labels_refreshed(&self, prev_tls_cert_labels: HashSet<[String; 3]>, completed: bool) {
+        labels_refreshed(&self.tls_cert_gauge_vec, &self.tls_cert_labels, prev_tls_cert_labels, completed);
     }
 
     #[inline]
@@ -95,11 +161,18 @@ impl MonitorData {
             .await?
             .ok_or_else(|| MqttError::from(format!("node({}) does not exist", node_id)))?;
         self.stats_gauge_vec_sets(&node, &stats).await;
+        self.tls_cert_gauge_vec_sets(&stats);
 
         let metrics = get_metrics_one(message_type, node_id)
             .await?
             .ok_or_else(|| MqttError::from(format!("node({}) does not exist", node_id)))?;
-        metrics.build_prometheus_metrics(&node, &self.metrics_gauge_vec);
+        metrics.build_prometheus_metrics(&node, &self.metrics_counter_vec);
+        self.histograms.set(node.clone(), *metrics);
+
+        let topic_metrics = get_topic_metrics_one(message_type, node_id)
+            .await?
+            .ok_or_else(|| MqttError::from(format!("node({}) does not exist", node_id)))?;
+        self.topic_metrics_sets(&node, &topic_metrics);
 
         Ok(())
     }
@@ -123,24 +196,51 @@ impl MonitorData {
             if !only_sum {
                 self.stats_gauge_vec_sets(&node, &stats).await;
             }
+            self.tls_cert_gauge_vec_sets(&stats);
             stats_all.add(*stats);
         }
 
         for (node_id, metrics) in get_metrics_all(message_type).await?.into_iter().flatten() {
             let node = node_id.to_string();
+            metrics_all.add(&metrics);
             if !only_sum {
-                metrics.build_prometheus_metrics(&node, &self.metrics_gauge_vec);
+                metrics.build_prometheus_metrics(&node, &self.metrics_counter_vec);
+                self.histograms.set(node, *metrics);
             }
-            metrics_all.add(&metrics);
         }
 
         self.nodes_gauge_vec_sets("all", &node_info_all).await;
         self.stats_gauge_vec_sets("all", &stats_all).await;
-        metrics_all.build_prometheus_metrics("all", &self.metrics_gauge_vec);
+        metrics_all.build_prometheus_metrics("all", &self.metrics_counter_vec);
+        self.histograms.set("all".into(), metrics_all);
+
+        let mut topic_metrics_nodes = Vec::new();
+        for (node_id, topic_metrics) in get_topic_metrics_all(message_type).await?.into_iter().flatten() {
+            if !only_sum {
+                self.topic_metrics_sets(&node_id.to_string(), &topic_metrics);
+            }
+            topic_metrics_nodes.push(topic_metrics);
+        }
+        self.topic_metrics_sets("all", &merge_topic_metrics(topic_metrics_nodes));
 
         Ok(())
     }
 
+    #[inline]
+    fn topic_metrics_sets(&self, label: &str, topic_metrics: &[TopicMetricsInfo]) {
+        let mut topic_metrics_labels = self.topic_metrics_labels.lock();
+        for info in topic_metrics {
+            for (item, c) in info.counters() {
+                let counter = self.topic_metrics_counter_vec.with_label_values(&[label, &info.topic, item]);
+                set_counter(&counter, c as u64);
+                topic_metrics_labels.insert([label.to_owned(), info.topic.clone(), item.to_owned()]);
+            }
+            for (item, r) in info.rates() {
+                self.topic_rates_gauge_vec.with_label_values(&[label, &info.topic, item]).set(r);
+            }
+        }
+    }
+
     #[inline]
     async fn nodes_gauge_vec_sets(&self, label: &str, node_info: &NodeInfo) {
         self.nodes_gauge_vec.with_label_values(&[label, "load1"]).set(node_info.load1 as f64);
@@ -159,6 +259,18 @@ impl MonitorData {
             .set(node_info.node_status.running() as f64);
     }
 
+    #[inline]
+    fn tls_cert_gauge_vec_sets(&self, stats: &Stats) {
+        let mut tls_cert_labels = self.tls_cert_labels.lock();
+        for cert in stats.tls_certs.iter() {
+            let labels = [cert.node_id.to_string(), cert.listener.clone(), cert.addr.to_string()];
+            self.tls_cert_gauge_vec
+                .with_label_values(&[&labels[0], &labels[1], &labels[2]])
+                .set(cert.not_after);
+            tls_cert_labels.insert(labels);
+        }
+    }
+
     #[inline]
     async fn stats_gauge_vec_sets(&self, label: &str, stats: &Stats) {
         self.stats_gauge_vec
@@ -288,7 +400,8 @@ impl Monitor {
         if let Some(md) = self.catcheds.entry(typ).or_insert_with(|| MonitorData::new(typ)).value() {
             md.nodes_gauge_vec.reset();
             md.stats_gauge_vec.reset();
-            md.metrics_gauge_vec.reset();
+            md.histograms.reset();
+            md.topic_rates_gauge_vec.reset();
             if let Err(e) = md.refresh_data(message_type).await {
                 log::warn!("refresh data error, {:?}", e)
             }
@@ -312,3 +425,279 @@ impl Monitor {
         }
     }
 }
+
+//Counters are set to the values counted by the nodes instead of being reset before each refresh,
+//the refresh of a cluster takes a while and scrapes in the meantime would see them drop to zero
+#[inline]
+fn set_counter(counter: &IntCounter, value: u64) {
+    let current = counter.get();
+    if value >= current {
+        counter.inc_by(value - current);
+    } else {
+        //The node has restarted
+        counter.reset();
+        counter.inc_by(value);
+    }
+}
+
+//Removes the label values that were not set again by a complete refresh,
+//after a failed refresh the previous label values are kept
+#[inline]
+fn labels_refreshed<T: MetricVecBuilder>(
+    vec: &MetricVec<T>,
+    labels: &Mutex<HashSet<[String; 3]>>,
+    prev_labels: HashSet<[String; 3]>,
+    completed: bool,
+) {
+    let mut labels = labels.lock();
+    if !completed {
+        labels.extend(prev_labels);
+        return;
+    }
+    for l in prev_labels.difference(&labels) {
+        if let Err(e) = vec.remove_label_values(&[l[0].as_str(), l[1].as_str(), l[2].as_str()]) {
+            log::debug!("remove label values error, {:?}", e);
+        }
+    }
+}
+
+const HISTOGRAMS: [(&str, &str, &[&str]); 5] = [
+    ("rmqtt_handshake_duration_seconds", "time from receiving CONNECT to sending CONNACK", &["node"]),
+    ("rmqtt_auth_duration_seconds", "execution time of the authentication hooks", &["node"]),
+    ("rmqtt_acl_duration_seconds", "execution time of the ACL hooks", &["node", "action"]),
+    (
+        "rmqtt_message_latency_seconds",
+        "time from the creation of a message to sending it to a subscriber",
+        &["node"],
+    ),
+    (
+        "rmqtt_hook_duration_seconds",
+        "execution time of the hook handlers of each plugin",
+        &["node", "plugin", "hook"],
+    ),
+];
+
+///Latency histograms of the nodes, the buckets are counted by the nodes and exported as they are
+#[derive(Clone)]
+struct Histograms {
+    descs: Arc<Vec<Desc>>,
+    //(node label, metrics)
+    metrics: Arc<RwLock<Vec<(String, Metrics)>>>,
+}
+
+impl Histograms {
+    fn new() -> PromeResult<Self> {
+        let descs = HISTOGRAMS
+            .iter()
+            .map(|(name, help, labels)| {
+                Desc::new(
+                    (*name).into(),
+                    (*help).into(),
+                    labels.iter().map(|l| (*l).into()).collect(),
+                    HashMap::new(),
+                )
+            })
+            .collect::<PromeResult<Vec<_>>>()?;
+        Ok(Self { descs: Arc::new(descs), metrics: Arc::new(RwLock::new(Vec::new())) })
+    }
+
+    #[inline]
+    fn set(&self, node: String, metrics: Metrics) {
+        if let Ok(mut items) = self.metrics.write() {
+            items.push((node, metrics));
+        }
+    }
+
+    #[inline]
+    fn reset(&self) {
+        if let Ok(mut items) = self.metrics.write() {
+            items.clear();
+        }
+    }
+}
+
+impl Collector for Histograms {
+    fn desc(&self) -> Vec<&Desc> {
+        self.descs.iter().collect()
+    }
+
+    fn collect(&self) -> Vec<MetricFamily> {
+        let items = match self.metrics.read() {
+            Ok(items) => items,
+            Err(_) => return Vec::new(),
+        };
+        let mut handshake = Vec::new();
+        let mut auth = Vec::new();
+        let mut acl = Vec::new();
+        let mut latency = Vec::new();
+        let mut hook = Vec::new();
+        for (node, metrics) in items.iter() {
+            handshake.push(histogram_metric(&[("node", node)], metrics.handshake_duration()));
+            auth.push(histogram_metric(&[("node", node)], metrics.auth_duration()));
+            acl.push(histogram_metric(
+                &[("node", node), ("action", "publish")],
+                metrics.acl_publish_duration(),
+            ));
+            acl.push(histogram_metric(
+                &[("node", node), ("action", "subscribe")],
+                metrics.acl_subscribe_duration(),
+            ));
+            latency.push(histogram_metric(&[("node", node)], metrics.message_latency()));
+            for (plugin, typ, h) in metrics.hook_duration().to_vec() {
+                hook.push(histogram_metric(
+                    &[("node", node), ("plugin", &plugin), ("hook", typ.as_str())],
+                    &h,
+                ));
+            }
+        }
+        HISTOGRAMS
+            .iter()
+            .zip([handshake, auth, acl, latency, hook])
+            .filter(|(_, metrics)| !metrics.is_empty())
+            .map(|((name, help, _), metrics)| {
+                let mut mf = MetricFamily::default();
+                mf.set_name((*name).into());
+                mf.set_help((*help).into());
+                mf.set_field_type(MetricType::HISTOGRAM);
+                mf.set_metric(metrics.into());
+                mf
+            })
+            .collect()
+    }
+}
+
+#[inline]
+fn histogram_metric(labels: &[(&str, &str)], h: &Histogram) -> Metric {
+    let mut ph = proto::Histogram::default();
+    ph.set_sample_count(h.count());
+    ph.set_sample_sum(h.sum().as_secs_f64());
+    for (le, count) in h.buckets() {
+        let mut b = Bucket::default();
+        b.set_upper_bound(le);
+        b.set_cumulative_count(count);
+        ph.mut_bucket().push(b);
+    }
+    let mut m = Metric::default();
+    for (name, value) in labels {
+        let mut l = LabelPair::default();
+        l.set_name((*name).into());
+        l.set_value((*value).into());
+        m.mut_label().push(l);
+    }
+    m.set_histogram(ph);
+    m
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rmqtt::tls::CertInfo;
+
+    fn stats(certs: &[(&str, u16, i64)]) -> Stats {
+        let tls_certs = certs
+            .iter()
+            .map(|(listener, port, not_after)| CertInfo {
+                node_id: 1,
+                listener: listener.to_string(),
+                addr: ([0, 0, 0, 0], *port).into(),
+                not_after: *not_after,
+            })
+            .collect();
+        Stats { tls_certs, ..Default::default() }
+    }
+
+    fn refresh(data: &MonitorData, stats: Option<Stats>) {
+        let prev_tls_cert_labels = std::mem::take(&mut *data.tls_cert_labels.lock());
+        if let Some(stats) = stats.as_ref() {
+            data.tls_cert_gauge_vec_sets(stats);
+        }
+        data.tls_cert_labels_refreshed(prev_tls_cert_labels, stats.is_some());
+    }
+
+    fn tls_certs(data: &MonitorData) -> Vec<(String, i64)> {
+        let mut certs = data
+            .tls_cert_gauge_vec
+            .collect()
+            .iter()
+            .flat_map(|mf| mf.get_metric().iter())
+            .map(|m| {
+                let listener = m.get_label().iter().find(|l| l.get_name() == "listener").unwrap();
+                (listener.get_value().to_owned(), m.get_gauge().get_value() as i64)
+            })
+            .collect::<Vec<_>>();
+        certs.sort();
+        certs
+    }
+
+    #[test]
+    fn tls_cert_labels() {
+        let data = MonitorData::new(PrometheusDataType::Node(1)).unwrap();
+        refresh(&data, Some(stats(&[("external", 8883, 100), ("internal", 18883, 200)])));
+        assert_eq!(tls_certs(&data), [("external".into(), 100), ("internal".into(), 200)]);
+
+        //the renamed listener and the removed listener are gone
+        refresh(&data, Some(stats(&[("public", 8883, 300)])));
+        assert_eq!(tls_certs(&data), [("public".into(), 300)]);
+
+        //a failed refresh keeps the label values, they are removed after the next complete refresh
+        refresh(&data, None);
+        assert_eq!(tls_certs(&data), [("public".into(), 300)]);
+        assert_eq!(data.tls_cert_labels.lock().len(), 1);
+        refresh(&data, Some(stats(&[])));
+        assert!(tls_certs(&data).is_empty());
+        assert!(data.tls_cert_labels.lock().is_empty());
+    }
+
+    #[test]
+    fn counters_are_set() {
+        let data = MonitorData::new(PrometheusDataType::Node(1)).unwrap();
+        let counter = data.metrics_counter_vec.with_label_values(&["1", "messages.publish"]);
+        set_counter(&counter, 10);
+        assert_eq!(counter.get(), 10);
+        set_counter(&counter, 15);
+        assert_eq!(counter.get(), 15);
+        //a restarted node counts from zero again
+        set_counter(&counter, 3);
+        assert_eq!(counter.get(), 3);
+    }
+
+    fn topics(data: &MonitorData) -> Vec<String> {
+        let mut topics = data
+            .topic_metrics_counter_vec
+            .collect()
+            .iter()
+            .flat_map(|mf| mf.get_metric().iter())
+            .map(|m| m.get_label().iter().find(|l| l.get_name() == "topic").unwrap().get_value().to_owned())
+            .collect::<Vec<_>>();
+        topics.sort();
+        topics.dedup();
+        topics
+    }
+
+    #[test]
+    fn topic_metrics_labels() {
+        let data = MonitorData::new(PrometheusDataType::Node(1)).unwrap();
+        let info = |topic: &str| TopicMetricsInfo { topic: topic.into(), ..Default::default() };
+        let refresh = |infos: Option<Vec<TopicMetricsInfo>>| {
+            let prev = std::mem::take(&mut *data.topic_metrics_labels.lock());
+            if let Some(infos) = infos.as_ref() {
+                data.topic_metrics_sets("1", infos);
+            }
+            labels_refreshed(
+                &data.topic_metrics_counter_vec,
+                &data.topic_metrics_labels,
+                prev,
+                infos.is_some(),
+            );
+        };
+
+        refresh(Some(vec![info("a/#"), info("b/#")]));
+        assert_eq!(topics(&data), ["a/#", "b/#"]);
+
+        //the unregistered topic filter is removed after a complete refresh only
+        refresh(None);
+        assert_eq!(topics(&data), ["a/#", "b/#"]);
+        refresh(Some(vec![info("b/#")]));
+        assert_eq!(topics(&data), ["b/#"]);
+    }
+}
@@ -0,0 +1,911 @@
+use std::fs::{self, File};
+use std::io::{BufWriter, Read, Seek, SeekFrom, Write};
+use std::net::IpAddr;
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{sync_channel, Receiver, SyncSender, TrySendError};
+use std::sync::{Arc, Mutex, RwLock};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+use rmqtt::{
+    async_trait::async_trait,
+    log,
+    once_cell::sync::OnceCell,
+    serde_json::{self, json},
+    tokio,
+};
+use rmqtt::{
+    broker::hook::{Handler, HookResult, Parameter, ReturnType},
+    broker::topic::TopicTree,
+    format_timestamp_millis, timestamp_millis, DashMap, Id, MqttError, Publish, PublishAclResult, QoSEx,
+    Result, Runtime, TimestampMillis, Topic,
+};
+
+use super::config::TraceConfig;
+use super::types::TraceParams;
+
+///Maximum number of bytes of a log read
+pub(crate) const TRACE_LOG_READ_MAX: usize = 4 * 1024 * 1024;
+
+///Interval of the polls of a log stream
+pub(crate) const TRACE_STREAM_INTERVAL: Duration = Duration::from_secs(1);
+
+///What the trace matches
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum TraceType {
+    Clientid,
+    Username,
+    IpAddress,
+    Topic,
+}
+
+///How the message payloads are written to the log
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum PayloadEncode {
+    #[default]
+    Text,
+    Hex,
+    Hidden,
+}
+
+///Definition of a trace, the same on all nodes of the cluster
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct TraceDef {
+    pub name: String,
+    pub typ: TraceType,
+    //Client ID, username, IP address or topic filter
+    pub filter: String,
+    pub payload_encode: PayloadEncode,
+    pub start_at: TimestampMillis,
+    pub end_at: TimestampMillis,
+}
+
+impl TraceDef {
+    #[inline]
+    pub(crate) fn from_params(params: &TraceParams, cfg: &TraceConfig) -> Result<Self> {
+        if !is_valid_name(&params.name) {
+            return Err(MqttError::from(
+                "name must be 1 to 64 characters of letters, digits, '-' and '_'".to_string(),
+            ));
+        }
+        let filter = match params.typ {
+            TraceType::Clientid => params.clientid.as_ref(),
+            TraceType::Username => params.username.as_ref(),
+            TraceType::IpAddress => params.ip_address.as_ref(),
+            TraceType::Topic => params.topic.as_ref(),
+        };
+        let filter = match filter {
+            Some(filter) if !filter.is_empty() => filter.clone(),
+            _ => return Err(MqttError::from(format!("{} is empty", params.typ.as_str()))),
+        };
+        //Validate the filter
+        Filter::new(params.typ, &filter)?;
+        if params.duration.is_zero() || params.duration > cfg.duration_max {
+            return Err(MqttError::from(format!(
+                "duration must be greater than 0 and at most {:?}",
+                cfg.duration_max
+            )));
+        }
+        let start_at = timestamp_millis();
+        Ok(Self {
+            name: params.name.clone(),
+            typ: params.typ,
+            filter,
+            payload_encode: params.payload_encode,
+            start_at,
+            end_at: start_at + params.duration.as_millis() as TimestampMillis,
+        })
+    }
+
+    #[inline]
+    pub fn to_json(&self) -> serde_json::Value {
+        json!({
+            "name": self.name,
+            "type": self.typ.as_str(),
+            self.typ.as_str(): self.filter,
+            "payload_encode": self.payload_encode,
+            "start_at": format_timestamp_millis(self.start_at),
+            "end_at": format_timestamp_millis(self.end_at),
+        })
+    }
+}
+
+impl TraceType {
+    #[inline]
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            TraceType::Clientid => "clientid",
+            TraceType::Username => "username",
+            TraceType::IpAddress => "ip_address",
+            TraceType::Topic => "topic",
+        }
+    }
+}
+
+///Trace on a node
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct TraceInfo {
+    pub def: TraceDef,
+    pub running: bool,
+    //Size of the log files
+    pub log_size: u64,
+}
+
+///Part of the log of a trace, the log files are read as one file, from the oldest rotated file.
+///The positions count from the start of the trace, they do not move when the oldest file is dropped
+#[derive(Deserialize, Serialize, Debug, Clone, Default)]
+pub struct TraceLog {
+    pub data: String,
+    //Position of the next read
+    pub position: u64,
+    //Position of the end of the log
+    pub size: u64,
+    pub running: bool,
+}
+
+enum Filter {
+    Clientid(String),
+    Username(String),
+    IpAddress(IpAddr),
+    Topic(TopicTree<()>),
+}
+
+impl Filter {
+    fn new(typ: TraceType, filter: &str) -> Result<Self> {
+        Ok(match typ {
+            TraceType::Clientid => Filter::Clientid(filter.into()),
+            TraceType::Username => Filter::Username(filter.into()),
+            TraceType::IpAddress => Filter::IpAddress(
+                IpAddr::from_str(filter)
+                    .map_err(|e| MqttError::from(format!("invalid ip_address '{}', {}", filter, e)))?,
+            ),
+            TraceType::Topic => {
+                let topic = Topic::from_str(filter)
+                    .map_err(|e| MqttError::from(format!("invalid topic filter '{}', {:?}", filter, e)))?;
+                let mut tree = TopicTree::default();
+                tree.insert(&topic, ());
+                Filter::Topic(tree)
+            }
+        })
+    }
+
+    #[inline]
+    fn is_match_id(&self, id: &Id) -> bool {
+        match self {
+            Filter::Clientid(client_id) => id.client_id.as_str() == client_id,
+            Filter::Username(username) => {
+                id.username.as_ref().map(|u| u.as_str() == username).unwrap_or(false)
+            }
+            Filter::IpAddress(ip) => id.remote_addr.map(|addr| addr.ip() == *ip).unwrap_or(false),
+            Filter::Topic(_) => false,
+        }
+    }
+
+    #[inline]
+    fn is_match_topic(&self, topic: &str) -> bool {
+        match self {
+            Filter::Topic(tree) => Topic::from_str(topic).map(|t| tree.is_match(&t)).unwrap_or(false),
+            _ => false,
+        }
+    }
+}
+
+///Maximum number of lines waiting to be written to the log of a trace, new lines are dropped when it is full
+const TRACE_QUEUE_MAX: usize = 10_000;
+
+///Writes the log of a trace on its own thread, so the hooks do not wait for the file system
+struct TraceWriter {
+    name: String,
+    cfg: TraceConfig,
+    file: Option<BufWriter<File>>,
+    size: u64,
+    base: Arc<Mutex<u64>>,
+}
+
+impl TraceWriter {
+    #[inline]
+    fn new(name: String, cfg: TraceConfig, file: File, base: Arc<Mutex<u64>>) -> Self {
+        Self { name, cfg, file: Some(BufWriter::new(file)), size: 0, base }
+    }
+
+    //The lines queued together are written with one flush
+    fn run(mut self, rx: Receiver<String>) {
+        while let Ok(line) = rx.recv() {
+            self.write(&line);
+            while let Ok(line) = rx.try_recv() {
+                self.write(&line);
+            }
+            self.flush();
+        }
+    }
+
+    fn write(&mut self, line: &str) {
+        let f = if let Some(f) = self.file.as_mut() {
+            f
+        } else {
+            return;
+        };
+        if let Err(e) = f.write_all(line.as_bytes()) {
+            log::warn!("trace {} write error, {:?}", self.name, e);
+            return;
+        }
+        self.size += line.len() as u64;
+        if self.size >= self.cfg.file_max_size.as_u64() {
+            self.rotate();
+        }
+    }
+
+    #[inline]
+    fn flush(&mut self) {
+        if let Some(Err(e)) = self.file.as_mut().map(|f| f.flush()) {
+            log::warn!("trace {} write error, {:?}", self.name, e);
+        }
+    }
+
+    //{name}.log -> {name}.log.1 -> {name}.log.2 ..., the size of the dropped oldest file is added to the base
+    fn rotate(&mut self) {
+        self.flush();
+        self.file = None;
+        let (cfg, name) = (&self.cfg, &self.name);
+        let mut base = self.base.lock().unwrap_or_else(|e| e.into_inner());
+        *base += fs::metadata(log_path(cfg, name, cfg.file_rotations)).map(|m| m.len()).unwrap_or(0);
+        for n in (1..cfg.file_rotations).rev() {
+            let _ = fs::rename(log_path(cfg, name, n), log_path(cfg, name, n + 1));
+        }
+        if cfg.file_rotations > 0 {
+            let _ = fs::rename(log_path(cfg, name, 0), log_path(cfg, name, 1));
+        }
+        self.size = 0;
+        match File::create(log_path(cfg, name, 0)) {
+            Ok(f) => self.file = Some(BufWriter::new(f)),
+            Err(e) => log::warn!("trace {} create log file error, {:?}", name, e),
+        }
+    }
+}
+
+struct Trace {
+    def: TraceDef,
+    filter: Filter,
+    stopped: AtomicBool,
+    writer: Mutex<Option<(SyncSender<String>, JoinHandle<()>)>>,
+    //A dropped line is logged once
+    dropped: AtomicBool,
+    //Position of the start of the log files, the bytes of the dropped rotated files
+    base: Arc<Mutex<u64>>,
+}
+
+impl Trace {
+    #[inline]
+    fn is_running(&self) -> bool {
+        let now = timestamp_millis();
+        !self.stopped.load(Ordering::SeqCst) && now >= self.def.start_at && now < self.def.end_at
+    }
+
+    #[inline]
+    fn write(&self, line: String) {
+        let writer = self.writer.lock().unwrap_or_else(|e| e.into_inner());
+        if let Some((tx, _)) = writer.as_ref() {
+            if let Err(TrySendError::Full(_)) = tx.try_send(line) {
+                if !self.dropped.swap(true, Ordering::SeqCst) {
+                    log::warn!("trace {} queue is full, lines are dropped", self.def.name);
+                }
+            }
+        }
+    }
+
+    ///Waits until the queued lines are written and the log file is closed
+    #[inline]
+    fn close(&self) {
+        let writer = self.writer.lock().unwrap_or_else(|e| e.into_inner()).take();
+        if let Some((tx, handle)) = writer {
+            drop(tx);
+            if handle.join().is_err() {
+                log::warn!("trace {} writer thread panicked", self.def.name);
+            }
+        }
+    }
+
+    ///Closes the log on a blocking thread
+    async fn shutdown(self: Arc<Self>) {
+        let name = self.def.name.clone();
+        if let Err(e) = tokio::task::spawn_blocking(move || self.close()).await {
+            log::warn!("trace {} close error, {:?}", name, e);
+        }
+    }
+}
+
+pub(crate) struct TraceManager {
+    cfg: RwLock<TraceConfig>,
+    traces: DashMap<String, Arc<Trace>>,
+}
+
+impl TraceManager {
+    #[inline]
+    pub(crate) fn instance() -> &'static TraceManager {
+        static INSTANCE: OnceCell<TraceManager> = OnceCell::new();
+        INSTANCE.get_or_init(|| Self { cfg: RwLock::new(TraceConfig::default()), traces: DashMap::default() })
+    }
+
+    #[inline]
+    pub(crate) fn set_config(&self, cfg: TraceConfig) {
+        *self.cfg.write().unwrap_or_else(|e| e.into_inner()) = cfg;
+    }
+
+    #[inline]
+    pub(crate) fn config(&self) -> TraceConfig {
+        self.cfg.read().unwrap_or_else(|e| e.into_inner()).clone()
+    }
+
+    #[inline]
+    fn is_empty(&self) -> bool {
+        self.traces.is_empty()
+    }
+
+    pub(crate) fn start(&self, def: TraceDef) -> Result<()> {
+        let cfg = self.config();
+        let filter = Filter::new(def.typ, &def.filter)?;
+        if self.traces.contains_key(&def.name) {
+            return Err(MqttError::from(format!("trace {} already exists", def.name)));
+        }
+        if self.traces.len() >= cfg.max {
+            return Err(MqttError::from(format!(
+                "the number of traces has reached the maximum of {}, delete the stopped traces",
+                cfg.max
+            )));
+        }
+        fs::create_dir_all(&cfg.dir)?;
+        for n in 1..=cfg.file_rotations {
+            let _ = fs::remove_file(log_path(&cfg, &def.name, n));
+        }
+        let file = File::create(log_path(&cfg, &def.name, 0))?;
+        let (tx, rx) = sync_channel(TRACE_QUEUE_MAX);
+        let base = Arc::new(Mutex::new(0));
+        let writer = TraceWriter::new(def.name.clone(), cfg, file, base.clone());
+        let handle =
+            thread::Builder::new().name(format!("trace-{}", def.name)).spawn(move || writer.run(rx))?;
+        log::info!("trace {} started, {:?}", def.name, def);
+        let trace = Trace {
+            def,
+            filter,
+            stopped: AtomicBool::new(false),
+            writer: Mutex::new(Some((tx, handle))),
+            dropped: AtomicBool::new(false),
+            base,
+        };
+        self.traces.insert(trace.def.name.clone(), Arc::new(trace));
+        Ok(())
+    }
+
+    ///Stop the trace, the log files are kept until the trace is deleted. Returns false if it does not exist
+    pub(crate) async fn stop(&self, name: &str) -> bool {
+        let trace = if let Some(trace) = self.traces.get(name) {
+            trace.value().clone()
+        } else {
+            return false;
+        };
+        trace.stopped.store(true, Ordering::SeqCst);
+        trace.shutdown().await;
+        log::info!("trace {} stopped", name);
+        true
+    }
+
+    ///Delete the trace and its log files
+    pub(crate) async fn delete(&self, name: &str) -> bool {
+        if let Some((_, trace)) = self.traces.remove(name) {
+            trace.shutdown().await;
+            let cfg = self.config();
+            for n in 0..=cfg.file_rotations {
+                let _ = fs::remove_file(log_path(&cfg, name, n));
+            }
+            log::info!("trace {} deleted", name);
+            true
+        } else {
+            false
+        }
+    }
+
+    pub(crate) fn list(&self) -> Vec<TraceInfo> {
+        let cfg = self.config();
+        let mut traces = self
+            .traces
+            .iter()
+            .map(|entry| {
+                let trace = entry.value();
+                TraceInfo {
+                    def: trace.def.clone(),
+                    running: trace.is_running(),
+                    log_size: log_files(&cfg, &trace.def.name).iter().map(|(_, size)| size).sum(),
+                }
+            })
+            .collect::<Vec<_>>();
+        traces.sort_by(|a, b| a.def.name.cmp(&b.def.name));
+        traces
+    }
+
+    ///Read the log from the position, a position before the start, whose file has been dropped,
+    ///is moved to the start and a position beyond the end is moved to the end
+    pub(crate) fn read_log(&self, name: &str, position: u64, bytes: usize) -> Result<Option<TraceLog>> {
+        let trace = if let Some(trace) = self.traces.get(name) {
+            trace.value().clone()
+        } else {
+            return Ok(None);
+        };
+        let running = trace.is_running();
+        //The files are not rotated during the read
+        let base = trace.base.lock().unwrap_or_else(|e| e.into_inner());
+        let files = log_files(&self.config(), name);
+        let size = *base + files.iter().map(|(_, size)| size).sum::<u64>();
+        let position = position.clamp(*base, size);
+
+        let mut data = Vec::new();
+        let mut offset = *base;
+        let limit = bytes.min(TRACE_LOG_READ_MAX);
+        for (path, file_size) in files {
+            if data.len() >= limit {
+                break;
+            }
+            if position >= offset + file_size {
+                offset += file_size;
+                continue;
+            }
+            let mut f = File::open(&path)?;
+            f.seek(SeekFrom::Start(position.saturating_sub(offset)))?;
+            f.take((limit - data.len()) as u64).read_to_end(&mut data)?;
+            offset += file_size;
+        }
+        //Do not split a line if the read is full
+        if data.len() >= limit {
+            if let Some(pos) = data.iter().rposition(|c| *c == b'\n') {
+                data.truncate(pos + 1);
+            }
+        }
+        Ok(Some(TraceLog {
+            position: position + data.len() as u64,
+            data: String::from_utf8_lossy(&data).into_owned(),
+            size,
+            running,
+        }))
+    }
+
+    ///Write an event to the running traces that match
+    fn trace<M, F>(&self, is_match: M, event: &str, f: F)
+    where
+        M: Fn(&Filter) -> bool,
+        F: Fn(PayloadEncode, usize) -> String,
+    {
+        let mut payload_max_size = None;
+        for entry in self.traces.iter() {
+            let trace = entry.value();
+            if !is_match(&trace.filter) || !trace.is_running() {
+                continue;
+            }
+            let payload_max_size = *payload_max_size.get_or_insert_with(|| self.config().payload_max_size);
+            let line = format!(
+                "{} {} node={} {}\n",
+                format_timestamp_millis(timestamp_millis()),
+                event,
+                Runtime::instance().node.id(),
+                f(trace.def.payload_encode, payload_max_size.as_usize())
+            );
+            trace.write(line);
+        }
+    }
+}
+
+#[inline]
+fn is_valid_name(name: &str) -> bool {
+    !name.is_empty()
+        && name.len() <= 64
+        && name.bytes().all(|c| c.is_ascii_alphanumeric() || c == b'-' || c == b'_')
+}
+
+#[inline]
+fn log_path(cfg: &TraceConfig, name: &str, n: usize) -> PathBuf {
+    let file_name = if n == 0 { format!("{}.log", name) } else { format!("{}.log.{}", name, n) };
+    PathBuf::from(&cfg.dir).join(file_name)
+}
+
+///The log files that exist and their sizes, from the oldest rotated file
+#[inline]
+fn log_files(cfg: &TraceConfig, name: &str) -> Vec<(PathBuf, u64)> {
+    (0..=cfg.file_rotations)
+        .rev()
+        .filter_map(|n| {
+            let path = log_path(cfg, name, n);
+            fs::metadata(&path).ok().map(|m| (path, m.len()))
+        })
+        .collect()
+}
+
+#[inline]
+fn id_fields(id: &Id) -> String {
+    format!(
+        "clientid={} username={} ip={}",
+        id.client_id,
+        id.username_ref(),
+        id.remote_addr.map(|addr| addr.to_string()).unwrap_or_default()
+    )
+}
+
+#[inline]
+fn publish_fields(p: &Publish, encode: PayloadEncode, payload_max_size: usize) -> String {
+    let mut fields = format!(
+        "topic={} qos={} retain={} dup={} packet_id={} payload_size={}",
+        p.topic,
+        p.qos.value(),
+        p.retain,
+        p.dup,
+        p.packet_id().unwrap_or_default(),
+        p.payload.len()
+    );
+    let payload = &p.payload[..p.payload.len().min(payload_max_size)];
+    match encode {
+        PayloadEncode::Text => {
+            fields.push_str(&format!(" payload={:?}", String::from_utf8_lossy(payload)));
+        }
+        PayloadEncode::Hex => {
+            fields.push_str(" payload=");
+            for c in payload {
+                fields.push_str(&format!("{:02x}", c));
+            }
+        }
+        PayloadEncode::Hidden => {}
+    }
+    fields
+}
+
+///Writes the events of the clients and messages that match the traces
+pub(crate) struct TraceHandler;
+
+#[async_trait]
+impl Handler for TraceHandler {
+    async fn hook(&self, param: &Parameter, acc: Option<HookResult>) -> ReturnType {
+        let traces = TraceManager::instance();
+        if traces.is_empty() {
+            return (true, acc);
+        }
+        match param {
+            Parameter::ClientConnect(connect_info) => {
+                let id = connect_info.id();
+                traces.trace(
+                    |f| f.is_match_id(id),
+                    "client_connect",
+                    |_, _| {
+                        format!(
+                            "{} proto_ver={} keepalive={} clean_start={}",
+                            id_fields(id),
+                            connect_info.proto_ver(),
+                            connect_info.keep_alive(),
+                            connect_info.clean_start()
+                        )
+                    },
+                );
+            }
+            Parameter::ClientConnack(connect_info, reason) => {
+                let id = connect_info.id();
+                traces.trace(
+                    |f| f.is_match_id(id),
+                    "client_connack",
+                    |_, _| format!("{} reason={:?}", id_fields(id), reason),
+                );
+            }
+            Parameter::ClientConnected(s) => {
+                traces.trace(|f| f.is_match_id(&s.id), "client_connected", |_, _| id_fields(&s.id));
+            }
+            Parameter::ClientDisconnected(s, reason) => {
+                traces.trace(
+                    |f| f.is_match_id(&s.id),
+                    "client_disconnected",
+                    |_, _| format!("{} reason={:?}", id_fields(&s.id), reason.to_string()),
+                );
+            }
+            Parameter::ClientKeepalive(s, is_ping) => {
+                traces.trace(
+                    |f| f.is_match_id(&s.id),
+                    "client_keepalive",
+                    |_, _| format!("{} ping={}", id_fields(&s.id), is_ping),
+                );
+            }
+            Parameter::SessionCreated(s) => {
+                traces.trace(|f| f.is_match_id(&s.id), "session_created", |_, _| id_fields(&s.id));
+            }
+            Parameter::SessionTerminated(s, reason) => {
+                traces.trace(
+                    |f| f.is_match_id(&s.id),
+                    "session_terminated",
+                    |_, _| format!("{} reason={:?}", id_fields(&s.id), reason.to_string()),
+                );
+            }
+            Parameter::ClientSubscribe(s, sub) => {
+                traces.trace(
+                    |f| f.is_match_id(&s.id) || f.is_match_topic(&sub.topic_filter),
+                    "client_subscribe",
+                    |_, _| {
+                        format!(
+                            "{} topic_filter={} qos={}",
+                            id_fields(&s.id),
+                            sub.topic_filter,
+                            sub.opts.qos().value()
+                        )
+                    },
+                );
+            }
+            Parameter::ClientSubscribeAclChecked(s, sub, acl_result) => {
+                traces.trace(
+                    |f| f.is_match_id(&s.id) || f.is_match_topic(&sub.topic_filter),
+                    "client_subscribe_acl_checked",
+                    |_, _| {
+                        let result = match acl_result.map(|r| (r.success(), r.ack_reason)) {
+                            Some((Some(qos), _)) => format!("allow qos={}", qos.value()),
+                            Some((None, reason)) => format!("deny reason={:?}", reason),
+                            None => "allow no_decision=true".into(),
+                        };
+                        format!("{} topic_filter={} result={}", id_fields(&s.id), sub.topic_filter, result)
+                    },
+                );
+            }
+            Parameter::SessionSubscribed(s, sub) => {
+                traces.trace(
+                    |f| f.is_match_id(&s.id) || f.is_match_topic(&sub.topic_filter),
+                    "session_subscribed",
+                    |_, _| {
+                        format!(
+                            "{} topic_filter={} qos={}",
+                            id_fields(&s.id),
+                            sub.topic_filter,
+                            sub.opts.qos().value()
+                        )
+                    },
+                );
+            }
+            Parameter::ClientUnsubscribe(s, unsub) => {
+                traces.trace(
+                    |f| f.is_match_id(&s.id) || f.is_match_topic(&unsub.topic_filter),
+                    "client_unsubscribe",
+                    |_, _| format!("{} topic_filter={}", id_fields(&s.id), unsub.topic_filter),
+                );
+            }
+            Parameter::SessionUnsubscribed(s, unsub) => {
+                traces.trace(
+                    |f| f.is_match_id(&s.id) || f.is_match_topic(&unsub.topic_filter),
+                    "session_unsubscribed",
+                    |_, _| format!("{} topic_filter={}", id_fields(&s.id), unsub.topic_filter),
+                );
+            }
+            Parameter::MessagePublishAclChecked(s, p, acl_result) => {
+                traces.trace(
+                    |f| f.is_match_id(&s.id) || f.is_match_topic(&p.topic),
+                    "message_publish_acl_checked",
+                    |_, _| {
+                        let result = match acl_result {
+                            PublishAclResult::Allow => "allow".into(),
+                            PublishAclResult::Rejected(disconnect) => {
+                                format!("deny disconnect={}", disconnect)
+                            }
+                        };
+                        format!("{} topic={} result={}", id_fields(&s.id), p.topic, result)
+                    },
+                );
+            }
+            Parameter::MessagePublish(_, from, p) => {
+                traces.trace(
+                    |f| f.is_match_id(from) || f.is_match_topic(&p.topic),
+                    "message_publish",
+                    |encode, max| {
+                        format!(
+                            "from_type={} {} {}",
+                            from.typ().as_str(),
+                            id_fields(from),
+                            publish_fields(p, encode, max)
+                        )
+                    },
+                );
+            }
+            Parameter::MessageDelivered(s, from, p) => {
+                traces.trace(
+                    |f| f.is_match_id(&s.id) || f.is_match_id(from) || f.is_match_topic(&p.topic),
+                    "message_delivered",
+                    |encode, max| {
+                        format!(
+                            "{} from_clientid={} {}",
+                            id_fields(&s.id),
+                            from.client_id,
+                            publish_fields(p, encode, max)
+                        )
+                    },
+                );
+            }
+            Parameter::MessageAcked(s, from, p) => {
+                traces.trace(
+                    |f| f.is_match_id(&s.id) || f.is_match_id(from) || f.is_match_topic(&p.topic),
+                    "message_acked",
+                    |encode, max| {
+                        format!(
+                            "{} from_clientid={} {}",
+                            id_fields(&s.id),
+                            from.client_id,
+                            publish_fields(p, encode, max)
+                        )
+                    },
+                );
+            }
+            Parameter::MessageDropped(to, from, p, reason) => {
+                traces.trace(
+                    |f| {
+                        to.as_ref().map(|to| f.is_match_id(to)).unwrap_or(false)
+                            || f.is_match_id(from)
+                            || f.is_match_topic(&p.topic)
+                    },
+                    "message_dropped",
+                    |encode, max| {
+                        format!(
+                            "to_clientid={} from_clientid={} reason={:?} {}",
+                            to.as_ref().map(|to| to.client_id.as_str()).unwrap_or_default(),
+                            from.client_id,
+                            reason.to_string(),
+                            publish_fields(p, encode, max)
+                        )
+                    },
+                );
+            }
+            Parameter::OfflineMessage(s, from, p) => {
+                traces.trace(
+                    |f| f.is_match_id(&s.id) || f.is_match_id(from) || f.is_match_topic(&p.topic),
+                    "offline_message",
+                    |encode, max| {
+                        format!(
+                            "{} from_clientid={} {}",
+                            id_fields(&s.id),
+                            from.client_id,
+                            publish_fields(p, encode, max)
+                        )
+                    },
+                );
+            }
+            _ => {}
+        }
+        (true, acc)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rmqtt::settings::Bytesize;
+
+    use super::*;
+
+    fn config(name: &str, file_max_size: usize, file_rotations: usize) -> TraceConfig {
+        let dir = std::env::temp_dir().join(format!("rmqtt-trace-test-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        TraceConfig {
+            dir: dir.to_string_lossy().into_owned(),
+            file_max_size: Bytesize::from(file_max_size),
+            file_rotations,
+            ..Default::default()
+        }
+    }
+
+    fn def(name: &str) -> TraceDef {
+        let start_at = timestamp_millis();
+        TraceDef {
+            name: name.into(),
+            typ: TraceType::Clientid,
+            filter: "c1".into(),
+            payload_encode: PayloadEncode::Text,
+            start_at,
+            end_at: start_at + 60_000,
+        }
+    }
+
+    #[test]
+    fn valid_name() {
+        assert!(is_valid_name("trace-1_A"));
+        assert!(is_valid_name(&"a".repeat(64)));
+        assert!(!is_valid_name(""));
+        assert!(!is_valid_name(&"a".repeat(65)));
+        assert!(!is_valid_name("../trace"));
+        assert!(!is_valid_name(".."));
+        assert!(!is_valid_name("a/b"));
+        assert!(!is_valid_name("a\\b"));
+        assert!(!is_valid_name("a.log"));
+        assert!(!is_valid_name("a b"));
+    }
+
+    #[test]
+    fn rotation() {
+        let cfg = config("rotation", 20, 2);
+        fs::create_dir_all(&cfg.dir).unwrap();
+        let file = File::create(log_path(&cfg, "t1", 0)).unwrap();
+        let (tx, rx) = sync_channel(TRACE_QUEUE_MAX);
+        //Each line is 10 bytes, the file is rotated after every second line
+        for i in 0..7 {
+            tx.send(format!("line-{:04}\n", i)).unwrap();
+        }
+        drop(tx);
+        let base = Arc::new(Mutex::new(0));
+        TraceWriter::new("t1".into(), cfg.clone(), file, base.clone()).run(rx);
+
+        let read = |n| fs::read_to_string(log_path(&cfg, "t1", n)).unwrap();
+        assert_eq!(read(0), "line-0006\n");
+        assert_eq!(read(1), "line-0004\nline-0005\n");
+        assert_eq!(read(2), "line-0002\nline-0003\n");
+        //Only file_rotations rotated files are kept
+        assert!(!log_path(&cfg, "t1", 3).exists());
+        assert_eq!(log_files(&cfg, "t1").iter().map(|(_, size)| size).sum::<u64>(), 50);
+        //line-0000 and line-0001 were dropped
+        assert_eq!(*base.lock().unwrap(), 20);
+        let _ = fs::remove_dir_all(&cfg.dir);
+    }
+
+    #[tokio::test(crate = "rmqtt::tokio")]
+    async fn read_log() {
+        let cfg = config("read_log", 20, 3);
+        let traces = TraceManager { cfg: RwLock::new(cfg.clone()), traces: DashMap::default() };
+        traces.start(def("t1")).unwrap();
+        let trace = traces.traces.get("t1").map(|t| t.value().clone()).unwrap();
+        for i in 0..5 {
+            trace.write(format!("line-{:04}\n", i));
+        }
+        //The queued lines are written when the trace is stopped
+        assert!(traces.stop("t1").await);
+        assert!(traces.read_log("t2", 0, 1024).unwrap().is_none());
+
+        //The rotated files are read as one file, from the oldest
+        let log = traces.read_log("t1", 0, 1024).unwrap().unwrap();
+        assert_eq!(log.data, "line-0000\nline-0001\nline-0002\nline-0003\nline-0004\n");
+        assert_eq!(log.position, 50);
+        assert_eq!(log.size, 50);
+        assert!(!log.running);
+
+        //Across the boundary of two files
+        let log = traces.read_log("t1", 15, 15).unwrap().unwrap();
+        assert_eq!(log.data, "0001\nline-0002\n");
+        assert_eq!(log.position, 30);
+
+        //A full read does not split a line
+        let log = traces.read_log("t1", 10, 25).unwrap().unwrap();
+        assert_eq!(log.data, "line-0001\nline-0002\n");
+        assert_eq!(log.position, 30);
+
+        //A position beyond the end is moved to the end
+        let log = traces.read_log("t1", 100, 1024).unwrap().unwrap();
+        assert_eq!(log.data, "");
+        assert_eq!(log.position, 50);
+
+        assert!(traces.delete("t1").await);
+        assert!(!traces.delete("t1").await);
+        assert!(!traces.stop("t1").await);
+        assert!(log_files(&cfg, "t1").is_empty());
+        let _ = fs::remove_dir_all(&cfg.dir);
+    }
+
+    #[tokio::test(crate = "rmqtt::tokio")]
+    async fn read_log_dropped() {
+        let cfg = config("read_log_dropped", 20, 1);
+        let traces = TraceManager { cfg: RwLock::new(cfg.clone()), traces: DashMap::default() };
+        traces.start(def("t1")).unwrap();
+        let trace = traces.traces.get("t1").map(|t| t.value().clone()).unwrap();
+        for i in 0..5 {
+            trace.write(format!("line-{:04}\n", i));
+        }
+        assert!(traces.stop("t1").await);
+
+        //line-0000 and line-0001 were dropped, a position before the start is moved to the start
+        let log = traces.read_log("t1", 0, 1024).unwrap().unwrap();
+        assert_eq!(log.data, "line-0002\nline-0003\nline-0004\n");
+        assert_eq!(log.position, 50);
+        assert_eq!(log.size, 50);
+
+        //The positions are not moved by the dropped file
+        let log = traces.read_log("t1", 30, 1024).unwrap().unwrap();
+        assert_eq!(log.data, "line-0003\nline-0004\n");
+        assert_eq!(log.position, 50);
+
+        assert!(traces.delete("t1").await);
+        let _ = fs::remove_dir_all(&cfg.dir);
+    }
+}
@@ -1,16 +1,25 @@
 use serde::de::{self, Deserialize};
 use serde::ser::{self, Serialize};
+use std::net::IpAddr;
 use std::time::Duration;
 
+use rmqtt::broker::alarm::AlarmInfo;
+use rmqtt::broker::auth_chain::AuthenticatorStats;
+use rmqtt::broker::hook::HookTrace;
 use rmqtt::chrono::LocalResult;
 use rmqtt::node::{BrokerInfo, NodeInfo, NodeStatus};
 use rmqtt::plugin::PluginInfo;
-use rmqtt::settings::{deserialize_datetime_option, serialize_datetime_option};
+use rmqtt::settings::listener::ListenerChanges;
+use rmqtt::settings::{deserialize_datetime_option, deserialize_duration, serialize_datetime_option};
 use rmqtt::{anyhow, bincode, chrono, serde_json, HashMap, MqttError, QoS};
-use rmqtt::{metrics::Metrics, stats::Stats};
-use rmqtt::{ClientId, NodeId, Timestamp, TopicFilter, TopicName, UserName};
+use rmqtt::{metrics::Metrics, stats::Stats, topic_metrics::TopicMetricsInfo};
+use rmqtt::{
+    ClientId, DelayedPublish, DelayedPublishId, NodeId, Timestamp, TopicFilter, TopicName, UserName,
+};
 use rmqtt::{PublishProperties, Result};
 
+use super::trace::{PayloadEncode, TraceDef, TraceInfo, TraceLog, TraceType};
+
 #[derive(Serialize, Deserialize, Clone, Debug)]
 pub enum Message<'a> {
     BrokerInfo,
@@ -27,6 +36,23 @@ pub enum Message<'a> {
     ReloadPluginConfig { name: &'a str },
     LoadPlugin { name: &'a str },
     UnloadPlugin { name: &'a str },
+    DelayedList { limit: usize },
+    DelayedGet { id: DelayedPublishId },
+    DelayedCancel { id: DelayedPublishId },
+    ReloadListeners,
+    PluginSend { name: &'a str, msg: String },
+    AclExplain(AclExplainParams),
+    AuthChain,
+    TopicMetricsList,
+    TopicMetricsRegister { topic: &'a str },
+    TopicMetricsUnregister { topic: &'a str },
+    TraceStart(TraceDef),
+    TraceStop { name: &'a str },
+    TraceDelete { name: &'a str },
+    TraceList,
+    TraceLog { name: &'a str, position: u64, bytes: usize },
+    AlarmList,
+    AlarmClearDeactivated,
 }
 
 impl Message<'_> {
@@ -56,6 +82,24 @@ pub enum MessageReply {
     ReloadPluginConfig,
     LoadPlugin,
     UnloadPlugin(bool),
+    DelayedList(Vec<DelayedPublish>),
+    DelayedGet(Option<DelayedPublish>),
+    DelayedCancel(Option<DelayedPublish>),
+    ReloadListeners(ListenerChanges),
+    PluginSend(String),
+    AclExplain(Option<AclExplainResult>),
+    AuthChain(Vec<AuthenticatorStats>),
+    TopicMetricsList(Vec<TopicMetricsInfo>),
+    TopicMetricsRegister(bool),
+    TopicMetricsUnregister(bool),
+    TraceStart,
+    TraceStop(bool),
+    TraceDelete(bool),
+    TraceList(Vec<TraceInfo>),
+    TraceLog(Option<TraceLog>),
+    //Activated and deactivated alarms
+    AlarmList(Vec<AlarmInfo>, Vec<AlarmInfo>),
+    AlarmClearDeactivated(usize),
 }
 
 impl MessageReply {
@@ -313,6 +357,103 @@ pub struct UnsubscribeParams {
     pub clientid: ClientId,
 }
 
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct TopicMetricsParams {
+    //Topic filter to be monitored, wildcards are allowed
+    pub topic: String,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct TraceParams {
+    pub name: String,
+    #[serde(rename = "type")]
+    pub typ: TraceType,
+    pub clientid: Option<String>,
+    pub username: Option<String>,
+    pub ip_address: Option<String>,
+    //Topic filter, wildcards are allowed
+    pub topic: Option<String>,
+    #[serde(default = "TraceParams::duration_default", deserialize_with = "deserialize_duration")]
+    pub duration: Duration,
+    #[serde(default)]
+    pub payload_encode: PayloadEncode,
+}
+
+impl TraceParams {
+    #[inline]
+    fn duration_default() -> Duration {
+        Duration::from_secs(60 * 10)
+    }
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct JwtRevokeParams {
+    //jti or sub
+    pub kind: String,
+    pub value: String,
+    //Unix timestamp in seconds after which the entry is dropped, usually the 'exp' of the token
+    #[serde(default)]
+    pub expire_at: Option<Timestamp>,
+}
+
+#[derive(Deserialize, Serialize, Debug, Copy, Clone)]
+#[serde(rename_all = "lowercase")]
+pub enum AclAction {
+    Publish,
+    Subscribe,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct AclExplainParams {
+    //Client connected to the cluster
+    #[serde(default)]
+    pub clientid: Option<String>,
+    //Synthetic connection, it is authenticated in dry-run mode
+    #[serde(default)]
+    pub connect_info: Option<SampleConnectInfo>,
+    pub action: AclAction,
+    pub topic: String,
+    #[serde(default)]
+    pub qos: u8,
+    #[serde(default)]
+    pub retain: bool,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct SampleConnectInfo {
+    pub clientid: String,
+    #[serde(default)]
+    pub username: Option<String>,
+    #[serde(default)]
+    pub password: Option<String>,
+    #[serde(default)]
+    pub ipaddr: Option<IpAddr>,
+    //MQTT Protocol Ver, 3=MQTT 3.1, 4=MQTT 3.11, 5=MQTT 5.0, default 4
+    #[serde(default)]
+    pub protocol: Option<u8>,
+    //Port of the listener the client connects to, the TCP or TLS listener with the lowest port by default
+    #[serde(default)]
+    pub port: Option<u16>,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct AclExplainResult {
+    pub node: NodeId,
+    pub clientid: String,
+    pub synthetic: bool,
+    //Authentication result of the synthetic connection, the ACL is not checked if it is refused
+    pub authenticate: Option<String>,
+    pub authenticate_trace: Vec<HookTrace>,
+    //Superusers skip the ACL hooks
+    pub superuser: bool,
+    pub allow: bool,
+    //ACL result, in debug format
+    pub result: String,
+    //Plugin of the handler that made the decision, "superuser" for superusers, None if no handler did
+    pub decided_by: Option<String>,
+    pub trace: Vec<HookTrace>,
+}
+
 #[derive(Deserialize, Serialize, Debug, Copy, Clone, Hash, Eq, PartialEq)]
 pub enum PrometheusDataType {
     All,
@@ -9,6 +9,7 @@ use rmqtt::{
     async_trait::async_trait,
     log,
     serde_json::{self, json},
+    tokio,
 };
 use std::sync::atomic::Ordering;
 use std::sync::Arc;
@@ -16,7 +17,7 @@ use std::sync::Arc;
 use rmqtt::{
     broker::hook::Register,
     plugin::{PackageInfo, Plugin},
-    register, Result, Runtime,
+    register, MqttError, Result, Runtime,
 };
 use rmqtt_storage::init_db;
 
@@ -102,6 +103,30 @@ impl Plugin for StoragePlugin {
         Ok(false)
     }
 
+    #[inline]
+    async fn flush(&self) -> Result<()> {
+        if let MessageMgr::Storage(mgr) = self.message_mgr {
+            log::info!(
+                "{} flush, msg_queue_count: {}",
+                self.name(),
+                mgr.msg_queue_count.load(Ordering::SeqCst)
+            );
+            //The storage may be unreachable, the shutdown does not wait for it forever
+            let timeout = self.runtime.settings.node.shutdown.drain_timeout;
+            match tokio::time::timeout(timeout, mgr.flush()).await {
+                Ok(res) => res?,
+                Err(_) => {
+                    return Err(MqttError::from(format!(
+                        "flush timeout, not flushed, msg_queue_count: {}, msg_fwds_count: {}",
+                        mgr.msg_queue_count.load(Ordering::SeqCst),
+                        mgr.msg_fwds_count.load(Ordering::SeqCst)
+                    )))
+                }
+            }
+        }
+        Ok(())
+    }
+
     #[inline]
     async fn attrs(&self) -> serde_json::Value {
         self.message_mgr.info().await
@@ -80,7 +80,7 @@ impl StorageMessageManager {
         let messages_received_max =
             StorageMessageManagerInner::storage_new_messages_counter(&storage_db).await?;
         log::info!("messages_received_max: {}", messages_received_max.load(Ordering::SeqCst));
-        let (exec, msg_tx, msg_queue_count) = Self::serve(cfg)?;
+        let (exec, msg_tx, msg_queue_count, msg_fwds_count) = Self::serve(cfg)?;
 
         let inner = Arc::new(StorageMessageManagerInner {
             storage_db,
@@ -89,13 +89,17 @@ impl StorageMessageManager {
             messages_received_max,
             msg_tx,
             msg_queue_count,
+            msg_fwds_count,
             id_generater,
             should_merge_on_get,
         });
         Ok(Self { inner, exec })
     }
 
-    fn serve(cfg: Arc<PluginConfig>) -> Result<(TaskExecQueue, mpsc::Sender<Msg>, Arc<AtomicIsize>)> {
+    #[allow(clippy::type_complexity)]
+    fn serve(
+        cfg: Arc<PluginConfig>,
+    ) -> Result<(TaskExecQueue, mpsc::Sender<Msg>, Arc<AtomicIsize>, Arc<AtomicIsize>)> {
         let queue_max = 300_000;
         let (exec, task_runner) = Builder::default().workers(1000).queue_max(queue_max).build();
 
@@ -105,6 +109,8 @@ impl StorageMessageManager {
 
         let msg_queue_count = Arc::new(AtomicIsize::new(0));
         let msg_queue_count1 = msg_queue_count.clone();
+        let msg_fwds_count = Arc::new(AtomicIsize::new(0));
+        let msg_fwds_count1 = msg_fwds_count.clone();
         let (msg_tx, mut msg_rx) = mpsc::channel::<Msg>(300_000);
         tokio::spawn(async move {
             loop {
@@ -114,8 +120,6 @@ impl StorageMessageManager {
                 sleep(Duration::from_millis(10)).await;
             }
             if let Some(msg_mgr) = INSTANCE.get() {
-                let msg_fwds_count = Arc::new(AtomicIsize::new(0));
-
                 let mut merger_msgs = Vec::new();
                 while let Some(msg) = msg_rx.next().await {
                     merger_msgs.push(msg);
@@ -132,18 +136,18 @@ impl StorageMessageManager {
                     //merge and send
                     let msgs = std::mem::take(&mut merger_msgs);
 
+                    msg_fwds_count1.fetch_add(1, Ordering::SeqCst);
                     msg_queue_count1.fetch_sub(msgs.len() as isize, Ordering::Relaxed);
-                    msg_fwds_count.fetch_add(1, Ordering::SeqCst);
-                    while msg_fwds_count.load(Ordering::SeqCst) > 500 {
+                    while msg_fwds_count1.load(Ordering::SeqCst) > 500 {
                         tokio::time::sleep(Duration::from_millis(1)).await;
                     }
 
-                    let msg_fwds_count1 = msg_fwds_count.clone();
+                    let msg_fwds_count2 = msg_fwds_count1.clone();
                     tokio::spawn(async move {
                         if let Err(e) = msg_mgr._batch_msg_forwardeds(msgs).await {
                             log::warn!("{:?}", e);
                         }
-                        msg_fwds_count1.fetch_sub(1, Ordering::SeqCst);
+                        msg_fwds_count2.fetch_sub(1, Ordering::SeqCst);
                     });
                 }
                 log::error!("Recv failed because receiver is gone");
@@ -204,7 +208,7 @@ impl StorageMessageManager {
             }
         });
 
-        Ok((exec, msg_tx, msg_queue_count))
+        Ok((exec, msg_tx, msg_queue_count, msg_fwds_count))
     }
 }
 
@@ -225,6 +229,7 @@ pub struct StorageMessageManagerInner {
 
     msg_tx: mpsc::Sender<Msg>,
     pub(crate) msg_queue_count: Arc<AtomicIsize>,
+    pub(crate) msg_fwds_count: Arc<AtomicIsize>,
 
     id_generater: AtomicUsize,
     should_merge_on_get: bool,
@@ -280,6 +285,17 @@ impl StorageMessageManagerInner {
         Ok(())
     }
 
+    ///Wait until the queued messages are written to the storage
+    #[inline]
+    pub(crate) async fn flush(&self) -> Result<()> {
+        while self.msg_queue_count.load(Ordering::SeqCst) > 0
+            || self.msg_fwds_count.load(Ordering::SeqCst) > 0
+        {
+            sleep(Duration::from_millis(10)).await;
+        }
+        self.storage_save_msg_id().await
+    }
+
     #[inline]
     async fn storage_save_msg_id(&self) -> Result<()> {
         let curr_msg_id = self.id_generater.load(Ordering::SeqCst);
@@ -27,7 +27,7 @@ use rmqtt::{
     broker::inflight::InflightMessage,
     broker::types::DisconnectInfo,
     plugin::{PackageInfo, Plugin},
-    register, timestamp_millis, ClientId, From, Publish, Result, Runtime, Session, SessionState,
+    register, timestamp_millis, ClientId, From, MqttError, Publish, Result, Runtime, Session, SessionState,
     SessionSubMap, SessionSubs, TimestampMillis,
 };
 
@@ -342,6 +342,20 @@ impl Plugin for StoragePlugin {
         Ok(false)
     }
 
+    #[inline]
+    async fn flush(&self) -> Result<()> {
+        log::info!("{} flush, pending_saves: {}", self.name(), self.session_mgr.pending_saves());
+        //The storage may be unreachable, the shutdown does not wait for it forever
+        let timeout = self.runtime.settings.node.shutdown.drain_timeout;
+        if tokio::time::timeout(timeout, self.session_mgr.flush()).await.is_err() {
+            return Err(MqttError::from(format!(
+                "flush timeout, not flushed, pending_saves: {}",
+                self.session_mgr.pending_saves()
+            )));
+        }
+        Ok(())
+    }
+
     #[inline]
     async fn attrs(&self) -> serde_json::Value {
         let max_limit = 100;
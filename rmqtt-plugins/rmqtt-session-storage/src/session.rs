@@ -1,7 +1,7 @@
 use serde::{Deserialize, Deserializer, Serialize, Serializer};
 
 use std::ops::Deref;
-use std::sync::atomic::{AtomicI64, AtomicU8, Ordering};
+use std::sync::atomic::{AtomicI64, AtomicU8, AtomicUsize, Ordering};
 use std::sync::Arc;
 use std::time::Duration;
 
@@ -30,6 +30,8 @@ pub(crate) const INFLIGHT_MESSAGES: &[u8] = b"5";
 pub(crate) struct StorageSessionManager {
     storage_db: DefaultStorageDB,
     _stored_session_infos: StoredSessionInfos,
+    //Session infos that are being saved in the background
+    pending_saves: AtomicUsize,
 }
 
 impl StorageSessionManager {
@@ -39,7 +41,24 @@ impl StorageSessionManager {
         _stored_session_infos: StoredSessionInfos,
     ) -> &'static StorageSessionManager {
         static INSTANCE: OnceCell<StorageSessionManager> = OnceCell::new();
-        INSTANCE.get_or_init(|| Self { storage_db, _stored_session_infos })
+        INSTANCE.get_or_init(|| Self {
+            storage_db,
+            _stored_session_infos,
+            pending_saves: AtomicUsize::new(0),
+        })
+    }
+
+    #[inline]
+    pub(crate) fn pending_saves(&self) -> usize {
+        self.pending_saves.load(Ordering::SeqCst)
+    }
+
+    ///Wait until the session infos being saved in the background are written to the storage
+    #[inline]
+    pub(crate) async fn flush(&self) {
+        while self.pending_saves() > 0 {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
     }
 }
 
@@ -100,6 +119,8 @@ impl SessionManager for &'static StorageSessionManager {
             ));
             if connected {
                 let s1 = s.clone();
+                let mgr: &'static StorageSessionManager = *self;
+                mgr.pending_saves.fetch_add(1, Ordering::SeqCst);
                 tokio::spawn(async move {
                     if let Err(e) = s1.save_to_db().await {
                         log::error!("Save session info error to db, {:?}", e);
@@ -130,6 +151,7 @@ impl SessionManager for &'static StorageSessionManager {
                             }
                         }
                     }
+                    mgr.pending_saves.fetch_sub(1, Ordering::SeqCst);
                 });
             }
             Ok(s)
@@ -414,6 +436,11 @@ impl SessionLike for StorageSession {
         self.inner.superuser().await
     }
 
+    #[inline]
+    async fn superuser_set(&self, superuser: bool) -> Result<()> {
+        self.inner.superuser_set(superuser).await
+    }
+
     #[inline]
     async fn connected(&self) -> Result<bool> {
         self.inner.connected().await
@@ -8,7 +8,6 @@ extern crate rmqtt_macros;
 use config::PluginConfig;
 use rmqtt::{
     async_trait::async_trait,
-    bytes::Bytes,
     chrono, log,
     serde_json::{self, json},
     tokio::spawn,
@@ -17,12 +16,10 @@ use rmqtt::{
 };
 use rmqtt::{
     broker::hook::{Handler, HookResult, Parameter, Register, ReturnType, Type},
-    broker::types::{From, Id},
+    broker::topic_metrics::TopicMetrics,
     plugin::{PackageInfo, Plugin},
-    register, timestamp_millis, ClientId, NodeId, Publish, PublishProperties, QoS, Result, Runtime,
-    SessionState, TopicName, UserName,
+    register, NodeId, QoS, Result, Runtime, SessionState,
 };
-use std::convert::From as _;
 use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
 use std::time::Duration;
@@ -65,6 +62,7 @@ impl SystemTopicPlugin {
                 if running.load(Ordering::SeqCst) {
                     Self::send_stats(runtime, publish_qos, expiry_interval).await;
                     Self::send_metrics(runtime, publish_qos, expiry_interval).await;
+                    Self::send_topic_metrics(runtime, publish_qos, expiry_interval).await;
                 }
             }
         });
@@ -76,7 +74,7 @@ impl SystemTopicPlugin {
         let payload = runtime.stats.clone().await.to_json().await;
         let nodeid = runtime.node.id();
         let topic = format!("$SYS/brokers/{}/stats", nodeid);
-        sys_publish(nodeid, topic, publish_qos, payload, expiry_interval).await;
+        SessionState::sys_publish(nodeid, topic, publish_qos, payload, expiry_interval).await;
     }
 
     //Metrics
@@ -85,7 +83,21 @@ impl SystemTopicPlugin {
         let payload = Runtime::instance().metrics.to_json();
         let nodeid = runtime.node.id();
         let topic = format!("$SYS/brokers/{}/metrics", nodeid);
-        sys_publish(nodeid, topic, publish_qos, payload, expiry_interval).await;
+        SessionState::sys_publish(nodeid, topic, publish_qos, payload, expiry_interval).await;
+    }
+
+    //Metrics of the monitored topic filters, not published if no topic filter is monitored
+    //$SYS/brokers/${node}/topic-metrics
+    async fn send_topic_metrics(runtime: &'static Runtime, publish_qos: QoS, expiry_interval: Duration) {
+        let topic_metrics = TopicMetrics::instance();
+        if topic_metrics.is_empty() {
+            return;
+        }
+        let payload =
+            serde_json::Value::Array(topic_metrics.list().iter().map(|info| info.to_json()).collect());
+        let nodeid = runtime.node.id();
+        let topic = format!("$SYS/brokers/{}/topic-metrics", nodeid);
+        SessionState::sys_publish(nodeid, topic, publish_qos, payload, expiry_interval).await;
     }
 }
 
@@ -102,6 +114,8 @@ impl Plugin for SystemTopicPlugin {
         self.register.add(Type::ClientDisconnected, Box::new(SystemTopicHandler::new(cfg))).await;
         self.register.add(Type::SessionSubscribed, Box::new(SystemTopicHandler::new(cfg))).await;
         self.register.add(Type::SessionUnsubscribed, Box::new(SystemTopicHandler::new(cfg))).await;
+        self.register.add(Type::AlarmActivated, Box::new(SystemTopicHandler::new(cfg))).await;
+        self.register.add(Type::AlarmDeactivated, Box::new(SystemTopicHandler::new(cfg))).await;
 
         Self::start(self.runtime, self.cfg.clone(), self.running.clone());
         Ok(())
@@ -250,6 +264,24 @@ impl Handler for SystemTopicHandler {
                 Some((topic, body))
             }
 
+            Parameter::AlarmActivated(alarm) => {
+                let mut body = alarm.to_json();
+                if let Some(obj) = body.as_object_mut() {
+                    obj.insert("time".into(), serde_json::Value::String(now_time));
+                }
+                let topic = format!("$SYS/brokers/{}/alarms/activate", self.nodeid);
+                Some((topic, body))
+            }
+
+            Parameter::AlarmDeactivated(alarm) => {
+                let mut body = alarm.to_json();
+                if let Some(obj) = body.as_object_mut() {
+                    obj.insert("time".into(), serde_json::Value::String(now_time));
+                }
+                let topic = format!("$SYS/brokers/{}/alarms/deactivate", self.nodeid);
+                Some((topic, body))
+            }
+
             _ => {
                 log::error!("unimplemented, {:?}", param);
                 None
@@ -261,61 +293,8 @@ impl Handler for SystemTopicHandler {
                 (cfg_rl.publish_qos, cfg_rl.message_expiry_interval)
             };
 
-            spawn(sys_publish(nodeid, topic, publish_qos, payload, expiry_interval));
+            spawn(SessionState::sys_publish(nodeid, topic, publish_qos, payload, expiry_interval));
         }
         (true, acc)
     }
 }
-
-#[inline]
-async fn sys_publish(
-    nodeid: NodeId,
-    topic: String,
-    publish_qos: QoS,
-    payload: serde_json::Value,
-    message_expiry_interval: Duration,
-) {
-    match serde_json::to_string(&payload) {
-        Ok(payload) => {
-            let from = From::from_system(Id::new(
-                nodeid,
-                None,
-                None,
-                ClientId::from_static("system"),
-                Some(UserName::from("system")),
-            ));
-
-            let p = Publish {
-                dup: false,
-                retain: false,
-                qos: publish_qos,
-                topic: TopicName::from(topic),
-                packet_id: None,
-                payload: Bytes::from(payload),
-                properties: PublishProperties::default(),
-                delay_interval: None,
-                create_time: timestamp_millis(),
-            };
-
-            //hook, message_publish
-            let p = Runtime::instance()
-                .extends
-                .hook_mgr()
-                .await
-                .message_publish(None, from.clone(), &p)
-                .await
-                .unwrap_or(p);
-
-            let storage_available = Runtime::instance().extends.message_mgr().await.enable();
-
-            if let Err(e) =
-                SessionState::forwards(from, p, storage_available, Some(message_expiry_interval)).await
-            {
-                log::warn!("{:?}", e);
-            }
-        }
-        Err(e) => {
-            log::error!("{:?}", e);
-        }
-    }
-}
@@ -257,6 +257,19 @@ impl Plugin for WebHookPlugin {
             )
             .await;
 
+        self.register
+            .add(
+                Type::AlarmActivated,
+                Box::new(WebHookHandler { tx: tx.clone(), chan_queue_count: chan_queue_count.clone() }),
+            )
+            .await;
+        self.register
+            .add(
+                Type::AlarmDeactivated,
+                Box::new(WebHookHandler { tx: tx.clone(), chan_queue_count: chan_queue_count.clone() }),
+            )
+            .await;
+
         Ok(())
     }
 
@@ -710,6 +723,14 @@ impl Handler for WebHookHandler {
                     Some((None, body))
                 }
             }
+
+            Parameter::AlarmActivated(alarm) | Parameter::AlarmDeactivated(alarm) => {
+                let mut body = alarm.to_json();
+                if let Some(obj) = body.as_object_mut() {
+                    obj.insert("time".into(), serde_json::Value::String(now_time));
+                }
+                Some((None, body))
+            }
             _ => {
                 log::error!("parameter is: {:?}", param);
                 None
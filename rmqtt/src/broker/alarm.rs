@@ -0,0 +1,481 @@
+use std::collections::VecDeque;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use once_cell::sync::OnceCell;
+use parking_lot::{Mutex, RwLock};
+use serde::de::{self, Deserializer};
+use serde::ser::{self, Serializer};
+use serde::{Deserialize, Serialize};
+use systemstat::Platform;
+use tokio::sync::mpsc;
+
+use crate::broker::executor::listener_connections;
+use crate::{timestamp_millis, DashMap, NodeId, Runtime, TimestampMillis};
+
+///CPU usage is higher than alarm.cpu_high_watermark
+pub const ALARM_HIGH_CPU_USAGE: &str = "high_cpu_usage";
+///Memory usage of the system is higher than alarm.memory_high_watermark
+pub const ALARM_HIGH_MEMORY_USAGE: &str = "high_system_memory_usage";
+///Connections of a listener are close to its max_connections, "too_many_connections/{port}"
+pub const ALARM_TOO_MANY_CONNECTIONS: &str = "too_many_connections";
+///Messages are dropped because the message queues of the sessions are full
+pub const ALARM_MQUEUE_SATURATION: &str = "mqueue_saturation";
+///Disk usage of a storage directory is higher than alarm.disk_high_watermark, "high_disk_usage/{path}"
+pub const ALARM_HIGH_DISK_USAGE: &str = "high_disk_usage";
+///The raft cluster has no leader, the quorum is lost, activated by rmqtt-cluster-raft
+pub const ALARM_CLUSTER_RAFT_UNAVAILABLE: &str = "cluster_raft_unavailable";
+///A bridge client is disconnected from the remote server, "bridge_disconnected/{client_id}",
+///activated by the bridge plugins
+pub const ALARM_BRIDGE_DISCONNECTED: &str = "bridge_disconnected";
+
+///An alarm of a node
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct AlarmInfo {
+    pub node_id: NodeId,
+    pub name: String,
+    pub message: String,
+    #[serde(
+        serialize_with = "AlarmInfo::serialize_details",
+        deserialize_with = "AlarmInfo::deserialize_details"
+    )]
+    pub details: serde_json::Value,
+    pub activated_at: TimestampMillis,
+    //None if the alarm is activated
+    pub deactivated_at: Option<TimestampMillis>,
+}
+
+impl AlarmInfo {
+    #[inline]
+    fn serialize_details<S>(details: &serde_json::Value, s: S) -> std::result::Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serde_json::to_vec(details).map_err(ser::Error::custom)?.serialize(s)
+    }
+
+    #[inline]
+    fn deserialize_details<'de, D>(d: D) -> std::result::Result<serde_json::Value, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        serde_json::from_slice(&Vec::deserialize(d)?).map_err(de::Error::custom)
+    }
+
+    #[inline]
+    pub fn is_activated(&self) -> bool {
+        self.deactivated_at.is_none()
+    }
+
+    #[inline]
+    pub fn to_json(&self) -> serde_json::Value {
+        serde_json::json!({
+            "node": self.node_id,
+            "name": self.name,
+            "message": self.message,
+            "details": self.details,
+            "activated": self.is_activated(),
+            "activated_at": self.activated_at,
+            "deactivated_at": self.deactivated_at,
+        })
+    }
+}
+
+enum AlarmEvent {
+    Activated(AlarmInfo),
+    Deactivated(AlarmInfo),
+}
+
+//Settings used by the periodic check, taken once per check
+struct CheckContext {
+    node_id: NodeId,
+    history_max: usize,
+}
+
+///Registry of the alarms of this node. The activated and deactivated alarms are passed to the
+///alarm_activated and alarm_deactivated hooks in the order they occur
+pub struct Alarms {
+    activated: DashMap<String, AlarmInfo>,
+    deactivated: RwLock<VecDeque<AlarmInfo>>,
+    events_tx: mpsc::UnboundedSender<AlarmEvent>,
+    events_rx: Mutex<Option<mpsc::UnboundedReceiver<AlarmEvent>>>,
+    mqueue_full_drops: AtomicUsize,
+}
+
+impl Alarms {
+    #[inline]
+    pub fn instance() -> &'static Alarms {
+        static INSTANCE: OnceCell<Alarms> = OnceCell::new();
+        INSTANCE.get_or_init(Self::new)
+    }
+
+    #[inline]
+    fn new() -> Self {
+        let (events_tx, events_rx) = mpsc::unbounded_channel();
+        Self {
+            activated: DashMap::default(),
+            deactivated: RwLock::new(VecDeque::new()),
+            events_tx,
+            events_rx: Mutex::new(Some(events_rx)),
+            mqueue_full_drops: AtomicUsize::new(0),
+        }
+    }
+
+    ///Start passing the alarm events to the hooks, the events that occurred before are not lost
+    pub(crate) fn start(&self) {
+        if let Some(mut events_rx) = self.events_rx.lock().take() {
+            tokio::spawn(async move {
+                while let Some(event) = events_rx.recv().await {
+                    let hook_mgr = Runtime::instance().extends.hook_mgr().await;
+                    match event {
+                        AlarmEvent::Activated(alarm) => hook_mgr.alarm_activated(&alarm).await,
+                        AlarmEvent::Deactivated(alarm) => hook_mgr.alarm_deactivated(&alarm).await,
+                    }
+                }
+            });
+        }
+    }
+
+    ///Activate an alarm, returns false if it is already activated or the alarms are disabled,
+    ///the message and details of an activated alarm are updated
+    pub fn activate<N, M>(&self, name: N, message: M, details: serde_json::Value) -> bool
+    where
+        N: Into<String>,
+        M: Into<String>,
+    {
+        if !Runtime::instance().settings.alarm.enable {
+            return false;
+        }
+        self._activate(Runtime::instance().node.id(), name.into(), message.into(), details)
+    }
+
+    fn _activate(&self, node_id: NodeId, name: String, message: String, details: serde_json::Value) -> bool {
+        if let Some(mut alarm) = self.activated.get_mut(&name) {
+            alarm.message = message;
+            alarm.details = details;
+            return false;
+        }
+        let alarm = AlarmInfo {
+            node_id,
+            name: name.clone(),
+            message,
+            details,
+            activated_at: timestamp_millis(),
+            deactivated_at: None,
+        };
+        log::warn!("alarm activated, {}: {}, {}", alarm.name, alarm.message, alarm.details);
+        self.activated.insert(name, alarm.clone());
+        let _ = self.events_tx.send(AlarmEvent::Activated(alarm));
+        true
+    }
+
+    ///Deactivate an alarm and move it to the history, returns false if it is not activated
+    #[inline]
+    pub fn deactivate(&self, name: &str) -> bool {
+        self._deactivate(name, Runtime::instance().settings.alarm.history_max)
+    }
+
+    fn _deactivate(&self, name: &str, history_max: usize) -> bool {
+        let mut alarm = if let Some((_, alarm)) = self.activated.remove(name) {
+            alarm
+        } else {
+            return false;
+        };
+        alarm.deactivated_at = Some(timestamp_millis());
+        log::info!("alarm deactivated, {}: {}", alarm.name, alarm.message);
+        {
+            let mut deactivated = self.deactivated.write();
+            deactivated.push_front(alarm.clone());
+            deactivated.truncate(history_max);
+        }
+        let _ = self.events_tx.send(AlarmEvent::Deactivated(alarm));
+        true
+    }
+
+    #[inline]
+    pub fn is_activated(&self, name: &str) -> bool {
+        self.activated.contains_key(name)
+    }
+
+    ///Activated alarms, sorted by activation time
+    #[inline]
+    pub fn activated(&self) -> Vec<AlarmInfo> {
+        let mut alarms = self.activated.iter().map(|entry| entry.value().clone()).collect::<Vec<_>>();
+        alarms.sort_by_key(|a| a.activated_at);
+        alarms
+    }
+
+    ///Deactivated alarms, the most recently deactivated first
+    #[inline]
+    pub fn deactivated(&self) -> Vec<AlarmInfo> {
+        self.deactivated.read().iter().cloned().collect()
+    }
+
+    ///Clear the history of the deactivated alarms, returns the number of cleared alarms
+    #[inline]
+    pub fn clear_deactivated(&self) -> usize {
+        let mut deactivated = self.deactivated.write();
+        let n = deactivated.len();
+        deactivated.clear();
+        n
+    }
+
+    #[inline]
+    pub(crate) fn mqueue_full(&self) {
+        self.mqueue_full_drops.fetch_add(1, Ordering::Relaxed);
+    }
+
+    ///Check the CPU, memory, connections, message queues and disk space, called periodically
+    ///by the scheduler
+    pub(crate) async fn check(&self) {
+        let cfg = &Runtime::instance().settings.alarm;
+        let node = &Runtime::instance().node;
+        let sys = systemstat::System::new();
+        let ctx = CheckContext { node_id: node.id(), history_max: cfg.history_max };
+
+        //CPU, the load is updated by the scheduler if the busy status check is enabled
+        if !Runtime::instance().settings.node.busy.check_enable {
+            node.update_cpuload().await;
+        }
+        let cpuload = node.cpuload();
+        self.check_watermark(
+            &ctx,
+            ALARM_HIGH_CPU_USAGE,
+            cpuload,
+            (cfg.cpu_high_watermark, cfg.cpu_low_watermark),
+            || format!("CPU usage is higher than {}%", cfg.cpu_high_watermark),
+            || serde_json::json!({ "usage": cpuload }),
+        );
+
+        //Memory
+        if let Ok(mem) = sys.memory() {
+            let total = mem.total.as_u64();
+            if total > 0 {
+                let used = systemstat::saturating_sub_bytes(mem.total, mem.free).as_u64();
+                let usage = used as f32 * 100.0 / total as f32;
+                self.check_watermark(
+                    &ctx,
+                    ALARM_HIGH_MEMORY_USAGE,
+                    usage,
+                    (cfg.memory_high_watermark, cfg.memory_low_watermark),
+                    || format!("System memory usage is higher than {}%", cfg.memory_high_watermark),
+                    || serde_json::json!({ "usage": usage, "total": total, "used": used }),
+                );
+            }
+        }
+
+        //Connections of the listeners
+        for (typ, l) in Runtime::instance().settings.listeners.all() {
+            if l.max_connections == 0 {
+                continue;
+            }
+            let port = l.addr.port();
+            let connections = listener_connections(port).max(0) as usize;
+            let usage = connections as f32 * 100.0 / l.max_connections as f32;
+            self.check_watermark(
+                &ctx,
+                format!("{}/{}", ALARM_TOO_MANY_CONNECTIONS, port),
+                usage,
+                (cfg.connections_high_watermark, cfg.connections_low_watermark),
+                || {
+                    format!(
+                        "Connections of the {} listener {} are higher than {}% of max_connections",
+                        typ, l.name, cfg.connections_high_watermark
+                    )
+                },
+                || {
+                    serde_json::json!({
+                        "listener": format!("{}/{}", typ, l.name),
+                        "addr": l.addr.to_string(),
+                        "connections": connections,
+                        "max_connections": l.max_connections,
+                    })
+                },
+            );
+        }
+
+        //Message queues
+        let drops = self.mqueue_full_drops.swap(0, Ordering::Relaxed);
+        if cfg.mqueue_full_drops > 0 && drops >= cfg.mqueue_full_drops {
+            self.activate(
+                ALARM_MQUEUE_SATURATION,
+                "Messages are dropped because the message queues are full",
+                serde_json::json!({
+                    "dropped": drops,
+                    "interval": cfg.check_interval.as_secs(),
+                    "message_queues": Runtime::instance().stats.message_queues.count(),
+                }),
+            );
+        } else if drops == 0 {
+            self.deactivate(ALARM_MQUEUE_SATURATION);
+        }
+
+        //Disk space of the storage directories
+        if !cfg.disk_paths.is_empty() {
+            let mounts = sys.mounts().unwrap_or_default();
+            for path in cfg.disk_paths.iter() {
+                let path = path.replace("{node}", &node.id().to_string());
+                //The file system of the longest mount point that contains the path, relative paths and
+                //symbolic links are resolved first
+                let abs_path = absolute_path(&path);
+                let fs = mounts
+                    .iter()
+                    .filter(|m| abs_path.starts_with(&m.fs_mounted_on))
+                    .max_by_key(|m| m.fs_mounted_on.len());
+                let fs = if let Some(fs) = fs {
+                    fs
+                } else {
+                    continue;
+                };
+                let total = fs.total.as_u64();
+                if total == 0 {
+                    continue;
+                }
+                let avail = fs.avail.as_u64();
+                let usage = total.saturating_sub(avail) as f32 * 100.0 / total as f32;
+                self.check_watermark(
+                    &ctx,
+                    format!("{}/{}", ALARM_HIGH_DISK_USAGE, path),
+                    usage,
+                    (cfg.disk_high_watermark, cfg.disk_low_watermark),
+                    || format!("Disk usage of {} is higher than {}%", path, cfg.disk_high_watermark),
+                    || {
+                        serde_json::json!({
+                            "path": path,
+                            "mount_point": fs.fs_mounted_on,
+                            "usage": usage,
+                            "total": total,
+                            "avail": avail,
+                        })
+                    },
+                );
+            }
+        }
+    }
+
+    ///Activated above the high watermark and deactivated below the low watermark, the state is kept
+    ///in between
+    #[inline]
+    fn check_watermark<N, M, D>(
+        &self,
+        ctx: &CheckContext,
+        name: N,
+        value: f32,
+        (high, low): (f32, f32),
+        message: M,
+        details: D,
+    ) where
+        N: Into<String> + AsRef<str>,
+        M: FnOnce() -> String,
+        D: FnOnce() -> serde_json::Value,
+    {
+        if value > high {
+            self._activate(ctx.node_id, name.into(), message(), details());
+        } else if value < low {
+            self._deactivate(name.as_ref(), ctx.history_max);
+        }
+    }
+}
+
+///The absolute path with the symbolic links resolved. A path that does not exist is only made absolute
+#[inline]
+fn absolute_path(path: &str) -> PathBuf {
+    let path = Path::new(path);
+    std::fs::canonicalize(path).unwrap_or_else(|_| match std::env::current_dir() {
+        Ok(dir) if path.is_relative() => dir.join(path),
+        _ => path.to_path_buf(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CTX: CheckContext = CheckContext { node_id: 1, history_max: 10 };
+
+    fn events(alarms: &Alarms) -> Vec<AlarmEvent> {
+        let mut events_rx = alarms.events_rx.lock();
+        let events_rx = events_rx.as_mut().unwrap();
+        let mut events = Vec::new();
+        while let Ok(event) = events_rx.try_recv() {
+            events.push(event);
+        }
+        events
+    }
+
+    #[test]
+    fn watermark_hysteresis() {
+        let alarms = Alarms::new();
+        let check = |value| {
+            alarms.check_watermark(
+                &CTX,
+                ALARM_HIGH_CPU_USAGE,
+                value,
+                (80.0, 60.0),
+                || "CPU usage is higher than 80%".into(),
+                || serde_json::json!({ "usage": value }),
+            )
+        };
+        check(70.0);
+        assert!(!alarms.is_activated(ALARM_HIGH_CPU_USAGE));
+        check(90.0);
+        assert!(alarms.is_activated(ALARM_HIGH_CPU_USAGE));
+        //Between the watermarks the alarm stays activated
+        check(70.0);
+        assert!(alarms.is_activated(ALARM_HIGH_CPU_USAGE));
+        check(50.0);
+        assert!(!alarms.is_activated(ALARM_HIGH_CPU_USAGE));
+        //and stays deactivated
+        check(70.0);
+        assert!(!alarms.is_activated(ALARM_HIGH_CPU_USAGE));
+        check(85.0);
+        assert!(alarms.is_activated(ALARM_HIGH_CPU_USAGE));
+
+        let events = events(&alarms);
+        assert_eq!(events.len(), 3);
+        assert!(matches!(&events[1], AlarmEvent::Deactivated(a) if a.deactivated_at.is_some()));
+        assert_eq!(alarms.deactivated().len(), 1);
+    }
+
+    #[test]
+    fn activate_update() {
+        let alarms = Alarms::new();
+        assert!(alarms._activate(1, "a1".into(), "message 1".into(), serde_json::json!({ "usage": 90 })));
+        let activated_at = alarms.activated()[0].activated_at;
+        //Already activated, updated without a new event
+        assert!(!alarms._activate(1, "a1".into(), "message 2".into(), serde_json::json!({ "usage": 95 })));
+        let alarm = &alarms.activated()[0];
+        assert_eq!(alarm.message, "message 2");
+        assert_eq!(alarm.details, serde_json::json!({ "usage": 95 }));
+        assert_eq!(alarm.activated_at, activated_at);
+        assert_eq!(alarms.activated().len(), 1);
+        let events = events(&alarms);
+        assert_eq!(events.len(), 1);
+        assert!(matches!(&events[0], AlarmEvent::Activated(a) if a.message == "message 1"));
+    }
+
+    #[test]
+    fn deactivate_history() {
+        let alarms = Alarms::new();
+        assert!(!alarms._deactivate("a0", 2));
+        for name in ["a1", "a2", "a3"] {
+            alarms._activate(1, name.into(), name.into(), serde_json::Value::Null);
+            assert!(alarms._deactivate(name, 2));
+            assert!(!alarms._deactivate(name, 2));
+        }
+        //The most recently deactivated first, at most history_max
+        let names = alarms.deactivated().into_iter().map(|a| a.name).collect::<Vec<_>>();
+        assert_eq!(names, vec!["a3", "a2"]);
+        assert!(alarms.activated().is_empty());
+        assert_eq!(alarms.clear_deactivated(), 2);
+    }
+
+    #[test]
+    fn disk_path() {
+        let cwd = std::env::current_dir().unwrap();
+        assert!(absolute_path("no-such-dir/db").is_absolute());
+        assert_eq!(absolute_path("no-such-dir/db"), cwd.join("no-such-dir/db"));
+        assert_eq!(absolute_path("."), std::fs::canonicalize(&cwd).unwrap());
+        assert_eq!(absolute_path("/no-such-dir/db"), PathBuf::from("/no-such-dir/db"));
+    }
+}
@@ -0,0 +1,228 @@
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use once_cell::sync::OnceCell;
+
+use crate::broker::hook::HookResult;
+use crate::settings::auth::{AuthControl, Authenticator};
+use crate::{AuthResult, DashMap};
+
+///Result of one authenticator of the auth chain
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Decision {
+    Allow,
+    Deny,
+    //No decision, such as an unknown user
+    Ignore,
+}
+
+impl Decision {
+    #[inline]
+    pub fn from_result(result: Option<&HookResult>) -> Self {
+        match result {
+            Some(HookResult::AuthResult(AuthResult::Allow(..))) => Decision::Allow,
+            Some(HookResult::AuthResult(AuthResult::BadUsernameOrPassword))
+            | Some(HookResult::AuthResult(AuthResult::NotAuthorized)) => Decision::Deny,
+            _ => Decision::Ignore,
+        }
+    }
+}
+
+///Whether the result of the authenticator ends the chain. A required authenticator that does not decide
+///ends the chain with failure.
+#[inline]
+pub(crate) fn ends_chain(control: AuthControl, decision: Decision) -> bool {
+    matches!(
+        (control, decision),
+        (AuthControl::Sufficient, Decision::Allow)
+            | (AuthControl::Required, Decision::Deny)
+            | (AuthControl::Required, Decision::Ignore)
+    )
+}
+
+///Results of the authenticators that did not end the chain
+pub(crate) struct Undecided<T> {
+    allowed: Option<(AuthControl, T)>,
+    denied: Option<(AuthControl, T)>,
+}
+
+impl<T> Default for Undecided<T> {
+    #[inline]
+    fn default() -> Self {
+        Self { allowed: None, denied: None }
+    }
+}
+
+impl<T> Undecided<T> {
+    ///Keeps the first allow and the first deny, a result of an optional authenticator is replaced by
+    ///the result of a required or sufficient authenticator
+    #[inline]
+    pub(crate) fn push(&mut self, control: AuthControl, decision: Decision, value: T) {
+        let slot = match decision {
+            Decision::Allow => &mut self.allowed,
+            Decision::Deny => &mut self.denied,
+            Decision::Ignore => return,
+        };
+        let replace = match slot {
+            None => true,
+            Some((prev, _)) => *prev == AuthControl::Optional && control != AuthControl::Optional,
+        };
+        if replace {
+            *slot = Some((control, value));
+        }
+    }
+
+    ///The first allow, or the first deny if no authenticator allowed the client. The results of optional
+    ///authenticators are used only if no required or sufficient authenticator decided.
+    #[inline]
+    pub(crate) fn decide(self) -> Option<T> {
+        match (self.allowed, self.denied) {
+            (Some((control, allowed)), _) if control != AuthControl::Optional => Some(allowed),
+            (_, Some((control, denied))) if control != AuthControl::Optional => Some(denied),
+            (allowed, denied) => allowed.or(denied).map(|(_, v)| v),
+        }
+    }
+}
+
+#[derive(Default)]
+struct Counters {
+    allow: AtomicUsize,
+    deny: AtomicUsize,
+    ignore: AtomicUsize,
+    decided: AtomicUsize,
+}
+
+///Results of the authenticators of the auth chain on this node
+pub struct AuthChainStats {
+    counters: DashMap<String, Counters>,
+}
+
+impl AuthChainStats {
+    #[inline]
+    pub fn instance() -> &'static AuthChainStats {
+        static INSTANCE: OnceCell<AuthChainStats> = OnceCell::new();
+        INSTANCE.get_or_init(|| Self { counters: DashMap::default() })
+    }
+
+    #[inline]
+    fn with<F: FnOnce(&Counters)>(&self, plugin: &str, f: F) {
+        if let Some(c) = self.counters.get(plugin) {
+            f(c.value())
+        } else {
+            f(self.counters.entry(plugin.to_owned()).or_default().value())
+        }
+    }
+
+    #[inline]
+    pub(crate) fn record(&self, plugin: &str, decision: Decision) {
+        self.with(plugin, |c| {
+            match decision {
+                Decision::Allow => c.allow.fetch_add(1, Ordering::SeqCst),
+                Decision::Deny => c.deny.fetch_add(1, Ordering::SeqCst),
+                Decision::Ignore => c.ignore.fetch_add(1, Ordering::SeqCst),
+            };
+        })
+    }
+
+    ///The authenticator decided the result of the chain
+    #[inline]
+    pub(crate) fn decided(&self, plugin: &str) {
+        self.with(plugin, |c| {
+            c.decided.fetch_add(1, Ordering::SeqCst);
+        })
+    }
+
+    ///Statistics of the authenticators of the chain, in the configured order
+    pub fn stats(&self, chain: &[Authenticator]) -> Vec<AuthenticatorStats> {
+        chain
+            .iter()
+            .map(|a| {
+                let load = |f: fn(&Counters) -> &AtomicUsize| {
+                    self.counters.get(&a.plugin).map(|c| f(c.value()).load(Ordering::SeqCst)).unwrap_or(0)
+                };
+                AuthenticatorStats {
+                    plugin: a.plugin.clone(),
+                    control: a.control,
+                    allow: load(|c| &c.allow),
+                    deny: load(|c| &c.deny),
+                    ignore: load(|c| &c.ignore),
+                    decided: load(|c| &c.decided),
+                }
+            })
+            .collect()
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct AuthenticatorStats {
+    pub plugin: String,
+    pub control: AuthControl,
+    pub allow: usize,
+    pub deny: usize,
+    pub ignore: usize,
+    //Number of times the result of the chain was decided by this authenticator
+    pub decided: usize,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use AuthControl::*;
+    use Decision::*;
+
+    //Runs the decisions through the chain, returns the index of the authenticator that decided
+    fn run(chain: &[(AuthControl, Decision)]) -> Option<usize> {
+        let mut undecided = Undecided::default();
+        for (i, (control, decision)) in chain.iter().enumerate() {
+            if ends_chain(*control, *decision) {
+                return Some(i);
+            }
+            undecided.push(*control, *decision, i);
+        }
+        undecided.decide()
+    }
+
+    #[test]
+    fn ends() {
+        assert!(ends_chain(Sufficient, Allow));
+        assert!(!ends_chain(Sufficient, Deny));
+        assert!(!ends_chain(Sufficient, Ignore));
+        assert!(!ends_chain(Required, Allow));
+        assert!(ends_chain(Required, Deny));
+        assert!(ends_chain(Required, Ignore));
+        assert!(!ends_chain(Optional, Allow));
+        assert!(!ends_chain(Optional, Deny));
+        assert!(!ends_chain(Optional, Ignore));
+    }
+
+    #[test]
+    fn sufficient() {
+        assert_eq!(run(&[(Sufficient, Allow), (Required, Deny)]), Some(0));
+        assert_eq!(run(&[(Sufficient, Deny), (Sufficient, Allow)]), Some(1));
+        assert_eq!(run(&[(Sufficient, Ignore), (Sufficient, Deny)]), Some(1));
+        assert_eq!(run(&[(Sufficient, Ignore), (Sufficient, Ignore)]), None);
+        //A sufficient deny is not overridden by an optional allow
+        assert_eq!(run(&[(Sufficient, Deny), (Optional, Allow)]), Some(0));
+        assert_eq!(run(&[(Optional, Allow), (Sufficient, Deny)]), Some(1));
+    }
+
+    #[test]
+    fn required() {
+        assert_eq!(run(&[(Required, Deny), (Sufficient, Allow)]), Some(0));
+        assert_eq!(run(&[(Required, Ignore), (Optional, Allow)]), Some(0));
+        assert_eq!(run(&[(Optional, Allow), (Required, Deny)]), Some(1));
+        assert_eq!(run(&[(Required, Allow), (Sufficient, Allow)]), Some(1));
+        assert_eq!(run(&[(Required, Allow), (Sufficient, Deny)]), Some(0));
+        assert_eq!(run(&[(Optional, Deny), (Required, Allow)]), Some(1));
+        assert_eq!(run(&[(Required, Allow), (Optional, Deny)]), Some(0));
+    }
+
+    #[test]
+    fn optional() {
+        assert_eq!(run(&[(Optional, Allow)]), Some(0));
+        assert_eq!(run(&[(Optional, Deny)]), Some(0));
+        assert_eq!(run(&[(Optional, Ignore)]), None);
+        assert_eq!(run(&[(Optional, Deny), (Optional, Allow)]), Some(1));
+        assert_eq!(run(&[(Optional, Allow), (Optional, Allow)]), Some(0));
+        assert_eq!(run(&[(Optional, Deny), (Sufficient, Ignore), (Optional, Deny)]), Some(0));
+    }
+}
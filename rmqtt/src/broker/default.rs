@@ -1,29 +1,42 @@
 use std::collections::{BTreeMap, BinaryHeap};
 use std::convert::From as _f;
+use std::hash::Hasher;
 use std::num::NonZeroU16;
 use std::num::NonZeroU32;
 use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
 use std::sync::Arc;
+use std::time::Instant;
 
 #[allow(unused_imports)]
 use bitflags::Flags;
 use itertools::Itertools;
 use ntex_mqtt::types::{MQTT_LEVEL_31, MQTT_LEVEL_311, MQTT_LEVEL_5};
 use once_cell::sync::OnceCell;
+use rand::seq::SliceRandom;
 use tokio::sync::oneshot;
 use tokio::sync::RwLock;
 use tokio::sync::{self, Mutex, OwnedMutexGuard};
 use tokio::time::Duration;
 use uuid::Uuid;
 
+use crate::broker::alarm::{AlarmInfo, Alarms};
+use crate::broker::auth_chain::{ends_chain, AuthChainStats, Decision, Undecided};
 use crate::broker::fitter::{Fitter, FitterManager};
-use crate::broker::hook::{Handler, Hook, HookManager, HookResult, Parameter, Priority, Register, Type};
+use crate::broker::hook::{
+    dry_run_trace, is_dry_run, is_plugin_handler, Handler, Hook, HookManager, HookResult, Parameter,
+    Priority, Register, ReturnType, Type,
+};
 use crate::broker::inflight::InflightMessage;
+use crate::broker::metrics::{Histogram, Metrics};
 use crate::broker::session::{Session, SessionLike, SessionManager};
 use crate::broker::topic::{Topic, VecToTopic};
+use crate::broker::topic_metrics::TopicMetrics;
 use crate::broker::types::*;
 use crate::settings::acl::AuthInfo;
-use crate::settings::listener::Listener;
+use crate::settings::auth::Authenticator;
+use crate::settings::listener::{Listener, PublishLimit};
+use crate::settings::SharedSubscriptionStrategy;
 use crate::stats::Counter;
 use crate::{grpc, MqttError, Result, Runtime, SessionState};
 
@@ -708,16 +721,7 @@ impl DefaultRouter {
         for (topic_filter, _node_ids) in self.topics.read().await.matches(&topic).iter() {
             let topic_filter = topic_filter.to_topic_filter();
             #[allow(clippy::mutable_key_type)]
-            let mut groups: HashMap<
-                SharedGroup,
-                Vec<(
-                    NodeId,
-                    ClientId,
-                    SubscriptionOptions,
-                    Option<Vec<SubscriptionIdentifier>>,
-                    Option<IsOnline>,
-                )>,
-            > = HashMap::default();
+            let mut groups: HashMap<SharedGroup, Vec<SharedSubChoice>> = HashMap::default();
 
             if let Some(rels) = self.relations.get(&topic_filter) {
                 for (client_id, (id, opts)) in rels.iter() {
@@ -751,8 +755,12 @@ impl DefaultRouter {
             for (group, mut s_subs) in groups.drain() {
                 log::debug!("group: {}, s_subs: {:?}", group, s_subs);
                 let group_cids = s_subs.iter().map(|(_, cid, _, _, _)| cid.clone()).collect();
-                if let Some((idx, is_online)) =
-                    Runtime::instance().extends.shared_subscription().await.choice(&s_subs).await
+                if let Some((idx, is_online)) = Runtime::instance()
+                    .extends
+                    .shared_subscription()
+                    .await
+                    .choice(&this_id, topic_name, &topic_filter, &group, &s_subs)
+                    .await
                 {
                     let (node_id, client_id, opts, _, _) = s_subs.remove(idx);
                     collector_map.entry(node_id).or_default().add(
@@ -979,10 +987,17 @@ impl Router for &'static DefaultRouter {
                 }
             }).unwrap_or(false);
             if remove_enable {
-                let remove_ok = rels.value_mut().remove(&id.client_id).is_some();
+                let removed = rels.value_mut().remove(&id.client_id);
+                let remove_ok = removed.is_some();
                 if remove_ok {
                     self.relations_count.dec();
                 }
+                //the last subscriber of the $share group on this topic filter is gone
+                if let Some(group) = removed.as_ref().and_then(|(_, opts)| opts.shared_group()) {
+                    if !rels.values().any(|(_, opts)| opts.shared_group() == Some(group)) {
+                        DefaultSharedSubscription::instance().remove(group, topic_filter);
+                    }
+                }
                 Some((rels.is_empty(), remove_ok))
             } else {
                 None
@@ -1141,18 +1156,137 @@ impl Router for &'static DefaultRouter {
     }
 }
 
-pub struct DefaultSharedSubscription {}
+pub struct DefaultSharedSubscription {
+    round_robins: DashMap<(SharedGroup, TopicFilter), AtomicUsize>,
+}
 
 impl DefaultSharedSubscription {
     #[inline]
     pub fn instance() -> &'static DefaultSharedSubscription {
         static INSTANCE: OnceCell<DefaultSharedSubscription> = OnceCell::new();
-        INSTANCE.get_or_init(|| Self {})
+        INSTANCE.get_or_init(|| Self { round_robins: DashMap::default() })
+    }
+
+    ///Removes the round robin counter of the $share group on the topic filter
+    #[inline]
+    pub fn remove(&self, group: &str, topic_filter: &str) {
+        self.round_robins.remove(&(SharedGroup::from(group), TopicFilter::from(topic_filter)));
+    }
+
+    ///The strategy configured for the $share group, otherwise for the publisher's listener,
+    ///otherwise the default strategy
+    #[inline]
+    pub fn strategy(&self, from: &Id, group: &SharedGroup) -> SharedSubscriptionStrategy {
+        let mqtt = &Runtime::instance().settings.mqtt;
+        if let Some(strategy) = mqtt.shared_subscription_group_strategies.get::<str>(group) {
+            return *strategy;
+        }
+        from.local_addr
+            .and_then(|addr| Runtime::instance().settings.listeners.get(addr.port()))
+            .and_then(|listen_cfg| listen_cfg.shared_subscription_strategy)
+            .unwrap_or(mqtt.shared_subscription_strategy)
+    }
+
+    ///Highest random weight, the same key always selects the same subscriber as long as it is a member.
+    ///SipHash-1-3 with fixed keys, so the weights are the same on all nodes, builds and platforms
+    #[inline]
+    fn rendezvous_weight(key: &str, node_id: NodeId, client_id: &str) -> u64 {
+        let mut hasher = siphasher::sip::SipHasher13::new_with_keys(0, 0);
+        hasher.write(key.as_bytes());
+        hasher.write(&[0xff]);
+        hasher.write(&node_id.to_le_bytes());
+        hasher.write(client_id.as_bytes());
+        hasher.finish()
+    }
+
+    #[inline]
+    async fn loads(ncs: &[SharedSubChoice], candidates: &[usize]) -> HashMap<usize, usize> {
+        let this_node_id = Runtime::instance().node.id();
+        let shared = Runtime::instance().extends.shared().await;
+        let mut loads = HashMap::default();
+        for idx in candidates {
+            let (node_id, client_id, _, _, _) = &ncs[*idx];
+            if *node_id != this_node_id {
+                continue;
+            }
+            if let Some(s) = shared.entry(Id::from(*node_id, client_id.clone())).session() {
+                let load = s.inflight_win().read().await.len() + s.deliver_queue().len();
+                loads.insert(*idx, load);
+            }
+        }
+        loads
     }
 }
 
 #[async_trait]
-impl SharedSubscription for &'static DefaultSharedSubscription {}
+impl SharedSubscription for &'static DefaultSharedSubscription {
+    #[inline]
+    async fn choice(
+        &self,
+        from: &Id,
+        topic: &TopicName,
+        topic_filter: &TopicFilter,
+        group: &SharedGroup,
+        ncs: &[SharedSubChoice],
+    ) -> Option<(usize, IsOnline)> {
+        let strategy = self.strategy(from, group);
+        if ncs.is_empty() || matches!(strategy, SharedSubscriptionStrategy::Random) {
+            return super::random_choice(ncs).await;
+        }
+
+        //candidates in order of preference
+        let mut candidates = (0..ncs.len()).collect::<Vec<_>>();
+        candidates.sort_by(|a, b| (ncs[*a].0, &ncs[*a].1).cmp(&(ncs[*b].0, &ncs[*b].1)));
+        match strategy {
+            SharedSubscriptionStrategy::Random => {}
+            SharedSubscriptionStrategy::RoundRobin => {
+                let n = self
+                    .round_robins
+                    .entry((group.clone(), topic_filter.clone()))
+                    .or_default()
+                    .fetch_add(1, Ordering::Relaxed);
+                candidates.rotate_left(n % ncs.len());
+            }
+            SharedSubscriptionStrategy::Sticky => {
+                candidates.sort_by_cached_key(|idx| {
+                    std::cmp::Reverse(Self::rendezvous_weight(&from.client_id, ncs[*idx].0, &ncs[*idx].1))
+                });
+            }
+            SharedSubscriptionStrategy::HashTopic => {
+                candidates.sort_by_cached_key(|idx| {
+                    std::cmp::Reverse(Self::rendezvous_weight(topic, ncs[*idx].0, &ncs[*idx].1))
+                });
+            }
+            SharedSubscriptionStrategy::LeastInflight => {
+                //subscribers on other nodes are only selected if there are none on this node
+                let loads = Self::loads(ncs, &candidates).await;
+                candidates.shuffle(&mut rand::rng());
+                candidates.sort_by_key(|idx| loads.get(idx).copied().unwrap_or(usize::MAX));
+            }
+            SharedSubscriptionStrategy::LocalFirst => {
+                let this_node_id = Runtime::instance().node.id();
+                candidates.shuffle(&mut rand::rng());
+                candidates.sort_by_key(|idx| ncs[*idx].0 != this_node_id);
+            }
+        }
+
+        //the first online subscriber, otherwise the last one
+        let mut last = None;
+        for idx in candidates {
+            let (node_id, client_id, _, _, is_online) = &ncs[idx];
+            let is_online = if let Some(is_online) = is_online {
+                *is_online
+            } else {
+                Runtime::instance().extends.router().await.is_online(*node_id, client_id).await
+            };
+            if is_online {
+                return Some((idx, true));
+            }
+            last = Some(idx);
+        }
+        last.map(|idx| (idx, false))
+    }
+}
 
 pub struct DefaultRetainStorage {
     pub messages: RwLock<RetainTree<TimedValue<Retain>>>,
@@ -1397,16 +1531,34 @@ impl Fitter for DefaultFitter {
             0
         }
     }
+
+    #[inline]
+    fn publish_limit(&self, auth_info: Option<&AuthInfo>) -> PublishLimit {
+        auth_info.and_then(|a| a.publish_limit).unwrap_or_else(|| self.listen_cfg.publish_limit())
+    }
 }
 
 struct HookEntry {
     handler: Box<dyn Handler>,
     enabled: bool,
+    //Execution time of the handlers of the plugin for this hook type
+    duration: Arc<Histogram>,
 }
 
 impl HookEntry {
-    fn new(handler: Box<dyn Handler>) -> Self {
-        Self { handler, enabled: false }
+    fn new(typ: Type, handler: Box<dyn Handler>) -> Self {
+        let duration = Metrics::instance().hook_duration().get(handler.type_name(), typ);
+        Self { handler, enabled: false, duration }
+    }
+
+    #[inline]
+    async fn hook(&self, p: &Parameter<'_>, acc: Option<HookResult>) -> ReturnType {
+        let now = Instant::now();
+        let res = self.handler.hook(p, acc).await;
+        if !is_dry_run() {
+            self.duration.observe(now.elapsed());
+        }
+        res
     }
 }
 
@@ -1436,7 +1588,7 @@ impl DefaultHookManager {
         if contains_key {
             Err(MqttError::from(format!("handler id is repetition, key is {:?}, type is {:?}", key, typ)))
         } else {
-            type_handlers.insert(key, HookEntry::new(handler));
+            type_handlers.insert(key, HookEntry::new(typ, handler));
             Ok(id)
         }
     }
@@ -1447,9 +1599,13 @@ impl DefaultHookManager {
         let type_handlers = { self.handlers.get(&t).map(|h| (*h.value()).clone()) };
         if let Some(type_handlers) = type_handlers {
             let type_handlers = type_handlers.read().await;
-            for (_, entry) in type_handlers.iter().rev() {
+            let dry_run = is_dry_run();
+            for ((priority, _), entry) in type_handlers.iter().rev() {
                 if entry.enabled {
-                    let (proceed, new_acc) = entry.handler.hook(&p, acc).await;
+                    let (proceed, new_acc) = entry.hook(&p, acc).await;
+                    if dry_run {
+                        dry_run_trace(t, entry.handler.type_name(), *priority, proceed, new_acc.as_ref());
+                    }
                     if !proceed {
                         return new_acc;
                     }
@@ -1459,6 +1615,108 @@ impl DefaultHookManager {
         }
         acc
     }
+
+    ///Runs the authenticators of the auth chain in the configured order. The authentication handlers of
+    ///the plugins that are not in the chain run first, in the order of their priorities.
+    async fn exec_auth_chain(
+        &self,
+        chain: &[Authenticator],
+        connect_info: &ConnectInfo,
+    ) -> Option<HookResult> {
+        let t = Type::ClientAuthenticate;
+        let p = Parameter::ClientAuthenticate(connect_info);
+        let type_handlers = { self.handlers.get(&t).map(|h| (*h.value()).clone()) }?;
+        let type_handlers = type_handlers.read().await;
+        let dry_run = is_dry_run();
+
+        let mut acc = None;
+        for ((priority, _), entry) in type_handlers.iter().rev() {
+            let name = entry.handler.type_name();
+            if !entry.enabled || chain.iter().any(|a| is_plugin_handler(name, &a.plugin)) {
+                continue;
+            }
+            let (proceed, new_acc) = entry.hook(&p, acc).await;
+            if dry_run {
+                dry_run_trace(t, name, *priority, proceed, new_acc.as_ref());
+            }
+            if !proceed {
+                return new_acc;
+            }
+            acc = new_acc;
+        }
+
+        let stats = AuthChainStats::instance();
+        let mut undecided = Undecided::default();
+        for a in chain {
+            //The handlers of a plugin run as a normal hook, without the results of the other plugins
+            let mut result = None;
+            for ((priority, _), entry) in type_handlers.iter().rev() {
+                let name = entry.handler.type_name();
+                if !entry.enabled || !is_plugin_handler(name, &a.plugin) {
+                    continue;
+                }
+                let (proceed, new_acc) = entry.hook(&p, result).await;
+                if dry_run {
+                    dry_run_trace(t, name, *priority, proceed, new_acc.as_ref());
+                }
+                result = new_acc;
+                if !proceed {
+                    break;
+                }
+            }
+
+            let decision = Decision::from_result(result.as_ref());
+            log::debug!("{:?} auth chain, {} ({:?}): {:?}", connect_info.id(), a.plugin, a.control, decision);
+            if !dry_run {
+                stats.record(&a.plugin, decision);
+            }
+            if ends_chain(a.control, decision) {
+                let result = if decision == Decision::Ignore {
+                    Some(HookResult::AuthResult(AuthResult::NotAuthorized))
+                } else {
+                    result
+                };
+                return self.auth_chain_decided(connect_info, &a.plugin, result, dry_run);
+            }
+            undecided.push(a.control, decision, (a.plugin.as_str(), result));
+        }
+
+        match undecided.decide() {
+            Some((plugin, result)) => self.auth_chain_decided(connect_info, plugin, result, dry_run),
+            None => {
+                log::debug!("{:?} auth chain, no authenticator decided", connect_info.id());
+                if !dry_run {
+                    Runtime::instance().metrics.client_auth_chain_notfound_inc();
+                }
+                acc
+            }
+        }
+    }
+
+    #[inline]
+    fn auth_chain_decided(
+        &self,
+        connect_info: &ConnectInfo,
+        plugin: &str,
+        result: Option<HookResult>,
+        dry_run: bool,
+    ) -> Option<HookResult> {
+        let allow = Decision::from_result(result.as_ref()) == Decision::Allow;
+        if allow {
+            log::debug!("{:?} auth chain, allowed by {}", connect_info.id(), plugin);
+        } else {
+            log::info!("{:?} auth chain, denied by {}, {:?}", connect_info.id(), plugin, result);
+        }
+        if !dry_run {
+            AuthChainStats::instance().decided(plugin);
+            if allow {
+                Runtime::instance().metrics.client_auth_chain_allow_inc();
+            } else {
+                Runtime::instance().metrics.client_auth_chain_deny_inc();
+            }
+        }
+        result
+    }
 }
 
 #[async_trait]
@@ -1479,13 +1737,16 @@ impl HookManager for &'static DefaultHookManager {
     }
 
     #[inline]
-    async fn client_connect(&self, connect_info: &ConnectInfo) -> Option<UserProperties> {
+    async fn client_connect(
+        &self,
+        connect_info: &ConnectInfo,
+    ) -> std::result::Result<Option<UserProperties>, ConnectAckReason> {
         let result = self.exec(Type::ClientConnect, Parameter::ClientConnect(connect_info)).await;
         log::debug!("{:?} result: {:?}", connect_info.id(), result);
-        if let Some(HookResult::UserProperties(props)) = result {
-            Some(props)
-        } else {
-            None
+        match result {
+            Some(HookResult::UserProperties(props)) => Ok(Some(props)),
+            Some(HookResult::ConnectAckReason(reason)) if !reason.success() => Err(reason),
+            _ => Ok(None),
         }
     }
 
@@ -1508,7 +1769,16 @@ impl HookManager for &'static DefaultHookManager {
             return (ok(), false, None);
         }
 
-        let result = self.exec(Type::ClientAuthenticate, Parameter::ClientAuthenticate(connect_info)).await;
+        let chain = &Runtime::instance().settings.auth.chain;
+        let now = Instant::now();
+        let result = if chain.is_empty() {
+            self.exec(Type::ClientAuthenticate, Parameter::ClientAuthenticate(connect_info)).await
+        } else {
+            self.exec_auth_chain(chain, connect_info).await
+        };
+        if !is_dry_run() {
+            Runtime::instance().metrics.auth_duration().observe(now.elapsed());
+        }
         log::debug!("{:?} result: {:?}", connect_info.id(), result);
         let (bad_user_or_pass, not_auth) = match result {
             Some(HookResult::AuthResult(AuthResult::BadUsernameOrPassword)) => (true, false),
@@ -1555,6 +1825,28 @@ impl HookManager for &'static DefaultHookManager {
         (ok(), false, None)
     }
 
+    ///enhanced authenticate, one step of the MQTT 5.0 AUTH exchange
+    #[inline]
+    async fn client_enhanced_authenticate(
+        &self,
+        connect_info: &ConnectInfo,
+        auth: &EnhancedAuth,
+        data: Option<&AuthData>,
+    ) -> EnhancedAuthResult {
+        let result = self
+            .exec(
+                Type::ClientEnhancedAuthenticate,
+                Parameter::ClientEnhancedAuthenticate(connect_info, auth, data),
+            )
+            .await;
+        log::debug!("{:?} {:?} result: {:?}", connect_info.id(), auth, result);
+        if let Some(HookResult::EnhancedAuthResult(r)) = result {
+            r
+        } else {
+            EnhancedAuthResult::NotFound
+        }
+    }
+
     ///When sending mqtt:: connectack message
     async fn client_connack(
         &self,
@@ -1584,6 +1876,10 @@ impl HookManager for &'static DefaultHookManager {
     ///Publish message Dropped
     #[inline]
     async fn message_dropped(&self, to: Option<To>, from: From, publish: Publish, reason: Reason) {
+        TopicMetrics::instance().message_dropped(&publish);
+        if matches!(reason, Reason::MessageQueueFull) {
+            Alarms::instance().mqueue_full();
+        }
         let _ = self.exec(Type::MessageDropped, Parameter::MessageDropped(to, from, publish, reason)).await;
     }
 
@@ -1593,6 +1889,18 @@ impl HookManager for &'static DefaultHookManager {
         let _ = self.exec(Type::MessageNonsubscribed, Parameter::MessageNonsubscribed(from)).await;
     }
 
+    ///An alarm is activated
+    #[inline]
+    async fn alarm_activated(&self, alarm: &AlarmInfo) {
+        let _ = self.exec(Type::AlarmActivated, Parameter::AlarmActivated(alarm)).await;
+    }
+
+    ///An alarm is deactivated
+    #[inline]
+    async fn alarm_deactivated(&self, alarm: &AlarmInfo) {
+        let _ = self.exec(Type::AlarmDeactivated, Parameter::AlarmDeactivated(alarm)).await;
+    }
+
     ///grpc message received
     #[inline]
     async fn grpc_message_received(
@@ -1697,36 +2005,66 @@ impl Hook for DefaultHook {
 
     #[inline]
     async fn client_subscribe_check_acl(&self, sub: &Subscribe) -> Option<SubscribeAclResult> {
-        if self.s.superuser().await.unwrap_or_default() {
-            return Some(SubscribeAclResult::new_success(sub.opts.qos(), None));
-        }
-        let reply = self
-            .manager
-            .exec(Type::ClientSubscribeCheckAcl, Parameter::ClientSubscribeCheckAcl(&self.s, sub))
-            .await;
-        log::debug!("{:?} result: {:?}", self.s.id, reply);
-        if let Some(HookResult::SubscribeAclResult(r)) = reply {
-            Some(r)
+        let acl_result = if self.s.superuser().await.unwrap_or_default() {
+            Some(SubscribeAclResult::new_success(sub.opts.qos(), None))
         } else {
-            None
+            let now = Instant::now();
+            let reply = self
+                .manager
+                .exec(Type::ClientSubscribeCheckAcl, Parameter::ClientSubscribeCheckAcl(&self.s, sub))
+                .await;
+            if !is_dry_run() {
+                Runtime::instance().metrics.acl_subscribe_duration().observe(now.elapsed());
+            }
+            log::debug!("{:?} result: {:?}", self.s.id, reply);
+            if let Some(HookResult::SubscribeAclResult(r)) = reply {
+                Some(r)
+            } else {
+                None
+            }
+        };
+        if !is_dry_run() {
+            let _ = self
+                .manager
+                .exec(
+                    Type::ClientSubscribeAclChecked,
+                    Parameter::ClientSubscribeAclChecked(&self.s, sub, acl_result.as_ref()),
+                )
+                .await;
         }
+        acl_result
     }
 
     #[inline]
     async fn message_publish_check_acl(&self, publish: &Publish) -> PublishAclResult {
-        if self.s.superuser().await.unwrap_or_default() {
-            return PublishAclResult::Allow;
-        }
-        let result = self
-            .manager
-            .exec(Type::MessagePublishCheckAcl, Parameter::MessagePublishCheckAcl(&self.s, publish))
-            .await;
-        log::debug!("{:?} result: {:?}", self.s.id, result);
-        if let Some(HookResult::PublishAclResult(acl_result)) = result {
-            acl_result
-        } else {
+        let acl_result = if self.s.superuser().await.unwrap_or_default() {
             PublishAclResult::Allow
+        } else {
+            let now = Instant::now();
+            let result = self
+                .manager
+                .exec(Type::MessagePublishCheckAcl, Parameter::MessagePublishCheckAcl(&self.s, publish))
+                .await;
+            if !is_dry_run() {
+                Runtime::instance().metrics.acl_publish_duration().observe(now.elapsed());
+            }
+            log::debug!("{:?} result: {:?}", self.s.id, result);
+            if let Some(HookResult::PublishAclResult(acl_result)) = result {
+                acl_result
+            } else {
+                PublishAclResult::Allow
+            }
+        };
+        if !is_dry_run() {
+            let _ = self
+                .manager
+                .exec(
+                    Type::MessagePublishAclChecked,
+                    Parameter::MessagePublishAclChecked(&self.s, publish, &acl_result),
+                )
+                .await;
         }
+        acl_result
     }
 
     #[inline]
@@ -1843,6 +2181,21 @@ impl Hook for DefaultHook {
     async fn client_keepalive(&self, ping: IsPing) {
         let _ = self.manager.exec(Type::ClientKeepalive, Parameter::ClientKeepalive(&self.s, ping)).await;
     }
+
+    #[inline]
+    async fn client_reauthenticate(
+        &self,
+        auth: &EnhancedAuth,
+        data: Option<&AuthData>,
+    ) -> EnhancedAuthResult {
+        match self.s.connect_info().await {
+            Ok(connect_info) => self.manager.client_enhanced_authenticate(&connect_info, auth, data).await,
+            Err(e) => {
+                log::warn!("{:?} re-authenticate error, {:?}", self.s.id, e);
+                EnhancedAuthResult::Failure(ConnectAckReasonV5::UnspecifiedError)
+            }
+        }
+    }
 }
 
 pub struct DefaultSessionManager {}
@@ -1904,6 +2257,8 @@ pub struct DefaultSession {
 
     created_at: TimestampMillis,
     state_flags: SessionStateFlags,
+    //Changed when the client re-authenticates, so it is not kept in state_flags
+    superuser: AtomicBool,
     connected_at: TimestampMillis,
 
     pub disconnect_info: RwLock<DisconnectInfo>,
@@ -1930,9 +2285,6 @@ impl DefaultSession {
         if session_present {
             state_flags.insert(SessionStateFlags::SessionPresent);
         }
-        if superuser {
-            state_flags.insert(SessionStateFlags::Superuser);
-        }
         if connected {
             state_flags.insert(SessionStateFlags::Connected);
         }
@@ -1948,6 +2300,7 @@ impl DefaultSession {
 
             created_at,
             state_flags,
+            superuser: AtomicBool::new(superuser),
             connected_at,
 
             disconnect_info: RwLock::new(disconnect_info),
@@ -2032,7 +2385,11 @@ impl SessionLike for DefaultSession {
         Ok(self.conn_info.proto_ver())
     }
     async fn superuser(&self) -> Result<bool> {
-        Ok(self.state_flags.contains(SessionStateFlags::Superuser))
+        Ok(self.superuser.load(Ordering::SeqCst))
+    }
+    async fn superuser_set(&self, superuser: bool) -> Result<()> {
+        self.superuser.store(superuser, Ordering::SeqCst);
+        Ok(())
     }
     async fn connected(&self) -> Result<bool> {
         Ok(self.state_flags.contains(SessionStateFlags::Connected)
@@ -2091,6 +2448,7 @@ impl SessionLike for DefaultSession {
 
 pub struct DefaultDelayedSender {
     msgs: RwLock<BinaryHeap<DelayedPublish>>,
+    id_gen: AtomicU64,
 }
 
 impl DefaultDelayedSender {
@@ -2098,7 +2456,7 @@ impl DefaultDelayedSender {
     pub fn instance() -> &'static DefaultDelayedSender {
         static INSTANCE: OnceCell<DefaultDelayedSender> = OnceCell::new();
         INSTANCE.get_or_init(|| {
-            let s = Self { msgs: RwLock::new(BinaryHeap::default()) };
+            let s = Self { msgs: RwLock::new(BinaryHeap::default()), id_gen: AtomicU64::new(1) };
             tokio::spawn(Self::start());
             s
         })
@@ -2175,7 +2533,14 @@ impl DelayedSender for &'static DefaultDelayedSender {
     ) -> Result<Option<(From, Publish)>> {
         let mut msgs = self.msgs.write().await;
         if msgs.len() < Runtime::instance().settings.mqtt.delayed_publish_max {
-            msgs.push(DelayedPublish::new(from, publish, message_storage_available, message_expiry_interval));
+            let id = self.id_gen.fetch_add(1, Ordering::SeqCst);
+            msgs.push(DelayedPublish::new(
+                id,
+                from,
+                publish,
+                message_storage_available,
+                message_expiry_interval,
+            ));
             Runtime::instance().stats.delayed_publishs.max_max(msgs.len() as isize);
             Ok(None)
         } else {
@@ -2187,6 +2552,28 @@ impl DelayedSender for &'static DefaultDelayedSender {
     async fn len(&self) -> usize {
         self.msgs.read().await.len()
     }
+
+    #[inline]
+    async fn list(&self, offset: usize, limit: usize) -> Result<Vec<DelayedPublish>> {
+        let mut msgs = self.msgs.read().await.iter().cloned().collect::<Vec<_>>();
+        msgs.sort_by_key(|dp| (dp.expired_time, dp.id));
+        Ok(msgs.into_iter().skip(offset).take(limit).collect())
+    }
+
+    #[inline]
+    async fn get(&self, id: DelayedPublishId) -> Result<Option<DelayedPublish>> {
+        Ok(self.msgs.read().await.iter().find(|dp| dp.id == id).cloned())
+    }
+
+    #[inline]
+    async fn cancel(&self, id: DelayedPublishId) -> Result<Option<DelayedPublish>> {
+        let mut msgs = self.msgs.write().await;
+        let cancelled = msgs.iter().find(|dp| dp.id == id).cloned();
+        if cancelled.is_some() {
+            msgs.retain(|dp| dp.id != id);
+        }
+        Ok(cancelled)
+    }
 }
 
 pub struct DefaultAutoSubscription {}
@@ -2201,3 +2588,26 @@ impl DefaultAutoSubscription {
 
 #[async_trait]
 impl AutoSubscription for &'static DefaultAutoSubscription {}
+
+#[cfg(test)]
+mod tests {
+    use super::DefaultSharedSubscription;
+
+    #[test]
+    fn rendezvous_weight() {
+        //The weights must not change between builds, the nodes of a cluster select the same subscriber
+        assert_eq!(
+            DefaultSharedSubscription::rendezvous_weight("dev-1", 1, "worker-a"),
+            14983982144603217825
+        );
+        assert_eq!(
+            DefaultSharedSubscription::rendezvous_weight("dev-1", 2, "worker-a"),
+            12520142699404189421
+        );
+        assert_eq!(
+            DefaultSharedSubscription::rendezvous_weight("sensor/1/temp", 1, "worker-b"),
+            899120068627365346
+        );
+        assert_eq!(DefaultSharedSubscription::rendezvous_weight("", 0, ""), 10100562149316582883);
+    }
+}
@@ -18,45 +18,77 @@ use crate::Runtime;
 pub type Port = u16;
 
 std::thread_local! {
-    pub static HANDSHAKE_EXECUTORS: DashMap<Port, LocalTaskExecQueue> = DashMap::default();
+    pub static HANDSHAKE_EXECUTORS: DashMap<Port, (Listener, LocalTaskExecQueue)> = DashMap::default();
 }
 
 #[inline]
 pub(crate) fn get_handshake_exec(name: Port, listen_cfg: Listener) -> LocalTaskExecQueue {
     HANDSHAKE_EXECUTORS.with(|m| {
-        m.entry(name)
-            .or_insert_with(|| {
-                let (exec, task_runner) = LocalBuilder::default()
-                    .workers(listen_cfg.max_handshaking_limit / listen_cfg.workers)
-                    .queue_max(listen_cfg.max_connections / listen_cfg.workers)
-                    .build();
-
-                let busy_limit = if Runtime::instance().settings.node.busy.handshaking == 0 {
-                    (listen_cfg.max_handshaking_limit as f64 * 0.35) as usize
-                } else {
-                    Runtime::instance().settings.node.busy.handshaking
-                };
-
-                set_active_count(name, exec.active_count(), Some(busy_limit));
-                let exec1 = exec.clone();
-                spawn_local(async move {
-                    futures::future::join(task_runner, async move {
-                        loop {
-                            set_active_count(name, exec1.active_count(), None);
-                            set_rate(name, exec1.rate().await);
-                            tokio::time::sleep(Duration::from_secs(3)).await;
-                        }
-                    })
-                    .await;
-                });
-
-                exec
-            })
-            .value()
-            .clone()
+        if let Some(entry) = m.get(&name) {
+            let (cfg, exec) = entry.value();
+            if cfg.ptr_eq(&listen_cfg) {
+                return exec.clone();
+            }
+        }
+        //Listener configuration is new or has been reloaded
+        let exec = new_handshake_exec(name, listen_cfg.clone());
+        m.insert(name, (listen_cfg, exec.clone()));
+        exec
     })
 }
 
+#[inline]
+fn is_current_handshake_exec(name: Port, listen_cfg: &Listener) -> bool {
+    HANDSHAKE_EXECUTORS
+        .with(|m| m.get(&name).map(|entry| entry.value().0.ptr_eq(listen_cfg)).unwrap_or(false))
+}
+
+fn new_handshake_exec(name: Port, listen_cfg: Listener) -> LocalTaskExecQueue {
+    let (exec, task_runner) = LocalBuilder::default()
+        .workers(listen_cfg.max_handshaking_limit / listen_cfg.workers)
+        .queue_max(listen_cfg.max_connections / listen_cfg.workers)
+        .build();
+
+    let busy_limit = if Runtime::instance().settings.node.busy.handshaking == 0 {
+        (listen_cfg.max_handshaking_limit as f64 * 0.35) as usize
+    } else {
+        Runtime::instance().settings.node.busy.handshaking
+    };
+
+    set_active_count(name, exec.active_count(), Some(busy_limit));
+    let exec1 = exec.clone();
+    spawn_local(async move {
+        futures::future::join(task_runner, async move {
+            while is_current_handshake_exec(name, &listen_cfg) {
+                set_active_count(name, exec1.active_count(), None);
+                set_rate(name, exec1.rate().await);
+                tokio::time::sleep(Duration::from_secs(3)).await;
+            }
+        })
+        .await;
+    });
+
+    exec
+}
+
+//Current number of connections per listener
+static LISTENER_CONNECTIONS: Lazy<DashMap<Port, isize>> = Lazy::new(DashMap::default);
+
+#[inline]
+pub(crate) fn listener_connections_inc(name: Port) {
+    *LISTENER_CONNECTIONS.entry(name).or_default() += 1;
+}
+
+#[inline]
+pub(crate) fn listener_connections_dec(name: Port) {
+    *LISTENER_CONNECTIONS.entry(name).or_default() -= 1;
+}
+
+#[inline]
+pub fn listener_connections(name: Port) -> isize {
+    LISTENER_CONNECTIONS.get(&name).map(|c| *c.value()).unwrap_or_default()
+}
+
 static ACTIVE_COUNTS: OnceCell<DashMap<(Port, ThreadId), (isize, isize)>> = OnceCell::new();
 
 #[inline]
@@ -3,7 +3,8 @@ use std::sync::Arc;
 use std::time::Duration;
 
 use crate::broker::types::*;
-use crate::settings::listener::Listener;
+use crate::settings::acl::AuthInfo;
+use crate::settings::listener::{Listener, PublishLimit};
 use crate::Result;
 
 pub trait FitterManager: Sync + Send {
@@ -37,4 +38,9 @@ pub trait Fitter: Sync + Send {
 
     ///server topic alias maximum, S(Max Limit) -> C
     fn max_server_topic_aliases(&self) -> u16;
+
+    ///Inbound publish limits, messages and payload bytes per period. auth_info is returned by the
+    ///authentication, so the limits can be set per user or client,
+    ///default value: publish_rate_limit, publish_bytes_rate_limit and publish_limit_action of the listener
+    fn publish_limit(&self, auth_info: Option<&AuthInfo>) -> PublishLimit;
 }
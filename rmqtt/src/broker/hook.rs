@@ -1,3 +1,9 @@
+use std::future::Future;
+use std::sync::Arc;
+
+use parking_lot::Mutex;
+
+use crate::broker::alarm::AlarmInfo;
 use crate::broker::inflight::InflightMessage;
 use crate::broker::types::*;
 use crate::settings::acl::AuthInfo;
@@ -16,8 +22,12 @@ pub trait HookManager: Sync + Send {
     ///Before the server startup
     async fn before_startup(&self);
 
-    ///When a connect message is received
-    async fn client_connect(&self, connect_info: &ConnectInfo) -> Option<UserProperties>;
+    ///When a connect message is received, the connection is refused if a handler returns
+    ///a failed ConnectAckReason
+    async fn client_connect(
+        &self,
+        connect_info: &ConnectInfo,
+    ) -> std::result::Result<Option<UserProperties>, ConnectAckReason>;
 
     ///authenticate
     async fn client_authenticate(
@@ -26,6 +36,14 @@ pub trait HookManager: Sync + Send {
         allow_anonymous: bool,
     ) -> (ConnectAckReason, Superuser, Option<AuthInfo>);
 
+    ///enhanced authenticate, one step of the MQTT 5.0 AUTH exchange
+    async fn client_enhanced_authenticate(
+        &self,
+        connect_info: &ConnectInfo,
+        auth: &EnhancedAuth,
+        data: Option<&AuthData>,
+    ) -> EnhancedAuthResult;
+
     ///When sending mqtt:: connectack message
     async fn client_connack(
         &self,
@@ -42,6 +60,12 @@ pub trait HookManager: Sync + Send {
     ///Publish message nonsubscribed
     async fn message_nonsubscribed(&self, from: From);
 
+    ///An alarm is activated
+    async fn alarm_activated(&self, alarm: &AlarmInfo);
+
+    ///An alarm is deactivated
+    async fn alarm_deactivated(&self, alarm: &AlarmInfo);
+
     ///grpc message received
     async fn grpc_message_received(
         &self,
@@ -66,6 +90,11 @@ pub trait Register: Sync + Send {
 #[async_trait]
 pub trait Handler: Sync + Send {
     async fn hook(&self, param: &Parameter, acc: Option<HookResult>) -> ReturnType;
+
+    ///Type name of the handler, such as "rmqtt_acl::AclHandler", the crate identifies the plugin
+    fn type_name(&self) -> &'static str {
+        std::any::type_name::<Self>()
+    }
 }
 
 #[async_trait]
@@ -82,10 +111,10 @@ pub trait Hook: Sync + Send {
     ///Session terminated
     async fn session_terminated(&self, r: Reason);
 
-    ///subscribe check acl
+    ///subscribe check acl, the result is passed to the client_subscribe_acl_checked handlers
     async fn client_subscribe_check_acl(&self, subscribe: &Subscribe) -> Option<SubscribeAclResult>;
 
-    ///publish check acl
+    ///publish check acl, the result is passed to the message_publish_acl_checked handlers
     async fn message_publish_check_acl(&self, publish: &Publish) -> PublishAclResult;
 
     ///Subscribe message received
@@ -120,6 +149,10 @@ pub trait Hook: Sync + Send {
 
     ///Client Keepalive
     async fn client_keepalive(&self, ping: IsPing);
+
+    ///re-authenticate, AUTH packet received after the connection is established
+    async fn client_reauthenticate(&self, auth: &EnhancedAuth, data: Option<&AuthData>)
+        -> EnhancedAuthResult;
 }
 
 #[derive(Debug, Clone, Copy, Eq, PartialEq, Hash, Deserialize, Serialize)]
@@ -132,6 +165,7 @@ pub enum Type {
     SessionUnsubscribed,
 
     ClientAuthenticate,
+    ClientEnhancedAuthenticate,
     ClientConnect,
     ClientConnack,
     ClientConnected,
@@ -139,9 +173,11 @@ pub enum Type {
     ClientSubscribe,
     ClientUnsubscribe,
     ClientSubscribeCheckAcl,
+    ClientSubscribeAclChecked,
     ClientKeepalive,
 
     MessagePublishCheckAcl,
+    MessagePublishAclChecked,
     MessagePublish,
     MessageDelivered,
     MessageAcked,
@@ -152,9 +188,55 @@ pub enum Type {
     OfflineMessage,
     OfflineInflightMessages,
 
+    AlarmActivated,
+    AlarmDeactivated,
+
     GrpcMessageReceived,
 }
 
+impl Type {
+    #[inline]
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Type::BeforeStartup => "before_startup",
+
+            Type::SessionCreated => "session_created",
+            Type::SessionTerminated => "session_terminated",
+            Type::SessionSubscribed => "session_subscribed",
+            Type::SessionUnsubscribed => "session_unsubscribed",
+
+            Type::ClientAuthenticate => "client_authenticate",
+            Type::ClientEnhancedAuthenticate => "client_enhanced_authenticate",
+            Type::ClientConnect => "client_connect",
+            Type::ClientConnack => "client_connack",
+            Type::ClientConnected => "client_connected",
+            Type::ClientDisconnected => "client_disconnected",
+            Type::ClientSubscribe => "client_subscribe",
+            Type::ClientUnsubscribe => "client_unsubscribe",
+            Type::ClientSubscribeCheckAcl => "client_subscribe_check_acl",
+            Type::ClientSubscribeAclChecked => "client_subscribe_acl_checked",
+            Type::ClientKeepalive => "client_keepalive",
+
+            Type::MessagePublishCheckAcl => "message_publish_check_acl",
+            Type::MessagePublishAclChecked => "message_publish_acl_checked",
+            Type::MessagePublish => "message_publish",
+            Type::MessageDelivered => "message_delivered",
+            Type::MessageAcked => "message_acked",
+            Type::MessageDropped => "message_dropped",
+            Type::MessageExpiryCheck => "message_expiry_check",
+            Type::MessageNonsubscribed => "message_nonsubscribed",
+
+            Type::OfflineMessage => "offline_message",
+            Type::OfflineInflightMessages => "offline_inflight_messages",
+
+            Type::AlarmActivated => "alarm_activated",
+            Type::AlarmDeactivated => "alarm_deactivated",
+
+            Type::GrpcMessageReceived => "grpc_message_received",
+        }
+    }
+}
+
 impl std::convert::From<&str> for Type {
     fn from(t: &str) -> Type {
         match t {
@@ -166,6 +248,7 @@ impl std::convert::From<&str> for Type {
             "session_unsubscribed" => Type::SessionUnsubscribed,
 
             "client_authenticate" => Type::ClientAuthenticate,
+            "client_enhanced_authenticate" => Type::ClientEnhancedAuthenticate,
             "client_connect" => Type::ClientConnect,
             "client_connack" => Type::ClientConnack,
             "client_connected" => Type::ClientConnected,
@@ -173,9 +256,11 @@ impl std::convert::From<&str> for Type {
             "client_subscribe" => Type::ClientSubscribe,
             "client_unsubscribe" => Type::ClientUnsubscribe,
             "client_subscribe_check_acl" => Type::ClientSubscribeCheckAcl,
+            "client_subscribe_acl_checked" => Type::ClientSubscribeAclChecked,
             "client_keepalive" => Type::ClientKeepalive,
 
             "message_publish_check_acl" => Type::MessagePublishCheckAcl,
+            "message_publish_acl_checked" => Type::MessagePublishAclChecked,
             "message_publish" => Type::MessagePublish,
             "message_delivered" => Type::MessageDelivered,
             "message_acked" => Type::MessageAcked,
@@ -186,6 +271,9 @@ impl std::convert::From<&str> for Type {
             "offline_message" => Type::OfflineMessage,
             "offline_inflight_messages" => Type::OfflineInflightMessages,
 
+            "alarm_activated" => Type::AlarmActivated,
+            "alarm_deactivated" => Type::AlarmDeactivated,
+
             "grpc_message_received" => Type::GrpcMessageReceived,
 
             _ => unreachable!("{:?} is not defined", t),
@@ -205,14 +293,17 @@ pub enum Parameter<'a> {
     ClientConnect(&'a ConnectInfo),
     ClientConnack(&'a ConnectInfo, &'a ConnectAckReason),
     ClientAuthenticate(&'a ConnectInfo),
+    ClientEnhancedAuthenticate(&'a ConnectInfo, &'a EnhancedAuth, Option<&'a AuthData>),
     ClientConnected(&'a Session),
     ClientDisconnected(&'a Session, Reason),
     ClientSubscribe(&'a Session, &'a Subscribe),
     ClientUnsubscribe(&'a Session, &'a Unsubscribe),
     ClientSubscribeCheckAcl(&'a Session, &'a Subscribe),
+    ClientSubscribeAclChecked(&'a Session, &'a Subscribe, Option<&'a SubscribeAclResult>),
     ClientKeepalive(&'a Session, IsPing),
 
     MessagePublishCheckAcl(&'a Session, &'a Publish),
+    MessagePublishAclChecked(&'a Session, &'a Publish, &'a PublishAclResult),
     MessagePublish(Option<&'a Session>, From, &'a Publish),
     MessageDelivered(&'a Session, From, &'a Publish),
     MessageAcked(&'a Session, From, &'a Publish),
@@ -223,6 +314,9 @@ pub enum Parameter<'a> {
     OfflineMessage(&'a Session, From, &'a Publish),
     OfflineInflightMessages(&'a Session, Vec<InflightMessage>),
 
+    AlarmActivated(&'a AlarmInfo),
+    AlarmDeactivated(&'a AlarmInfo),
+
     GrpcMessageReceived(grpc::MessageType, grpc::Message),
 }
 
@@ -237,6 +331,7 @@ impl Parameter<'_> {
             Parameter::SessionUnsubscribed(_, _) => Type::SessionUnsubscribed,
 
             Parameter::ClientAuthenticate(_) => Type::ClientAuthenticate,
+            Parameter::ClientEnhancedAuthenticate(_, _, _) => Type::ClientEnhancedAuthenticate,
             Parameter::ClientConnect(_) => Type::ClientConnect,
             Parameter::ClientConnack(_, _) => Type::ClientConnack,
             Parameter::ClientConnected(_) => Type::ClientConnected,
@@ -244,9 +339,11 @@ impl Parameter<'_> {
             Parameter::ClientSubscribe(_, _) => Type::ClientSubscribe,
             Parameter::ClientUnsubscribe(_, _) => Type::ClientUnsubscribe,
             Parameter::ClientSubscribeCheckAcl(_, _) => Type::ClientSubscribeCheckAcl,
+            Parameter::ClientSubscribeAclChecked(_, _, _) => Type::ClientSubscribeAclChecked,
             Parameter::ClientKeepalive(_, _) => Type::ClientKeepalive,
 
             Parameter::MessagePublishCheckAcl(_, _) => Type::MessagePublishCheckAcl,
+            Parameter::MessagePublishAclChecked(_, _, _) => Type::MessagePublishAclChecked,
             Parameter::MessagePublish(_, _, _) => Type::MessagePublish,
             Parameter::MessageDelivered(_, _, _) => Type::MessageDelivered,
             Parameter::MessageAcked(_, _, _) => Type::MessageAcked,
@@ -257,6 +354,9 @@ impl Parameter<'_> {
             Parameter::OfflineMessage(_, _, _) => Type::OfflineMessage,
             Parameter::OfflineInflightMessages(_, _) => Type::OfflineInflightMessages,
 
+            Parameter::AlarmActivated(_) => Type::AlarmActivated,
+            Parameter::AlarmDeactivated(_) => Type::AlarmDeactivated,
+
             Parameter::GrpcMessageReceived(_, _) => Type::GrpcMessageReceived,
         }
     }
@@ -268,7 +368,9 @@ pub enum HookResult {
     UserProperties(UserProperties),
     ///Authentication failed, for ClientAuthenticate
     AuthResult(AuthResult),
-    ///ConnectAckReason, for ClientConnack
+    ///Enhanced authentication result, for ClientEnhancedAuthenticate
+    EnhancedAuthResult(EnhancedAuthResult),
+    ///ConnectAckReason, for ClientConnect/ClientConnack
     ConnectAckReason(ConnectAckReason),
     ///TopicFilters, for ClientSubscribe/ClientUnsubscribe
     TopicFilter(Option<TopicFilter>),
@@ -283,3 +385,80 @@ pub enum HookResult {
     ///for GrpcMessageReceived
     GrpcMessageReply(Result<grpc::MessageReply>),
 }
+
+///A handler executed in dry-run mode
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct HookTrace {
+    pub typ: Type,
+    //Plugin the handler belongs to, derived from the crate of the handler
+    pub plugin: String,
+    pub handler: String,
+    pub priority: Priority,
+    pub proceed: Proceed,
+    //Result returned by the handler, in debug format
+    pub result: Option<String>,
+    //Notes recorded by the handler, such as the matched rule or a cache hit
+    pub notes: Vec<String>,
+}
+
+#[derive(Default)]
+struct DryRun {
+    traces: Mutex<Vec<HookTrace>>,
+    notes: Mutex<Vec<String>>,
+}
+
+tokio::task_local! {
+    static DRY_RUN: Arc<DryRun>;
+}
+
+///Runs the future in dry-run mode, returns its output and the handlers executed by the hooks it called.
+///Handlers should not change any state in dry-run mode, see is_dry_run().
+pub async fn dry_run<F: Future>(f: F) -> (F::Output, Vec<HookTrace>) {
+    let ctx = Arc::new(DryRun::default());
+    let output = DRY_RUN.scope(ctx.clone(), f).await;
+    let traces = std::mem::take(&mut *ctx.traces.lock());
+    (output, traces)
+}
+
+///Whether the hooks are executed in dry-run mode
+#[inline]
+pub fn is_dry_run() -> bool {
+    DRY_RUN.try_with(|_| ()).is_ok()
+}
+
+///Records a note for the handler being executed in dry-run mode, the note is not built otherwise
+#[inline]
+pub fn dry_run_note<F: FnOnce() -> String>(f: F) {
+    let _ = DRY_RUN.try_with(|ctx| ctx.notes.lock().push(f()));
+}
+
+#[inline]
+pub(crate) fn dry_run_trace(
+    typ: Type,
+    handler: &'static str,
+    priority: Priority,
+    proceed: Proceed,
+    result: Option<&HookResult>,
+) {
+    let _ = DRY_RUN.try_with(|ctx| {
+        let notes = std::mem::take(&mut *ctx.notes.lock());
+        ctx.traces.lock().push(HookTrace {
+            typ,
+            plugin: handler.split("::").next().unwrap_or_default().replace('_', "-"),
+            handler: handler.to_owned(),
+            priority,
+            proceed,
+            result: result.map(|r| format!("{:?}", r)),
+            notes,
+        })
+    });
+}
+
+///Whether the handler belongs to the plugin, the plugin is identified by the crate of the handler type,
+///such as "rmqtt_acl::AclHandler" for "rmqtt-acl"
+#[inline]
+pub(crate) fn is_plugin_handler(handler: &str, plugin: &str) -> bool {
+    let krate = handler.split("::").next().unwrap_or_default();
+    krate.len() == plugin.len()
+        && krate.bytes().zip(plugin.bytes()).all(|(a, b)| a == b || (a == b'_' && b == b'-'))
+}
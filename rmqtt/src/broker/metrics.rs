@@ -1,12 +1,24 @@
-use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use serde::de::{Deserialize, Deserializer};
+use serde::ser::{Serialize, Serializer};
 
 use rmqtt_macros::Metrics;
 
+use crate::broker::hook::Type;
+use crate::broker::types::QoS;
+use crate::DashMap;
+
 #[derive(Serialize, Deserialize, Debug, Default, Metrics)]
 pub struct Metrics {
     client_authenticate: AtomicUsize,
     client_auth_anonymous: AtomicUsize,
     client_auth_anonymous_error: AtomicUsize,
+    client_auth_chain_allow: AtomicUsize,
+    client_auth_chain_deny: AtomicUsize,
+    client_auth_chain_notfound: AtomicUsize,
     client_handshaking_timeout: AtomicUsize,
     client_connect: AtomicUsize,
     client_connack: AtomicUsize,
@@ -23,6 +35,7 @@ pub struct Metrics {
     client_subscribe_auth_error: AtomicUsize,
     client_publish_auth_error: AtomicUsize,
     client_publish_error: AtomicUsize,
+    client_publish_rate_limited: AtomicUsize,
 
     session_subscribed: AtomicUsize,
     session_unsubscribed: AtomicUsize,
@@ -30,14 +43,45 @@ pub struct Metrics {
     session_resumed: AtomicUsize,
     session_terminated: AtomicUsize,
 
+    //Bytes read from and written to the client connections, including the TLS and WebSocket framing
+    bytes_received: AtomicUsize,
+    bytes_sent: AtomicUsize,
+
+    packets_received: AtomicUsize,
+    packets_connect_received: AtomicUsize,
+    packets_publish_received: AtomicUsize,
+    packets_puback_received: AtomicUsize,
+    packets_pubrec_received: AtomicUsize,
+    packets_pubcomp_received: AtomicUsize,
+    packets_subscribe_received: AtomicUsize,
+    packets_unsubscribe_received: AtomicUsize,
+    packets_pingreq_received: AtomicUsize,
+    packets_disconnect_received: AtomicUsize,
+    packets_auth_received: AtomicUsize,
+
+    packets_sent: AtomicUsize,
+    packets_connack_sent: AtomicUsize,
+    packets_publish_sent: AtomicUsize,
+    packets_puback_sent: AtomicUsize,
+    packets_pubrec_sent: AtomicUsize,
+    packets_pubrel_sent: AtomicUsize,
+    packets_suback_sent: AtomicUsize,
+    packets_unsuback_sent: AtomicUsize,
+    packets_pingresp_sent: AtomicUsize,
+    packets_disconnect_sent: AtomicUsize,
+    packets_auth_sent: AtomicUsize,
+
     messages_publish: AtomicUsize,
-    // messages_received: AtomicUsize,
-    // messages_received_qos0: AtomicUsize,
-    // messages_received_qos1: AtomicUsize,
-    // messages_received_qos2: AtomicUsize,
+    messages_received: AtomicUsize,
+    messages_received_qos0: AtomicUsize,
+    messages_received_qos1: AtomicUsize,
+    messages_received_qos2: AtomicUsize,
     messages_delivered: AtomicUsize,
     // messages_forward: AtomicUsize,
-    // messages_sent: AtomicUsize,
+    messages_sent: AtomicUsize,
+    messages_sent_qos0: AtomicUsize,
+    messages_sent_qos1: AtomicUsize,
+    messages_sent_qos2: AtomicUsize,
     messages_acked: AtomicUsize,
     messages_dropped: AtomicUsize,
 
@@ -70,4 +114,323 @@ pub struct Metrics {
     messages_nonsubscribed_lastwill: AtomicUsize,
     messages_nonsubscribed_system: AtomicUsize,
     messages_nonsubscribed_bridge: AtomicUsize,
+
+    //From receiving the CONNECT packet to sending the CONNACK packet
+    handshake_duration: Histogram,
+    //Authentication hooks, see Hook::client_authenticate()
+    auth_duration: Histogram,
+    //ACL hooks, see Hook::message_publish_check_acl() and Hook::client_subscribe_check_acl()
+    acl_publish_duration: Histogram,
+    acl_subscribe_duration: Histogram,
+    //From the creation of a message to sending it to a subscriber
+    message_latency: Histogram,
+    //Execution time of the hook handlers of each plugin
+    hook_duration: HookDurations,
+}
+
+///MQTT packet types counted by the packets.* metrics
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PacketType {
+    Connect,
+    Connack,
+    Publish,
+    Puback,
+    Pubrec,
+    Pubrel,
+    Pubcomp,
+    Subscribe,
+    Suback,
+    Unsubscribe,
+    Unsuback,
+    Pingreq,
+    Pingresp,
+    Disconnect,
+    Auth,
+}
+
+impl Metrics {
+    #[inline]
+    pub fn packet_received(&self, typ: PacketType) {
+        self.packets_received_inc();
+        match typ {
+            PacketType::Connect => self.packets_connect_received_inc(),
+            PacketType::Publish => self.packets_publish_received_inc(),
+            PacketType::Puback => self.packets_puback_received_inc(),
+            PacketType::Pubrec => self.packets_pubrec_received_inc(),
+            PacketType::Pubcomp => self.packets_pubcomp_received_inc(),
+            PacketType::Subscribe => self.packets_subscribe_received_inc(),
+            PacketType::Unsubscribe => self.packets_unsubscribe_received_inc(),
+            PacketType::Pingreq => self.packets_pingreq_received_inc(),
+            PacketType::Disconnect => self.packets_disconnect_received_inc(),
+            PacketType::Auth => self.packets_auth_received_inc(),
+            _ => {}
+        }
+    }
+
+    #[inline]
+    pub fn packet_sent(&self, typ: PacketType) {
+        self.packets_sent_inc();
+        match typ {
+            PacketType::Connack => self.packets_connack_sent_inc(),
+            PacketType::Publish => self.packets_publish_sent_inc(),
+            PacketType::Puback => self.packets_puback_sent_inc(),
+            PacketType::Pubrec => self.packets_pubrec_sent_inc(),
+            PacketType::Pubrel => self.packets_pubrel_sent_inc(),
+            PacketType::Suback => self.packets_suback_sent_inc(),
+            PacketType::Unsuback => self.packets_unsuback_sent_inc(),
+            PacketType::Pingresp => self.packets_pingresp_sent_inc(),
+            PacketType::Disconnect => self.packets_disconnect_sent_inc(),
+            PacketType::Auth => self.packets_auth_sent_inc(),
+            _ => {}
+        }
+    }
+
+    #[inline]
+    pub fn message_received(&self, qos: QoS) {
+        self.messages_received_inc();
+        match qos {
+            QoS::AtMostOnce => self.messages_received_qos0_inc(),
+            QoS::AtLeastOnce => self.messages_received_qos1_inc(),
+            QoS::ExactlyOnce => self.messages_received_qos2_inc(),
+        }
+    }
+
+    #[inline]
+    pub fn message_sent(&self, qos: QoS) {
+        self.messages_sent_inc();
+        match qos {
+            QoS::AtMostOnce => self.messages_sent_qos0_inc(),
+            QoS::AtLeastOnce => self.messages_sent_qos1_inc(),
+            QoS::ExactlyOnce => self.messages_sent_qos2_inc(),
+        }
+    }
+}
+
+///Upper bounds of the latency histogram buckets, in seconds
+pub const LATENCY_BUCKETS: [f64; 14] =
+    [0.0005, 0.001, 0.0025, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0];
+
+///Latency histogram with the buckets of LATENCY_BUCKETS
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct Histogram {
+    //Observations of each bucket that are greater than the upper bound of the previous bucket
+    buckets: [AtomicU64; LATENCY_BUCKETS.len()],
+    //Observations greater than the upper bound of the last bucket
+    overflow: AtomicU64,
+    //Sum of the observations, in microseconds
+    sum: AtomicU64,
+    count: AtomicU64,
+}
+
+impl Histogram {
+    #[inline]
+    pub fn observe(&self, d: Duration) {
+        let secs = d.as_secs_f64();
+        match LATENCY_BUCKETS.iter().position(|le| secs <= *le) {
+            Some(i) => self.buckets[i].fetch_add(1, Ordering::SeqCst),
+            None => self.overflow.fetch_add(1, Ordering::SeqCst),
+        };
+        self.sum.fetch_add(d.as_micros() as u64, Ordering::SeqCst);
+        self.count.fetch_add(1, Ordering::SeqCst);
+    }
+
+    #[inline]
+    pub fn count(&self) -> u64 {
+        self.count.load(Ordering::SeqCst)
+    }
+
+    #[inline]
+    pub fn sum(&self) -> Duration {
+        Duration::from_micros(self.sum.load(Ordering::SeqCst))
+    }
+
+    ///Cumulative counts, (upper bound in seconds, number of observations less than or equal to it)
+    #[inline]
+    pub fn buckets(&self) -> Vec<(f64, u64)> {
+        let mut total = 0;
+        LATENCY_BUCKETS
+            .iter()
+            .zip(self.buckets.iter())
+            .map(|(le, c)| {
+                total += c.load(Ordering::SeqCst);
+                (*le, total)
+            })
+            .collect()
+    }
+
+    #[inline]
+    pub fn add(&self, other: &Histogram) {
+        for (c, o) in self.buckets.iter().zip(other.buckets.iter()) {
+            c.fetch_add(o.load(Ordering::SeqCst), Ordering::SeqCst);
+        }
+        self.overflow.fetch_add(other.overflow.load(Ordering::SeqCst), Ordering::SeqCst);
+        self.sum.fetch_add(other.sum.load(Ordering::SeqCst), Ordering::SeqCst);
+        self.count.fetch_add(other.count.load(Ordering::SeqCst), Ordering::SeqCst);
+    }
+
+    #[inline]
+    pub fn to_json(&self) -> serde_json::Value {
+        let buckets =
+            self.buckets().into_iter().map(|(le, c)| (le.to_string(), serde_json::Value::from(c))).collect();
+        json!({
+            "count": self.count(),
+            "sum": self.sum().as_secs_f64(),
+            "buckets": serde_json::Value::Object(buckets),
+        })
+    }
+}
+
+impl Clone for Histogram {
+    fn clone(&self) -> Self {
+        let h = Histogram::default();
+        h.add(self);
+        h
+    }
+}
+
+///Execution time of the hook handlers, by plugin and hook type
+#[derive(Debug, Default)]
+pub struct HookDurations {
+    inner: DashMap<(String, Type), Arc<Histogram>>,
+}
+
+impl HookDurations {
+    ///Histogram of the plugin of the handler for the hook type, the plugin is identified
+    ///by the crate of the handler type, such as "rmqtt_acl::AclHandler" for "rmqtt-acl"
+    #[inline]
+    pub(crate) fn get(&self, handler: &str, typ: Type) -> Arc<Histogram> {
+        let plugin = handler.split("::").next().unwrap_or_default().replace('_', "-");
+        self.inner.entry((plugin, typ)).or_default().value().clone()
+    }
+
+    ///(plugin, hook type, histogram), sorted by plugin and hook type
+    #[inline]
+    pub fn to_vec(&self) -> Vec<(String, Type, Histogram)> {
+        let mut items = self
+            .inner
+            .iter()
+            .map(|e| (e.key().0.clone(), e.key().1, e.value().as_ref().clone()))
+            .collect::<Vec<_>>();
+        items.sort_by(|a, b| (a.0.as_str(), a.1.as_str()).cmp(&(b.0.as_str(), b.1.as_str())));
+        items
+    }
+
+    #[inline]
+    pub fn add(&self, other: &HookDurations) {
+        for e in other.inner.iter() {
+            self.inner.entry(e.key().clone()).or_default().add(e.value());
+        }
+    }
+
+    #[inline]
+    pub fn to_json(&self) -> serde_json::Value {
+        let mut plugins = serde_json::Map::new();
+        for (plugin, typ, h) in self.to_vec() {
+            if let Some(hooks) = plugins.entry(plugin).or_insert_with(|| json!({})).as_object_mut() {
+                hooks.insert(typ.as_str().into(), h.to_json());
+            }
+        }
+        serde_json::Value::Object(plugins)
+    }
+}
+
+impl Clone for HookDurations {
+    fn clone(&self) -> Self {
+        let h = HookDurations::default();
+        h.add(self);
+        h
+    }
+}
+
+impl Serialize for HookDurations {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        self.to_vec().serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for HookDurations {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let items = Vec::<(String, Type, Histogram)>::deserialize(deserializer)?;
+        let inner = items.into_iter().map(|(plugin, typ, h)| ((plugin, typ), Arc::new(h))).collect();
+        Ok(HookDurations { inner })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn histogram_observe() {
+        let h = Histogram::default();
+        h.observe(Duration::from_micros(300));
+        h.observe(Duration::from_millis(20));
+        h.observe(Duration::from_secs(20));
+        assert_eq!(h.count(), 3);
+        assert_eq!(h.sum(), Duration::from_micros(20_020_300));
+        let buckets = h.buckets();
+        assert_eq!(buckets.len(), LATENCY_BUCKETS.len());
+        assert_eq!(buckets[0], (0.0005, 1));
+        assert_eq!(buckets[4], (0.01, 1));
+        assert_eq!(buckets[5], (0.025, 2));
+        //the overflow is only in the count
+        assert_eq!(buckets[LATENCY_BUCKETS.len() - 1], (10.0, 2));
+    }
+
+    #[test]
+    fn histogram_bucket_boundaries() {
+        let h = Histogram::default();
+        //an observation equal to the upper bound falls in that bucket
+        h.observe(Duration::from_micros(500));
+        h.observe(Duration::from_micros(501));
+        h.observe(Duration::from_secs(10));
+        h.observe(Duration::from_micros(10_000_001));
+        let buckets = h.buckets();
+        assert_eq!(buckets[0].1, 1);
+        assert_eq!(buckets[1].1, 2);
+        assert_eq!(buckets[LATENCY_BUCKETS.len() - 2].1, 2);
+        assert_eq!(buckets[LATENCY_BUCKETS.len() - 1].1, 3);
+        assert_eq!(h.count(), 4);
+    }
+
+    #[test]
+    fn histogram_add() {
+        let a = Histogram::default();
+        a.observe(Duration::from_millis(1));
+        a.observe(Duration::from_secs(30));
+        let b = Histogram::default();
+        b.observe(Duration::from_millis(1));
+        b.observe(Duration::from_millis(100));
+        a.add(&b);
+        assert_eq!(a.count(), 4);
+        assert_eq!(a.sum(), Duration::from_millis(30_102));
+        let buckets = a.buckets();
+        assert_eq!(buckets[1], (0.001, 2));
+        assert_eq!(buckets[7], (0.1, 3));
+        assert_eq!(buckets[LATENCY_BUCKETS.len() - 1].1, 3);
+        assert_eq!(b.count(), 2);
+    }
+
+    #[test]
+    fn hook_durations_add() {
+        let a = HookDurations::default();
+        a.get("rmqtt_acl::AclHandler", Type::ClientConnect).observe(Duration::from_millis(1));
+        a.get("rmqtt_acl::AclHandler", Type::ClientConnect).observe(Duration::from_millis(2));
+        let b = HookDurations::default();
+        b.get("rmqtt_acl::AclHandler", Type::ClientConnect).observe(Duration::from_millis(3));
+        b.get("rmqtt_auth_http::AuthHandler", Type::ClientAuthenticate).observe(Duration::from_millis(4));
+        a.add(&b);
+
+        let items = a.to_vec();
+        assert_eq!(items.len(), 2);
+        assert_eq!(items[0].0, "rmqtt-acl");
+        assert_eq!(items[0].1, Type::ClientConnect);
+        assert_eq!(items[0].2.count(), 3);
+        assert_eq!(items[0].2.sum(), Duration::from_millis(6));
+        assert_eq!(items[1].0, "rmqtt-auth-http");
+        assert_eq!(items[1].2.count(), 1);
+
+        let json = a.to_json();
+        assert_eq!(json["rmqtt-acl"][Type::ClientConnect.as_str()]["count"], 3);
+    }
 }
@@ -13,6 +13,8 @@ use crate::{grpc, MqttError, Result, Runtime};
 
 type HashMap<K, V> = std::collections::HashMap<K, V, ahash::RandomState>;
 
+pub mod alarm;
+pub mod auth_chain;
 pub mod default;
 pub mod error;
 pub mod executor;
@@ -22,9 +24,12 @@ pub mod inflight;
 pub mod metrics;
 pub mod queue;
 pub mod retain;
+pub mod scram;
 pub mod session;
 pub mod stats;
+pub mod telemetry;
 pub mod topic;
+pub mod topic_metrics;
 pub mod types;
 pub mod v3;
 pub mod v5;
@@ -239,45 +244,48 @@ pub trait SharedSubscription: Sync + Send {
     #[inline]
     async fn choice(
         &self,
-        ncs: &[(
-            NodeId,
-            ClientId,
-            SubscriptionOptions,
-            Option<Vec<SubscriptionIdentifier>>,
-            Option<IsOnline>,
-        )],
+        _from: &Id,
+        _topic: &TopicName,
+        _topic_filter: &TopicFilter,
+        _group: &SharedGroup,
+        ncs: &[SharedSubChoice],
     ) -> Option<(usize, IsOnline)> {
-        if ncs.is_empty() {
-            return None;
-        }
+        random_choice(ncs).await
+    }
+}
 
-        let mut tmp_ncs = ncs
-            .iter()
-            .enumerate()
-            .map(|(idx, (node_id, client_id, _, _, is_online))| (idx, node_id, client_id, is_online))
-            .collect::<Vec<_>>();
+///Select a subscriber at random, online subscribers take precedence
+pub async fn random_choice(ncs: &[SharedSubChoice]) -> Option<(usize, IsOnline)> {
+    if ncs.is_empty() {
+        return None;
+    }
 
-        while !tmp_ncs.is_empty() {
-            let r_idx = if tmp_ncs.len() == 1 { 0 } else { (rand::random::<u64>() as usize) % tmp_ncs.len() };
+    let mut tmp_ncs = ncs
+        .iter()
+        .enumerate()
+        .map(|(idx, (node_id, client_id, _, _, is_online))| (idx, node_id, client_id, is_online))
+        .collect::<Vec<_>>();
 
-            let (idx, node_id, client_id, is_online) = tmp_ncs.remove(r_idx);
+    while !tmp_ncs.is_empty() {
+        let r_idx = if tmp_ncs.len() == 1 { 0 } else { (rand::random::<u64>() as usize) % tmp_ncs.len() };
 
-            let is_online = if let Some(is_online) = is_online {
-                *is_online
-            } else {
-                Runtime::instance().extends.router().await.is_online(*node_id, client_id).await
-            };
+        let (idx, node_id, client_id, is_online) = tmp_ncs.remove(r_idx);
 
-            if is_online {
-                return Some((idx, true));
-            }
+        let is_online = if let Some(is_online) = is_online {
+            *is_online
+        } else {
+            Runtime::instance().extends.router().await.is_online(*node_id, client_id).await
+        };
 
-            if tmp_ncs.is_empty() {
-                return Some((idx, is_online));
-            }
+        if is_online {
+            return Some((idx, true));
+        }
+
+        if tmp_ncs.is_empty() {
+            return Some((idx, is_online));
         }
-        return None;
     }
+    None
 }
 
 #[async_trait]
@@ -395,6 +403,15 @@ pub trait DelayedSender: Sync + Send {
     async fn is_empty(&self) -> bool {
         self.len().await == 0
     }
+
+    ///Pending delayed messages, ordered by expiration time
+    async fn list(&self, offset: usize, limit: usize) -> Result<Vec<DelayedPublish>>;
+
+    ///Get a pending delayed message
+    async fn get(&self, id: DelayedPublishId) -> Result<Option<DelayedPublish>>;
+
+    ///Cancel a pending delayed message, return the cancelled message
+    async fn cancel(&self, id: DelayedPublishId) -> Result<Option<DelayedPublish>>;
 }
 
 //Automatic subscription
@@ -1,6 +1,7 @@
 use std::num::NonZeroU32;
 use std::pin::Pin;
 use std::rc::Rc;
+use std::sync::atomic::{AtomicU32, Ordering};
 use std::sync::Arc;
 use std::task::{Context, Poll};
 use std::time::Duration;
@@ -119,6 +120,9 @@ impl<T> Stream for ReceiverStream<T> {
 
 pub struct Limiter {
     l: DirectLimiter,
+    burst: NonZeroU32,
+    //Cells taken beyond the burst size, paid before any further cells are taken
+    debt: AtomicU32,
 }
 
 impl Limiter {
@@ -132,7 +136,52 @@ impl Limiter {
         log::debug!("burst: {:?}, {:?}, {:?}", burst, replenish_n_per, period);
         let q = Quota::with_period(period).ok_or_else(|| anyhow!("period is 0"))?.allow_burst(burst);
         let l = RateLimiter::direct(q);
-        Ok(Self { l })
+        Ok(Self { l, burst, debt: AtomicU32::new(0) })
+    }
+
+    ///Waits until n cells and the debt are taken, more cells than the burst size are taken in several
+    ///refill periods
+    #[inline]
+    pub async fn acquire(&self, n: NonZeroU32) {
+        let mut rest = n.get().saturating_add(self.debt.swap(0, Ordering::SeqCst));
+        while let Some(n) = NonZeroU32::new(rest.min(self.burst.get())) {
+            let _ = self.l.until_n_ready(n).await;
+            rest -= n.get();
+        }
+    }
+
+    ///Takes n cells if they are available now and there is no debt left. More cells than the burst size
+    ///are taken if the bucket is full, the cells beyond the burst size are charged as debt, so that the
+    ///following calls fail until it is paid by the refill.
+    #[inline]
+    pub fn try_acquire(&self, n: NonZeroU32) -> bool {
+        if !self.pay_debt() {
+            return false;
+        }
+        if n <= self.burst {
+            matches!(self.l.check_n(n), Ok(Ok(())))
+        } else if matches!(self.l.check_n(self.burst), Ok(Ok(()))) {
+            self.debt.fetch_add(n.get() - self.burst.get(), Ordering::SeqCst);
+            true
+        } else {
+            false
+        }
+    }
+
+    //Pays the debt with the cells available now, returns true if no debt is left
+    #[inline]
+    fn pay_debt(&self) -> bool {
+        loop {
+            let debt = self.debt.load(Ordering::SeqCst);
+            let n = match NonZeroU32::new(debt.min(self.burst.get())) {
+                Some(n) => n,
+                None => return true,
+            };
+            if !matches!(self.l.check_n(n), Ok(Ok(()))) {
+                return false;
+            }
+            self.debt.fetch_sub(n.get(), Ordering::SeqCst);
+        }
     }
 
     #[inline]
@@ -226,6 +275,28 @@ impl<T> Queue<T> {
 }
 
 mod test {
+    #[test]
+    fn try_acquire_oversized() {
+        use std::num::NonZeroU32;
+        use std::time::Duration;
+
+        //One cell per second
+        let limiter = super::Limiter::new(NonZeroU32::new(10).unwrap(), Duration::from_secs(10)).unwrap();
+        let n = |n: u32| NonZeroU32::new(n).unwrap();
+
+        //A message larger than the burst size is taken from a full bucket, the rest is charged as debt
+        assert!(limiter.try_acquire(n(25)));
+        assert_eq!(limiter.debt.load(super::Ordering::SeqCst), 15);
+        assert!(!limiter.try_acquire(n(1)));
+
+        //A message larger than the burst size needs a full bucket
+        let limiter = super::Limiter::new(NonZeroU32::new(10).unwrap(), Duration::from_secs(10)).unwrap();
+        assert!(limiter.try_acquire(n(5)));
+        assert!(!limiter.try_acquire(n(25)));
+        assert!(limiter.try_acquire(n(5)));
+        assert!(!limiter.try_acquire(n(1)));
+    }
+
     #[ntex::main]
     #[test]
     async fn channel() {
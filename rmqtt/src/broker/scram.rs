@@ -0,0 +1,239 @@
+//! SCRAM-SHA-256 (RFC 5802, RFC 7677), server side of the exchange.
+//!
+//! Used by MQTT 5.0 enhanced authentication, authentication method "SCRAM-SHA-256":
+//!   CONNECT(client-first) -> AUTH(server-first) -> AUTH(client-final) -> CONNACK(server-final)
+
+use base64::prelude::{Engine, BASE64_STANDARD};
+use hmac::{Hmac, Mac};
+use sha2::{Digest, Sha256};
+use subtle::ConstantTimeEq;
+
+use crate::{MqttError, Result};
+
+pub const SCRAM_SHA_256: &str = "SCRAM-SHA-256";
+pub const ITERATIONS_DEFAULT: u32 = 4096;
+const ITERATIONS_MIN: u32 = 4096;
+
+type HmacSha256 = Hmac<Sha256>;
+
+///User credentials, the plaintext password is not required after they are generated
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ScramCredentials {
+    pub salt: Vec<u8>,
+    pub iterations: u32,
+    pub stored_key: Vec<u8>,
+    pub server_key: Vec<u8>,
+}
+
+impl ScramCredentials {
+    #[inline]
+    pub fn new(password: &[u8], salt: Vec<u8>, iterations: u32) -> Self {
+        let mut salted_password = [0u8; 32];
+        pbkdf2::pbkdf2_hmac::<Sha256>(password, &salt, iterations, &mut salted_password);
+        let client_key = hmac(&salted_password, b"Client Key");
+        let stored_key = Sha256::digest(&client_key).to_vec();
+        let server_key = hmac(&salted_password, b"Server Key");
+        Self { salt, iterations, stored_key, server_key }
+    }
+
+    ///Generate credentials with a random salt
+    #[inline]
+    pub fn generate(password: &[u8], iterations: u32) -> Self {
+        Self::new(password, rand::random::<[u8; 16]>().to_vec(), iterations)
+    }
+
+    ///{"salt": "base64", "iterations": 4096, "stored_key": "base64", "server_key": "base64"}
+    #[inline]
+    pub fn from_json(v: &serde_json::Value) -> Result<Self> {
+        let field = |name: &str| -> Result<Vec<u8>> {
+            let v = v
+                .get(name)
+                .and_then(|v| v.as_str())
+                .ok_or_else(|| MqttError::from(format!("SCRAM credentials, '{}' does not exist", name)))?;
+            BASE64_STANDARD.decode(v).map_err(|e| MqttError::from(format!("SCRAM credentials, {}", e)))
+        };
+        let iterations =
+            v.get("iterations").and_then(|v| v.as_u64()).map(|i| i as u32).unwrap_or(ITERATIONS_DEFAULT);
+        Ok(Self {
+            salt: field("salt")?,
+            iterations,
+            stored_key: field("stored_key")?,
+            server_key: field("server_key")?,
+        })
+    }
+
+    #[inline]
+    pub fn to_json(&self) -> serde_json::Value {
+        json!({
+            "salt": BASE64_STANDARD.encode(&self.salt),
+            "iterations": self.iterations,
+            "stored_key": BASE64_STANDARD.encode(&self.stored_key),
+            "server_key": BASE64_STANDARD.encode(&self.server_key),
+        })
+    }
+}
+
+///client-first-message
+#[derive(Debug, Clone)]
+pub struct ClientFirst {
+    pub username: String,
+    gs2_header: String,
+    nonce: String,
+    bare: String,
+}
+
+impl ClientFirst {
+    #[inline]
+    pub fn parse(data: &[u8]) -> Result<Self> {
+        let msg = std::str::from_utf8(data)?;
+        //gs2-header, channel binding is not supported
+        let bare_idx = if msg.starts_with("n,") || msg.starts_with("y,") {
+            msg[2..].find(',').map(|i| i + 3)
+        } else {
+            None
+        }
+        .ok_or_else(|| MqttError::from("SCRAM client-first-message, unsupported gs2 header"))?;
+        let (gs2_header, bare) = msg.split_at(bare_idx);
+
+        let mut parts = bare.split(',');
+        let username = parts
+            .next()
+            .and_then(|p| p.strip_prefix("n="))
+            .ok_or_else(|| MqttError::from("SCRAM client-first-message, username does not exist"))?;
+        let username = username.replace("=2C", ",").replace("=3D", "=");
+        let nonce = parts
+            .next()
+            .and_then(|p| p.strip_prefix("r="))
+            .filter(|n| !n.is_empty())
+            .ok_or_else(|| MqttError::from("SCRAM client-first-message, nonce does not exist"))?;
+
+        Ok(Self { username, gs2_header: gs2_header.into(), nonce: nonce.into(), bare: bare.into() })
+    }
+}
+
+///Server side of one SCRAM exchange, created after the client-first-message is received
+#[derive(Debug, Clone)]
+pub struct ScramServer {
+    client_first: ClientFirst,
+    credentials: ScramCredentials,
+    nonce: String,
+    server_first: String,
+}
+
+impl ScramServer {
+    #[inline]
+    pub fn new(client_first: ClientFirst, credentials: ScramCredentials) -> Result<Self> {
+        let server_nonce = BASE64_STANDARD.encode(rand::random::<[u8; 18]>());
+        Self::with_nonce(client_first, credentials, &server_nonce)
+    }
+
+    #[inline]
+    fn with_nonce(
+        client_first: ClientFirst,
+        credentials: ScramCredentials,
+        server_nonce: &str,
+    ) -> Result<Self> {
+        if credentials.iterations < ITERATIONS_MIN {
+            return Err(MqttError::from(format!(
+                "SCRAM credentials, iterations must be at least {}",
+                ITERATIONS_MIN
+            )));
+        }
+        let nonce = format!("{}{}", client_first.nonce, server_nonce);
+        let server_first = format!(
+            "r={},s={},i={}",
+            nonce,
+            BASE64_STANDARD.encode(&credentials.salt),
+            credentials.iterations
+        );
+        Ok(Self { client_first, credentials, nonce, server_first })
+    }
+
+    #[inline]
+    pub fn username(&self) -> &str {
+        &self.client_first.username
+    }
+
+    ///server-first-message
+    #[inline]
+    pub fn server_first(&self) -> &[u8] {
+        self.server_first.as_bytes()
+    }
+
+    ///Verify the client-final-message and return the server-final-message
+    #[inline]
+    pub fn client_final(&self, data: &[u8]) -> Result<Vec<u8>> {
+        let msg = std::str::from_utf8(data)?;
+        let (without_proof, proof) = msg
+            .rsplit_once(",p=")
+            .ok_or_else(|| MqttError::from("SCRAM client-final-message, proof does not exist"))?;
+
+        let mut parts = without_proof.split(',');
+        let cbind = parts
+            .next()
+            .and_then(|p| p.strip_prefix("c="))
+            .and_then(|c| BASE64_STANDARD.decode(c).ok())
+            .ok_or_else(|| MqttError::from("SCRAM client-final-message, channel binding does not exist"))?;
+        if cbind != self.client_first.gs2_header.as_bytes() {
+            return Err(MqttError::from("SCRAM client-final-message, channel binding mismatch"));
+        }
+        if parts.next().and_then(|p| p.strip_prefix("r=")) != Some(self.nonce.as_str()) {
+            return Err(MqttError::from("SCRAM client-final-message, nonce mismatch"));
+        }
+
+        let proof = BASE64_STANDARD
+            .decode(proof)
+            .map_err(|e| MqttError::from(format!("SCRAM client-final-message, {}", e)))?;
+        let auth_message = format!("{},{},{}", self.client_first.bare, self.server_first, without_proof);
+        let client_signature = hmac(&self.credentials.stored_key, auth_message.as_bytes());
+        if proof.len() != client_signature.len() {
+            return Err(MqttError::from("SCRAM client-final-message, invalid proof"));
+        }
+        let client_key = proof.iter().zip(client_signature.iter()).map(|(p, s)| p ^ s).collect::<Vec<u8>>();
+        if !bool::from(Sha256::digest(&client_key).as_slice().ct_eq(&self.credentials.stored_key)) {
+            return Err(MqttError::from("SCRAM client-final-message, invalid proof"));
+        }
+
+        let server_signature = hmac(&self.credentials.server_key, auth_message.as_bytes());
+        Ok(format!("v={}", BASE64_STANDARD.encode(server_signature)).into_bytes())
+    }
+}
+
+#[inline]
+fn hmac(key: &[u8], data: &[u8]) -> Vec<u8> {
+    let mut mac = <HmacSha256 as Mac>::new_from_slice(key).expect("HMAC can take key of any size");
+    mac.update(data);
+    mac.finalize().into_bytes().to_vec()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    //RFC 7677, section 3
+    #[test]
+    fn scram_sha_256() {
+        let salt = BASE64_STANDARD.decode("W22ZaJ0SNY7soEsUEjb6gQ==").unwrap();
+        let credentials = ScramCredentials::new(b"pencil", salt, 4096);
+
+        let client_first = ClientFirst::parse(b"n,,n=user,r=rOprNGfwEbeRWgbNEkqO").unwrap();
+        assert_eq!(client_first.username, "user");
+
+        let server =
+            ScramServer::with_nonce(client_first, credentials, "%hvYDpWUa2RaTCAfuxFIlj)hNlF$k0").unwrap();
+        assert_eq!(
+            server.server_first(),
+            b"r=rOprNGfwEbeRWgbNEkqO%hvYDpWUa2RaTCAfuxFIlj)hNlF$k0,s=W22ZaJ0SNY7soEsUEjb6gQ==,i=4096"
+        );
+
+        let server_final = server
+            .client_final(b"c=biws,r=rOprNGfwEbeRWgbNEkqO%hvYDpWUa2RaTCAfuxFIlj)hNlF$k0,p=dHzbZapWIk4jUhN+Ute9ytag9zjfMHgsqmmiz7AndVQ=")
+            .unwrap();
+        assert_eq!(server_final, b"v=6rriTRBi23WpRR/wtup+mMhUZUn/dB5nLTJRsjl95G4=");
+
+        assert!(server
+            .client_final(b"c=biws,r=rOprNGfwEbeRWgbNEkqO%hvYDpWUa2RaTCAfuxFIlj)hNlF$k0,p=AAAAZapWIk4jUhN+Ute9ytag9zjfMHgsqmmiz7AndVQ=")
+            .is_err());
+        assert!(ClientFirst::parse(b"p=tls-unique,,n=user,r=abc").is_err());
+    }
+}
@@ -1,6 +1,6 @@
 use std::convert::From as _f;
 use std::fmt;
-use std::num::NonZeroU16;
+use std::num::{NonZeroU16, NonZeroU32};
 use std::ops::Deref;
 use std::rc::Rc;
 use std::str::FromStr;
@@ -13,15 +13,19 @@ use futures::StreamExt;
 use tokio::sync::RwLock;
 use tokio::time::{Duration, Instant};
 
-use ntex_mqtt::v5::codec::RetainHandling;
+use ntex_mqtt::v5::codec::{PublishAckReason, RetainHandling};
 
+use crate::broker::default::DefaultSessionManager;
+use crate::broker::executor::{listener_connections_dec, listener_connections_inc};
 use crate::broker::hook::Hook;
 use crate::broker::inflight::{Inflight, InflightMessage, MomentStatus};
 use crate::broker::queue::{self, Limiter, Policy};
 use crate::broker::types::*;
-use crate::metrics::Metrics;
+use crate::metrics::{Metrics, PacketType};
 use crate::settings::acl::AuthInfo;
-use crate::settings::listener::Listener;
+use crate::settings::listener::{Listener, PublishLimit, PublishLimitAction};
+use crate::telemetry::{start_publish_span, SpanKind};
+use crate::topic_metrics::TopicMetrics;
 use crate::{MqttError, Result, Runtime};
 
 #[derive(Clone)]
@@ -33,6 +37,54 @@ pub struct SessionState {
     pub deliver_queue_tx: Option<MessageSender>,
     pub server_topic_aliases: Option<Rc<ServerTopicAliases>>,
     pub client_topic_aliases: Option<Rc<ClientTopicAliases>>,
+    pub publish_limiter: Option<Rc<PublishLimiter>>,
+}
+
+///Inbound publish limits of a client, see Fitter::publish_limit()
+pub struct PublishLimiter {
+    messages: Option<Limiter>,
+    bytes: Option<Limiter>,
+    action: PublishLimitAction,
+}
+
+impl PublishLimiter {
+    #[inline]
+    fn new(limit: PublishLimit) -> Result<Option<Self>> {
+        if limit.is_unlimited() {
+            return Ok(None);
+        }
+        let messages = limit.rate.map(|(burst, period)| Limiter::new(burst, period)).transpose()?;
+        let bytes = limit.bytes_rate.map(|(burst, period)| Limiter::new(burst, period)).transpose()?;
+        Ok(Some(Self { messages, bytes, action: limit.action }))
+    }
+
+    ///Returns false if the limit is exceeded and the action is disconnect,
+    ///otherwise waits until the message is allowed
+    #[inline]
+    async fn acquire(&self, payload_len: usize) -> bool {
+        let bytes = NonZeroU32::new(payload_len.min(u32::MAX as usize) as u32);
+        let messages_ok = self.messages.as_ref().map(|l| l.try_acquire(NonZeroU32::MIN)).unwrap_or(true);
+        let bytes_ok = match (&self.bytes, bytes) {
+            (Some(l), Some(n)) => l.try_acquire(n),
+            _ => true,
+        };
+        if messages_ok && bytes_ok {
+            return true;
+        }
+        Metrics::instance().client_publish_rate_limited_inc();
+        match self.action {
+            PublishLimitAction::Disconnect => false,
+            PublishLimitAction::Backpressure => {
+                if let (false, Some(l)) = (messages_ok, &self.messages) {
+                    l.acquire(NonZeroU32::MIN).await;
+                }
+                if let (false, Some(l), Some(n)) = (bytes_ok, &self.bytes, bytes) {
+                    l.acquire(n).await;
+                }
+                true
+            }
+        }
+    }
 }
 
 impl fmt::Debug for SessionState {
@@ -68,6 +120,14 @@ impl SessionState {
         };
         log::debug!("server_topic_aliases: {:?}", server_topic_aliases);
         log::debug!("client_topic_aliases: {:?}", client_topic_aliases);
+        let publish_limit = session.fitter.publish_limit(session.auth_info().as_deref());
+        let publish_limiter = match PublishLimiter::new(publish_limit) {
+            Ok(limiter) => limiter.map(Rc::new),
+            Err(e) => {
+                log::warn!("{:?} publish limit {:?} error, {:?}", session.id, publish_limit, e);
+                None
+            }
+        };
         Self {
             tx: None,
             session,
@@ -76,6 +136,7 @@ impl SessionState {
             deliver_queue_tx: None,
             server_topic_aliases,
             client_topic_aliases,
+            publish_limiter,
         }
     }
 
@@ -103,11 +164,15 @@ impl SessionState {
         };
 
         let mut flags = StateFlags::empty();
+        let mut shutdown_ack = None;
 
         log::debug!("{:?} there are {} offline messages ...", state.id, state.deliver_queue().len());
 
         ntex::rt::spawn(async move {
             Runtime::instance().stats.connections.inc();
+            if let Some(local_addr) = state.id.local_addr {
+                listener_connections_inc(local_addr.port());
+            }
 
             let (state, deliver_queue_tx, mut deliver_queue_rx) = state.deliver_queue_channel(&limiter);
 
@@ -214,6 +279,15 @@ impl SessionState {
                                     }else{
                                         log::warn!("{:?} Message::Unsubscribe, reply sender is closed", state.id);
                                     }
+                                },
+                                Message::Shutdown(ack_tx, server_reference) => {
+                                    log::debug!("{:?} Message::Shutdown, server_reference: {:?}", state.id, server_reference);
+                                    state.send_shutdown_disconnect(server_reference);
+                                    if let Err(e) = state.disconnected_reason_add(Reason::ServerShuttingDown).await {
+                                        log::error!("{:?} disconnected reason add error: {:?}", state.id, e);
+                                    }
+                                    shutdown_ack = Some(ack_tx);
+                                    break
                                 }
                             }
                         }else{
@@ -275,6 +349,9 @@ impl SessionState {
             );
 
             Runtime::instance().stats.connections.dec();
+            if let Some(local_addr) = state.id.local_addr {
+                listener_connections_dec(local_addr.port());
+            }
 
             //Setting the disconnected state
             if let Err(e) = state.disconnected_set(None, None).await {
@@ -325,6 +402,11 @@ impl SessionState {
                     state.hook.offline_inflight_messages(inflight_messages).await;
                 }
 
+                //The session has been saved, the node can continue to shut down
+                if let Some(ack_tx) = shutdown_ack.take() {
+                    let _ = ack_tx.send(());
+                }
+
                 //Start offline event loop
                 Self::offline_start(
                     state.clone(),
@@ -456,6 +538,7 @@ impl SessionState {
             deliver_queue_tx: None,
             server_topic_aliases: None,
             client_topic_aliases: None,
+            publish_limiter: None,
         };
 
         let limiter = {
@@ -561,6 +644,23 @@ impl SessionState {
         }
     }
 
+    ///MQTT 5.0 only, the connection of MQTT 3.1.1 clients is just closed
+    #[inline]
+    fn send_shutdown_disconnect(&self, server_reference: Option<ByteString>) {
+        //Only MQTT 5.0 has a server side DISCONNECT packet
+        if let Some(sink @ Sink::V5(_)) = self.sink.as_ref() {
+            let reason_code = if server_reference.is_some() {
+                DisconnectReasonCode::UseAnotherServer
+            } else {
+                DisconnectReasonCode::ServerShuttingDown
+            };
+            let disconnect = DisconnectV5 { reason_code, server_reference, ..Default::default() };
+            if let Err(e) = sink.send(Packet::V5(PacketV5::Disconnect(disconnect))) {
+                log::debug!("{:?} send disconnect error, {:?}", self.id, e);
+            }
+        }
+    }
+
     #[inline]
     fn last_will_enable(&self, flags: StateFlags, clean_session: bool) -> bool {
         let session_present =
@@ -737,16 +837,27 @@ impl SessionState {
             publish.set_packet_id(self.inflight_win().read().await.next_id()?);
         }
 
+        //The subscriber receives the span of the delivery as the parent
+        let mut span = start_publish_span("mqtt.deliver", SpanKind::Producer, &publish);
+        if let Some(span) = span.as_mut() {
+            span.set_attribute("messaging.client_id", self.id.client_id.as_str());
+            span.inject(&mut publish);
+        }
+
         //hook, message_delivered
         let publish = self.hook.message_delivered(from.clone(), &publish).await.unwrap_or(publish);
 
         //send message
-        sink.publish(
-            &publish,
-            expiry_check_res.message_expiry_interval(),
-            self.server_topic_aliases.as_ref(),
-        )
-        .await?; //@TODO ... at exception, send hook and or store message
+        if let Err(e) = sink
+            .publish(&publish, expiry_check_res.message_expiry_interval(), self.server_topic_aliases.as_ref())
+            .await
+        {
+            if let Some(span) = span.as_mut() {
+                span.set_error(&e);
+            }
+            return Err(e); //@TODO ... at exception, send hook and or store message
+        }
+        drop(span);
 
         //cache messages to inflight window
         let moment_status = match publish.qos() {
@@ -974,46 +1085,63 @@ impl SessionState {
 
     #[inline]
     pub async fn publish_v3(&self, publish: &v3::Publish) -> Result<bool> {
+        let qos = publish.qos();
         match self.publish(Publish::from(publish)).await {
-            Err(e) => {
-                Metrics::instance().client_publish_error_inc();
-                if let Err(e) =
-                    self.disconnected_reason_add(Reason::PublishFailed(ByteString::from(e.to_string()))).await
-                {
-                    log::error!("{:?} disconnected reason add error: {:?}", self.id, e);
-                }
-                Err(e)
+            Err(e) => Err(self.publish_error(e).await),
+            Ok(reply) if reply.reason_code == PublishAckReason::TopicNameInvalid => {
+                let e =
+                    MqttError::PublishAckReason(reply.reason_code, reply.reason_string.unwrap_or_default());
+                Err(self.publish_error(e).await)
             }
-            Ok(false) => {
+            Ok(reply) if !reply.is_success() => {
                 Metrics::instance().client_publish_error_inc();
+                Self::publish_ack_sent(qos);
                 Ok(false)
             }
-            Ok(true) => Ok(true),
+            Ok(_) => {
+                Self::publish_ack_sent(qos);
+                Ok(true)
+            }
         }
     }
 
     #[inline]
-    pub async fn publish_v5(&self, publish: &v5::Publish) -> Result<bool> {
+    pub async fn publish_v5(&self, publish: &v5::Publish) -> Result<PublishReply> {
         match self._publish_v5(publish).await {
-            Err(e) => {
-                Metrics::instance().client_publish_error_inc();
-                if let Err(e) =
-                    self.disconnected_reason_add(Reason::PublishFailed(ByteString::from(e.to_string()))).await
-                {
-                    log::error!("{:?} disconnected reason add error: {:?}", self.id, e);
+            Err(e) => Err(self.publish_error(e).await),
+            Ok(reply) => {
+                if !reply.is_success() {
+                    Metrics::instance().client_publish_error_inc();
                 }
-                Err(e)
-            }
-            Ok(false) => {
-                Metrics::instance().client_publish_error_inc();
-                Ok(false)
+                Self::publish_ack_sent(publish.qos());
+                Ok(reply)
             }
-            Ok(true) => Ok(true),
         }
     }
 
+    ///PUBACK or PUBREC is sent by the codec once the message is handled
     #[inline]
-    async fn _publish_v5(&self, publish: &v5::Publish) -> Result<bool> {
+    fn publish_ack_sent(qos: QoS) {
+        match qos {
+            QoS::AtMostOnce => {}
+            QoS::AtLeastOnce => Metrics::instance().packet_sent(PacketType::Puback),
+            QoS::ExactlyOnce => Metrics::instance().packet_sent(PacketType::Pubrec),
+        }
+    }
+
+    #[inline]
+    async fn publish_error(&self, e: MqttError) -> MqttError {
+        Metrics::instance().client_publish_error_inc();
+        if let Err(e) =
+            self.disconnected_reason_add(Reason::PublishFailed(ByteString::from(e.to_string()))).await
+        {
+            log::error!("{:?} disconnected reason add error: {:?}", self.id, e);
+        }
+        e
+    }
+
+    #[inline]
+    async fn _publish_v5(&self, publish: &v5::Publish) -> Result<PublishReply> {
         log::debug!("{:?} publish: {:?}", self.id, publish);
         let mut p = Publish::from(publish);
         if let Some(client_topic_aliases) = &self.client_topic_aliases {
@@ -1023,11 +1151,49 @@ impl SessionState {
     }
 
     #[inline]
-    async fn publish(&self, mut publish: Publish) -> Result<bool> {
+    async fn publish(&self, mut publish: Publish) -> Result<PublishReply> {
+        Metrics::instance().packet_received(PacketType::Publish);
+        Metrics::instance().message_received(publish.qos());
+
+        //The span of the publish is the parent of the hooks, the bridges and the route of the message
+        let mut span = start_publish_span("mqtt.publish", SpanKind::Server, &publish);
+        if let Some(span) = span.as_mut() {
+            span.set_attribute("messaging.client_id", self.id.client_id.as_str());
+            span.inject(&mut publish);
+        }
+        let res = self.handle_publish(publish).await;
+        if let Some(span) = span.as_mut() {
+            match &res {
+                Ok(reply) => span.set_attribute("messaging.mqtt.reason", format!("{:?}", reply.reason_code)),
+                Err(e) => span.set_error(e),
+            }
+        }
+        res
+    }
+
+    #[inline]
+    async fn handle_publish(&self, mut publish: Publish) -> Result<PublishReply> {
+        if let Some(limiter) = &self.publish_limiter {
+            if !limiter.acquire(publish.payload.len()).await {
+                return Err(MqttError::PublishAckReason(
+                    PublishAckReason::QuotaExceeded,
+                    ByteString::from_static("Publish rate limit exceeded"),
+                ));
+            }
+        }
+
         let from = From::from_custom(self.id.clone());
 
         if self.listen_cfg().delayed_publish {
-            publish = Runtime::instance().extends.delayed_sender().await.parse(publish)?;
+            publish = match Runtime::instance().extends.delayed_sender().await.parse(publish) {
+                Ok(publish) => publish,
+                Err(e) => {
+                    return Ok(PublishReply::new(
+                        PublishAckReason::TopicNameInvalid,
+                        Some(ByteString::from(e.to_string())),
+                    ))
+                }
+            };
         }
 
         //hook, message_publish
@@ -1050,7 +1216,10 @@ impl SessionState {
                     "Publish Refused, reason: hook::message_publish_check_acl() -> Rejected(Disconnect)",
                 ))
             } else {
-                Ok(false)
+                Ok(PublishReply::new(
+                    PublishAckReason::NotAuthorized,
+                    Some(ByteString::from_static("Publish Refused, not authorized")),
+                ))
             };
         }
 
@@ -1074,7 +1243,9 @@ impl SessionState {
                 .await?
             {
                 if Runtime::instance().settings.mqtt.delayed_publish_immediate {
-                    Self::forwards(f, p, message_storage_available, message_expiry_interval).await?;
+                    return Ok(Self::forwards_reply(
+                        Self::forwards(f, p, message_storage_available, message_expiry_interval).await?,
+                    ));
                 } else {
                     //hook, Message dropped
                     Runtime::instance()
@@ -1083,24 +1254,97 @@ impl SessionState {
                         .await
                         .message_dropped(None, f, p, Reason::DelayedPublishRefused)
                         .await;
-                    return Ok(false);
+                    return Ok(PublishReply::new(
+                        PublishAckReason::QuotaExceeded,
+                        Some(ByteString::from_static("Delayed Publish Refused, too many delayed messages")),
+                    ));
                 }
             }
-            return Ok(true);
+            return Ok(PublishReply::success());
         }
 
-        Self::forwards(from, publish, message_storage_available, message_expiry_interval).await?;
+        Ok(Self::forwards_reply(
+            Self::forwards(from, publish, message_storage_available, message_expiry_interval).await?,
+        ))
+    }
 
-        Ok(true)
+    #[inline]
+    fn forwards_reply(matched: bool) -> PublishReply {
+        if matched {
+            PublishReply::success()
+        } else {
+            PublishReply::new(PublishAckReason::NoMatchingSubscribers, None)
+        }
     }
 
+    ///Publish a message as the system client, such as the messages of the $SYS topics
+    pub async fn sys_publish(
+        nodeid: NodeId,
+        topic: String,
+        publish_qos: QoS,
+        payload: serde_json::Value,
+        message_expiry_interval: Duration,
+    ) {
+        match serde_json::to_string(&payload) {
+            Ok(payload) => {
+                let from = From::from_system(Id::new(
+                    nodeid,
+                    None,
+                    None,
+                    ClientId::from_static("system"),
+                    Some(UserName::from("system")),
+                ));
+
+                let p = Publish {
+                    dup: false,
+                    retain: false,
+                    qos: publish_qos,
+                    topic: TopicName::from(topic),
+                    packet_id: None,
+                    payload: bytes::Bytes::from(payload),
+                    properties: PublishProperties::default(),
+                    delay_interval: None,
+                    create_time: timestamp_millis(),
+                };
+
+                //hook, message_publish
+                let p = Runtime::instance()
+                    .extends
+                    .hook_mgr()
+                    .await
+                    .message_publish(None, from.clone(), &p)
+                    .await
+                    .unwrap_or(p);
+
+                let storage_available = Runtime::instance().extends.message_mgr().await.enable();
+
+                if let Err(e) =
+                    Self::forwards(from, p, storage_available, Some(message_expiry_interval)).await
+                {
+                    log::warn!("{:?}", e);
+                }
+            }
+            Err(e) => {
+                log::error!("{:?}", e);
+            }
+        }
+    }
+
+    ///Forward the message to the subscribers, returns false if there are no matching subscribers
     #[inline]
     pub async fn forwards(
         from: From,
-        publish: Publish,
+        mut publish: Publish,
         message_storage_available: bool,
         message_expiry_interval: Option<Duration>,
-    ) -> Result<()> {
+    ) -> Result<bool> {
+        let mut span = start_publish_span("mqtt.route", SpanKind::Internal, &publish);
+        if let Some(span) = span.as_mut() {
+            span.set_attribute("rmqtt.from.type", from.typ().as_str());
+            span.set_attribute("rmqtt.from.clientid", from.client_id.as_str());
+            span.inject(&mut publish);
+        }
+
         //make message id
         let msg_id = if message_storage_available {
             Some(Runtime::instance().extends.message_mgr().await.next_msg_id())
@@ -1127,9 +1371,13 @@ impl SessionState {
                 None
             };
 
+        TopicMetrics::instance().message_in(&publish);
+
+        let mut matched = true;
         let sub_cids = match Runtime::instance().extends.shared().await.forwards(from.clone(), publish).await
         {
             Ok(None) => {
+                matched = false;
                 //hook, message_nonsubscribed
                 Runtime::instance().extends.hook_mgr().await.message_nonsubscribed(from).await;
                 None
@@ -1149,6 +1397,10 @@ impl SessionState {
             }
         };
 
+        if let Some(span) = span.as_mut() {
+            span.set_attribute("rmqtt.matched", matched);
+        }
+
         if let Some((msg_id, from, p, expiry_interval)) = stored_msg {
             //Store messages before they expire
             if let Err(e) = Runtime::instance()
@@ -1162,7 +1414,7 @@ impl SessionState {
             }
         }
 
-        Ok(())
+        Ok(matched)
     }
 
     #[inline]
@@ -1331,10 +1583,23 @@ pub struct _Session {
     inner: Arc<dyn SessionLike>,
     pub id: Id,
     pub fitter: FitterType,
-    pub auth_info: Option<AuthInfo>,
+    auth_info: parking_lot::RwLock<Option<Arc<AuthInfo>>>,
     pub extra_attrs: RwLock<ExtraAttrs>,
 }
 
+impl _Session {
+    ///Authentication information, replaced when the client re-authenticates
+    #[inline]
+    pub fn auth_info(&self) -> Option<Arc<AuthInfo>> {
+        self.auth_info.read().clone()
+    }
+
+    #[inline]
+    pub(crate) fn auth_info_set(&self, auth_info: Option<AuthInfo>) {
+        *self.auth_info.write() = auth_info.map(Arc::new);
+    }
+}
+
 impl Deref for _Session {
     type Target = dyn SessionLike;
     #[inline]
@@ -1430,6 +1695,55 @@ impl Session {
                 last_id,
             )
             .await?;
+        let auth_info = parking_lot::RwLock::new(auth_info.map(Arc::new));
+        Ok(Self(Arc::new(_Session { inner: session_like, id, fitter, auth_info, extra_attrs })))
+    }
+
+    ///Session of a client that is not connected, it is not registered anywhere and is only used to
+    ///run the hooks in dry-run mode, see hook::dry_run()
+    pub async fn detached(
+        id: Id,
+        listen_cfg: Listener,
+        conn_info: ConnectInfoType,
+        auth_info: Option<AuthInfo>,
+        superuser: bool,
+    ) -> Result<Self> {
+        let fitter = Runtime::instance().extends.fitter_mgr().await.create(
+            conn_info.clone(),
+            id.clone(),
+            listen_cfg.clone(),
+        );
+        let max_inflight = fitter.max_inflight().get() as usize;
+        let deliver_queue = MessageQueue::new(fitter.max_mqueue_len());
+        let out_inflight = Inflight::new(
+            max_inflight,
+            listen_cfg.message_retry_interval.as_millis() as TimestampMillis,
+            listen_cfg.message_expiry_interval.as_millis() as TimestampMillis,
+        );
+        let now = timestamp_millis();
+        //The sessions counter is decreased when the session is dropped
+        Runtime::instance().stats.sessions.inc();
+        //The default session manager keeps the session in memory only
+        let session_like = DefaultSessionManager::instance()
+            .create(
+                id.clone(),
+                listen_cfg,
+                fitter.clone(),
+                SessionSubs::new(),
+                Arc::new(deliver_queue),
+                Arc::new(RwLock::new(out_inflight)),
+                conn_info,
+                now,
+                now,
+                false,
+                superuser,
+                false,
+                None,
+                None,
+            )
+            .await?;
+        let extra_attrs = RwLock::new(ExtraAttrs::new());
+        let auth_info = parking_lot::RwLock::new(auth_info.map(Arc::new));
         Ok(Self(Arc::new(_Session { inner: session_like, id, fitter, auth_info, extra_attrs })))
     }
 
@@ -1538,6 +1852,8 @@ pub trait SessionLike: Sync + Send {
     fn password(&self) -> Option<&Password>;
     async fn protocol(&self) -> Result<u8>;
     async fn superuser(&self) -> Result<bool>;
+    ///Set when the client re-authenticates
+    async fn superuser_set(&self, superuser: bool) -> Result<()>;
     async fn connected(&self) -> Result<bool>;
     async fn connected_at(&self) -> Result<TimestampMillis>;
 
@@ -7,6 +7,7 @@ use once_cell::sync::OnceCell;
 use crate::broker::executor::{get_active_count, get_rate};
 #[cfg(feature = "debug")]
 use crate::runtime::TaskExecStats;
+use crate::tls::CertInfo;
 use crate::{HashMap, NodeId, Runtime, StatsMergeMode};
 
 type Current = AtomicIsize;
@@ -166,6 +167,8 @@ pub struct Stats {
     pub topics_map: HashMap<NodeId, Counter>,
     pub routes_map: HashMap<NodeId, Counter>,
 
+    pub tls_certs: Vec<CertInfo>,
+
     #[cfg(feature = "debug")]
     debug_client_states_map: HashMap<NodeId, usize>,
     #[cfg(feature = "debug")]
@@ -205,6 +208,8 @@ impl Stats {
             topics_map: HashMap::default(),
             routes_map: HashMap::default(),
 
+            tls_certs: Vec::new(),
+
             #[cfg(feature = "debug")]
             debug_client_states_map: HashMap::default(),
             #[cfg(feature = "debug")]
@@ -298,6 +303,7 @@ impl Stats {
             retaineds,
             topics_map,
             routes_map,
+            tls_certs: crate::tls::cert_infos(),
 
             #[cfg(feature = "debug")]
             debug_client_states_map,
@@ -335,6 +341,7 @@ impl Stats {
 
         self.topics_map.extend(other.topics_map);
         self.routes_map.extend(other.routes_map);
+        self.tls_certs.extend(other.tls_certs);
 
         #[cfg(feature = "debug")]
         {
@@ -0,0 +1,601 @@
+use std::borrow::Cow;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use bytestring::ByteString;
+use once_cell::sync::OnceCell;
+use reqwest::header::{HeaderMap, HeaderName, HeaderValue};
+use tokio::sync::mpsc;
+
+use crate::broker::types::{From, NodeId, Publish, QoSEx};
+use crate::settings::Tracing;
+use crate::{MqttError, Result};
+
+///Name of the W3C trace context header and of the MQTT 5 user property that carries it
+pub const TRACEPARENT: &str = "traceparent";
+
+///W3C trace context, see https://www.w3.org/TR/trace-context/
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TraceContext {
+    pub trace_id: u128,
+    pub span_id: u64,
+    pub sampled: bool,
+}
+
+impl TraceContext {
+    ///Parse a 'traceparent' value, such as "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01"
+    #[inline]
+    pub fn parse(traceparent: &str) -> Option<TraceContext> {
+        let mut parts = traceparent.trim().split('-');
+        let version = parts.next().filter(|v| v.len() == 2)?;
+        let trace_id = parts.next().filter(|v| v.len() == 32)?;
+        let span_id = parts.next().filter(|v| v.len() == 16)?;
+        let flags = parts.next().filter(|v| v.len() == 2)?;
+        //Version 00 has exactly four parts, later versions may append more
+        if version == "ff" || (version == "00" && parts.next().is_some()) {
+            return None;
+        }
+        u8::from_str_radix(version, 16).ok()?;
+        let trace_id = u128::from_str_radix(trace_id, 16).ok().filter(|id| *id != 0)?;
+        let span_id = u64::from_str_radix(span_id, 16).ok().filter(|id| *id != 0)?;
+        let flags = u8::from_str_radix(flags, 16).ok()?;
+        Some(TraceContext { trace_id, span_id, sampled: flags & 0x01 == 0x01 })
+    }
+
+    #[inline]
+    pub fn to_traceparent(&self) -> String {
+        format!("00-{:032x}-{:016x}-{:02x}", self.trace_id, self.span_id, self.sampled as u8)
+    }
+
+    ///The trace context of the 'traceparent' user property of the message
+    #[inline]
+    pub fn from_publish(p: &Publish) -> Option<TraceContext> {
+        p.properties
+            .user_properties
+            .iter()
+            .find(|(name, _)| name.as_ref() == TRACEPARENT)
+            .and_then(|(_, value)| TraceContext::parse(value))
+    }
+
+    ///Set the 'traceparent' user property of the message, replacing the previous one
+    #[inline]
+    pub fn inject(&self, p: &mut Publish) {
+        let user_properties = &mut p.properties.user_properties;
+        user_properties.retain(|(name, _)| name.as_ref() != TRACEPARENT);
+        user_properties.push((ByteString::from_static(TRACEPARENT), ByteString::from(self.to_traceparent())));
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum SpanKind {
+    #[default]
+    Internal = 1,
+    Server = 2,
+    Client = 3,
+    Producer = 4,
+    Consumer = 5,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum AttributeValue {
+    String(String),
+    Int(i64),
+    Bool(bool),
+}
+
+impl std::convert::From<&str> for AttributeValue {
+    fn from(v: &str) -> Self {
+        AttributeValue::String(v.into())
+    }
+}
+
+impl std::convert::From<String> for AttributeValue {
+    fn from(v: String) -> Self {
+        AttributeValue::String(v)
+    }
+}
+
+impl std::convert::From<i64> for AttributeValue {
+    fn from(v: i64) -> Self {
+        AttributeValue::Int(v)
+    }
+}
+
+impl std::convert::From<usize> for AttributeValue {
+    fn from(v: usize) -> Self {
+        AttributeValue::Int(v as i64)
+    }
+}
+
+impl std::convert::From<u64> for AttributeValue {
+    fn from(v: u64) -> Self {
+        AttributeValue::Int(v as i64)
+    }
+}
+
+impl std::convert::From<u8> for AttributeValue {
+    fn from(v: u8) -> Self {
+        AttributeValue::Int(v as i64)
+    }
+}
+
+impl std::convert::From<bool> for AttributeValue {
+    fn from(v: bool) -> Self {
+        AttributeValue::Bool(v)
+    }
+}
+
+impl AttributeValue {
+    #[inline]
+    fn to_otlp_json(&self) -> serde_json::Value {
+        match self {
+            AttributeValue::String(v) => json!({ "stringValue": v }),
+            //int64 values are strings in OTLP/JSON
+            AttributeValue::Int(v) => json!({ "intValue": v.to_string() }),
+            AttributeValue::Bool(v) => json!({ "boolValue": v }),
+        }
+    }
+}
+
+///A finished span
+#[derive(Debug, Clone, Default)]
+pub struct SpanData {
+    pub trace_id: u128,
+    pub span_id: u64,
+    pub parent_span_id: Option<u64>,
+    pub name: Cow<'static, str>,
+    pub kind: SpanKind,
+    //Unix timestamps in nanoseconds
+    pub start_time: u64,
+    pub end_time: u64,
+    pub attributes: Vec<(&'static str, AttributeValue)>,
+    //Error message if the operation failed
+    pub error: Option<String>,
+}
+
+impl SpanData {
+    #[inline]
+    pub fn attribute(&self, key: &str) -> Option<&AttributeValue> {
+        self.attributes.iter().find(|(k, _)| *k == key).map(|(_, v)| v)
+    }
+
+    #[inline]
+    fn to_otlp_json(&self) -> serde_json::Value {
+        let status = match &self.error {
+            Some(e) => json!({ "code": 2, "message": e }),
+            None => json!({ "code": 0 }),
+        };
+        let mut span = json!({
+            "traceId": format!("{:032x}", self.trace_id),
+            "spanId": format!("{:016x}", self.span_id),
+            "name": self.name,
+            "kind": self.kind as u8,
+            "startTimeUnixNano": self.start_time.to_string(),
+            "endTimeUnixNano": self.end_time.to_string(),
+            "attributes": attributes_to_otlp_json(self.attributes.iter().map(|(k, v)| (*k, v))),
+            "status": status,
+        });
+        if let (Some(parent_span_id), Some(obj)) = (self.parent_span_id, span.as_object_mut()) {
+            obj.insert("parentSpanId".into(), json!(format!("{:016x}", parent_span_id)));
+        }
+        span
+    }
+}
+
+///A span in progress, it is ended and queued for export when it is dropped
+pub struct Span {
+    data: SpanData,
+    tracer: &'static Tracer,
+}
+
+impl Span {
+    #[inline]
+    pub fn context(&self) -> TraceContext {
+        TraceContext { trace_id: self.data.trace_id, span_id: self.data.span_id, sampled: true }
+    }
+
+    #[inline]
+    pub fn set_attribute<V: Into<AttributeValue>>(&mut self, key: &'static str, value: V) {
+        self.data.attributes.push((key, value.into()));
+    }
+
+    #[inline]
+    pub fn set_error<E: ToString>(&mut self, e: E) {
+        self.data.error = Some(e.to_string());
+    }
+
+    ///Make this span the parent of the next hops of the message. A message that did not carry a trace
+    ///context gets one only while the spans are exported.
+    #[inline]
+    pub fn inject(&self, p: &mut Publish) {
+        if self.tracer.exporting() || TraceContext::from_publish(p).is_some() {
+            self.context().inject(p)
+        }
+    }
+}
+
+impl Drop for Span {
+    fn drop(&mut self) {
+        let mut data = std::mem::take(&mut self.data);
+        data.end_time = now_nanos();
+        self.tracer.queue(data);
+    }
+}
+
+#[async_trait]
+pub trait SpanExporter: Sync + Send {
+    async fn export(&self, spans: Vec<SpanData>) -> Result<()>;
+}
+
+pub struct Tracer {
+    sample_ratio: f64,
+    tx: mpsc::Sender<SpanData>,
+    dropped: AtomicUsize,
+}
+
+static TRACER: OnceCell<Tracer> = OnceCell::new();
+
+impl Tracer {
+    ///Create a tracer and start the task that exports the spans, must be called in a tokio runtime
+    pub fn new(cfg: &Tracing, exporter: Box<dyn SpanExporter>) -> Self {
+        let (tx, rx) = mpsc::channel(cfg.queue_max.max(1));
+        tokio::spawn(Self::export_loop(rx, exporter, cfg.export_interval, cfg.export_batch_max.max(1)));
+        Self { sample_ratio: cfg.sample_ratio, tx, dropped: AtomicUsize::new(0) }
+    }
+
+    ///Set the global tracer, spans are exported to the OTLP endpoint of the configuration
+    pub(crate) fn init(cfg: &Tracing, node_id: NodeId) -> Result<()> {
+        if !cfg.enable {
+            return Ok(());
+        }
+        let exporter = OtlpHttpExporter::new(cfg, node_id)?;
+        Self::set_global(Tracer::new(cfg, Box::new(exporter)))?;
+        log::info!("tracing enabled, spans are exported to {}", cfg.endpoint);
+        Ok(())
+    }
+
+    #[inline]
+    pub fn set_global(tracer: Tracer) -> Result<()> {
+        TRACER.set(tracer).map_err(|_| MqttError::from("the global tracer is already set"))
+    }
+
+    ///The global tracer, None if tracing is disabled
+    #[inline]
+    pub fn global() -> Option<&'static Tracer> {
+        TRACER.get()
+    }
+
+    ///Start a span, a child of the parent if there is one. None is returned if the trace is not sampled,
+    ///a trace started here is sampled by 'sample_ratio', otherwise the sampled flag of the parent is followed.
+    #[inline]
+    pub fn start_span<N: Into<Cow<'static, str>>>(
+        &'static self,
+        name: N,
+        kind: SpanKind,
+        parent: Option<TraceContext>,
+    ) -> Option<Span> {
+        let (trace_id, parent_span_id) = match parent {
+            Some(parent) if !parent.sampled => return None,
+            Some(parent) => (parent.trace_id, Some(parent.span_id)),
+            None if self.sample_ratio < 1.0 && rand::random::<f64>() >= self.sample_ratio => return None,
+            None => (rand::random::<u128>().max(1), None),
+        };
+        Some(Span {
+            data: SpanData {
+                trace_id,
+                span_id: rand::random::<u64>().max(1),
+                parent_span_id,
+                name: name.into(),
+                kind,
+                start_time: now_nanos(),
+                ..Default::default()
+            },
+            tracer: self,
+        })
+    }
+
+    ///The export task is running, the spans are not exported once it has stopped
+    #[inline]
+    pub fn exporting(&self) -> bool {
+        !self.tx.is_closed()
+    }
+
+    ///Number of spans dropped because the export queue was full
+    #[inline]
+    pub fn dropped(&self) -> usize {
+        self.dropped.load(Ordering::SeqCst)
+    }
+
+    #[inline]
+    fn queue(&self, span: SpanData) {
+        if self.tx.try_send(span).is_err() {
+            self.dropped.fetch_add(1, Ordering::SeqCst);
+        }
+    }
+
+    async fn export_loop(
+        mut rx: mpsc::Receiver<SpanData>,
+        exporter: Box<dyn SpanExporter>,
+        interval: std::time::Duration,
+        batch_max: usize,
+    ) {
+        let mut ticker = tokio::time::interval(interval);
+        let mut batch = Vec::new();
+        loop {
+            let closed = tokio::select! {
+                span = rx.recv() => match span {
+                    Some(span) => {
+                        batch.push(span);
+                        if batch.len() < batch_max {
+                            continue;
+                        }
+                        false
+                    }
+                    None => true,
+                },
+                _ = ticker.tick() => false,
+            };
+            if !batch.is_empty() {
+                if let Err(e) = exporter.export(std::mem::take(&mut batch)).await {
+                    log::warn!("failed to export spans, {:?}", e);
+                }
+            }
+            if closed {
+                break;
+            }
+        }
+    }
+}
+
+///Start a span of the message path with the global tracer, the parent is the 'traceparent' of the message.
+///None is returned if tracing is disabled or the trace is not sampled.
+#[inline]
+pub fn start_publish_span<N: Into<Cow<'static, str>>>(name: N, kind: SpanKind, p: &Publish) -> Option<Span> {
+    let mut span = Tracer::global()?.start_span(name, kind, TraceContext::from_publish(p))?;
+    span.set_attribute("messaging.system", "mqtt");
+    span.set_attribute("messaging.destination.name", p.topic.as_str());
+    span.set_attribute("messaging.mqtt.qos", p.qos.value());
+    span.set_attribute("messaging.message.body.size", p.payload.len());
+    Some(span)
+}
+
+///Start the span of a message forwarded by another node of the cluster, the span becomes the parent of
+///the deliveries on this node. The message is cloned only if it is traced.
+#[inline]
+pub fn start_forwarded_span<'a, N: Into<Cow<'static, str>>>(
+    name: N,
+    from: &From,
+    p: &'a Publish,
+) -> (Cow<'a, Publish>, Option<Span>) {
+    match start_publish_span(name, SpanKind::Consumer, p) {
+        Some(mut span) => {
+            span.set_attribute("rmqtt.from.node", from.node());
+            span.set_attribute("rmqtt.from.clientid", from.client_id.as_str());
+            let mut p = p.clone();
+            span.inject(&mut p);
+            (Cow::Owned(p), Some(span))
+        }
+        None => (Cow::Borrowed(p), None),
+    }
+}
+
+///Exports the spans to an OpenTelemetry collector with OTLP/HTTP, JSON encoding
+pub struct OtlpHttpExporter {
+    client: reqwest::Client,
+    endpoint: String,
+    headers: HeaderMap,
+    resource: Vec<(&'static str, AttributeValue)>,
+}
+
+impl OtlpHttpExporter {
+    pub fn new(cfg: &Tracing, node_id: NodeId) -> Result<Self> {
+        let client =
+            reqwest::Client::builder().timeout(cfg.export_timeout).build().map_err(anyhow::Error::new)?;
+        let mut headers = HeaderMap::new();
+        for (name, value) in cfg.headers.iter() {
+            let name = HeaderName::from_bytes(name.as_bytes()).map_err(|e| MqttError::from(e.to_string()))?;
+            let value = HeaderValue::from_str(value).map_err(|e| MqttError::from(e.to_string()))?;
+            headers.insert(name, value);
+        }
+        let resource = vec![
+            ("service.name", AttributeValue::from(cfg.service_name.as_str())),
+            ("service.instance.id", AttributeValue::from(node_id.to_string())),
+            ("service.version", AttributeValue::from(env!("CARGO_PKG_VERSION"))),
+        ];
+        Ok(Self { client, endpoint: cfg.endpoint.clone(), headers, resource })
+    }
+}
+
+#[async_trait]
+impl SpanExporter for OtlpHttpExporter {
+    async fn export(&self, spans: Vec<SpanData>) -> Result<()> {
+        let body = to_otlp_json(&self.resource, &spans);
+        let resp = self
+            .client
+            .post(&self.endpoint)
+            .headers(self.headers.clone())
+            .json(&body)
+            .send()
+            .await
+            .map_err(anyhow::Error::new)?;
+        if !resp.status().is_success() {
+            return Err(MqttError::from(format!(
+                "OTLP export of {} spans failed, status: {}",
+                spans.len(),
+                resp.status()
+            )));
+        }
+        Ok(())
+    }
+}
+
+///Keeps the exported spans in memory, a stand-in for the collector in tests
+#[derive(Clone, Default)]
+pub struct MemoryExporter {
+    spans: Arc<parking_lot::Mutex<Vec<SpanData>>>,
+}
+
+impl MemoryExporter {
+    #[inline]
+    pub fn spans(&self) -> Vec<SpanData> {
+        self.spans.lock().clone()
+    }
+}
+
+#[async_trait]
+impl SpanExporter for MemoryExporter {
+    async fn export(&self, spans: Vec<SpanData>) -> Result<()> {
+        self.spans.lock().extend(spans);
+        Ok(())
+    }
+}
+
+///Body of an OTLP/HTTP JSON export request
+#[inline]
+pub fn to_otlp_json(resource: &[(&'static str, AttributeValue)], spans: &[SpanData]) -> serde_json::Value {
+    json!({
+        "resourceSpans": [{
+            "resource": {
+                "attributes": attributes_to_otlp_json(resource.iter().map(|(k, v)| (*k, v))),
+            },
+            "scopeSpans": [{
+                "scope": { "name": "rmqtt", "version": env!("CARGO_PKG_VERSION") },
+                "spans": spans.iter().map(|s| s.to_otlp_json()).collect::<Vec<_>>(),
+            }],
+        }],
+    })
+}
+
+#[inline]
+fn attributes_to_otlp_json<'a, I: Iterator<Item = (&'a str, &'a AttributeValue)>>(
+    attrs: I,
+) -> serde_json::Value {
+    serde_json::Value::Array(attrs.map(|(k, v)| json!({ "key": k, "value": v.to_otlp_json() })).collect())
+}
+
+#[inline]
+fn now_nanos() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_nanos() as u64).unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::broker::types::{PublishProperties, QoS, TopicName};
+    use std::time::Duration;
+
+    fn publish(traceparent: Option<&str>) -> Publish {
+        let mut properties = PublishProperties::default();
+        if let Some(tp) = traceparent {
+            properties.user_properties.push((ByteString::from_static(TRACEPARENT), ByteString::from(tp)));
+        }
+        Publish {
+            dup: false,
+            retain: false,
+            qos: QoS::AtLeastOnce,
+            topic: TopicName::from_static("sensor/1/temp"),
+            packet_id: None,
+            payload: ntex::util::Bytes::from_static(b"23.5"),
+            properties,
+            delay_interval: None,
+            create_time: 0,
+        }
+    }
+
+    #[test]
+    fn traceparent() {
+        let tp = "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01";
+        let ctx = TraceContext::parse(tp).unwrap();
+        assert_eq!(ctx.trace_id, 0x4bf92f3577b34da6a3ce929d0e0e4736);
+        assert_eq!(ctx.span_id, 0x00f067aa0ba902b7);
+        assert!(ctx.sampled);
+        assert_eq!(ctx.to_traceparent(), tp);
+
+        assert!(
+            !TraceContext::parse("00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-00").unwrap().sampled
+        );
+        //Later versions may append fields
+        assert!(TraceContext::parse("01-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01-xyz").is_some());
+
+        assert!(TraceContext::parse("00-00000000000000000000000000000000-00f067aa0ba902b7-01").is_none());
+        assert!(TraceContext::parse("00-4bf92f3577b34da6a3ce929d0e0e4736-0000000000000000-01").is_none());
+        assert!(TraceContext::parse("ff-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01").is_none());
+        assert!(TraceContext::parse("00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01-xyz").is_none());
+        assert!(TraceContext::parse("00-4bf92f3577b34da6a3ce929d0e0e47-00f067aa0ba902b7-01").is_none());
+        assert!(TraceContext::parse("").is_none());
+    }
+
+    #[test]
+    fn inject() {
+        let mut p = publish(Some("00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01"));
+        let ctx = TraceContext { trace_id: 1, span_id: 2, sampled: true };
+        ctx.inject(&mut p);
+        assert_eq!(p.properties.user_properties.len(), 1);
+        assert_eq!(TraceContext::from_publish(&p), Some(ctx));
+    }
+
+    #[test]
+    fn inject_if_exporting() {
+        let rt = tokio::runtime::Builder::new_current_thread().enable_all().build().unwrap();
+        let tracer: &'static Tracer = rt.block_on(async {
+            let cfg = Tracing { enable: true, ..Default::default() };
+            Box::leak(Box::new(Tracer::new(&cfg, Box::new(MemoryExporter::default()))))
+        });
+        let span = tracer.start_span("mqtt.publish", SpanKind::Server, None).unwrap();
+        let mut p = publish(None);
+        span.inject(&mut p);
+        assert_eq!(TraceContext::from_publish(&p), Some(span.context()));
+
+        //The export task is dropped with the runtime
+        drop(rt);
+        assert!(!tracer.exporting());
+        let mut p = publish(None);
+        span.inject(&mut p);
+        assert!(p.properties.user_properties.is_empty());
+        //The context the message carried is still continued
+        let mut p = publish(Some("00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01"));
+        span.inject(&mut p);
+        assert_eq!(TraceContext::from_publish(&p), Some(span.context()));
+    }
+
+    #[tokio::test]
+    async fn spans_are_exported() {
+        let exporter = MemoryExporter::default();
+        let cfg = Tracing { enable: true, export_interval: Duration::from_millis(10), ..Default::default() };
+        let tracer: &'static Tracer = Box::leak(Box::new(Tracer::new(&cfg, Box::new(exporter.clone()))));
+
+        let mut p = publish(Some("00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01"));
+        let parent = TraceContext::from_publish(&p);
+        let mut span = tracer.start_span("mqtt.publish", SpanKind::Server, parent).unwrap();
+        span.set_attribute("messaging.client_id", "c1");
+        span.inject(&mut p);
+        let publish_span_id = span.context().span_id;
+        drop(span);
+
+        //The next hop is a child of the publish span
+        let child = tracer.start_span("mqtt.deliver", SpanKind::Producer, TraceContext::from_publish(&p));
+        drop(child);
+
+        //Not sampled by the parent
+        let unsampled = TraceContext::parse("00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-00");
+        assert!(tracer.start_span("mqtt.publish", SpanKind::Server, unsampled).is_none());
+
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        let spans = exporter.spans();
+        assert_eq!(spans.len(), 2);
+        assert_eq!(spans[0].name, "mqtt.publish");
+        assert_eq!(spans[0].trace_id, 0x4bf92f3577b34da6a3ce929d0e0e4736);
+        assert_eq!(spans[0].parent_span_id, Some(0x00f067aa0ba902b7));
+        assert_eq!(spans[0].attribute("messaging.client_id"), Some(&AttributeValue::from("c1")));
+        assert!(spans[0].end_time >= spans[0].start_time);
+        assert_eq!(spans[1].trace_id, spans[0].trace_id);
+        assert_eq!(spans[1].parent_span_id, Some(publish_span_id));
+
+        let body = to_otlp_json(&[("service.name", AttributeValue::from("rmqtt"))], &spans);
+        let span = &body["resourceSpans"][0]["scopeSpans"][0]["spans"][0];
+        assert_eq!(span["traceId"], "4bf92f3577b34da6a3ce929d0e0e4736");
+        assert_eq!(span["parentSpanId"], "00f067aa0ba902b7");
+        assert_eq!(span["kind"], 2);
+    }
+}
@@ -0,0 +1,471 @@
+use std::str::FromStr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+use once_cell::sync::OnceCell;
+use parking_lot::{Mutex, RwLock};
+
+use crate::broker::topic::{Topic, TopicTree};
+use crate::broker::types::{Publish, QoS, TopicFilter, TopicName};
+use crate::{timestamp_millis, DashMap, MqttError, Result, TimestampMillis};
+
+///Maximum number of topic filters that can be monitored
+pub const MAX_TOPIC_METRICS: usize = 512;
+
+///Message metrics of the topic filters that are registered for monitoring at runtime
+pub struct TopicMetrics {
+    items: DashMap<TopicFilter, Arc<TopicMetricsItem>>,
+    tree: RwLock<TopicTree<TopicFilter>>,
+}
+
+impl TopicMetrics {
+    #[inline]
+    pub fn instance() -> &'static TopicMetrics {
+        static INSTANCE: OnceCell<TopicMetrics> = OnceCell::new();
+        INSTANCE.get_or_init(Self::new)
+    }
+
+    #[inline]
+    fn new() -> Self {
+        Self { items: DashMap::default(), tree: RwLock::new(TopicTree::default()) }
+    }
+
+    ///Start monitoring the topic filter, returns false if it is already monitored
+    #[inline]
+    pub fn register(&self, topic_filter: &str) -> Result<bool> {
+        let topic = Topic::from_str(topic_filter)
+            .map_err(|e| MqttError::from(format!("invalid topic filter '{}', {:?}", topic_filter, e)))?;
+        let topic_filter = TopicFilter::from(topic_filter);
+        let mut tree = self.tree.write();
+        if self.items.contains_key(&topic_filter) {
+            return Ok(false);
+        }
+        if self.items.len() >= MAX_TOPIC_METRICS {
+            return Err(MqttError::from(format!(
+                "the number of monitored topic filters has reached the maximum of {}",
+                MAX_TOPIC_METRICS
+            )));
+        }
+        tree.insert(&topic, topic_filter.clone());
+        self.items.insert(topic_filter, Arc::new(TopicMetricsItem::new()));
+        Ok(true)
+    }
+
+    ///Stop monitoring the topic filter, returns false if it is not monitored
+    #[inline]
+    pub fn unregister(&self, topic_filter: &str) -> bool {
+        let mut tree = self.tree.write();
+        if self.items.remove(topic_filter).is_none() {
+            return false;
+        }
+        if let Ok(topic) = Topic::from_str(topic_filter) {
+            tree.remove(&topic, &TopicFilter::from(topic_filter));
+        }
+        true
+    }
+
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.items.is_empty()
+    }
+
+    #[inline]
+    pub fn get(&self, topic_filter: &str) -> Option<TopicMetricsInfo> {
+        self.items.get(topic_filter).map(|item| item.value().to_info(item.key()))
+    }
+
+    ///Metrics of all monitored topic filters, sorted by topic filter
+    #[inline]
+    pub fn list(&self) -> Vec<TopicMetricsInfo> {
+        let mut infos = self.items.iter().map(|item| item.value().to_info(item.key())).collect::<Vec<_>>();
+        infos.sort_by(|a, b| a.topic.cmp(&b.topic));
+        infos
+    }
+
+    ///Recalculate the rates of all monitored topic filters, called periodically by the scheduler
+    #[inline]
+    pub fn update_rates(&self) {
+        let now = timestamp_millis();
+        for item in self.items.iter() {
+            item.value().update_rates(now);
+        }
+    }
+
+    #[inline]
+    pub(crate) fn message_in(&self, p: &Publish) {
+        self.matches(p.topic(), |item| item.messages_in.inc(p));
+    }
+
+    #[inline]
+    pub(crate) fn message_out(&self, p: &Publish) {
+        self.matches(p.topic(), |item| item.messages_out.inc(p));
+    }
+
+    #[inline]
+    pub(crate) fn message_dropped(&self, p: &Publish) {
+        self.matches(p.topic(), |item| item.messages_dropped.inc(p));
+    }
+
+    #[inline]
+    fn matches<F: Fn(&TopicMetricsItem)>(&self, topic: &TopicName, f: F) {
+        if self.items.is_empty() {
+            return;
+        }
+        let topic = match Topic::from_str(topic) {
+            Ok(t) => t,
+            Err(_) => return,
+        };
+        let tree = self.tree.read();
+        for (_, topic_filters) in tree.matches(&topic).iter() {
+            for topic_filter in topic_filters {
+                if let Some(item) = self.items.get(topic_filter) {
+                    f(item.value());
+                }
+            }
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct MessageCounter {
+    qos0: AtomicUsize,
+    qos1: AtomicUsize,
+    qos2: AtomicUsize,
+    bytes: AtomicUsize,
+}
+
+impl MessageCounter {
+    #[inline]
+    fn inc(&self, p: &Publish) {
+        match p.qos() {
+            QoS::AtMostOnce => self.qos0.fetch_add(1, Ordering::SeqCst),
+            QoS::AtLeastOnce => self.qos1.fetch_add(1, Ordering::SeqCst),
+            QoS::ExactlyOnce => self.qos2.fetch_add(1, Ordering::SeqCst),
+        };
+        self.bytes.fetch_add(p.payload.len(), Ordering::SeqCst);
+    }
+
+    #[inline]
+    fn get(&self) -> MessageCount {
+        MessageCount {
+            qos0: self.qos0.load(Ordering::SeqCst),
+            qos1: self.qos1.load(Ordering::SeqCst),
+            qos2: self.qos2.load(Ordering::SeqCst),
+            bytes: self.bytes.load(Ordering::SeqCst),
+        }
+    }
+}
+
+#[derive(Debug)]
+struct TopicMetricsItem {
+    create_time: TimestampMillis,
+    messages_in: MessageCounter,
+    messages_out: MessageCounter,
+    messages_dropped: MessageCounter,
+    //(sample time, counts at the sample time, rates since the previous sample)
+    rates: Mutex<(TimestampMillis, [MessageCount; 3], TopicRates)>,
+}
+
+impl TopicMetricsItem {
+    #[inline]
+    fn new() -> Self {
+        let now = timestamp_millis();
+        Self {
+            create_time: now,
+            messages_in: MessageCounter::default(),
+            messages_out: MessageCounter::default(),
+            messages_dropped: MessageCounter::default(),
+            rates: Mutex::new((now, Default::default(), TopicRates::default())),
+        }
+    }
+
+    #[inline]
+    fn counts(&self) -> [MessageCount; 3] {
+        [self.messages_in.get(), self.messages_out.get(), self.messages_dropped.get()]
+    }
+
+    #[inline]
+    fn update_rates(&self, now: TimestampMillis) {
+        let counts = self.counts();
+        let mut rates = self.rates.lock();
+        let (last_time, last_counts, _) = &*rates;
+        let secs = (now - *last_time) as f64 / 1000.0;
+        if secs <= 0.0 {
+            return;
+        }
+        let rate = |c: usize, last: usize| c.saturating_sub(last) as f64 / secs;
+        let [in_, out, dropped] = &counts;
+        let [last_in, last_out, last_dropped] = last_counts;
+        let new_rates = TopicRates {
+            messages_in: rate(in_.count(), last_in.count()),
+            messages_out: rate(out.count(), last_out.count()),
+            messages_dropped: rate(dropped.count(), last_dropped.count()),
+            bytes_in: rate(in_.bytes, last_in.bytes),
+            bytes_out: rate(out.bytes, last_out.bytes),
+        };
+        *rates = (now, counts, new_rates);
+    }
+
+    #[inline]
+    fn to_info(&self, topic_filter: &TopicFilter) -> TopicMetricsInfo {
+        let [messages_in, messages_out, messages_dropped] = self.counts();
+        TopicMetricsInfo {
+            topic: topic_filter.to_string(),
+            create_time: self.create_time,
+            messages_in,
+            messages_out,
+            messages_dropped,
+            rates: self.rates.lock().2.clone(),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default)]
+pub struct MessageCount {
+    pub qos0: usize,
+    pub qos1: usize,
+    pub qos2: usize,
+    //Payload bytes
+    pub bytes: usize,
+}
+
+impl MessageCount {
+    #[inline]
+    pub fn count(&self) -> usize {
+        self.qos0 + self.qos1 + self.qos2
+    }
+
+    #[inline]
+    pub fn add(&mut self, other: &MessageCount) {
+        self.qos0 += other.qos0;
+        self.qos1 += other.qos1;
+        self.qos2 += other.qos2;
+        self.bytes += other.bytes;
+    }
+}
+
+///Per second rates, calculated by the scheduler every few seconds
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct TopicRates {
+    pub messages_in: f64,
+    pub messages_out: f64,
+    pub messages_dropped: f64,
+    pub bytes_in: f64,
+    pub bytes_out: f64,
+}
+
+impl TopicRates {
+    #[inline]
+    pub fn add(&mut self, other: &TopicRates) {
+        self.messages_in += other.messages_in;
+        self.messages_out += other.messages_out;
+        self.messages_dropped += other.messages_dropped;
+        self.bytes_in += other.bytes_in;
+        self.bytes_out += other.bytes_out;
+    }
+}
+
+///Snapshot of the metrics of a monitored topic filter
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct TopicMetricsInfo {
+    pub topic: String,
+    pub create_time: TimestampMillis,
+    //Messages published to the matching topics
+    pub messages_in: MessageCount,
+    //Messages sent to the subscribers
+    pub messages_out: MessageCount,
+    pub messages_dropped: MessageCount,
+    pub rates: TopicRates,
+}
+
+impl TopicMetricsInfo {
+    ///Merge the metrics of the same topic filter of another node
+    #[inline]
+    pub fn add(&mut self, other: &TopicMetricsInfo) {
+        if self.create_time == 0 || (other.create_time > 0 && other.create_time < self.create_time) {
+            self.create_time = other.create_time;
+        }
+        self.messages_in.add(&other.messages_in);
+        self.messages_out.add(&other.messages_out);
+        self.messages_dropped.add(&other.messages_dropped);
+        self.rates.add(&other.rates);
+    }
+
+    ///Counters, (item, value), such as ("messages.in.qos1", 10)
+    #[inline]
+    pub fn counters(&self) -> Vec<(&'static str, usize)> {
+        vec![
+            ("messages.in", self.messages_in.count()),
+            ("messages.in.qos0", self.messages_in.qos0),
+            ("messages.in.qos1", self.messages_in.qos1),
+            ("messages.in.qos2", self.messages_in.qos2),
+            ("messages.out", self.messages_out.count()),
+            ("messages.out.qos0", self.messages_out.qos0),
+            ("messages.out.qos1", self.messages_out.qos1),
+            ("messages.out.qos2", self.messages_out.qos2),
+            ("messages.dropped", self.messages_dropped.count()),
+            ("messages.dropped.qos0", self.messages_dropped.qos0),
+            ("messages.dropped.qos1", self.messages_dropped.qos1),
+            ("messages.dropped.qos2", self.messages_dropped.qos2),
+            ("bytes.in", self.messages_in.bytes),
+            ("bytes.out", self.messages_out.bytes),
+            ("bytes.dropped", self.messages_dropped.bytes),
+        ]
+    }
+
+    ///Rates, (item, value per second), such as ("messages.in.rate", 1.5)
+    #[inline]
+    pub fn rates(&self) -> Vec<(&'static str, f64)> {
+        vec![
+            ("messages.in.rate", self.rates.messages_in),
+            ("messages.out.rate", self.rates.messages_out),
+            ("messages.dropped.rate", self.rates.messages_dropped),
+            ("bytes.in.rate", self.rates.bytes_in),
+            ("bytes.out.rate", self.rates.bytes_out),
+        ]
+    }
+
+    #[inline]
+    pub fn to_json(&self) -> serde_json::Value {
+        let mut metrics = serde_json::Map::new();
+        for (item, c) in self.counters() {
+            metrics.insert(item.into(), serde_json::Value::from(c));
+        }
+        for (item, r) in self.rates() {
+            metrics.insert(item.into(), serde_json::Value::from((r * 100.0).round() / 100.0));
+        }
+        json!({
+            "topic": self.topic,
+            "create_time": self.create_time,
+            "metrics": metrics,
+        })
+    }
+}
+
+///Merge the metrics of the topic filters of several nodes, sorted by topic filter
+#[inline]
+pub fn merge_topic_metrics<I: IntoIterator<Item = Vec<TopicMetricsInfo>>>(nodes: I) -> Vec<TopicMetricsInfo> {
+    let mut merged: std::collections::BTreeMap<String, TopicMetricsInfo> = Default::default();
+    for infos in nodes {
+        for info in infos {
+            merged
+                .entry(info.topic.clone())
+                .or_insert_with(|| TopicMetricsInfo { topic: info.topic.clone(), ..Default::default() })
+                .add(&info);
+        }
+    }
+    merged.into_values().collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn publish(topic: &str, qos: QoS, payload: &'static [u8]) -> Publish {
+        Publish {
+            dup: false,
+            retain: false,
+            qos,
+            topic: TopicName::from(topic),
+            packet_id: None,
+            payload: payload.into(),
+            properties: Default::default(),
+            delay_interval: None,
+            create_time: 0,
+        }
+    }
+
+    #[test]
+    fn register_limit() {
+        let m = TopicMetrics::new();
+        for i in 0..MAX_TOPIC_METRICS {
+            assert!(m.register(&format!("t/{}", i)).unwrap());
+        }
+        assert!(!m.register("t/0").unwrap());
+        assert!(m.register("t/x").is_err());
+        assert!(m.unregister("t/0"));
+        assert!(m.register("t/x").unwrap());
+        assert_eq!(m.list().len(), MAX_TOPIC_METRICS);
+    }
+
+    #[test]
+    fn unregister() {
+        let m = TopicMetrics::new();
+        assert!(m.register("a/+").unwrap());
+        m.message_in(&publish("a/1", QoS::AtMostOnce, b"1"));
+        assert!(m.unregister("a/+"));
+        assert!(!m.unregister("a/+"));
+        assert!(m.is_empty());
+        assert!(m.get("a/+").is_none());
+
+        //registering again starts from zero and the old tree entry is gone
+        assert!(m.register("a/+").unwrap());
+        m.message_in(&publish("a/1", QoS::AtMostOnce, b"1"));
+        assert_eq!(m.get("a/+").unwrap().messages_in.count(), 1);
+    }
+
+    #[test]
+    fn wildcard_matches() {
+        let m = TopicMetrics::new();
+        m.register("a/#").unwrap();
+        m.register("a/+/c").unwrap();
+        m.register("a/b/c").unwrap();
+        m.register("x/y").unwrap();
+
+        m.message_in(&publish("a/b/c", QoS::AtLeastOnce, b"hello"));
+        m.message_in(&publish("a/d/c", QoS::ExactlyOnce, b"hi"));
+        m.message_out(&publish("a/b", QoS::AtMostOnce, b"abc"));
+        m.message_dropped(&publish("b/c", QoS::AtMostOnce, b"abc"));
+
+        let all = m.get("a/#").unwrap();
+        assert_eq!(all.messages_in.qos1, 1);
+        assert_eq!(all.messages_in.qos2, 1);
+        assert_eq!(all.messages_in.bytes, 7);
+        assert_eq!(all.messages_out.qos0, 1);
+        assert_eq!(all.messages_out.bytes, 3);
+
+        let plus = m.get("a/+/c").unwrap();
+        assert_eq!(plus.messages_in.count(), 2);
+        assert_eq!(plus.messages_out.count(), 0);
+
+        let exact = m.get("a/b/c").unwrap();
+        assert_eq!(exact.messages_in.count(), 1);
+        assert_eq!(exact.messages_in.bytes, 5);
+
+        for info in m.list() {
+            assert_eq!(info.messages_dropped.count(), 0);
+        }
+        assert_eq!(m.get("x/y").unwrap().messages_in.count(), 0);
+    }
+
+    #[test]
+    fn update_rates() {
+        let m = TopicMetrics::new();
+        m.register("a/#").unwrap();
+        for _ in 0..3 {
+            m.message_in(&publish("a/b", QoS::AtLeastOnce, b"0123456789"));
+        }
+        m.message_out(&publish("a/b", QoS::AtLeastOnce, b"0123456789"));
+
+        let item = m.items.get("a/#").unwrap().value().clone();
+        let start = item.rates.lock().0;
+        item.update_rates(start + 2000);
+        let rates = m.get("a/#").unwrap().rates;
+        assert_eq!(rates.messages_in, 1.5);
+        assert_eq!(rates.bytes_in, 15.0);
+        assert_eq!(rates.messages_out, 0.5);
+        assert_eq!(rates.messages_dropped, 0.0);
+
+        //no time has passed, the rates are kept
+        item.update_rates(start + 2000);
+        assert_eq!(m.get("a/#").unwrap().rates.messages_in, 1.5);
+
+        //only the messages since the previous sample are counted
+        m.message_in(&publish("a/b", QoS::AtMostOnce, b"0123456789"));
+        item.update_rates(start + 3000);
+        let rates = m.get("a/#").unwrap().rates;
+        assert_eq!(rates.messages_in, 1.0);
+        assert_eq!(rates.bytes_in, 10.0);
+        assert_eq!(rates.messages_out, 0.0);
+    }
+}
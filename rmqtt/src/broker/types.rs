@@ -47,9 +47,12 @@ use ntex_mqtt::TopicLevel;
 
 use crate::broker::fitter::Fitter;
 use crate::broker::inflight::Inflight;
+use crate::broker::metrics::PacketType;
 use crate::broker::queue::{Queue, Sender};
 use crate::broker::session::OfflineInfo;
+use crate::broker::topic_metrics::TopicMetrics;
 use crate::settings::acl::AuthInfo;
+use crate::tls::PeerCert;
 use crate::{MqttError, Result, Runtime};
 
 pub type NodeId = u64;
@@ -88,6 +91,7 @@ pub type DashMap<K, V> = dashmap::DashMap<K, V, ahash::RandomState>;
 pub type HashMap<K, V> = std::collections::HashMap<K, V, ahash::RandomState>;
 pub type QoS = ntex_mqtt::types::QoS;
 pub type PublishReceiveTime = TimestampMillis;
+pub type DelayedPublishId = u64;
 pub type Subscriptions = Vec<(TopicFilter, SubscriptionOptions)>;
 pub type TopicFilters = Vec<TopicFilter>;
 pub type SubscriptionClientIds = Option<Vec<(ClientId, Option<(TopicFilter, SharedGroup)>)>>;
@@ -150,6 +154,23 @@ impl std::convert::From<Id> for ConnectInfo {
 }
 
 impl ConnectInfo {
+    ///Connect info with the given credentials, such as a sample connection evaluated in dry-run mode.
+    ///MQTT 3.1 is treated as MQTT 3.1.1.
+    #[inline]
+    pub fn with_credentials(
+        id: Id,
+        username: Option<UserName>,
+        password: Option<Password>,
+        proto_ver: u8,
+    ) -> Self {
+        let client_id = id.client_id.clone();
+        if proto_ver == MQTT_LEVEL_5 {
+            ConnectInfo::V5(id, Box::new(ConnectV5 { client_id, username, password, ..Default::default() }))
+        } else {
+            ConnectInfo::V3(id, ConnectV3 { client_id, username, password, ..Default::default() })
+        }
+    }
+
     #[inline]
     pub fn id(&self) -> &Id {
         match self {
@@ -358,6 +379,39 @@ pub enum PublishAclResult {
     Rejected(IsDisconnect),
 }
 
+///The result of an inbound publish, returned to MQTT 5.0 clients in PUBACK/PUBREC
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PublishReply {
+    pub reason_code: PublishAckReason,
+    pub reason_string: Option<ByteString>,
+    pub user_properties: UserProperties,
+}
+
+impl PublishReply {
+    #[inline]
+    pub fn success() -> Self {
+        Self::new(PublishAckReason::Success, None)
+    }
+
+    #[inline]
+    pub fn new(reason_code: PublishAckReason, reason_string: Option<ByteString>) -> Self {
+        Self { reason_code, reason_string, user_properties: UserProperties::default() }
+    }
+
+    ///User properties set by the broker or the hooks, they are not an echo of the message
+    #[inline]
+    pub fn user_properties(mut self, user_properties: UserProperties) -> Self {
+        self.user_properties = user_properties;
+        self
+    }
+
+    ///The message is accepted, even if there are no matching subscribers
+    #[inline]
+    pub fn is_success(&self) -> bool {
+        matches!(self.reason_code, PublishAckReason::Success | PublishAckReason::NoMatchingSubscribers)
+    }
+}
+
 #[derive(Debug, Clone)]
 pub enum AuthResult {
     Allow(Superuser, Option<AuthInfo>),
@@ -367,6 +421,79 @@ pub enum AuthResult {
     NotAuthorized,
 }
 
+pub type AuthMethod = bytestring::ByteString;
+pub type AuthData = Bytes;
+
+///MQTT 5.0 enhanced authentication, the state of an AUTH exchange.
+///
+///The same instance is passed to every step of one exchange, authenticators can keep
+///intermediate data (for example, the SCRAM server nonce) in `attrs`.
+#[derive(Clone)]
+pub struct EnhancedAuth {
+    pub method: AuthMethod,
+    ///true if the exchange was started by the client with an AUTH(ReAuthenticate) packet
+    pub reauth: bool,
+    pub attrs: Arc<parking_lot::RwLock<ExtraAttrs>>,
+}
+
+impl EnhancedAuth {
+    #[inline]
+    pub fn new(method: AuthMethod, reauth: bool) -> Self {
+        Self { method, reauth, attrs: Arc::new(parking_lot::RwLock::new(ExtraAttrs::new())) }
+    }
+}
+
+impl std::fmt::Debug for EnhancedAuth {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "EnhancedAuth {{ method: {}, reauth: {}, attrs: {} }}",
+            self.method,
+            self.reauth,
+            self.attrs.read().len()
+        )
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum EnhancedAuthResult {
+    ///Authentication is complete, the optional data is returned to the client in CONNACK/AUTH,
+    ///the optional username is the identity authenticated by the method
+    Success(Superuser, Option<AuthInfo>, Option<AuthData>, Option<UserName>),
+    ///Authentication continues, the data is returned to the client in AUTH(ContinueAuthentication)
+    Continue(AuthData),
+    ///Authentication failed
+    Failure(ConnectAckReasonV5),
+    ///The authentication method is not supported
+    NotFound,
+}
+
+impl EnhancedAuthResult {
+    #[inline]
+    pub fn disconnect_reason_code(&self) -> DisconnectReasonCode {
+        match self {
+            EnhancedAuthResult::Failure(ConnectAckReasonV5::BadAuthenticationMethod)
+            | EnhancedAuthResult::NotFound => DisconnectReasonCode::BadAuthenticationMethod,
+            _ => DisconnectReasonCode::NotAuthorized,
+        }
+    }
+
+    ///Username of the client after enhanced authentication. A client without a username takes the
+    ///username authenticated by the method, a different username is refused.
+    #[inline]
+    pub fn authenticated_username(
+        username: Option<&UserName>,
+        authenticated: Option<UserName>,
+    ) -> Result<Option<UserName>> {
+        match (username, authenticated) {
+            (Some(username), Some(authenticated)) if *username != authenticated => Err(MqttError::from(
+                format!("username {} is not the authenticated user {}", username, authenticated),
+            )),
+            (username, authenticated) => Ok(authenticated.or_else(|| username.cloned())),
+        }
+    }
+}
+
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub enum MessageExpiryCheckResult {
     Expiry,
@@ -395,6 +522,9 @@ pub type OtherSubRelations = HashMap<NodeId, Vec<TopicFilter>>;
 pub type ClearSubscriptions = bool;
 
 pub type SharedGroupType = (SharedGroup, IsOnline, Vec<ClientId>);
+//A member of a shared subscription group, to be selected by the shared subscription strategy
+pub type SharedSubChoice =
+    (NodeId, ClientId, SubscriptionOptions, Option<Vec<SubscriptionIdentifier>>, Option<IsOnline>);
 
 pub type AllRelationsMap = DashMap<TopicFilter, HashMap<ClientId, (Id, SubscriptionOptions)>>;
 
@@ -1173,11 +1303,27 @@ impl Sink {
             Sink::V3(_) => p.into_v3(),
             Sink::V5(_) => p.into_v5(message_expiry_interval, server_topic_aliases).await,
         };
-        self.send(pkt)
+        self.send(pkt)?;
+        let metrics = &Runtime::instance().metrics;
+        metrics.message_sent(p.qos());
+        metrics
+            .message_latency()
+            .observe(Duration::from_millis((timestamp_millis() - p.create_time).max(0) as u64));
+        TopicMetrics::instance().message_out(p);
+        Ok(())
     }
 
     #[inline]
     pub(crate) fn send(&self, p: Packet) -> Result<()> {
+        let typ = match &p {
+            Packet::V3(PacketV3::Publish(_)) | Packet::V5(PacketV5::Publish(_)) => Some(PacketType::Publish),
+            Packet::V3(PacketV3::PublishRelease { .. }) | Packet::V5(PacketV5::PublishRelease(_)) => {
+                Some(PacketType::Pubrel)
+            }
+            Packet::V5(PacketV5::Disconnect(_)) => Some(PacketType::Disconnect),
+            Packet::V5(PacketV5::Auth(_)) => Some(PacketType::Auth),
+            _ => None,
+        };
         match self {
             Sink::V3(s) => {
                 if let Packet::V3(p) = p {
@@ -1194,6 +1340,9 @@ impl Sink {
                 }
             }
         }
+        if let Some(typ) = typ {
+            Runtime::instance().metrics.packet_sent(typ);
+        }
         Ok(())
     }
 }
@@ -1611,6 +1760,19 @@ impl Id {
         remote_addr: Option<SocketAddr>,
         client_id: ClientId,
         username: Option<UserName>,
+    ) -> Self {
+        Self::with_peer_cert(node_id, local_addr, remote_addr, client_id, username, None)
+    }
+
+    ///Id of a client connected with a TLS client certificate
+    #[inline]
+    pub fn with_peer_cert(
+        node_id: NodeId,
+        local_addr: Option<SocketAddr>,
+        remote_addr: Option<SocketAddr>,
+        client_id: ClientId,
+        username: Option<UserName>,
+        peer_cert: Option<PeerCert>,
     ) -> Self {
         Self(Arc::new(_Id {
             node_id,
@@ -1619,9 +1781,16 @@ impl Id {
             client_id,
             username,
             create_time: timestamp_millis(),
+            peer_cert: peer_cert.map(Arc::new),
         }))
     }
 
+    ///Id of the same connection with another username
+    #[inline]
+    pub fn with_username(&self, username: Option<UserName>) -> Self {
+        Self(Arc::new(_Id { username, ..self.0.as_ref().clone() }))
+    }
+
     #[inline]
     pub fn to_json(&self) -> serde_json::Value {
         json!({
@@ -1774,6 +1943,10 @@ pub struct _Id {
     #[get_size(size_fn = get_option_bytestring_size_helper)]
     pub username: Option<UserName>,
     pub create_time: TimestampMillis,
+    //Client certificate of the TLS connection, only available on the node the client is connected to
+    #[serde(skip)]
+    #[get_size(size_fn = get_option_peer_cert_size_helper)]
+    pub peer_cert: Option<Arc<PeerCert>>,
 }
 
 fn get_bytestring_size_helper(s: &ByteString) -> usize {
@@ -1788,6 +1961,17 @@ fn get_option_bytestring_size_helper(s: &Option<ByteString>) -> usize {
     }
 }
 
+fn get_option_peer_cert_size_helper(s: &Option<Arc<PeerCert>>) -> usize {
+    if let Some(s) = s {
+        size_of_val(s.as_ref())
+            + s.cn.as_ref().map(|s| s.len()).unwrap_or_default()
+            + s.dn.as_ref().map(|s| s.len()).unwrap_or_default()
+            + s.san.as_ref().map(|s| s.len()).unwrap_or_default()
+    } else {
+        0
+    }
+}
+
 fn get_option_addr_size_helper(s: &Option<SocketAddr>) -> usize {
     if let Some(s) = s {
         match s {
@@ -1866,6 +2050,7 @@ pub enum Message {
     Keepalive(IsPing),
     Subscribe(Subscribe, oneshot::Sender<Result<SubscribeReturn>>),
     Unsubscribe(Unsubscribe, oneshot::Sender<Result<()>>),
+    Shutdown(oneshot::Sender<()>, Option<ByteString>),
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -2184,6 +2369,11 @@ impl ExtraAttrs {
         self.attrs.get_mut(key).and_then(|v| v.downcast_mut::<T>())
     }
 
+    #[inline]
+    pub fn remove<T: Any + Sync + Send>(&mut self, key: &str) -> Option<T> {
+        self.attrs.remove(key).and_then(|v| v.downcast::<T>().ok()).map(|v| *v)
+    }
+
     #[inline]
     pub fn get_default_mut<T: Any + Sync + Send, F: Fn() -> T>(
         &mut self,
@@ -2303,6 +2493,7 @@ pub enum Reason {
     Reasons(Vec<Reason>),
     #[default]
     Unknown,
+    ServerShuttingDown,
 }
 
 impl Reason {
@@ -2425,6 +2616,9 @@ impl Display for Reason {
             Reason::Unknown => {
                 "Unknown" //unknown
             }
+            Reason::ServerShuttingDown => {
+                "ServerShuttingDown" //server shutting down
+            }
         };
         write!(f, "{}", r)
     }
@@ -2619,8 +2813,9 @@ pub enum StatsMergeMode {
     Min,     // Represents taking the minimum value of the data;
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct DelayedPublish {
+    pub id: DelayedPublishId,
     pub expired_time: TimestampMillis,
     pub from: From,
     pub publish: Publish,
@@ -2631,6 +2826,7 @@ pub struct DelayedPublish {
 impl DelayedPublish {
     #[inline]
     pub fn new(
+        id: DelayedPublishId,
         from: From,
         publish: Publish,
         message_storage_available: bool,
@@ -2640,13 +2836,28 @@ impl DelayedPublish {
             .delay_interval
             .map(|di| timestamp_millis() + (di as TimestampMillis * 1000))
             .unwrap_or_else(timestamp_millis);
-        Self { expired_time, from, publish, message_storage_available, message_expiry_interval }
+        Self { id, expired_time, from, publish, message_storage_available, message_expiry_interval }
     }
 
     #[inline]
     pub fn is_expired(&self) -> bool {
         timestamp_millis() > self.expired_time
     }
+
+    #[inline]
+    pub fn to_json(&self) -> serde_json::Value {
+        let json = json!({
+            "id": self.id,
+            "expired_at": format_timestamp_millis(self.expired_time),
+            "topic": self.publish.topic,
+            "qos": self.publish.qos.value(),
+            "retain": self.publish.retain,
+            "payload": BASE64_STANDARD.encode(self.publish.payload.as_ref()),
+            "delay_interval": self.publish.delay_interval,
+            "create_time": format_timestamp_millis(self.publish.create_time),
+        });
+        self.from.to_from_json(json)
+    }
 }
 
 impl std::cmp::Eq for DelayedPublish {}
@@ -2723,3 +2934,41 @@ fn test_reason() {
     ]);
     assert_eq!(reasons.to_string(), "PublishRefused,Kicked,MessageExpiration");
 }
+
+#[test]
+fn test_publish_reply() {
+    let user_properties: UserProperties = vec![
+        (ByteString::from_static("k1"), ByteString::from_static("v1")),
+        (ByteString::from_static("k2"), ByteString::from_static("v2")),
+    ];
+    let reply = PublishReply::new(
+        PublishAckReason::NoMatchingSubscribers,
+        Some(ByteString::from_static("no subscribers")),
+    )
+    .user_properties(user_properties.clone());
+    assert!(reply.is_success());
+    assert_eq!(reply.reason_code, PublishAckReason::NoMatchingSubscribers);
+    assert_eq!(reply.reason_string, Some(ByteString::from_static("no subscribers")));
+    assert_eq!(reply.user_properties, user_properties);
+
+    let reply = PublishReply::new(PublishAckReason::NotAuthorized, None);
+    assert!(!reply.is_success());
+    assert!(reply.user_properties.is_empty());
+}
+
+#[test]
+fn test_authenticated_username() {
+    let authenticated = || Some(UserName::from("user"));
+
+    //The username in CONNECT is not the authenticated user
+    let other = UserName::from("admin");
+    assert!(EnhancedAuthResult::authenticated_username(Some(&other), authenticated()).is_err());
+
+    let user = UserName::from("user");
+    assert_eq!(EnhancedAuthResult::authenticated_username(Some(&user), authenticated()).unwrap(), Some(user));
+    assert_eq!(
+        EnhancedAuthResult::authenticated_username(None, authenticated()).unwrap(),
+        Some(UserName::from("user"))
+    );
+    assert_eq!(EnhancedAuthResult::authenticated_username(Some(&other), None).unwrap(), Some(other));
+}
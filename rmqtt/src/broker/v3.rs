@@ -2,14 +2,19 @@ use bytestring::ByteString;
 use std::convert::From as _f;
 use std::net::SocketAddr;
 use std::sync::Arc;
+use std::time::Instant;
 
 use rust_box::task_exec_queue::LocalSpawnExt;
 use uuid::Uuid;
 
-use crate::broker::executor::{get_handshake_exec, is_too_many_unavailable, unavailable_stats};
+use crate::broker::executor::{
+    get_handshake_exec, is_too_many_unavailable, listener_connections, unavailable_stats,
+};
 use crate::broker::{inflight::MomentStatus, types::*};
+use crate::metrics::PacketType;
 use crate::runtime::Runtime;
 use crate::settings::listener::Listener;
+use crate::tls::{PeerCert, PeerCertRefused};
 use crate::{MqttError, Result, Session, SessionState};
 
 #[inline]
@@ -40,10 +45,31 @@ async fn refused_ack<Io>(
 
 #[inline]
 pub async fn handshake<Io: 'static>(
+    listen_cfg: Listener,
+    handshake: v3::Handshake<Io>,
+    remote_addr: SocketAddr,
+    local_addr: SocketAddr,
+    peer_cert: Option<PeerCert>,
+) -> Result<v3::HandshakeAck<Io, SessionState>, MqttError> {
+    let now = Instant::now();
+    let metrics = &Runtime::instance().metrics;
+    metrics.packet_received(PacketType::Connect);
+    let res = handle_connect(listen_cfg, handshake, remote_addr, local_addr, peer_cert).await;
+    //CONNACK is sent unless the connection is closed
+    if res.is_ok() {
+        metrics.packet_sent(PacketType::Connack);
+    }
+    metrics.handshake_duration().observe(now.elapsed());
+    res
+}
+
+#[inline]
+async fn handle_connect<Io: 'static>(
     listen_cfg: Listener,
     mut handshake: v3::Handshake<Io>,
     remote_addr: SocketAddr,
     local_addr: SocketAddr,
+    peer_cert: Option<PeerCert>,
 ) -> Result<v3::HandshakeAck<Io, SessionState>, MqttError> {
     log::debug!(
         "new Connection: local_addr: {:?}, remote: {:?}, {:?}, listen_cfg: {:?}",
@@ -69,6 +95,57 @@ pub async fn handshake<Io: 'static>(
         return Ok(ConnectAckReason::V3(ConnectAckReasonV3::ServiceUnavailable).v3_error_ack(handshake));
     }
 
+    //Reject the service if the listener has reached the maximum number of connections.
+    if listener_connections(local_addr.port()) >= listen_cfg.max_connections as isize {
+        log::warn!(
+            "{:?} Connection Refused, handshake fail, reason: too many connections, max_connections: {}",
+            Id::new(
+                Runtime::instance().node.id(),
+                Some(local_addr),
+                Some(remote_addr),
+                ClientId::default(),
+                handshake.packet().username.clone(),
+            ),
+            listen_cfg.max_connections
+        );
+        return Ok(ConnectAckReason::V3(ConnectAckReasonV3::ServiceUnavailable).v3_error_ack(handshake));
+    }
+
+    //Use the identity of the client certificate as the username or client id, the connection is refused
+    //if there is no client certificate or the configured field is missing
+    match PeerCert::identity(
+        peer_cert.as_ref(),
+        listen_cfg.peer_cert_as_username,
+        listen_cfg.peer_cert_as_clientid,
+    ) {
+        Ok((username, client_id)) => {
+            if let Some(username) = username {
+                handshake.packet_mut().username = Some(username);
+            }
+            if let Some(client_id) = client_id {
+                handshake.packet_mut().client_id = client_id;
+            }
+        }
+        Err(refused) => {
+            log::info!(
+                "{:?} Connection Refused, handshake fail, reason: {:?} is missing in the client certificate",
+                Id::new(
+                    Runtime::instance().node.id(),
+                    Some(local_addr),
+                    Some(remote_addr),
+                    handshake.packet().client_id.clone(),
+                    handshake.packet().username.clone(),
+                ),
+                refused
+            );
+            let ack_code = match refused {
+                PeerCertRefused::Username(_) => ConnectAckReasonV3::BadUserNameOrPassword,
+                PeerCertRefused::ClientId(_) => ConnectAckReasonV3::IdentifierRejected,
+            };
+            return Ok(ConnectAckReason::V3(ack_code).v3_error_ack(handshake));
+        }
+    }
+
     if handshake.packet().client_id.is_empty() {
         if handshake.packet().clean_session {
             handshake.packet_mut().client_id =
@@ -88,12 +165,13 @@ pub async fn handshake<Io: 'static>(
         }
     }
 
-    let id = Id::new(
+    let id = Id::with_peer_cert(
         Runtime::instance().node.id(),
         Some(local_addr),
         Some(remote_addr),
         handshake.packet().client_id.clone(),
         handshake.packet().username.clone(),
+        peer_cert,
     );
 
     Runtime::instance().stats.handshakings.max_max(handshake.handshakings());
@@ -130,7 +208,10 @@ async fn _handshake<Io: 'static>(
     let connect_info = Arc::new(ConnectInfo::V3(id.clone(), handshake.packet().clone()));
 
     //hook, client connect
-    let _ = Runtime::instance().extends.hook_mgr().await.client_connect(&connect_info).await;
+    if let Err(ack) = Runtime::instance().extends.hook_mgr().await.client_connect(&connect_info).await {
+        let ack = if let ConnectAckReason::V3(ack) = ack { ack } else { ConnectAckReasonV3::NotAuthorized };
+        return Ok(refused_ack(handshake, &connect_info, ack, "Connection refused by hook".into()).await);
+    }
 
     if listen_cfg.max_clientid_len > 0 && id.client_id.len() > listen_cfg.max_clientid_len {
         return Ok(refused_ack(
@@ -330,6 +411,7 @@ async fn subscribes(
             sub.fail()
         }
     }
+    Runtime::instance().metrics.packet_sent(PacketType::Suback);
     Ok(subs.ack())
 }
 
@@ -344,6 +426,7 @@ async fn unsubscribes(
         let unsub = Unsubscribe::from(topic_filter, shared_subscription, limit_subscription)?;
         state.unsubscribe(unsub).await?;
     }
+    Runtime::instance().metrics.packet_sent(PacketType::Unsuback);
     Ok(unsubs.ack())
 }
 
@@ -354,9 +437,11 @@ pub async fn control_message(
 ) -> Result<v3::ControlResult, MqttError> {
     log::debug!("{:?} incoming control message -> {:?}", state.id, ctrl_msg);
 
+    let metrics = &Runtime::instance().metrics;
     let crs = match ctrl_msg {
         v3::ControlMessage::Subscribe(subs) => {
             let _ = state.send(Message::Keepalive(false));
+            metrics.packet_received(PacketType::Subscribe);
             match subscribes(&state, subs).await {
                 Err(e) => {
                     log::warn!("{:?} Subscribe failed, reason: {}", state.id, e);
@@ -372,6 +457,7 @@ pub async fn control_message(
         }
         v3::ControlMessage::Unsubscribe(unsubs) => {
             let _ = state.send(Message::Keepalive(false));
+            metrics.packet_received(PacketType::Unsubscribe);
             match unsubscribes(&state, unsubs).await {
                 Err(e) => {
                     log::warn!("{:?} Unsubscribe failed, reason: {}", state.id, e);
@@ -387,10 +473,13 @@ pub async fn control_message(
         }
         v3::ControlMessage::Ping(ping) => {
             let _ = state.send(Message::Keepalive(true));
+            metrics.packet_received(PacketType::Pingreq);
+            metrics.packet_sent(PacketType::Pingresp);
             ping.ack()
         }
         v3::ControlMessage::Disconnect(disc) => {
             //let _ = state.send(Message::Keepalive(false));
+            metrics.packet_received(PacketType::Disconnect);
             state.send(Message::Disconnect(Disconnect::V3))?;
             disc.ack()
         }
@@ -436,15 +525,18 @@ pub async fn publish(state: v3::Session<SessionState>, pub_msg: v3::PublishMessa
             }
         }
         v3::PublishMessage::PublishAck(packet_id) => {
+            Runtime::instance().metrics.packet_received(PacketType::Puback);
             if let Some(iflt_msg) = state.inflight_win().write().await.remove(&packet_id.get()) {
                 //hook, message_ack
                 state.hook.message_acked(iflt_msg.from, &iflt_msg.publish).await;
             }
         }
         v3::PublishMessage::PublishReceived(packet_id) => {
+            Runtime::instance().metrics.packet_received(PacketType::Pubrec);
             state.inflight_win().write().await.update_status(&packet_id.get(), MomentStatus::UnComplete);
         }
         v3::PublishMessage::PublishComplete(packet_id) => {
+            Runtime::instance().metrics.packet_received(PacketType::Pubcomp);
             if let Some(iflt_msg) = state.inflight_win().write().await.remove(&packet_id.get()) {
                 //hook, message_ack
                 state.hook.message_acked(iflt_msg.from, &iflt_msg.publish).await;
@@ -2,16 +2,24 @@ use bytestring::ByteString;
 use std::convert::From as _f;
 use std::net::SocketAddr;
 use std::sync::Arc;
+use std::time::Instant;
 
-use ntex_mqtt::v5::codec::{Auth, PublishAckReason};
+use ntex::codec::{AsyncRead, AsyncWrite, Decoder, Encoder};
+use ntex::util::BytesMut;
+use ntex_mqtt::v5::codec::{Auth, AuthReasonCode, Codec, PublishAckReason};
 use ntex_mqtt::v5::PublishAck;
 use ntex_mqtt::v5::PublishResult;
 use rust_box::task_exec_queue::LocalSpawnExt;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
 use uuid::Uuid;
 
-use crate::broker::executor::{get_handshake_exec, is_too_many_unavailable, unavailable_stats};
+use crate::broker::executor::{
+    get_handshake_exec, is_too_many_unavailable, listener_connections, unavailable_stats,
+};
 use crate::broker::{inflight::MomentStatus, types::*};
+use crate::metrics::PacketType;
 use crate::settings::listener::Listener;
+use crate::tls::{PeerCert, PeerCertRefused};
 use crate::{MqttError, Result, Runtime, Session, SessionState};
 
 #[inline]
@@ -41,11 +49,32 @@ async fn refused_ack<Io>(
 }
 
 #[inline]
-pub async fn handshake<Io: 'static>(
+pub async fn handshake<Io: AsyncRead + AsyncWrite + Unpin + 'static>(
+    listen_cfg: Listener,
+    handshake: v5::Handshake<Io>,
+    remote_addr: SocketAddr,
+    local_addr: SocketAddr,
+    peer_cert: Option<PeerCert>,
+) -> Result<v5::HandshakeAck<Io, SessionState>, MqttError> {
+    let now = Instant::now();
+    let metrics = &Runtime::instance().metrics;
+    metrics.packet_received(PacketType::Connect);
+    let res = handle_connect(listen_cfg, handshake, remote_addr, local_addr, peer_cert).await;
+    //CONNACK is sent unless the connection is closed
+    if res.is_ok() {
+        metrics.packet_sent(PacketType::Connack);
+    }
+    metrics.handshake_duration().observe(now.elapsed());
+    res
+}
+
+#[inline]
+async fn handle_connect<Io: AsyncRead + AsyncWrite + Unpin + 'static>(
     listen_cfg: Listener,
     mut handshake: v5::Handshake<Io>,
     remote_addr: SocketAddr,
     local_addr: SocketAddr,
+    peer_cert: Option<PeerCert>,
 ) -> Result<v5::HandshakeAck<Io, SessionState>, MqttError> {
     log::debug!(
         "new Connection: local_addr: {:?}, remote: {:?}, {:?}, listen_cfg: {:?}",
@@ -71,6 +100,57 @@ pub async fn handshake<Io: 'static>(
         return Ok(ConnectAckReason::V5(ConnectAckReasonV5::ServerUnavailable).v5_error_ack(handshake));
     }
 
+    //Reject the service if the listener has reached the maximum number of connections.
+    if listener_connections(local_addr.port()) >= listen_cfg.max_connections as isize {
+        log::warn!(
+            "{:?} Connection Refused, handshake fail, reason: too many connections, max_connections: {}",
+            Id::new(
+                Runtime::instance().node.id(),
+                Some(local_addr),
+                Some(remote_addr),
+                ClientId::default(),
+                handshake.packet().username.clone(),
+            ),
+            listen_cfg.max_connections
+        );
+        return Ok(ConnectAckReason::V5(ConnectAckReasonV5::ServerUnavailable).v5_error_ack(handshake));
+    }
+
+    //Use the identity of the client certificate as the username or client id, the connection is refused
+    //if there is no client certificate or the configured field is missing
+    match PeerCert::identity(
+        peer_cert.as_ref(),
+        listen_cfg.peer_cert_as_username,
+        listen_cfg.peer_cert_as_clientid,
+    ) {
+        Ok((username, client_id)) => {
+            if let Some(username) = username {
+                handshake.packet_mut().username = Some(username);
+            }
+            if let Some(client_id) = client_id {
+                handshake.packet_mut().client_id = client_id;
+            }
+        }
+        Err(refused) => {
+            log::info!(
+                "{:?} Connection Refused, handshake fail, reason: {:?} is missing in the client certificate",
+                Id::new(
+                    Runtime::instance().node.id(),
+                    Some(local_addr),
+                    Some(remote_addr),
+                    handshake.packet().client_id.clone(),
+                    handshake.packet().username.clone(),
+                ),
+                refused
+            );
+            let ack_code = match refused {
+                PeerCertRefused::Username(_) => ConnectAckReasonV5::BadUserNameOrPassword,
+                PeerCertRefused::ClientId(_) => ConnectAckReasonV5::ClientIdentifierNotValid,
+            };
+            return Ok(ConnectAckReason::V5(ack_code).v5_error_ack(handshake));
+        }
+    }
+
     let assigned_client_id = if handshake.packet().client_id.is_empty() {
         handshake.packet_mut().client_id =
             ClientId::from(Uuid::new_v4().as_simple().encode_lower(&mut Uuid::encode_buffer()).to_owned());
@@ -79,12 +159,13 @@ pub async fn handshake<Io: 'static>(
         false
     };
 
-    let id = Id::new(
+    let id = Id::with_peer_cert(
         Runtime::instance().node.id(),
         Some(local_addr),
         Some(remote_addr),
         handshake.packet().client_id.clone(),
         handshake.packet().username.clone(),
+        peer_cert,
     );
 
     Runtime::instance().stats.handshakings.max_max(handshake.handshakings());
@@ -108,16 +189,23 @@ pub async fn handshake<Io: 'static>(
 }
 
 #[inline]
-pub async fn _handshake<Io: 'static>(
-    id: Id,
+pub async fn _handshake<Io: AsyncRead + AsyncWrite + Unpin + 'static>(
+    mut id: Id,
     listen_cfg: Listener,
     mut handshake: v5::Handshake<Io>,
     is_assigned_client_id: bool,
 ) -> Result<v5::HandshakeAck<Io, SessionState>, MqttError> {
-    let connect_info = Arc::new(ConnectInfo::V5(id.clone(), Box::new(handshake.packet().clone())));
+    let mut connect_info = Arc::new(ConnectInfo::V5(id.clone(), Box::new(handshake.packet().clone())));
     log::debug!("handshake.packet(): {:?}", handshake.packet());
     //hook, client connect
-    let _user_props = Runtime::instance().extends.hook_mgr().await.client_connect(&connect_info).await;
+    let _user_props = match Runtime::instance().extends.hook_mgr().await.client_connect(&connect_info).await {
+        Ok(user_props) => user_props,
+        Err(ack) => {
+            let ack =
+                if let ConnectAckReason::V5(ack) = ack { ack } else { ConnectAckReasonV5::NotAuthorized };
+            return Ok(refused_ack(handshake, &connect_info, ack, "Connection refused by hook".into()).await);
+        }
+    };
 
     if listen_cfg.max_clientid_len > 0 && id.client_id.len() > listen_cfg.max_clientid_len {
         return Ok(refused_ack(
@@ -129,17 +217,6 @@ pub async fn _handshake<Io: 'static>(
         .await);
     }
 
-    //Extended Auth is not supported
-    if handshake.packet().auth_method.is_some() {
-        return Ok(refused_ack(
-            handshake,
-            &connect_info,
-            ConnectAckReasonV5::BadAuthenticationMethod,
-            "extended Auth is not supported".into(),
-        )
-        .await);
-    }
-
     let entry = Runtime::instance().extends.shared().await.entry(id.clone());
     let max_sessions = Runtime::instance().settings.mqtt.max_sessions;
     if max_sessions > 0 && Runtime::instance().stats.sessions.count() >= max_sessions && !entry.exist() {
@@ -151,20 +228,78 @@ pub async fn _handshake<Io: 'static>(
                 ).await);
     }
 
-    //hook, client authenticate
-    let (ack, superuser, auth_info) = Runtime::instance()
-        .extends
-        .hook_mgr()
-        .await
-        .client_authenticate(&connect_info, listen_cfg.allow_anonymous)
-        .await;
-    if !ack.success() {
-        if let ConnectAckReason::V5(ack) = ack {
-            return Ok(refused_ack(handshake, &connect_info, ack, "Authentication failed".into()).await);
-        } else {
-            unreachable!()
+    let auth_method = handshake.packet().auth_method.clone();
+    let (superuser, auth_info, auth_data) = if let Some(auth_method) = auth_method {
+        //hook, client enhanced authenticate
+        match enhanced_authenticate(&mut handshake, &connect_info, auth_method, &listen_cfg).await {
+            Ok(EnhancedAuthResult::Success(superuser, auth_info, auth_data, username)) => {
+                //The session takes the identity authenticated by the method
+                match EnhancedAuthResult::authenticated_username(id.username.as_ref(), username) {
+                    Ok(username) if username != id.username => {
+                        handshake.packet_mut().username = username.clone();
+                        id = id.with_username(username);
+                        connect_info =
+                            Arc::new(ConnectInfo::V5(id.clone(), Box::new(handshake.packet().clone())));
+                    }
+                    Ok(_) => {}
+                    Err(e) => {
+                        return Ok(refused_ack(
+                            handshake,
+                            &connect_info,
+                            ConnectAckReasonV5::NotAuthorized,
+                            format!("Enhanced authentication failed, {}", e),
+                        )
+                        .await);
+                    }
+                }
+                (superuser, auth_info, auth_data)
+            }
+            Ok(EnhancedAuthResult::Failure(ack)) => {
+                return Ok(refused_ack(
+                    handshake,
+                    &connect_info,
+                    ack,
+                    "Enhanced authentication failed".into(),
+                )
+                .await);
+            }
+            Ok(EnhancedAuthResult::NotFound) => {
+                return Ok(refused_ack(
+                    handshake,
+                    &connect_info,
+                    ConnectAckReasonV5::BadAuthenticationMethod,
+                    "authentication method is not supported".into(),
+                )
+                .await);
+            }
+            Ok(EnhancedAuthResult::Continue(_)) => unreachable!(),
+            Err(e) => {
+                return Ok(refused_ack(
+                    handshake,
+                    &connect_info,
+                    ConnectAckReasonV5::NotAuthorized,
+                    format!("Enhanced authentication failed, {}", e),
+                )
+                .await);
+            }
         }
-    }
+    } else {
+        //hook, client authenticate
+        let (ack, superuser, auth_info) = Runtime::instance()
+            .extends
+            .hook_mgr()
+            .await
+            .client_authenticate(&connect_info, listen_cfg.allow_anonymous)
+            .await;
+        if !ack.success() {
+            if let ConnectAckReason::V5(ack) = ack {
+                return Ok(refused_ack(handshake, &connect_info, ack, "Authentication failed".into()).await);
+            } else {
+                unreachable!()
+            }
+        }
+        (superuser, auth_info, None)
+    };
 
     let sink = handshake.sink();
     let packet = handshake.packet_mut();
@@ -329,6 +464,7 @@ pub async fn _handshake<Io: 'static>(
     let shared_subscription_available =
         Runtime::instance().extends.shared_subscription().await.is_supported(state.listen_cfg());
     let assigned_client_id = if is_assigned_client_id { Some(state.id.client_id.clone()) } else { None };
+    let auth_method = packet.auth_method.clone();
     Ok(handshake.ack(state).keep_alive(keep_alive).with(|ack: &mut v5::codec::ConnectAck| {
         ack.session_present = session_present;
         ack.server_keepalive_sec = Some(server_keepalive_sec);
@@ -342,10 +478,178 @@ pub async fn _handshake<Io: 'static>(
         ack.wildcard_subscription_available = Some(true);
         ack.subscription_identifiers_available = Some(true);
         ack.shared_subscription_available = Some(shared_subscription_available);
+        ack.auth_method = auth_method;
+        ack.auth_data = auth_data;
         log::debug!("{:?} handshake.ack: {:?}", id, ack);
     }))
 }
 
+///MQTT 5.0 enhanced authentication, AUTH packets are exchanged with the client before CONNACK.
+///
+///The client must not send any packet other than AUTH or DISCONNECT until it has received
+///CONNACK, so the exchange is done directly on the connection.
+async fn enhanced_authenticate<Io: AsyncRead + AsyncWrite + Unpin>(
+    handshake: &mut v5::Handshake<Io>,
+    connect_info: &ConnectInfo,
+    auth_method: AuthMethod,
+    listen_cfg: &Listener,
+) -> Result<EnhancedAuthResult> {
+    let auth = EnhancedAuth::new(auth_method.clone(), false);
+    let codec = Codec::new().max_inbound_size(listen_cfg.max_packet_size.as_u32());
+    let timeout = listen_cfg.handshake_timeout;
+    let mut auth_data = handshake.packet().auth_data.clone();
+    let mut buf = BytesMut::new();
+    loop {
+        let result = Runtime::instance()
+            .extends
+            .hook_mgr()
+            .await
+            .client_enhanced_authenticate(connect_info, &auth, auth_data.as_ref())
+            .await;
+        let data = match result {
+            EnhancedAuthResult::Continue(data) => data,
+            _ => return Ok(result),
+        };
+
+        let mut out = BytesMut::new();
+        codec
+            .encode(
+                PacketV5::Auth(Auth {
+                    reason_code: AuthReasonCode::ContinueAuthentication,
+                    auth_method: Some(auth_method.clone()),
+                    auth_data: Some(data),
+                    ..Default::default()
+                }),
+                &mut out,
+            )
+            .map_err(|e| MqttError::from(format!("{:?}", e)))?;
+        handshake.io().write_all(&out).await?;
+        handshake.io().flush().await?;
+        Runtime::instance().metrics.packet_sent(PacketType::Auth);
+
+        let packet = tokio::time::timeout(timeout, read_packet(handshake.io(), &codec, &mut buf))
+            .await
+            .map_err(|_| MqttError::Timeout(timeout))??;
+        log::debug!("{:?} enhanced authentication, packet: {:?}", connect_info.id(), packet);
+        match packet {
+            PacketV5::Auth(_) => Runtime::instance().metrics.packet_received(PacketType::Auth),
+            PacketV5::Disconnect(_) => Runtime::instance().metrics.packet_received(PacketType::Disconnect),
+            _ => {}
+        }
+        match packet {
+            PacketV5::Auth(a) if a.reason_code == AuthReasonCode::ContinueAuthentication => {
+                if a.auth_method.as_ref() != Some(&auth_method) {
+                    return Ok(EnhancedAuthResult::Failure(ConnectAckReasonV5::BadAuthenticationMethod));
+                }
+                auth_data = a.auth_data;
+            }
+            PacketV5::Disconnect(_) => {
+                return Err(MqttError::from("DISCONNECT received during enhanced authentication"))
+            }
+            p => {
+                return Err(MqttError::from(format!(
+                    "unexpected packet during enhanced authentication, {:?}",
+                    p
+                )))
+            }
+        }
+    }
+}
+
+#[inline]
+async fn read_packet<Io: AsyncRead + Unpin>(
+    io: &mut Io,
+    codec: &Codec,
+    buf: &mut BytesMut,
+) -> Result<PacketV5> {
+    let mut chunk = [0u8; 1024];
+    loop {
+        if let Some((packet, _)) = codec.decode(buf).map_err(|e| MqttError::from(format!("{:?}", e)))? {
+            return Ok(packet);
+        }
+        let n = io.read(&mut chunk).await?;
+        if n == 0 {
+            return Err(MqttError::from("connection closed during enhanced authentication"));
+        }
+        buf.extend_from_slice(&chunk[..n]);
+    }
+}
+
+const ENHANCED_AUTH_KEY: &str = "enhanced-auth";
+
+///MQTT 5.0 re-authentication, AUTH packet received after the connection is established
+async fn reauthenticate(
+    state: &v5::Session<SessionState>,
+    auth: &Auth,
+) -> std::result::Result<Auth, (DisconnectReasonCode, MqttError)> {
+    let connect_info = state.connect_info().await.map_err(|e| (DisconnectReasonCode::UnspecifiedError, e))?;
+    let auth_method = if let ConnectInfo::V5(_, connect) = connect_info.as_ref() {
+        connect.auth_method.clone()
+    } else {
+        None
+    };
+    //The authentication method must be the same as the one in CONNECT
+    let auth_method = match auth_method {
+        Some(auth_method) if auth.auth_method.as_ref() == Some(&auth_method) => auth_method,
+        _ => {
+            return Err((
+                DisconnectReasonCode::BadAuthenticationMethod,
+                MqttError::from("re-authentication, bad authentication method"),
+            ))
+        }
+    };
+
+    let enhanced_auth = match auth.reason_code {
+        AuthReasonCode::ReAuthenticate => {
+            let enhanced_auth = EnhancedAuth::new(auth_method.clone(), true);
+            state.extra_attrs.write().await.insert(ENHANCED_AUTH_KEY.into(), enhanced_auth.clone());
+            enhanced_auth
+        }
+        AuthReasonCode::ContinueAuthentication => state
+            .extra_attrs
+            .read()
+            .await
+            .get::<EnhancedAuth>(ENHANCED_AUTH_KEY)
+            .cloned()
+            .ok_or_else(|| {
+                (DisconnectReasonCode::ProtocolError, MqttError::from("re-authentication has not started"))
+            })?,
+        AuthReasonCode::Success => {
+            return Err((
+                DisconnectReasonCode::ProtocolError,
+                MqttError::from("re-authentication, unexpected reason code"),
+            ))
+        }
+    };
+
+    //hook, client reauthenticate
+    let result = state.hook.client_reauthenticate(&enhanced_auth, auth.auth_data.as_ref()).await;
+    log::debug!("{:?} re-authentication result: {:?}", state.id, result);
+    let (reason_code, auth_data) = match result {
+        EnhancedAuthResult::Continue(data) => (AuthReasonCode::ContinueAuthentication, Some(data)),
+        EnhancedAuthResult::Success(superuser, auth_info, data, username) => {
+            state.extra_attrs.write().await.remove::<EnhancedAuth>(ENHANCED_AUTH_KEY);
+            //Re-authentication can not change the identity of the session
+            if username.is_some() && username != state.id.username {
+                return Err((
+                    DisconnectReasonCode::NotAuthorized,
+                    MqttError::from(
+                        "re-authentication, the authenticated user is not the user of the session",
+                    ),
+                ));
+            }
+            state.superuser_set(superuser).await.map_err(|e| (DisconnectReasonCode::UnspecifiedError, e))?;
+            state.auth_info_set(auth_info);
+            (AuthReasonCode::Success, data)
+        }
+        EnhancedAuthResult::Failure(_) | EnhancedAuthResult::NotFound => {
+            state.extra_attrs.write().await.remove::<EnhancedAuth>(ENHANCED_AUTH_KEY);
+            return Err((result.disconnect_reason_code(), MqttError::from("re-authentication failed")));
+        }
+    };
+    Ok(Auth { reason_code, auth_method: Some(auth_method), auth_data, ..Default::default() })
+}
+
 async fn subscribes(
     state: &v5::Session<SessionState>,
     mut subs: v5::control::Subscribe,
@@ -364,6 +668,7 @@ async fn subscribes(
             sub.fail(sub_ret.into_inner())
         }
     }
+    Runtime::instance().metrics.packet_sent(PacketType::Suback);
     Ok(subs.ack())
 }
 
@@ -378,6 +683,7 @@ async fn unsubscribes(
         let unsub = Unsubscribe::from(topic_filter, shared_subscription, limit_subscription)?;
         state.unsubscribe(unsub).await?;
     }
+    Runtime::instance().metrics.packet_sent(PacketType::Unsuback);
     Ok(unsubs.ack())
 }
 
@@ -387,17 +693,44 @@ pub async fn control_message<E: std::fmt::Debug>(
 ) -> Result<v5::ControlResult, MqttError> {
     log::debug!("{:?} incoming control message -> {:?}", state.id, ctrl_msg);
 
+    let metrics = &Runtime::instance().metrics;
     let crs = match ctrl_msg {
         v5::ControlMessage::Auth(auth) => {
             let _ = state.send(Message::Keepalive(false));
-            auth.ack(Auth::default())
+            metrics.packet_received(PacketType::Auth);
+            match reauthenticate(&state, auth.packet()).await {
+                Err((reason_code, e)) => {
+                    log::warn!("{:?} Re-authentication failed, reason: {}", state.id, e);
+                    if let Some(sink) = state.sink.as_ref() {
+                        let disconnect = DisconnectV5 {
+                            reason_code,
+                            reason_string: Some(ByteString::from(e.to_string())),
+                            ..Default::default()
+                        };
+                        if let Err(e) = sink.send(Packet::V5(PacketV5::Disconnect(disconnect))) {
+                            log::debug!("{:?} send disconnect error, {:?}", state.id, e);
+                        }
+                    }
+                    state
+                        .disconnected_reason_add(Reason::ProtocolError(ByteString::from(e.to_string())))
+                        .await?;
+                    return Err(e);
+                }
+                Ok(ack) => {
+                    metrics.packet_sent(PacketType::Auth);
+                    auth.ack(ack)
+                }
+            }
         }
         v5::ControlMessage::Ping(ping) => {
             let _ = state.send(Message::Keepalive(true));
+            metrics.packet_received(PacketType::Pingreq);
+            metrics.packet_sent(PacketType::Pingresp);
             ping.ack()
         }
         v5::ControlMessage::Subscribe(subs) => {
             let _ = state.send(Message::Keepalive(false));
+            metrics.packet_received(PacketType::Subscribe);
             match subscribes(&state, subs).await {
                 Err(e) => {
                     log::warn!("{:?} Subscribe failed, reason: {}", state.id, e);
@@ -413,6 +746,7 @@ pub async fn control_message<E: std::fmt::Debug>(
         }
         v5::ControlMessage::Unsubscribe(unsubs) => {
             let _ = state.send(Message::Keepalive(false));
+            metrics.packet_received(PacketType::Unsubscribe);
             match unsubscribes(&state, unsubs).await {
                 Err(e) => {
                     log::warn!("{:?} Unsubscribe failed, reason: {}", state.id, e);
@@ -428,6 +762,7 @@ pub async fn control_message<E: std::fmt::Debug>(
         }
         v5::ControlMessage::Disconnect(disconnect) => {
             //disconnect.packet().user_properties
+            metrics.packet_received(PacketType::Disconnect);
             state.send(Message::Disconnect(Disconnect::V5(disconnect.packet().clone())))?;
             disconnect.ack()
         }
@@ -443,7 +778,14 @@ pub async fn control_message<E: std::fmt::Debug>(
             {
                 log::debug!("{:?} Closed error, reason: {:?}", state.id, e);
             }
-            err.ack(DisconnectReasonCode::ServerBusy)
+            let reason_code = match err.get_err() {
+                MqttError::PublishAckReason(PublishAckReason::QuotaExceeded, _) => {
+                    DisconnectReasonCode::QuotaExceeded
+                }
+                _ => DisconnectReasonCode::ServerBusy,
+            };
+            metrics.packet_sent(PacketType::Disconnect);
+            err.ack(reason_code)
         }
         v5::ControlMessage::ProtocolError(protocol_error) => {
             if let Err(e) = state.send(Message::Closed(Reason::ProtocolError(ByteString::from(format!(
@@ -471,38 +813,47 @@ pub async fn publish(
     match pub_msg {
         v5::PublishMessage::Publish(publish) => {
             let publish_fut = async move {
-                if let Err(e) = state.publish_v5(&publish).await {
-                    log::warn!(
-                        "{:?} Publish failed, reason: {:?}",
-                        state.id,
-                        state.disconnected_reason().await
-                    );
-                    Err(e)
-                } else {
-                    Ok(())
+                match state.publish_v5(&publish).await {
+                    Err(e) => {
+                        log::warn!(
+                            "{:?} Publish failed, reason: {:?}",
+                            state.id,
+                            state.disconnected_reason().await
+                        );
+                        Err(e)
+                    }
+                    Ok(reply) => {
+                        if !reply.is_success() {
+                            log::debug!("{:?} Publish refused, reply: {:?}", state.id, reply);
+                        }
+                        Ok(publish_ack(&state, reply).await)
+                    }
                 }
             };
-            if Runtime::instance().is_busy().await {
+            let ack = if Runtime::instance().is_busy().await {
                 Runtime::local_exec()
                     .spawn(publish_fut)
                     .result()
                     .await
-                    .map_err(|e| MqttError::from(e.to_string()))??;
+                    .map_err(|e| MqttError::from(e.to_string()))??
             } else {
-                publish_fut.await?;
-            }
-            return Ok(PublishResult::PublishAck(PublishAck::new(PublishAckReason::Success)));
+                publish_fut.await?
+            };
+            return Ok(PublishResult::PublishAck(ack));
         }
         v5::PublishMessage::PublishAck(ref ack) => {
+            Runtime::instance().metrics.packet_received(PacketType::Puback);
             if let Some(iflt_msg) = state.inflight_win().write().await.remove(&ack.packet_id.get()) {
                 //hook, message_ack
                 state.hook.message_acked(iflt_msg.from, &iflt_msg.publish).await;
             }
         }
         v5::PublishMessage::PublishReceived(ref ack) => {
+            Runtime::instance().metrics.packet_received(PacketType::Pubrec);
             state.inflight_win().write().await.update_status(&ack.packet_id.get(), MomentStatus::UnComplete);
         }
         v5::PublishMessage::PublishComplete(ref ack2) => {
+            Runtime::instance().metrics.packet_received(PacketType::Pubcomp);
             if let Some(iflt_msg) = state.inflight_win().write().await.remove(&ack2.packet_id.get()) {
                 //hook, message_ack
                 state.hook.message_acked(iflt_msg.from, &iflt_msg.publish).await;
@@ -512,3 +863,26 @@ pub async fn publish(
 
     Ok(pub_msg.ack())
 }
+
+///PUBACK/PUBREC, the reason string and user properties are only sent if the client
+///requested problem information
+#[inline]
+async fn publish_ack(state: &v5::Session<SessionState>, reply: PublishReply) -> PublishAck {
+    let mut ack = PublishAck::new(reply.reason_code);
+    if reply.reason_string.is_none() && reply.user_properties.is_empty() {
+        return ack;
+    }
+    let request_problem_info = match state.connect_info().await.as_deref() {
+        Ok(ConnectInfo::V5(_, connect)) => connect.request_problem_info,
+        _ => true,
+    };
+    if request_problem_info {
+        if let Some(reason_string) = reply.reason_string {
+            ack = ack.reason(reason_string);
+        }
+        if !reply.user_properties.is_empty() {
+            ack = ack.properties(|props| props.extend(reply.user_properties));
+        }
+    }
+    ack
+}
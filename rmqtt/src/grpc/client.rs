@@ -5,14 +5,18 @@ use std::time::Duration;
 use tokio::sync::mpsc::{channel, Receiver, Sender};
 use tokio::sync::oneshot::Sender as OneshotSender;
 use tokio::sync::RwLock;
-use tonic::transport::{Channel, Endpoint};
+use tonic::metadata::{Ascii, MetadataValue};
+use tonic::service::interceptor::InterceptedService;
+use tonic::service::Interceptor;
+use tonic::transport::{Certificate, Channel, ClientTlsConfig, Endpoint, Identity};
+use tonic::{Request, Status};
 
 use crate::{MqttError, Result, Runtime};
 
 use super::pb::{self, node_service_client::NodeServiceClient};
-use super::{Message, MessageReply, MessageType};
+use super::{Message, MessageReply, MessageType, AUTHORIZATION};
 
-type NodeServiceClientType = NodeServiceClient<Channel>;
+type NodeServiceClientType = NodeServiceClient<InterceptedService<Channel, CookieInterceptor>>;
 
 #[derive(Clone)]
 pub struct NodeGrpcClient {
@@ -30,13 +34,18 @@ impl NodeGrpcClient {
     pub async fn new(server_addr: &str) -> Result<Self> {
         log::debug!("rpc.client_timeout: {:?}", Runtime::instance().settings.rpc.client_timeout);
         let concurrency_limit = Runtime::instance().settings.rpc.client_concurrency_limit + 1;
-        let endpoint = Channel::from_shared(format!("http://{}", server_addr))
+        let tls_enable = Runtime::instance().settings.rpc.tls_enable();
+        let scheme = if tls_enable { "https" } else { "http" };
+        let mut endpoint = Channel::from_shared(format!("{}://{}", scheme, server_addr))
             .map(|endpoint| {
                 endpoint
                     .concurrency_limit(concurrency_limit)
                     .timeout(Runtime::instance().settings.rpc.client_timeout)
             })
             .map_err(anyhow::Error::new)?;
+        if tls_enable {
+            endpoint = endpoint.tls_config(Self::tls_config()?)?;
+        }
         let active_tasks = Arc::new(AtomicUsize::new(0));
         let channel_tasks = Arc::new(AtomicUsize::new(0));
         let grpc_client = Arc::new(RwLock::new(None));
@@ -103,6 +112,22 @@ impl NodeGrpcClient {
         self.channel_tasks.load(Ordering::SeqCst)
     }
 
+    //Mutual TLS, the server certificate must be issued by 'rpc.tls_ca'
+    fn tls_config() -> Result<ClientTlsConfig> {
+        let rpccfg = &Runtime::instance().settings.rpc;
+        let (cert, key, ca) = match (&rpccfg.tls_cert, &rpccfg.tls_key, &rpccfg.tls_ca) {
+            (Some(cert), Some(key), Some(ca)) => (cert, key, ca),
+            _ => return Err(MqttError::from("rpc.tls_cert, rpc.tls_key and rpc.tls_ca are required")),
+        };
+        let identity = Identity::from_pem(std::fs::read(cert)?, std::fs::read(key)?);
+        let ca = Certificate::from_pem(std::fs::read(ca)?);
+        let mut tls_config = ClientTlsConfig::new().ca_certificate(ca).identity(identity);
+        if let Some(domain) = &rpccfg.tls_domain {
+            tls_config = tls_config.domain_name(domain);
+        }
+        Ok(tls_config)
+    }
+
     #[inline]
     async fn _connect(endpoint: &Endpoint) -> Result<NodeServiceClientType> {
         let channel =
@@ -110,7 +135,10 @@ impl NodeGrpcClient {
                 .await
                 .map_err(anyhow::Error::new)?
                 .map_err(anyhow::Error::new)?;
-        let client = NodeServiceClient::new(channel);
+        let client = NodeServiceClient::with_interceptor(
+            channel,
+            CookieInterceptor::new(&Runtime::instance().settings.node.cookie)?,
+        );
         Ok(client)
     }
 
@@ -303,3 +331,42 @@ impl NodeGrpcClient {
         }
     }
 }
+
+//Attach the cookie of this node, 'node.cookie', to each request
+#[derive(Clone)]
+pub struct CookieInterceptor {
+    cookie: MetadataValue<Ascii>,
+}
+
+impl CookieInterceptor {
+    #[inline]
+    fn new(cookie: &str) -> Result<Self> {
+        let cookie = MetadataValue::try_from(cookie)
+            .map_err(|e| MqttError::from(format!("invalid node.cookie, {}", e)))?;
+        Ok(Self { cookie })
+    }
+}
+
+impl Interceptor for CookieInterceptor {
+    #[inline]
+    fn call(&mut self, mut req: Request<()>) -> std::result::Result<Request<()>, Status> {
+        req.metadata_mut().insert(AUTHORIZATION, self.cookie.clone());
+        Ok(req)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::grpc::server::Server;
+
+    #[test]
+    fn cookie_interceptor() {
+        let mut interceptor = CookieInterceptor::new("secret").unwrap();
+        let req = interceptor.call(Request::new(())).unwrap();
+        assert_eq!(req.metadata().get(AUTHORIZATION).unwrap(), "secret");
+        assert!(Server::check_cookie(req, Some(b"secret")).is_ok());
+
+        assert!(CookieInterceptor::new("secret\n").is_err());
+    }
+}
@@ -12,7 +12,7 @@ use crate::broker::types::{
     SubsSearchParams, SubsSearchResult, TopicFilter, TopicName,
 };
 use crate::{
-    Addr, ClientId, MsgID, OfflineSession, Result, SharedGroup, SubRelations, SubRelationsMap,
+    Addr, ClientId, MsgID, OfflineSession, Result, Runtime, SharedGroup, SubRelations, SubRelationsMap,
     SubscriptionClientIds,
 };
 
@@ -29,6 +29,9 @@ pub type MessageType = u64;
 
 pub const MESSAGE_TYPE_MESSAGE_GET: u64 = 22;
 
+///Request metadata carrying the cookie of the node
+pub(crate) const AUTHORIZATION: &str = "authorization";
+
 #[derive(Serialize, Deserialize, Clone, Debug)]
 pub enum Message {
     Forwards(From, Publish),
@@ -199,3 +202,64 @@ impl MessageBroadcaster {
         }
     }
 }
+
+///Encodes a plugin message carried in Message::Data
+#[inline]
+pub fn encode_data<T: serde::Serialize>(msg: &T) -> Result<Vec<u8>> {
+    Ok(bincode::serialize(msg).map_err(anyhow::Error::new)?)
+}
+
+///Decodes a plugin message carried in Message::Data
+#[inline]
+pub fn decode_data<T: serde::de::DeserializeOwned>(data: &[u8]) -> Result<T> {
+    Ok(bincode::deserialize::<T>(data).map_err(anyhow::Error::new)?)
+}
+
+///Sends the data to all other nodes of the cluster, the nodes that fail or reply with an error are logged.
+///Plugins use it to replicate the changes made on this node.
+pub async fn broadcast_data(msg_type: MessageType, data: Vec<u8>, timeout: Duration) {
+    let grpc_clients = Runtime::instance().extends.shared().await.get_grpc_clients();
+    if grpc_clients.is_empty() {
+        return;
+    }
+    for (id, reply) in
+        MessageBroadcaster::new(grpc_clients, msg_type, Message::Data(data), Some(timeout)).join_all().await
+    {
+        match reply {
+            Ok(MessageReply::Error(e)) => {
+                log::warn!("send data to node({}) error, message type: {}, {}", id, msg_type, e)
+            }
+            Err(e) => log::warn!("send data to node({}) error, message type: {}, {:?}", id, msg_type, e),
+            Ok(_) => {}
+        }
+    }
+}
+
+///Sends the request to all other nodes of the cluster and returns the data they reply with.
+///
+///Plugins that keep their state on each node use it on start, the state of this node misses the
+///changes made on the other nodes while it was down. The nodes that fail or reply without data are
+///logged and skipped, the caller merges the replies into its own state.
+pub async fn collect_data(msg_type: MessageType, data: Vec<u8>, timeout: Duration) -> Vec<(NodeId, Vec<u8>)> {
+    let grpc_clients = Runtime::instance().extends.shared().await.get_grpc_clients();
+    if grpc_clients.is_empty() {
+        return Vec::new();
+    }
+    MessageBroadcaster::new(grpc_clients, msg_type, Message::Data(data), Some(timeout))
+        .join_all()
+        .await
+        .into_iter()
+        .filter_map(|(id, reply)| match reply {
+            Ok(MessageReply::Data(data)) => Some((id, data)),
+            reply => {
+                log::warn!(
+                    "collect data from node({}) error, message type: {}, reply: {:?}",
+                    id,
+                    msg_type,
+                    reply
+                );
+                None
+            }
+        })
+        .collect()
+}
@@ -2,15 +2,17 @@ use std::sync::atomic::{AtomicIsize, Ordering};
 use std::sync::Arc;
 
 use once_cell::sync::Lazy;
-use tonic::{transport, Response};
+use subtle::ConstantTimeEq;
+use tonic::transport::{Certificate, Identity, ServerTlsConfig};
+use tonic::{transport, Request, Response, Status};
 
-use crate::{Result, Runtime};
+use crate::{MqttError, Result, Runtime};
 
 use super::pb::{
     self,
     node_service_server::{NodeService, NodeServiceServer},
 };
-use super::{Message, MessageReply, MessageType, MESSAGE_TYPE_MESSAGE_GET};
+use super::{Message, MessageReply, MessageType, AUTHORIZATION, MESSAGE_TYPE_MESSAGE_GET};
 
 pub struct Server {}
 
@@ -24,16 +26,20 @@ impl Server {
 
         let rpccfg = Runtime::instance().settings.rpc.clone();
 
-        //NodeServiceServer::with_interceptor(RmqttNodeService::default(), Self::check_auth)
-
         log::info!(
-            "gRPC server is listening on tcp://{:?}, reuseaddr: {}, reuseport: {}",
+            "gRPC server is listening on tcp://{:?}, reuseaddr: {}, reuseport: {}, tls: {}, cookie_auth: {}",
             rpccfg.server_addr,
             rpccfg.reuseaddr,
-            rpccfg.reuseport
+            rpccfg.reuseport,
+            rpccfg.tls_enable(),
+            rpccfg.cookie_auth
         );
-        let server =
-            transport::Server::builder().add_service(NodeServiceServer::new(NodeGrpcService::default()));
+        let mut builder = transport::Server::builder();
+        if rpccfg.tls_enable() {
+            builder = builder.tls_config(Self::tls_config()?)?;
+        }
+        let server = builder
+            .add_service(NodeServiceServer::with_interceptor(NodeGrpcService::default(), Self::check_auth));
 
         if rpccfg.reuseaddr || rpccfg.reuseport {
             let listener = tokio_stream::wrappers::TcpListenerStream::new(tokio::net::TcpListener::from_std(
@@ -46,16 +52,41 @@ impl Server {
         Ok(())
     }
 
-    // fn check_auth(req: Request<()>) -> std::result::Result<Request<()>, Status> {
-    //     log::debug!("check_auth, req: {:?}", req);
-    //
-    //     let token = MetadataValue::from_str(Runtime::instance().settings.node.cookie.as_str())
-    //         .map_err(|e| Status::new(Code::Unauthenticated, e.to_string()))?;
-    //     match req.metadata().get("authorization") {
-    //         Some(t) if token == t => Ok(req),
-    //         _ => Err(Status::unauthenticated("No valid auth token")),
-    //     }
-    // }
+    //Mutual TLS, the client certificate must be issued by 'rpc.tls_ca'
+    fn tls_config() -> Result<ServerTlsConfig> {
+        let rpccfg = &Runtime::instance().settings.rpc;
+        let (cert, key, ca) = match (&rpccfg.tls_cert, &rpccfg.tls_key, &rpccfg.tls_ca) {
+            (Some(cert), Some(key), Some(ca)) => (cert, key, ca),
+            _ => return Err(MqttError::from("rpc.tls_cert, rpc.tls_key and rpc.tls_ca are required")),
+        };
+        let identity = Identity::from_pem(std::fs::read(cert)?, std::fs::read(key)?);
+        let ca = Certificate::from_pem(std::fs::read(ca)?);
+        Ok(ServerTlsConfig::new().identity(identity).client_ca_root(ca))
+    }
+
+    fn check_auth(req: Request<()>) -> std::result::Result<Request<()>, Status> {
+        let settings = &Runtime::instance().settings;
+        Self::check_cookie(req, settings.rpc.cookie_auth.then(|| settings.node.cookie.as_bytes()))
+    }
+
+    //Without a cookie, 'rpc.cookie_auth' is off, all requests are accepted
+    pub(crate) fn check_cookie(
+        req: Request<()>,
+        cookie: Option<&[u8]>,
+    ) -> std::result::Result<Request<()>, Status> {
+        let cookie = match cookie {
+            Some(cookie) => cookie,
+            None => return Ok(req),
+        };
+        match req.metadata().get(AUTHORIZATION) {
+            //Constant-time comparison, the time taken does not reveal how much of the cookie matches
+            Some(t) if bool::from(t.as_bytes().ct_eq(cookie)) => Ok(req),
+            _ => {
+                log::warn!("gRPC request rejected, invalid cookie, remote addr: {:?}", req.remote_addr());
+                Err(Status::unauthenticated("No valid auth token"))
+            }
+        }
+    }
 
     #[inline]
     pub fn bind(
@@ -160,3 +191,35 @@ pub static ACTIVE_REQUEST_COUNT: Lazy<Arc<AtomicIsize>> = Lazy::new(|| Arc::new(
 pub fn active_grpc_requests() -> isize {
     ACTIVE_REQUEST_COUNT.load(Ordering::SeqCst)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tonic::metadata::MetadataValue;
+
+    fn request(cookie: Option<&'static str>) -> Request<()> {
+        let mut req = Request::new(());
+        if let Some(cookie) = cookie {
+            req.metadata_mut().insert(AUTHORIZATION, MetadataValue::from_static(cookie));
+        }
+        req
+    }
+
+    #[test]
+    fn check_cookie() {
+        assert!(Server::check_cookie(request(Some("secret")), Some(b"secret")).is_ok());
+
+        let status = Server::check_cookie(request(Some("secreT")), Some(b"secret")).unwrap_err();
+        assert_eq!(status.code(), tonic::Code::Unauthenticated);
+        assert!(Server::check_cookie(request(Some("secre")), Some(b"secret")).is_err());
+        assert!(Server::check_cookie(request(Some("")), Some(b"secret")).is_err());
+
+        //missing header
+        let status = Server::check_cookie(request(None), Some(b"secret")).unwrap_err();
+        assert_eq!(status.code(), tonic::Code::Unauthenticated);
+
+        //cookie_auth is off
+        assert!(Server::check_cookie(request(None), None).is_ok());
+        assert!(Server::check_cookie(request(Some("other")), None).is_ok());
+    }
+}
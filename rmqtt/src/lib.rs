@@ -28,6 +28,7 @@ pub use log;
 pub use ntex;
 pub use ntex_mqtt;
 pub use once_cell;
+pub use parking_lot;
 pub use pin_project_lite;
 pub use prometheus;
 pub use rand;
@@ -47,7 +48,7 @@ pub use crate::broker::{
     error::MqttError,
     metrics,
     session::{Session, SessionState},
-    stats,
+    stats, telemetry, topic_metrics,
     types::*,
 };
 pub use crate::runtime::Runtime;
@@ -62,3 +63,4 @@ pub mod node;
 pub mod plugin;
 pub mod runtime;
 pub mod settings;
+pub mod tls;
@@ -2,11 +2,17 @@ use std::ops::Deref;
 use std::sync::atomic::{AtomicI64, Ordering};
 use std::time::{Duration, Instant};
 
+use bytestring::ByteString;
+use futures::channel::oneshot;
+use futures::stream::{FuturesUnordered, StreamExt};
 use once_cell::sync::Lazy;
 use systemstat::Platform;
+use tokio::sync::broadcast;
 
+use crate::broker::types::Message;
 use crate::grpc::client::NodeGrpcClient;
 use crate::grpc::server::Server;
+use crate::settings::listener::ListenerChanges;
 use crate::{NodeId, Result, Runtime};
 
 #[allow(dead_code)]
@@ -17,11 +23,13 @@ mod version {
 pub struct Node {
     pub start_time: chrono::DateTime<chrono::Local>,
     cpuload: AtomicI64,
+    listener_changes_tx: broadcast::Sender<ListenerChanges>,
 }
 
 impl Node {
     pub(crate) fn new() -> Self {
-        Self { start_time: chrono::Local::now(), cpuload: AtomicI64::new(0) }
+        let (listener_changes_tx, _) = broadcast::channel(16);
+        Self { start_time: chrono::Local::now(), cpuload: AtomicI64::new(0), listener_changes_tx }
     }
 
     #[inline]
@@ -63,6 +71,64 @@ impl Node {
         });
     }
 
+    ///Disconnect all local clients and wait until their sessions are saved, or until the drain timeout.
+    ///Returns the number of sessions that have not completed within the drain timeout.
+    pub async fn shutdown(&self) -> usize {
+        let cfg = &Runtime::instance().settings.node.shutdown;
+        let server_reference = cfg.server_reference.as_ref().map(|r| ByteString::from(r.as_str()));
+
+        let txs = Runtime::instance()
+            .extends
+            .shared()
+            .await
+            .iter()
+            .filter_map(|entry| entry.tx())
+            .collect::<Vec<_>>();
+        let mut acks = FuturesUnordered::new();
+        for tx in txs {
+            let (ack_tx, ack_rx) = oneshot::channel();
+            if tx.unbounded_send(Message::Shutdown(ack_tx, server_reference.clone())).is_ok() {
+                acks.push(ack_rx);
+            }
+        }
+        log::info!("shutting down, disconnecting {} sessions ...", acks.len());
+
+        let now = Instant::now();
+        let drain = async { while acks.next().await.is_some() {} };
+        if tokio::time::timeout(cfg.drain_timeout, drain).await.is_err() {
+            log::warn!("shutting down, drain timeout, {} sessions have not completed", acks.len());
+        } else {
+            log::info!("shutting down, all sessions are disconnected, cost time: {:?}", now.elapsed());
+        }
+        acks.len()
+    }
+
+    ///Re-read the listener configuration and reload the TLS certificates of the listeners.
+    ///Modified listeners apply to new connections, existing connections are not affected.
+    pub fn reload_listeners(&self) -> Result<ListenerChanges> {
+        let changes = Runtime::instance().settings.reload_listeners()?;
+        crate::tls::reload_certs();
+        log::info!("listeners reloaded, {:?}", changes);
+        for l in changes.restart_required.iter() {
+            log::warn!(
+                "{} listener {} on {} has changed socket options, they only take effect after restart",
+                l.typ,
+                l.name,
+                l.addr
+            );
+        }
+        if !changes.is_empty() {
+            let _ = self.listener_changes_tx.send(changes.clone());
+        }
+        Ok(changes)
+    }
+
+    ///Subscribe to the listener changes, the receiver is responsible for starting and stopping the listeners
+    #[inline]
+    pub fn listener_changes(&self) -> broadcast::Receiver<ListenerChanges> {
+        self.listener_changes_tx.subscribe()
+    }
+
     #[inline]
     pub async fn status(&self) -> NodeStatus {
         match Runtime::instance().extends.shared().await.health_status().await {
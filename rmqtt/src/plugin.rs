@@ -1,5 +1,6 @@
 use core::pin::Pin;
 use std::future::Future;
+use std::sync::atomic::{AtomicUsize, Ordering};
 
 use dashmap::iter::Iter;
 use dashmap::mapref::one::{Ref, RefMut};
@@ -61,6 +62,12 @@ pub trait Plugin: PackageInfo + Send + Sync {
         Ok(true)
     }
 
+    ///Called when the broker shuts down, before stop, to persist data that has not yet been written
+    #[inline]
+    async fn flush(&self) -> Result<()> {
+        Ok(())
+    }
+
     #[inline]
     async fn attrs(&self) -> serde_json::Value {
         serde_json::Value::Null
@@ -122,6 +129,8 @@ pub struct Entry {
     active: bool,
     //will reject start, stop, and load config operations
     immutable: bool,
+    //registration order
+    seq: usize,
     plugin: Option<DynPlugin>,
     plugin_f: Option<DynPluginFn>,
 }
@@ -237,11 +246,12 @@ impl PluginInfo {
 
 pub struct Manager {
     plugins: DashMap<String, Entry>,
+    seq: AtomicUsize,
 }
 
 impl Manager {
     pub(crate) fn new() -> Self {
-        Self { plugins: DashMap::default() }
+        Self { plugins: DashMap::default(), seq: AtomicUsize::new(0) }
     }
 
     ///Register a Plugin
@@ -270,7 +280,9 @@ impl Manager {
             (None, Some(boxed_f))
         };
 
-        let entry = Entry { inited: default_startup, active: default_startup, immutable, plugin, plugin_f };
+        let seq = self.seq.fetch_add(1, Ordering::SeqCst);
+        let entry =
+            Entry { inited: default_startup, active: default_startup, immutable, seq, plugin, plugin_f };
         self.plugins.insert(name, entry);
         Ok(())
     }
@@ -330,6 +342,45 @@ impl Manager {
         }
     }
 
+    ///Flush and stop all active plugins in reverse order of registration, when the broker shuts down.
+    ///Immutable plugins (cluster, storage) are flushed last and are not stopped.
+    pub async fn shutdown(&self) {
+        let mut actives = self
+            .plugins
+            .iter()
+            .filter(|entry| entry.active)
+            .map(|entry| (entry.immutable, entry.seq, entry.key().clone()))
+            .collect::<Vec<_>>();
+        actives.sort_by(|(immutable1, seq1, _), (immutable2, seq2, _)| {
+            immutable1.cmp(immutable2).then(seq2.cmp(seq1))
+        });
+
+        for (immutable, _, name) in actives {
+            if let Some(mut entry) = self.plugins.get_mut(&name) {
+                let plugin = match entry.plugin_mut().await {
+                    Ok(plugin) => plugin,
+                    Err(e) => {
+                        log::warn!("{} shutdown error, {:?}", name, e);
+                        continue;
+                    }
+                };
+                if let Err(e) = plugin.flush().await {
+                    log::warn!("{} flush error, {:?}", name, e);
+                }
+                if immutable {
+                    continue;
+                }
+                match plugin.stop().await {
+                    Ok(stopped) => {
+                        entry.active = !stopped;
+                        log::info!("{} is stopped: {}", name, stopped);
+                    }
+                    Err(e) => log::warn!("{} stop error, {:?}", name, e),
+                }
+            }
+        }
+    }
+
     ///Plugin is active
     pub fn is_active(&self, name: &str) -> bool {
         if let Some(entry) = self.plugins.get(name) {
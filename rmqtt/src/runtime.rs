@@ -14,7 +14,10 @@ use tokio_cron_scheduler::JobScheduler;
 
 use crate::logger::{config_logger, Logger};
 use crate::{
-    broker::{executor::is_busy as handshake_is_busy, metrics::Metrics, stats::Stats, types::DashMap},
+    broker::{
+        alarm::Alarms, executor::is_busy as handshake_is_busy, metrics::Metrics, stats::Stats,
+        telemetry::Tracer, topic_metrics::TopicMetrics, types::DashMap,
+    },
     extend,
     node::Node,
     plugin,
@@ -65,6 +68,7 @@ impl Runtime {
             sched,
         };
         INSTANCE.set(r).map_err(|_| anyhow!("set runtime failed"))?;
+        Tracer::init(&settings.tracing, settings.node.id)?;
         Ok(INSTANCE.get().ok_or_else(|| anyhow!("runtime is None"))?)
     }
 
@@ -116,6 +120,28 @@ pub async fn scheduler_init() -> Result<()> {
         Runtime::instance().sched.add(async_job_5).await.map_err(anyhow::Error::new)?;
     }
 
+    //Rates of the monitored topics, every 5 seconds
+    let topic_rates_job = tokio_cron_scheduler::Job::new_async("*/5 * * * * *", move |_uuid, _l| {
+        Box::pin(async move {
+            TopicMetrics::instance().update_rates();
+        })
+    })
+    .map_err(anyhow::Error::new)?;
+    Runtime::instance().sched.add(topic_rates_job).await.map_err(anyhow::Error::new)?;
+
+    //Built-in alarms
+    Alarms::instance().start();
+    if Runtime::instance().settings.alarm.enable {
+        let check_interval = Runtime::instance().settings.alarm.check_interval;
+        let alarm_job = tokio_cron_scheduler::Job::new_repeated_async(check_interval, move |_uuid, _l| {
+            Box::pin(async move {
+                Alarms::instance().check().await;
+            })
+        })
+        .map_err(anyhow::Error::new)?;
+        Runtime::instance().sched.add(alarm_job).await.map_err(anyhow::Error::new)?;
+    }
+
     Ok(())
 }
 
@@ -4,7 +4,8 @@ use std::time::Duration;
 
 use ntex_mqtt::v5::codec::SubscribeAckReason;
 
-use crate::broker::hook::{HookResult, ReturnType};
+use crate::broker::hook::{dry_run_note, HookResult, ReturnType};
+use crate::settings::listener::PublishLimit;
 use crate::{anyhow::anyhow, serde_json, PublishAclResult, SubscribeAclResult};
 use crate::{timestamp, ConnectInfo, MqttError, Publish, QoS, Result, Subscribe};
 
@@ -18,6 +19,8 @@ pub struct AuthInfo {
     pub superuser: bool,
     pub expire_at: Option<Duration>,
     pub rules: Vec<Rule>,
+    //Inbound publish limits of the client, replaces the limits of the listener, see Fitter::publish_limit()
+    pub publish_limit: Option<PublishLimit>,
 }
 
 impl AuthInfo {
@@ -41,6 +44,7 @@ impl AuthInfo {
             if !rule.subscribe_hit(subscribe).await {
                 continue;
             }
+            dry_run_note(|| format!("auth info rule matched, {:?}", rule));
 
             return match rule.permission {
                 Permission::Allow => Some((
@@ -75,6 +79,7 @@ impl AuthInfo {
             return match rule.permission {
                 Permission::Allow => {
                     if rule.publish_allow_hit(publish).await {
+                        dry_run_note(|| format!("auth info rule matched, {:?}", rule));
                         Some((false, Some(HookResult::PublishAclResult(PublishAclResult::Allow))))
                     } else {
                         continue;
@@ -82,6 +87,7 @@ impl AuthInfo {
                 }
                 Permission::Deny => {
                     if rule.publish_deny_hit(publish).await {
+                        dry_run_note(|| format!("auth info rule matched, {:?}", rule));
                         Some((
                             false,
                             Some(HookResult::PublishAclResult(PublishAclResult::Rejected(
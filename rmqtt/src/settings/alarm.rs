@@ -0,0 +1,153 @@
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+
+use super::deserialize_duration;
+use crate::{MqttError, Result};
+
+///Thresholds of the built-in alarms, an alarm is activated when the value rises above the high watermark
+///and deactivated when it falls below the low watermark
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct Alarm {
+    #[serde(default = "Alarm::enable_default")]
+    pub enable: bool,
+    //Interval of checking the CPU, memory, connections, message queues and disk space
+    #[serde(default = "Alarm::check_interval_default", deserialize_with = "deserialize_duration")]
+    pub check_interval: Duration,
+    //Maximum number of deactivated alarms that are kept in the history
+    #[serde(default = "Alarm::history_max_default")]
+    pub history_max: usize,
+
+    //CPU usage, 0.0 - 100.0
+    #[serde(default = "Alarm::cpu_high_watermark_default")]
+    pub cpu_high_watermark: f32,
+    #[serde(default = "Alarm::cpu_low_watermark_default")]
+    pub cpu_low_watermark: f32,
+
+    //Memory usage of the system, 0.0 - 100.0
+    #[serde(default = "Alarm::memory_high_watermark_default")]
+    pub memory_high_watermark: f32,
+    #[serde(default = "Alarm::memory_low_watermark_default")]
+    pub memory_low_watermark: f32,
+
+    //Connections of a listener, percentage of its max_connections, 0.0 - 100.0
+    #[serde(default = "Alarm::connections_high_watermark_default")]
+    pub connections_high_watermark: f32,
+    #[serde(default = "Alarm::connections_low_watermark_default")]
+    pub connections_low_watermark: f32,
+
+    //Messages dropped because the message queue of a session is full, within a check interval
+    #[serde(default = "Alarm::mqueue_full_drops_default")]
+    pub mqueue_full_drops: usize,
+
+    //Directories of the local storages, such as the sled databases of the plugins
+    #[serde(default = "Alarm::disk_paths_default")]
+    pub disk_paths: Vec<String>,
+    //Disk usage of the file system of a directory, 0.0 - 100.0
+    #[serde(default = "Alarm::disk_high_watermark_default")]
+    pub disk_high_watermark: f32,
+    #[serde(default = "Alarm::disk_low_watermark_default")]
+    pub disk_low_watermark: f32,
+}
+
+impl Default for Alarm {
+    #[inline]
+    fn default() -> Self {
+        Self {
+            enable: Self::enable_default(),
+            check_interval: Self::check_interval_default(),
+            history_max: Self::history_max_default(),
+            cpu_high_watermark: Self::cpu_high_watermark_default(),
+            cpu_low_watermark: Self::cpu_low_watermark_default(),
+            memory_high_watermark: Self::memory_high_watermark_default(),
+            memory_low_watermark: Self::memory_low_watermark_default(),
+            connections_high_watermark: Self::connections_high_watermark_default(),
+            connections_low_watermark: Self::connections_low_watermark_default(),
+            mqueue_full_drops: Self::mqueue_full_drops_default(),
+            disk_paths: Self::disk_paths_default(),
+            disk_high_watermark: Self::disk_high_watermark_default(),
+            disk_low_watermark: Self::disk_low_watermark_default(),
+        }
+    }
+}
+
+impl Alarm {
+    fn enable_default() -> bool {
+        true
+    }
+
+    fn check_interval_default() -> Duration {
+        Duration::from_secs(10)
+    }
+
+    fn history_max_default() -> usize {
+        1000
+    }
+
+    fn cpu_high_watermark_default() -> f32 {
+        80.0
+    }
+
+    fn cpu_low_watermark_default() -> f32 {
+        60.0
+    }
+
+    fn memory_high_watermark_default() -> f32 {
+        80.0
+    }
+
+    fn memory_low_watermark_default() -> f32 {
+        60.0
+    }
+
+    fn connections_high_watermark_default() -> f32 {
+        90.0
+    }
+
+    fn connections_low_watermark_default() -> f32 {
+        80.0
+    }
+
+    fn mqueue_full_drops_default() -> usize {
+        1
+    }
+
+    fn disk_paths_default() -> Vec<String> {
+        vec!["/var/log/rmqtt/.cache".into()]
+    }
+
+    fn disk_high_watermark_default() -> f32 {
+        90.0
+    }
+
+    fn disk_low_watermark_default() -> f32 {
+        80.0
+    }
+
+    #[inline]
+    pub(crate) fn check(&self) -> Result<()> {
+        for (name, high, low) in [
+            ("cpu", self.cpu_high_watermark, self.cpu_low_watermark),
+            ("memory", self.memory_high_watermark, self.memory_low_watermark),
+            ("connections", self.connections_high_watermark, self.connections_low_watermark),
+            ("disk", self.disk_high_watermark, self.disk_low_watermark),
+        ] {
+            if !(0.0..=100.0).contains(&high) || !(0.0..=100.0).contains(&low) {
+                return Err(MqttError::from(format!(
+                    "alarm.{}_*_watermark must be between 0.0 and 100.0",
+                    name
+                )));
+            }
+            if low > high {
+                return Err(MqttError::from(format!(
+                    "alarm.{}_low_watermark must not be greater than alarm.{}_high_watermark",
+                    name, name
+                )));
+            }
+        }
+        if self.check_interval.is_zero() {
+            return Err(MqttError::from("alarm.check_interval must be greater than 0"));
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,46 @@
+use serde::{Deserialize, Serialize};
+
+use crate::{MqttError, Result};
+
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub struct Auth {
+    ///Authenticators in the order they are run. If empty, the authentication handlers
+    ///run in the order of their hook priorities.
+    #[serde(default)]
+    pub chain: Vec<Authenticator>,
+}
+
+impl Auth {
+    #[inline]
+    pub(crate) fn check(&self) -> Result<()> {
+        for (i, a) in self.chain.iter().enumerate() {
+            if a.plugin.is_empty() {
+                return Err(MqttError::from("auth.chain, plugin name is empty"));
+            }
+            if self.chain[..i].iter().any(|prev| prev.plugin == a.plugin) {
+                return Err(MqttError::from(format!("auth.chain, plugin {} is repeated", a.plugin)));
+            }
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct Authenticator {
+    //Plugin name, such as "rmqtt-auth-http"
+    pub plugin: String,
+    pub control: AuthControl,
+}
+
+///How the result of an authenticator affects the chain
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AuthControl {
+    ///Allow ends the chain with success, deny or no decision moves on to the next authenticator.
+    ///The deny is used if no other authenticator allows the client.
+    Sufficient,
+    ///Deny or no decision ends the chain with failure, allow moves on to the next authenticator
+    Required,
+    ///The result is used only if no other authenticator decides
+    Optional,
+}
@@ -1,3 +1,4 @@
+use std::fmt;
 use std::net::SocketAddr;
 use std::num::{NonZeroU16, NonZeroU32};
 use std::ops::Deref;
@@ -5,11 +6,13 @@ use std::str::FromStr;
 use std::sync::Arc;
 use std::time::Duration;
 
+use parking_lot::RwLock;
 use serde::de::{self, Deserialize, Deserializer};
+use serde::Serialize;
 
 use crate::broker::types::QoS;
 
-use super::{deserialize_addr, deserialize_duration, to_duration, Bytesize};
+use super::{deserialize_addr, deserialize_duration, to_duration, Bytesize, SharedSubscriptionStrategy};
 
 type HashMap<K, V> = std::collections::HashMap<K, V, ahash::RandomState>;
 
@@ -33,89 +36,271 @@ pub struct Listeners {
     #[serde(default)]
     _wsss: HashMap<String, ListenerInner>,
 
+    //Active listeners, replaced when the listener configuration is reloaded
     #[serde(default, skip)]
-    pub tcps: HashMap<Port, Listener>,
-    #[serde(default, skip)]
-    pub tlss: HashMap<Port, Listener>,
-    #[serde(default, skip)]
-    pub wss: HashMap<Port, Listener>,
-    #[serde(default, skip)]
-    pub wsss: HashMap<Port, Listener>,
+    actives: Arc<RwLock<HashMap<(ListenerType, Port), Listener>>>,
 }
 
 impl Listeners {
     #[inline]
     pub(crate) fn init(&mut self) {
-        for (name, mut inner) in self._tcps.drain() {
-            if inner.enable {
-                inner.name = name;
-                self.tcps.insert(inner.addr.port(), Listener::new(inner));
-            }
-        }
-
-        for (name, mut inner) in self._tlss.drain() {
-            if inner.enable {
-                inner.name = name;
-                self.tlss.insert(inner.addr.port(), Listener::new(inner));
+        let mut actives = HashMap::default();
+        for (typ, listeners) in [
+            (ListenerType::Tcp, self._tcps.drain()),
+            (ListenerType::Tls, self._tlss.drain()),
+            (ListenerType::Ws, self._wss.drain()),
+            (ListenerType::Wss, self._wsss.drain()),
+        ] {
+            for (name, mut inner) in listeners {
+                if inner.enable {
+                    inner.name = name;
+                    actives.insert((typ, inner.addr.port()), Listener::new(inner));
+                }
             }
         }
-
-        for (name, mut inner) in self._wss.drain() {
-            if inner.enable {
-                inner.name = name;
-                self.wss.insert(inner.addr.port(), Listener::new(inner));
-            }
+        if !actives.keys().any(|(typ, _)| matches!(typ, ListenerType::Tcp | ListenerType::Tls)) {
+            //set default
+            let inner = Listener::default();
+            actives.insert((ListenerType::Tcp, inner.addr.port()), inner);
         }
+        *self.actives.write() = actives;
+    }
 
-        for (name, mut inner) in self._wsss.drain() {
-            if inner.enable {
-                inner.name = name;
-                self.wsss.insert(inner.addr.port(), Listener::new(inner));
-            }
-        }
+    #[inline]
+    pub fn find(&self, typ: ListenerType, port: u16) -> Option<Listener> {
+        self.actives.read().get(&(typ, port)).cloned()
     }
 
     #[inline]
     pub fn tcp(&self, port: u16) -> Option<Listener> {
-        self.tcps.get(&port).cloned()
+        self.find(ListenerType::Tcp, port)
     }
 
     #[inline]
     pub fn tls(&self, port: u16) -> Option<Listener> {
-        self.tlss.get(&port).cloned()
+        self.find(ListenerType::Tls, port)
     }
 
     #[inline]
     pub fn ws(&self, port: u16) -> Option<Listener> {
-        self.wss.get(&port).cloned()
+        self.find(ListenerType::Ws, port)
     }
 
     #[inline]
     pub fn wss(&self, port: u16) -> Option<Listener> {
-        self.wsss.get(&port).cloned()
+        self.find(ListenerType::Wss, port)
     }
 
     #[inline]
     pub fn get(&self, port: u16) -> Option<Listener> {
-        if let Some(l) = self.tcp(port) {
-            return Some(l);
-        }
-        if let Some(l) = self.tls(port) {
-            return Some(l);
+        let actives = self.actives.read();
+        [ListenerType::Tcp, ListenerType::Tls, ListenerType::Ws, ListenerType::Wss]
+            .into_iter()
+            .find_map(|typ| actives.get(&(typ, port)).cloned())
+    }
+
+    ///All active listeners
+    #[inline]
+    pub fn all(&self) -> Vec<(ListenerType, Listener)> {
+        self.actives.read().iter().map(|((typ, _), l)| (*typ, l.clone())).collect()
+    }
+
+    ///Replace the active listeners with the ones from the newly loaded configuration,
+    ///unchanged listeners are kept as they are.
+    pub(crate) fn reload(&self, mut new: Listeners) -> ListenerChanges {
+        new.init();
+        let news = std::mem::take(&mut *new.actives.write());
+        let mut changes = ListenerChanges::default();
+        let mut actives = self.actives.write();
+        actives.retain(|key, l| {
+            let keep = news.contains_key(key);
+            if !keep {
+                changes.removed.push(ListenerChange::new(key.0, l));
+            }
+            keep
+        });
+        for (key, l) in news {
+            match actives.get(&key) {
+                None => {
+                    changes.added.push(ListenerChange::new(key.0, &l));
+                    actives.insert(key, l);
+                }
+                Some(old) if old.inner.as_ref() != l.inner.as_ref() => {
+                    if old.is_bind_changed(&l) {
+                        changes.restart_required.push(ListenerChange::new(key.0, &l));
+                    }
+                    changes.updated.push(ListenerChange::new(key.0, &l));
+                    actives.insert(key, l);
+                }
+                Some(_) => {}
+            }
         }
-        if let Some(l) = self.ws(port) {
-            return Some(l);
+        changes
+    }
+}
+
+///What happens when a client publishes faster than its publish limits
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PublishLimitAction {
+    //Wait until the message is allowed, the broker stops reading from the connection meanwhile
+    #[default]
+    Backpressure,
+    //Disconnect the client, MQTT 5.0 clients receive the QuotaExceeded reason code
+    Disconnect,
+}
+
+///Inbound publish limits of a client
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct PublishLimit {
+    //Messages per period
+    pub rate: Option<(NonZeroU32, Duration)>,
+    //Payload bytes per period
+    pub bytes_rate: Option<(NonZeroU32, Duration)>,
+    pub action: PublishLimitAction,
+}
+
+impl PublishLimit {
+    #[inline]
+    pub fn is_unlimited(&self) -> bool {
+        self.rate.is_none() && self.bytes_rate.is_none()
+    }
+
+    ///Reads "publish_rate_limit", "publish_bytes_rate_limit" and "publish_limit_action" from the result
+    ///of an authentication, such as a JSON response or JWT claims. None if no limit is set.
+    pub fn from_fields<'a, F>(get: F) -> crate::Result<Option<PublishLimit>>
+    where
+        F: Fn(&str) -> Option<&'a serde_json::Value>,
+    {
+        let rate =
+            get("publish_rate_limit").and_then(|v| v.as_str()).map(Self::parse_rate_limit).transpose()?;
+        let bytes_rate = get("publish_bytes_rate_limit")
+            .and_then(|v| v.as_str())
+            .map(Self::parse_bytes_rate_limit)
+            .transpose()?;
+        if rate.is_none() && bytes_rate.is_none() {
+            return Ok(None);
         }
-        if let Some(l) = self.wss(port) {
-            return Some(l);
+        let action = get("publish_limit_action")
+            .map(|v| serde_json::from_value::<PublishLimitAction>(v.clone()))
+            .transpose()?
+            .unwrap_or_default();
+        Ok(Some(PublishLimit { rate, bytes_rate, action }))
+    }
+
+    ///Parses a message rate such as "100,1s"
+    #[inline]
+    pub fn parse_rate_limit(v: &str) -> crate::Result<(NonZeroU32, Duration)> {
+        Self::parse(v, |n| n.parse::<u32>().ok())
+    }
+
+    ///Parses a byte rate such as "1M,1s"
+    #[inline]
+    pub fn parse_bytes_rate_limit(v: &str) -> crate::Result<(NonZeroU32, Duration)> {
+        Self::parse(v, |n| u32::try_from(Bytesize::from(n).as_usize()).ok())
+    }
+
+    #[inline]
+    fn parse<F: Fn(&str) -> Option<u32>>(v: &str, f: F) -> crate::Result<(NonZeroU32, Duration)> {
+        let err = || crate::MqttError::from(format!("value format error, {}", v));
+        let (n, period) = v.split_once(',').ok_or_else(err)?;
+        let n = f(n.trim()).and_then(NonZeroU32::new).ok_or_else(err)?;
+        let period = to_duration(period.trim());
+        if period.is_zero() {
+            return Err(err());
         }
-        None
+        Ok((n, period))
+    }
+}
+
+///Field of the client certificate used as the client identity
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PeerCertField {
+    //Common name of the subject
+    Cn,
+    //Distinguished name of the subject
+    Dn,
+    //First subject alternative name
+    San,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ListenerType {
+    Tcp,
+    Tls,
+    Ws,
+    Wss,
+}
+
+impl ListenerType {
+    #[inline]
+    pub fn is_tls(&self) -> bool {
+        matches!(self, ListenerType::Tls | ListenerType::Wss)
+    }
+}
+
+impl fmt::Display for ListenerType {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let typ = match self {
+            ListenerType::Tcp => "tcp",
+            ListenerType::Tls => "tls",
+            ListenerType::Ws => "ws",
+            ListenerType::Wss => "wss",
+        };
+        write!(f, "{}", typ)
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ListenerChange {
+    pub typ: ListenerType,
+    pub name: String,
+    pub addr: SocketAddr,
+}
+
+impl ListenerChange {
+    #[inline]
+    fn new(typ: ListenerType, l: &Listener) -> Self {
+        Self { typ, name: l.name.clone(), addr: l.addr }
     }
 
     #[inline]
-    pub(crate) fn set_default(&mut self) {
-        let inner = Listener::default();
-        self.tcps.insert(inner.addr.port(), inner);
+    pub fn to_json(&self) -> serde_json::Value {
+        json!({
+            "type": self.typ,
+            "name": self.name,
+            "addr": self.addr,
+        })
+    }
+}
+
+///Result of a listener configuration reload
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ListenerChanges {
+    pub added: Vec<ListenerChange>,
+    pub removed: Vec<ListenerChange>,
+    pub updated: Vec<ListenerChange>,
+    //Updated listeners whose socket or protocol options changed, these only take effect after restart
+    pub restart_required: Vec<ListenerChange>,
+}
+
+impl ListenerChanges {
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty() && self.updated.is_empty()
+    }
+
+    #[inline]
+    pub fn to_json(&self) -> serde_json::Value {
+        let to_json = |changes: &Vec<ListenerChange>| changes.iter().map(|c| c.to_json()).collect::<Vec<_>>();
+        json!({
+            "added": to_json(&self.added),
+            "removed": to_json(&self.removed),
+            "updated": to_json(&self.updated),
+            "restart_required": to_json(&self.restart_required),
+        })
     }
 }
 
@@ -129,6 +314,27 @@ impl Listener {
     fn new(inner: ListenerInner) -> Self {
         Self { inner: Arc::new(inner) }
     }
+
+    ///Whether the options fixed when the listening socket is bound have changed
+    #[inline]
+    fn is_bind_changed(&self, other: &Listener) -> bool {
+        self.addr != other.addr
+            || self.workers != other.workers
+            || self.backlog != other.backlog
+            || self.reuseaddr != other.reuseaddr
+            || self.reuseport != other.reuseport
+            || self.max_inflight != other.max_inflight
+            || self.max_packet_size != other.max_packet_size
+            || self.handshake_timeout != other.handshake_timeout
+            || self.cross_certificate != other.cross_certificate
+            || self.proxy_protocol != other.proxy_protocol
+            || self.proxy_protocol_timeout != other.proxy_protocol_timeout
+    }
+
+    #[inline]
+    pub fn ptr_eq(&self, other: &Listener) -> bool {
+        Arc::ptr_eq(&self.inner, &other.inner)
+    }
 }
 
 impl Deref for Listener {
@@ -138,7 +344,7 @@ impl Deref for Listener {
     }
 }
 
-#[derive(Debug, Clone, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Deserialize)]
 pub struct ListenerInner {
     #[serde(default)]
     pub name: String,
@@ -182,6 +388,15 @@ pub struct ListenerInner {
     )]
     pub mqueue_rate_limit: (NonZeroU32, Duration),
 
+    //Inbound publish limits of each client, messages per period, such as "100,1s"
+    #[serde(default, deserialize_with = "ListenerInner::deserialize_publish_rate_limit")]
+    pub publish_rate_limit: Option<(NonZeroU32, Duration)>,
+    //Payload bytes per period, such as "1M,1s"
+    #[serde(default, deserialize_with = "ListenerInner::deserialize_publish_bytes_rate_limit")]
+    pub publish_bytes_rate_limit: Option<(NonZeroU32, Duration)>,
+    #[serde(default)]
+    pub publish_limit_action: PublishLimitAction,
+
     #[serde(default = "ListenerInner::max_clientid_len_default")]
     pub max_clientid_len: usize,
 
@@ -217,6 +432,9 @@ pub struct ListenerInner {
 
     #[serde(default = "ListenerInner::shared_subscription_default")]
     pub shared_subscription: bool,
+    //Shared subscription strategy for messages published through this listener
+    #[serde(default)]
+    pub shared_subscription_strategy: Option<SharedSubscriptionStrategy>,
 
     #[serde(default)]
     pub max_topic_aliases: u16,
@@ -225,6 +443,33 @@ pub struct ListenerInner {
     pub cross_certificate: bool,
     pub cert: Option<String>,
     pub key: Option<String>,
+    //CA bundle used to verify client certificates when cross_certificate is enabled,
+    //if not set, the certificates in cert are used
+    #[serde(default)]
+    pub cacert: Option<String>,
+    //Interval for checking whether the cert, key and cacert files have changed, 0 means disabled
+    #[serde(
+        default = "ListenerInner::cert_watch_interval_default",
+        deserialize_with = "deserialize_duration"
+    )]
+    pub cert_watch_interval: Duration,
+    //Use a field of the client certificate as the username, "cn" | "dn" | "san"
+    #[serde(default)]
+    pub peer_cert_as_username: Option<PeerCertField>,
+    //Use a field of the client certificate as the client id, "cn" | "dn" | "san"
+    #[serde(default)]
+    pub peer_cert_as_clientid: Option<PeerCertField>,
+
+    //Whether to read the PROXY protocol v1/v2 header sent by a load balancer, the client address
+    //in the header is used as the remote address of the connection
+    #[serde(default)]
+    pub proxy_protocol: bool,
+    //Timeout for receiving the PROXY protocol header
+    #[serde(
+        default = "ListenerInner::proxy_protocol_timeout_default",
+        deserialize_with = "deserialize_duration"
+    )]
+    pub proxy_protocol_timeout: Duration,
 
     #[serde(default)]
     pub limit_subscription: bool,
@@ -254,6 +499,9 @@ impl Default for ListenerInner {
             handshake_timeout: ListenerInner::handshake_timeout_default(),
             max_mqueue_len: ListenerInner::max_mqueue_len_default(),
             mqueue_rate_limit: ListenerInner::mqueue_rate_limit_default(),
+            publish_rate_limit: None,
+            publish_bytes_rate_limit: None,
+            publish_limit_action: PublishLimitAction::default(),
             max_clientid_len: ListenerInner::max_clientid_len_default(),
             max_qos_allowed: ListenerInner::max_qos_allowed_default(),
             max_topic_levels: ListenerInner::max_topic_levels_default(),
@@ -262,10 +510,17 @@ impl Default for ListenerInner {
             message_expiry_interval: ListenerInner::message_expiry_interval_default(),
             max_subscriptions: ListenerInner::max_subscriptions_default(),
             shared_subscription: ListenerInner::shared_subscription_default(),
+            shared_subscription_strategy: None,
             max_topic_aliases: 0,
             cross_certificate: ListenerInner::cross_certificate_default(),
             cert: None,
             key: None,
+            cacert: None,
+            cert_watch_interval: ListenerInner::cert_watch_interval_default(),
+            peer_cert_as_username: None,
+            peer_cert_as_clientid: None,
+            proxy_protocol: false,
+            proxy_protocol_timeout: ListenerInner::proxy_protocol_timeout_default(),
             limit_subscription: false,
             delayed_publish: false,
         }
@@ -380,6 +635,14 @@ impl ListenerInner {
     fn shared_subscription_default() -> bool {
         true
     }
+    #[inline]
+    fn cert_watch_interval_default() -> Duration {
+        Duration::from_secs(60)
+    }
+    #[inline]
+    fn proxy_protocol_timeout_default() -> Duration {
+        Duration::from_secs(3)
+    }
 
     #[inline]
     pub fn handshake_timeout(&self) -> u16 {
@@ -413,6 +676,42 @@ impl ListenerInner {
             Err(de::Error::custom(format!("mqueue_rate_limit, value format error, {}", pair.join(","))))
         }
     }
+    #[inline]
+    fn deserialize_publish_rate_limit<'de, D>(
+        deserializer: D,
+    ) -> Result<Option<(NonZeroU32, Duration)>, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let v = String::deserialize(deserializer)?;
+        PublishLimit::parse_rate_limit(&v)
+            .map(Some)
+            .map_err(|e| de::Error::custom(format!("publish_rate_limit, {}", e)))
+    }
+
+    #[inline]
+    fn deserialize_publish_bytes_rate_limit<'de, D>(
+        deserializer: D,
+    ) -> Result<Option<(NonZeroU32, Duration)>, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let v = String::deserialize(deserializer)?;
+        PublishLimit::parse_bytes_rate_limit(&v)
+            .map(Some)
+            .map_err(|e| de::Error::custom(format!("publish_bytes_rate_limit, {}", e)))
+    }
+
+    ///Inbound publish limits of the listener, see Fitter::publish_limit()
+    #[inline]
+    pub fn publish_limit(&self) -> PublishLimit {
+        PublishLimit {
+            rate: self.publish_rate_limit,
+            bytes_rate: self.publish_bytes_rate_limit,
+            action: self.publish_limit_action,
+        }
+    }
+
     #[inline]
     fn deserialize_max_qos_allowed<'de, D>(deserializer: D) -> Result<QoS, D::Error>
     where
@@ -445,3 +744,74 @@ impl ListenerInner {
         false
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn listeners(tcps: serde_json::Value) -> Listeners {
+        let mut listeners: Listeners = serde_json::from_value(json!({ "tcp": tcps })).unwrap();
+        listeners.init();
+        listeners
+    }
+
+    fn names(changes: &[ListenerChange]) -> Vec<&str> {
+        let mut names = changes.iter().map(|c| c.name.as_str()).collect::<Vec<_>>();
+        names.sort();
+        names
+    }
+
+    #[test]
+    fn reload() {
+        let actives = listeners(json!({
+            "external": {"addr": "0.0.0.0:1883"},
+            "internal": {"addr": "0.0.0.0:11883"},
+            "old": {"addr": "0.0.0.0:21883"},
+        }));
+        let external = actives.tcp(1883).unwrap();
+
+        let changes = actives.reload(listeners(json!({
+            "external": {"addr": "0.0.0.0:1883"},
+            "internal": {"addr": "0.0.0.0:11883", "max_connections": 10},
+            "new": {"addr": "0.0.0.0:31883"},
+        })));
+        assert_eq!(names(&changes.added), ["new"]);
+        assert_eq!(names(&changes.removed), ["old"]);
+        //max_connections is checked in the handshake, it takes effect without restart
+        assert_eq!(names(&changes.updated), ["internal"]);
+        assert!(changes.restart_required.is_empty());
+        assert!(actives.tcp(21883).is_none());
+        assert_eq!(actives.tcp(11883).unwrap().max_connections, 10);
+        assert_eq!(actives.tcp(31883).unwrap().name, "new");
+        //unchanged listeners are kept as they are
+        assert!(actives.tcp(1883).unwrap().ptr_eq(&external));
+
+        let changes = actives.reload(listeners(json!({
+            "external": {"addr": "127.0.0.1:1883"},
+            "internal": {"addr": "0.0.0.0:11883", "max_connections": 10, "workers": 2},
+            "new": {"addr": "0.0.0.0:31883"},
+        })));
+        assert!(changes.added.is_empty() && changes.removed.is_empty());
+        assert_eq!(names(&changes.updated), ["external", "internal"]);
+        assert_eq!(names(&changes.restart_required), ["external", "internal"]);
+
+        let changes = actives.reload(listeners(json!({
+            "external": {"addr": "127.0.0.1:1883"},
+            "internal": {"addr": "0.0.0.0:11883", "max_connections": 10, "workers": 2},
+            "new": {"addr": "0.0.0.0:31883"},
+        })));
+        assert!(changes.is_empty());
+        assert!(changes.restart_required.is_empty());
+    }
+
+    #[test]
+    fn reload_default_listener() {
+        let actives = listeners(json!({"external": {"addr": "0.0.0.0:1883"}}));
+        //without TCP and TLS listeners, the default TCP listener is used
+        let changes = actives.reload(listeners(json!({})));
+        assert!(changes.removed.is_empty());
+        assert!(changes.added.is_empty());
+        assert_eq!(actives.all().len(), 1);
+        assert_eq!(actives.tcp(1883).map(|l| l.addr), Some(Listener::default().addr));
+    }
+}
@@ -16,15 +16,23 @@ use serde::Serialize;
 
 use crate::{Addr, MqttError, NodeId, Result};
 
+pub use self::alarm::Alarm;
+use self::auth::Auth;
 pub use self::listener::Listener;
-use self::listener::Listeners;
+use self::listener::{ListenerChanges, Listeners};
 use self::log::Log;
 pub use self::options::Options;
+pub use self::tracing::Tracing;
 
 pub mod acl;
+pub mod alarm;
+pub mod auth;
 pub mod listener;
 pub mod log;
 pub mod options;
+pub mod tracing;
+
+type HashMap<K, V> = std::collections::HashMap<K, V, ahash::RandomState>;
 
 static SETTINGS: OnceCell<Settings> = OnceCell::new();
 
@@ -48,6 +56,12 @@ pub struct Inner {
     pub plugins: Plugins,
     #[serde(default)]
     pub mqtt: Mqtt,
+    #[serde(default)]
+    pub auth: Auth,
+    #[serde(default)]
+    pub tracing: Tracing,
+    #[serde(default)]
+    pub alarm: Alarm,
     #[serde(default, skip)]
     pub opts: Options,
 }
@@ -61,6 +75,25 @@ impl Deref for Settings {
 
 impl Settings {
     fn new(opts: Options) -> Result<Self> {
+        let mut inner = Self::load(&opts)?;
+
+        inner.listeners.init();
+
+        //Command line configuration overriding file configuration
+        if let Some(id) = opts.node_id {
+            if id > 0 {
+                inner.node.id = id;
+            }
+        }
+        if let Some(plugins_default_startups) = opts.plugins_default_startups.as_ref() {
+            inner.plugins.default_startups.clone_from(plugins_default_startups)
+        }
+
+        inner.opts = opts;
+        Ok(Self(Arc::new(inner)))
+    }
+
+    fn load(opts: &Options) -> Result<Inner> {
         let mut builder = Config::builder()
             .add_source(File::with_name("/etc/rmqtt/rmqtt").required(false))
             .add_source(File::with_name("/etc/rmqtt").required(false))
@@ -76,26 +109,26 @@ impl Settings {
             builder = builder.add_source(File::with_name(cfg).required(false));
         }
 
-        let mut inner: Inner = builder.build()?.try_deserialize()?;
+        let inner: Inner = builder.build()?.try_deserialize()?;
 
-        inner.listeners.init();
-        if inner.listeners.tcps.is_empty() && inner.listeners.tlss.is_empty() {
-            //set default
-            inner.listeners.set_default();
+        if inner.rpc.tls_cert.is_some() != inner.rpc.tls_key.is_some() {
+            return Err(MqttError::from("rpc.tls_cert and rpc.tls_key must be configured together"));
         }
-
-        //Command line configuration overriding file configuration
-        if let Some(id) = opts.node_id {
-            if id > 0 {
-                inner.node.id = id;
-            }
-        }
-        if let Some(plugins_default_startups) = opts.plugins_default_startups.as_ref() {
-            inner.plugins.default_startups.clone_from(plugins_default_startups)
+        if inner.rpc.tls_enable() && inner.rpc.tls_ca.is_none() {
+            return Err(MqttError::from("rpc.tls_ca is required when rpc TLS is enabled"));
         }
+        inner.auth.check()?;
+        inner.tracing.check()?;
+        inner.alarm.check()?;
+        Ok(inner)
+    }
 
-        inner.opts = opts;
-        Ok(Self(Arc::new(inner)))
+    ///Re-read the configuration files and apply the listener configuration,
+    ///other configuration items are not changed.
+    #[inline]
+    pub(crate) fn reload_listeners(&self) -> Result<ListenerChanges> {
+        let inner = Self::load(&self.opts)?;
+        Ok(self.listeners.reload(inner.listeners))
     }
 
     #[inline]
@@ -120,6 +153,24 @@ impl Settings {
         crate::log::info!("local_exec_queue_max is {}", cfg.task.local_exec_queue_max);
         crate::log::info!("local_exec_rate_limit is {:?}", cfg.task.local_exec_rate_limit);
         crate::log::info!("node.busy config is: {:?}", cfg.node.busy);
+        crate::log::info!("node.shutdown config is: {:?}", cfg.node.shutdown);
+        crate::log::info!("alarm config is: {:?}", cfg.alarm);
+        crate::log::info!("rpc.cookie_auth is {}, rpc TLS is {}", cfg.rpc.cookie_auth, cfg.rpc.tls_enable());
+        if cfg.rpc.cookie_auth && cfg.node.cookie == Node::cookie_default() {
+            crate::log::warn!(
+                "rpc.cookie_auth is enabled with the default node.cookie, which is public, \
+                 set a secret node.cookie on all nodes"
+            );
+        }
+        if cfg.rpc.cookie_auth && !cfg.rpc.tls_enable() {
+            crate::log::warn!(
+                "rpc.cookie_auth is enabled without rpc TLS, node.cookie is sent in plain text, \
+                 set rpc.tls_cert, rpc.tls_key and rpc.tls_ca"
+            );
+        }
+        if !cfg.auth.chain.is_empty() {
+            crate::log::info!("auth.chain is {:?}", cfg.auth.chain);
+        }
 
         if cfg.opts.node_grpc_addrs.is_some() {
             crate::log::info!("node_grpc_addrs is {:?}", cfg.opts.node_grpc_addrs);
@@ -232,6 +283,8 @@ pub struct Node {
     // pub crash_dump: String,
     #[serde(default)]
     pub busy: Busy,
+    #[serde(default)]
+    pub shutdown: Shutdown,
 }
 
 impl Node {
@@ -291,6 +344,30 @@ impl Busy {
     }
 }
 
+#[derive(Debug, Clone, Deserialize)]
+pub struct Shutdown {
+    //The maximum time to wait for the clients to be disconnected and their sessions to be saved.
+    #[serde(default = "Shutdown::drain_timeout_default", deserialize_with = "deserialize_duration")]
+    pub drain_timeout: Duration,
+    //If set, MQTT 5.0 clients are disconnected with 'UseAnotherServer' and this server reference,
+    //otherwise with 'ServerShuttingDown'.
+    #[serde(default)]
+    pub server_reference: Option<String>,
+}
+
+impl Default for Shutdown {
+    #[inline]
+    fn default() -> Self {
+        Self { drain_timeout: Self::drain_timeout_default(), server_reference: None }
+    }
+}
+
+impl Shutdown {
+    fn drain_timeout_default() -> Duration {
+        Duration::from_secs(30)
+    }
+}
+
 #[derive(Debug, Clone, Deserialize)]
 pub struct Rpc {
     #[serde(default = "Rpc::server_addr_default", deserialize_with = "deserialize_addr")]
@@ -314,6 +391,23 @@ pub struct Rpc {
     //#Maximum number of messages sent in batch
     #[serde(default = "Rpc::batch_size_default")]
     pub batch_size: usize,
+
+    //Reject requests that do not carry the cookie of this cluster, 'node.cookie'
+    #[serde(default)]
+    pub cookie_auth: bool,
+
+    //TLS is enabled when both the certificate and the private key are set, PEM format.
+    //The same certificate is used by the server and by the client of this node.
+    #[serde(default)]
+    pub tls_cert: Option<String>,
+    #[serde(default)]
+    pub tls_key: Option<String>,
+    //CA certificate used to verify the certificates of other nodes, required when TLS is enabled
+    #[serde(default)]
+    pub tls_ca: Option<String>,
+    //Domain name used to verify the server certificate, default is the host of the server address
+    #[serde(default)]
+    pub tls_domain: Option<String>,
 }
 
 impl Default for Rpc {
@@ -327,11 +421,21 @@ impl Default for Rpc {
             server_workers: Self::server_workers_default(),
             client_concurrency_limit: Self::client_concurrency_limit_default(),
             client_timeout: Self::client_timeout_default(),
+            cookie_auth: false,
+            tls_cert: None,
+            tls_key: None,
+            tls_ca: None,
+            tls_domain: None,
         }
     }
 }
 
 impl Rpc {
+    #[inline]
+    pub fn tls_enable(&self) -> bool {
+        self.tls_cert.is_some() && self.tls_key.is_some()
+    }
+
     fn reuseaddr_default() -> bool {
         true
     }
@@ -441,6 +545,12 @@ pub struct Mqtt {
     pub delayed_publish_immediate: bool,
     #[serde(default = "Mqtt::max_sessions_default")]
     pub max_sessions: isize,
+    //Default shared subscription strategy
+    #[serde(default)]
+    pub shared_subscription_strategy: SharedSubscriptionStrategy,
+    //Shared subscription strategy by $share group name
+    #[serde(default)]
+    pub shared_subscription_group_strategies: HashMap<String, SharedSubscriptionStrategy>,
 }
 
 impl Mqtt {
@@ -457,11 +567,30 @@ impl Mqtt {
     }
 }
 
+///How to select a subscriber from a shared subscription group
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SharedSubscriptionStrategy {
+    ///Select a subscriber at random
+    #[default]
+    Random,
+    ///Select subscribers in turn
+    RoundRobin,
+    ///Messages from the same publisher (client id) always go to the same subscriber
+    Sticky,
+    ///Messages with the same topic always go to the same subscriber
+    HashTopic,
+    ///Select the subscriber on this node with the fewest inflight and queued messages
+    LeastInflight,
+    ///Select a subscriber on this node if there is one, otherwise at random
+    LocalFirst,
+}
+
 const BYTESIZE_K: usize = 1024;
 const BYTESIZE_M: usize = 1048576;
 const BYTESIZE_G: usize = 1073741824;
 
-#[derive(Clone, Copy, Default)]
+#[derive(Clone, Copy, Default, PartialEq, Eq)]
 pub struct Bytesize(usize);
 
 impl Bytesize {
@@ -0,0 +1,98 @@
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+
+use super::deserialize_duration;
+use crate::{MqttError, Result};
+
+type HashMap<K, V> = std::collections::HashMap<K, V, ahash::RandomState>;
+
+///OpenTelemetry tracing of the message path, the spans are exported with OTLP/HTTP (JSON)
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct Tracing {
+    #[serde(default)]
+    pub enable: bool,
+    //OTLP/HTTP traces endpoint of the collector
+    #[serde(default = "Tracing::endpoint_default")]
+    pub endpoint: String,
+    //Extra HTTP headers of the export requests, such as an authorization token
+    #[serde(default)]
+    pub headers: HashMap<String, String>,
+    #[serde(default = "Tracing::service_name_default")]
+    pub service_name: String,
+    //Ratio of the traces started by this node that are sampled, 0.0 - 1.0. A message that carries
+    //a 'traceparent' follows the sampled flag of its parent
+    #[serde(default = "Tracing::sample_ratio_default")]
+    pub sample_ratio: f64,
+    #[serde(default = "Tracing::export_interval_default", deserialize_with = "deserialize_duration")]
+    pub export_interval: Duration,
+    #[serde(default = "Tracing::export_timeout_default", deserialize_with = "deserialize_duration")]
+    pub export_timeout: Duration,
+    //Maximum number of spans in an export request
+    #[serde(default = "Tracing::export_batch_max_default")]
+    pub export_batch_max: usize,
+    //Maximum number of spans waiting to be exported, new spans are dropped when it is full
+    #[serde(default = "Tracing::queue_max_default")]
+    pub queue_max: usize,
+}
+
+impl Default for Tracing {
+    #[inline]
+    fn default() -> Self {
+        Self {
+            enable: false,
+            endpoint: Self::endpoint_default(),
+            headers: HashMap::default(),
+            service_name: Self::service_name_default(),
+            sample_ratio: Self::sample_ratio_default(),
+            export_interval: Self::export_interval_default(),
+            export_timeout: Self::export_timeout_default(),
+            export_batch_max: Self::export_batch_max_default(),
+            queue_max: Self::queue_max_default(),
+        }
+    }
+}
+
+impl Tracing {
+    fn endpoint_default() -> String {
+        "http://127.0.0.1:4318/v1/traces".into()
+    }
+
+    fn service_name_default() -> String {
+        "rmqtt".into()
+    }
+
+    fn sample_ratio_default() -> f64 {
+        1.0
+    }
+
+    fn export_interval_default() -> Duration {
+        Duration::from_secs(5)
+    }
+
+    fn export_timeout_default() -> Duration {
+        Duration::from_secs(10)
+    }
+
+    fn export_batch_max_default() -> usize {
+        512
+    }
+
+    fn queue_max_default() -> usize {
+        100_000
+    }
+
+    #[inline]
+    pub(crate) fn check(&self) -> Result<()> {
+        if !(0.0..=1.0).contains(&self.sample_ratio) {
+            return Err(MqttError::from("tracing.sample_ratio must be between 0.0 and 1.0"));
+        }
+        if self.enable && self.endpoint.is_empty() {
+            return Err(MqttError::from("tracing.endpoint is empty"));
+        }
+        if self.export_batch_max == 0 {
+            return Err(MqttError::from("tracing.export_batch_max must be greater than 0"));
+        }
+        Ok(())
+    }
+}
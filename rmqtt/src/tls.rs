@@ -0,0 +1,620 @@
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+
+use anyhow::anyhow;
+use once_cell::sync::Lazy;
+use rustls::client::danger::HandshakeSignatureValid;
+#[cfg(not(target_os = "windows"))]
+use rustls::crypto::aws_lc_rs as provider;
+#[cfg(target_os = "windows")]
+use rustls::crypto::ring as provider;
+use rustls::crypto::CryptoProvider;
+use rustls::pki_types::pem::PemObject;
+use rustls::pki_types::{CertificateDer, PrivateKeyDer, UnixTime};
+use rustls::server::danger::{ClientCertVerified, ClientCertVerifier};
+use rustls::server::{ClientHello, ResolvesServerCert, WebPkiClientVerifier};
+use rustls::sign::CertifiedKey;
+use rustls::{DigitallySignedStruct, DistinguishedName, RootCertStore, ServerConfig, SignatureScheme};
+use x509_parser::extensions::GeneralName;
+
+use crate::broker::types::DashMap;
+use crate::settings::listener::{Listener, PeerCertField};
+use crate::{ClientId, MqttError, NodeId, Result, Runtime, Timestamp, UserName};
+
+type Port = u16;
+
+//TLS state of the listeners, by listening port
+static LISTENER_TLS: Lazy<DashMap<Port, Arc<ListenerTls>>> = Lazy::new(DashMap::default);
+
+///Build the TLS configuration of the listener. The certificate chain, private key and the CA bundle
+///used to verify client certificates are swapped when the files change on disk, or when the listener
+///configuration is reloaded.
+pub fn server_config(listen_cfg: &Listener) -> Result<ServerConfig> {
+    let provider = Arc::new(provider::default_provider());
+    let loaded = Loaded::load(&provider, listen_cfg)?;
+
+    let client_verifier = loaded.client_verifier.clone().map(|v| Arc::new(ClientVerifier::new(v)));
+    let tls = Arc::new(ListenerTls::new(listen_cfg, loaded, client_verifier.clone()));
+    let port = listen_cfg.addr.port();
+    LISTENER_TLS.insert(port, tls.clone());
+    if !listen_cfg.cert_watch_interval.is_zero() {
+        tokio::spawn(watch(port, tls.clone()));
+    }
+
+    let builder = ServerConfig::builder_with_provider(provider)
+        .with_safe_default_protocol_versions()
+        .map_err(|e| anyhow!(e))?;
+    let builder = if let Some(client_verifier) = client_verifier {
+        builder.with_client_cert_verifier(client_verifier)
+    } else {
+        builder.with_client_cert_verifier(WebPkiClientVerifier::no_client_auth())
+    };
+    Ok(builder.with_cert_resolver(tls))
+}
+
+///Reload the certificate files of all active TLS listeners, new connections will use the reloaded
+///certificate. If loading fails, the listener continues to use the previous certificate.
+pub(crate) fn reload_certs() {
+    let listeners = Runtime::instance()
+        .settings
+        .listeners
+        .all()
+        .into_iter()
+        .filter(|(typ, _)| typ.is_tls())
+        .map(|(_, l)| (l.addr.port(), l))
+        .collect::<std::collections::HashMap<_, _>>();
+    _reload_certs(&listeners);
+}
+
+//The TLS state of the listeners that are no longer active is removed
+fn _reload_certs(listeners: &std::collections::HashMap<Port, Listener>) {
+    LISTENER_TLS.retain(|port, _| listeners.contains_key(port));
+
+    let provider = provider::default_provider();
+    for entry in LISTENER_TLS.iter() {
+        let (port, tls) = entry.pair();
+        if let Some(listen_cfg) = listeners.get(port) {
+            tls.reload(&provider, listen_cfg);
+        }
+    }
+}
+
+///Certificates currently used by the TLS listeners of this node
+pub fn cert_infos() -> Vec<CertInfo> {
+    let node_id = Runtime::instance().node.id();
+    LISTENER_TLS
+        .iter()
+        .map(|entry| {
+            let state = entry.value().state.read();
+            CertInfo { node_id, listener: state.name.clone(), addr: state.addr, not_after: state.not_after }
+        })
+        .collect()
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct CertInfo {
+    pub node_id: NodeId,
+    pub listener: String,
+    pub addr: SocketAddr,
+    //Expiration time of the server certificate, unix timestamp in seconds
+    pub not_after: Timestamp,
+}
+
+///Identity fields of a verified client certificate
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash)]
+pub struct PeerCert {
+    //Common name of the subject
+    pub cn: Option<String>,
+    //Distinguished name of the subject, e.g. "CN=client1, O=rmqtt"
+    pub dn: Option<String>,
+    //First subject alternative name, DNS name, email or URI
+    pub san: Option<String>,
+}
+
+impl PeerCert {
+    ///Client certificate of the TLS connection, None if the client did not present one
+    #[inline]
+    pub fn from_conn(conn: &rustls::ServerConnection) -> Option<Self> {
+        conn.peer_certificates()
+            .and_then(|certs| certs.first())
+            .and_then(|cert| Self::from_der(cert.as_ref()))
+    }
+
+    pub fn from_der(der: &[u8]) -> Option<Self> {
+        let (_, cert) = x509_parser::parse_x509_certificate(der)
+            .map_err(|e| log::warn!("bad client certificate, {}", e))
+            .ok()?;
+        let subject = cert.subject();
+        let cn = subject.iter_common_name().next().and_then(|cn| cn.as_str().ok()).map(String::from);
+        let dn = Some(subject.to_string()).filter(|dn| !dn.is_empty());
+        let san = cert.subject_alternative_name().ok().flatten().and_then(|san| {
+            san.value.general_names.iter().find_map(|name| match name {
+                GeneralName::DNSName(s) | GeneralName::RFC822Name(s) | GeneralName::URI(s) => {
+                    Some(s.to_string())
+                }
+                _ => None,
+            })
+        });
+        Some(Self { cn, dn, san })
+    }
+
+    #[inline]
+    pub fn get(&self, field: PeerCertField) -> Option<&str> {
+        match field {
+            PeerCertField::Cn => self.cn.as_deref(),
+            PeerCertField::Dn => self.dn.as_deref(),
+            PeerCertField::San => self.san.as_deref(),
+        }
+    }
+
+    ///The username and the client id taken from the client certificate, by 'peer_cert_as_username' and
+    ///'peer_cert_as_clientid' of the listener. None if the field is not configured.
+    pub fn identity(
+        peer_cert: Option<&PeerCert>,
+        as_username: Option<PeerCertField>,
+        as_clientid: Option<PeerCertField>,
+    ) -> std::result::Result<(Option<UserName>, Option<ClientId>), PeerCertRefused> {
+        let get = |field: PeerCertField| peer_cert.and_then(|c| c.get(field));
+        let username = as_username
+            .map(|field| {
+                get(field).map(|un| UserName::from(un.to_owned())).ok_or(PeerCertRefused::Username(field))
+            })
+            .transpose()?;
+        let client_id = as_clientid
+            .map(|field| {
+                get(field).map(|id| ClientId::from(id.to_owned())).ok_or(PeerCertRefused::ClientId(field))
+            })
+            .transpose()?;
+        Ok((username, client_id))
+    }
+}
+
+///The connection is refused, there is no client certificate or the configured field is missing
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PeerCertRefused {
+    //The field used as the username
+    Username(PeerCertField),
+    //The field used as the client id
+    ClientId(PeerCertField),
+}
+
+//Watch the certificate files of the listener until the listener is removed or restarted
+async fn watch(port: Port, tls: Arc<ListenerTls>) {
+    let provider = provider::default_provider();
+    loop {
+        let interval = Runtime::instance()
+            .settings
+            .listeners
+            .get(port)
+            .map(|l| l.cert_watch_interval)
+            .unwrap_or_default();
+        tokio::time::sleep(if interval.is_zero() { Duration::from_secs(60) } else { interval }).await;
+
+        let listen_cfg = Runtime::instance().settings.listeners.get(port);
+        if !watch_check(&provider, port, &tls, listen_cfg.as_ref()) {
+            break;
+        }
+    }
+}
+
+//Reload the certificate files if they have changed, false if the listener is removed or restarted
+fn watch_check(
+    provider: &CryptoProvider,
+    port: Port,
+    tls: &Arc<ListenerTls>,
+    listen_cfg: Option<&Listener>,
+) -> bool {
+    if !LISTENER_TLS.get(&port).map(|entry| Arc::ptr_eq(entry.value(), tls)).unwrap_or(false) {
+        return false;
+    }
+    if let Some(listen_cfg) = listen_cfg.filter(|l| !l.cert_watch_interval.is_zero()) {
+        if tls.is_modified(listen_cfg) {
+            tls.reload(provider, listen_cfg);
+        }
+    }
+    true
+}
+
+struct ListenerTls {
+    certified_key: parking_lot::RwLock<Arc<CertifiedKey>>,
+    client_verifier: Option<Arc<ClientVerifier>>,
+    state: parking_lot::RwLock<State>,
+}
+
+struct State {
+    name: String,
+    addr: SocketAddr,
+    not_after: Timestamp,
+    //Modification time of the cert, key and cacert files at the last successful load
+    modifieds: Vec<Option<SystemTime>>,
+}
+
+impl ListenerTls {
+    #[inline]
+    fn new(listen_cfg: &Listener, loaded: Loaded, client_verifier: Option<Arc<ClientVerifier>>) -> Self {
+        let state = State {
+            name: listen_cfg.name.clone(),
+            addr: listen_cfg.addr,
+            not_after: loaded.not_after,
+            modifieds: loaded.modifieds,
+        };
+        Self {
+            certified_key: parking_lot::RwLock::new(Arc::new(loaded.certified_key)),
+            client_verifier,
+            state: parking_lot::RwLock::new(state),
+        }
+    }
+
+    #[inline]
+    fn is_modified(&self, listen_cfg: &Listener) -> bool {
+        self.state.read().modifieds != modifieds(listen_cfg)
+    }
+
+    fn reload(&self, provider: &CryptoProvider, listen_cfg: &Listener) {
+        match Loaded::load(provider, listen_cfg) {
+            Ok(loaded) => {
+                *self.certified_key.write() = Arc::new(loaded.certified_key);
+                if let (Some(client_verifier), Some(v)) =
+                    (self.client_verifier.as_ref(), loaded.client_verifier)
+                {
+                    client_verifier.set(v);
+                }
+                let mut state = self.state.write();
+                state.name.clone_from(&listen_cfg.name);
+                state.not_after = loaded.not_after;
+                state.modifieds = loaded.modifieds;
+                log::info!(
+                    "{} tls certificate reloaded, cert: {:?}, expires at: {}",
+                    listen_cfg.name,
+                    listen_cfg.cert,
+                    crate::format_timestamp(loaded.not_after)
+                );
+            }
+            Err(e) => {
+                log::warn!(
+                    "{} tls certificate reload failed, cert: {:?}, key: {:?}, cacert: {:?}, {:?}",
+                    listen_cfg.name,
+                    listen_cfg.cert,
+                    listen_cfg.key,
+                    listen_cfg.cacert,
+                    e
+                );
+            }
+        }
+    }
+}
+
+impl std::fmt::Debug for ListenerTls {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let state = self.state.read();
+        write!(
+            f,
+            "ListenerTls {{ name: {}, addr: {}, not_after: {} }}",
+            state.name, state.addr, state.not_after
+        )
+    }
+}
+
+impl ResolvesServerCert for ListenerTls {
+    #[inline]
+    fn resolve(&self, _client_hello: ClientHello<'_>) -> Option<Arc<CertifiedKey>> {
+        Some(self.certified_key.read().clone())
+    }
+}
+
+struct Loaded {
+    certified_key: CertifiedKey,
+    client_verifier: Option<Arc<dyn ClientCertVerifier>>,
+    not_after: Timestamp,
+    modifieds: Vec<Option<SystemTime>>,
+}
+
+impl Loaded {
+    fn load(provider: &CryptoProvider, listen_cfg: &Listener) -> Result<Self> {
+        let modifieds = modifieds(listen_cfg);
+        let cert_file = listen_cfg.cert.as_ref().ok_or::<MqttError>("cert is None".into())?;
+        let key_file = listen_cfg.key.as_ref().ok_or::<MqttError>("key is None".into())?;
+
+        let cert_chain = load_certs(cert_file)?;
+        let key = PrivateKeyDer::from_pem_file(key_file).map_err(|e| anyhow!(e))?;
+
+        let not_after = cert_chain
+            .first()
+            .map(|cert| x509_parser::parse_x509_certificate(cert.as_ref()))
+            .transpose()
+            .map_err(|e| anyhow!(format!("bad certs, {}", e)))?
+            .map(|(_, cert)| cert.validity().not_after.timestamp())
+            .ok_or_else(|| MqttError::from("bad certs, certificate is not found"))?;
+
+        let client_verifier = if listen_cfg.cross_certificate {
+            let roots = if let Some(cacert_file) = listen_cfg.cacert.as_ref() {
+                load_certs(cacert_file)?
+            } else {
+                cert_chain.clone()
+            };
+            let mut client_auth_roots = RootCertStore::empty();
+            for root in roots {
+                client_auth_roots.add(root).map_err(|e| anyhow!(e))?;
+            }
+            Some(
+                WebPkiClientVerifier::builder_with_provider(
+                    client_auth_roots.into(),
+                    Arc::new(provider.clone()),
+                )
+                .build()
+                .map_err(|e| anyhow!(e))?,
+            )
+        } else {
+            None
+        };
+
+        let signing_key = provider
+            .key_provider
+            .load_private_key(key)
+            .map_err(|e| anyhow!(format!("bad certs/private key, {}", e)))?;
+        let certified_key = CertifiedKey::new(cert_chain, signing_key);
+        //The cert and key files may be replaced one after the other
+        certified_key.keys_match().map_err(|e| anyhow!(format!("bad certs/private key, {}", e)))?;
+
+        Ok(Self { certified_key, client_verifier, not_after, modifieds })
+    }
+}
+
+#[inline]
+fn load_certs(file: &str) -> Result<Vec<CertificateDer<'static>>> {
+    Ok(CertificateDer::pem_file_iter(file)
+        .map_err(|e| anyhow!(e))?
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| anyhow!(e))?)
+}
+
+#[inline]
+fn modifieds(listen_cfg: &Listener) -> Vec<Option<SystemTime>> {
+    [listen_cfg.cert.as_ref(), listen_cfg.key.as_ref(), listen_cfg.cacert.as_ref()]
+        .into_iter()
+        .map(|file| file.and_then(|file| std::fs::metadata(file).and_then(|m| m.modified()).ok()))
+        .collect()
+}
+
+//Client certificate verifier that can be replaced when the CA bundle changes
+#[derive(Debug)]
+struct ClientVerifier {
+    inner: parking_lot::RwLock<Arc<dyn ClientCertVerifier>>,
+}
+
+impl ClientVerifier {
+    #[inline]
+    fn new(inner: Arc<dyn ClientCertVerifier>) -> Self {
+        Self { inner: parking_lot::RwLock::new(inner) }
+    }
+
+    #[inline]
+    fn get(&self) -> Arc<dyn ClientCertVerifier> {
+        self.inner.read().clone()
+    }
+
+    #[inline]
+    fn set(&self, inner: Arc<dyn ClientCertVerifier>) {
+        *self.inner.write() = inner;
+    }
+}
+
+impl ClientCertVerifier for ClientVerifier {
+    #[inline]
+    fn offer_client_auth(&self) -> bool {
+        self.get().offer_client_auth()
+    }
+
+    #[inline]
+    fn client_auth_mandatory(&self) -> bool {
+        self.get().client_auth_mandatory()
+    }
+
+    //The subjects cannot be borrowed from a replaceable verifier, no hints are sent to the client
+    #[inline]
+    fn root_hint_subjects(&self) -> &[DistinguishedName] {
+        &[]
+    }
+
+    #[inline]
+    fn verify_client_cert(
+        &self,
+        end_entity: &CertificateDer<'_>,
+        intermediates: &[CertificateDer<'_>],
+        now: UnixTime,
+    ) -> Result<ClientCertVerified, rustls::Error> {
+        self.get().verify_client_cert(end_entity, intermediates, now)
+    }
+
+    #[inline]
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        self.get().verify_tls12_signature(message, cert, dss)
+    }
+
+    #[inline]
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        self.get().verify_tls13_signature(message, cert, dss)
+    }
+
+    #[inline]
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.get().supported_verify_schemes()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::settings::listener::Listeners;
+
+    //rmqtt-bin/rmqtt.pem and rmqtt-bin/client.pem expire at these times
+    const SERVER_NOT_AFTER: Timestamp = 2008724507;
+    const CLIENT_NOT_AFTER: Timestamp = 2008724486;
+
+    struct Files {
+        dir: std::path::PathBuf,
+    }
+
+    impl Files {
+        fn new(name: &str) -> Self {
+            let dir = std::env::temp_dir().join(format!("rmqtt-tls-test-{}-{}", name, std::process::id()));
+            let _ = std::fs::remove_dir_all(&dir);
+            std::fs::create_dir_all(&dir).unwrap();
+            Self { dir }
+        }
+
+        fn path(&self, name: &str) -> String {
+            self.dir.join(name).to_string_lossy().into_owned()
+        }
+
+        //Copies a certificate file of rmqtt-bin, the modification time is set explicitly, so the
+        //change is seen even if the file system has a coarse timestamp granularity
+        fn copy(&self, from: &str, to: &str, modified: u64) {
+            let from = std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join("../rmqtt-bin").join(from);
+            std::fs::copy(from, self.path(to)).unwrap();
+            let file = std::fs::File::options().write(true).open(self.path(to)).unwrap();
+            file.set_modified(SystemTime::UNIX_EPOCH + Duration::from_secs(modified)).unwrap();
+        }
+    }
+
+    impl Drop for Files {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_dir_all(&self.dir);
+        }
+    }
+
+    fn listener(files: &Files, port: Port, watch: &str) -> Listener {
+        let mut listeners: Listeners = serde_json::from_value(json!({ "tls": { "tls-test": {
+            "addr": format!("0.0.0.0:{}", port),
+            "cert": files.path("cert.pem"),
+            "key": files.path("cert.key"),
+            "cert_watch_interval": watch,
+        }}}))
+        .unwrap();
+        listeners.init();
+        listeners.tls(port).unwrap()
+    }
+
+    fn peer_cert(file: &str) -> Option<PeerCert> {
+        let file = std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join("../rmqtt-bin").join(file);
+        let certs = load_certs(&file.to_string_lossy()).unwrap();
+        PeerCert::from_der(certs[0].as_ref())
+    }
+
+    #[test]
+    fn peer_cert_from_der() {
+        let cert = peer_cert("rmqtt.pem").unwrap();
+        assert_eq!(cert.get(PeerCertField::Cn), Some("Server certificate"));
+        assert_eq!(cert.get(PeerCertField::Dn), Some("CN=Server certificate, OU=RMQTT, O=RMQTT, C=CN"));
+        //the first DNS name of "DNS:rmqtt.com, DNS:localhost, IP Address:192.168.1.1"
+        assert_eq!(cert.get(PeerCertField::San), Some("rmqtt.com"));
+
+        //without subject alternative names
+        let cert = peer_cert("client.pem").unwrap();
+        assert_eq!(cert.cn.as_deref(), Some("Server certificate"));
+        assert_eq!(cert.san, None);
+
+        assert_eq!(PeerCert::from_der(b"not a certificate"), None);
+    }
+
+    #[test]
+    fn peer_cert_identity() {
+        let cert = peer_cert("client.pem");
+        assert_eq!(PeerCert::identity(cert.as_ref(), None, None), Ok((None, None)));
+        assert_eq!(
+            PeerCert::identity(cert.as_ref(), Some(PeerCertField::Cn), Some(PeerCertField::Dn)),
+            Ok((
+                Some(UserName::from("Server certificate")),
+                Some(ClientId::from("CN=Server certificate, OU=RMQTT, O=RMQTT, C=CN"))
+            ))
+        );
+        assert_eq!(
+            PeerCert::identity(cert.as_ref(), Some(PeerCertField::Cn), Some(PeerCertField::San)),
+            Err(PeerCertRefused::ClientId(PeerCertField::San))
+        );
+        assert_eq!(
+            PeerCert::identity(None, None, Some(PeerCertField::Cn)),
+            Err(PeerCertRefused::ClientId(PeerCertField::Cn))
+        );
+        assert_eq!(
+            PeerCert::identity(None, Some(PeerCertField::Dn), Some(PeerCertField::Cn)),
+            Err(PeerCertRefused::Username(PeerCertField::Dn))
+        );
+    }
+
+    fn listener_tls(provider: &CryptoProvider, listen_cfg: &Listener) -> Arc<ListenerTls> {
+        let loaded = Loaded::load(provider, listen_cfg).unwrap();
+        let tls = Arc::new(ListenerTls::new(listen_cfg, loaded, None));
+        LISTENER_TLS.insert(listen_cfg.addr.port(), tls.clone());
+        tls
+    }
+
+    #[test]
+    fn reload_certs() {
+        let provider = provider::default_provider();
+        let files = Files::new("reload");
+        files.copy("rmqtt.pem", "cert.pem", 1);
+        files.copy("rmqtt.key", "cert.key", 1);
+        let listen_cfg = listener(&files, 48881, "0s");
+        let tls = listener_tls(&provider, &listen_cfg);
+        assert_eq!(tls.state.read().not_after, SERVER_NOT_AFTER);
+
+        //a certificate that does not match the private key is not used
+        files.copy("client.pem", "cert.pem", 2);
+        let listeners = [(48881, listen_cfg.clone())].into_iter().collect();
+        _reload_certs(&listeners);
+        assert_eq!(tls.state.read().not_after, SERVER_NOT_AFTER);
+        assert!(tls.is_modified(&listen_cfg));
+
+        files.copy("client.key", "cert.key", 2);
+        _reload_certs(&listeners);
+        assert_eq!(tls.state.read().not_after, CLIENT_NOT_AFTER);
+        assert!(!tls.is_modified(&listen_cfg));
+
+        //the listener is no longer active
+        _reload_certs(&Default::default());
+        assert!(!LISTENER_TLS.contains_key(&48881));
+    }
+
+    #[test]
+    fn watch() {
+        let provider = provider::default_provider();
+        let files = Files::new("watch");
+        files.copy("rmqtt.pem", "cert.pem", 1);
+        files.copy("rmqtt.key", "cert.key", 1);
+        let listen_cfg = listener(&files, 48882, "1s");
+        let tls = listener_tls(&provider, &listen_cfg);
+
+        //unchanged files
+        assert!(watch_check(&provider, 48882, &tls, Some(&listen_cfg)));
+        assert_eq!(tls.state.read().not_after, SERVER_NOT_AFTER);
+
+        files.copy("client.pem", "cert.pem", 2);
+        files.copy("client.key", "cert.key", 2);
+        //watching is disabled by the listener configuration
+        let unwatched = listener(&files, 48882, "0s");
+        assert!(watch_check(&provider, 48882, &tls, Some(&unwatched)));
+        assert_eq!(tls.state.read().not_after, SERVER_NOT_AFTER);
+
+        assert!(watch_check(&provider, 48882, &tls, Some(&listen_cfg)));
+        assert_eq!(tls.state.read().not_after, CLIENT_NOT_AFTER);
+
+        //the listener is restarted, the watch of the previous TLS state stops
+        let restarted = listener_tls(&provider, &listen_cfg);
+        assert!(!watch_check(&provider, 48882, &tls, Some(&listen_cfg)));
+        assert!(watch_check(&provider, 48882, &restarted, Some(&listen_cfg)));
+        LISTENER_TLS.remove(&48882);
+        assert!(!watch_check(&provider, 48882, &restarted, Some(&listen_cfg)));
+    }
+}